@@ -136,6 +136,9 @@ pub struct DeployArgs {
     /// allows pre-deploy tests to be skipped
     #[arg(long)]
     pub no_test: bool,
+    /// label the deployment with what is deployed, such as a commit or a version
+    #[arg(long)]
+    pub label: Option<String>,
 }
 
 #[derive(Parser, Debug)]
@@ -38,6 +38,7 @@ impl Client {
         data: Vec<u8>,
         project: &ProjectName,
         no_test: bool,
+        label: Option<&str>,
     ) -> Result<deployment::Response> {
         let mut path = format!(
             "/projects/{}/services/{}",
@@ -45,8 +46,16 @@ impl Client {
             project.as_str()
         );
 
+        let mut query = Vec::new();
         if no_test {
-            let _ = write!(path, "?no-test");
+            query.push("no-test".to_string());
+        }
+        if let Some(label) = label {
+            let label: String = url::form_urlencoded::byte_serialize(label.as_bytes()).collect();
+            query.push(format!("label={label}"));
+        }
+        if !query.is_empty() {
+            let _ = write!(path, "?{}", query.join("&"));
         }
 
         self.post(path, Some(data))
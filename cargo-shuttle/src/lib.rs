@@ -467,7 +467,12 @@ impl Shuttle {
         let data = self.make_archive()?;
 
         let deployment = client
-            .deploy(data, self.ctx.project_name(), args.no_test)
+            .deploy(
+                data,
+                self.ctx.project_name(),
+                args.no_test,
+                args.label.as_deref(),
+            )
             .await?;
 
         let mut stream = client
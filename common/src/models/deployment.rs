@@ -14,6 +14,38 @@ pub struct Response {
     pub service_id: Uuid,
     pub state: State,
     pub last_update: DateTime<Utc>,
+    /// The account which made the deployment
+    #[serde(default)]
+    pub deployed_by: Option<String>,
+    /// What was deployed, such as a commit or a version
+    #[serde(default)]
+    pub label: Option<String>,
+}
+
+/// An entry in the history of the deployments of a project
+#[derive(Deserialize, Serialize)]
+pub struct HistoryEntry {
+    pub id: Uuid,
+    pub service_id: Uuid,
+    pub state: State,
+    pub created_at: DateTime<Utc>,
+    #[serde(default)]
+    pub deployed_by: Option<String>,
+    #[serde(default)]
+    pub label: Option<String>,
+}
+
+/// A deploy the gateway has yet to hand over to the deployer of its
+/// project, either because it is being handed over or because it is
+/// queued behind the one which is
+#[derive(Deserialize, Serialize)]
+pub struct PendingDeploy {
+    pub id: Uuid,
+    /// [`State::Running`] for the deploy in progress, [`State::Queued`]
+    /// for the ones waiting for it to be done
+    pub state: State,
+    pub requested_by: String,
+    pub requested_at: DateTime<Utc>,
 }
 
 impl Display for Response {
@@ -27,7 +59,16 @@ impl Display for Response {
                 .dim(),
             self.id,
             self.state.to_string().cyan()
-        )
+        )?;
+
+        if let Some(label) = &self.label {
+            write!(f, " ({label})")?;
+        }
+        if let Some(deployed_by) = &self.deployed_by {
+            write!(f, ", deployed by {deployed_by}")?;
+        }
+
+        Ok(())
     }
 }
 
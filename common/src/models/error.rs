@@ -10,6 +10,14 @@ use tracing::{error, warn};
 pub struct ApiError {
     pub message: String,
     pub status_code: u16,
+    /// A stable identifier of the error for tooling to branch on.
+    /// Only set for errors coming from an [`ErrorKind`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub code: Option<String>,
+    /// Alternatives the user could try instead, such as available
+    /// project names when the requested one is taken
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub suggestions: Vec<String>,
 }
 
 impl ApiError {
@@ -25,7 +33,13 @@ impl Display for ApiError {
             "{}\nmessage: {}",
             self.status().to_string().bold(),
             self.message.to_string().with(Color::Red)
-        )
+        )?;
+
+        if !self.suggestions.is_empty() {
+            write!(f, "\nsuggestions: {}", self.suggestions.join(", "))?;
+        }
+
+        Ok(())
     }
 }
 
@@ -40,18 +54,122 @@ pub enum ErrorKind {
     Forbidden,
     UserNotFound,
     UserAlreadyExists,
+    KeyNotFound,
     ProjectNotFound,
     InvalidProjectName,
     ProjectAlreadyExists,
     ProjectNotReady,
+    ProjectStarting,
     ProjectUnavailable,
+    ProjectUnreachable,
+    ProjectTimedOut,
+    ProjectFrozen,
+    ProjectBusy,
     CustomDomainNotFound,
+    AliasNotFound,
     InvalidCustomDomain,
     CustomDomainAlreadyExists,
+    CertificateStillValid,
+    CertificateNotFound,
     InvalidOperation,
+    InvalidEnvVar,
+    WebhookNotFound,
+    ContainerNotFound,
+    ContainerConflict,
+    InvalidWebhookUrl,
+    InvalidRateLimit,
+    InvalidHeaderRule,
+    InvalidBasicAuth,
+    InvalidConnectionLimit,
+    InvalidBackup,
+    InvalidIpFilter,
+    InvalidResponseCache,
+    InvalidDeploymentTimeout,
+    InvalidResourceLimits,
+    InvalidCanary,
+    InvalidProjectTag,
+    InvalidLogLevel,
+    InvalidGitSource,
+    InvalidArchive,
+    InvalidMaintenancePage,
+    GitFetchFailed,
+    GitSourceTooLarge,
+    RateLimited,
+    HeadersTooLarge,
+    ProjectOverloaded,
+    IpBlocked,
+    IdempotencyKeyReused,
     Internal,
     NotReady,
     ServiceUnavailable,
+    Maintenance,
+    NodeDraining,
+}
+
+impl ErrorKind {
+    /// The stable, machine readable code of this kind of error. These
+    /// never change once released, even if a variant is renamed
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::KeyMissing => "key_missing",
+            Self::BadHost => "bad_host",
+            Self::KeyMalformed => "key_malformed",
+            Self::Unauthorized => "unauthorized",
+            Self::Forbidden => "forbidden",
+            Self::UserNotFound => "user_not_found",
+            Self::UserAlreadyExists => "user_already_exists",
+            Self::KeyNotFound => "key_not_found",
+            Self::ProjectNotFound => "project_not_found",
+            Self::InvalidProjectName => "invalid_project_name",
+            Self::ProjectAlreadyExists => "project_already_exists",
+            Self::ProjectNotReady => "project_not_ready",
+            Self::ProjectStarting => "project_starting",
+            Self::ProjectUnavailable => "project_unavailable",
+            Self::ProjectUnreachable => "project_unreachable",
+            Self::ProjectTimedOut => "project_timed_out",
+            Self::ProjectFrozen => "project_frozen",
+            Self::ProjectBusy => "project_busy",
+            Self::CustomDomainNotFound => "custom_domain_not_found",
+            Self::AliasNotFound => "alias_not_found",
+            Self::InvalidCustomDomain => "invalid_custom_domain",
+            Self::CustomDomainAlreadyExists => "custom_domain_already_exists",
+            Self::CertificateStillValid => "certificate_still_valid",
+            Self::CertificateNotFound => "certificate_not_found",
+            Self::InvalidOperation => "invalid_operation",
+            Self::InvalidEnvVar => "invalid_env_var",
+            Self::WebhookNotFound => "webhook_not_found",
+            Self::ContainerNotFound => "container_not_found",
+            Self::ContainerConflict => "container_conflict",
+            Self::InvalidWebhookUrl => "invalid_webhook_url",
+            Self::InvalidRateLimit => "invalid_rate_limit",
+            Self::InvalidHeaderRule => "invalid_header_rule",
+            Self::InvalidBasicAuth => "invalid_basic_auth",
+            Self::InvalidConnectionLimit => "invalid_connection_limit",
+            Self::InvalidBackup => "invalid_backup",
+            Self::InvalidIpFilter => "invalid_ip_filter",
+            Self::InvalidResponseCache => "invalid_response_cache",
+            Self::InvalidDeploymentTimeout => "invalid_deployment_timeout",
+            Self::InvalidResourceLimits => "invalid_resource_limits",
+            Self::InvalidCanary => "invalid_canary",
+            Self::InvalidProjectTag => "invalid_project_tag",
+            Self::InvalidLogLevel => "invalid_log_level",
+            Self::InvalidGitSource => "invalid_git_source",
+            Self::InvalidArchive => "invalid_archive",
+            Self::InvalidMaintenancePage => "invalid_maintenance_page",
+            Self::GitFetchFailed => "git_fetch_failed",
+            Self::GitSourceTooLarge => "git_source_too_large",
+            Self::RateLimited => "rate_limited",
+            Self::HeadersTooLarge => "headers_too_large",
+            Self::ProjectOverloaded => "project_overloaded",
+            Self::IpBlocked => "ip_blocked",
+            Self::IdempotencyKeyReused => "idempotency_key_reused",
+            Self::Internal => "internal",
+            Self::NotReady => "not_ready",
+            Self::ServiceUnavailable => "service_unavailable",
+            Self::Maintenance => "maintenance",
+            Self::NodeDraining => "node_draining",
+        }
+    }
 }
 
 impl From<ErrorKind> for ApiError {
@@ -63,18 +181,43 @@ impl From<ErrorKind> for ApiError {
                 StatusCode::SERVICE_UNAVAILABLE,
                 "we're experiencing a high workload right now, please try again in a little bit",
             ),
+            ErrorKind::Maintenance => (
+                StatusCode::SERVICE_UNAVAILABLE,
+                "shuttle is down for maintenance, creating projects and deploying are disabled until it is over. Existing projects keep running",
+            ),
+            ErrorKind::NodeDraining => (
+                StatusCode::SERVICE_UNAVAILABLE,
+                "this node is being taken out of rotation and does not take new projects, please try again in a little bit",
+            ),
             ErrorKind::KeyMalformed => (StatusCode::BAD_REQUEST, "request has an invalid key"),
             ErrorKind::BadHost => (StatusCode::BAD_REQUEST, "the 'Host' header is invalid"),
             ErrorKind::UserNotFound => (StatusCode::NOT_FOUND, "user not found"),
-            ErrorKind::UserAlreadyExists => (StatusCode::BAD_REQUEST, "user already exists"),
+            ErrorKind::UserAlreadyExists => (StatusCode::CONFLICT, "user already exists"),
             ErrorKind::ProjectNotFound => (
                 StatusCode::NOT_FOUND,
                 "project not found. Run `cargo shuttle project new` to create a new project.",
             ),
             ErrorKind::ProjectNotReady => (StatusCode::SERVICE_UNAVAILABLE, "project not ready"),
+            ErrorKind::ProjectStarting => (
+                StatusCode::SERVICE_UNAVAILABLE,
+                "project is starting up, try again in a few seconds",
+            ),
             ErrorKind::ProjectUnavailable => {
                 (StatusCode::BAD_GATEWAY, "project returned invalid response")
             }
+            ErrorKind::ProjectUnreachable => (StatusCode::BAD_GATEWAY, "project is unreachable"),
+            ErrorKind::ProjectTimedOut => (
+                StatusCode::GATEWAY_TIMEOUT,
+                "project took too long to respond",
+            ),
+            ErrorKind::ProjectFrozen => (
+                StatusCode::FORBIDDEN,
+                "project has been frozen by an admin, please contact support",
+            ),
+            ErrorKind::ProjectBusy => (
+                StatusCode::CONFLICT,
+                "another deployment of this project is already in progress, try again once it is done",
+            ),
             ErrorKind::InvalidProjectName => (
                 StatusCode::BAD_REQUEST,
                 r#"
@@ -91,15 +234,129 @@ impl From<ErrorKind> for ApiError {
                 StatusCode::BAD_REQUEST,
                 "the requested operation is invalid",
             ),
+            ErrorKind::InvalidEnvVar => (
+                StatusCode::BAD_REQUEST,
+                "invalid environment variable name. Names must start with a letter or `_` and only contain alphanumeric characters or `_`",
+            ),
+            ErrorKind::InvalidWebhookUrl => (
+                StatusCode::BAD_REQUEST,
+                "invalid webhook url. It has to be an absolute `http` or `https` url",
+            ),
+            ErrorKind::InvalidRateLimit => (
+                StatusCode::BAD_REQUEST,
+                "invalid rate limit. Both the requests per second and the burst have to be at least 1",
+            ),
+            ErrorKind::InvalidHeaderRule => (
+                StatusCode::BAD_REQUEST,
+                "invalid header rule. Header names and values have to be valid HTTP headers",
+            ),
+            ErrorKind::InvalidBasicAuth => (
+                StatusCode::BAD_REQUEST,
+                "invalid basic auth credentials. The username cannot be empty or contain `:` and the password cannot be empty",
+            ),
+            ErrorKind::InvalidConnectionLimit => (
+                StatusCode::BAD_REQUEST,
+                "invalid connection limit. At least 1 connection has to be allowed",
+            ),
+            ErrorKind::InvalidBackup => (
+                StatusCode::BAD_REQUEST,
+                "invalid backup. It has to be complete, taken at the same schema version and restored into an empty instance",
+            ),
+            ErrorKind::InvalidIpFilter => (
+                StatusCode::BAD_REQUEST,
+                "invalid IP filter. Ranges have to be in CIDR notation, such as `10.0.0.0/8` or `2001:db8::/32`",
+            ),
+            ErrorKind::InvalidResponseCache => (
+                StatusCode::BAD_REQUEST,
+                "invalid response cache. It has to keep between 1 and 10000 responses",
+            ),
+            ErrorKind::InvalidDeploymentTimeout => (
+                StatusCode::BAD_REQUEST,
+                "invalid deployment timeout. Deployments need at least 1 second to start",
+            ),
+            ErrorKind::InvalidResourceLimits => (
+                StatusCode::BAD_REQUEST,
+                "invalid resource limits. Projects need at least 1 MiB of memory and 1 millicpu",
+            ),
+            ErrorKind::InvalidCanary => (
+                StatusCode::BAD_REQUEST,
+                "invalid canary. It has to be another project of the same account, with a weight from 0 to 100",
+            ),
+            ErrorKind::InvalidGitSource => (
+                StatusCode::BAD_REQUEST,
+                "invalid git source. Use the https URL of a repository and a branch, tag or commit of it",
+            ),
+            ErrorKind::InvalidArchive => (
+                StatusCode::BAD_REQUEST,
+                "the uploaded crate is not a gzipped tar archive. Deploy with `cargo shuttle deploy`",
+            ),
+            ErrorKind::InvalidMaintenancePage => (
+                StatusCode::BAD_REQUEST,
+                "invalid maintenance page. It cannot be empty or larger than 64 KiB, and visitors have to be told to retry after at least 1 second",
+            ),
+            ErrorKind::GitFetchFailed => (
+                StatusCode::BAD_REQUEST,
+                "could not fetch the git repository. Check that the reference exists and that the project has a token for it if it is private",
+            ),
+            ErrorKind::GitSourceTooLarge => (
+                StatusCode::PAYLOAD_TOO_LARGE,
+                "the git repository is too large to be deployed from. Deploy it with `cargo shuttle deploy` instead",
+            ),
+            ErrorKind::InvalidLogLevel => (
+                StatusCode::BAD_REQUEST,
+                "invalid log level. Use tracing filter directives, like `info,shuttle_gateway=debug`",
+            ),
+            ErrorKind::InvalidProjectTag => (
+                StatusCode::BAD_REQUEST,
+                "invalid project tag. Tags are up to 64 letters, digits, `-`, `_`, `.` or `:`, starting with a letter or digit, and a project can have up to 20 of them",
+            ),
+            ErrorKind::RateLimited => (
+                StatusCode::TOO_MANY_REQUESTS,
+                "too many requests to this project, please slow down",
+            ),
+            ErrorKind::HeadersTooLarge => (
+                StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE,
+                "the headers of the request are too large",
+            ),
+            ErrorKind::ProjectOverloaded => (
+                StatusCode::SERVICE_UNAVAILABLE,
+                "this project has too many connections open, please try again in a little bit",
+            ),
+            ErrorKind::IpBlocked => (
+                StatusCode::FORBIDDEN,
+                "this project cannot be reached from your IP address",
+            ),
+            ErrorKind::IdempotencyKeyReused => (
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "this idempotency key was already used for a different request",
+            ),
             ErrorKind::ProjectAlreadyExists => (
                 StatusCode::BAD_REQUEST,
                 "a project with the same name already exists",
             ),
             ErrorKind::InvalidCustomDomain => (StatusCode::BAD_REQUEST, "invalid custom domain"),
             ErrorKind::CustomDomainNotFound => (StatusCode::NOT_FOUND, "custom domain not found"),
+            ErrorKind::AliasNotFound => (StatusCode::NOT_FOUND, "alias not found"),
+            ErrorKind::CertificateNotFound => {
+                (StatusCode::NOT_FOUND, "the domain has no certificate")
+            }
+            ErrorKind::KeyNotFound => (StatusCode::NOT_FOUND, "key not found"),
+            ErrorKind::WebhookNotFound => (StatusCode::NOT_FOUND, "project has no webhook"),
+            ErrorKind::ContainerNotFound => (
+                StatusCode::NOT_FOUND,
+                "project has no container, it has either not been created yet or been destroyed",
+            ),
+            ErrorKind::ContainerConflict => (
+                StatusCode::CONFLICT,
+                "the container of the project is in the middle of something else, try again later",
+            ),
             ErrorKind::CustomDomainAlreadyExists => {
                 (StatusCode::BAD_REQUEST, "custom domain already in use")
             }
+            ErrorKind::CertificateStillValid => (
+                StatusCode::CONFLICT,
+                "the domain already has a certificate which is not about to expire. Use `force=true` to issue a new one anyway",
+            ),
             ErrorKind::Unauthorized => (StatusCode::UNAUTHORIZED, "unauthorized"),
             ErrorKind::Forbidden => (StatusCode::FORBIDDEN, "forbidden"),
             ErrorKind::NotReady => (StatusCode::INTERNAL_SERVER_ERROR, "service not ready"),
@@ -107,6 +364,8 @@ impl From<ErrorKind> for ApiError {
         Self {
             message: error_message.to_string(),
             status_code: status.as_u16(),
+            code: Some(kind.code().to_string()),
+            suggestions: Vec::new(),
         }
     }
 }
@@ -144,6 +403,94 @@ impl From<StatusCode> for ApiError {
         Self {
             message: message.to_string(),
             status_code: code.as_u16(),
+            code: None,
+            suggestions: Vec::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    #[test]
+    fn error_codes() {
+        for (kind, code) in [
+            (ErrorKind::KeyMissing, "key_missing"),
+            (ErrorKind::BadHost, "bad_host"),
+            (ErrorKind::KeyMalformed, "key_malformed"),
+            (ErrorKind::Unauthorized, "unauthorized"),
+            (ErrorKind::Forbidden, "forbidden"),
+            (ErrorKind::UserNotFound, "user_not_found"),
+            (ErrorKind::UserAlreadyExists, "user_already_exists"),
+            (ErrorKind::KeyNotFound, "key_not_found"),
+            (ErrorKind::ProjectNotFound, "project_not_found"),
+            (ErrorKind::InvalidProjectName, "invalid_project_name"),
+            (ErrorKind::ProjectAlreadyExists, "project_already_exists"),
+            (ErrorKind::ProjectNotReady, "project_not_ready"),
+            (ErrorKind::ProjectStarting, "project_starting"),
+            (ErrorKind::ProjectUnavailable, "project_unavailable"),
+            (ErrorKind::ProjectUnreachable, "project_unreachable"),
+            (ErrorKind::ProjectTimedOut, "project_timed_out"),
+            (ErrorKind::ProjectFrozen, "project_frozen"),
+            (ErrorKind::ProjectBusy, "project_busy"),
+            (ErrorKind::CustomDomainNotFound, "custom_domain_not_found"),
+            (ErrorKind::AliasNotFound, "alias_not_found"),
+            (ErrorKind::InvalidCustomDomain, "invalid_custom_domain"),
+            (
+                ErrorKind::CustomDomainAlreadyExists,
+                "custom_domain_already_exists",
+            ),
+            (ErrorKind::CertificateStillValid, "certificate_still_valid"),
+            (ErrorKind::CertificateNotFound, "certificate_not_found"),
+            (ErrorKind::InvalidOperation, "invalid_operation"),
+            (ErrorKind::InvalidEnvVar, "invalid_env_var"),
+            (ErrorKind::WebhookNotFound, "webhook_not_found"),
+            (ErrorKind::ContainerNotFound, "container_not_found"),
+            (ErrorKind::ContainerConflict, "container_conflict"),
+            (ErrorKind::InvalidWebhookUrl, "invalid_webhook_url"),
+            (ErrorKind::InvalidRateLimit, "invalid_rate_limit"),
+            (ErrorKind::InvalidHeaderRule, "invalid_header_rule"),
+            (ErrorKind::InvalidBasicAuth, "invalid_basic_auth"),
+            (
+                ErrorKind::InvalidConnectionLimit,
+                "invalid_connection_limit",
+            ),
+            (ErrorKind::InvalidBackup, "invalid_backup"),
+            (ErrorKind::InvalidIpFilter, "invalid_ip_filter"),
+            (ErrorKind::InvalidResponseCache, "invalid_response_cache"),
+            (
+                ErrorKind::InvalidDeploymentTimeout,
+                "invalid_deployment_timeout",
+            ),
+            (ErrorKind::InvalidResourceLimits, "invalid_resource_limits"),
+            (ErrorKind::InvalidCanary, "invalid_canary"),
+            (ErrorKind::InvalidProjectTag, "invalid_project_tag"),
+            (ErrorKind::InvalidLogLevel, "invalid_log_level"),
+            (ErrorKind::InvalidGitSource, "invalid_git_source"),
+            (ErrorKind::InvalidArchive, "invalid_archive"),
+            (
+                ErrorKind::InvalidMaintenancePage,
+                "invalid_maintenance_page",
+            ),
+            (ErrorKind::GitFetchFailed, "git_fetch_failed"),
+            (ErrorKind::GitSourceTooLarge, "git_source_too_large"),
+            (ErrorKind::RateLimited, "rate_limited"),
+            (ErrorKind::HeadersTooLarge, "headers_too_large"),
+            (ErrorKind::ProjectOverloaded, "project_overloaded"),
+            (ErrorKind::IpBlocked, "ip_blocked"),
+            (ErrorKind::IdempotencyKeyReused, "idempotency_key_reused"),
+            (ErrorKind::Internal, "internal"),
+            (ErrorKind::NotReady, "not_ready"),
+            (ErrorKind::ServiceUnavailable, "service_unavailable"),
+            (ErrorKind::Maintenance, "maintenance"),
+            (ErrorKind::NodeDraining, "node_draining"),
+        ] {
+            let error = serde_json::to_value(ApiError::from(kind)).unwrap();
+            assert_eq!(error["code"], code, "{kind}");
         }
+
+        let error = serde_json::to_value(ApiError::from(StatusCode::BAD_GATEWAY)).unwrap();
+        assert!(error.get("code").is_none());
     }
 }
@@ -1,16 +1,21 @@
+use chrono::{DateTime, Utc};
 use comfy_table::{
     modifiers::UTF8_ROUND_CORNERS, presets::UTF8_FULL, Cell, CellAlignment, Color,
     ContentArrangement, Table,
 };
 use crossterm::style::Stylize;
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::fmt::{Display, Formatter};
 use strum::Display;
 
-#[derive(Deserialize, Serialize)]
+#[derive(Clone, Deserialize, Serialize)]
 pub struct Response {
     pub name: String,
     pub state: State,
+    /// The last time the proxy forwarded traffic to the project
+    #[serde(default)]
+    pub last_activity: Option<DateTime<Utc>>,
 }
 
 #[derive(Clone, Debug, Deserialize, Display, Serialize, Eq, PartialEq)]
@@ -24,6 +29,7 @@ pub enum State {
     Ready,
     Stopping,
     Stopped,
+    Frozen,
     Destroying,
     Destroyed,
     Errored,
@@ -46,7 +52,7 @@ impl State {
             Self::Creating | Self::Attaching | Self::Starting | Self::Started => Color::Cyan,
             Self::Ready => Color::Green,
             Self::Stopped | Self::Stopping | Self::Destroying | Self::Destroyed => Color::Blue,
-            Self::Errored => Color::Red,
+            Self::Frozen | Self::Errored => Color::Red,
         }
     }
 }
@@ -57,6 +63,409 @@ pub struct AdminResponse {
     pub account_name: String,
 }
 
+/// What makes the proxy send a host to a project
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum RouteKind {
+    Project,
+    Alias,
+    CustomDomain,
+}
+
+/// An entry of the routing table of the proxy
+#[derive(Debug, Deserialize, Serialize, Eq, PartialEq)]
+pub struct Route {
+    pub host: String,
+    pub kind: RouteKind,
+    pub project: String,
+    /// State of the project the host leads to
+    pub state: String,
+    /// Where requests for the host go, when the project is ready
+    pub backend: Option<String>,
+    /// Whether another route takes the host, as an alias named after
+    /// an existing project does
+    pub shadowed: bool,
+}
+
+/// Everything the gateway knows about a project, for support
+#[derive(Deserialize, Serialize)]
+pub struct DiagnosticsResponse {
+    pub name: String,
+    pub account_name: String,
+    /// The state of the project as persisted, with secrets redacted
+    pub state: serde_json::Value,
+    /// What docker currently reports about the container of the project
+    pub container: Option<ContainerSummary>,
+    /// The tasks of the project currently in flight
+    pub tasks: Vec<super::stats::TaskSummary>,
+    /// The last time the proxy forwarded traffic to the project
+    pub last_activity: Option<DateTime<Utc>>,
+    /// Where the proxy sends the traffic of the project to
+    pub backend: Option<String>,
+    pub certificates: Vec<CertificateStatus>,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct ContainerSummary {
+    pub id: Option<String>,
+    pub name: Option<String>,
+    pub image: Option<String>,
+    pub status: Option<String>,
+    pub started_at: Option<String>,
+    pub restart_count: Option<i64>,
+    /// Where the proxy reaches the container on the network of the
+    /// gateway, while it is running
+    pub address: Option<String>,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct CertificateStatus {
+    pub fqdn: String,
+    /// Whether the certificate was issued for this domain alone rather
+    /// than being the wildcard one of the gateway
+    pub custom: bool,
+    /// Whether TLS connections to the domain are answered with the
+    /// certificate. Unknown when the gateway does not terminate TLS.
+    pub served: Option<bool>,
+}
+
+/// What is known about the certificate a domain is served with
+#[derive(Deserialize, Serialize)]
+pub struct CertificateDetails {
+    pub fqdn: String,
+    /// Who issued the certificate. Unknown when it cannot be read.
+    pub issuer: Option<String>,
+    /// When the certificate stops being valid. Unknown when it cannot
+    /// be read.
+    pub not_after: Option<DateTime<Utc>>,
+    /// Whether the gateway gets the certificate issued and renewed
+    /// over ACME
+    pub acme_managed: bool,
+    /// When the gateway last tried to get a certificate issued for the
+    /// domain, if ever
+    pub last_renewal_attempt: Option<DateTime<Utc>>,
+    /// Why that attempt failed, if it did
+    pub last_renewal_error: Option<String>,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct EnvResponse {
+    /// Names of the environment variables set on the project. Their
+    /// values are always redacted
+    pub vars: BTreeMap<String, String>,
+    /// Whether the project needs to be recreated for changes to be
+    /// picked up
+    pub restart_required: bool,
+}
+
+/// Candidate names for new projects, to be checked all at once
+#[derive(Deserialize, Serialize)]
+pub struct NameCheckRequest {
+    pub names: Vec<String>,
+}
+
+/// Whether a candidate name can be given to a new project
+#[derive(Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub struct NameCheck {
+    pub name: String,
+    pub valid: bool,
+    /// Why the name is not valid, when it is not
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    /// Whether no other project or alias goes by the name
+    pub available: bool,
+}
+
+/// Deploy a commit of a Git repository rather than an uploaded crate
+#[derive(Deserialize, Serialize)]
+pub struct GitDeployRequest {
+    /// The `https` URL of the repository
+    pub url: String,
+    /// The branch, tag or commit to deploy
+    pub reference: String,
+}
+
+/// Token used to fetch the private repositories a project is deployed from
+#[derive(Deserialize, Serialize)]
+pub struct GitToken {
+    pub token: String,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct RenameRequest {
+    /// The name the project goes by from now on
+    pub name: String,
+    /// Whether the old subdomain redirects to the new one for a while
+    #[serde(default)]
+    pub redirect: bool,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct WebhookRequest {
+    /// Where events about the project are POSTed to
+    pub url: String,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct WebhookResponse {
+    pub url: String,
+    /// Used to sign every event. Only returned when the webhook is set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub secret: Option<String>,
+}
+
+/// What happened to a project
+#[derive(Clone, Copy, Debug, Deserialize, Display, Serialize, Eq, PartialEq, strum::EnumString)]
+#[serde(rename_all = "snake_case")]
+#[strum(serialize_all = "snake_case")]
+pub enum EventKind {
+    Created,
+    Deployed,
+    /// Became ready for the first time
+    Started,
+    /// Became ready again after having been ready before
+    Restarted,
+    Stopped,
+    Crashed,
+    Destroyed,
+    DomainAdded,
+}
+
+/// An entry of the activity feed of a project
+#[derive(Debug, Deserialize, Serialize, Eq, PartialEq)]
+pub struct Event {
+    /// Pass as `before` to get the page of older events
+    pub id: i64,
+    pub at: DateTime<Utc>,
+    pub kind: EventKind,
+    pub detail: Option<String>,
+}
+
+/// How the proxy talks to a project
+#[derive(Clone, Copy, Debug, Deserialize, Display, Serialize, Eq, PartialEq, strum::EnumString)]
+#[serde(rename_all = "lowercase")]
+#[strum(serialize_all = "lowercase")]
+pub enum UpstreamProtocol {
+    Http1,
+    /// HTTP/2 with prior knowledge, for gRPC services and the like
+    Http2,
+}
+
+impl Default for UpstreamProtocol {
+    fn default() -> Self {
+        Self::Http1
+    }
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize, Eq, PartialEq)]
+pub struct Upstream {
+    pub protocol: UpstreamProtocol,
+}
+
+/// An experimental behaviour of the gateway, turned on for some
+/// projects only while it is being tried out
+#[derive(
+    Clone,
+    Copy,
+    Debug,
+    Deserialize,
+    Display,
+    Serialize,
+    Eq,
+    PartialEq,
+    Ord,
+    PartialOrd,
+    Hash,
+    strum::EnumString,
+)]
+#[serde(rename_all = "kebab-case")]
+#[strum(serialize_all = "kebab-case")]
+pub enum FeatureFlag {
+    /// Speak HTTP/2 with prior knowledge to the project, whatever its
+    /// upstream protocol is
+    Http2Upstream,
+    /// Read the responses of the project whole before sending them on,
+    /// whatever the body mode of the proxy is
+    BufferedBodies,
+}
+
+/// Credentials the proxy asks for before letting requests through to
+/// a project
+#[derive(Deserialize, Serialize)]
+pub struct BasicAuthRequest {
+    pub username: String,
+    pub password: String,
+}
+
+/// The password is never sent back, not even hashed
+#[derive(Debug, Deserialize, Serialize, Eq, PartialEq)]
+pub struct BasicAuthResponse {
+    pub username: String,
+}
+
+/// How many requests a project accepts through the proxy
+#[derive(Clone, Debug, Deserialize, Serialize, Eq, PartialEq)]
+pub struct RateLimit {
+    /// Sustained number of requests allowed every second
+    pub requests_per_second: u32,
+    /// Number of requests allowed at once on top of the sustained rate
+    pub burst: u32,
+}
+
+/// How long the deployments of a project have to build, and then to
+/// load, before they are marked as crashed
+#[derive(Clone, Debug, Deserialize, Serialize, Eq, PartialEq)]
+pub struct DeploymentTimeout {
+    pub timeout_secs: u32,
+}
+
+/// What visitors of a project get while it switches over to a new
+/// deploy, in place of the errors the proxy would answer them with
+#[derive(Clone, Debug, Deserialize, Serialize, Eq, PartialEq)]
+pub struct MaintenancePage {
+    /// The HTML of the page
+    pub body: String,
+    /// How long visitors are told to wait before trying again
+    pub retry_after_secs: u32,
+}
+
+/// How much of the host the container of a project can use, set for
+/// an account as the default of its projects or for a single project
+#[derive(Clone, Debug, Deserialize, Serialize, Eq, PartialEq)]
+pub struct ResourceLimits {
+    /// Hard limit on memory, in MiB
+    pub memory_mib: u32,
+    /// Thousandths of a CPU, so that 1000 is one whole CPU
+    pub millicpus: u32,
+}
+
+/// Part of the traffic of a project sent to another project of the
+/// same account, running a new version of it
+#[derive(Clone, Debug, Deserialize, Serialize, Eq, PartialEq)]
+pub struct Canary {
+    /// The project running the new version
+    pub project: String,
+    /// Percentage of the requests sent to the canary, from 0 to 100.
+    /// Promoting the canary sets it to 100.
+    pub weight: u8,
+    /// Send every client to the same side of the split, going by its
+    /// IP address
+    #[serde(default)]
+    pub sticky: bool,
+}
+
+/// How many requests a project has open with the proxy at once
+#[derive(Clone, Debug, Deserialize, Serialize, Eq, PartialEq)]
+pub struct ConnectionLimit {
+    pub max_connections: u32,
+}
+
+/// Responses the proxy keeps for a project, to answer `GET`s with
+/// without going to it
+#[derive(Clone, Debug, Deserialize, Serialize, Eq, PartialEq)]
+pub struct ResponseCache {
+    /// Most responses kept at once, with the oldest let go of first
+    pub max_entries: u32,
+}
+
+/// Client IP ranges, in CIDR notation, allowed or denied to reach a
+/// project through the proxy
+#[derive(Clone, Debug, Default, Deserialize, Serialize, Eq, PartialEq)]
+pub struct IpFilter {
+    /// Only these ranges get through, unless this is empty
+    #[serde(default)]
+    pub allow: Vec<String>,
+    /// These ranges never get through, even if they are allowed
+    #[serde(default)]
+    pub deny: Vec<String>,
+}
+
+/// Headers rewritten by the proxy on the way to and from a project
+#[derive(Clone, Debug, Default, Deserialize, Serialize, Eq, PartialEq)]
+pub struct HeaderRules {
+    /// Applied to requests before they reach the project
+    #[serde(default)]
+    pub request: HeaderRuleSet,
+    /// Applied to responses before they reach the client
+    #[serde(default)]
+    pub response: HeaderRuleSet,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize, Eq, PartialEq)]
+pub struct HeaderRuleSet {
+    /// Headers added next to the ones already there
+    #[serde(default)]
+    pub add: BTreeMap<String, String>,
+    /// Headers replacing any already there under the same name
+    #[serde(default)]
+    pub set: BTreeMap<String, String>,
+    /// Headers stripped
+    #[serde(default)]
+    pub remove: Vec<String>,
+}
+
+#[derive(Default, Deserialize, Serialize)]
+pub struct RolloutRequest {
+    /// Image to move projects onto. Defaults to the gateway's image
+    pub image: Option<String>,
+    /// How many projects are recreated at the same time
+    pub concurrency: Option<usize>,
+}
+
+#[derive(Clone, Debug, Deserialize, Display, Serialize, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+#[strum(serialize_all = "lowercase")]
+pub enum RolloutState {
+    Running,
+    Completed,
+    /// Stopped early after too many projects failed to be recreated
+    Halted,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct RolloutStatus {
+    pub image: String,
+    pub state: RolloutState,
+    pub started_at: DateTime<Utc>,
+    /// Number of projects to move onto the image
+    pub total: usize,
+    pub migrated: Vec<String>,
+    pub failed: Vec<String>,
+}
+
+/// What is sent to a project's webhook when its state changes
+#[derive(Debug, Deserialize, Serialize)]
+pub struct WebhookEvent {
+    pub project_name: String,
+    pub state: State,
+    pub timestamp: DateTime<Utc>,
+}
+
+/// Version of the [`ExportManifest`] format. Bumped whenever a change
+/// would trip up readers of an older version
+pub const EXPORT_MANIFEST_VERSION: u32 = 1;
+
+/// The projects of an account, one page at a time
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ExportManifest {
+    pub version: u32,
+    pub account_name: String,
+    pub projects: Vec<ExportedProject>,
+    /// Pass as `after` to get the next page. Missing on the last page
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub next: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ExportedProject {
+    pub name: String,
+    pub state: State,
+    /// Names of the environment variables set on the project. Their
+    /// values are never exported
+    pub env: Vec<String>,
+    pub custom_domains: Vec<String>,
+}
+
 pub fn get_table(projects: &Vec<Response>) -> String {
     if projects.is_empty() {
         format!(
@@ -1,3 +1,6 @@
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
@@ -11,3 +14,85 @@ pub struct LoadResponse {
     pub builds_count: usize,
     pub has_capacity: bool,
 }
+
+#[derive(Deserialize, Serialize)]
+pub struct TaskSummary {
+    pub id: Uuid,
+    pub project_name: String,
+    pub state: String,
+    pub started_at: DateTime<Utc>,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct TasksResponse {
+    pub queue_depth: usize,
+    pub oldest_task_age_secs: Option<i64>,
+    pub tasks: Vec<TaskSummary>,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct CacheResponse {
+    pub hits: u64,
+    pub misses: u64,
+    pub hit_ratio: Option<f64>,
+}
+
+/// Bytes in the bodies which went through the user proxy for a
+/// project, counted since the gateway last started
+#[derive(Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub struct TrafficResponse {
+    pub bytes_in: u64,
+    pub bytes_out: u64,
+}
+
+/// The state of the circuit breaker in front of Docker
+#[derive(Deserialize, Serialize)]
+pub struct DockerBreakerResponse {
+    /// One of `closed`, `open` or `half-open`
+    pub state: String,
+    pub consecutive_failures: u32,
+    /// Seconds until the next probe, when open
+    pub retry_in_secs: Option<u64>,
+}
+
+/// The filter deciding which traces a service emits
+#[derive(Deserialize, Serialize)]
+pub struct LogLevel {
+    /// Tracing filter directives, like `info,shuttle_gateway=debug`
+    pub filter: String,
+}
+
+/// How far along taking the node out of rotation is
+#[derive(Deserialize, Serialize)]
+pub struct DrainResponse {
+    pub draining: bool,
+    /// The projects still running on the node
+    pub running_projects: Vec<String>,
+}
+
+/// A certificate issued on request
+#[derive(Deserialize, Serialize)]
+pub struct CertificateResponse {
+    pub fqdn: String,
+    /// Whether it took the place of a certificate the domain had
+    pub replaced: bool,
+    pub not_after: DateTime<Utc>,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct CapacityResponse {
+    pub running_projects: usize,
+    pub max_running_projects: Option<usize>,
+    pub has_capacity: bool,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct PlatformResponse {
+    pub accounts: u64,
+    /// How many projects are in each state
+    pub projects: BTreeMap<String, u64>,
+    /// Counted since the gateway last started
+    pub deployments_today: u64,
+    /// Counted since the gateway last started
+    pub proxy_requests: u64,
+}
@@ -1,3 +1,4 @@
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 
 #[derive(Deserialize, Serialize)]
@@ -6,3 +7,78 @@ pub struct Response {
     pub key: String,
     pub projects: Vec<String>,
 }
+
+/// Something a scoped key can be allowed to do
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum Action {
+    /// Look at projects and their deployments
+    Read,
+    /// Create new projects
+    Create,
+    /// Deploy to and manage the deployments of projects
+    Deploy,
+    /// Change the settings of projects, such as their env vars
+    Configure,
+    /// Destroy projects
+    Delete,
+}
+
+/// Mint a key restricted to some projects and/or actions. A missing
+/// restriction means the key is not restricted on it.
+#[derive(Deserialize, Serialize, Default)]
+pub struct ScopedKeyRequest {
+    pub projects: Option<Vec<String>>,
+    pub actions: Option<Vec<Action>>,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct ScopedKeyResponse {
+    #[serde(default)]
+    pub id: String,
+    pub key: String,
+    pub projects: Option<Vec<String>>,
+    pub actions: Option<Vec<Action>>,
+}
+
+/// What a key is restricted to, as listed to its owner
+#[derive(Deserialize, Serialize)]
+pub struct KeyScope {
+    pub projects: Option<Vec<String>>,
+    pub actions: Option<Vec<Action>>,
+}
+
+/// Everything known about a key except its secret
+#[derive(Deserialize, Serialize)]
+pub struct KeyResponse {
+    pub id: String,
+    pub created_at: Option<DateTime<Utc>>,
+    pub last_used_at: Option<DateTime<Utc>>,
+    /// `None` for the account's own key, which is unrestricted
+    pub scope: Option<KeyScope>,
+}
+
+/// Act as another account for support. The token only reads, unless
+/// asked otherwise.
+#[derive(Deserialize, Serialize, Default)]
+pub struct ImpersonateRequest {
+    #[serde(default)]
+    pub allow_writes: bool,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct ImpersonateResponse {
+    pub account_name: String,
+    pub key: String,
+    pub read_only: bool,
+    pub expires_at: DateTime<Utc>,
+}
+
+/// Something done by `actor` while acting as `effective`
+#[derive(Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub struct AuditEntry {
+    pub at: DateTime<Utc>,
+    pub actor: String,
+    pub effective: String,
+    pub action: String,
+}
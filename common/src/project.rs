@@ -37,6 +37,12 @@ impl std::fmt::Display for ProjectName {
 
 impl ProjectName {
     pub fn is_valid(hostname: &str) -> bool {
+        Self::validate(hostname).is_ok()
+    }
+
+    /// Check `hostname` against the rules above, returning the first
+    /// one it violates
+    pub fn validate(hostname: &str) -> Result<(), ProjectNameError> {
         fn is_valid_char(byte: u8) -> bool {
             matches!(byte, b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'-' | b'_')
         }
@@ -58,12 +64,34 @@ impl ProjectName {
 
         let separators = ['-', '_'];
 
-        !(hostname.bytes().any(|byte| !is_valid_char(byte))
-            || is_reserved(hostname)
-            || !is_profanity_free(hostname)
-            || hostname.ends_with(separators)
-            || hostname.starts_with(separators)
-            || hostname.is_empty())
+        if hostname.is_empty() {
+            return Err(ProjectNameError::Empty);
+        }
+
+        if is_reserved(hostname) {
+            return Err(ProjectNameError::Reserved(hostname.to_string()));
+        }
+
+        if let Some(c) = hostname
+            .chars()
+            .find(|c| !c.is_ascii() || !is_valid_char(*c as u8))
+        {
+            return Err(ProjectNameError::InvalidChar(c));
+        }
+
+        if let Some(c) = hostname.chars().next().filter(|c| separators.contains(c)) {
+            return Err(ProjectNameError::LeadingSeparator(c));
+        }
+
+        if let Some(c) = hostname.chars().last().filter(|c| separators.contains(c)) {
+            return Err(ProjectNameError::TrailingSeparator(c));
+        }
+
+        if !is_profanity_free(hostname) {
+            return Err(ProjectNameError::Profanity);
+        }
+
+        Ok(())
     }
 
     pub fn as_str(&self) -> &str {
@@ -81,32 +109,45 @@ impl FromStr for ProjectName {
     type Err = ProjectNameError;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        match ProjectName::is_valid(s) {
-            true => Ok(ProjectName(s.to_string())),
-            false => Err(ProjectNameError::InvalidName(s.to_string())),
-        }
+        ProjectName::validate(s).map(|_| ProjectName(s.to_string()))
     }
 }
 
-#[derive(Debug)]
+/// The rule a project name was rejected for
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum ProjectNameError {
-    InvalidName(String),
+    Empty,
+    /// Longer than the given number of characters
+    TooLong(usize),
+    InvalidChar(char),
+    Uppercase,
+    LeadingSeparator(char),
+    TrailingSeparator(char),
+    Profanity,
+    Reserved(String),
 }
 
 impl Display for ProjectNameError {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         match self {
-            ProjectNameError::InvalidName(name) => write!(
-                f,
-                r#"
-`{}` is an invalid project name. project name must
-1. start and end with alphanumeric characters.
-2. only contain characters inside of the alphanumeric range, except for `-`, or `_`.
-3. not be empty.,
-4. not contain profanity.
-5. not be a reserved word."#,
-                name
-            ),
+            ProjectNameError::Empty => write!(f, "project name must not be empty"),
+            ProjectNameError::TooLong(max) => {
+                write!(f, "project name must not be longer than {max} characters")
+            }
+            ProjectNameError::InvalidChar(c) => {
+                write!(f, "project name must not contain the character `{c}`")
+            }
+            ProjectNameError::Uppercase => {
+                write!(f, "project name must not contain uppercase characters")
+            }
+            ProjectNameError::LeadingSeparator(c) => {
+                write!(f, "project name must not start with `{c}`")
+            }
+            ProjectNameError::TrailingSeparator(c) => {
+                write!(f, "project name must not end with `{c}`")
+            }
+            ProjectNameError::Profanity => write!(f, "project name must not contain profanity"),
+            ProjectNameError::Reserved(name) => write!(f, "`{name}` is a reserved name"),
         }
     }
 }
@@ -163,4 +204,44 @@ pub mod tests {
             assert!(project_name.is_err(), "{:?} was ok", hostname);
         }
     }
+
+    #[test]
+    fn invalid_hostname_details() {
+        for (hostname, error, message) in [
+            (
+                "",
+                ProjectNameError::Empty,
+                "project name must not be empty",
+            ),
+            (
+                "asdf@fasd",
+                ProjectNameError::InvalidChar('@'),
+                "project name must not contain the character `@`",
+            ),
+            (
+                "-invalid-name",
+                ProjectNameError::LeadingSeparator('-'),
+                "project name must not start with `-`",
+            ),
+            (
+                "invalid__",
+                ProjectNameError::TrailingSeparator('_'),
+                "project name must not end with `_`",
+            ),
+            (
+                "test-condom-condom",
+                ProjectNameError::Profanity,
+                "project name must not contain profanity",
+            ),
+            (
+                "shuttle.rs",
+                ProjectNameError::Reserved("shuttle.rs".to_string()),
+                "`shuttle.rs` is a reserved name",
+            ),
+        ] {
+            let err = ProjectName::from_str(hostname).unwrap_err();
+            assert_eq!(err, error, "{:?} gave the wrong error", hostname);
+            assert_eq!(err.to_string(), message);
+        }
+    }
 }
@@ -0,0 +1,4 @@
+ALTER TABLE deployments ADD COLUMN created_at INTEGER; -- Unix epoch of when the deployment was queued
+
+-- The first update of older deployments is long gone so the last one has to do
+UPDATE deployments SET created_at = last_update WHERE created_at IS NULL;
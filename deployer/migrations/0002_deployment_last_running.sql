@@ -0,0 +1,6 @@
+ALTER TABLE deployments ADD COLUMN last_running INTEGER; -- Unix epoch of when the deployment was last seen running
+
+-- Older deployments only have their state logs to go by
+UPDATE deployments SET last_running = (
+    SELECT MAX(timestamp) FROM logs WHERE logs.id = deployments.id AND logs.state = 'Running'
+);
@@ -0,0 +1,2 @@
+ALTER TABLE deployments ADD COLUMN deployed_by TEXT; -- Account which made the deployment
+ALTER TABLE deployments ADD COLUMN label TEXT; -- What was deployed, such as a commit or a version
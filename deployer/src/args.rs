@@ -49,4 +49,14 @@ pub struct Args {
     /// Uri to folder to store all artifacts
     #[clap(long, default_value = "/tmp")]
     pub artifacts_path: PathBuf,
+
+    /// Number of deployments kept in the history of every service.
+    /// Older ones are pruned in the background
+    #[clap(long, default_value = "50")]
+    pub deployment_retention: u32,
+
+    /// Seconds a deployment can spend building, and then loading, before
+    /// it is marked as crashed
+    #[clap(long, default_value = "1800")]
+    pub deployment_timeout: u64,
 }
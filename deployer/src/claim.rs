@@ -0,0 +1,87 @@
+use std::{io, path::Path, time::Duration};
+
+/// How often a warm deployer looks for its claim
+const CLAIM_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Complete the command line of a deployer started with `--claim <path>`.
+///
+/// The gateway keeps containers warm before knowing which project they
+/// will serve. They start with `--claim <path>` instead of the arguments
+/// naming their project, and wait for the gateway to write those
+/// arguments to `path`, as a JSON array, once a project claims them. The
+/// file is kept on the project volume, so the container is recreated
+/// with the same claim.
+pub async fn claimed_args(mut args: Vec<String>) -> io::Result<Vec<String>> {
+    let at = match args.iter().position(|arg| arg == "--claim") {
+        Some(at) => at,
+        None => return Ok(args),
+    };
+    let path = args
+        .get(at + 1)
+        .cloned()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "--claim needs a path"))?;
+    args.drain(at..at + 2);
+
+    let claim = wait_for_claim(Path::new(&path)).await?;
+    let claimed: Vec<String> = serde_json::from_slice(&claim)
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+    args.extend(claimed);
+
+    Ok(args)
+}
+
+async fn wait_for_claim(path: &Path) -> io::Result<Vec<u8>> {
+    loop {
+        match tokio::fs::read(path).await {
+            Ok(claim) => return Ok(claim),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                tokio::time::sleep(CLAIM_POLL_INTERVAL).await
+            }
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempdir::TempDir;
+
+    use super::*;
+
+    fn strings(args: &[&str]) -> Vec<String> {
+        args.iter().map(ToString::to_string).collect()
+    }
+
+    #[tokio::test]
+    async fn claimed_args() {
+        let args = strings(&["deployer", "--project", "matrix"]);
+        assert_eq!(super::claimed_args(args.clone()).await.unwrap(), args);
+
+        let dir = TempDir::new("claim").unwrap();
+        let path = dir.path().join("claim.json");
+        let args = strings(&[
+            "deployer",
+            "--claim",
+            path.to_str().unwrap(),
+            "--api-address",
+            "0.0.0.0:8001",
+        ]);
+
+        let claimed = tokio::spawn(super::claimed_args(args));
+
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        assert!(!claimed.is_finished());
+
+        std::fs::write(&path, r#"["--project", "matrix"]"#).unwrap();
+        assert_eq!(
+            claimed.await.unwrap().unwrap(),
+            strings(&[
+                "deployer",
+                "--api-address",
+                "0.0.0.0:8001",
+                "--project",
+                "matrix"
+            ])
+        );
+    }
+}
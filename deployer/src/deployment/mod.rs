@@ -7,6 +7,7 @@ pub mod runtime_logger;
 mod storage_manager;
 
 use std::path::PathBuf;
+use std::time::Duration;
 
 pub use queue::Queued;
 pub use run::{ActiveDeploymentsGetter, Built};
@@ -25,6 +26,10 @@ const QUEUE_BUFFER_SIZE: usize = 100;
 const RUN_BUFFER_SIZE: usize = 100;
 const KILL_BUFFER_SIZE: usize = 10;
 
+/// How long a deployment has to build, and then to load, when no other
+/// timeout is given
+pub const DEFAULT_DEPLOYMENT_TIMEOUT: Duration = Duration::from_secs(30 * 60);
+
 pub struct DeploymentManagerBuilder<AF, RLF, LR, SR, ADG, QC> {
     abstract_factory: Option<AF>,
     runtime_logger_factory: Option<RLF>,
@@ -33,6 +38,7 @@ pub struct DeploymentManagerBuilder<AF, RLF, LR, SR, ADG, QC> {
     active_deployment_getter: Option<ADG>,
     artifacts_path: Option<PathBuf>,
     queue_client: Option<QC>,
+    deployment_timeout: Option<Duration>,
 }
 
 impl<AF, RLF, LR, SR, ADG, QC> DeploymentManagerBuilder<AF, RLF, LR, SR, ADG, QC>
@@ -86,6 +92,14 @@ where
         self
     }
 
+    /// Mark deployments which are not running after building for, or
+    /// then loading for, `deployment_timeout` as crashed
+    pub fn deployment_timeout(mut self, deployment_timeout: Duration) -> Self {
+        self.deployment_timeout = Some(deployment_timeout);
+
+        self
+    }
+
     /// Creates two Tokio tasks, one for building queued services, the other for
     /// executing/deploying built services. Two multi-producer, single consumer
     /// channels are also created which are for moving on-going service
@@ -106,6 +120,9 @@ where
             .expect("an active deployment getter to be set");
         let artifacts_path = self.artifacts_path.expect("artifacts path to be set");
         let queue_client = self.queue_client.expect("a queue client to be set");
+        let deployment_timeout = self
+            .deployment_timeout
+            .unwrap_or(DEFAULT_DEPLOYMENT_TIMEOUT);
 
         let (queue_send, queue_recv) = mpsc::channel(QUEUE_BUFFER_SIZE);
         let (run_send, run_recv) = mpsc::channel(RUN_BUFFER_SIZE);
@@ -121,6 +138,7 @@ where
             secret_recorder,
             storage_manager.clone(),
             queue_client,
+            deployment_timeout,
         ));
         tokio::spawn(run::task(
             run_recv,
@@ -129,6 +147,7 @@ where
             runtime_logger_factory,
             active_deployment_getter,
             storage_manager.clone(),
+            deployment_timeout,
         ));
 
         DeploymentManager {
@@ -175,6 +194,7 @@ impl DeploymentManager {
             active_deployment_getter: None,
             artifacts_path: None,
             queue_client: None,
+            deployment_timeout: None,
         }
     }
 
@@ -38,6 +38,7 @@ pub async fn task(
     secret_recorder: impl SecretRecorder,
     storage_manager: StorageManager,
     queue_client: impl BuildQueueClient,
+    deployment_timeout: Duration,
 ) {
     info!("Queue task started");
 
@@ -70,12 +71,16 @@ pub async fn task(
                     Err(err) => return build_failed(&id, err),
                 }
 
-                match queued
-                    .handle(storage_manager, log_recorder, secret_recorder)
-                    .await
+                // A build which hangs would otherwise keep its slot forever
+                match timeout(
+                    deployment_timeout,
+                    queued.handle(storage_manager, log_recorder, secret_recorder),
+                )
+                .await
                 {
-                    Ok(built) => promote_to_run(built, run_send_cloned).await,
-                    Err(err) => build_failed(&id, err),
+                    Ok(Ok(built)) => promote_to_run(built, run_send_cloned).await,
+                    Ok(Err(err)) => build_failed(&id, err),
+                    Err(_) => build_failed(&id, Error::Timeout(deployment_timeout)),
                 }
 
                 remove_from_queue(queue_client, id).await
@@ -3,6 +3,7 @@ use std::{
     net::{Ipv4Addr, SocketAddr},
     path::PathBuf,
     str::FromStr,
+    time::{Duration, Instant},
 };
 
 use async_trait::async_trait;
@@ -13,7 +14,7 @@ use shuttle_service::{
     loader::{LoadedService, Loader},
     Factory, Logger,
 };
-use tokio::task::JoinError;
+use tokio::{task::JoinError, time::timeout};
 use tracing::{debug, debug_span, error, info, instrument, trace, Instrument};
 use tracing_opentelemetry::OpenTelemetrySpanExt;
 use uuid::Uuid;
@@ -27,6 +28,7 @@ use crate::error::{Error, Result};
 /// Run a task which takes runnable deploys from a channel and starts them up with a factory provided by the
 /// abstract factory and a runtime logger provided by the logger factory
 /// A deploy is killed when it receives a signal from the kill channel
+/// A deploy which is not running after `deployment_timeout` is marked as crashed
 pub async fn task(
     mut recv: RunReceiver,
     kill_send: KillSender,
@@ -34,6 +36,7 @@ pub async fn task(
     logger_factory: impl runtime_logger::Factory,
     active_deployment_getter: impl ActiveDeploymentsGetter,
     storage_manager: StorageManager,
+    deployment_timeout: Duration,
 ) {
     info!("Run task started");
 
@@ -118,6 +121,7 @@ pub async fn task(
                         kill_recv,
                         old_deployments_killer,
                         cleanup,
+                        deployment_timeout,
                     )
                     .await
                 {
@@ -201,7 +205,7 @@ pub struct Built {
 }
 
 impl Built {
-    #[instrument(skip(self, storage_manager, factory, logger, kill_recv, kill_old_deployments, cleanup), fields(id = %self.id, state = %State::Loading))]
+    #[instrument(skip(self, storage_manager, factory, logger, kill_recv, kill_old_deployments, cleanup, deployment_timeout), fields(id = %self.id, state = %State::Loading))]
     #[allow(clippy::too_many_arguments)]
     async fn handle(
         self,
@@ -214,15 +218,40 @@ impl Built {
         cleanup: impl FnOnce(std::result::Result<std::result::Result<(), shuttle_service::Error>, JoinError>)
             + Send
             + 'static,
+        deployment_timeout: Duration,
     ) -> Result<()> {
         let so_path = storage_manager.deployment_library_path(&self.id)?;
-        let service = load_deployment(address, so_path, factory, logger).await?;
+        let started = Instant::now();
 
-        kill_old_deployments.await?;
+        // Loading provisions the resources of the service, which can hang
+        let (handle, library) = timeout(
+            deployment_timeout,
+            load_deployment(address, so_path, factory, logger),
+        )
+        .await
+        .map_err(|_| Error::Timeout(deployment_timeout))??;
+
+        let remaining = deployment_timeout.saturating_sub(started.elapsed());
+        let killed_old = match timeout(remaining, kill_old_deployments).await {
+            Ok(res) => res,
+            Err(_) => Err(Error::Timeout(deployment_timeout)),
+        };
+
+        // The service was started by the loader, so it has to be stopped
+        // before its library can be closed
+        if let Err(err) = killed_old {
+            handle.abort();
+            let _ = handle.await;
+            if let Err(close_err) = library.close() {
+                crashed_cleanup(&self.id, close_err);
+            }
+
+            return Err(err);
+        }
 
         info!("got handle for deployment");
         // Execute loaded service
-        tokio::spawn(run(self.id, service, address, kill_recv, cleanup));
+        tokio::spawn(run(self.id, (handle, library), address, kill_recv, cleanup));
 
         Ok(())
     }
@@ -302,6 +331,7 @@ mod tests {
     use super::Built;
 
     const RESOURCES_PATH: &str = "tests/resources";
+    const TIMEOUT: Duration = Duration::from_secs(60);
 
     struct StubFactory;
 
@@ -392,6 +422,7 @@ mod tests {
                 kill_recv,
                 kill_old_deployments(),
                 handle_cleanup,
+                TIMEOUT,
             )
             .await
             .unwrap();
@@ -440,6 +471,7 @@ mod tests {
                 kill_recv,
                 kill_old_deployments(),
                 handle_cleanup,
+                TIMEOUT,
             )
             .await
             .unwrap();
@@ -482,6 +514,7 @@ mod tests {
                 kill_recv,
                 kill_old_deployments(),
                 handle_cleanup,
+                TIMEOUT,
             )
             .await
             .unwrap();
@@ -512,6 +545,7 @@ mod tests {
                 kill_recv,
                 kill_old_deployments(),
                 handle_cleanup,
+                TIMEOUT,
             )
             .await;
 
@@ -522,6 +556,40 @@ mod tests {
         );
     }
 
+    // A deployment which never gets ready is stopped once its time is up
+    #[tokio::test]
+    async fn times_out() {
+        let (built, storage_manager) = make_so_and_built("sleep-async");
+        let (_kill_send, kill_recv) = broadcast::channel(1);
+
+        let handle_cleanup = |_result| panic!("the service should never reach running");
+        let addr = SocketAddr::new(Ipv4Addr::LOCALHOST.into(), 8001);
+        let mut factory = StubFactory;
+        let logger = get_logger(built.id);
+
+        let result = tokio::time::timeout(
+            Duration::from_secs(5),
+            built.handle(
+                addr,
+                storage_manager,
+                &mut factory,
+                logger,
+                kill_recv,
+                futures::future::pending(),
+                handle_cleanup,
+                Duration::from_secs(1),
+            ),
+        )
+        .await
+        .expect("the deployment should have timed out on its own");
+
+        assert!(
+            matches!(result, Err(Error::Timeout(timeout)) if timeout == Duration::from_secs(1)),
+            "expected the deployment to time out: {:?}",
+            result
+        );
+    }
+
     #[tokio::test]
     async fn missing_so() {
         let built = Built {
@@ -547,6 +615,7 @@ mod tests {
                 kill_recv,
                 kill_old_deployments(),
                 handle_cleanup,
+                TIMEOUT,
             )
             .await;
 
@@ -1,5 +1,6 @@
 use std::error::Error as StdError;
 use std::io;
+use std::time::Duration;
 use thiserror::Error;
 
 use shuttle_service::loader::LoaderError;
@@ -30,6 +31,8 @@ pub enum Error {
     OldCleanup(#[source] Box<dyn StdError + Send>),
     #[error("Gateway client error: {0}")]
     GatewayClient(#[from] gateway_client::Error),
+    #[error("Deployment did not start within {0:?}")]
+    Timeout(Duration),
 }
 
 #[derive(Error, Debug)]
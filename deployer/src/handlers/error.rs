@@ -21,6 +21,10 @@ pub enum Error {
     },
     #[error("record could not be found")]
     NotFound,
+    #[error("there is no previous successful deployment to roll back to")]
+    NoRollback,
+    #[error("the upload of the crate was cut short: {0}")]
+    IncompleteUpload(String),
     #[error("Custom error: {0}")]
     Custom(#[from] anyhow::Error),
 }
@@ -42,6 +46,8 @@ impl IntoResponse for Error {
     fn into_response(self) -> Response {
         let code = match self {
             Error::NotFound => StatusCode::NOT_FOUND,
+            Error::NoRollback => StatusCode::CONFLICT,
+            Error::IncompleteUpload(_) => StatusCode::BAD_REQUEST,
             _ => StatusCode::INTERNAL_SERVER_ERROR,
         };
 
@@ -54,6 +60,8 @@ impl IntoResponse for Error {
             Json(ApiError {
                 message: self.to_string(),
                 status_code: code.as_u16(),
+                code: None,
+                suggestions: Vec::new(),
             }),
         )
             .into_response()
@@ -3,14 +3,15 @@ mod error;
 use axum::body::{Body, BoxBody};
 use axum::extract::ws::{self, WebSocket};
 use axum::extract::{Extension, MatchedPath, Path, Query};
-use axum::http::{Request, Response};
+use axum::http::header::CONTENT_LENGTH;
+use axum::http::{HeaderMap, Request, Response};
 use axum::middleware::from_extractor;
 use axum::routing::{get, post, Router};
 use axum::{extract::BodyStream, Json};
-use bytes::BufMut;
+use bytes::{BufMut, Bytes};
 use chrono::{TimeZone, Utc};
 use fqdn::FQDN;
-use futures::StreamExt;
+use futures::{Stream, StreamExt};
 use opentelemetry::global;
 use opentelemetry_http::HeaderExtractor;
 use shuttle_common::backends::metrics::Metrics;
@@ -24,7 +25,7 @@ use tracing::{debug, debug_span, error, field, instrument, trace, Span};
 use tracing_opentelemetry::OpenTelemetrySpanExt;
 use uuid::Uuid;
 
-use crate::deployment::{DeploymentManager, Queued};
+use crate::deployment::{Built, DeploymentManager, Queued};
 use crate::persistence::{Deployment, Log, Persistence, SecretGetter, State};
 
 use std::collections::HashMap;
@@ -32,6 +33,13 @@ use std::time::Duration;
 
 pub use {self::error::Error, self::error::Result};
 
+/// Number of deployments in a page of the history unless asked otherwise
+pub const DEPLOYMENT_HISTORY_PAGE_SIZE: u32 = 20;
+/// Largest page of the history which can be asked for
+pub const DEPLOYMENT_HISTORY_MAX_PAGE_SIZE: u32 = 100;
+/// Header the gateway names the account behind a request with
+pub const ACCOUNT_NAME_HEADER: &str = "X-Shuttle-Account-Name";
+
 mod project;
 
 pub fn make_router(
@@ -51,6 +59,7 @@ pub fn make_router(
             "/projects/:project_name/services/:service_name/summary",
             get(get_service_summary),
         )
+        .route("/projects/:project_name/deployments", get(get_deployments))
         .route(
             "/projects/:project_name/deployments/:deployment_id",
             get(get_deployment).delete(delete_deployment),
@@ -67,6 +76,7 @@ pub fn make_router(
             "/projects/:project_name/secrets/:service_name",
             get(get_secrets),
         )
+        .route("/projects/:project_name/rollback", post(post_rollback))
         .route("/projects/:project_name/clean", post(post_clean))
         .layer(Extension(persistence))
         .layer(Extension(deployment_manager))
@@ -86,7 +96,7 @@ pub fn make_router(
 
                     let account_name = request
                         .headers()
-                        .get("X-Shuttle-Account-Name")
+                        .get(ACCOUNT_NAME_HEADER)
                         .map(|value| value.to_str().unwrap_or_default());
 
                     let span = debug_span!(
@@ -137,6 +147,29 @@ async fn list_services(
     Ok(Json(services))
 }
 
+#[instrument(skip_all, fields(%project_name))]
+async fn get_deployments(
+    Extension(persistence): Extension<Persistence>,
+    Path(project_name): Path<String>,
+    Query(params): Query<HashMap<String, String>>,
+) -> Result<Json<Vec<shuttle_common::models::deployment::HistoryEntry>>> {
+    let param = |name: &str| params.get(name).and_then(|value| value.parse::<u32>().ok());
+
+    let page = param("page").unwrap_or(0);
+    let limit = param("limit")
+        .unwrap_or(DEPLOYMENT_HISTORY_PAGE_SIZE)
+        .clamp(1, DEPLOYMENT_HISTORY_MAX_PAGE_SIZE);
+
+    let deployments = persistence
+        .get_deployment_history(limit, page.saturating_mul(limit))
+        .await?
+        .into_iter()
+        .map(Into::into)
+        .collect();
+
+    Ok(Json(deployments))
+}
+
 #[instrument(skip(persistence))]
 async fn get_service(
     Extension(persistence): Extension<Persistence>,
@@ -212,27 +245,73 @@ async fn post_service(
     Extension(deployment_manager): Extension<DeploymentManager>,
     Path((project_name, service_name)): Path<(String, String)>,
     Query(params): Query<HashMap<String, String>>,
-    mut stream: BodyStream,
+    headers: HeaderMap,
+    stream: BodyStream,
 ) -> Result<Json<shuttle_common::models::deployment::Response>> {
-    let service = persistence.get_or_create_service(&service_name).await?;
+    let (deployment, queued) =
+        accept_upload(&persistence, &service_name, &params, &headers, stream).await?;
+
+    deployment_manager.queue_push(queued).await;
+
+    Ok(Json(deployment.into()))
+}
+
+/// Receive the whole of an uploaded crate, and only then record a
+/// deployment of it. Nothing is recorded for uploads which are cut
+/// short.
+async fn accept_upload<S>(
+    persistence: &Persistence,
+    service_name: &str,
+    params: &HashMap<String, String>,
+    headers: &HeaderMap,
+    mut stream: S,
+) -> Result<(Deployment, Queued)>
+where
+    S: Stream<Item = std::result::Result<Bytes, axum::Error>> + Unpin,
+{
+    let mut data = Vec::new();
+    while let Some(buf) = stream.next().await {
+        let buf = buf.map_err(|err| Error::IncompleteUpload(err.to_string()))?;
+        debug!("Received {} bytes", buf.len());
+        data.put(buf);
+    }
+    debug!("Received a total of {} bytes", data.len());
+
+    let expected = headers
+        .get(CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<usize>().ok());
+    if let Some(expected) = expected {
+        if data.len() != expected {
+            return Err(Error::IncompleteUpload(format!(
+                "received {} of {expected} bytes",
+                data.len()
+            )));
+        }
+    }
+
+    let service = persistence.get_or_create_service(service_name).await?;
     let id = Uuid::new_v4();
 
+    // Set by the gateway to the account the deployment is made with
+    let deployed_by = headers
+        .get(ACCOUNT_NAME_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .map(ToString::to_string);
+
     let deployment = Deployment {
         id,
         service_id: service.id,
         state: State::Queued,
         last_update: Utc::now(),
         address: None,
+        deployed_by,
+        label: params
+            .get("label")
+            .cloned()
+            .filter(|label| !label.is_empty()),
     };
 
-    let mut data = Vec::new();
-    while let Some(buf) = stream.next().await {
-        let buf = buf?;
-        debug!("Received {} bytes", buf.len());
-        data.put(buf);
-    }
-    debug!("Received a total of {} bytes", data.len());
-
     persistence.insert_deployment(deployment.clone()).await?;
 
     let queued = Queued {
@@ -244,9 +323,7 @@ async fn post_service(
         tracing_context: Default::default(),
     };
 
-    deployment_manager.queue_push(queued).await;
-
-    Ok(Json(deployment.into()))
+    Ok((deployment, queued))
 }
 
 #[instrument(skip_all, fields(%project_name, %service_name))]
@@ -319,6 +396,51 @@ async fn delete_deployment(
     }
 }
 
+/// Run the previous successful deployment again, in place of the
+/// newest one of the project
+#[instrument(skip_all, fields(%project_name))]
+async fn post_rollback(
+    Extension(persistence): Extension<Persistence>,
+    Extension(deployment_manager): Extension<DeploymentManager>,
+    Path(project_name): Path<String>,
+) -> Result<Json<shuttle_common::models::deployment::Response>> {
+    let newest = persistence
+        .get_deployment_history(1, 0)
+        .await?
+        .pop()
+        .ok_or(Error::NoRollback)?;
+    let rollback = persistence
+        .get_rollback_deployment(&newest.service_id)
+        .await?
+        .ok_or(Error::NoRollback)?;
+
+    // The library of a deployment is all it takes to run it again
+    let so_path = deployment_manager
+        .storage_manager()
+        .deployment_library_path(&rollback.id)
+        .map_err(anyhow::Error::from)?;
+    if !so_path.exists() {
+        return Err(Error::NoRollback);
+    }
+
+    let deployment = persistence
+        .get_deployment(&rollback.id)
+        .await?
+        .ok_or(Error::NotFound)?;
+
+    // Running it kills the deployments currently active for the service
+    deployment_manager
+        .run_push(Built {
+            id: rollback.id,
+            service_name: rollback.service_name,
+            service_id: rollback.service_id,
+            tracing_context: Default::default(),
+        })
+        .await;
+
+    Ok(Json(deployment.into()))
+}
+
 #[instrument(skip_all, fields(%project_name, %deployment_id))]
 async fn get_logs(
     Extension(persistence): Extension<Persistence>,
@@ -435,3 +557,87 @@ async fn post_clean(
 async fn get_status() -> String {
     "Ok".to_string()
 }
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use axum::http::StatusCode;
+    use axum::response::IntoResponse;
+    use futures::stream;
+
+    use super::*;
+
+    fn upload(
+        chunks: Vec<std::result::Result<&'static [u8], &'static str>>,
+    ) -> impl Stream<Item = std::result::Result<Bytes, axum::Error>> + Unpin {
+        stream::iter(chunks.into_iter().map(|chunk| {
+            chunk
+                .map(Bytes::from_static)
+                .map_err(|err| axum::Error::new(io::Error::new(io::ErrorKind::UnexpectedEof, err)))
+        }))
+    }
+
+    fn content_length(len: usize) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(CONTENT_LENGTH, len.into());
+        headers
+    }
+
+    #[tokio::test]
+    async fn partial_uploads_are_rejected() {
+        let (persistence, _) = Persistence::new_in_memory().await;
+        let params = HashMap::new();
+
+        // the connection drops mid-body
+        let err = accept_upload(
+            &persistence,
+            "matrix",
+            &params,
+            &content_length(8),
+            upload(vec![Ok(b"crat"), Err("connection reset")]),
+        )
+        .await
+        .unwrap_err();
+        assert!(matches!(err, Error::IncompleteUpload(_)), "{err:?}");
+
+        // the body ends before all it said it would send
+        let err = accept_upload(
+            &persistence,
+            "matrix",
+            &params,
+            &content_length(8),
+            upload(vec![Ok(b"crat")]),
+        )
+        .await
+        .unwrap_err();
+        assert!(matches!(err, Error::IncompleteUpload(_)), "{err:?}");
+        assert_eq!(err.into_response().status(), StatusCode::BAD_REQUEST);
+
+        // neither left a deployment behind
+        assert!(persistence
+            .get_service_by_name("matrix")
+            .await
+            .unwrap()
+            .is_none());
+
+        let (deployment, queued) = accept_upload(
+            &persistence,
+            "matrix",
+            &params,
+            &content_length(8),
+            upload(vec![Ok(b"crat"), Ok(b"e.gz")]),
+        )
+        .await
+        .unwrap();
+        assert_eq!(queued.data, b"crate.gz");
+        assert_eq!(
+            persistence
+                .get_deployments(&deployment.service_id)
+                .await
+                .unwrap()
+                .len(),
+            1
+        );
+    }
+}
@@ -1,6 +1,7 @@
-use std::{convert::Infallible, net::SocketAddr};
+use std::{convert::Infallible, net::SocketAddr, time::Duration};
 
 pub use args::Args;
+pub use claim::claimed_args;
 pub use deployment::{
     deploy_layer::DeployLayer, provisioner_factory::AbstractProvisionerFactory,
     runtime_logger::RuntimeLoggerFactory,
@@ -18,12 +19,16 @@ use tracing::{error, info};
 use crate::deployment::gateway_client::GatewayClient;
 
 mod args;
+mod claim;
 mod deployment;
 mod error;
 mod handlers;
 mod persistence;
 mod proxy;
 
+/// How often old deployments are pruned from the history
+const DEPLOYMENT_PRUNE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
 pub async fn start(
     abstract_factory: impl provisioner_factory::AbstractFactory,
     runtime_logger_factory: impl runtime_logger::Factory,
@@ -38,6 +43,7 @@ pub async fn start(
         .active_deployment_getter(persistence.clone())
         .artifacts_path(args.artifacts_path)
         .queue_client(GatewayClient::new(args.gateway_uri))
+        .deployment_timeout(Duration::from_secs(args.deployment_timeout))
         .build();
 
     persistence.cleanup_invalid_states().await.unwrap();
@@ -54,6 +60,11 @@ pub async fn start(
         deployment_manager.run_push(built).await;
     }
 
+    tokio::spawn(prune_deployments(
+        persistence.clone(),
+        args.deployment_retention,
+    ));
+
     let router = handlers::make_router(
         persistence,
         deployment_manager,
@@ -71,6 +82,23 @@ pub async fn start(
         .unwrap_or_else(|_| panic!("Failed to bind to address: {}", args.api_address));
 }
 
+async fn prune_deployments(persistence: Persistence, keep: u32) {
+    let mut interval = tokio::time::interval(DEPLOYMENT_PRUNE_INTERVAL);
+
+    loop {
+        interval.tick().await;
+
+        match persistence.prune_deployments(keep).await {
+            Ok(0) => {}
+            Ok(count) => info!(count, keep, "pruned old deployments"),
+            Err(error) => error!(
+                error = &error as &dyn std::error::Error,
+                "failed to prune old deployments"
+            ),
+        }
+    }
+}
+
 pub async fn start_proxy(
     proxy_address: SocketAddr,
     fqdn: FQDN,
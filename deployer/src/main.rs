@@ -1,7 +1,7 @@
 use clap::Parser;
 use opentelemetry::global;
 use shuttle_deployer::{
-    start, start_proxy, AbstractProvisionerFactory, Args, DeployLayer, Persistence,
+    claimed_args, start, start_proxy, AbstractProvisionerFactory, Args, DeployLayer, Persistence,
     RuntimeLoggerFactory,
 };
 use tokio::select;
@@ -14,7 +14,11 @@ use tracing_subscriber::{fmt, EnvFilter};
 // Without this, both threads just don't start up
 #[tokio::main(flavor = "multi_thread")]
 async fn main() {
-    let args = Args::parse();
+    // Warm containers only learn which project they serve once claimed
+    let args = claimed_args(std::env::args().collect())
+        .await
+        .expect("could not read the claim of this deployer");
+    let args = Args::parse_from(args);
 
     trace!(args = ?args, "parsed args");
 
@@ -14,6 +14,10 @@ pub struct Deployment {
     pub state: State,
     pub last_update: DateTime<Utc>,
     pub address: Option<SocketAddr>,
+    /// The account which made the deployment
+    pub deployed_by: Option<String>,
+    /// What was deployed, such as a commit or a version
+    pub label: Option<String>,
 }
 
 impl FromRow<'_, SqliteRow> for Deployment {
@@ -36,6 +40,8 @@ impl FromRow<'_, SqliteRow> for Deployment {
             state: row.try_get("state")?,
             last_update: row.try_get("last_update")?,
             address,
+            deployed_by: row.try_get("deployed_by")?,
+            label: row.try_get("label")?,
         })
     }
 }
@@ -47,6 +53,32 @@ impl From<Deployment> for shuttle_common::models::deployment::Response {
             service_id: deployment.service_id,
             state: deployment.state.into(),
             last_update: deployment.last_update,
+            deployed_by: deployment.deployed_by,
+            label: deployment.label,
+        }
+    }
+}
+
+/// A deployment as listed in the history of the project
+#[derive(sqlx::FromRow, Debug, PartialEq, Eq)]
+pub struct DeploymentHistoryEntry {
+    pub id: Uuid,
+    pub service_id: Uuid,
+    pub state: State,
+    pub created_at: DateTime<Utc>,
+    pub deployed_by: Option<String>,
+    pub label: Option<String>,
+}
+
+impl From<DeploymentHistoryEntry> for shuttle_common::models::deployment::HistoryEntry {
+    fn from(entry: DeploymentHistoryEntry) -> Self {
+        shuttle_common::models::deployment::HistoryEntry {
+            id: entry.id,
+            service_id: entry.service_id,
+            state: entry.state.into(),
+            created_at: entry.created_at,
+            deployed_by: entry.deployed_by,
+            label: entry.label,
         }
     }
 }
@@ -27,7 +27,7 @@ use tracing::{error, info, instrument, trace};
 use uuid::Uuid;
 
 use self::deployment::DeploymentRunnable;
-pub use self::deployment::{Deployment, DeploymentState};
+pub use self::deployment::{Deployment, DeploymentHistoryEntry, DeploymentState};
 pub use self::error::Error as PersistenceError;
 pub use self::log::{Level as LogLevel, Log};
 pub use self::resource::{Resource, ResourceRecorder, Type as ResourceType};
@@ -66,7 +66,7 @@ impl Persistence {
     }
 
     #[allow(dead_code)]
-    async fn new_in_memory() -> (Self, JoinHandle<()>) {
+    pub(crate) async fn new_in_memory() -> (Self, JoinHandle<()>) {
         let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
         Self::from_pool(pool).await
     }
@@ -158,14 +158,18 @@ impl Persistence {
     pub async fn insert_deployment(&self, deployment: impl Into<Deployment>) -> Result<()> {
         let deployment = deployment.into();
 
+        // The deployment is new so its last update is when it was created
         sqlx::query(
-            "INSERT INTO deployments (id, service_id, state, last_update, address) VALUES (?, ?, ?, ?, ?)",
+            "INSERT INTO deployments (id, service_id, state, last_update, address, created_at, deployed_by, label) VALUES (?, ?, ?, ?, ?, ?, ?, ?)",
         )
         .bind(deployment.id)
         .bind(deployment.service_id)
         .bind(deployment.state)
         .bind(deployment.last_update)
         .bind(deployment.address.map(|socket| socket.to_string()))
+        .bind(deployment.last_update)
+        .bind(deployment.deployed_by)
+        .bind(deployment.label)
         .execute(&self.pool)
         .await
         .map(|_| ())
@@ -184,6 +188,85 @@ impl Persistence {
             .map_err(Error::from)
     }
 
+    /// A page of the deployments of all services, newest first
+    pub async fn get_deployment_history(
+        &self,
+        limit: u32,
+        offset: u32,
+    ) -> Result<Vec<DeploymentHistoryEntry>> {
+        sqlx::query_as(
+            "SELECT id, service_id, state, created_at, deployed_by, label FROM deployments ORDER BY created_at DESC, rowid DESC LIMIT ? OFFSET ?",
+        )
+        .bind(limit)
+        .bind(offset)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(Error::from)
+    }
+
+    /// Remove all but the `keep` newest deployments of every service,
+    /// along with their logs. A running deployment is never removed.
+    /// Returns how many deployments were removed.
+    pub async fn prune_deployments(&self, keep: u32) -> Result<usize> {
+        let ids: Vec<(Uuid,)> = sqlx::query_as(
+            r#"SELECT id FROM (
+                SELECT id, state, ROW_NUMBER() OVER (
+                    PARTITION BY service_id ORDER BY created_at DESC, rowid DESC
+                ) AS position
+                FROM deployments
+            )
+            WHERE position > ? AND state != ?"#,
+        )
+        .bind(keep)
+        .bind(State::Running)
+        .fetch_all(&self.pool)
+        .await?;
+
+        let mut transaction = self.pool.begin().await?;
+        for (id,) in ids.iter() {
+            sqlx::query("DELETE FROM logs WHERE id = ?")
+                .bind(id)
+                .execute(&mut transaction)
+                .await?;
+            sqlx::query("DELETE FROM deployments WHERE id = ?")
+                .bind(id)
+                .execute(&mut transaction)
+                .await?;
+        }
+        transaction.commit().await?;
+
+        Ok(ids.len())
+    }
+
+    /// The deployment to go back to when the newest one of a service
+    /// turns out to be bad: the newest one before it which ran and was
+    /// then stopped or completed. Deployments pruned from the history
+    /// cannot be gone back to.
+    pub async fn get_rollback_deployment(
+        &self,
+        service_id: &Uuid,
+    ) -> Result<Option<DeploymentRunnable>> {
+        sqlx::query_as(
+            r#"SELECT d.id, service_id, s.name AS service_name
+                FROM deployments AS d
+                JOIN services AS s ON s.id = d.service_id
+                WHERE service_id = ? AND last_running IS NOT NULL AND state IN (?, ?)
+                    AND d.id != (
+                        SELECT id FROM deployments WHERE service_id = ?
+                        ORDER BY created_at DESC, rowid DESC LIMIT 1
+                    )
+                ORDER BY created_at DESC, d.rowid DESC
+                LIMIT 1"#,
+        )
+        .bind(service_id)
+        .bind(State::Stopped)
+        .bind(State::Completed)
+        .bind(service_id)
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(Error::from)
+    }
+
     pub async fn get_active_deployment(&self, service_id: &Uuid) -> Result<Option<Deployment>> {
         sqlx::query_as("SELECT * FROM deployments WHERE service_id = ? AND state = ?")
             .bind(service_id)
@@ -305,10 +388,12 @@ async fn update_deployment(pool: &SqlitePool, state: impl Into<DeploymentState>)
 
     // TODO: Handle moving to 'active_deployments' table for State::Running.
 
-    sqlx::query("UPDATE deployments SET state = ?, last_update = ?, address = ? WHERE id = ?")
+    sqlx::query("UPDATE deployments SET state = ?, last_update = ?, address = ?, last_running = CASE WHEN ? THEN ? ELSE last_running END WHERE id = ?")
         .bind(state.state)
         .bind(state.last_update)
         .bind(state.address.map(|socket| socket.to_string()))
+        .bind(state.state == State::Running)
+        .bind(state.last_update)
         .bind(state.id)
         .execute(pool)
         .await
@@ -492,6 +577,8 @@ mod tests {
             state: State::Queued,
             last_update: Utc.with_ymd_and_hms(2022, 4, 25, 4, 43, 33).unwrap(),
             address: None,
+            deployed_by: None,
+            label: None,
         };
 
         p.insert_deployment(deployment.clone()).await.unwrap();
@@ -529,6 +616,8 @@ mod tests {
             state: State::Crashed,
             last_update: Utc.with_ymd_and_hms(2022, 4, 25, 7, 29, 35).unwrap(),
             address: None,
+            deployed_by: None,
+            label: None,
         };
         let deployment_stopped = Deployment {
             id: Uuid::new_v4(),
@@ -536,6 +625,8 @@ mod tests {
             state: State::Stopped,
             last_update: Utc.with_ymd_and_hms(2022, 4, 25, 7, 49, 35).unwrap(),
             address: None,
+            deployed_by: None,
+            label: None,
         };
         let deployment_other = Deployment {
             id: Uuid::new_v4(),
@@ -543,6 +634,8 @@ mod tests {
             state: State::Running,
             last_update: Utc.with_ymd_and_hms(2022, 4, 25, 7, 39, 39).unwrap(),
             address: None,
+            deployed_by: None,
+            label: None,
         };
         let deployment_running = Deployment {
             id: Uuid::new_v4(),
@@ -550,6 +643,8 @@ mod tests {
             state: State::Running,
             last_update: Utc.with_ymd_and_hms(2022, 4, 25, 7, 48, 29).unwrap(),
             address: Some(SocketAddr::new(Ipv4Addr::LOCALHOST.into(), 9876)),
+            deployed_by: None,
+            label: None,
         };
 
         for deployment in [
@@ -589,6 +684,8 @@ mod tests {
             state: State::Crashed,
             last_update: Utc::now(),
             address: None,
+            deployed_by: None,
+            label: None,
         };
         let deployment_stopped = Deployment {
             id: Uuid::new_v4(),
@@ -596,6 +693,8 @@ mod tests {
             state: State::Stopped,
             last_update: Utc::now(),
             address: None,
+            deployed_by: None,
+            label: None,
         };
         let deployment_running = Deployment {
             id: Uuid::new_v4(),
@@ -603,6 +702,8 @@ mod tests {
             state: State::Running,
             last_update: Utc::now(),
             address: Some(SocketAddr::new(Ipv4Addr::LOCALHOST.into(), 9876)),
+            deployed_by: None,
+            label: None,
         };
         let deployment_queued = Deployment {
             id: queued_id,
@@ -610,6 +711,8 @@ mod tests {
             state: State::Queued,
             last_update: Utc::now(),
             address: None,
+            deployed_by: None,
+            label: None,
         };
         let deployment_building = Deployment {
             id: building_id,
@@ -617,6 +720,8 @@ mod tests {
             state: State::Building,
             last_update: Utc::now(),
             address: None,
+            deployed_by: None,
+            label: None,
         };
         let deployment_built = Deployment {
             id: built_id,
@@ -624,6 +729,8 @@ mod tests {
             state: State::Built,
             last_update: Utc::now(),
             address: None,
+            deployed_by: None,
+            label: None,
         };
         let deployment_loading = Deployment {
             id: loading_id,
@@ -631,6 +738,8 @@ mod tests {
             state: State::Loading,
             last_update: Utc::now(),
             address: None,
+            deployed_by: None,
+            label: None,
         };
 
         for deployment in [
@@ -689,6 +798,8 @@ mod tests {
                 state: State::Built,
                 last_update: Utc.with_ymd_and_hms(2022, 4, 25, 4, 29, 33).unwrap(),
                 address: None,
+                deployed_by: None,
+                label: None,
             },
             Deployment {
                 id: id_1,
@@ -696,6 +807,8 @@ mod tests {
                 state: State::Running,
                 last_update: Utc.with_ymd_and_hms(2022, 4, 25, 4, 29, 44).unwrap(),
                 address: None,
+                deployed_by: None,
+                label: None,
             },
             Deployment {
                 id: id_2,
@@ -703,6 +816,8 @@ mod tests {
                 state: State::Running,
                 last_update: Utc.with_ymd_and_hms(2022, 4, 25, 4, 33, 48).unwrap(),
                 address: None,
+                deployed_by: None,
+                label: None,
             },
             Deployment {
                 id: Uuid::new_v4(),
@@ -710,6 +825,8 @@ mod tests {
                 state: State::Crashed,
                 last_update: Utc.with_ymd_and_hms(2022, 4, 25, 4, 38, 52).unwrap(),
                 address: None,
+                deployed_by: None,
+                label: None,
             },
             Deployment {
                 id: id_3,
@@ -717,6 +834,8 @@ mod tests {
                 state: State::Running,
                 last_update: Utc.with_ymd_and_hms(2022, 4, 25, 4, 42, 32).unwrap(),
                 address: None,
+                deployed_by: None,
+                label: None,
             },
         ] {
             p.insert_deployment(deployment).await.unwrap();
@@ -758,6 +877,8 @@ mod tests {
                 state: State::Running,
                 last_update: Utc::now(),
                 address: None,
+                deployed_by: None,
+                label: None,
             },
             Deployment {
                 id: Uuid::new_v4(),
@@ -765,6 +886,8 @@ mod tests {
                 state: State::Running,
                 last_update: Utc::now(),
                 address: None,
+                deployed_by: None,
+                label: None,
             },
         ];
 
@@ -788,6 +911,223 @@ mod tests {
         assert!(p.get_deployments(&service_id).await.unwrap().is_empty());
     }
 
+    #[tokio::test(flavor = "multi_thread")]
+    async fn deployment_history() {
+        let (p, _) = Persistence::new_in_memory().await;
+
+        let service_id = add_service(&p.pool).await.unwrap();
+        let other_id = add_service(&p.pool).await.unwrap();
+
+        let mut ids = Vec::new();
+        for minute in 0..5 {
+            let deployment = Deployment {
+                id: Uuid::new_v4(),
+                service_id,
+                state: State::Stopped,
+                last_update: Utc.with_ymd_and_hms(2022, 4, 25, 7, minute, 0).unwrap(),
+                address: None,
+                deployed_by: None,
+                label: None,
+            };
+            p.insert_deployment(deployment.clone()).await.unwrap();
+            ids.push(deployment.id);
+        }
+        let other = Deployment {
+            id: Uuid::new_v4(),
+            service_id: other_id,
+            state: State::Crashed,
+            last_update: Utc.with_ymd_and_hms(2022, 4, 25, 6, 0, 0).unwrap(),
+            address: None,
+            deployed_by: None,
+            label: None,
+        };
+        p.insert_deployment(other.clone()).await.unwrap();
+
+        // Later updates do not move a deployment in the history
+        update_deployment(
+            &p.pool,
+            DeploymentState {
+                id: ids[0],
+                state: State::Crashed,
+                last_update: Utc::now(),
+                address: None,
+            },
+        )
+        .await
+        .unwrap();
+
+        let history: Vec<_> = p
+            .get_deployment_history(10, 0)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|entry| entry.id)
+            .collect();
+        let mut expected: Vec<_> = ids.iter().rev().cloned().collect();
+        expected.push(other.id);
+        assert_eq!(history, expected);
+
+        let first = p.get_deployment_history(2, 0).await.unwrap();
+        let second = p.get_deployment_history(2, 2).await.unwrap();
+        let last = p.get_deployment_history(2, 4).await.unwrap();
+        assert_eq!(
+            first
+                .iter()
+                .chain(second.iter())
+                .chain(last.iter())
+                .map(|entry| entry.id)
+                .collect::<Vec<_>>(),
+            expected
+        );
+        assert_eq!(last.len(), 2);
+        assert_eq!(
+            first[0].created_at,
+            Utc.with_ymd_and_hms(2022, 4, 25, 7, 4, 0).unwrap()
+        );
+
+        // Only the newest deployments of every service are kept
+        let log = Log {
+            id: ids[0],
+            timestamp: Utc::now(),
+            state: State::Crashed,
+            level: Level::Info,
+            file: None,
+            line: None,
+            target: String::new(),
+            fields: json!({"message": "crashed"}),
+        };
+        insert_log(&p.pool, log).await.unwrap();
+
+        assert_eq!(p.prune_deployments(2).await.unwrap(), 3);
+        assert_eq!(p.prune_deployments(2).await.unwrap(), 0);
+
+        let history: Vec<_> = p
+            .get_deployment_history(10, 0)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|entry| entry.id)
+            .collect();
+        assert_eq!(history, vec![ids[4], ids[3], other.id]);
+        assert!(p.get_deployment_logs(&ids[0]).await.unwrap().is_empty());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn deployment_metadata() {
+        let (p, _) = Persistence::new_in_memory().await;
+        let service_id = add_service(&p.pool).await.unwrap();
+
+        let labeled = Deployment {
+            id: Uuid::new_v4(),
+            service_id,
+            state: State::Queued,
+            last_update: Utc.with_ymd_and_hms(2022, 4, 25, 9, 0, 0).unwrap(),
+            address: None,
+            deployed_by: Some("trinity".to_string()),
+            label: Some("v1.2.0 (3f2c1a9)".to_string()),
+        };
+        let unlabeled = Deployment {
+            id: Uuid::new_v4(),
+            last_update: Utc.with_ymd_and_hms(2022, 4, 25, 9, 5, 0).unwrap(),
+            deployed_by: None,
+            label: None,
+            ..labeled.clone()
+        };
+        p.insert_deployment(labeled.clone()).await.unwrap();
+        p.insert_deployment(unlabeled.clone()).await.unwrap();
+
+        assert_eq!(
+            p.get_deployment(&labeled.id).await.unwrap().unwrap(),
+            labeled
+        );
+
+        let response: shuttle_common::models::deployment::Response =
+            p.get_deployment(&labeled.id).await.unwrap().unwrap().into();
+        assert_eq!(response.deployed_by.as_deref(), Some("trinity"));
+        assert_eq!(response.label.as_deref(), Some("v1.2.0 (3f2c1a9)"));
+
+        let history = p.get_deployment_history(10, 0).await.unwrap();
+        assert_eq!(
+            history
+                .iter()
+                .map(|entry| (entry.deployed_by.as_deref(), entry.label.as_deref()))
+                .collect::<Vec<_>>(),
+            [(None, None), (Some("trinity"), Some("v1.2.0 (3f2c1a9)"))]
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn deployment_rollback() {
+        let (p, _) = Persistence::new_in_memory().await;
+        let service_id = add_service_named(&p.pool, "rollback").await.unwrap();
+
+        let deploy = |minute| {
+            let p = p.clone();
+            async move {
+                let deployment = Deployment {
+                    id: Uuid::new_v4(),
+                    service_id,
+                    state: State::Queued,
+                    last_update: Utc.with_ymd_and_hms(2022, 4, 25, 8, minute, 0).unwrap(),
+                    address: None,
+                    deployed_by: None,
+                    label: None,
+                };
+                p.insert_deployment(deployment.clone()).await.unwrap();
+                deployment.id
+            }
+        };
+        let pool = &p.pool;
+        let set_state = move |id, state| {
+            update_deployment(
+                pool,
+                DeploymentState {
+                    id,
+                    state,
+                    last_update: Utc::now(),
+                    address: None,
+                },
+            )
+        };
+
+        // Never got to run before the deployer restarted
+        let interrupted = deploy(0).await;
+        set_state(interrupted, State::Stopped).await.unwrap();
+
+        let first = deploy(1).await;
+        set_state(first, State::Running).await.unwrap();
+
+        // Nothing to go back to yet
+        assert_eq!(p.get_rollback_deployment(&service_id).await.unwrap(), None);
+
+        let second = deploy(2).await;
+        set_state(first, State::Stopped).await.unwrap();
+        set_state(second, State::Crashed).await.unwrap();
+
+        let rollback = p.get_rollback_deployment(&service_id).await.unwrap();
+        assert_eq!(
+            rollback,
+            Some(DeploymentRunnable {
+                id: first,
+                service_name: "rollback".to_string(),
+                service_id,
+            })
+        );
+
+        // Running it again makes it the active deployment
+        set_state(first, State::Running).await.unwrap();
+        assert_eq!(
+            p.get_active_deployment(&service_id)
+                .await
+                .unwrap()
+                .map(|deployment| deployment.id),
+            Some(first)
+        );
+
+        // The deployment which never ran is no option either
+        assert_eq!(p.get_rollback_deployment(&service_id).await.unwrap(), None);
+    }
+
     #[tokio::test(flavor = "multi_thread")]
     async fn log_insert() {
         let (p, _) = Persistence::new_in_memory().await;
@@ -909,6 +1249,8 @@ mod tests {
             state: State::Queued, // Should be different from the state recorded below
             last_update: Utc.with_ymd_and_hms(2022, 4, 29, 2, 39, 39).unwrap(),
             address: None,
+            deployed_by: None,
+            label: None,
         })
         .await
         .unwrap();
@@ -949,6 +1291,8 @@ mod tests {
                 state: State::Running,
                 last_update: Utc.with_ymd_and_hms(2022, 4, 29, 2, 39, 59).unwrap(),
                 address: Some(SocketAddr::new(Ipv4Addr::LOCALHOST.into(), 12345)),
+                deployed_by: None,
+                label: None,
             }
         );
     }
@@ -1125,6 +1469,8 @@ mod tests {
                 state: State::Built,
                 last_update: Utc.with_ymd_and_hms(2022, 4, 25, 4, 29, 33).unwrap(),
                 address: None,
+                deployed_by: None,
+                label: None,
             },
             Deployment {
                 id: Uuid::new_v4(),
@@ -1132,6 +1478,8 @@ mod tests {
                 state: State::Stopped,
                 last_update: Utc.with_ymd_and_hms(2022, 4, 25, 4, 29, 44).unwrap(),
                 address: None,
+                deployed_by: None,
+                label: None,
             },
             Deployment {
                 id: id_1,
@@ -1139,6 +1487,8 @@ mod tests {
                 state: State::Running,
                 last_update: Utc.with_ymd_and_hms(2022, 4, 25, 4, 33, 48).unwrap(),
                 address: None,
+                deployed_by: None,
+                label: None,
             },
             Deployment {
                 id: Uuid::new_v4(),
@@ -1146,6 +1496,8 @@ mod tests {
                 state: State::Crashed,
                 last_update: Utc.with_ymd_and_hms(2022, 4, 25, 4, 38, 52).unwrap(),
                 address: None,
+                deployed_by: None,
+                label: None,
             },
             Deployment {
                 id: id_2,
@@ -1153,6 +1505,8 @@ mod tests {
                 state: State::Running,
                 last_update: Utc.with_ymd_and_hms(2022, 4, 25, 4, 42, 32).unwrap(),
                 address: None,
+                deployed_by: None,
+                label: None,
             },
         ] {
             p.insert_deployment(deployment).await.unwrap();
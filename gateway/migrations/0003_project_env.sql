@@ -0,0 +1,6 @@
+CREATE TABLE IF NOT EXISTS project_env (
+  project_name TEXT NOT NULL REFERENCES projects (project_name),
+  name TEXT NOT NULL,
+  value BLOB NOT NULL,
+  PRIMARY KEY (project_name, name)
+);
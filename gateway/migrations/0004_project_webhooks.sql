@@ -0,0 +1,5 @@
+CREATE TABLE IF NOT EXISTS project_webhooks (
+  project_name TEXT PRIMARY KEY REFERENCES projects (project_name),
+  url TEXT NOT NULL,
+  secret TEXT NOT NULL
+);
@@ -0,0 +1,5 @@
+CREATE TABLE IF NOT EXISTS project_rate_limits (
+  project_name TEXT PRIMARY KEY REFERENCES projects (project_name),
+  requests_per_second INTEGER NOT NULL,
+  burst INTEGER NOT NULL
+);
@@ -0,0 +1,5 @@
+CREATE TABLE IF NOT EXISTS scoped_keys (
+  key TEXT PRIMARY KEY,
+  account_name TEXT NOT NULL REFERENCES accounts (account_name),
+  scope JSON NOT NULL
+);
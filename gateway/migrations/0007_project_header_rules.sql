@@ -0,0 +1,4 @@
+CREATE TABLE IF NOT EXISTS project_header_rules (
+  project_name TEXT PRIMARY KEY REFERENCES projects (project_name),
+  rules JSON NOT NULL
+);
@@ -0,0 +1 @@
+ALTER TABLE accounts ADD auditor BOOLEAN DEFAULT FALSE NOT NULL;
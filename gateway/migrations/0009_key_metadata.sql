@@ -0,0 +1,6 @@
+ALTER TABLE scoped_keys ADD id TEXT;
+ALTER TABLE scoped_keys ADD created_at TEXT;
+ALTER TABLE scoped_keys ADD last_used_at TEXT;
+UPDATE scoped_keys SET id = lower(hex(randomblob(8))) WHERE id IS NULL;
+CREATE UNIQUE INDEX IF NOT EXISTS scoped_keys_id ON scoped_keys (id);
+ALTER TABLE accounts ADD key_last_used_at TEXT;
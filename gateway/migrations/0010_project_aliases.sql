@@ -0,0 +1,4 @@
+CREATE TABLE IF NOT EXISTS project_aliases (
+  alias TEXT PRIMARY KEY,
+  project_name TEXT NOT NULL REFERENCES projects (project_name)
+);
@@ -0,0 +1,5 @@
+CREATE TABLE IF NOT EXISTS project_redirects (
+  project_name TEXT PRIMARY KEY,
+  target TEXT NOT NULL,
+  expires_at TEXT NOT NULL
+);
@@ -0,0 +1,5 @@
+CREATE TABLE IF NOT EXISTS project_basic_auth (
+  project_name TEXT PRIMARY KEY REFERENCES projects (project_name),
+  username TEXT NOT NULL,
+  password_hash TEXT NOT NULL
+);
@@ -0,0 +1,4 @@
+CREATE TABLE IF NOT EXISTS project_upstreams (
+  project_name TEXT PRIMARY KEY REFERENCES projects (project_name),
+  protocol TEXT NOT NULL
+);
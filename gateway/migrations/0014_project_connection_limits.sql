@@ -0,0 +1,4 @@
+CREATE TABLE IF NOT EXISTS project_connection_limits (
+  project_name TEXT PRIMARY KEY REFERENCES projects (project_name),
+  max_connections INTEGER NOT NULL
+);
@@ -0,0 +1,4 @@
+CREATE TABLE IF NOT EXISTS project_ip_filters (
+  project_name TEXT PRIMARY KEY REFERENCES projects (project_name),
+  filter JSON NOT NULL
+);
@@ -0,0 +1,4 @@
+CREATE TABLE IF NOT EXISTS project_response_caches (
+  project_name TEXT PRIMARY KEY REFERENCES projects (project_name),
+  max_entries INTEGER NOT NULL
+);
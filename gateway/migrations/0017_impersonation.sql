@@ -0,0 +1,15 @@
+CREATE TABLE IF NOT EXISTS impersonation_tokens (
+  key TEXT PRIMARY KEY,
+  actor TEXT NOT NULL REFERENCES accounts (account_name),
+  account_name TEXT NOT NULL REFERENCES accounts (account_name),
+  scope JSON NOT NULL,
+  expires_at TEXT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS audit_log (
+  id INTEGER PRIMARY KEY AUTOINCREMENT,
+  at TEXT NOT NULL,
+  actor TEXT NOT NULL,
+  effective TEXT NOT NULL,
+  action TEXT NOT NULL
+);
@@ -0,0 +1,5 @@
+CREATE TABLE IF NOT EXISTS project_tags (
+  project_name TEXT NOT NULL REFERENCES projects (project_name),
+  tag TEXT NOT NULL,
+  PRIMARY KEY (project_name, tag)
+);
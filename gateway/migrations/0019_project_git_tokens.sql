@@ -0,0 +1,4 @@
+CREATE TABLE IF NOT EXISTS project_git_tokens (
+  project_name TEXT PRIMARY KEY REFERENCES projects (project_name),
+  token BLOB NOT NULL
+);
@@ -0,0 +1,4 @@
+CREATE TABLE IF NOT EXISTS project_deployment_timeouts (
+  project_name TEXT PRIMARY KEY REFERENCES projects (project_name),
+  timeout_secs INTEGER NOT NULL
+);
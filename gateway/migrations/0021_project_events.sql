@@ -0,0 +1,7 @@
+CREATE TABLE IF NOT EXISTS project_events (
+  id INTEGER PRIMARY KEY AUTOINCREMENT,
+  project_name TEXT NOT NULL REFERENCES projects (project_name),
+  at TEXT NOT NULL,
+  kind TEXT NOT NULL,
+  detail TEXT
+);
@@ -0,0 +1,11 @@
+CREATE TABLE IF NOT EXISTS account_resource_limits (
+  account_name TEXT PRIMARY KEY REFERENCES accounts (account_name),
+  memory_mib INTEGER NOT NULL,
+  millicpus INTEGER NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS project_resource_limits (
+  project_name TEXT PRIMARY KEY REFERENCES projects (project_name),
+  memory_mib INTEGER NOT NULL,
+  millicpus INTEGER NOT NULL
+);
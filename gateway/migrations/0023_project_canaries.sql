@@ -0,0 +1,6 @@
+CREATE TABLE IF NOT EXISTS project_canaries (
+  project_name TEXT PRIMARY KEY REFERENCES projects (project_name),
+  canary TEXT NOT NULL,
+  weight INTEGER NOT NULL,
+  sticky BOOLEAN NOT NULL
+);
@@ -0,0 +1,5 @@
+CREATE TABLE IF NOT EXISTS project_leases (
+  project_name TEXT PRIMARY KEY REFERENCES projects (project_name),
+  holder TEXT NOT NULL,
+  expires_at INTEGER NOT NULL
+);
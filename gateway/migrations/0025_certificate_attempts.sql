@@ -0,0 +1,5 @@
+CREATE TABLE IF NOT EXISTS certificate_attempts (
+  fqdn TEXT PRIMARY KEY,
+  attempted_at TEXT NOT NULL,
+  error TEXT
+);
@@ -0,0 +1,5 @@
+CREATE TABLE IF NOT EXISTS project_feature_flags (
+  project_name TEXT NOT NULL REFERENCES projects (project_name),
+  flag TEXT NOT NULL,
+  PRIMARY KEY (project_name, flag)
+);
@@ -0,0 +1,5 @@
+CREATE TABLE IF NOT EXISTS project_maintenance_pages (
+  project_name TEXT PRIMARY KEY REFERENCES projects (project_name),
+  body TEXT NOT NULL,
+  retry_after_secs INTEGER NOT NULL
+);
@@ -0,0 +1,179 @@
+-- Rows keyed by a project follow it when it is renamed. SQLite cannot
+-- change the foreign key of a table in place, so each one is rebuilt.
+
+CREATE TABLE custom_domains_new (
+  fqdn TEXT PRIMARY KEY,
+  project_name TEXT NOT NULL REFERENCES projects (project_name) ON UPDATE CASCADE,
+  certificate TEXT NOT NULL,
+  private_key TEXT NOT NULL
+);
+INSERT INTO custom_domains_new SELECT * FROM custom_domains;
+DROP TABLE custom_domains;
+ALTER TABLE custom_domains_new RENAME TO custom_domains;
+
+CREATE TABLE project_env_new (
+  project_name TEXT NOT NULL REFERENCES projects (project_name) ON UPDATE CASCADE,
+  name TEXT NOT NULL,
+  value BLOB NOT NULL,
+  PRIMARY KEY (project_name, name)
+);
+INSERT INTO project_env_new SELECT * FROM project_env;
+DROP TABLE project_env;
+ALTER TABLE project_env_new RENAME TO project_env;
+
+CREATE TABLE project_webhooks_new (
+  project_name TEXT PRIMARY KEY REFERENCES projects (project_name) ON UPDATE CASCADE,
+  url TEXT NOT NULL,
+  secret TEXT NOT NULL
+);
+INSERT INTO project_webhooks_new SELECT * FROM project_webhooks;
+DROP TABLE project_webhooks;
+ALTER TABLE project_webhooks_new RENAME TO project_webhooks;
+
+CREATE TABLE project_rate_limits_new (
+  project_name TEXT PRIMARY KEY REFERENCES projects (project_name) ON UPDATE CASCADE,
+  requests_per_second INTEGER NOT NULL,
+  burst INTEGER NOT NULL
+);
+INSERT INTO project_rate_limits_new SELECT * FROM project_rate_limits;
+DROP TABLE project_rate_limits;
+ALTER TABLE project_rate_limits_new RENAME TO project_rate_limits;
+
+CREATE TABLE project_header_rules_new (
+  project_name TEXT PRIMARY KEY REFERENCES projects (project_name) ON UPDATE CASCADE,
+  rules JSON NOT NULL
+);
+INSERT INTO project_header_rules_new SELECT * FROM project_header_rules;
+DROP TABLE project_header_rules;
+ALTER TABLE project_header_rules_new RENAME TO project_header_rules;
+
+CREATE TABLE project_aliases_new (
+  alias TEXT PRIMARY KEY,
+  project_name TEXT NOT NULL REFERENCES projects (project_name) ON UPDATE CASCADE
+);
+INSERT INTO project_aliases_new SELECT * FROM project_aliases;
+DROP TABLE project_aliases;
+ALTER TABLE project_aliases_new RENAME TO project_aliases;
+
+CREATE TABLE project_basic_auth_new (
+  project_name TEXT PRIMARY KEY REFERENCES projects (project_name) ON UPDATE CASCADE,
+  username TEXT NOT NULL,
+  password_hash TEXT NOT NULL
+);
+INSERT INTO project_basic_auth_new SELECT * FROM project_basic_auth;
+DROP TABLE project_basic_auth;
+ALTER TABLE project_basic_auth_new RENAME TO project_basic_auth;
+
+CREATE TABLE project_upstreams_new (
+  project_name TEXT PRIMARY KEY REFERENCES projects (project_name) ON UPDATE CASCADE,
+  protocol TEXT NOT NULL
+);
+INSERT INTO project_upstreams_new SELECT * FROM project_upstreams;
+DROP TABLE project_upstreams;
+ALTER TABLE project_upstreams_new RENAME TO project_upstreams;
+
+CREATE TABLE project_connection_limits_new (
+  project_name TEXT PRIMARY KEY REFERENCES projects (project_name) ON UPDATE CASCADE,
+  max_connections INTEGER NOT NULL
+);
+INSERT INTO project_connection_limits_new SELECT * FROM project_connection_limits;
+DROP TABLE project_connection_limits;
+ALTER TABLE project_connection_limits_new RENAME TO project_connection_limits;
+
+CREATE TABLE project_ip_filters_new (
+  project_name TEXT PRIMARY KEY REFERENCES projects (project_name) ON UPDATE CASCADE,
+  filter JSON NOT NULL
+);
+INSERT INTO project_ip_filters_new SELECT * FROM project_ip_filters;
+DROP TABLE project_ip_filters;
+ALTER TABLE project_ip_filters_new RENAME TO project_ip_filters;
+
+CREATE TABLE project_response_caches_new (
+  project_name TEXT PRIMARY KEY REFERENCES projects (project_name) ON UPDATE CASCADE,
+  max_entries INTEGER NOT NULL
+);
+INSERT INTO project_response_caches_new SELECT * FROM project_response_caches;
+DROP TABLE project_response_caches;
+ALTER TABLE project_response_caches_new RENAME TO project_response_caches;
+
+CREATE TABLE project_tags_new (
+  project_name TEXT NOT NULL REFERENCES projects (project_name) ON UPDATE CASCADE,
+  tag TEXT NOT NULL,
+  PRIMARY KEY (project_name, tag)
+);
+INSERT INTO project_tags_new SELECT * FROM project_tags;
+DROP TABLE project_tags;
+ALTER TABLE project_tags_new RENAME TO project_tags;
+
+CREATE TABLE project_git_tokens_new (
+  project_name TEXT PRIMARY KEY REFERENCES projects (project_name) ON UPDATE CASCADE,
+  token BLOB NOT NULL
+);
+INSERT INTO project_git_tokens_new SELECT * FROM project_git_tokens;
+DROP TABLE project_git_tokens;
+ALTER TABLE project_git_tokens_new RENAME TO project_git_tokens;
+
+CREATE TABLE project_deployment_timeouts_new (
+  project_name TEXT PRIMARY KEY REFERENCES projects (project_name) ON UPDATE CASCADE,
+  timeout_secs INTEGER NOT NULL
+);
+INSERT INTO project_deployment_timeouts_new SELECT * FROM project_deployment_timeouts;
+DROP TABLE project_deployment_timeouts;
+ALTER TABLE project_deployment_timeouts_new RENAME TO project_deployment_timeouts;
+
+CREATE TABLE project_events_new (
+  id INTEGER PRIMARY KEY AUTOINCREMENT,
+  project_name TEXT NOT NULL REFERENCES projects (project_name) ON UPDATE CASCADE,
+  at TEXT NOT NULL,
+  kind TEXT NOT NULL,
+  detail TEXT
+);
+INSERT INTO project_events_new SELECT * FROM project_events;
+DROP TABLE project_events;
+ALTER TABLE project_events_new RENAME TO project_events;
+
+CREATE TABLE project_resource_limits_new (
+  project_name TEXT PRIMARY KEY REFERENCES projects (project_name) ON UPDATE CASCADE,
+  memory_mib INTEGER NOT NULL,
+  millicpus INTEGER NOT NULL
+);
+INSERT INTO project_resource_limits_new SELECT * FROM project_resource_limits;
+DROP TABLE project_resource_limits;
+ALTER TABLE project_resource_limits_new RENAME TO project_resource_limits;
+
+CREATE TABLE project_canaries_new (
+  project_name TEXT PRIMARY KEY REFERENCES projects (project_name) ON UPDATE CASCADE,
+  canary TEXT NOT NULL,
+  weight INTEGER NOT NULL,
+  sticky BOOLEAN NOT NULL
+);
+INSERT INTO project_canaries_new SELECT * FROM project_canaries;
+DROP TABLE project_canaries;
+ALTER TABLE project_canaries_new RENAME TO project_canaries;
+
+CREATE TABLE project_leases_new (
+  project_name TEXT PRIMARY KEY REFERENCES projects (project_name) ON UPDATE CASCADE,
+  holder TEXT NOT NULL,
+  expires_at INTEGER NOT NULL
+);
+INSERT INTO project_leases_new SELECT * FROM project_leases;
+DROP TABLE project_leases;
+ALTER TABLE project_leases_new RENAME TO project_leases;
+
+CREATE TABLE project_feature_flags_new (
+  project_name TEXT NOT NULL REFERENCES projects (project_name) ON UPDATE CASCADE,
+  flag TEXT NOT NULL,
+  PRIMARY KEY (project_name, flag)
+);
+INSERT INTO project_feature_flags_new SELECT * FROM project_feature_flags;
+DROP TABLE project_feature_flags;
+ALTER TABLE project_feature_flags_new RENAME TO project_feature_flags;
+
+CREATE TABLE project_maintenance_pages_new (
+  project_name TEXT PRIMARY KEY REFERENCES projects (project_name) ON UPDATE CASCADE,
+  body TEXT NOT NULL,
+  retry_after_secs INTEGER NOT NULL
+);
+INSERT INTO project_maintenance_pages_new SELECT * FROM project_maintenance_pages;
+DROP TABLE project_maintenance_pages;
+ALTER TABLE project_maintenance_pages_new RENAME TO project_maintenance_pages;
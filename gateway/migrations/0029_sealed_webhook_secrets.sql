@@ -0,0 +1,3 @@
+-- Webhook secrets are sealed like env vars, leaving `secret` empty. The
+-- ones stored in the clear so far are sealed when the gateway starts.
+ALTER TABLE project_webhooks ADD sealed_secret BLOB;
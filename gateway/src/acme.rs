@@ -1,29 +1,68 @@
 use std::collections::HashMap;
-use std::sync::Arc;
+use std::future::Future;
+use std::io::Cursor;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex as StdMutex};
 use std::task::{Context, Poll};
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use axum::body::boxed;
 use axum::response::Response;
+use chrono::{DateTime, NaiveDateTime, Utc};
 use fqdn::FQDN;
 use futures::future::BoxFuture;
 use hyper::server::conn::AddrStream;
 use hyper::{Body, Request};
 use instant_acme::{
     Account, AccountCredentials, Authorization, AuthorizationStatus, Challenge, ChallengeType,
-    Identifier, KeyAuthorization, LetsEncrypt, NewAccount, NewOrder, Order, OrderStatus,
+    Identifier, LetsEncrypt, NewAccount, NewOrder, Order, OrderState, OrderStatus,
 };
 use rcgen::{Certificate, CertificateParams, DistinguishedName};
 use tokio::sync::Mutex;
 use tokio::time::sleep;
 use tower::{Layer, Service};
-use tracing::{error, trace, warn};
+use tracing::{error, info, trace, warn};
 
 use crate::proxy::AsResponderTo;
-use crate::{Error, ProjectName};
+use crate::tls::{ChainAndPrivateKey, GatewayCertResolver};
+use crate::{Error, ErrorKind, ProjectName};
 
 const MAX_RETRIES: usize = 15;
 
+/// How often a [`DnsProvider`] is asked whether a challenge record has
+/// propagated
+const DNS_PROPAGATION_POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// How many times a [`DnsProvider`] is asked whether a challenge
+/// record has propagated before giving up
+const DNS_PROPAGATION_MAX_POLLS: usize = 60;
+
+/// How ACME problem types for rate limits end
+const RATE_LIMITED_PROBLEM: &str = "rateLimited";
+
+/// How long certificate requests are held back once the ACME server
+/// rate limited one, if it did not say for how long
+const RATE_LIMIT_BACKOFF: Duration = Duration::from_secs(60 * 60);
+
+/// How long before it expires a certificate can be issued again for a
+/// domain without being forced
+const CERTIFICATE_RENEWAL_WINDOW: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+
+/// Longest a domain name can be, without its trailing dot
+const MAX_DOMAIN_LEN: usize = 253;
+
+/// Longest a single label of a domain name can be
+const MAX_LABEL_LEN: usize = 63;
+
+/// A certificate issued by [`AcmeClient::issue_certificate`]
+pub struct IssuedCertificate {
+    pub chain: String,
+    pub private_key: String,
+    /// Whether it took the place of a certificate the domain had
+    pub replaced: bool,
+    pub not_after: DateTime<Utc>,
+}
+
 #[derive(Debug, Eq, PartialEq)]
 pub struct CustomDomain {
     pub fqdn: FQDN,
@@ -32,32 +71,279 @@ pub struct CustomDomain {
     pub private_key: String,
 }
 
-/// An ACME client implementation that completes Http01 challenges
+/// Check that `domain` is a name a custom domain can be set up for, and
+/// normalize it to lowercase without a trailing dot so that the same
+/// domain is always known under the same name.
+///
+/// Wildcards are refused since custom domains are certified through
+/// Http01 challenges, which cannot be done for them.
+pub fn parse_custom_domain(domain: &str) -> Result<FQDN, Error> {
+    let invalid =
+        |detail: String| Error::from_kind(ErrorKind::InvalidCustomDomain).with_detail(detail);
+
+    let domain = domain.trim().to_lowercase();
+    let domain = domain.strip_suffix('.').unwrap_or(&domain);
+
+    if domain.is_empty() {
+        return Err(invalid("the domain is empty".to_string()));
+    }
+
+    if domain
+        .trim_start_matches('[')
+        .trim_end_matches(']')
+        .parse::<IpAddr>()
+        .is_ok()
+    {
+        return Err(invalid(format!(
+            "`{domain}` is an IP address, custom domains have to be domain names"
+        )));
+    }
+
+    if domain.len() > MAX_DOMAIN_LEN {
+        return Err(invalid(format!(
+            "the domain is longer than {MAX_DOMAIN_LEN} characters"
+        )));
+    }
+
+    let labels: Vec<&str> = domain.split('.').collect();
+    if labels.len() < 2 {
+        return Err(invalid(format!(
+            "`{domain}` is not a fully qualified domain name, such as `api.example.com`"
+        )));
+    }
+
+    for label in &labels {
+        if label.is_empty() {
+            return Err(invalid(format!("`{domain}` has an empty label")));
+        }
+        if *label == "*" {
+            return Err(invalid(
+                "wildcard domains are not supported, every subdomain has to be added on its own"
+                    .to_string(),
+            ));
+        }
+        if label.len() > MAX_LABEL_LEN {
+            return Err(invalid(format!(
+                "the label `{label}` is longer than {MAX_LABEL_LEN} characters"
+            )));
+        }
+        if !label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') {
+            return Err(invalid(format!(
+                "the label `{label}` can only have letters, digits and hyphens. Internationalized names have to be given in punycode"
+            )));
+        }
+        if label.starts_with('-') || label.ends_with('-') {
+            return Err(invalid(format!(
+                "the label `{label}` cannot start or end with a hyphen"
+            )));
+        }
+    }
+
+    let tld = labels[labels.len() - 1];
+    if tld.chars().all(|c| c.is_ascii_digit()) {
+        return Err(invalid(format!("`{tld}` is not a valid top-level domain")));
+    }
+
+    domain
+        .parse()
+        .map_err(|_| invalid(format!("`{domain}` is not a valid domain name")))
+}
+
+/// Places the TXT records Dns01 challenges are completed with
+#[async_trait]
+pub trait DnsProvider: Send + Sync {
+    /// Create a TXT record at `name` holding `value`
+    async fn create_txt_record(&self, name: &str, value: &str) -> Result<(), AcmeClientError>;
+
+    /// Remove the TXT record at `name` holding `value`
+    async fn delete_txt_record(&self, name: &str, value: &str) -> Result<(), AcmeClientError>;
+
+    /// Whether the TXT record at `name` holding `value` is visible to
+    /// the ACME server yet
+    async fn is_propagated(&self, name: &str, value: &str) -> Result<bool, AcmeClientError>;
+}
+
+/// A [`DnsProvider`] handing records over to an HTTP hook
+///
+/// Records are created and removed by `POST`ing `{"action": "present"}`
+/// or `{"action": "cleanup"}` along with their `name` and `value` to
+/// the hook. The hook has to answer `GET ?name=..&value=..` with `200
+/// OK` once a record has propagated.
+pub struct HttpDnsHook {
+    url: String,
+    token: Option<String>,
+    client: reqwest::Client,
+}
+
+impl HttpDnsHook {
+    pub fn new(url: String, token: Option<String>) -> Self {
+        Self {
+            url,
+            token,
+            client: reqwest::Client::new(),
+        }
+    }
+
+    fn request(&self, method: reqwest::Method) -> reqwest::RequestBuilder {
+        let builder = self.client.request(method, &self.url);
+        match &self.token {
+            Some(token) => builder.bearer_auth(token),
+            None => builder,
+        }
+    }
+
+    async fn post(&self, action: &str, name: &str, value: &str) -> Result<(), AcmeClientError> {
+        self.request(reqwest::Method::POST)
+            .json(&serde_json::json!({
+                "action": action,
+                "name": name,
+                "value": value,
+            }))
+            .send()
+            .await
+            .and_then(|resp| resp.error_for_status())
+            .map(|_| ())
+            .map_err(|error| {
+                error!(%error, action, name, "dns hook request failed");
+                AcmeClientError::DnsRecord
+            })
+    }
+}
+
+#[async_trait]
+impl DnsProvider for HttpDnsHook {
+    async fn create_txt_record(&self, name: &str, value: &str) -> Result<(), AcmeClientError> {
+        self.post("present", name, value).await
+    }
+
+    async fn delete_txt_record(&self, name: &str, value: &str) -> Result<(), AcmeClientError> {
+        self.post("cleanup", name, value).await
+    }
+
+    async fn is_propagated(&self, name: &str, value: &str) -> Result<bool, AcmeClientError> {
+        let resp = self
+            .request(reqwest::Method::GET)
+            .query(&[("name", name), ("value", value)])
+            .send()
+            .await
+            .map_err(|error| {
+                error!(%error, name, "dns hook request failed");
+                AcmeClientError::DnsRecord
+            })?;
+
+        Ok(resp.status() == reqwest::StatusCode::OK)
+    }
+}
+
+/// An order for a certificate, as far as completing it goes. It only
+/// exists so that orders can be mocked in tests.
+#[async_trait]
+trait AcmeOrder: Send {
+    async fn authorizations(&mut self) -> Result<Vec<Authorization>, AcmeClientError>;
+
+    /// What an Http01 challenge has to be answered with
+    fn http01_key_authorization(&self, challenge: &Challenge) -> String;
+
+    /// What the TXT record of a Dns01 challenge has to hold
+    fn dns01_value(&self, challenge: &Challenge) -> String;
+
+    async fn set_challenge_ready(&mut self, url: &str) -> Result<(), AcmeClientError>;
+
+    async fn status(&mut self) -> Result<OrderStatus, AcmeClientError>;
+
+    /// Submit the signing request and return the certificate chain
+    async fn finalize(&mut self, csr_der: &[u8]) -> Result<String, AcmeClientError>;
+}
+
+struct AccountOrder {
+    order: Order,
+    state: OrderState,
+}
+
+#[async_trait]
+impl AcmeOrder for AccountOrder {
+    async fn authorizations(&mut self) -> Result<Vec<Authorization>, AcmeClientError> {
+        self.order
+            .authorizations(&self.state.authorizations)
+            .await
+            .map_err(|error| {
+                error!(%error, "failed to get authorizations information");
+                classify(&error, AcmeClientError::AuthorizationCreation)
+            })
+    }
+
+    fn http01_key_authorization(&self, challenge: &Challenge) -> String {
+        self.order.key_authorization(challenge).as_str().to_owned()
+    }
+
+    fn dns01_value(&self, challenge: &Challenge) -> String {
+        self.order.key_authorization(challenge).dns_value()
+    }
+
+    async fn set_challenge_ready(&mut self, url: &str) -> Result<(), AcmeClientError> {
+        self.order.set_challenge_ready(url).await.map_err(|error| {
+            error!(%error, "failed to mark challenge as ready");
+            classify(&error, AcmeClientError::SetReadyFailed)
+        })
+    }
+
+    async fn status(&mut self) -> Result<OrderStatus, AcmeClientError> {
+        let state = self.order.state().await.map_err(|error| {
+            error!(%error, "got error while fetching state");
+            classify(&error, AcmeClientError::FetchingState)
+        })?;
+
+        trace!(?state, "order state refreshed");
+
+        Ok(state.status)
+    }
+
+    async fn finalize(&mut self, csr_der: &[u8]) -> Result<String, AcmeClientError> {
+        self.order
+            .finalize(csr_der, &self.state.finalize)
+            .await
+            .map_err(|error| {
+                error!(%error, "failed to finalize certificate request");
+                classify(&error, AcmeClientError::OrderFinalizing)
+            })
+    }
+}
+
+/// An ACME client implementation that completes Http01 challenges,
+/// and Dns01 challenges through a [`DnsProvider`]
 /// It is safe to clone this type as it functions as a singleton
 #[derive(Clone, Default)]
-pub struct AcmeClient(Arc<Mutex<HashMap<String, KeyAuthorization>>>);
+pub struct AcmeClient {
+    http01_authorizations: Arc<Mutex<HashMap<String, String>>>,
+    dns_provider: Option<Arc<dyn DnsProvider>>,
+    /// Set while backing off from the rate limit of the ACME server
+    backoff_until: Arc<StdMutex<Option<Instant>>>,
+}
 
 impl AcmeClient {
     pub fn new() -> Self {
-        Self(Arc::new(Mutex::new(HashMap::default())))
+        Self::default()
+    }
+
+    /// Complete Dns01 challenges by placing their records with
+    /// `provider` instead of asking for them to be placed by hand
+    pub fn with_dns_provider<P: DnsProvider + 'static>(mut self, provider: P) -> Self {
+        self.dns_provider = Some(Arc::new(provider));
+        self
     }
 
-    async fn add_http01_challenge_authorization(&self, token: String, key: KeyAuthorization) {
+    async fn add_http01_challenge_authorization(&self, token: String, key: String) {
         trace!(token, "saving acme http01 challenge");
-        self.0.lock().await.insert(token, key);
+        self.http01_authorizations.lock().await.insert(token, key);
     }
 
     async fn get_http01_challenge_authorization(&self, token: &str) -> Option<String> {
-        self.0
-            .lock()
-            .await
-            .get(token)
-            .map(|key| key.as_str().to_owned())
+        self.http01_authorizations.lock().await.get(token).cloned()
     }
 
     async fn remove_http01_challenge_authorization(&self, token: &str) {
         trace!(token, "removing acme http01 challenge");
-        self.0.lock().await.remove(token);
+        self.http01_authorizations.lock().await.remove(token);
     }
 
     /// Create a new ACME account that can be restored using by deserializing the returned JSON into a [instant_acme::AccountCredentials]
@@ -80,7 +366,7 @@ impl AcmeClient {
             .await
             .map_err(|error| {
                 error!(%error, "got error while creating acme account");
-                AcmeClientError::AccountCreation
+                classify(&error, AcmeClientError::AccountCreation)
             })?;
 
         let credentials = serde_json::to_value(account.credentials()).map_err(|error| {
@@ -92,12 +378,118 @@ impl AcmeClient {
     }
 
     /// Create an ACME-signed certificate and return it and its
-    /// associated PEM-encoded private key
+    /// associated PEM-encoded private key. Once rate limited, requests
+    /// fail with [`AcmeClientError::RateLimited`] without reaching the
+    /// ACME server until it said to try again.
     pub async fn create_certificate(
         &self,
         identifier: &str,
         challenge_type: ChallengeType,
         credentials: AccountCredentials<'_>,
+    ) -> Result<(String, String), AcmeClientError> {
+        self.backing_off(self.order_certificate(identifier, challenge_type, credentials))
+            .await
+    }
+
+    /// Issue a certificate for `identifier` right away and serve it,
+    /// unless it already has one which is not about to expire and
+    /// `force` is not set. Wildcard names are certified through Dns01
+    /// challenges and other names through Http01 ones.
+    pub async fn issue_certificate(
+        &self,
+        resolver: &GatewayCertResolver,
+        identifier: &str,
+        force: bool,
+        credentials: AccountCredentials<'_>,
+    ) -> Result<IssuedCertificate, Error> {
+        let challenge_type = if identifier.starts_with("*.") {
+            ChallengeType::Dns01
+        } else {
+            ChallengeType::Http01
+        };
+
+        self.issue_with(
+            resolver,
+            identifier,
+            force,
+            self.create_certificate(identifier, challenge_type, credentials),
+        )
+        .await
+    }
+
+    async fn issue_with<F>(
+        &self,
+        resolver: &GatewayCertResolver,
+        identifier: &str,
+        force: bool,
+        order: F,
+    ) -> Result<IssuedCertificate, Error>
+    where
+        F: Future<Output = Result<(String, String), AcmeClientError>>,
+    {
+        let existing = resolver.store().get(identifier).await?;
+        if let Some(existing) = &existing {
+            // One whose expiry cannot be read is as good as expired
+            let renewal = chrono::Duration::from_std(CERTIFICATE_RENEWAL_WINDOW).unwrap();
+            let still_valid = existing
+                .not_after()
+                .map_or(false, |not_after| not_after - renewal > Utc::now());
+            if still_valid && !force {
+                return Err(Error::from_kind(ErrorKind::CertificateStillValid));
+            }
+        }
+
+        info!(identifier, force, "issuing certificate");
+        let (chain, private_key) = order.await?;
+
+        let mut buf = Vec::new();
+        buf.extend(chain.as_bytes());
+        buf.extend(private_key.as_bytes());
+        let certs = ChainAndPrivateKey::parse_pem(Cursor::new(buf))?;
+        let not_after = certs.not_after()?;
+        resolver.serve_der(identifier, certs).await?;
+
+        Ok(IssuedCertificate {
+            chain,
+            private_key,
+            replaced: existing.is_some(),
+            not_after,
+        })
+    }
+
+    /// Run `request` to the ACME server unless backing off from its
+    /// rate limit, and start backing off if it hit it. Retrying right
+    /// away would only make the limit last longer, or worse.
+    async fn backing_off<T, F>(&self, request: F) -> Result<T, AcmeClientError>
+    where
+        F: Future<Output = Result<T, AcmeClientError>>,
+    {
+        if let Some(until) = *self.backoff_until.lock().unwrap() {
+            let retry_after = until.saturating_duration_since(Instant::now());
+            if !retry_after.is_zero() {
+                warn!(?retry_after, "holding back acme request while rate limited");
+                return Err(AcmeClientError::RateLimited {
+                    retry_after: Some(retry_after),
+                });
+            }
+        }
+
+        let result = request.await;
+
+        if let Err(AcmeClientError::RateLimited { retry_after }) = &result {
+            let wait = retry_after.unwrap_or(RATE_LIMIT_BACKOFF);
+            warn!(?wait, "rate limited by the acme server, backing off");
+            *self.backoff_until.lock().unwrap() = Some(Instant::now() + wait);
+        }
+
+        result
+    }
+
+    async fn order_certificate(
+        &self,
+        identifier: &str,
+        challenge_type: ChallengeType,
+        credentials: AccountCredentials<'_>,
     ) -> Result<(String, String), AcmeClientError> {
         trace!(identifier, "requesting acme certificate");
 
@@ -109,24 +501,31 @@ impl AcmeClient {
             AcmeClientError::AccountCreation
         })?;
 
-        let (mut order, state) = account
+        let (order, state) = account
             .new_order(&NewOrder {
                 identifiers: &[Identifier::Dns(identifier.to_string())],
             })
             .await
             .map_err(|error| {
                 error!(%error, "failed to order certificate");
-                AcmeClientError::OrderCreation
+                classify(&error, AcmeClientError::OrderCreation)
             })?;
 
-        let authorizations =
-            order
-                .authorizations(&state.authorizations)
-                .await
-                .map_err(|error| {
-                    error!(%error, "failed to get authorizations information");
-                    AcmeClientError::AuthorizationCreation
-                })?;
+        self.complete_order(
+            identifier,
+            challenge_type,
+            &mut AccountOrder { order, state },
+        )
+        .await
+    }
+
+    async fn complete_order<O: AcmeOrder>(
+        &self,
+        identifier: &str,
+        challenge_type: ChallengeType,
+        order: &mut O,
+    ) -> Result<(String, String), AcmeClientError> {
+        let authorizations = order.authorizations().await?;
 
         // There should only ever be 1 authorization as we only provide 1 domain at a time
         debug_assert!(authorizations.len() == 1);
@@ -134,7 +533,7 @@ impl AcmeClient {
 
         trace!(?authorization, "got authorization");
 
-        self.complete_challenge(challenge_type, authorization, &mut order)
+        self.complete_challenge(challenge_type, authorization, order)
             .await?;
 
         let certificate = {
@@ -150,13 +549,7 @@ impl AcmeClient {
             AcmeClientError::CertificateSigning
         })?;
 
-        let certificate_chain = order
-            .finalize(&signing_request, &state.finalize)
-            .await
-            .map_err(|error| {
-                error!(%error, "failed to finalize certificate request");
-                AcmeClientError::OrderFinalizing
-            })?;
+        let certificate_chain = order.finalize(&signing_request).await?;
 
         Ok((certificate_chain, certificate.serialize_private_key_pem()))
     }
@@ -170,25 +563,24 @@ impl AcmeClient {
             .iter()
             .find(|c| c.r#type == ty)
             .ok_or_else(|| {
-                error!("http-01 challenge not found");
+                error!(?ty, "challenge not found");
                 AcmeClientError::MissingChallenge
             })
     }
 
-    async fn wait_for_termination(&self, order: &mut Order) -> Result<(), AcmeClientError> {
+    async fn wait_for_termination<O: AcmeOrder>(
+        &self,
+        order: &mut O,
+    ) -> Result<(), AcmeClientError> {
         // Exponential backoff until order changes status
         let mut tries = 1;
         let mut delay = Duration::from_millis(250);
-        let state = loop {
+        let status = loop {
             sleep(delay).await;
-            let state = order.state().await.map_err(|error| {
-                error!(%error, "got error while fetching state");
-                AcmeClientError::FetchingState
-            })?;
+            let status = order.status().await?;
 
-            trace!(?state, "order state refreshed");
-            match state.status {
-                OrderStatus::Ready => break state,
+            match status {
+                OrderStatus::Ready => break status,
                 OrderStatus::Invalid => {
                     return Err(AcmeClientError::ChallengeInvalid);
                 }
@@ -196,9 +588,9 @@ impl AcmeClient {
                     delay *= 2;
                     tries += 1;
                     if tries < MAX_RETRIES {
-                        trace!(?state, tries, attempt_in=?delay, "order not yet ready");
+                        trace!(?status, tries, attempt_in=?delay, "order not yet ready");
                     } else {
-                        error!(?state, tries, "order not ready in {MAX_RETRIES} tries");
+                        error!(?status, tries, "order not ready in {MAX_RETRIES} tries");
                         return Err(AcmeClientError::ChallengeTimeout);
                     }
                 }
@@ -206,16 +598,16 @@ impl AcmeClient {
             }
         };
 
-        trace!(?state, "challenge completed");
+        trace!(?status, "challenge completed");
 
         Ok(())
     }
 
-    async fn complete_challenge(
+    async fn complete_challenge<O: AcmeOrder>(
         &self,
         ty: ChallengeType,
         authorization: &Authorization,
-        order: &mut Order,
+        order: &mut O,
     ) -> Result<(), AcmeClientError> {
         // Don't complete challenge for orders that are already valid
         if let AuthorizationStatus::Valid = authorization.status {
@@ -232,52 +624,91 @@ impl AcmeClient {
         }
     }
 
-    async fn complete_dns01_challenge(
+    async fn complete_dns01_challenge<O: AcmeOrder>(
         &self,
         identifier: &Identifier,
         challenge: &Challenge,
-        order: &mut Order,
+        order: &mut O,
     ) -> Result<(), AcmeClientError> {
         let Identifier::Dns(domain) = identifier;
 
-        let digest = order.key_authorization(challenge).dns_value();
-        warn!("dns-01 challenge: _acme-challenge.{domain} 300 IN TXT \"{digest}\"");
+        // Wildcard names are authorized through the domain they cover
+        let domain = domain.strip_prefix("*.").unwrap_or(domain);
+        let name = format!("_acme-challenge.{domain}");
+        let digest = order.dns01_value(challenge);
+
+        let provider = match &self.dns_provider {
+            Some(provider) => provider,
+            None => {
+                warn!("dns-01 challenge: {name} 300 IN TXT \"{digest}\"");
 
-        // Wait 60 secs to insert the record manually and for it to
-        // propagate before moving on
-        sleep(Duration::from_secs(60)).await;
+                // Wait 60 secs to insert the record manually and for it to
+                // propagate before moving on
+                sleep(Duration::from_secs(60)).await;
 
-        order
-            .set_challenge_ready(&challenge.url)
-            .await
-            .map_err(|error| {
-                error!(%error, "failed to mark challenge as ready");
-                AcmeClientError::SetReadyFailed
-            })?;
+                order.set_challenge_ready(&challenge.url).await?;
+
+                return self.wait_for_termination(order).await;
+            }
+        };
+
+        trace!(name, "placing dns-01 challenge record");
+        provider.create_txt_record(&name, &digest).await?;
+
+        let res = async {
+            Self::wait_for_propagation(provider.as_ref(), &name, &digest).await?;
 
-        self.wait_for_termination(order).await
+            order.set_challenge_ready(&challenge.url).await?;
+
+            self.wait_for_termination(order).await
+        }
+        .await;
+
+        trace!(name, "removing dns-01 challenge record");
+        if let Err(error) = provider.delete_txt_record(&name, &digest).await {
+            // Not fatal: a stale record does not get in the way of
+            // future challenges
+            warn!(%error, name, "failed to remove dns-01 challenge record");
+        }
+
+        res
     }
 
-    async fn complete_http01_challenge(
+    async fn wait_for_propagation(
+        provider: &dyn DnsProvider,
+        name: &str,
+        value: &str,
+    ) -> Result<(), AcmeClientError> {
+        for polls in 1..=DNS_PROPAGATION_MAX_POLLS {
+            if provider.is_propagated(name, value).await? {
+                trace!(name, polls, "dns-01 challenge record propagated");
+                return Ok(());
+            }
+
+            sleep(DNS_PROPAGATION_POLL_INTERVAL).await;
+        }
+
+        error!(
+            name,
+            "dns-01 challenge record not propagated in {DNS_PROPAGATION_MAX_POLLS} polls"
+        );
+        Err(AcmeClientError::DnsPropagationTimeout)
+    }
+
+    async fn complete_http01_challenge<O: AcmeOrder>(
         &self,
         challenge: &Challenge,
-        order: &mut Order,
+        order: &mut O,
     ) -> Result<(), AcmeClientError> {
         trace!(?challenge, "will complete challenge");
 
         self.add_http01_challenge_authorization(
             challenge.token.clone(),
-            order.key_authorization(challenge),
+            order.http01_key_authorization(challenge),
         )
         .await;
 
-        order
-            .set_challenge_ready(&challenge.url)
-            .await
-            .map_err(|error| {
-                error!(%error, "failed to mark challenge as ready");
-                AcmeClientError::SetReadyFailed
-            })?;
+        order.set_challenge_ready(&challenge.url).await?;
 
         let res = self.wait_for_termination(order).await;
 
@@ -288,8 +719,49 @@ impl AcmeClient {
     }
 }
 
+/// Turn `error` into [`AcmeClientError::RateLimited`] if it is the ACME
+/// server rate limiting us, or into `otherwise` if not
+fn classify(error: &instant_acme::Error, otherwise: AcmeClientError) -> AcmeClientError {
+    let message = error.to_string();
+    if message.contains(RATE_LIMITED_PROBLEM) {
+        AcmeClientError::RateLimited {
+            retry_after: retry_after(&message, Utc::now()),
+        }
+    } else {
+        otherwise
+    }
+}
+
+/// How long a rate limit problem asks to wait for. Let's Encrypt says
+/// until when in its detail, as in `retry after 2023-01-01 10:00:00 UTC`
+/// or `retry after 2023-01-01T10:00:00Z`.
+fn retry_after(message: &str, now: DateTime<Utc>) -> Option<Duration> {
+    let index = message.to_ascii_lowercase().find("retry after ")?;
+    let rest = message[index + "retry after ".len()..].trim_start();
+
+    let rfc3339 = rest
+        .split_whitespace()
+        .next()
+        .map(|word| word.trim_end_matches(|c: char| !c.is_ascii_alphanumeric()))
+        .and_then(|word| DateTime::parse_from_rfc3339(word).ok())
+        .map(|at| at.with_timezone(&Utc));
+    let at = rfc3339.or_else(|| {
+        let at = rest.get(.."2023-01-01 10:00:00".len())?;
+        NaiveDateTime::parse_from_str(at, "%Y-%m-%d %H:%M:%S")
+            .ok()
+            .map(|at| DateTime::<Utc>::from_utc(at, Utc))
+    })?;
+
+    Some((at - now).to_std().unwrap_or_default())
+}
+
 #[derive(Debug, strum::Display)]
 pub enum AcmeClientError {
+    /// The ACME server turned a request away for going over its rate
+    /// limits, and may have said after how long to try again
+    RateLimited {
+        retry_after: Option<Duration>,
+    },
     AccountCreation,
     AuthorizationCreation,
     CertificateCreation,
@@ -301,6 +773,8 @@ pub enum AcmeClientError {
     OrderFinalizing,
     MissingChallenge,
     ChallengeNotSupported,
+    DnsPropagationTimeout,
+    DnsRecord,
     Serializing,
     SetReadyFailed,
 }
@@ -400,3 +874,366 @@ where
         })
     }
 }
+
+#[cfg(test)]
+pub mod tests {
+    use std::sync::Mutex as StdMutex;
+
+    use serde_json::json;
+
+    use super::*;
+
+    const CHALLENGE_RECORD: &str = "_acme-challenge.example.com";
+
+    #[test]
+    fn custom_domain_validation() {
+        for (domain, normalized) in [
+            ("example.com", "example.com"),
+            ("API.Example.COM", "api.example.com"),
+            ("api.example.com.", "api.example.com"),
+            (" my-app.example.co.uk ", "my-app.example.co.uk"),
+            ("xn--bcher-kva.example", "xn--bcher-kva.example"),
+        ] {
+            assert_eq!(
+                parse_custom_domain(domain).unwrap().to_string(),
+                normalized,
+                "{domain}"
+            );
+        }
+
+        let long_label = "a".repeat(64);
+        let long_domain = format!("{}.com", ["a".repeat(60); 5].join("."));
+        for (domain, reason) in [
+            ("", "empty"),
+            (".", "empty"),
+            ("10.0.0.1", "IP address"),
+            ("[2001:db8::1]", "IP address"),
+            ("::1", "IP address"),
+            ("*.example.com", "wildcard"),
+            ("api.*.example.com", "wildcard"),
+            ("localhost", "not a fully qualified domain name"),
+            ("example.com..", "empty label"),
+            ("api..example.com", "empty label"),
+            (".example.com", "empty label"),
+            (&format!("{long_label}.com"), "longer than 63"),
+            (&long_domain, "longer than 253"),
+            ("under_score.example.com", "letters, digits and hyphens"),
+            ("bücher.example", "punycode"),
+            ("example.com/path", "letters, digits and hyphens"),
+            ("-api.example.com", "hyphen"),
+            ("api-.example.com", "hyphen"),
+            ("example.123", "top-level domain"),
+        ] {
+            let err = parse_custom_domain(domain).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::InvalidCustomDomain, "{domain}");
+            assert!(
+                err.detail().unwrap().contains(reason),
+                "{domain}: {}",
+                err.detail().unwrap()
+            );
+        }
+    }
+
+    #[derive(Clone, Default)]
+    struct MockDns {
+        records: Arc<StdMutex<HashMap<String, String>>>,
+    }
+
+    #[async_trait]
+    impl DnsProvider for MockDns {
+        async fn create_txt_record(&self, name: &str, value: &str) -> Result<(), AcmeClientError> {
+            self.records
+                .lock()
+                .unwrap()
+                .insert(name.to_string(), value.to_string());
+            Ok(())
+        }
+
+        async fn delete_txt_record(&self, name: &str, _: &str) -> Result<(), AcmeClientError> {
+            self.records.lock().unwrap().remove(name);
+            Ok(())
+        }
+
+        async fn is_propagated(&self, name: &str, value: &str) -> Result<bool, AcmeClientError> {
+            Ok(self.records.lock().unwrap().get(name).map(String::as_str) == Some(value))
+        }
+    }
+
+    /// Plays the part of the ACME server: the challenge only passes
+    /// if the expected record is in place when it is marked as ready
+    struct MockOrder {
+        dns: MockDns,
+        validated: Option<bool>,
+        signing_request: Option<Vec<u8>>,
+        chain: String,
+    }
+
+    #[async_trait]
+    impl AcmeOrder for MockOrder {
+        async fn authorizations(&mut self) -> Result<Vec<Authorization>, AcmeClientError> {
+            let authorization = serde_json::from_value(json!({
+                "identifier": { "type": "dns", "value": "example.com" },
+                "status": "pending",
+                "challenges": [
+                    {
+                        "type": "http-01",
+                        "url": "https://acme.test/challenge/1",
+                        "token": "http-token",
+                        "status": "pending"
+                    },
+                    {
+                        "type": "dns-01",
+                        "url": "https://acme.test/challenge/2",
+                        "token": "dns-token",
+                        "status": "pending"
+                    }
+                ]
+            }))
+            .unwrap();
+
+            Ok(vec![authorization])
+        }
+
+        fn http01_key_authorization(&self, challenge: &Challenge) -> String {
+            format!("{}.thumbprint", challenge.token)
+        }
+
+        fn dns01_value(&self, challenge: &Challenge) -> String {
+            format!("digest-of-{}", challenge.token)
+        }
+
+        async fn set_challenge_ready(&mut self, url: &str) -> Result<(), AcmeClientError> {
+            assert_eq!(url, "https://acme.test/challenge/2");
+
+            let records = self.dns.records.lock().unwrap();
+            self.validated = Some(
+                records.get(CHALLENGE_RECORD).map(String::as_str) == Some("digest-of-dns-token"),
+            );
+
+            Ok(())
+        }
+
+        async fn status(&mut self) -> Result<OrderStatus, AcmeClientError> {
+            Ok(match self.validated {
+                None => OrderStatus::Pending,
+                Some(true) => OrderStatus::Ready,
+                Some(false) => OrderStatus::Invalid,
+            })
+        }
+
+        async fn finalize(&mut self, csr_der: &[u8]) -> Result<String, AcmeClientError> {
+            self.signing_request = Some(csr_der.to_vec());
+            Ok(self.chain.clone())
+        }
+    }
+
+    #[tokio::test]
+    async fn dns01_wildcard_certificate() {
+        let dns = MockDns::default();
+        let client = AcmeClient::new().with_dns_provider(dns.clone());
+
+        let issued =
+            Certificate::from_params(CertificateParams::new(vec!["*.example.com".to_string()]))
+                .unwrap();
+
+        let mut order = MockOrder {
+            dns: dns.clone(),
+            validated: None,
+            signing_request: None,
+            chain: issued.serialize_pem().unwrap(),
+        };
+
+        let (chain, private_key) = client
+            .complete_order("*.example.com", ChallengeType::Dns01, &mut order)
+            .await
+            .unwrap();
+
+        assert_eq!(order.validated, Some(true));
+        assert_eq!(chain, order.chain);
+        assert!(private_key.contains("PRIVATE KEY"));
+
+        // the certificate is requested for the wildcard name
+        let signing_request = order.signing_request.unwrap();
+        assert!(signing_request
+            .windows("*.example.com".len())
+            .any(|window| window == b"*.example.com"));
+
+        // and the challenge record is cleaned up afterwards
+        assert!(dns.records.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn issue_certificate_on_request() {
+        let dns = MockDns::default();
+        let client = AcmeClient::new().with_dns_provider(dns.clone());
+        let resolver = GatewayCertResolver::new();
+
+        let order = |not_after| {
+            let mut params = CertificateParams::new(vec!["example.com".to_string()]);
+            params.not_after = not_after;
+            MockOrder {
+                dns: dns.clone(),
+                validated: None,
+                signing_request: None,
+                chain: Certificate::from_params(params)
+                    .unwrap()
+                    .serialize_pem()
+                    .unwrap(),
+            }
+        };
+        async fn issue(
+            client: &AcmeClient,
+            resolver: &GatewayCertResolver,
+            force: bool,
+            order: &mut MockOrder,
+        ) -> Result<IssuedCertificate, Error> {
+            client
+                .issue_with(
+                    resolver,
+                    "example.com",
+                    force,
+                    client.complete_order("example.com", ChallengeType::Dns01, order),
+                )
+                .await
+        }
+
+        // a domain without a certificate gets one
+        let mut first = order(rcgen::date_time_ymd(2100, 1, 1));
+        let issued = issue(&client, &resolver, false, &mut first).await.unwrap();
+        assert_eq!(first.validated, Some(true));
+        assert_eq!(issued.chain, first.chain);
+        assert!(!issued.replaced);
+        assert_eq!(issued.not_after.to_rfc3339(), "2100-01-01T00:00:00+00:00");
+        assert!(resolver.get("example.com").await.is_some());
+
+        // while it is valid, a new one is only issued when forced to
+        let mut refused = order(rcgen::date_time_ymd(2100, 1, 1));
+        assert_eq!(
+            issue(&client, &resolver, false, &mut refused)
+                .await
+                .unwrap_err()
+                .kind(),
+            ErrorKind::CertificateStillValid
+        );
+        assert_eq!(refused.validated, None);
+
+        let mut forced = order(rcgen::date_time_ymd(2000, 1, 1));
+        let issued = issue(&client, &resolver, true, &mut forced).await.unwrap();
+        assert_eq!(forced.validated, Some(true));
+        assert!(issued.replaced);
+
+        // and one which has expired is replaced without forcing
+        let mut renewed = order(rcgen::date_time_ymd(2100, 1, 1));
+        let issued = issue(&client, &resolver, false, &mut renewed)
+            .await
+            .unwrap();
+        assert_eq!(renewed.validated, Some(true));
+        assert!(issued.replaced);
+        assert_eq!(
+            resolver
+                .store()
+                .get("example.com")
+                .await
+                .unwrap()
+                .unwrap()
+                .not_after()
+                .unwrap(),
+            issued.not_after
+        );
+    }
+
+    /// An ACME server turning every request away for its rate limit
+    struct RateLimitedOrder {
+        requests: usize,
+        retry_after: Duration,
+    }
+
+    #[async_trait]
+    impl AcmeOrder for RateLimitedOrder {
+        async fn authorizations(&mut self) -> Result<Vec<Authorization>, AcmeClientError> {
+            self.requests += 1;
+            Err(AcmeClientError::RateLimited {
+                retry_after: Some(self.retry_after),
+            })
+        }
+
+        fn http01_key_authorization(&self, _: &Challenge) -> String {
+            unreachable!("rate limited orders go no further")
+        }
+
+        fn dns01_value(&self, _: &Challenge) -> String {
+            unreachable!("rate limited orders go no further")
+        }
+
+        async fn set_challenge_ready(&mut self, _: &str) -> Result<(), AcmeClientError> {
+            unreachable!("rate limited orders go no further")
+        }
+
+        async fn status(&mut self) -> Result<OrderStatus, AcmeClientError> {
+            unreachable!("rate limited orders go no further")
+        }
+
+        async fn finalize(&mut self, _: &[u8]) -> Result<String, AcmeClientError> {
+            unreachable!("rate limited orders go no further")
+        }
+    }
+
+    #[tokio::test]
+    async fn acme_rate_limit_backoff() {
+        // how long to wait is read from the problem when it says
+        let now = DateTime::parse_from_rfc3339("2023-01-01T10:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        for (detail, expected) in [
+            (
+                "too many certificates already issued for exact set of domains: example.com: see https://letsencrypt.org/docs/rate-limits/, retry after 2023-01-01 10:30:00 UTC",
+                Some(Duration::from_secs(30 * 60)),
+            ),
+            (
+                "too many failed authorizations recently, Retry after 2023-01-01T10:00:30Z.",
+                Some(Duration::from_secs(30)),
+            ),
+            ("too many new orders recently", None),
+        ] {
+            assert_eq!(retry_after(detail, now), expected, "{detail}");
+        }
+
+        let client = AcmeClient::new();
+        let mut order = RateLimitedOrder {
+            requests: 0,
+            retry_after: Duration::from_millis(200),
+        };
+
+        let result = client
+            .backing_off(client.complete_order("example.com", ChallengeType::Http01, &mut order))
+            .await;
+        assert!(matches!(result, Err(AcmeClientError::RateLimited { .. })));
+        assert_eq!(order.requests, 1);
+
+        // trying again right away does not reach the server
+        let result = client
+            .backing_off(client.complete_order("example.com", ChallengeType::Http01, &mut order))
+            .await;
+        match result {
+            Err(AcmeClientError::RateLimited {
+                retry_after: Some(retry_after),
+            }) => assert!(retry_after <= Duration::from_millis(200)),
+            other => panic!("expected to be held back, got {other:?}"),
+        }
+        assert_eq!(order.requests, 1);
+
+        // but once the wait is over, it does
+        sleep(Duration::from_millis(250)).await;
+        let _ = client
+            .backing_off(client.complete_order("example.com", ChallengeType::Http01, &mut order))
+            .await;
+        assert_eq!(order.requests, 2);
+
+        // and clients are asked to come back later
+        let error: Error = AcmeClientError::RateLimited {
+            retry_after: Some(Duration::from_secs(60)),
+        }
+        .into();
+        assert_eq!(error.kind(), ErrorKind::ServiceUnavailable);
+    }
+}
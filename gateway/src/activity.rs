@@ -0,0 +1,54 @@
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+use chrono::{DateTime, Utc};
+
+use crate::ProjectName;
+
+/// When the user proxy last forwarded traffic to each project. This
+/// is only kept in memory, so a restarted gateway starts afresh.
+#[derive(Clone, Default)]
+pub struct ActivityTracker {
+    last_seen: Arc<RwLock<HashMap<ProjectName, DateTime<Utc>>>>,
+}
+
+impl ActivityTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record traffic for `project_name` at the current time
+    pub fn touch(&self, project_name: &ProjectName) {
+        self.last_seen
+            .write()
+            .unwrap()
+            .insert(project_name.clone(), Utc::now());
+    }
+
+    /// The last time traffic was seen for `project_name`, if ever
+    pub fn last_activity(&self, project_name: &ProjectName) -> Option<DateTime<Utc>> {
+        self.last_seen.read().unwrap().get(project_name).copied()
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    #[test]
+    fn activity_tracker() {
+        let tracker = ActivityTracker::new();
+        let matrix: ProjectName = "matrix".parse().unwrap();
+
+        assert_eq!(tracker.last_activity(&matrix), None);
+
+        let before = Utc::now();
+        tracker.touch(&matrix);
+        let seen = tracker.last_activity(&matrix).unwrap();
+        assert!(seen >= before);
+
+        tracker.touch(&matrix);
+        assert!(tracker.last_activity(&matrix).unwrap() >= seen);
+        assert_eq!(tracker.last_activity(&"reloaded".parse().unwrap()), None);
+    }
+}
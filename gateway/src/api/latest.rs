@@ -1,40 +1,82 @@
+use std::collections::BTreeMap;
 use std::io::Cursor;
 use std::net::SocketAddr;
 use std::sync::Arc;
 use std::time::Duration;
 
 use axum::body::{Body, BoxBody};
-use axum::extract::{Extension, MatchedPath, Path, State};
+use axum::extract::{Extension, MatchedPath, Path, Query, State};
 use axum::http::Request;
 use axum::middleware::from_extractor;
 use axum::response::Response;
-use axum::routing::{any, get, post};
+use axum::routing::{any, delete, get, post, put};
 use axum::{Json as AxumJson, Router};
-use fqdn::FQDN;
 use futures::Future;
-use http::StatusCode;
+use http::header::CONTENT_TYPE;
+use http::{HeaderMap, Method, StatusCode};
 use instant_acme::{AccountCredentials, ChallengeType};
 use serde::{Deserialize, Serialize};
 use shuttle_common::backends::metrics::Metrics;
+use shuttle_common::models::deployment::PendingDeploy;
 use shuttle_common::models::error::ErrorKind;
+use shuttle_common::models::user::Action;
 use shuttle_common::models::{project, stats, user};
 use tokio::sync::mpsc::Sender;
 use tokio::sync::{Mutex, MutexGuard};
 use tower_http::trace::TraceLayer;
-use tracing::{debug, debug_span, field, instrument, Span};
+use tracing::{debug, debug_span, field, info, instrument, warn, Span};
 use ttl_cache::TtlCache;
 use uuid::Uuid;
 
-use crate::acme::{AcmeClient, CustomDomain};
-use crate::auth::{Admin, ScopedUser, User};
+use crate::acme::{parse_custom_domain, AcmeClient, CustomDomain};
+use crate::auth::{Admin, KeyScope, ScopedUser, User};
+use crate::backup::Backup;
+use crate::deploy::check_archive;
+use crate::env;
+use crate::git::GitSource;
+use crate::loglevel::LogLevel;
 use crate::project::{Project, ProjectCreating};
 use crate::task::{self, BoxedTask, TaskResult};
-use crate::tls::GatewayCertResolver;
+use crate::tls::{ChainAndPrivateKey, GatewayCertResolver};
 use crate::worker::WORKER_QUEUE_SIZE;
-use crate::{AccountName, Error, GatewayService, ProjectName};
+use crate::{AccountName, DockerContext, Error, GatewayService, ProjectName};
 
 pub const SVC_DEGRADED_THRESHOLD: usize = 128;
 
+/// Number of projects in a page of an export unless asked otherwise
+pub const EXPORT_PAGE_SIZE: u32 = 100;
+/// Largest page of an export which can be asked for
+pub const EXPORT_MAX_PAGE_SIZE: u32 = 1000;
+
+/// Most names which can be checked in a single request
+pub const MAX_NAME_CHECKS: usize = 50;
+
+/// Number of audit log entries listed unless asked otherwise
+pub const AUDIT_LOG_PAGE_SIZE: u32 = 100;
+/// Most audit log entries which can be listed at once
+pub const AUDIT_LOG_MAX_PAGE_SIZE: u32 = 1000;
+
+/// Number of events of a project listed unless asked otherwise
+pub const PROJECT_EVENTS_PAGE_SIZE: u32 = 50;
+/// Most events of a project which can be listed at once
+pub const PROJECT_EVENTS_MAX_PAGE_SIZE: u32 = 500;
+
+/// The version of the API served here, bumped whenever a change to it
+/// would break existing clients
+pub const API_VERSION: &str = "1";
+
+/// Header under which clients can make a project creation safe to retry
+pub const IDEMPOTENCY_KEY_HEADER: &str = "Idempotency-Key";
+/// How long the result of a creation is kept for its idempotency key
+pub const IDEMPOTENCY_KEY_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+/// Most idempotency keys remembered at once. The oldest are forgotten first
+pub const IDEMPOTENCY_KEY_CAPACITY: usize = 4096;
+
+/// The outcome of creations by (account, idempotency key). An entry is
+/// locked for as long as its creation is in flight, so that duplicates
+/// racing each other wait for the first one instead of acting again
+type IdempotencyKeys = TtlCache<(String, String), Arc<Mutex<Option<project::Response>>>>;
+
 #[derive(Serialize, Deserialize)]
 #[serde(rename_all = "lowercase")]
 pub enum GatewayStatus {
@@ -48,6 +90,33 @@ pub struct StatusResponse {
     status: GatewayStatus,
 }
 
+#[derive(Serialize, Deserialize)]
+pub struct MaintenanceResponse {
+    pub enabled: bool,
+}
+
+/// What a client needs to know to tell whether it can talk to this
+/// gateway. The commit and build time are those handed to the image
+/// build, and are missing from builds made outside of it.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct VersionResponse {
+    pub version: String,
+    pub git_sha: Option<String>,
+    pub build_time: Option<String>,
+    pub api_version: String,
+}
+
+impl VersionResponse {
+    pub fn current() -> Self {
+        Self {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            git_sha: option_env!("COMMIT_SHA").map(str::to_string),
+            build_time: option_env!("BUILD_TIME").map(str::to_string),
+            api_version: API_VERSION.to_string(),
+        }
+    }
+}
+
 impl StatusResponse {
     pub fn healthy() -> Self {
         Self {
@@ -72,8 +141,13 @@ impl StatusResponse {
 async fn get_user(
     State(RouterState { service, .. }): State<RouterState>,
     Path(account_name): Path<AccountName>,
-    _: Admin,
+    Admin { user: admin }: Admin,
 ) -> Result<AxumJson<user::Response>, Error> {
+    // The response holds the user's key, which would let an auditor act as them
+    if !admin.is_super_user() {
+        return Err(Error::from_kind(ErrorKind::Forbidden));
+    }
+
     let user = User::retrieve_from_account_name(&service, account_name).await?;
 
     Ok(AxumJson(user.into()))
@@ -90,48 +164,314 @@ async fn post_user(
     Ok(AxumJson(user.into()))
 }
 
-#[instrument(skip(service))]
+#[instrument(skip_all, fields(%account_name))]
+async fn get_keys(
+    State(RouterState { service, .. }): State<RouterState>,
+    Path(account_name): Path<AccountName>,
+    user: User,
+) -> Result<AxumJson<Vec<user::KeyResponse>>, Error> {
+    // A user can look at their own keys with a full key. Admins and
+    // auditors can look at anyone's, but never with a scoped key
+    if user.scope.is_some() {
+        return Err(Error::from_kind(ErrorKind::Forbidden));
+    }
+    let is_owner = user.name == account_name;
+    if !is_owner && !user.is_super_user() && !user.is_auditor() {
+        return Err(Error::from_kind(ErrorKind::Forbidden));
+    }
+
+    let keys = service.list_keys(&account_name).await?;
+
+    Ok(AxumJson(keys))
+}
+
+#[instrument(skip_all, fields(%account_name, %key_id))]
+async fn delete_key(
+    State(RouterState { service, .. }): State<RouterState>,
+    Path((account_name, key_id)): Path<(AccountName, String)>,
+    user: User,
+) -> Result<(), Error> {
+    if user.scope.is_some() {
+        return Err(Error::from_kind(ErrorKind::Forbidden));
+    }
+    let is_owner = user.name == account_name && !user.is_auditor();
+    if !is_owner && !user.is_super_user() {
+        return Err(Error::from_kind(ErrorKind::Forbidden));
+    }
+
+    service.revoke_key(&account_name, &key_id).await
+}
+
+#[instrument(skip_all)]
+async fn post_scoped_key(
+    State(RouterState { service, .. }): State<RouterState>,
+    user: User,
+    AxumJson(request): AxumJson<user::ScopedKeyRequest>,
+) -> Result<AxumJson<user::ScopedKeyResponse>, Error> {
+    // Only a full key can mint narrower ones, and auditors cannot mint any
+    if user.scope.is_some() || user.is_auditor() {
+        return Err(Error::from_kind(ErrorKind::Forbidden));
+    }
+
+    let projects = request
+        .projects
+        .map(|projects| {
+            projects
+                .iter()
+                .map(|project| project.parse::<ProjectName>())
+                .collect::<Result<Vec<_>, _>>()
+        })
+        .transpose()?;
+
+    if let Some(projects) = &projects {
+        if !user.is_super_user() && projects.iter().any(|p| !user.projects.contains(p)) {
+            return Err(Error::from_kind(ErrorKind::ProjectNotFound));
+        }
+    }
+
+    let scope = KeyScope {
+        projects,
+        actions: request.actions,
+    };
+    let (id, key) = service.create_scoped_key(&user.name, &scope).await?;
+
+    Ok(AxumJson(user::ScopedKeyResponse {
+        id,
+        key: key.to_string(),
+        projects: scope
+            .projects
+            .map(|projects| projects.iter().map(ToString::to_string).collect()),
+        actions: scope.actions,
+    }))
+}
+
+#[instrument(skip(service, user))]
 async fn get_project(
     State(RouterState { service, .. }): State<RouterState>,
-    ScopedUser { scope, .. }: ScopedUser,
+    ScopedUser { user, scope }: ScopedUser,
 ) -> Result<AxumJson<project::Response>, Error> {
+    user.ensure_action_allowed(Action::Read)?;
+
     let state = service.find_project(&scope).await?.into();
     let response = project::Response {
         name: scope.to_string(),
         state,
+        last_activity: service.activity_tracker().last_activity(&scope),
     };
 
     Ok(AxumJson(response))
 }
 
+#[derive(Deserialize)]
+struct ProjectListParams {
+    /// Only list the projects with this tag
+    tag: Option<String>,
+}
+
 async fn get_projects_list(
     State(RouterState { service, .. }): State<RouterState>,
-    User { name, .. }: User,
+    user: User,
+    Query(params): Query<ProjectListParams>,
 ) -> Result<AxumJson<Vec<project::Response>>, Error> {
+    user.ensure_action_allowed(Action::Read)?;
+
+    let tagged = match params.tag {
+        Some(tag) => Some(
+            service
+                .iter_user_projects_tagged(&user.name, &tag)
+                .await?
+                .collect::<Vec<_>>(),
+        ),
+        None => None,
+    };
+
     let projects = service
-        .iter_user_projects_detailed(name.clone())
+        .iter_user_projects_detailed(user.name.clone())
         .await?
         .into_iter()
-        .map(|project| project::Response {
-            name: project.0.to_string(),
-            state: project.1.into(),
+        .filter(|(name, _)| {
+            user.scope
+                .as_ref()
+                .map_or(true, |scope| scope.allows_project(name))
+        })
+        .filter(|(name, _)| tagged.as_ref().map_or(true, |tagged| tagged.contains(name)))
+        .map(|(name, project)| project::Response {
+            name: name.to_string(),
+            state: project.into(),
+            last_activity: service.activity_tracker().last_activity(&name),
         })
         .collect();
 
     Ok(AxumJson(projects))
 }
 
+#[derive(Deserialize)]
+struct DrainParams {
+    /// Also stop the projects running on the node
+    #[serde(default)]
+    stop_running: bool,
+}
+
+#[derive(Deserialize)]
+struct CreateProjectParams {
+    /// Another project of the same account to copy the configuration of
+    from: Option<ProjectName>,
+}
+
+#[derive(Deserialize)]
+struct ExportParams {
+    after: Option<ProjectName>,
+    limit: Option<u32>,
+}
+
+#[instrument(skip_all, fields(account_name = %user.name))]
+async fn get_projects_export(
+    State(RouterState { service, .. }): State<RouterState>,
+    user: User,
+    Query(params): Query<ExportParams>,
+) -> Result<AxumJson<project::ExportManifest>, Error> {
+    user.ensure_action_allowed(Action::Read)?;
+
+    let limit = params
+        .limit
+        .unwrap_or(EXPORT_PAGE_SIZE)
+        .clamp(1, EXPORT_MAX_PAGE_SIZE);
+
+    // One more than asked for tells whether there is a next page
+    let mut page: Vec<_> = service
+        .iter_user_projects_page(&user.name, params.after.as_ref(), limit + 1)
+        .await?
+        .collect();
+
+    let next = if page.len() > limit as usize {
+        page.truncate(limit as usize);
+        page.last().map(|(name, _)| name.to_string())
+    } else {
+        None
+    };
+
+    let mut projects = Vec::with_capacity(page.len());
+    for (name, state) in page {
+        if let Some(scope) = &user.scope {
+            if !scope.allows_project(&name) {
+                continue;
+            }
+        }
+
+        projects.push(project::ExportedProject {
+            env: service.iter_project_env_names(&name).await?.collect(),
+            custom_domains: service.iter_project_custom_domains(&name).await?.collect(),
+            name: name.to_string(),
+            state: state.into(),
+        });
+    }
+
+    Ok(AxumJson(project::ExportManifest {
+        version: project::EXPORT_MANIFEST_VERSION,
+        account_name: user.name.to_string(),
+        projects,
+        next,
+    }))
+}
+
 #[instrument(skip_all, fields(%project))]
 async fn post_project(
     State(RouterState {
-        service, sender, ..
+        service,
+        sender,
+        idempotency_keys,
+        ..
     }): State<RouterState>,
-    User { name, .. }: User,
-    Path(project): Path<ProjectName>,
+    user: User,
+    headers: HeaderMap,
+    Path(project): Path<String>,
+    Query(params): Query<CreateProjectParams>,
 ) -> Result<AxumJson<project::Response>, Error> {
-    let state = service
-        .create_project(project.clone(), name.clone())
-        .await?;
+    // Parsed here rather than by `Path` so that what is wrong with
+    // the name makes it into the response
+    let project: ProjectName = project.parse()?;
+
+    user.ensure_allowed(&project, Action::Create)?;
+
+    let key = match headers
+        .get(IDEMPOTENCY_KEY_HEADER)
+        .and_then(|key| key.to_str().ok())
+    {
+        Some(key) => (user.name.to_string(), key.to_string()),
+        None => return create_project(service, sender, user, project, params).await,
+    };
+
+    let entry = {
+        let mut keys = idempotency_keys.lock().await;
+        match keys.get(&key) {
+            Some(entry) => Arc::clone(entry),
+            None => {
+                let entry = Arc::new(Mutex::new(None));
+                keys.insert(key, Arc::clone(&entry), IDEMPOTENCY_KEY_TTL);
+                entry
+            }
+        }
+    };
+
+    let mut outcome = entry.lock().await;
+    if let Some(response) = &*outcome {
+        if response.name != project.to_string() {
+            return Err(Error::from_kind(ErrorKind::IdempotencyKeyReused));
+        }
+
+        debug!("replaying the creation of an idempotency key");
+        return Ok(AxumJson(response.clone()));
+    }
+
+    // Failures are not remembered so that the request can be retried
+    // with the same key once whatever went wrong is fixed
+    let AxumJson(response) = create_project(service, sender, user, project, params).await?;
+    *outcome = Some(response.clone());
+
+    Ok(AxumJson(response))
+}
+
+async fn create_project(
+    service: Arc<GatewayService>,
+    sender: Sender<BoxedTask>,
+    user: User,
+    project: ProjectName,
+    CreateProjectParams { from }: CreateProjectParams,
+) -> Result<AxumJson<project::Response>, Error> {
+    // Only projects of the same account can be forked, and the
+    // projects of others are not even acknowledged
+    if let Some(source) = &from {
+        user.ensure_allowed(source, Action::Read)?;
+        if !user.projects.contains(source) {
+            return Err(Error::from_kind(ErrorKind::ProjectNotFound));
+        }
+    }
+
+    service.ensure_not_in_maintenance()?;
+    service.ensure_not_draining()?;
+
+    let state = match service
+        .create_project(project.clone(), user.name.clone())
+        .await
+    {
+        Err(err) if err.kind() == ErrorKind::ProjectAlreadyExists => {
+            let suggestions = service.suggest_project_names(&project).await?;
+            return Err(err.with_suggestions(suggestions));
+        }
+        state => state?,
+    };
+
+    // Copied before the project is started so that its container is
+    // created with the env vars
+    if let Some(source) = &from {
+        service.copy_project_config(source, &project).await?;
+    }
+
+    // The project waits for a slot if the node is full, which it can
+    // take from a project of a lower tier
+    if let Err(err) = service.make_room_for(&project, &sender).await {
+        warn!(error = %err, "failed to make room for a new project");
+    }
 
     service
         .new_task()
@@ -142,703 +482,4900 @@ async fn post_project(
     let response = project::Response {
         name: project.to_string(),
         state: state.into(),
+        last_activity: service.activity_tracker().last_activity(&project),
     };
 
     Ok(AxumJson(response))
 }
 
+/// Tell for every one of a bunch of names whether a new project could
+/// be created under it, so candidates can be tried out in one go
+#[instrument(skip_all, fields(names = names.len()))]
+async fn post_check_project_names(
+    State(RouterState { service, .. }): State<RouterState>,
+    _: User,
+    AxumJson(project::NameCheckRequest { names }): AxumJson<project::NameCheckRequest>,
+) -> Result<AxumJson<Vec<project::NameCheck>>, Error> {
+    if names.len() > MAX_NAME_CHECKS {
+        return Err(Error::custom(
+            ErrorKind::InvalidOperation,
+            format!("at most {MAX_NAME_CHECKS} names can be checked at once"),
+        ));
+    }
+
+    let mut checks = Vec::with_capacity(names.len());
+    for name in names {
+        // The same rules as creations are held to
+        let parsed = name.parse::<ProjectName>().and_then(|project| {
+            project
+                .validate()
+                .map(|()| project)
+                .map_err(|err| Error::from_kind(ErrorKind::InvalidProjectName).with_detail(err))
+        });
+
+        let check = match parsed {
+            Ok(project) => project::NameCheck {
+                available: service.is_project_name_available(&project).await?,
+                name,
+                valid: true,
+                reason: None,
+            },
+            Err(err) => project::NameCheck {
+                name,
+                valid: false,
+                reason: err.detail().map(ToString::to_string),
+                available: false,
+            },
+        };
+        checks.push(check);
+    }
+
+    Ok(AxumJson(checks))
+}
+
 #[instrument(skip_all, fields(%project))]
 async fn delete_project(
     State(RouterState {
         service, sender, ..
     }): State<RouterState>,
-    ScopedUser { scope: project, .. }: ScopedUser,
+    ScopedUser {
+        user,
+        scope: project,
+    }: ScopedUser,
 ) -> Result<AxumJson<project::Response>, Error> {
-    let state = service.find_project(&project).await?;
+    user.ensure_action_allowed(Action::Delete)?;
 
-    let mut response = project::Response {
+    let state = service.begin_destroy_project(&project).await?;
+    let destroyed = state.is_destroyed();
+
+    let response = project::Response {
         name: project.to_string(),
         state: state.into(),
+        last_activity: service.activity_tracker().last_activity(&project),
     };
 
-    if response.state == shuttle_common::models::project::State::Destroyed {
-        return Ok(AxumJson(response));
+    // A project still being destroyed is sent the task again, in case
+    // the one before it was lost along the way
+    if !destroyed {
+        service
+            .new_task()
+            .project(project)
+            .and_then(task::destroy())
+            .send(&sender)
+            .await?;
     }
 
-    // if project exists and isn't `Destroyed`, send destroy task
-    service
-        .new_task()
-        .project(project)
-        .and_then(task::destroy())
-        .send(&sender)
-        .await?;
-
-    response.state = shuttle_common::models::project::State::Destroying;
-
     Ok(AxumJson(response))
 }
 
-#[instrument(skip_all, fields(scope = %scoped_user.scope))]
-async fn route_project(
-    State(RouterState { service, .. }): State<RouterState>,
-    scoped_user: ScopedUser,
-    req: Request<Body>,
-) -> Result<Response<Body>, Error> {
-    service.route(&scoped_user, req).await
+fn redacted_env<I: IntoIterator<Item = String>>(names: I) -> BTreeMap<String, String> {
+    names
+        .into_iter()
+        .map(|name| (name, env::REDACTED.to_string()))
+        .collect()
 }
 
-async fn get_status(State(RouterState { sender, .. }): State<RouterState>) -> Response<Body> {
-    let (status, body) = if sender.is_closed() || sender.capacity() == 0 {
-        (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            StatusResponse::unhealthy(),
-        )
-    } else if sender.capacity() < WORKER_QUEUE_SIZE - SVC_DEGRADED_THRESHOLD {
-        (StatusCode::OK, StatusResponse::degraded())
-    } else {
-        (StatusCode::OK, StatusResponse::healthy())
-    };
+#[instrument(skip_all, fields(%project))]
+async fn get_project_env(
+    State(RouterState { service, .. }): State<RouterState>,
+    ScopedUser {
+        user,
+        scope: project,
+    }: ScopedUser,
+) -> Result<AxumJson<project::EnvResponse>, Error> {
+    user.ensure_action_allowed(Action::Read)?;
 
-    let body = serde_json::to_vec(&body).unwrap();
-    Response::builder()
-        .status(status)
-        .body(body.into())
-        .unwrap()
+    let vars = redacted_env(service.iter_project_env_names(&project).await?);
+
+    Ok(AxumJson(project::EnvResponse {
+        vars,
+        restart_required: false,
+    }))
 }
 
-#[instrument(skip_all)]
-async fn post_load(
-    State(RouterState { running_builds, .. }): State<RouterState>,
-    AxumJson(build): AxumJson<stats::LoadRequest>,
-) -> Result<AxumJson<stats::LoadResponse>, Error> {
-    let mut running_builds = running_builds.lock().await;
-    let mut load = calculate_capacity(&mut running_builds);
+#[instrument(skip_all, fields(%project))]
+async fn put_project_env(
+    State(RouterState { service, .. }): State<RouterState>,
+    ScopedUser {
+        user,
+        scope: project,
+    }: ScopedUser,
+    AxumJson(vars): AxumJson<BTreeMap<String, String>>,
+) -> Result<AxumJson<project::EnvResponse>, Error> {
+    user.ensure_action_allowed(Action::Configure)?;
 
-    if load.has_capacity
-        && running_builds
-            .insert(build.id, (), Duration::from_secs(60 * 10))
-            .is_none()
-    {
-        // Only increase when an item was not already in the queue
-        load.builds_count += 1;
-    }
+    service.set_project_env(&project, &vars).await?;
 
-    Ok(AxumJson(load))
+    let vars = redacted_env(service.iter_project_env_names(&project).await?);
+
+    // Env vars are only set when a container is created
+    Ok(AxumJson(project::EnvResponse {
+        vars,
+        restart_required: true,
+    }))
 }
 
-#[instrument(skip_all)]
-async fn delete_load(
-    State(RouterState { running_builds, .. }): State<RouterState>,
-    AxumJson(build): AxumJson<stats::LoadRequest>,
-) -> Result<AxumJson<stats::LoadResponse>, Error> {
-    let mut running_builds = running_builds.lock().await;
-    running_builds.remove(&build.id);
+#[instrument(skip_all, fields(%project, %name))]
+async fn delete_project_env(
+    State(RouterState { service, .. }): State<RouterState>,
+    ScopedUser {
+        user,
+        scope: project,
+    }: ScopedUser,
+    Path((_, name)): Path<(ProjectName, String)>,
+) -> Result<AxumJson<project::EnvResponse>, Error> {
+    user.ensure_action_allowed(Action::Configure)?;
 
-    let load = calculate_capacity(&mut running_builds);
+    service.remove_project_env(&project, &name).await?;
 
-    Ok(AxumJson(load))
+    let vars = redacted_env(service.iter_project_env_names(&project).await?);
+
+    Ok(AxumJson(project::EnvResponse {
+        vars,
+        restart_required: true,
+    }))
 }
 
-#[instrument(skip_all)]
-async fn get_load_admin(
-    _: Admin,
-    State(RouterState { running_builds, .. }): State<RouterState>,
-) -> Result<AxumJson<stats::LoadResponse>, Error> {
-    let mut running_builds = running_builds.lock().await;
+#[instrument(skip_all, fields(%project))]
+async fn get_project_aliases(
+    State(RouterState { service, .. }): State<RouterState>,
+    ScopedUser {
+        user,
+        scope: project,
+    }: ScopedUser,
+) -> Result<AxumJson<Vec<String>>, Error> {
+    user.ensure_action_allowed(Action::Read)?;
 
-    let load = calculate_capacity(&mut running_builds);
+    let aliases = service
+        .iter_project_aliases(&project)
+        .await?
+        .map(|alias| alias.to_string())
+        .collect();
 
-    Ok(AxumJson(load))
+    Ok(AxumJson(aliases))
 }
 
-#[instrument(skip_all)]
-async fn delete_load_admin(
-    _: Admin,
-    State(RouterState { running_builds, .. }): State<RouterState>,
-) -> Result<AxumJson<stats::LoadResponse>, Error> {
-    let mut running_builds = running_builds.lock().await;
-    running_builds.clear();
-
-    let load = calculate_capacity(&mut running_builds);
+#[instrument(skip_all, fields(%project, %alias))]
+async fn put_project_alias(
+    State(RouterState { service, .. }): State<RouterState>,
+    ScopedUser {
+        user,
+        scope: project,
+    }: ScopedUser,
+    Path((_, alias)): Path<(ProjectName, ProjectName)>,
+) -> Result<(), Error> {
+    user.ensure_action_allowed(Action::Configure)?;
 
-    Ok(AxumJson(load))
+    service.add_project_alias(&project, &alias).await
 }
 
-fn calculate_capacity(running_builds: &mut MutexGuard<TtlCache<Uuid, ()>>) -> stats::LoadResponse {
-    let active = running_builds.iter().count();
-    let capacity = running_builds.capacity();
-    let has_capacity = active < capacity;
+#[instrument(skip_all, fields(%project, %alias))]
+async fn delete_project_alias(
+    State(RouterState { service, .. }): State<RouterState>,
+    ScopedUser {
+        user,
+        scope: project,
+    }: ScopedUser,
+    Path((_, alias)): Path<(ProjectName, ProjectName)>,
+) -> Result<(), Error> {
+    user.ensure_action_allowed(Action::Configure)?;
 
-    stats::LoadResponse {
-        builds_count: active,
-        has_capacity,
-    }
+    service.remove_project_alias(&project, &alias).await
 }
 
-#[instrument(skip_all)]
-async fn revive_projects(
-    _: Admin,
-    State(RouterState {
-        service, sender, ..
-    }): State<RouterState>,
-) -> Result<(), Error> {
-    crate::project::exec::revive(service, sender)
-        .await
-        .map_err(|_| Error::from_kind(ErrorKind::Internal))
+#[instrument(skip_all, fields(%project))]
+async fn get_project_webhook(
+    State(RouterState { service, .. }): State<RouterState>,
+    ScopedUser {
+        user,
+        scope: project,
+    }: ScopedUser,
+) -> Result<AxumJson<project::WebhookResponse>, Error> {
+    user.ensure_action_allowed(Action::Read)?;
+
+    let webhook = service
+        .find_project_webhook(&project)
+        .await?
+        .ok_or_else(|| Error::from_kind(ErrorKind::WebhookNotFound))?;
+
+    // The secret is only ever handed out when the webhook is set
+    Ok(AxumJson(project::WebhookResponse {
+        url: webhook.url,
+        secret: None,
+    }))
 }
 
-#[instrument(skip_all, fields(%email, ?acme_server))]
-async fn create_acme_account(
-    _: Admin,
-    Extension(acme_client): Extension<AcmeClient>,
-    Path(email): Path<String>,
-    AxumJson(acme_server): AxumJson<Option<String>>,
-) -> Result<AxumJson<serde_json::Value>, Error> {
-    let res = acme_client.create_account(&email, acme_server).await?;
+#[instrument(skip_all, fields(%project))]
+async fn put_project_webhook(
+    State(RouterState { service, .. }): State<RouterState>,
+    ScopedUser {
+        user,
+        scope: project,
+    }: ScopedUser,
+    AxumJson(project::WebhookRequest { url }): AxumJson<project::WebhookRequest>,
+) -> Result<AxumJson<project::WebhookResponse>, Error> {
+    user.ensure_action_allowed(Action::Configure)?;
 
-    Ok(AxumJson(res))
+    let webhook = service.set_project_webhook(&project, url).await?;
+
+    Ok(AxumJson(project::WebhookResponse {
+        url: webhook.url,
+        secret: Some(webhook.secret),
+    }))
 }
 
-#[instrument(skip_all, fields(%project_name, %fqdn))]
-async fn request_acme_certificate(
-    _: Admin,
-    State(RouterState {
-        service, sender, ..
-    }): State<RouterState>,
-    Extension(acme_client): Extension<AcmeClient>,
-    Extension(resolver): Extension<Arc<GatewayCertResolver>>,
-    Path((project_name, fqdn)): Path<(ProjectName, String)>,
-    AxumJson(credentials): AxumJson<AccountCredentials<'_>>,
-) -> Result<String, Error> {
-    let fqdn: FQDN = fqdn
-        .parse()
-        .map_err(|_err| Error::from(ErrorKind::InvalidCustomDomain))?;
+#[instrument(skip_all, fields(%project))]
+async fn delete_project_webhook(
+    State(RouterState { service, .. }): State<RouterState>,
+    ScopedUser {
+        user,
+        scope: project,
+    }: ScopedUser,
+) -> Result<(), Error> {
+    user.ensure_action_allowed(Action::Configure)?;
 
-    let (certs, private_key) = match service.project_details_for_custom_domain(&fqdn).await {
-        Ok(CustomDomain {
-            certificate,
-            private_key,
-            ..
-        }) => (certificate, private_key),
-        Err(err) if err.kind() == ErrorKind::CustomDomainNotFound => {
-            let (certs, private_key) = acme_client
-                .create_certificate(&fqdn.to_string(), ChallengeType::Http01, credentials)
-                .await?;
-            service
-                .create_custom_domain(project_name.clone(), &fqdn, &certs, &private_key)
-                .await?;
-            (certs, private_key)
-        }
-        Err(err) => return Err(err),
-    };
+    service.remove_project_webhook(&project).await
+}
+
+#[instrument(skip_all, fields(%project))]
+async fn get_project_rate_limit(
+    State(RouterState { service, .. }): State<RouterState>,
+    ScopedUser {
+        user,
+        scope: project,
+    }: ScopedUser,
+) -> Result<AxumJson<Option<project::RateLimit>>, Error> {
+    user.ensure_action_allowed(Action::Read)?;
+
+    Ok(AxumJson(service.rate_limiter().limit(&project)))
+}
+
+#[instrument(skip_all, fields(%project))]
+async fn put_project_rate_limit(
+    State(RouterState { service, .. }): State<RouterState>,
+    ScopedUser {
+        user,
+        scope: project,
+    }: ScopedUser,
+    AxumJson(limit): AxumJson<project::RateLimit>,
+) -> Result<AxumJson<Option<project::RateLimit>>, Error> {
+    user.ensure_action_allowed(Action::Configure)?;
 
-    // destroy and recreate the project with the new domain
     service
-        .new_task()
-        .project(project_name)
-        .and_then(task::destroy())
-        .and_then(task::run_until_done())
-        .and_then(task::run({
-            let fqdn = fqdn.to_string();
-            move |ctx| {
-                let fqdn = fqdn.clone();
-                async move {
-                    let creating = ProjectCreating::new_with_random_initial_key(ctx.project_name)
-                        .with_fqdn(fqdn);
-                    TaskResult::Done(Project::Creating(creating))
-                }
-            }
-        }))
-        .send(&sender)
+        .set_project_rate_limit(&project, limit.clone())
         .await?;
 
-    let mut buf = Vec::new();
-    buf.extend(certs.as_bytes());
-    buf.extend(private_key.as_bytes());
-    resolver
-        .serve_pem(&fqdn.to_string(), Cursor::new(buf))
+    Ok(AxumJson(Some(limit)))
+}
+
+#[instrument(skip_all, fields(%project))]
+async fn delete_project_rate_limit(
+    State(RouterState { service, .. }): State<RouterState>,
+    ScopedUser {
+        user,
+        scope: project,
+    }: ScopedUser,
+) -> Result<AxumJson<Option<project::RateLimit>>, Error> {
+    user.ensure_action_allowed(Action::Configure)?;
+
+    service.remove_project_rate_limit(&project).await?;
+
+    Ok(AxumJson(None))
+}
+
+#[instrument(skip_all, fields(%project))]
+async fn get_project_canary(
+    State(RouterState { service, .. }): State<RouterState>,
+    ScopedUser {
+        user,
+        scope: project,
+    }: ScopedUser,
+) -> Result<AxumJson<Option<project::Canary>>, Error> {
+    user.ensure_action_allowed(Action::Read)?;
+
+    let canary = service
+        .canaries()
+        .split(&project)
+        .map(|split| split.canary.clone());
+
+    Ok(AxumJson(canary))
+}
+
+#[instrument(skip_all, fields(%project))]
+async fn put_project_canary(
+    State(RouterState { service, .. }): State<RouterState>,
+    ScopedUser {
+        user,
+        scope: project,
+    }: ScopedUser,
+    AxumJson(canary): AxumJson<project::Canary>,
+) -> Result<AxumJson<Option<project::Canary>>, Error> {
+    user.ensure_action_allowed(Action::Configure)?;
+
+    service.set_project_canary(&project, canary.clone()).await?;
+
+    Ok(AxumJson(Some(canary)))
+}
+
+#[instrument(skip_all, fields(%project))]
+async fn post_promote_project_canary(
+    State(RouterState { service, .. }): State<RouterState>,
+    ScopedUser {
+        user,
+        scope: project,
+    }: ScopedUser,
+) -> Result<AxumJson<Option<project::Canary>>, Error> {
+    user.ensure_action_allowed(Action::Configure)?;
+
+    let canary = service.promote_project_canary(&project).await?;
+
+    Ok(AxumJson(Some(canary)))
+}
+
+#[instrument(skip_all, fields(%project))]
+async fn delete_project_canary(
+    State(RouterState { service, .. }): State<RouterState>,
+    ScopedUser {
+        user,
+        scope: project,
+    }: ScopedUser,
+) -> Result<AxumJson<Option<project::Canary>>, Error> {
+    user.ensure_action_allowed(Action::Configure)?;
+
+    service.remove_project_canary(&project).await?;
+
+    Ok(AxumJson(None))
+}
+
+#[instrument(skip_all, fields(%project))]
+async fn get_project_ip_filter(
+    State(RouterState { service, .. }): State<RouterState>,
+    ScopedUser {
+        user,
+        scope: project,
+    }: ScopedUser,
+) -> Result<AxumJson<Option<project::IpFilter>>, Error> {
+    user.ensure_action_allowed(Action::Read)?;
+
+    let filter = service
+        .ip_filters()
+        .rules(&project)
+        .map(|rules| rules.filter.clone());
+
+    Ok(AxumJson(filter))
+}
+
+#[instrument(skip_all, fields(%project))]
+async fn put_project_ip_filter(
+    State(RouterState { service, .. }): State<RouterState>,
+    ScopedUser {
+        user,
+        scope: project,
+    }: ScopedUser,
+    AxumJson(filter): AxumJson<project::IpFilter>,
+) -> Result<AxumJson<Option<project::IpFilter>>, Error> {
+    user.ensure_action_allowed(Action::Configure)?;
+
+    service
+        .set_project_ip_filter(&project, filter.clone())
         .await?;
 
-    Ok("certificate created".to_string())
+    Ok(AxumJson(Some(filter)))
 }
 
-async fn get_projects(
-    _: Admin,
+#[instrument(skip_all, fields(%project))]
+async fn delete_project_ip_filter(
     State(RouterState { service, .. }): State<RouterState>,
-) -> Result<AxumJson<Vec<project::AdminResponse>>, Error> {
-    let projects = service
-        .iter_projects_detailed()
-        .await?
-        .into_iter()
-        .map(Into::into)
-        .collect();
+    ScopedUser {
+        user,
+        scope: project,
+    }: ScopedUser,
+) -> Result<AxumJson<Option<project::IpFilter>>, Error> {
+    user.ensure_action_allowed(Action::Configure)?;
 
-    Ok(AxumJson(projects))
+    service.remove_project_ip_filter(&project).await?;
+
+    Ok(AxumJson(None))
 }
 
-#[derive(Clone)]
-pub(crate) struct RouterState {
-    pub service: Arc<GatewayService>,
-    pub sender: Sender<BoxedTask>,
-    pub running_builds: Arc<Mutex<TtlCache<Uuid, ()>>>,
+#[instrument(skip_all, fields(%project))]
+async fn put_project_git_token(
+    State(RouterState { service, .. }): State<RouterState>,
+    ScopedUser {
+        user,
+        scope: project,
+    }: ScopedUser,
+    AxumJson(project::GitToken { token }): AxumJson<project::GitToken>,
+) -> Result<(), Error> {
+    user.ensure_action_allowed(Action::Configure)?;
+
+    service.set_project_git_token(&project, &token).await?;
+
+    Ok(())
 }
 
-pub struct ApiBuilder {
-    router: Router<RouterState>,
-    service: Option<Arc<GatewayService>>,
-    sender: Option<Sender<BoxedTask>>,
-    bind: Option<SocketAddr>,
+#[instrument(skip_all, fields(%project))]
+async fn delete_project_git_token(
+    State(RouterState { service, .. }): State<RouterState>,
+    ScopedUser {
+        user,
+        scope: project,
+    }: ScopedUser,
+) -> Result<(), Error> {
+    user.ensure_action_allowed(Action::Configure)?;
+
+    service.remove_project_git_token(&project).await?;
+
+    Ok(())
 }
 
-impl Default for ApiBuilder {
-    fn default() -> Self {
-        Self::new()
-    }
+#[instrument(skip_all, fields(%project))]
+async fn get_project_tags(
+    State(RouterState { service, .. }): State<RouterState>,
+    ScopedUser {
+        user,
+        scope: project,
+    }: ScopedUser,
+) -> Result<AxumJson<Vec<String>>, Error> {
+    user.ensure_action_allowed(Action::Read)?;
+
+    Ok(AxumJson(service.project_tags(&project).await?))
 }
 
-impl ApiBuilder {
-    pub fn new() -> Self {
-        Self {
-            router: Router::new(),
-            service: None,
-            sender: None,
-            bind: None,
-        }
-    }
+#[instrument(skip_all, fields(%project))]
+async fn put_project_tags(
+    State(RouterState { service, .. }): State<RouterState>,
+    ScopedUser {
+        user,
+        scope: project,
+    }: ScopedUser,
+    AxumJson(tags): AxumJson<Vec<String>>,
+) -> Result<AxumJson<Vec<String>>, Error> {
+    user.ensure_action_allowed(Action::Configure)?;
 
-    pub fn with_acme(mut self, acme: AcmeClient, resolver: Arc<GatewayCertResolver>) -> Self {
-        self.router = self
-            .router
-            .route("/admin/acme/:email", post(create_acme_account))
-            .route(
-                "/admin/acme/request/:project_name/:fqdn",
-                post(request_acme_certificate),
-            )
-            .layer(Extension(acme))
-            .layer(Extension(resolver));
-        self
-    }
+    Ok(AxumJson(service.set_project_tags(&project, &tags).await?))
+}
 
-    pub fn with_service(mut self, service: Arc<GatewayService>) -> Self {
-        self.service = Some(service);
-        self
-    }
+#[instrument(skip_all, fields(%project, %tag))]
+async fn delete_project_tag(
+    State(RouterState { service, .. }): State<RouterState>,
+    ScopedUser {
+        user,
+        scope: project,
+    }: ScopedUser,
+    Path((_, tag)): Path<(ProjectName, String)>,
+) -> Result<AxumJson<Vec<String>>, Error> {
+    user.ensure_action_allowed(Action::Configure)?;
 
-    pub fn with_sender(mut self, sender: Sender<BoxedTask>) -> Self {
-        self.sender = Some(sender);
-        self
-    }
+    service.remove_project_tag(&project, &tag).await?;
 
-    pub fn binding_to(mut self, addr: SocketAddr) -> Self {
-        self.bind = Some(addr);
-        self
-    }
+    Ok(AxumJson(service.project_tags(&project).await?))
+}
 
-    pub fn with_default_traces(mut self) -> Self {
-        self.router = self.router.route_layer(from_extractor::<Metrics>()).layer(
-            TraceLayer::new_for_http()
-                .make_span_with(|request: &Request<Body>| {
-                    let path = if let Some(path) = request.extensions().get::<MatchedPath>() {
-                        path.as_str()
-                    } else {
-                        ""
-                    };
+#[instrument(skip_all, fields(%project))]
+async fn get_project_response_cache(
+    State(RouterState { service, .. }): State<RouterState>,
+    ScopedUser {
+        user,
+        scope: project,
+    }: ScopedUser,
+) -> Result<AxumJson<Option<project::ResponseCache>>, Error> {
+    user.ensure_action_allowed(Action::Read)?;
 
-                    debug_span!(
-                        "request",
-                        http.uri = %request.uri(),
-                        http.method = %request.method(),
-                        http.status_code = field::Empty,
-                        account.name = field::Empty,
-                        // A bunch of extra things for metrics
-                        // Should be able to make this clearer once `Valuable` support lands in tracing
-                        request.path = path,
-                        request.params.project_name = field::Empty,
-                        request.params.account_name = field::Empty,
-                    )
-                })
-                .on_response(
-                    |response: &Response<BoxBody>, latency: Duration, span: &Span| {
-                        span.record("http.status_code", response.status().as_u16());
-                        debug!(
-                            latency = format_args!("{} ns", latency.as_nanos()),
-                            "finished processing request"
-                        );
-                    },
-                ),
-        );
-        self
-    }
+    Ok(AxumJson(service.response_cache().config(&project)))
+}
 
-    pub fn with_default_routes(mut self) -> Self {
-        self.router = self
-            .router
-            .route("/", get(get_status))
-            .route("/projects", get(get_projects_list))
-            .route(
-                "/projects/:project_name",
-                get(get_project).delete(delete_project).post(post_project),
-            )
-            .route("/users/:account_name", get(get_user).post(post_user))
-            .route("/projects/:project_name/*any", any(route_project))
-            .route("/stats/load", post(post_load).delete(delete_load))
-            .route("/admin/projects", get(get_projects))
-            .route("/admin/revive", post(revive_projects))
-            .route(
-                "/admin/stats/load",
-                get(get_load_admin).delete(delete_load_admin),
-            );
-        self
-    }
+#[instrument(skip_all, fields(%project))]
+async fn put_project_response_cache(
+    State(RouterState { service, .. }): State<RouterState>,
+    ScopedUser {
+        user,
+        scope: project,
+    }: ScopedUser,
+    AxumJson(config): AxumJson<project::ResponseCache>,
+) -> Result<AxumJson<Option<project::ResponseCache>>, Error> {
+    user.ensure_action_allowed(Action::Configure)?;
 
-    pub fn into_router(self) -> Router {
-        let service = self.service.expect("a GatewayService is required");
-        let sender = self.sender.expect("a task Sender is required");
+    service
+        .set_project_response_cache(&project, config.clone())
+        .await?;
 
-        // Allow about 4 cores per build
-        let mut concurrent_builds = num_cpus::get() / 4;
-        if concurrent_builds < 1 {
-            concurrent_builds = 1;
-        }
+    Ok(AxumJson(Some(config)))
+}
+
+#[instrument(skip_all, fields(%project))]
+async fn delete_project_response_cache(
+    State(RouterState { service, .. }): State<RouterState>,
+    ScopedUser {
+        user,
+        scope: project,
+    }: ScopedUser,
+) -> Result<AxumJson<Option<project::ResponseCache>>, Error> {
+    user.ensure_action_allowed(Action::Configure)?;
+
+    service.remove_project_response_cache(&project).await?;
+
+    Ok(AxumJson(None))
+}
+
+#[instrument(skip_all, fields(%project))]
+async fn get_project_connection_limit(
+    State(RouterState { service, .. }): State<RouterState>,
+    ScopedUser {
+        user,
+        scope: project,
+    }: ScopedUser,
+) -> Result<AxumJson<Option<project::ConnectionLimit>>, Error> {
+    user.ensure_action_allowed(Action::Read)?;
+
+    Ok(AxumJson(service.connection_limiter().limit(&project)))
+}
+
+#[instrument(skip_all, fields(%project))]
+async fn put_project_connection_limit(
+    State(RouterState { service, .. }): State<RouterState>,
+    ScopedUser {
+        user,
+        scope: project,
+    }: ScopedUser,
+    AxumJson(limit): AxumJson<project::ConnectionLimit>,
+) -> Result<AxumJson<Option<project::ConnectionLimit>>, Error> {
+    user.ensure_action_allowed(Action::Configure)?;
+
+    service
+        .set_project_connection_limit(&project, limit.clone())
+        .await?;
+
+    Ok(AxumJson(Some(limit)))
+}
+
+#[instrument(skip_all, fields(%project))]
+async fn delete_project_connection_limit(
+    State(RouterState { service, .. }): State<RouterState>,
+    ScopedUser {
+        user,
+        scope: project,
+    }: ScopedUser,
+) -> Result<AxumJson<Option<project::ConnectionLimit>>, Error> {
+    user.ensure_action_allowed(Action::Configure)?;
+
+    service.remove_project_connection_limit(&project).await?;
+
+    Ok(AxumJson(None))
+}
+
+#[instrument(skip_all, fields(%project))]
+async fn get_project_maintenance_page(
+    State(RouterState { service, .. }): State<RouterState>,
+    ScopedUser {
+        user,
+        scope: project,
+    }: ScopedUser,
+) -> Result<AxumJson<Option<project::MaintenancePage>>, Error> {
+    user.ensure_action_allowed(Action::Read)?;
+
+    Ok(AxumJson(service.project_maintenance_page(&project)))
+}
+
+#[instrument(skip_all, fields(%project))]
+async fn put_project_maintenance_page(
+    State(RouterState { service, .. }): State<RouterState>,
+    ScopedUser {
+        user,
+        scope: project,
+    }: ScopedUser,
+    AxumJson(page): AxumJson<project::MaintenancePage>,
+) -> Result<AxumJson<Option<project::MaintenancePage>>, Error> {
+    user.ensure_action_allowed(Action::Configure)?;
+
+    service
+        .set_project_maintenance_page(&project, page.clone())
+        .await?;
+
+    Ok(AxumJson(Some(page)))
+}
+
+#[instrument(skip_all, fields(%project))]
+async fn delete_project_maintenance_page(
+    State(RouterState { service, .. }): State<RouterState>,
+    ScopedUser {
+        user,
+        scope: project,
+    }: ScopedUser,
+) -> Result<AxumJson<Option<project::MaintenancePage>>, Error> {
+    user.ensure_action_allowed(Action::Configure)?;
+
+    service.remove_project_maintenance_page(&project).await?;
+
+    Ok(AxumJson(None))
+}
+
+#[instrument(skip_all, fields(%project))]
+async fn get_project_upstream(
+    State(RouterState { service, .. }): State<RouterState>,
+    ScopedUser {
+        user,
+        scope: project,
+    }: ScopedUser,
+) -> Result<AxumJson<project::Upstream>, Error> {
+    user.ensure_action_allowed(Action::Read)?;
+
+    Ok(AxumJson(project::Upstream {
+        protocol: service.project_upstream_protocol(&project),
+    }))
+}
+
+#[instrument(skip_all, fields(%project))]
+async fn put_project_upstream(
+    State(RouterState { service, .. }): State<RouterState>,
+    ScopedUser {
+        user,
+        scope: project,
+    }: ScopedUser,
+    AxumJson(upstream): AxumJson<project::Upstream>,
+) -> Result<AxumJson<project::Upstream>, Error> {
+    user.ensure_action_allowed(Action::Configure)?;
+
+    service
+        .set_project_upstream_protocol(&project, upstream.protocol)
+        .await?;
+
+    Ok(AxumJson(upstream))
+}
+
+#[instrument(skip_all, fields(%project))]
+async fn get_project_basic_auth(
+    State(RouterState { service, .. }): State<RouterState>,
+    ScopedUser {
+        user,
+        scope: project,
+    }: ScopedUser,
+) -> Result<AxumJson<Option<project::BasicAuthResponse>>, Error> {
+    user.ensure_action_allowed(Action::Read)?;
+
+    let auth = service
+        .basic_auth_gate()
+        .auth(&project)
+        .map(|auth| project::BasicAuthResponse {
+            username: auth.username.clone(),
+        });
+
+    Ok(AxumJson(auth))
+}
+
+#[instrument(skip_all, fields(%project))]
+async fn put_project_basic_auth(
+    State(RouterState { service, .. }): State<RouterState>,
+    ScopedUser {
+        user,
+        scope: project,
+    }: ScopedUser,
+    AxumJson(project::BasicAuthRequest { username, password }): AxumJson<project::BasicAuthRequest>,
+) -> Result<AxumJson<Option<project::BasicAuthResponse>>, Error> {
+    user.ensure_action_allowed(Action::Configure)?;
+
+    service
+        .set_project_basic_auth(&project, username.clone(), &password)
+        .await?;
+
+    Ok(AxumJson(Some(project::BasicAuthResponse { username })))
+}
+
+#[instrument(skip_all, fields(%project))]
+async fn delete_project_basic_auth(
+    State(RouterState { service, .. }): State<RouterState>,
+    ScopedUser {
+        user,
+        scope: project,
+    }: ScopedUser,
+) -> Result<AxumJson<Option<project::BasicAuthResponse>>, Error> {
+    user.ensure_action_allowed(Action::Configure)?;
+
+    service.remove_project_basic_auth(&project).await?;
+
+    Ok(AxumJson(None))
+}
+
+#[instrument(skip_all, fields(%project))]
+async fn get_project_header_rules(
+    State(RouterState { service, .. }): State<RouterState>,
+    ScopedUser {
+        user,
+        scope: project,
+    }: ScopedUser,
+) -> Result<AxumJson<project::HeaderRules>, Error> {
+    user.ensure_action_allowed(Action::Read)?;
+
+    let rules = service
+        .header_rewriter()
+        .rewrites(&project)
+        .map(|rewrites| rewrites.rules.clone())
+        .unwrap_or_default();
+
+    Ok(AxumJson(rules))
+}
+
+#[instrument(skip_all, fields(%project))]
+async fn put_project_header_rules(
+    State(RouterState { service, .. }): State<RouterState>,
+    ScopedUser {
+        user,
+        scope: project,
+    }: ScopedUser,
+    AxumJson(rules): AxumJson<project::HeaderRules>,
+) -> Result<AxumJson<project::HeaderRules>, Error> {
+    user.ensure_action_allowed(Action::Configure)?;
+
+    service
+        .set_project_header_rules(&project, rules.clone())
+        .await?;
+
+    Ok(AxumJson(rules))
+}
+
+#[instrument(skip_all, fields(%project))]
+async fn delete_project_header_rules(
+    State(RouterState { service, .. }): State<RouterState>,
+    ScopedUser {
+        user,
+        scope: project,
+    }: ScopedUser,
+) -> Result<AxumJson<project::HeaderRules>, Error> {
+    user.ensure_action_allowed(Action::Configure)?;
+
+    service.remove_project_header_rules(&project).await?;
+
+    Ok(AxumJson(project::HeaderRules::default()))
+}
+
+#[instrument(skip_all, fields(scope = %scoped_user.scope))]
+async fn route_project(
+    State(RouterState { service, .. }): State<RouterState>,
+    scoped_user: ScopedUser,
+    req: Request<Body>,
+) -> Result<Response<Body>, Error> {
+    // Anything but reads goes to the deployments of the project
+    let action = match *req.method() {
+        Method::GET | Method::HEAD => Action::Read,
+        _ => Action::Deploy,
+    };
+    scoped_user.user.ensure_action_allowed(action)?;
+
+    // New deployments are `POST`ed to the deployer, one at a time
+    if req.method() == Method::POST {
+        service.ensure_not_in_maintenance()?;
+
+        // Uploads which are not crates at all are turned away before
+        // any deploy is started for them
+        let req = if req
+            .uri()
+            .path()
+            .starts_with(&format!("/projects/{}/services/", scoped_user.scope))
+        {
+            check_archive(req).await?
+        } else {
+            req
+        };
+
+        let _guard = service
+            .lock_deploy(&scoped_user.scope, &scoped_user.user.name)
+            .await?;
+        let response = service.route(&scoped_user, req).await?;
+        if response.status().is_success() {
+            service.counters().record_deployment();
+            service.deploy_handed_over(&scoped_user.scope);
+            service
+                .record_project_event(&scoped_user.scope, project::EventKind::Deployed, None)
+                .await;
+        }
+        return Ok(response);
+    }
+
+    service.route(&scoped_user, req).await
+}
+
+#[instrument(skip_all, fields(project = %scoped_user.scope, %url, %reference))]
+async fn post_deploy_from_git(
+    State(RouterState { service, .. }): State<RouterState>,
+    scoped_user: ScopedUser,
+    AxumJson(project::GitDeployRequest { url, reference }): AxumJson<project::GitDeployRequest>,
+) -> Result<Response<Body>, Error> {
+    scoped_user.user.ensure_action_allowed(Action::Deploy)?;
+
+    let source = GitSource::new(&url, &reference)?;
+
+    service.ensure_not_in_maintenance()?;
+
+    let project_name = &scoped_user.scope;
+    let token = service.project_git_token(project_name).await?;
+    let archive = source.package(project_name, token.as_deref()).await?;
+
+    // From here on, it is as if the crate had been uploaded
+    let req = Request::builder()
+        .method(Method::POST)
+        .uri(format!("/projects/{project_name}/services/{project_name}"))
+        .header(CONTENT_TYPE, "application/octet-stream")
+        .body(Body::from(archive))
+        .unwrap();
+
+    let _guard = service
+        .lock_deploy(project_name, &scoped_user.user.name)
+        .await?;
+    let response = service.route(&scoped_user, req).await?;
+    if response.status().is_success() {
+        service.counters().record_deployment();
+        service.deploy_handed_over(project_name);
+        service
+            .record_project_event(
+                project_name,
+                project::EventKind::Deployed,
+                Some(format!("{url}@{reference}")),
+            )
+            .await;
+    }
+
+    Ok(response)
+}
+
+#[instrument(skip_all, fields(project = %scoped_user.scope, new_name = %name))]
+async fn post_rename_project(
+    State(RouterState {
+        service, sender, ..
+    }): State<RouterState>,
+    scoped_user: ScopedUser,
+    AxumJson(project::RenameRequest { name, redirect }): AxumJson<project::RenameRequest>,
+) -> Result<AxumJson<project::Response>, Error> {
+    scoped_user.user.ensure_action_allowed(Action::Deploy)?;
+
+    let new_name: ProjectName = name.parse()?;
+    scoped_user.user.ensure_allowed(&new_name, Action::Create)?;
+
+    service.ensure_not_in_maintenance()?;
+
+    // Deployments in flight would go to the container about to be
+    // replaced
+    let _guard = service.lock_deployments(&scoped_user.scope).await?;
+
+    let state = service
+        .rename_project(&scoped_user.scope, &new_name, redirect)
+        .await?;
+
+    service
+        .new_task()
+        .project(new_name.clone())
+        .send(&sender)
+        .await?;
+
+    Ok(AxumJson(project::Response {
+        name: new_name.to_string(),
+        state: state.into(),
+        last_activity: service.activity_tracker().last_activity(&new_name),
+    }))
+}
+
+#[derive(Debug, Deserialize)]
+struct ContainerLogsParams {
+    tail: Option<usize>,
+    #[serde(default)]
+    follow: bool,
+}
+
+#[instrument(skip_all, fields(%project_name))]
+async fn get_container_logs(
+    State(RouterState { service, .. }): State<RouterState>,
+    ScopedUser {
+        user,
+        scope: project_name,
+    }: ScopedUser,
+    Query(ContainerLogsParams { tail, follow }): Query<ContainerLogsParams>,
+) -> Result<Response<Body>, Error> {
+    user.ensure_action_allowed(Action::Read)?;
+
+    let project = service.find_project(&project_name).await?;
+
+    let logs = crate::project::container_logs(
+        &service.context(),
+        &project,
+        tail.unwrap_or(crate::project::DEFAULT_CONTAINER_LOGS_TAIL),
+        follow,
+    )
+    .await?;
+
+    Ok(Response::builder()
+        .header(CONTENT_TYPE, "text/plain; charset=utf-8")
+        .body(Body::wrap_stream(logs))
+        .unwrap())
+}
+
+/// The deploys the gateway holds on to, which the deployer does not
+/// know of yet
+#[instrument(skip_all, fields(%project_name))]
+async fn get_deployment_queue(
+    State(RouterState { service, .. }): State<RouterState>,
+    ScopedUser {
+        user,
+        scope: project_name,
+    }: ScopedUser,
+) -> Result<AxumJson<Vec<PendingDeploy>>, Error> {
+    user.ensure_action_allowed(Action::Read)?;
+
+    Ok(AxumJson(service.pending_deploys(&project_name)))
+}
+
+async fn get_status(State(RouterState { sender, .. }): State<RouterState>) -> Response<Body> {
+    let (status, body) = if sender.is_closed() || sender.capacity() == 0 {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            StatusResponse::unhealthy(),
+        )
+    } else if sender.capacity() < WORKER_QUEUE_SIZE - SVC_DEGRADED_THRESHOLD {
+        (StatusCode::OK, StatusResponse::degraded())
+    } else {
+        (StatusCode::OK, StatusResponse::healthy())
+    };
+
+    let body = serde_json::to_vec(&body).unwrap();
+    Response::builder()
+        .status(status)
+        .body(body.into())
+        .unwrap()
+}
+
+async fn get_version() -> AxumJson<VersionResponse> {
+    AxumJson(VersionResponse::current())
+}
+
+#[instrument(skip_all)]
+async fn post_load(
+    State(RouterState { running_builds, .. }): State<RouterState>,
+    AxumJson(build): AxumJson<stats::LoadRequest>,
+) -> Result<AxumJson<stats::LoadResponse>, Error> {
+    let mut running_builds = running_builds.lock().await;
+    let mut load = calculate_capacity(&mut running_builds);
+
+    if load.has_capacity
+        && running_builds
+            .insert(build.id, (), Duration::from_secs(60 * 10))
+            .is_none()
+    {
+        // Only increase when an item was not already in the queue
+        load.builds_count += 1;
+    }
+
+    Ok(AxumJson(load))
+}
+
+#[instrument(skip_all)]
+async fn delete_load(
+    State(RouterState { running_builds, .. }): State<RouterState>,
+    AxumJson(build): AxumJson<stats::LoadRequest>,
+) -> Result<AxumJson<stats::LoadResponse>, Error> {
+    let mut running_builds = running_builds.lock().await;
+    running_builds.remove(&build.id);
+
+    let load = calculate_capacity(&mut running_builds);
+
+    Ok(AxumJson(load))
+}
+
+#[instrument(skip_all)]
+async fn get_load_admin(
+    _: Admin,
+    State(RouterState { running_builds, .. }): State<RouterState>,
+) -> Result<AxumJson<stats::LoadResponse>, Error> {
+    let mut running_builds = running_builds.lock().await;
+
+    let load = calculate_capacity(&mut running_builds);
+
+    Ok(AxumJson(load))
+}
+
+#[instrument(skip_all)]
+async fn delete_load_admin(
+    _: Admin,
+    State(RouterState { running_builds, .. }): State<RouterState>,
+) -> Result<AxumJson<stats::LoadResponse>, Error> {
+    let mut running_builds = running_builds.lock().await;
+    running_builds.clear();
+
+    let load = calculate_capacity(&mut running_builds);
+
+    Ok(AxumJson(load))
+}
+
+fn calculate_capacity(running_builds: &mut MutexGuard<TtlCache<Uuid, ()>>) -> stats::LoadResponse {
+    let active = running_builds.iter().count();
+    let capacity = running_builds.capacity();
+    let has_capacity = active < capacity;
+
+    stats::LoadResponse {
+        builds_count: active,
+        has_capacity,
+    }
+}
+
+#[instrument(skip_all)]
+async fn get_maintenance(
+    _: Admin,
+    State(RouterState { service, .. }): State<RouterState>,
+) -> AxumJson<MaintenanceResponse> {
+    AxumJson(MaintenanceResponse {
+        enabled: service.is_in_maintenance(),
+    })
+}
+
+#[instrument(skip_all)]
+async fn put_maintenance(
+    _: Admin,
+    State(RouterState { service, .. }): State<RouterState>,
+) -> AxumJson<MaintenanceResponse> {
+    info!("turning maintenance mode on");
+    service.set_maintenance(true);
+
+    AxumJson(MaintenanceResponse { enabled: true })
+}
+
+#[instrument(skip_all)]
+async fn delete_maintenance(
+    _: Admin,
+    State(RouterState { service, .. }): State<RouterState>,
+) -> AxumJson<MaintenanceResponse> {
+    info!("turning maintenance mode off");
+    service.set_maintenance(false);
+
+    AxumJson(MaintenanceResponse { enabled: false })
+}
+
+async fn drain_status(service: &GatewayService) -> Result<stats::DrainResponse, Error> {
+    Ok(stats::DrainResponse {
+        draining: service.is_draining(),
+        running_projects: service
+            .iter_running_projects()
+            .await?
+            .into_iter()
+            .map(|project_name| project_name.to_string())
+            .collect(),
+    })
+}
+
+#[instrument(skip_all)]
+async fn get_drain(
+    _: Admin,
+    State(RouterState { service, .. }): State<RouterState>,
+) -> Result<AxumJson<stats::DrainResponse>, Error> {
+    Ok(AxumJson(drain_status(&service).await?))
+}
+
+#[instrument(skip_all, fields(stop_running))]
+async fn post_drain(
+    _: Admin,
+    State(RouterState {
+        service, sender, ..
+    }): State<RouterState>,
+    Query(DrainParams { stop_running }): Query<DrainParams>,
+) -> Result<AxumJson<stats::DrainResponse>, Error> {
+    let stopping = service.drain(stop_running, &sender).await?;
+    info!(stopping = stopping.len(), "draining the node");
+
+    Ok(AxumJson(drain_status(&service).await?))
+}
+
+#[instrument(skip_all)]
+async fn delete_drain(
+    _: Admin,
+    State(RouterState { service, .. }): State<RouterState>,
+) -> Result<AxumJson<stats::DrainResponse>, Error> {
+    info!("putting the node back in rotation");
+    service.set_draining(false);
+
+    Ok(AxumJson(drain_status(&service).await?))
+}
+
+#[instrument(skip(service))]
+async fn post_force_destroy_project(
+    _: Admin,
+    State(RouterState { service, .. }): State<RouterState>,
+    Path(project_name): Path<ProjectName>,
+) -> Result<AxumJson<project::Response>, Error> {
+    let state = service.force_destroy_project(&project_name).await?;
+
+    Ok(AxumJson(project::Response {
+        name: project_name.to_string(),
+        state: state.into(),
+        last_activity: service.activity_tracker().last_activity(&project_name),
+    }))
+}
+
+/// Placed in lieu of secrets in diagnostics
+const REDACTED: &str = "[redacted]";
+
+#[instrument(skip(service, resolver))]
+async fn get_project_diagnostics(
+    _: Admin,
+    State(RouterState { service, .. }): State<RouterState>,
+    resolver: Option<Extension<Arc<GatewayCertResolver>>>,
+    Path(project_name): Path<ProjectName>,
+) -> Result<AxumJson<project::DiagnosticsResponse>, Error> {
+    let project = service.find_project(&project_name).await?;
+    let account_name = service.account_name_from_project(&project_name).await?;
+
+    let mut state =
+        serde_json::to_value(&project).map_err(|err| Error::source(ErrorKind::Internal, err))?;
+    redact_secrets(&mut state);
+
+    let ctx = service.context();
+    let settings = ctx.container_settings();
+    let container = crate::project::container_summary(&ctx, &project_name).await?;
+
+    let tasks = service
+        .task_tracker()
+        .list()
+        .await
+        .into_iter()
+        .filter(|(_, record)| record.project_name == project_name)
+        .map(|(id, record)| stats::TaskSummary {
+            id,
+            project_name: record.project_name.to_string(),
+            state: record.state.to_string(),
+            started_at: record.started_at,
+        })
+        .collect();
+
+    // The subdomain of the project is covered by the wildcard
+    // certificate, its custom domains have their own
+    let mut certificates = vec![project::CertificateStatus {
+        fqdn: format!("{project_name}.{}", settings.fqdn),
+        custom: false,
+        served: match &resolver {
+            Some(Extension(resolver)) => Some(resolver.has_default().await),
+            None => None,
+        },
+    }];
+    for fqdn in service.iter_project_custom_domains(&project_name).await? {
+        let served = match &resolver {
+            Some(Extension(resolver)) => Some(resolver.get(&fqdn).await.is_some()),
+            None => None,
+        };
+        certificates.push(project::CertificateStatus {
+            fqdn,
+            custom: true,
+            served,
+        });
+    }
+
+    Ok(AxumJson(project::DiagnosticsResponse {
+        name: project_name.to_string(),
+        account_name: account_name.to_string(),
+        state,
+        container,
+        tasks,
+        last_activity: service.activity_tracker().last_activity(&project_name),
+        backend: project.target_ip()?.map(|ip| format!("{ip}:8000")),
+        certificates,
+    }))
+}
+
+/// Blank out the secrets in a serialized project state: the admin
+/// secret of its deployer, both as is and on the command line of its
+/// container, and the values of the env vars of its container
+fn redact_secrets(value: &mut serde_json::Value) {
+    match value {
+        serde_json::Value::Object(map) => {
+            for (key, value) in map.iter_mut() {
+                match (key.as_str(), value) {
+                    ("initial_key", value) => *value = REDACTED.into(),
+                    ("Env", serde_json::Value::Array(vars)) => {
+                        for var in vars.iter_mut() {
+                            let name = var
+                                .as_str()
+                                .and_then(|var| var.split_once('='))
+                                .map(|(name, _)| name.to_string());
+                            if let Some(name) = name {
+                                *var = format!("{name}={REDACTED}").into();
+                            }
+                        }
+                    }
+                    ("Cmd", serde_json::Value::Array(args)) => {
+                        let mut args = args.iter_mut();
+                        while let Some(arg) = args.next() {
+                            if *arg == "--admin-secret" {
+                                if let Some(secret) = args.next() {
+                                    *secret = REDACTED.into();
+                                }
+                            }
+                        }
+                    }
+                    (_, value) => redact_secrets(value),
+                }
+            }
+        }
+        serde_json::Value::Array(values) => values.iter_mut().for_each(redact_secrets),
+        _ => {}
+    }
+}
+
+#[instrument(skip(service, sender))]
+async fn post_freeze_project(
+    _: Admin,
+    State(RouterState {
+        service, sender, ..
+    }): State<RouterState>,
+    Path(project_name): Path<ProjectName>,
+) -> Result<AxumJson<project::Response>, Error> {
+    // Make sure the project exists before queuing anything for it
+    service.find_project(&project_name).await?;
+
+    info!("freezing project");
+    service
+        .new_task()
+        .project(project_name.clone())
+        .and_then(task::freeze())
+        .send(&sender)
+        .await?
+        .await;
+
+    let state = service.find_project(&project_name).await?;
+    if !state.is_frozen() {
+        return Err(Error::custom(
+            ErrorKind::InvalidOperation,
+            format!("cannot freeze a project in the `{}` state", state.state()),
+        ));
+    }
+
+    Ok(AxumJson(project::Response {
+        name: project_name.to_string(),
+        state: state.into(),
+        last_activity: service.activity_tracker().last_activity(&project_name),
+    }))
+}
+
+#[instrument(skip(service, sender))]
+async fn post_unfreeze_project(
+    _: Admin,
+    State(RouterState {
+        service, sender, ..
+    }): State<RouterState>,
+    Path(project_name): Path<ProjectName>,
+) -> Result<AxumJson<project::Response>, Error> {
+    if !service.find_project(&project_name).await?.is_frozen() {
+        return Err(Error::custom(
+            ErrorKind::InvalidOperation,
+            "project is not frozen",
+        ));
+    }
+
+    info!("unfreezing project");
+    service
+        .new_task()
+        .project(project_name.clone())
+        .and_then(task::unfreeze())
+        .send(&sender)
+        .await?
+        .await;
+
+    let state = service.find_project(&project_name).await?;
+
+    Ok(AxumJson(project::Response {
+        name: project_name.to_string(),
+        state: state.into(),
+        last_activity: service.activity_tracker().last_activity(&project_name),
+    }))
+}
+
+#[instrument(skip(service))]
+async fn get_deployment_timeout(
+    _: Admin,
+    State(RouterState { service, .. }): State<RouterState>,
+    Path(project_name): Path<ProjectName>,
+) -> Result<AxumJson<Option<project::DeploymentTimeout>>, Error> {
+    service.find_project(&project_name).await?;
+
+    Ok(AxumJson(
+        service.project_deployment_timeout(&project_name).await?,
+    ))
+}
+
+#[instrument(skip(service))]
+async fn put_deployment_timeout(
+    _: Admin,
+    State(RouterState { service, .. }): State<RouterState>,
+    Path(project_name): Path<ProjectName>,
+    AxumJson(timeout): AxumJson<project::DeploymentTimeout>,
+) -> Result<AxumJson<Option<project::DeploymentTimeout>>, Error> {
+    service.find_project(&project_name).await?;
+
+    service
+        .set_project_deployment_timeout(&project_name, timeout.clone())
+        .await?;
+
+    Ok(AxumJson(Some(timeout)))
+}
+
+#[instrument(skip(service))]
+async fn delete_deployment_timeout(
+    _: Admin,
+    State(RouterState { service, .. }): State<RouterState>,
+    Path(project_name): Path<ProjectName>,
+) -> Result<AxumJson<Option<project::DeploymentTimeout>>, Error> {
+    service
+        .remove_project_deployment_timeout(&project_name)
+        .await?;
+
+    Ok(AxumJson(None))
+}
+
+#[instrument(skip(service))]
+async fn get_account_resource_limits(
+    _: Admin,
+    State(RouterState { service, .. }): State<RouterState>,
+    Path(account_name): Path<AccountName>,
+) -> Result<AxumJson<Option<project::ResourceLimits>>, Error> {
+    User::retrieve_from_account_name(&service, account_name.clone()).await?;
+
+    Ok(AxumJson(
+        service.account_resource_limits(&account_name).await?,
+    ))
+}
+
+#[instrument(skip(service))]
+async fn put_account_resource_limits(
+    _: Admin,
+    State(RouterState { service, .. }): State<RouterState>,
+    Path(account_name): Path<AccountName>,
+    AxumJson(limits): AxumJson<project::ResourceLimits>,
+) -> Result<AxumJson<Option<project::ResourceLimits>>, Error> {
+    User::retrieve_from_account_name(&service, account_name.clone()).await?;
+
+    service
+        .set_account_resource_limits(&account_name, limits.clone())
+        .await?;
+
+    Ok(AxumJson(Some(limits)))
+}
+
+#[instrument(skip(service))]
+async fn delete_account_resource_limits(
+    _: Admin,
+    State(RouterState { service, .. }): State<RouterState>,
+    Path(account_name): Path<AccountName>,
+) -> Result<AxumJson<Option<project::ResourceLimits>>, Error> {
+    service
+        .remove_account_resource_limits(&account_name)
+        .await?;
+
+    Ok(AxumJson(None))
+}
+
+#[instrument(skip(service))]
+async fn get_project_feature_flags(
+    _: Admin,
+    State(RouterState { service, .. }): State<RouterState>,
+    Path(project_name): Path<ProjectName>,
+) -> Result<AxumJson<Vec<project::FeatureFlag>>, Error> {
+    service.find_project(&project_name).await?;
+
+    Ok(AxumJson(
+        service
+            .feature_flags()
+            .flags(&project_name)
+            .into_iter()
+            .collect(),
+    ))
+}
+
+#[instrument(skip(service))]
+async fn put_project_feature_flag(
+    _: Admin,
+    State(RouterState { service, .. }): State<RouterState>,
+    Path((project_name, flag)): Path<(ProjectName, project::FeatureFlag)>,
+) -> Result<AxumJson<Vec<project::FeatureFlag>>, Error> {
+    service.find_project(&project_name).await?;
+
+    service
+        .set_project_feature_flag(&project_name, flag, true)
+        .await?;
+
+    Ok(AxumJson(
+        service
+            .feature_flags()
+            .flags(&project_name)
+            .into_iter()
+            .collect(),
+    ))
+}
+
+#[instrument(skip(service))]
+async fn delete_project_feature_flag(
+    _: Admin,
+    State(RouterState { service, .. }): State<RouterState>,
+    Path((project_name, flag)): Path<(ProjectName, project::FeatureFlag)>,
+) -> Result<AxumJson<Vec<project::FeatureFlag>>, Error> {
+    service
+        .set_project_feature_flag(&project_name, flag, false)
+        .await?;
+
+    Ok(AxumJson(
+        service
+            .feature_flags()
+            .flags(&project_name)
+            .into_iter()
+            .collect(),
+    ))
+}
+
+#[instrument(skip(service))]
+async fn get_project_resource_limits(
+    _: Admin,
+    State(RouterState { service, .. }): State<RouterState>,
+    Path(project_name): Path<ProjectName>,
+) -> Result<AxumJson<Option<project::ResourceLimits>>, Error> {
+    service.find_project(&project_name).await?;
+
+    Ok(AxumJson(
+        service.project_resource_limits(&project_name).await?,
+    ))
+}
+
+#[instrument(skip(service))]
+async fn put_project_resource_limits(
+    _: Admin,
+    State(RouterState { service, .. }): State<RouterState>,
+    Path(project_name): Path<ProjectName>,
+    AxumJson(limits): AxumJson<project::ResourceLimits>,
+) -> Result<AxumJson<Option<project::ResourceLimits>>, Error> {
+    service.find_project(&project_name).await?;
+
+    service
+        .set_project_resource_limits(&project_name, limits.clone())
+        .await?;
+
+    Ok(AxumJson(Some(limits)))
+}
+
+#[instrument(skip(service))]
+async fn delete_project_resource_limits(
+    _: Admin,
+    State(RouterState { service, .. }): State<RouterState>,
+    Path(project_name): Path<ProjectName>,
+) -> Result<AxumJson<Option<project::ResourceLimits>>, Error> {
+    service
+        .remove_project_resource_limits(&project_name)
+        .await?;
+
+    Ok(AxumJson(None))
+}
+
+async fn get_rollout(
+    _: Admin,
+    State(RouterState { service, .. }): State<RouterState>,
+) -> Result<AxumJson<Option<project::RolloutStatus>>, Error> {
+    Ok(AxumJson(service.rollouts().status().await))
+}
+
+#[instrument(skip_all)]
+async fn post_rollout(
+    _: Admin,
+    State(RouterState {
+        service, sender, ..
+    }): State<RouterState>,
+    AxumJson(project::RolloutRequest { image, concurrency }): AxumJson<project::RolloutRequest>,
+) -> Result<AxumJson<project::RolloutStatus>, Error> {
+    let image = image.unwrap_or_else(|| service.context().container_settings().image.clone());
+
+    let status = service
+        .rollouts()
+        .start(service.clone(), sender, image, concurrency.unwrap_or(1))
+        .await?;
+
+    Ok(AxumJson(status))
+}
+
+#[instrument(skip_all)]
+async fn revive_projects(
+    _: Admin,
+    State(RouterState {
+        service, sender, ..
+    }): State<RouterState>,
+) -> Result<(), Error> {
+    crate::project::exec::revive(service, sender)
+        .await
+        .map_err(|_| Error::from_kind(ErrorKind::Internal))
+}
+
+#[instrument(skip_all, fields(%email, ?acme_server))]
+async fn create_acme_account(
+    _: Admin,
+    Extension(acme_client): Extension<AcmeClient>,
+    Path(email): Path<String>,
+    AxumJson(acme_server): AxumJson<Option<String>>,
+) -> Result<AxumJson<serde_json::Value>, Error> {
+    let res = acme_client.create_account(&email, acme_server).await?;
+
+    Ok(AxumJson(res))
+}
+
+#[instrument(skip_all, fields(%project_name, %fqdn))]
+async fn request_acme_certificate(
+    _: Admin,
+    State(RouterState {
+        service, sender, ..
+    }): State<RouterState>,
+    Extension(acme_client): Extension<AcmeClient>,
+    Extension(resolver): Extension<Arc<GatewayCertResolver>>,
+    Path((project_name, fqdn)): Path<(ProjectName, String)>,
+    AxumJson(credentials): AxumJson<AccountCredentials<'_>>,
+) -> Result<String, Error> {
+    let fqdn = parse_custom_domain(&fqdn)?;
+
+    let (certs, private_key) = match service.project_details_for_custom_domain(&fqdn).await {
+        Ok(CustomDomain {
+            certificate,
+            private_key,
+            ..
+        }) => (certificate, private_key),
+        Err(err) if err.kind() == ErrorKind::CustomDomainNotFound => {
+            let res = acme_client
+                .create_certificate(&fqdn.to_string(), ChallengeType::Http01, credentials)
+                .await;
+            service
+                .record_certificate_attempt(
+                    &fqdn.to_string(),
+                    res.as_ref().err().map(ToString::to_string),
+                )
+                .await?;
+            let (certs, private_key) = res?;
+            service
+                .create_custom_domain(project_name.clone(), &fqdn, &certs, &private_key)
+                .await?;
+            (certs, private_key)
+        }
+        Err(err) => return Err(err),
+    };
+
+    // destroy and recreate the project with the new domain
+    service
+        .new_task()
+        .project(project_name)
+        .and_then(task::destroy())
+        .and_then(task::run_until_done())
+        .and_then(task::run({
+            let fqdn = fqdn.to_string();
+            move |ctx| {
+                let fqdn = fqdn.clone();
+                async move {
+                    let creating = ProjectCreating::new_with_random_initial_key(ctx.project_name)
+                        .with_fqdn(fqdn);
+                    TaskResult::Done(Project::Creating(creating))
+                }
+            }
+        }))
+        .send(&sender)
+        .await?;
+
+    let mut buf = Vec::new();
+    buf.extend(certs.as_bytes());
+    buf.extend(private_key.as_bytes());
+    resolver
+        .serve_pem(&fqdn.to_string(), Cursor::new(buf))
+        .await?;
+
+    Ok("certificate created".to_string())
+}
+
+#[derive(Deserialize)]
+struct IssueCertificateParams {
+    fqdn: String,
+    #[serde(default)]
+    force: bool,
+}
+
+#[instrument(skip_all, fields(fqdn = %params.fqdn, force = params.force))]
+async fn post_issue_certificate(
+    _: Admin,
+    State(RouterState { service, .. }): State<RouterState>,
+    Extension(acme_client): Extension<AcmeClient>,
+    Extension(resolver): Extension<Arc<GatewayCertResolver>>,
+    Query(params): Query<IssueCertificateParams>,
+    AxumJson(credentials): AxumJson<AccountCredentials<'_>>,
+) -> Result<AxumJson<stats::CertificateResponse>, Error> {
+    // Wildcards are fine here, for the certificate of the proxy
+    let (identifier, custom_domain) = match params.fqdn.trim().strip_prefix("*.") {
+        Some(parent) => (format!("*.{}", parse_custom_domain(parent)?), None),
+        None => {
+            let fqdn = parse_custom_domain(&params.fqdn)?;
+            (fqdn.to_string(), Some(fqdn))
+        }
+    };
+
+    let res = acme_client
+        .issue_certificate(&resolver, &identifier, params.force, credentials)
+        .await;
+    // Nothing was attempted when the certificate is still good
+    if !matches!(&res, Err(err) if err.kind() == ErrorKind::CertificateStillValid) {
+        service
+            .record_certificate_attempt(&identifier, res.as_ref().err().map(ToString::to_string))
+            .await?;
+    }
+    let issued = res?;
+
+    // Custom domains are served from the database when the gateway
+    // starts, which has to have the new certificate too
+    if let Some(fqdn) = custom_domain {
+        service
+            .update_custom_domain_certificate(&fqdn, &issued.chain, &issued.private_key)
+            .await?;
+    }
+
+    Ok(AxumJson(stats::CertificateResponse {
+        fqdn: identifier,
+        replaced: issued.replaced,
+        not_after: issued.not_after,
+    }))
+}
+
+/// What is known about the certificate `fqdn` is served with, from the
+/// PEM encoded `certificate` if it can be read
+async fn certificate_details(
+    service: &GatewayService,
+    fqdn: String,
+    certificate: Option<ChainAndPrivateKey>,
+    acme_managed: bool,
+) -> Result<project::CertificateDetails, Error> {
+    let (last_renewal_attempt, last_renewal_error) = service
+        .last_certificate_attempt(&fqdn)
+        .await?
+        .map_or((None, None), |(at, error)| (Some(at), error));
+
+    Ok(project::CertificateDetails {
+        fqdn,
+        issuer: certificate.as_ref().and_then(ChainAndPrivateKey::issuer),
+        not_after: certificate.and_then(|certificate| certificate.not_after().ok()),
+        acme_managed,
+        last_renewal_attempt,
+        last_renewal_error,
+    })
+}
+
+/// Read a certificate as kept in the database, which may not be one
+fn parse_custom_domain_certificate(
+    CustomDomain {
+        certificate,
+        private_key,
+        ..
+    }: CustomDomain,
+) -> Option<ChainAndPrivateKey> {
+    let mut buf = Vec::new();
+    buf.extend(certificate.as_bytes());
+    buf.extend(private_key.as_bytes());
+    ChainAndPrivateKey::parse_pem(Cursor::new(buf)).ok()
+}
+
+/// The certificates of the custom domains of a project. Its subdomain
+/// is served with the wildcard certificate of the gateway, which is
+/// not the business of its owner.
+#[instrument(skip_all, fields(%project_name))]
+async fn get_project_certificates(
+    State(RouterState { service, .. }): State<RouterState>,
+    ScopedUser {
+        user,
+        scope: project_name,
+    }: ScopedUser,
+) -> Result<AxumJson<Vec<project::CertificateDetails>>, Error> {
+    user.ensure_action_allowed(Action::Read)?;
+
+    let mut certificates = Vec::new();
+    for fqdn in service.iter_project_custom_domains(&project_name).await? {
+        let custom_domain = service
+            .project_details_for_custom_domain(&parse_custom_domain(&fqdn)?)
+            .await?;
+        // Custom domains only ever get their certificate over ACME
+        certificates.push(
+            certificate_details(
+                &service,
+                fqdn,
+                parse_custom_domain_certificate(custom_domain),
+                true,
+            )
+            .await?,
+        );
+    }
+
+    Ok(AxumJson(certificates))
+}
+
+#[derive(Deserialize)]
+struct CertificateParams {
+    fqdn: String,
+}
+
+/// The certificate of any domain the gateway serves, custom or one of
+/// its own
+#[instrument(skip_all, fields(fqdn = %params.fqdn))]
+async fn get_certificate(
+    _: Admin,
+    State(RouterState { service, .. }): State<RouterState>,
+    resolver: Option<Extension<Arc<GatewayCertResolver>>>,
+    Query(params): Query<CertificateParams>,
+) -> Result<AxumJson<project::CertificateDetails>, Error> {
+    let identifier = match params.fqdn.trim().strip_prefix("*.") {
+        Some(parent) => format!("*.{}", parse_custom_domain(parent)?),
+        None => {
+            let fqdn = parse_custom_domain(&params.fqdn)?;
+            match service.project_details_for_custom_domain(&fqdn).await {
+                Ok(custom_domain) => {
+                    let details = certificate_details(
+                        &service,
+                        fqdn.to_string(),
+                        parse_custom_domain_certificate(custom_domain),
+                        true,
+                    )
+                    .await?;
+                    return Ok(AxumJson(details));
+                }
+                Err(err) if err.kind() == ErrorKind::CustomDomainNotFound => fqdn.to_string(),
+                Err(err) => return Err(err),
+            }
+        }
+    };
+
+    let certificate = match resolver {
+        Some(Extension(resolver)) => resolver.store().get(&identifier).await?,
+        None => None,
+    }
+    .ok_or_else(|| Error::from_kind(ErrorKind::CertificateNotFound))?;
+
+    // Other certificates are only the business of the gateway if it
+    // ever tried to get them issued
+    let acme_managed = service
+        .last_certificate_attempt(&identifier)
+        .await?
+        .is_some();
+    let details =
+        certificate_details(&service, identifier, Some(certificate), acme_managed).await?;
+
+    Ok(AxumJson(details))
+}
+
+#[instrument(skip_all, fields(%account_name))]
+async fn post_impersonate(
+    Admin { user }: Admin,
+    State(RouterState { service, .. }): State<RouterState>,
+    Path(account_name): Path<AccountName>,
+    AxumJson(request): AxumJson<user::ImpersonateRequest>,
+) -> Result<AxumJson<user::ImpersonateResponse>, Error> {
+    let (key, expires_at) = service
+        .create_impersonation(&user.name, &account_name, request.allow_writes)
+        .await?;
+
+    Ok(AxumJson(user::ImpersonateResponse {
+        account_name: account_name.to_string(),
+        key: key.to_string(),
+        read_only: !request.allow_writes,
+        expires_at,
+    }))
+}
+
+#[derive(Deserialize)]
+struct AuditLogParams {
+    limit: Option<u32>,
+}
+
+#[derive(Deserialize)]
+struct ProjectEventsParams {
+    /// Only list the events older than this one
+    before: Option<i64>,
+    limit: Option<u32>,
+}
+
+#[instrument(skip_all, fields(%project_name))]
+async fn get_project_events(
+    State(RouterState { service, .. }): State<RouterState>,
+    ScopedUser {
+        user,
+        scope: project_name,
+    }: ScopedUser,
+    Query(ProjectEventsParams { before, limit }): Query<ProjectEventsParams>,
+) -> Result<AxumJson<Vec<project::Event>>, Error> {
+    user.ensure_action_allowed(Action::Read)?;
+
+    let events = service
+        .list_project_events(
+            &project_name,
+            before,
+            limit
+                .unwrap_or(PROJECT_EVENTS_PAGE_SIZE)
+                .min(PROJECT_EVENTS_MAX_PAGE_SIZE),
+        )
+        .await?;
+
+    Ok(AxumJson(events))
+}
+
+async fn get_audit_log(
+    _: Admin,
+    State(RouterState { service, .. }): State<RouterState>,
+    Query(AuditLogParams { limit }): Query<AuditLogParams>,
+) -> Result<AxumJson<Vec<user::AuditEntry>>, Error> {
+    let entries = service
+        .list_audit_log(
+            limit
+                .unwrap_or(AUDIT_LOG_PAGE_SIZE)
+                .min(AUDIT_LOG_MAX_PAGE_SIZE),
+        )
+        .await?;
+
+    Ok(AxumJson(entries))
+}
+
+#[instrument(skip_all)]
+async fn get_tasks(
+    _: Admin,
+    State(RouterState {
+        service, sender, ..
+    }): State<RouterState>,
+) -> Result<AxumJson<stats::TasksResponse>, Error> {
+    let queue_depth = WORKER_QUEUE_SIZE.saturating_sub(sender.capacity());
+
+    let tasks: Vec<_> = service
+        .task_tracker()
+        .list()
+        .await
+        .into_iter()
+        .map(|(id, record)| stats::TaskSummary {
+            id,
+            project_name: record.project_name.to_string(),
+            state: record.state.to_string(),
+            started_at: record.started_at,
+        })
+        .collect();
+
+    // tasks are listed oldest first
+    let oldest_task_age_secs = tasks
+        .first()
+        .map(|task| (chrono::Utc::now() - task.started_at).num_seconds());
+
+    Ok(AxumJson(stats::TasksResponse {
+        queue_depth,
+        oldest_task_age_secs,
+        tasks,
+    }))
+}
+
+async fn get_cache_stats(
+    _: Admin,
+    State(RouterState { service, .. }): State<RouterState>,
+) -> Result<AxumJson<stats::CacheResponse>, Error> {
+    let cache = service.project_cache();
+
+    Ok(AxumJson(stats::CacheResponse {
+        hits: cache.hits(),
+        misses: cache.misses(),
+        hit_ratio: cache.hit_ratio(),
+    }))
+}
+
+async fn get_log_level(
+    _: Admin,
+    Extension(log_level): Extension<Arc<LogLevel>>,
+) -> AxumJson<stats::LogLevel> {
+    AxumJson(stats::LogLevel {
+        filter: log_level.current(),
+    })
+}
+
+async fn put_log_level(
+    _: Admin,
+    Extension(log_level): Extension<Arc<LogLevel>>,
+    AxumJson(stats::LogLevel { filter }): AxumJson<stats::LogLevel>,
+) -> Result<AxumJson<stats::LogLevel>, Error> {
+    log_level.set(&filter)?;
+
+    Ok(AxumJson(stats::LogLevel { filter }))
+}
+
+async fn get_docker_breaker(_: Admin) -> AxumJson<stats::DockerBreakerResponse> {
+    let snapshot = crate::breaker::DOCKER_BREAKER.snapshot();
+
+    AxumJson(stats::DockerBreakerResponse {
+        state: snapshot.state.as_str().to_string(),
+        consecutive_failures: snapshot.consecutive_failures,
+        retry_in_secs: snapshot.retry_in.map(|retry_in| retry_in.as_secs()),
+    })
+}
+
+async fn get_traffic_stats(
+    _: Admin,
+    State(RouterState { service, .. }): State<RouterState>,
+) -> Result<AxumJson<BTreeMap<String, stats::TrafficResponse>>, Error> {
+    let traffic = service
+        .traffic()
+        .snapshot()
+        .into_iter()
+        .map(|(name, (bytes_in, bytes_out))| {
+            (
+                name,
+                stats::TrafficResponse {
+                    bytes_in,
+                    bytes_out,
+                },
+            )
+        })
+        .collect();
+
+    Ok(AxumJson(traffic))
+}
+
+/// Restoring is left to the `import` command, as it can only be done
+/// into an instance which is not serving yet
+async fn get_backup(
+    Admin { user: admin }: Admin,
+    State(RouterState { service, .. }): State<RouterState>,
+) -> Result<AxumJson<Backup>, Error> {
+    // A backup holds every API key and private key, which no auditor is to see
+    if !admin.is_super_user() {
+        return Err(Error::from_kind(ErrorKind::Forbidden));
+    }
+
+    Ok(AxumJson(service.backup().await?))
+}
+
+async fn get_platform_stats(
+    _: Admin,
+    State(RouterState { service, .. }): State<RouterState>,
+) -> Result<AxumJson<stats::PlatformResponse>, Error> {
+    let counters = service.counters();
+
+    Ok(AxumJson(stats::PlatformResponse {
+        accounts: service.count_accounts().await?,
+        projects: service.count_projects_by_state().await?,
+        deployments_today: counters.deployments_today(),
+        proxy_requests: counters.proxy_requests(),
+    }))
+}
+
+async fn get_capacity(
+    _: Admin,
+    State(RouterState { service, .. }): State<RouterState>,
+) -> Result<AxumJson<stats::CapacityResponse>, Error> {
+    let ctx = service.context();
+    let running_projects = ctx
+        .running_projects()
+        .await
+        .map_err(|err| Error::source(ErrorKind::Internal, err))?;
+    let max_running_projects = ctx.container_settings().max_running_projects;
+
+    Ok(AxumJson(stats::CapacityResponse {
+        running_projects,
+        max_running_projects,
+        has_capacity: max_running_projects.map_or(true, |max| running_projects < max),
+    }))
+}
+
+async fn get_projects(
+    _: Admin,
+    State(RouterState { service, .. }): State<RouterState>,
+) -> Result<AxumJson<Vec<project::AdminResponse>>, Error> {
+    let projects = service
+        .iter_projects_detailed()
+        .await?
+        .into_iter()
+        .map(Into::into)
+        .collect();
+
+    Ok(AxumJson(projects))
+}
+
+#[instrument(skip_all)]
+async fn get_routes(
+    _: Admin,
+    State(RouterState { service, .. }): State<RouterState>,
+) -> Result<AxumJson<Vec<project::Route>>, Error> {
+    Ok(AxumJson(service.routing_table().await?))
+}
+
+#[derive(Clone)]
+pub(crate) struct RouterState {
+    pub service: Arc<GatewayService>,
+    pub sender: Sender<BoxedTask>,
+    pub running_builds: Arc<Mutex<TtlCache<Uuid, ()>>>,
+    pub idempotency_keys: Arc<Mutex<IdempotencyKeys>>,
+}
+
+pub struct ApiBuilder {
+    router: Router<RouterState>,
+    service: Option<Arc<GatewayService>>,
+    sender: Option<Sender<BoxedTask>>,
+    resolver: Option<Arc<GatewayCertResolver>>,
+    bind: Option<SocketAddr>,
+}
+
+impl Default for ApiBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ApiBuilder {
+    pub fn new() -> Self {
+        Self {
+            router: Router::new(),
+            service: None,
+            sender: None,
+            resolver: None,
+            bind: None,
+        }
+    }
+
+    pub fn with_acme(mut self, acme: AcmeClient, resolver: Arc<GatewayCertResolver>) -> Self {
+        self.router = self
+            .router
+            .route("/admin/acme/:email", post(create_acme_account))
+            .route(
+                "/admin/acme/request/:project_name/:fqdn",
+                post(request_acme_certificate),
+            )
+            .route("/admin/certs/issue", post(post_issue_certificate))
+            .layer(Extension(acme));
+        self.resolver = Some(resolver);
+        self
+    }
+
+    pub fn with_log_level(mut self, log_level: Arc<LogLevel>) -> Self {
+        self.router = self
+            .router
+            .route("/admin/log-level", get(get_log_level).put(put_log_level))
+            .layer(Extension(log_level));
+        self
+    }
+
+    pub fn with_service(mut self, service: Arc<GatewayService>) -> Self {
+        self.service = Some(service);
+        self
+    }
+
+    pub fn with_sender(mut self, sender: Sender<BoxedTask>) -> Self {
+        self.sender = Some(sender);
+        self
+    }
+
+    pub fn binding_to(mut self, addr: SocketAddr) -> Self {
+        self.bind = Some(addr);
+        self
+    }
+
+    pub fn with_default_traces(mut self) -> Self {
+        self.router = self.router.route_layer(from_extractor::<Metrics>()).layer(
+            TraceLayer::new_for_http()
+                .make_span_with(|request: &Request<Body>| {
+                    let path = if let Some(path) = request.extensions().get::<MatchedPath>() {
+                        path.as_str()
+                    } else {
+                        ""
+                    };
+
+                    debug_span!(
+                        "request",
+                        http.uri = %request.uri(),
+                        http.method = %request.method(),
+                        http.status_code = field::Empty,
+                        account.name = field::Empty,
+                        // A bunch of extra things for metrics
+                        // Should be able to make this clearer once `Valuable` support lands in tracing
+                        request.path = path,
+                        request.params.project_name = field::Empty,
+                        request.params.account_name = field::Empty,
+                        db.pool.wait_ms = field::Empty,
+                    )
+                })
+                .on_response(
+                    |response: &Response<BoxBody>, latency: Duration, span: &Span| {
+                        span.record("http.status_code", response.status().as_u16());
+                        debug!(
+                            latency = format_args!("{} ns", latency.as_nanos()),
+                            "finished processing request"
+                        );
+                    },
+                ),
+        );
+        self
+    }
+
+    pub fn with_default_routes(mut self) -> Self {
+        self.router = self
+            .router
+            .route("/", get(get_status))
+            .route("/version", get(get_version))
+            .route("/projects", get(get_projects_list))
+            .route("/projects/check", post(post_check_project_names))
+            .route(
+                "/projects/:project_name",
+                get(get_project).delete(delete_project).post(post_project),
+            )
+            .route("/users/:account_name", get(get_user).post(post_user))
+            .route("/users/:account_name/keys", get(get_keys))
+            .route(
+                "/users/:account_name/resource-limits",
+                get(get_account_resource_limits)
+                    .put(put_account_resource_limits)
+                    .delete(delete_account_resource_limits),
+            )
+            .route("/users/:account_name/keys/:key_id", delete(delete_key))
+            .route("/keys", post(post_scoped_key))
+            .route("/account/projects/export", get(get_projects_export))
+            .route(
+                "/projects/:project_name/container-logs",
+                get(get_container_logs),
+            )
+            .route("/projects/:project_name/events", get(get_project_events))
+            .route(
+                "/projects/:project_name/cert",
+                get(get_project_certificates),
+            )
+            .route(
+                "/projects/:project_name/deployments/queue",
+                get(get_deployment_queue),
+            )
+            .route("/projects/:project_name/rename", post(post_rename_project))
+            .route(
+                "/projects/:project_name/deploy/git",
+                post(post_deploy_from_git),
+            )
+            .route(
+                "/projects/:project_name/env",
+                get(get_project_env).put(put_project_env),
+            )
+            .route(
+                "/projects/:project_name/env/:name",
+                delete(delete_project_env),
+            )
+            .route("/projects/:project_name/aliases", get(get_project_aliases))
+            .route(
+                "/projects/:project_name/aliases/:alias",
+                put(put_project_alias).delete(delete_project_alias),
+            )
+            .route(
+                "/projects/:project_name/webhooks",
+                get(get_project_webhook)
+                    .put(put_project_webhook)
+                    .delete(delete_project_webhook),
+            )
+            .route(
+                "/projects/:project_name/ratelimit",
+                get(get_project_rate_limit)
+                    .put(put_project_rate_limit)
+                    .delete(delete_project_rate_limit),
+            )
+            .route(
+                "/projects/:project_name/canary",
+                get(get_project_canary)
+                    .put(put_project_canary)
+                    .delete(delete_project_canary),
+            )
+            .route(
+                "/projects/:project_name/canary/promote",
+                post(post_promote_project_canary),
+            )
+            .route(
+                "/projects/:project_name/ipfilter",
+                get(get_project_ip_filter)
+                    .put(put_project_ip_filter)
+                    .delete(delete_project_ip_filter),
+            )
+            .route(
+                "/projects/:project_name/git-token",
+                put(put_project_git_token).delete(delete_project_git_token),
+            )
+            .route(
+                "/projects/:project_name/tags",
+                get(get_project_tags).put(put_project_tags),
+            )
+            .route(
+                "/projects/:project_name/tags/:tag",
+                delete(delete_project_tag),
+            )
+            .route(
+                "/projects/:project_name/cache",
+                get(get_project_response_cache)
+                    .put(put_project_response_cache)
+                    .delete(delete_project_response_cache),
+            )
+            .route(
+                "/projects/:project_name/connlimit",
+                get(get_project_connection_limit)
+                    .put(put_project_connection_limit)
+                    .delete(delete_project_connection_limit),
+            )
+            .route(
+                "/projects/:project_name/maintenance-page",
+                get(get_project_maintenance_page)
+                    .put(put_project_maintenance_page)
+                    .delete(delete_project_maintenance_page),
+            )
+            .route(
+                "/projects/:project_name/upstream",
+                get(get_project_upstream).put(put_project_upstream),
+            )
+            .route(
+                "/projects/:project_name/basic-auth",
+                get(get_project_basic_auth)
+                    .put(put_project_basic_auth)
+                    .delete(delete_project_basic_auth),
+            )
+            .route(
+                "/projects/:project_name/headers",
+                get(get_project_header_rules)
+                    .put(put_project_header_rules)
+                    .delete(delete_project_header_rules),
+            )
+            .route("/projects/:project_name/*any", any(route_project))
+            .route("/stats/load", post(post_load).delete(delete_load))
+            .route("/admin/projects", get(get_projects))
+            .route("/admin/routes", get(get_routes))
+            .route("/admin/revive", post(revive_projects))
+            .route(
+                "/admin/projects/:project_name/force-destroy",
+                post(post_force_destroy_project),
+            )
+            .route(
+                "/admin/projects/:project_name/freeze",
+                post(post_freeze_project),
+            )
+            .route(
+                "/admin/projects/:project_name/unfreeze",
+                post(post_unfreeze_project),
+            )
+            .route(
+                "/admin/projects/:project_name/deployment-timeout",
+                get(get_deployment_timeout)
+                    .put(put_deployment_timeout)
+                    .delete(delete_deployment_timeout),
+            )
+            .route(
+                "/admin/projects/:project_name/resource-limits",
+                get(get_project_resource_limits)
+                    .put(put_project_resource_limits)
+                    .delete(delete_project_resource_limits),
+            )
+            .route(
+                "/admin/projects/:project_name/flags",
+                get(get_project_feature_flags),
+            )
+            .route(
+                "/admin/projects/:project_name/flags/:flag",
+                put(put_project_feature_flag).delete(delete_project_feature_flag),
+            )
+            .route("/admin/rollout", get(get_rollout).post(post_rollout))
+            .route(
+                "/admin/maintenance",
+                get(get_maintenance)
+                    .put(put_maintenance)
+                    .delete(delete_maintenance),
+            )
+            .route(
+                "/admin/drain",
+                get(get_drain).post(post_drain).delete(delete_drain),
+            )
+            .route(
+                "/admin/projects/:project_name/diagnostics",
+                get(get_project_diagnostics),
+            )
+            .route("/admin/tasks", get(get_tasks))
+            .route("/admin/certs", get(get_certificate))
+            .route("/admin/impersonate/:account_name", post(post_impersonate))
+            .route("/admin/audit", get(get_audit_log))
+            .route("/admin/backup", get(get_backup))
+            .route("/admin/stats", get(get_platform_stats))
+            .route("/admin/stats/cache", get(get_cache_stats))
+            .route("/admin/stats/traffic", get(get_traffic_stats))
+            .route("/admin/stats/docker", get(get_docker_breaker))
+            .route("/admin/capacity", get(get_capacity))
+            .route(
+                "/admin/stats/load",
+                get(get_load_admin).delete(delete_load_admin),
+            );
+        self
+    }
+
+    pub fn into_router(self) -> Router {
+        let service = self.service.expect("a GatewayService is required");
+        let sender = self.sender.expect("a task Sender is required");
+
+        // Allow about 4 cores per build
+        let mut concurrent_builds = num_cpus::get() / 4;
+        if concurrent_builds < 1 {
+            concurrent_builds = 1;
+        }
+
+        let running_builds = Arc::new(Mutex::new(TtlCache::new(concurrent_builds)));
+
+        let idempotency_keys = Arc::new(Mutex::new(TtlCache::new(IDEMPOTENCY_KEY_CAPACITY)));
+
+        // Layered last as routes added after `with_acme` read it too
+        let mut router = self.router;
+        if let Some(resolver) = self.resolver {
+            router = router.layer(Extension(resolver));
+        }
+
+        router.with_state(RouterState {
+            service,
+            sender,
+            running_builds,
+            idempotency_keys,
+        })
+    }
+
+    pub fn serve(self) -> impl Future<Output = Result<(), hyper::Error>> {
+        let bind = self.bind.expect("a socket address to bind to is required");
+        let router = self.into_router();
+        axum::Server::bind(&bind).serve(router.into_make_service())
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    use axum::body::Body;
+    use axum::headers::authorization::Bearer;
+    use axum::headers::Authorization;
+    use axum::http::Request;
+    use fqdn::FQDN;
+    use futures::{future, FutureExt, TryFutureExt};
+    use hyper::header::RETRY_AFTER;
+    use hyper::StatusCode;
+    use shuttle_common::models::error::ApiError;
+    use tokio::sync::mpsc::channel;
+    use tokio::sync::oneshot;
+    use tower::Service;
+
+    use super::*;
+    use crate::args::DeployConcurrency;
+    use crate::service::GatewayService;
+    use crate::tests::{RequestBuilderExt, World};
+    use crate::State as _;
+
+    /// A router serving the default routes of a fresh service, whose
+    /// tasks are dropped rather than run
+    async fn test_router(world: &World) -> (Arc<GatewayService>, Router) {
+        let service = Arc::new(GatewayService::init(world.args(), world.pool()).await);
+        let router = router_for(Arc::clone(&service));
+        (service, router)
+    }
+
+    /// A router serving the default routes of `service`
+    fn router_for(service: Arc<GatewayService>) -> Router {
+        builder_for(service).with_default_routes().into_router()
+    }
+
+    /// A builder for routes over `service`, whose tasks are dropped
+    /// rather than run
+    fn builder_for(service: Arc<GatewayService>) -> ApiBuilder {
+        let (sender, mut receiver) = channel::<BoxedTask>(256);
+        tokio::spawn(async move {
+            while receiver.recv().await.is_some() {
+                // do not do any work with inbound requests
+            }
+        });
+
+        ApiBuilder::new().with_service(service).with_sender(sender)
+    }
+
+    #[tokio::test]
+    async fn api_create_get_delete_projects() -> anyhow::Result<()> {
+        let world = World::new().await;
+        let (service, mut router) = test_router(&world).await;
+
+        let neo = service.create_user("neo".parse().unwrap()).await?;
+
+        let create_project = |project: &str| {
+            Request::builder()
+                .method("POST")
+                .uri(format!("/projects/{project}"))
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        let delete_project = |project: &str| {
+            Request::builder()
+                .method("DELETE")
+                .uri(format!("/projects/{project}"))
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        router
+            .call(create_project("matrix"))
+            .map_ok(|resp| assert_eq!(resp.status(), StatusCode::UNAUTHORIZED))
+            .await
+            .unwrap();
+
+        let authorization = Authorization::bearer(neo.key.as_str()).unwrap();
+
+        router
+            .call(create_project("matrix").with_header(&authorization))
+            .map_ok(|resp| {
+                assert_eq!(resp.status(), StatusCode::OK);
+            })
+            .await
+            .unwrap();
+
+        router
+            .call(create_project("matrix").with_header(&authorization))
+            .map_ok(|resp| {
+                assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+            })
+            .await
+            .unwrap();
+
+        let get_project = |project| {
+            Request::builder()
+                .method("GET")
+                .uri(format!("/projects/{project}"))
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        router
+            .call(get_project("matrix"))
+            .map_ok(|resp| {
+                assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+            })
+            .await
+            .unwrap();
+
+        router
+            .call(get_project("matrix").with_header(&authorization))
+            .map_ok(|resp| {
+                assert_eq!(resp.status(), StatusCode::OK);
+            })
+            .await
+            .unwrap();
+
+        router
+            .call(delete_project("matrix").with_header(&authorization))
+            .map_ok(|resp| {
+                assert_eq!(resp.status(), StatusCode::OK);
+            })
+            .await
+            .unwrap();
+
+        router
+            .call(create_project("reloaded").with_header(&authorization))
+            .map_ok(|resp| {
+                assert_eq!(resp.status(), StatusCode::OK);
+            })
+            .await
+            .unwrap();
+
+        let trinity = service.create_user("trinity".parse().unwrap()).await?;
+
+        let authorization = Authorization::bearer(trinity.key.as_str()).unwrap();
+
+        router
+            .call(get_project("reloaded").with_header(&authorization))
+            .map_ok(|resp| assert_eq!(resp.status(), StatusCode::NOT_FOUND))
+            .await
+            .unwrap();
+
+        router
+            .call(delete_project("reloaded").with_header(&authorization))
+            .map_ok(|resp| {
+                assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+            })
+            .await
+            .unwrap();
+
+        service
+            .set_super_user(&"trinity".parse().unwrap(), true)
+            .await?;
+
+        router
+            .call(get_project("reloaded").with_header(&authorization))
+            .map_ok(|resp| assert_eq!(resp.status(), StatusCode::OK))
+            .await
+            .unwrap();
+
+        router
+            .call(delete_project("reloaded").with_header(&authorization))
+            .map_ok(|resp| {
+                assert_eq!(resp.status(), StatusCode::OK);
+            })
+            .await
+            .unwrap();
+
+        // delete returns 404 for project that doesn't exist
+        router
+            .call(delete_project("resurrections").with_header(&authorization))
+            .map_ok(|resp| {
+                assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+            })
+            .await
+            .unwrap();
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn api_concurrent_delete_and_status() -> anyhow::Result<()> {
+        let world = World::new().await;
+        let service = Arc::new(GatewayService::init(world.args(), world.pool()).await);
+
+        let tasks = Arc::new(AtomicUsize::new(0));
+        let (sender, mut receiver) = channel::<BoxedTask>(256);
+        tokio::spawn({
+            let tasks = Arc::clone(&tasks);
+            async move {
+                while receiver.recv().await.is_some() {
+                    // count the tasks sent without doing any of them
+                    tasks.fetch_add(1, Ordering::SeqCst);
+                }
+            }
+        });
+
+        let router = ApiBuilder::new()
+            .with_service(Arc::clone(&service))
+            .with_sender(sender)
+            .with_default_routes()
+            .into_router();
+
+        let neo = service.create_user("neo".parse().unwrap()).await?;
+        let authorization = Authorization::bearer(neo.key.as_str()).unwrap();
+        let matrix: ProjectName = "matrix".parse().unwrap();
+        service.create_project(matrix.clone(), neo.name).await?;
+        let stopped: Project = serde_json::from_value(serde_json::json!({
+            "stopped": { "container": {} }
+        }))?;
+        service.update_project(&matrix, &stopped).await?;
+
+        let send = |method: &str| {
+            let mut router = router.clone();
+            let req = Request::builder()
+                .method(method)
+                .uri("/projects/matrix")
+                .body(Body::empty())
+                .unwrap()
+                .with_header(&authorization);
+            async move {
+                let resp = router.call(req).await.unwrap();
+                assert_eq!(resp.status(), StatusCode::OK);
+                let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+                let project: project::Response = serde_json::from_slice(&body).unwrap();
+                project.state
+            }
+        };
+        let race = || {
+            future::join_all((0..10).map(|i| {
+                let method = if i % 2 == 0 { "DELETE" } else { "GET" };
+                send(method).map(move |state| (method, state))
+            }))
+        };
+
+        // every delete sees the project on its way out, and a status
+        // read only ever sees it before or after that
+        for (method, state) in race().await {
+            match method {
+                "DELETE" => assert_eq!(state, project::State::Destroying),
+                _ => assert!(
+                    matches!(state, project::State::Stopped | project::State::Destroying),
+                    "{state:?}"
+                ),
+            }
+        }
+        assert_eq!(send("GET").await, project::State::Destroying);
+        assert_eq!(tasks.load(Ordering::SeqCst), 5);
+
+        // once it is gone, deletes do not bring it back nor send any
+        // more tasks
+        service
+            .update_project(
+                &matrix,
+                &Project::Destroyed(crate::project::ProjectDestroyed::new(None)),
+            )
+            .await?;
+        for (_, state) in race().await {
+            assert_eq!(state, project::State::Destroyed);
+        }
+        assert_eq!(tasks.load(Ordering::SeqCst), 5);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn api_create_project_conflict_suggestions() -> anyhow::Result<()> {
+        let world = World::new().await;
+        let (service, mut router) = test_router(&world).await;
+
+        let neo = service.create_user("neo".parse().unwrap()).await?;
+        let trinity = service.create_user("trinity".parse().unwrap()).await?;
+
+        // make the first numbered suggestion unavailable too
+        service
+            .create_project("matrix".parse().unwrap(), neo.name.clone())
+            .await?;
+        service
+            .create_project("matrix-2".parse().unwrap(), neo.name.clone())
+            .await?;
+
+        let resp = router
+            .call(
+                Request::builder()
+                    .method("POST")
+                    .uri("/projects/matrix")
+                    .body(Body::empty())
+                    .unwrap()
+                    .with_header(&Authorization::bearer(trinity.key.as_str()).unwrap()),
+            )
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+        let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+        let error: ApiError = serde_json::from_slice(&body).unwrap();
+
+        assert!(!error.suggestions.is_empty());
+        assert!(!error.suggestions.contains(&"matrix-2".to_string()));
+        for suggestion in error.suggestions {
+            let suggestion: ProjectName = suggestion.parse().unwrap();
+            assert!(suggestion.is_valid());
+            assert!(service.is_project_name_available(&suggestion).await?);
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn api_create_project_idempotency_key() -> anyhow::Result<()> {
+        let world = World::new().await;
+        let (service, mut router) = test_router(&world).await;
+
+        let neo = service.create_user("neo".parse().unwrap()).await?;
+
+        let create = |project: &str, key: &str| {
+            Request::builder()
+                .method("POST")
+                .uri(format!("/projects/{project}"))
+                .header(IDEMPOTENCY_KEY_HEADER, key)
+                .body(Body::empty())
+                .unwrap()
+                .with_header(&Authorization::bearer(neo.key.as_str()).unwrap())
+        };
+
+        let mut responses = Vec::new();
+        for _ in 0..2 {
+            let resp = router.call(create("matrix", "first-try")).await.unwrap();
+            assert_eq!(resp.status(), StatusCode::OK);
+            responses.push(hyper::body::to_bytes(resp.into_body()).await.unwrap());
+        }
+        assert_eq!(responses[0], responses[1]);
+
+        let projects = service
+            .iter_user_projects_detailed(neo.name.clone())
+            .await?
+            .collect::<Vec<_>>();
+        assert_eq!(projects.len(), 1);
+
+        // a key cannot be reused to create something else
+        let resp = router.call(create("reloaded", "first-try")).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+        // without a key, a duplicate is a conflict as always
+        let resp = router
+            .call(
+                Request::builder()
+                    .method("POST")
+                    .uri("/projects/matrix")
+                    .body(Body::empty())
+                    .unwrap()
+                    .with_header(&Authorization::bearer(neo.key.as_str()).unwrap()),
+            )
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn api_check_project_names() -> anyhow::Result<()> {
+        let world = World::new().await;
+        let (service, mut router) = test_router(&world).await;
+
+        let neo = service.create_user("neo".parse().unwrap()).await?;
+        let trinity = service.create_user("trinity".parse().unwrap()).await?;
+        let matrix: ProjectName = "matrix".parse().unwrap();
+        service
+            .create_project(matrix.clone(), trinity.name.clone())
+            .await?;
+        service
+            .add_project_alias(&matrix, &"reloaded".parse().unwrap())
+            .await?;
+
+        let check = |names: Vec<String>| {
+            Request::builder()
+                .method("POST")
+                .uri("/projects/check")
+                .header("Content-Type", "application/json")
+                .body(Body::from(
+                    serde_json::to_vec(&project::NameCheckRequest { names }).unwrap(),
+                ))
+                .unwrap()
+                .with_header(&Authorization::bearer(neo.key.as_str()).unwrap())
+        };
+
+        let names = [
+            "zion",
+            "matrix",
+            "reloaded",
+            "Nebuchadnezzar",
+            "-oracle",
+            "check",
+        ];
+        let resp = router
+            .call(check(names.iter().map(ToString::to_string).collect()))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+        let checks: Vec<project::NameCheck> = serde_json::from_slice(&body).unwrap();
+        assert_eq!(
+            checks
+                .iter()
+                .map(|check| (check.name.as_str(), check.valid, check.available))
+                .collect::<Vec<_>>(),
+            [
+                ("zion", true, true),
+                // taken by a project of someone else, or an alias
+                ("matrix", true, false),
+                ("reloaded", true, false),
+                ("Nebuchadnezzar", false, false),
+                ("-oracle", false, false),
+                ("check", false, false),
+            ]
+        );
+        assert!(checks[..3].iter().all(|check| check.reason.is_none()));
+        for (check, reason) in
+            checks[3..]
+                .iter()
+                .zip(["must not", "must not start with `-`", "reserved"])
+        {
+            let given = check.reason.as_deref().unwrap();
+            assert!(given.contains(reason), "{}: {given}", check.name);
+        }
+
+        let resp = router
+            .call(check(vec!["zion".to_string(); MAX_NAME_CHECKS + 1]))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn api_project_env_is_redacted() -> anyhow::Result<()> {
+        let world = World::new().await;
+        let (service, mut router) = test_router(&world).await;
+
+        let neo = service.create_user("neo".parse().unwrap()).await?;
+        let matrix: ProjectName = "matrix".parse().unwrap();
+        service
+            .create_project(matrix.clone(), neo.name.clone())
+            .await?;
+
+        let authorization = Authorization::bearer(neo.key.as_str()).unwrap();
+
+        let put_env = |body: &'static str| {
+            Request::builder()
+                .method("PUT")
+                .uri("/projects/matrix/env")
+                .header("Content-Type", "application/json")
+                .body(Body::from(body))
+                .unwrap()
+                .with_header(&authorization)
+        };
+
+        let resp = router
+            .call(put_env(r#"{"SECRET": "the one"}"#))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+        assert!(!String::from_utf8_lossy(&body).contains("the one"));
+        let env: project::EnvResponse = serde_json::from_slice(&body).unwrap();
+        assert!(env.restart_required);
+
+        let resp = router
+            .call(
+                Request::builder()
+                    .method("GET")
+                    .uri("/projects/matrix/env")
+                    .body(Body::empty())
+                    .unwrap()
+                    .with_header(&authorization),
+            )
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+        assert!(!String::from_utf8_lossy(&body).contains("the one"));
+        let env: project::EnvResponse = serde_json::from_slice(&body).unwrap();
+        assert_eq!(env.vars.get("SECRET").unwrap(), env::REDACTED);
+
+        // the plaintext is still handed to the state machine
+        assert_eq!(
+            service.project_env(&matrix).await?,
+            vec![("SECRET".to_string(), "the one".to_string())]
+        );
+
+        let resp = router
+            .call(put_env(r#"{"NOT=VALID": "value"}"#))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn api_create_get_users() -> anyhow::Result<()> {
+        let world = World::new().await;
+        let (service, mut router) = test_router(&world).await;
+
+        let get_neo = || {
+            Request::builder()
+                .method("GET")
+                .uri("/users/neo")
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        let post_trinity = || {
+            Request::builder()
+                .method("POST")
+                .uri("/users/trinity")
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        router
+            .call(get_neo())
+            .map_ok(|resp| {
+                assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+            })
+            .await
+            .unwrap();
+
+        let user = service.create_user("neo".parse().unwrap()).await?;
+
+        router
+            .call(get_neo())
+            .map_ok(|resp| {
+                assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+            })
+            .await
+            .unwrap();
+
+        let authorization = Authorization::bearer(user.key.as_str()).unwrap();
+
+        router
+            .call(get_neo().with_header(&authorization))
+            .map_ok(|resp| {
+                assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+            })
+            .await
+            .unwrap();
+
+        router
+            .call(post_trinity().with_header(&authorization))
+            .map_ok(|resp| assert_eq!(resp.status(), StatusCode::FORBIDDEN))
+            .await
+            .unwrap();
+
+        service.set_super_user(&user.name, true).await?;
+
+        router
+            .call(get_neo().with_header(&authorization))
+            .map_ok(|resp| {
+                assert_eq!(resp.status(), StatusCode::OK);
+            })
+            .await
+            .unwrap();
+
+        let resp = router
+            .call(post_trinity().with_header(&authorization))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(resp.into_body()).await?;
+        let trinity: user::Response = serde_json::from_slice(&body)?;
+
+        // creating the same user again is a conflict, which does not
+        // give away the key of the existing one
+        let resp = router
+            .call(post_trinity().with_header(&authorization))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::CONFLICT);
+        let body = hyper::body::to_bytes(resp.into_body()).await?;
+        assert!(!String::from_utf8_lossy(&body).contains(&trinity.key));
+        let error: ApiError = serde_json::from_slice(&body)?;
+        assert_eq!(error.code.as_deref(), Some("user_already_exists"));
+        assert_eq!(error.message, "user already exists");
+
+        assert_eq!(
+            service
+                .key_from_account_name(&"trinity".parse().unwrap())
+                .await?
+                .as_str(),
+            trinity.key
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn admin_tasks_queue_depth() -> anyhow::Result<()> {
+        let world = World::new().await;
+        let service = Arc::new(GatewayService::init(world.args(), world.pool()).await);
+
+        // never process anything so that tasks pile up in the queue
+        let (sender, _receiver) = channel::<BoxedTask>(WORKER_QUEUE_SIZE);
+
+        let mut router = ApiBuilder::new()
+            .with_service(Arc::clone(&service))
+            .with_sender(sender.clone())
+            .with_default_routes()
+            .into_router();
+
+        let admin = service.create_user("neo".parse().unwrap()).await?;
+        service.set_super_user(&admin.name, true).await?;
+        let authorization = Authorization::bearer(admin.key.as_str()).unwrap();
+
+        let get_tasks = || {
+            Request::builder()
+                .method("GET")
+                .uri("/admin/tasks")
+                .body(Body::empty())
+                .unwrap()
+                .with_header(&authorization)
+        };
+
+        let tasks_response = |resp: Response<BoxBody>| async move {
+            assert_eq!(resp.status(), StatusCode::OK);
+            let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+            serde_json::from_slice::<stats::TasksResponse>(&body).unwrap()
+        };
+
+        let tasks = tasks_response(router.call(get_tasks()).await.unwrap()).await;
+        assert_eq!(tasks.queue_depth, 0);
+        assert!(tasks.tasks.is_empty());
+        assert!(tasks.oldest_task_age_secs.is_none());
+
+        for project in ["matrix", "reloaded", "revolutions"] {
+            let project: ProjectName = project.parse().unwrap();
+            service
+                .create_project(project.clone(), admin.name.clone())
+                .await?;
+            service.new_task().project(project).send(&sender).await?;
+        }
+
+        let tasks = tasks_response(router.call(get_tasks()).await.unwrap()).await;
+        assert_eq!(tasks.queue_depth, 3);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn api_maintenance_mode() -> anyhow::Result<()> {
+        let world = World::new().await;
+        let (service, mut router) = test_router(&world).await;
+
+        let admin = service.create_user("neo".parse().unwrap()).await?;
+        service.set_super_user(&admin.name, true).await?;
+        let authorization = Authorization::bearer(admin.key.as_str()).unwrap();
+
+        service
+            .create_project("matrix".parse().unwrap(), admin.name.clone())
+            .await?;
+
+        let request = |method: &str, uri: &str| {
+            Request::builder()
+                .method(method)
+                .uri(uri)
+                .body(Body::empty())
+                .unwrap()
+                .with_header(&authorization)
+        };
+
+        let resp = router
+            .call(request("PUT", "/admin/maintenance"))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert!(service.is_in_maintenance());
+
+        let resp = router
+            .call(request("POST", "/projects/reloaded"))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+        let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+        let error: ApiError = serde_json::from_slice(&body).unwrap();
+        assert!(error.message.contains("maintenance"));
+
+        let resp = router
+            .call(request("POST", "/projects/matrix/services/matrix"))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+        // reads keep working
+        let resp = router.call(request("GET", "/")).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let resp = router
+            .call(request("GET", "/projects/matrix"))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let resp = router
+            .call(request("DELETE", "/admin/maintenance"))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let resp = router
+            .call(request("POST", "/projects/reloaded"))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn api_drain_node() -> anyhow::Result<()> {
+        let world = World::new().await;
+        let (service, mut router) = test_router(&world).await;
+
+        let admin = service.create_user("neo".parse().unwrap()).await?;
+        service.set_super_user(&admin.name, true).await?;
+        let authorization = Authorization::bearer(admin.key.as_str()).unwrap();
+
+        service
+            .create_project("matrix".parse().unwrap(), admin.name.clone())
+            .await?;
+
+        let request = |method: &str, uri: &str| {
+            Request::builder()
+                .method(method)
+                .uri(uri)
+                .body(Body::empty())
+                .unwrap()
+                .with_header(&authorization)
+        };
+
+        let resp = router
+            .call(request("POST", "/admin/drain?stop_running=true"))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+        let drain: stats::DrainResponse = serde_json::from_slice(&body).unwrap();
+        assert!(drain.draining);
+        assert!(service.is_draining());
+
+        let resp = router
+            .call(request("POST", "/projects/reloaded"))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+        let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+        let error: ApiError = serde_json::from_slice(&body).unwrap();
+        assert!(error.message.contains("out of rotation"));
+
+        // existing projects can still be looked at
+        let resp = router
+            .call(request("GET", "/projects/matrix"))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let resp = router.call(request("GET", "/admin/drain")).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+        let drain: stats::DrainResponse = serde_json::from_slice(&body).unwrap();
+        assert!(drain.draining);
+        assert!(drain.running_projects.is_empty());
+
+        let resp = router
+            .call(request("DELETE", "/admin/drain"))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let resp = router
+            .call(request("POST", "/projects/reloaded"))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn api_project_events() -> anyhow::Result<()> {
+        let world = World::new().await;
+        let (service, mut router) = test_router(&world).await;
+
+        let neo = service.create_user("neo".parse().unwrap()).await?;
+        let authorization = Authorization::bearer(neo.key.as_str()).unwrap();
+
+        let request = |method: &str, uri: &str| {
+            Request::builder()
+                .method(method)
+                .uri(uri)
+                .body(Body::empty())
+                .unwrap()
+                .with_header(&authorization)
+        };
+
+        let resp = router.call(request("POST", "/projects/matrix")).await?;
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let matrix: ProjectName = "matrix".parse().unwrap();
+        service
+            .record_project_event(&matrix, project::EventKind::Deployed, None)
+            .await;
+        service
+            .record_project_state(
+                &matrix,
+                &crate::project::Project::Errored(crate::project::ProjectError::internal(
+                    "out of memory",
+                )),
+            )
+            .await;
+        service
+            .create_custom_domain(
+                matrix.clone(),
+                &"neo.the.matrix".parse::<FQDN>().unwrap(),
+                "certificate",
+                "private key",
+            )
+            .await?;
+
+        let mut events = |uri: &str| {
+            let call = router.call(request("GET", uri));
+            async move {
+                let resp = call.await.unwrap();
+                assert_eq!(resp.status(), StatusCode::OK);
+                let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+                serde_json::from_slice::<Vec<project::Event>>(&body).unwrap()
+            }
+        };
+
+        let feed = events("/projects/matrix/events").await;
+        assert_eq!(
+            feed.iter().map(|event| event.kind).collect::<Vec<_>>(),
+            vec![
+                project::EventKind::DomainAdded,
+                project::EventKind::Crashed,
+                project::EventKind::Deployed,
+                project::EventKind::Created,
+            ]
+        );
+        assert_eq!(feed[0].detail.as_deref(), Some("neo.the.matrix"));
+        assert!(feed[1].detail.as_ref().unwrap().contains("out of memory"));
+
+        // older events are a page away
+        let page = events("/projects/matrix/events?limit=2").await;
+        assert_eq!(page, feed[..2]);
+        let page = events(&format!(
+            "/projects/matrix/events?limit=2&before={}",
+            page[1].id
+        ))
+        .await;
+        assert_eq!(page, feed[2..]);
+
+        // the gateway answers for the deploys it holds on to too, rather
+        // than the deployer of the project
+        let resp = router
+            .call(request("GET", "/projects/matrix/deployments/queue"))
+            .await?;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(resp.headers()[CONTENT_TYPE], "application/json");
+        let body = hyper::body::to_bytes(resp.into_body()).await?;
+        assert!(serde_json::from_slice::<Vec<PendingDeploy>>(&body)?.is_empty());
+
+        // the feed is only for the owner of the project
+        let trinity = service.create_user("trinity".parse().unwrap()).await?;
+        let resp = router
+            .call(
+                Request::get("/projects/matrix/events")
+                    .body(Body::empty())
+                    .unwrap()
+                    .with_header(&Authorization::bearer(trinity.key.as_str()).unwrap()),
+            )
+            .await?;
+        assert_ne!(resp.status(), StatusCode::OK);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn api_routing_table() -> anyhow::Result<()> {
+        let world = World::new().await;
+        let (service, mut router) = test_router(&world).await;
+
+        let admin = service.create_user("neo".parse().unwrap()).await?;
+        service.set_super_user(&admin.name, true).await?;
+        let authorization = Authorization::bearer(admin.key.as_str()).unwrap();
+
+        let matrix: ProjectName = "matrix".parse().unwrap();
+        service
+            .create_project(matrix.clone(), admin.name.clone())
+            .await?;
+        service
+            .add_project_alias(&matrix, &"matrix-canary".parse().unwrap())
+            .await?;
+        service
+            .create_custom_domain(
+                matrix.clone(),
+                &"neo.the.matrix".parse::<FQDN>().unwrap(),
+                "certificate",
+                "private key",
+            )
+            .await?;
+
+        let resp = router
+            .call(
+                Request::get("/admin/routes")
+                    .body(Body::empty())
+                    .unwrap()
+                    .with_header(&authorization),
+            )
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+        let routes: Vec<project::Route> = serde_json::from_slice(&body).unwrap();
+
+        let route = |host: String, kind: project::RouteKind| project::Route {
+            host,
+            kind,
+            project: "matrix".to_string(),
+            state: "creating".to_string(),
+            // the project is not ready yet
+            backend: None,
+            shadowed: false,
+        };
+        assert_eq!(
+            routes,
+            vec![
+                route(
+                    format!("matrix-canary.{}", world.fqdn()),
+                    project::RouteKind::Alias
+                ),
+                route(
+                    format!("matrix.{}", world.fqdn()),
+                    project::RouteKind::Project
+                ),
+                route(
+                    "neo.the.matrix".to_string(),
+                    project::RouteKind::CustomDomain
+                ),
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn api_invalid_project_name_details() -> anyhow::Result<()> {
+        let world = World::new().await;
+        let (service, mut router) = test_router(&world).await;
+
+        let neo = service.create_user("neo".parse().unwrap()).await?;
+        let authorization = Authorization::bearer(neo.key.as_str()).unwrap();
+
+        for (name, detail) in [
+            (
+                "a".repeat(64),
+                "project name must not be longer than 63 characters",
+            ),
+            (
+                "Matrix".to_string(),
+                "project name must not contain uppercase characters",
+            ),
+            (
+                "the_matrix".to_string(),
+                "project name must not contain the character `_`",
+            ),
+            (
+                "the.matrix".to_string(),
+                "project name must not contain the character `.`",
+            ),
+            (
+                "-matrix".to_string(),
+                "project name must not start with `-`",
+            ),
+            ("matrix-".to_string(), "project name must not end with `-`"),
+        ] {
+            let resp = router
+                .call(
+                    Request::builder()
+                        .method("POST")
+                        .uri(format!("/projects/{name}"))
+                        .body(Body::empty())
+                        .unwrap()
+                        .with_header(&authorization),
+                )
+                .await
+                .unwrap();
+            assert_eq!(resp.status(), StatusCode::BAD_REQUEST, "{name}");
+
+            let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+            let error: ApiError = serde_json::from_slice(&body).unwrap();
+            assert!(
+                error.message.starts_with(detail),
+                "{name} gave the wrong detail: {}",
+                error.message
+            );
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn api_scoped_keys() -> anyhow::Result<()> {
+        let world = World::new().await;
+        let (service, mut router) = test_router(&world).await;
+
+        let neo = service.create_user("neo".parse().unwrap()).await?;
+        let full = Authorization::bearer(neo.key.as_str()).unwrap();
+
+        service
+            .create_project("matrix".parse().unwrap(), neo.name.clone())
+            .await?;
+
+        let mint = |authorization: &Authorization<Bearer>| {
+            Request::builder()
+                .method("POST")
+                .uri("/keys")
+                .header(CONTENT_TYPE, "application/json")
+                .body(Body::from(
+                    serde_json::to_vec(&user::ScopedKeyRequest {
+                        projects: Some(vec!["matrix".to_string()]),
+                        actions: Some(vec![Action::Deploy]),
+                    })
+                    .unwrap(),
+                ))
+                .unwrap()
+                .with_header(authorization)
+        };
+
+        let resp = router.call(mint(&full)).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+        let minted: user::ScopedKeyResponse = serde_json::from_slice(&body).unwrap();
+        assert_eq!(minted.actions, Some(vec![Action::Deploy]));
+        let deploy_only = Authorization::bearer(&minted.key).unwrap();
+
+        let request = |method: &str, uri: &str| {
+            Request::builder()
+                .method(method)
+                .uri(uri)
+                .body(Body::empty())
+                .unwrap()
+                .with_header(&deploy_only)
+        };
+
+        // deploys get past the scope check, and only fail because
+        // nothing is running yet
+        let resp = router
+            .call(request("POST", "/projects/matrix/services/matrix"))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+        let resp = router
+            .call(request("DELETE", "/projects/matrix"))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+
+        let resp = router
+            .call(request("POST", "/projects/reloaded"))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+
+        // a scoped key cannot mint keys of its own
+        let resp = router.call(mint(&deploy_only)).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+
+        // while the full key can still do everything
+        let resp = router
+            .call(
+                Request::builder()
+                    .method("DELETE")
+                    .uri("/projects/matrix")
+                    .body(Body::empty())
+                    .unwrap()
+                    .with_header(&full),
+            )
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn api_concurrent_deploys_rejected() -> anyhow::Result<()> {
+        let world = World::new().await;
+        let service = Arc::new(
+            GatewayService::init(world.args(), world.pool())
+                .await
+                .with_deploy_concurrency(DeployConcurrency::Reject),
+        );
+
+        let mut router = router_for(Arc::clone(&service));
+
+        let neo = service.create_user("neo".parse().unwrap()).await?;
+        let matrix: ProjectName = "matrix".parse().unwrap();
+        service
+            .create_project(matrix.clone(), neo.name.clone())
+            .await?;
+        let authorization = Authorization::bearer(neo.key.as_str()).unwrap();
+
+        let deploy = |archive: Vec<u8>| {
+            Request::builder()
+                .method("POST")
+                .uri("/projects/matrix/services/matrix")
+                .body(Body::from(archive))
+                .unwrap()
+                .with_header(&authorization)
+        };
+
+        // a deploy is in progress
+        let guard = service.lock_deployments(&matrix).await?;
+
+        let resp = router.call(deploy(empty_crate())).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::CONFLICT);
+
+        // uploads which are not crates are turned away before that
+        let resp = router
+            .call(deploy(b"definitely not a crate".to_vec()))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        let body = hyper::body::to_bytes(resp.into_body()).await?;
+        let error: ApiError = serde_json::from_slice(&body)?;
+        assert_eq!(error.code.as_deref(), Some("invalid_archive"));
+
+        // once it is done, deploys make it through to the project,
+        // which is not running here
+        drop(guard);
+        let resp = router.call(deploy(empty_crate())).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+        Ok(())
+    }
+
+    /// A crate with nothing in it, packed as `cargo shuttle` does
+    fn empty_crate() -> Vec<u8> {
+        let encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::fast());
+        tar::Builder::new(encoder)
+            .into_inner()
+            .unwrap()
+            .finish()
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn api_list_and_revoke_keys() -> anyhow::Result<()> {
+        let world = World::new().await;
+        let (service, mut router) = test_router(&world).await;
+
+        let neo = service.create_user("neo".parse().unwrap()).await?;
+        let full = Authorization::bearer(neo.key.as_str()).unwrap();
+        let (id, scoped_key) = service
+            .create_scoped_key(
+                &neo.name,
+                &KeyScope {
+                    projects: None,
+                    actions: Some(vec![Action::Read]),
+                },
+            )
+            .await?;
+        let scoped = Authorization::bearer(scoped_key.to_string().as_str()).unwrap();
+
+        let request = |method: &str, uri: &str, authorization: &Authorization<Bearer>| {
+            Request::builder()
+                .method(method)
+                .uri(uri)
+                .body(Body::empty())
+                .unwrap()
+                .with_header(authorization)
+        };
+
+        // using the scoped key records when it was last used
+        let resp = router
+            .call(request("GET", "/projects", &scoped))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let resp = router
+            .call(request("GET", "/users/neo/keys", &full))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+        assert!(!String::from_utf8_lossy(&body).contains(neo.key.as_str()));
+        assert!(!String::from_utf8_lossy(&body).contains(&scoped_key.to_string()));
+
+        let keys: Vec<user::KeyResponse> = serde_json::from_slice(&body).unwrap();
+        assert_eq!(keys.len(), 2);
+        assert!(keys[0].scope.is_none());
+        assert_eq!(keys[1].id, id);
+        assert!(keys[1].created_at.is_some());
+        assert!(keys[1].last_used_at.is_some());
+        assert_eq!(
+            keys[1].scope.as_ref().unwrap().actions,
+            Some(vec![Action::Read])
+        );
+
+        // scoped keys cannot list or revoke keys
+        let resp = router
+            .call(request("GET", "/users/neo/keys", &scoped))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+
+        // not even when they were minted by a super user
+        let trinity = service.create_user("trinity".parse().unwrap()).await?;
+        service.set_super_user(&trinity.name, true).await?;
+        let (_, admin_scoped_key) = service
+            .create_scoped_key(
+                &trinity.name,
+                &KeyScope {
+                    projects: None,
+                    actions: Some(vec![Action::Read]),
+                },
+            )
+            .await?;
+        let admin_scoped = Authorization::bearer(admin_scoped_key.to_string().as_str()).unwrap();
+
+        let resp = router
+            .call(request("GET", "/users/neo/keys", &admin_scoped))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+
+        let resp = router
+            .call(request(
+                "DELETE",
+                &format!("/users/neo/keys/{id}"),
+                &admin_scoped,
+            ))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+
+        let resp = router
+            .call(request("DELETE", "/users/neo/keys/primary", &full))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+        let resp = router
+            .call(request("DELETE", &format!("/users/neo/keys/{id}"), &full))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let resp = router
+            .call(request("DELETE", &format!("/users/neo/keys/{id}"), &full))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+        // the revoked key no longer authenticates
+        let resp = router
+            .call(request("GET", "/projects", &scoped))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn api_auditor_read_only() -> anyhow::Result<()> {
+        let world = World::new().await;
+        let (service, mut router) = test_router(&world).await;
+
+        let neo = service.create_user("neo".parse().unwrap()).await?;
+        service
+            .create_project("matrix".parse().unwrap(), neo.name.clone())
+            .await?;
+
+        let smith = service.create_user("smith".parse().unwrap()).await?;
+        service.set_auditor(&smith.name, true).await?;
+        let auditor = Authorization::bearer(smith.key.as_str()).unwrap();
+
+        let request = |method: &str, uri: &str| {
+            Request::builder()
+                .method(method)
+                .uri(uri)
+                .body(Body::empty())
+                .unwrap()
+                .with_header(&auditor)
+        };
+
+        // reads work across every account
+        for uri in [
+            "/admin/projects",
+            "/projects/matrix",
+            "/projects/matrix/env",
+        ] {
+            let resp = router.call(request("GET", uri)).await.unwrap();
+            assert_eq!(resp.status(), StatusCode::OK, "GET {uri}");
+        }
+
+        // but nothing can be changed
+        for (method, uri) in [
+            ("DELETE", "/projects/matrix"),
+            ("POST", "/projects/reloaded"),
+            ("PUT", "/admin/maintenance"),
+            ("POST", "/admin/drain"),
+            ("POST", "/admin/projects/matrix/freeze"),
+            ("GET", "/users/neo"),
+        ] {
+            let resp = router.call(request(method, uri)).await.unwrap();
+            assert_eq!(resp.status(), StatusCode::FORBIDDEN, "{method} {uri}");
+        }
+
+        assert!(service
+            .find_project(&"matrix".parse().unwrap())
+            .await
+            .is_ok());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn api_backup_super_users_only() -> anyhow::Result<()> {
+        let world = World::new().await;
+        let (service, mut router) = test_router(&world).await;
+
+        let neo = service.create_user("neo".parse().unwrap()).await?;
+        service.set_super_user(&neo.name, true).await?;
+        let admin = Authorization::bearer(neo.key.as_str()).unwrap();
+
+        let smith = service.create_user("smith".parse().unwrap()).await?;
+        service.set_auditor(&smith.name, true).await?;
+        let auditor = Authorization::bearer(smith.key.as_str()).unwrap();
+
+        let request = |auth: &Authorization<Bearer>| {
+            Request::builder()
+                .uri("/admin/backup")
+                .body(Body::empty())
+                .unwrap()
+                .with_header(auth)
+        };
+
+        let resp = router.call(request(&auditor)).await?;
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+
+        let resp = router.call(request(&admin)).await?;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(resp.into_body()).await?;
+        let backup: Backup = serde_json::from_slice(&body)?;
+        assert_eq!(backup.version, crate::backup::BACKUP_VERSION);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn api_impersonation() -> anyhow::Result<()> {
+        let world = World::new().await;
+        let (service, mut router) = test_router(&world).await;
+
+        let neo = service.create_user("neo".parse().unwrap()).await?;
+        service
+            .create_project("matrix".parse().unwrap(), neo.name.clone())
+            .await?;
+
+        let trinity = service.create_user("trinity".parse().unwrap()).await?;
+        service.set_super_user(&trinity.name, true).await?;
+        let admin = Authorization::bearer(trinity.key.as_str()).unwrap();
+
+        let smith = service.create_user("smith".parse().unwrap()).await?;
+        service.set_auditor(&smith.name, true).await?;
+        let auditor = Authorization::bearer(smith.key.as_str()).unwrap();
+
+        let request = |method: &str, uri: &str, auth: &Authorization<Bearer>| {
+            let body = if method == "POST" {
+                Body::from("{}")
+            } else {
+                Body::empty()
+            };
+            Request::builder()
+                .method(method)
+                .uri(uri)
+                .header("Content-Type", "application/json")
+                .body(body)
+                .unwrap()
+                .with_header(auth)
+        };
+
+        // only super users can impersonate, and never another one
+        let resp = router
+            .call(request("POST", "/admin/impersonate/neo", &auditor))
+            .await?;
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+        let resp = router
+            .call(request("POST", "/admin/impersonate/smith", &admin))
+            .await?;
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+
+        let resp = router
+            .call(request("POST", "/admin/impersonate/neo", &admin))
+            .await?;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(resp.into_body()).await?;
+        let impersonation: user::ImpersonateResponse = serde_json::from_slice(&body)?;
+        assert_eq!(impersonation.account_name, "neo");
+        assert!(impersonation.read_only);
+        let as_neo = Authorization::bearer(&impersonation.key).unwrap();
+
+        // the token sees what the user sees, and only reads by default
+        let resp = router
+            .call(request("GET", "/projects/matrix", &as_neo))
+            .await?;
+        assert_eq!(resp.status(), StatusCode::OK);
+        for (method, uri) in [
+            ("DELETE", "/projects/matrix"),
+            ("GET", "/admin/projects"),
+            ("POST", "/admin/impersonate/neo"),
+        ] {
+            let resp = router.call(request(method, uri, &as_neo)).await?;
+            assert_eq!(resp.status(), StatusCode::FORBIDDEN, "{method} {uri}");
+        }
+
+        // everything done with it is on record with both actors
+        let resp = router
+            .call(request("GET", "/admin/audit", &auditor))
+            .await?;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(resp.into_body()).await?;
+        let entries: Vec<user::AuditEntry> = serde_json::from_slice(&body)?;
+        let actions: Vec<_> = entries
+            .iter()
+            .rev()
+            .map(|entry| {
+                assert_eq!(entry.actor, "trinity");
+                assert_eq!(entry.effective, "neo");
+                entry.action.as_str()
+            })
+            .collect();
+        assert_eq!(
+            actions,
+            [
+                "impersonate (read-only)",
+                "GET /projects/matrix",
+                "DELETE /projects/matrix",
+                "GET /admin/projects",
+                "POST /admin/impersonate/neo",
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn api_project_tags() -> anyhow::Result<()> {
+        let world = World::new().await;
+        let (service, mut router) = test_router(&world).await;
+
+        let neo = service.create_user("neo".parse().unwrap()).await?;
+        for project in ["matrix", "zion", "nebuchadnezzar"] {
+            service
+                .create_project(project.parse().unwrap(), neo.name.clone())
+                .await?;
+        }
+        let authorization = Authorization::bearer(neo.key.as_str()).unwrap();
+
+        let request = |method: &str, uri: &str, body: &str| {
+            Request::builder()
+                .method(method)
+                .uri(uri)
+                .header("Content-Type", "application/json")
+                .body(Body::from(body.to_string()))
+                .unwrap()
+                .with_header(&authorization)
+        };
+
+        let resp = router
+            .call(request(
+                "PUT",
+                "/projects/matrix/tags",
+                r#"["env:prod", "Team-A"]"#,
+            ))
+            .await?;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(resp.into_body()).await?;
+        let tags: Vec<String> = serde_json::from_slice(&body)?;
+        assert_eq!(tags, vec!["env:prod", "team-a"]);
+
+        router
+            .call(request("PUT", "/projects/zion/tags", r#"["env:prod"]"#))
+            .await?;
+        router
+            .call(request(
+                "PUT",
+                "/projects/nebuchadnezzar/tags",
+                r#"["env:dev"]"#,
+            ))
+            .await?;
+
+        for tags in [r#"["-leading"]"#, r#"["has space"]"#, r#"[""]"#] {
+            let resp = router
+                .call(request("PUT", "/projects/zion/tags", tags))
+                .await?;
+            assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        }
+
+        let list = |tag: &str| request("GET", &format!("/projects?tag={tag}"), "");
+        let resp = router.call(list("ENV:PROD")).await?;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(resp.into_body()).await?;
+        let projects: Vec<project::Response> = serde_json::from_slice(&body)?;
+        let mut names: Vec<_> = projects.into_iter().map(|p| p.name).collect();
+        names.sort();
+        assert_eq!(names, vec!["matrix", "zion"]);
+
+        let resp = router
+            .call(request("DELETE", "/projects/zion/tags/env:prod", ""))
+            .await?;
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let resp = router.call(list("env:prod")).await?;
+        let body = hyper::body::to_bytes(resp.into_body()).await?;
+        let projects: Vec<project::Response> = serde_json::from_slice(&body)?;
+        assert_eq!(projects.len(), 1);
+        assert_eq!(projects[0].name, "matrix");
+
+        let resp = router.call(request("GET", "/projects", "")).await?;
+        let body = hyper::body::to_bytes(resp.into_body()).await?;
+        let projects: Vec<project::Response> = serde_json::from_slice(&body)?;
+        assert_eq!(projects.len(), 3);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn api_version() -> anyhow::Result<()> {
+        let world = World::new().await;
+        let (_, mut router) = test_router(&world).await;
+
+        // anyone can ask, no key needed
+        let resp = router
+            .call(Request::get("/version").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(resp.into_body()).await?;
+        let version: VersionResponse = serde_json::from_slice(&body)?;
+        assert_eq!(version, VersionResponse::current());
+        assert_eq!(version.version, env!("CARGO_PKG_VERSION"));
+        assert_eq!(version.api_version, API_VERSION);
+
+        // the build info is always there, if only as nulls
+        let fields: serde_json::Value = serde_json::from_slice(&body)?;
+        for field in ["version", "git_sha", "build_time", "api_version"] {
+            assert!(fields.get(field).is_some(), "{field} is missing");
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn status() {
+        let world = World::new().await;
+        let service = Arc::new(GatewayService::init(world.args(), world.pool()).await);
+
+        let (sender, mut receiver) = channel::<BoxedTask>(1);
+        let (ctl_send, ctl_recv) = oneshot::channel();
+        let (done_send, done_recv) = oneshot::channel();
+        let worker = tokio::spawn(async move {
+            let mut done_send = Some(done_send);
+            // do not process until instructed
+            ctl_recv.await.unwrap();
+
+            while receiver.recv().await.is_some() {
+                done_send.take().unwrap().send(()).unwrap();
+                // do nothing
+            }
+        });
+
+        let mut router = ApiBuilder::new()
+            .with_service(Arc::clone(&service))
+            .with_sender(sender)
+            .with_default_routes()
+            .into_router();
+
+        let get_status = || {
+            Request::builder()
+                .method("GET")
+                .uri("/")
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        let resp = router.call(get_status()).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let neo: AccountName = "neo".parse().unwrap();
+        let matrix: ProjectName = "matrix".parse().unwrap();
+
+        let neo = service.create_user(neo).await.unwrap();
+        let authorization = Authorization::bearer(neo.key.as_str()).unwrap();
 
-        let running_builds = Arc::new(Mutex::new(TtlCache::new(concurrent_builds)));
+        let create_project = Request::builder()
+            .method("POST")
+            .uri(format!("/projects/{matrix}"))
+            .body(Body::empty())
+            .unwrap()
+            .with_header(&authorization);
 
-        self.router.with_state(RouterState {
-            service,
-            sender,
-            running_builds,
-        })
-    }
+        router.call(create_project).await.unwrap();
 
-    pub fn serve(self) -> impl Future<Output = Result<(), hyper::Error>> {
-        let bind = self.bind.expect("a socket address to bind to is required");
-        let router = self.into_router();
-        axum::Server::bind(&bind).serve(router.into_make_service())
-    }
-}
+        let resp = router.call(get_status()).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::INTERNAL_SERVER_ERROR);
 
-#[cfg(test)]
-pub mod tests {
-    use std::sync::Arc;
+        ctl_send.send(()).unwrap();
+        done_recv.await.unwrap();
 
-    use axum::body::Body;
-    use axum::headers::Authorization;
-    use axum::http::Request;
-    use futures::TryFutureExt;
-    use hyper::StatusCode;
-    use tokio::sync::mpsc::channel;
-    use tokio::sync::oneshot;
-    use tower::Service;
+        let resp = router.call(get_status()).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
 
-    use super::*;
-    use crate::service::GatewayService;
-    use crate::tests::{RequestBuilderExt, World};
+        worker.abort();
+        let _ = worker.await;
+
+        let resp = router.call(get_status()).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    }
 
     #[tokio::test]
-    async fn api_create_get_delete_projects() -> anyhow::Result<()> {
+    async fn api_freeze_project() -> anyhow::Result<()> {
         let world = World::new().await;
         let service = Arc::new(GatewayService::init(world.args(), world.pool()).await);
 
-        let (sender, mut receiver) = channel::<BoxedTask>(256);
-        tokio::spawn(async move {
-            while receiver.recv().await.is_some() {
-                // do not do any work with inbound requests
-            }
-        });
+        let worker = crate::worker::Worker::new();
+        let sender = worker.sender();
+        tokio::spawn(worker.start());
 
         let mut router = ApiBuilder::new()
             .with_service(Arc::clone(&service))
-            .with_sender(sender)
+            .with_sender(sender.clone())
             .with_default_routes()
             .into_router();
 
-        let neo = service.create_user("neo".parse().unwrap()).await?;
+        let admin = service.create_user("neo".parse().unwrap()).await?;
+        service.set_super_user(&admin.name, true).await?;
+        let authorization = Authorization::bearer(admin.key.as_str()).unwrap();
 
-        let create_project = |project: &str| {
-            Request::builder()
-                .method("POST")
-                .uri(format!("/projects/{project}"))
-                .body(Body::empty())
-                .unwrap()
-        };
+        let matrix: ProjectName = "matrix".parse().unwrap();
+        service
+            .create_project(matrix.clone(), admin.name.clone())
+            .await?;
+        service
+            .new_task()
+            .project(matrix.clone())
+            .send(&sender)
+            .await?
+            .await;
+        assert!(service.find_project(&matrix).await?.is_ready());
 
-        let delete_project = |project: &str| {
+        let request = |method: &str, uri: &str| {
             Request::builder()
-                .method("DELETE")
-                .uri(format!("/projects/{project}"))
+                .method(method)
+                .uri(uri)
                 .body(Body::empty())
                 .unwrap()
+                .with_header(&authorization)
         };
 
         router
-            .call(create_project("matrix"))
-            .map_ok(|resp| assert_eq!(resp.status(), StatusCode::UNAUTHORIZED))
+            .call(request("POST", "/admin/projects/matrix/freeze"))
+            .map_ok(|resp| assert_eq!(resp.status(), StatusCode::OK))
             .await
             .unwrap();
 
-        let authorization = Authorization::bearer(neo.key.as_str()).unwrap();
+        let frozen = service.find_project(&matrix).await?;
+        assert!(frozen.is_frozen());
+        let running = frozen
+            .container()
+            .and_then(|container| container.state)
+            .and_then(|state| state.running);
+        assert_ne!(running, Some(true));
 
-        router
-            .call(create_project("matrix").with_header(&authorization))
-            .map_ok(|resp| {
-                assert_eq!(resp.status(), StatusCode::OK);
-            })
+        let resp = router
+            .call(request("GET", "/projects/matrix/status"))
             .await
             .unwrap();
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+        let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+        let error: ApiError = serde_json::from_slice(&body).unwrap();
+        assert!(error.message.contains("frozen"));
 
-        router
-            .call(create_project("matrix").with_header(&authorization))
-            .map_ok(|resp| {
-                assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
-            })
+        // Nor can it be deleted to be created again without the freeze
+        let resp = router
+            .call(request("DELETE", "/projects/matrix"))
             .await
             .unwrap();
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+        assert!(service.find_project(&matrix).await?.is_frozen());
 
-        let get_project = |project| {
-            Request::builder()
-                .method("GET")
-                .uri(format!("/projects/{project}"))
-                .body(Body::empty())
-                .unwrap()
-        };
+        // Nothing revives a frozen project on its own
+        service
+            .new_task()
+            .project(matrix.clone())
+            .send(&sender)
+            .await?
+            .await;
+        assert!(service.find_project(&matrix).await?.is_frozen());
 
         router
-            .call(get_project("matrix"))
-            .map_ok(|resp| {
-                assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
-            })
+            .call(request("POST", "/admin/projects/matrix/unfreeze"))
+            .map_ok(|resp| assert_eq!(resp.status(), StatusCode::OK))
             .await
             .unwrap();
+        assert!(service.find_project(&matrix).await?.is_ready());
 
         router
-            .call(get_project("matrix").with_header(&authorization))
-            .map_ok(|resp| {
-                assert_eq!(resp.status(), StatusCode::OK);
-            })
+            .call(request("GET", "/projects/matrix/status"))
+            .map_ok(|resp| assert_eq!(resp.status(), StatusCode::OK))
             .await
             .unwrap();
 
+        // Unfreezing a project which is not frozen is a mistake
         router
-            .call(delete_project("matrix").with_header(&authorization))
-            .map_ok(|resp| {
-                assert_eq!(resp.status(), StatusCode::OK);
-            })
+            .call(request("POST", "/admin/projects/matrix/unfreeze"))
+            .map_ok(|resp| assert_eq!(resp.status(), StatusCode::BAD_REQUEST))
             .await
             .unwrap();
 
-        router
-            .call(create_project("reloaded").with_header(&authorization))
-            .map_ok(|resp| {
-                assert_eq!(resp.status(), StatusCode::OK);
-            })
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn api_fork_project() -> anyhow::Result<()> {
+        let world = World::new().await;
+        let (service, mut router) = test_router(&world).await;
+
+        let neo = service.create_user("neo".parse().unwrap()).await?;
+        let matrix: ProjectName = "matrix".parse().unwrap();
+        service
+            .create_project(matrix.clone(), neo.name.clone())
+            .await?;
+        service
+            .set_project_env(
+                &matrix,
+                &BTreeMap::from([("SECRET".to_string(), "the one".to_string())]),
+            )
+            .await?;
+        let rate_limit = project::RateLimit {
+            requests_per_second: 5,
+            burst: 10,
+        };
+        service
+            .set_project_rate_limit(&matrix, rate_limit.clone())
+            .await?;
+        let rules = project::HeaderRules {
+            response: project::HeaderRuleSet {
+                remove: vec!["server".to_string()],
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        service
+            .set_project_header_rules(&matrix, rules.clone())
+            .await?;
+        service
+            .set_project_webhook(&matrix, "https://1.1.1.1/hook".to_string())
+            .await?;
+
+        let trinity = service.create_user("trinity".parse().unwrap()).await?;
+        service
+            .create_project("zion".parse().unwrap(), trinity.name.clone())
+            .await?;
+
+        let authorization = Authorization::bearer(neo.key.as_str()).unwrap();
+        let fork = |uri: &str| {
+            Request::builder()
+                .method("POST")
+                .uri(uri)
+                .body(Body::empty())
+                .unwrap()
+                .with_header(&authorization)
+        };
+
+        let resp = router
+            .call(fork("/projects/reloaded?from=matrix"))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let reloaded: ProjectName = "reloaded".parse().unwrap();
+        // the names of env vars make it over, but not their values
+        assert_eq!(
+            service.project_env(&reloaded).await?,
+            vec![("SECRET".to_string(), String::new())]
+        );
+        assert_eq!(service.rate_limiter().limit(&reloaded), Some(rate_limit));
+        assert_eq!(
+            service.header_rewriter().rewrites(&reloaded).unwrap().rules,
+            rules
+        );
+        assert!(service.find_project_webhook(&reloaded).await?.is_none());
+
+        // projects of other accounts cannot be forked
+        let resp = router
+            .call(fork("/projects/revolutions?from=zion"))
             .await
             .unwrap();
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+        assert!(service
+            .find_project(&"revolutions".parse().unwrap())
+            .await
+            .is_err());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn api_export_projects() -> anyhow::Result<()> {
+        let world = World::new().await;
+        let (service, mut router) = test_router(&world).await;
 
+        let neo = service.create_user("neo".parse().unwrap()).await?;
         let trinity = service.create_user("trinity".parse().unwrap()).await?;
+        let authorization = Authorization::bearer(neo.key.as_str()).unwrap();
 
-        let authorization = Authorization::bearer(trinity.key.as_str()).unwrap();
+        for name in ["matrix", "reloaded", "revolutions"] {
+            service
+                .create_project(name.parse().unwrap(), neo.name.clone())
+                .await?;
+        }
+        service
+            .create_project("animatrix".parse().unwrap(), trinity.name.clone())
+            .await?;
+
+        let matrix: ProjectName = "matrix".parse().unwrap();
+        service
+            .set_project_env(
+                &matrix,
+                &BTreeMap::from([("DATABASE_URL".to_string(), "secret".to_string())]),
+            )
+            .await?;
+        service
+            .create_custom_domain(
+                matrix.clone(),
+                &"neo.the.matrix".parse::<FQDN>().unwrap(),
+                "certificate",
+                "private key",
+            )
+            .await?;
+
+        let mut export = |uri: &str| {
+            let req = Request::get(uri)
+                .body(Body::empty())
+                .unwrap()
+                .with_header(&authorization);
+            let call = router.call(req);
+            async move {
+                let resp = call.await.unwrap();
+                assert_eq!(resp.status(), StatusCode::OK);
+                let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+                serde_json::from_slice::<project::ExportManifest>(&body).unwrap()
+            }
+        };
+
+        let manifest = export("/account/projects/export").await;
+        assert_eq!(manifest.version, project::EXPORT_MANIFEST_VERSION);
+        assert_eq!(manifest.account_name, "neo");
+        assert!(manifest.next.is_none());
+
+        let names: Vec<_> = manifest.projects.iter().map(|p| p.name.as_str()).collect();
+        assert_eq!(names, ["matrix", "reloaded", "revolutions"]);
+
+        let exported = &manifest.projects[0];
+        assert_eq!(exported.state, project::State::Creating);
+        assert_eq!(exported.env, ["DATABASE_URL"]);
+        assert_eq!(exported.custom_domains, ["neo.the.matrix"]);
+        assert!(!serde_json::to_string(&manifest)?.contains("secret"));
+
+        // paging through gives back the same projects
+        let first = export("/account/projects/export?limit=2").await;
+        assert_eq!(first.projects.len(), 2);
+        assert_eq!(first.next.as_deref(), Some("reloaded"));
+
+        let second = export("/account/projects/export?limit=2&after=reloaded").await;
+        assert_eq!(second.projects.len(), 1);
+        assert_eq!(second.projects[0].name, "revolutions");
+        assert!(second.next.is_none());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn api_delete_cancels_create() -> anyhow::Result<()> {
+        let world = World::new().await;
+        let service = Arc::new(GatewayService::init(world.args(), world.pool()).await);
+
+        let worker = crate::worker::Worker::new();
+        let sender = worker.sender();
+        tokio::spawn(worker.start());
+
+        let mut router = ApiBuilder::new()
+            .with_service(Arc::clone(&service))
+            .with_sender(sender.clone())
+            .with_default_routes()
+            .into_router();
+
+        let neo = service.create_user("neo".parse().unwrap()).await?;
+        let authorization = Authorization::bearer(neo.key.as_str()).unwrap();
+
+        let matrix: ProjectName = "matrix".parse().unwrap();
+        service.create_project(matrix.clone(), neo.name).await?;
+        let create = service
+            .new_task()
+            .project(matrix.clone())
+            .send(&sender)
+            .await?;
+
+        // wait for the creation to be well under way
+        tokio::time::timeout(Duration::from_secs(60), async {
+            while let Project::Creating(_) = service.find_project(&matrix).await.unwrap() {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await?;
 
         router
-            .call(get_project("reloaded").with_header(&authorization))
-            .map_ok(|resp| assert_eq!(resp.status(), StatusCode::NOT_FOUND))
+            .call(
+                Request::delete("/projects/matrix")
+                    .body(Body::empty())
+                    .unwrap()
+                    .with_header(&authorization),
+            )
+            .map_ok(|resp| assert_eq!(resp.status(), StatusCode::OK))
             .await
             .unwrap();
 
-        router
-            .call(delete_project("reloaded").with_header(&authorization))
-            .map_ok(|resp| {
-                assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+        // the creation gives up rather than racing to completion
+        tokio::time::timeout(Duration::from_secs(30), create).await?;
+
+        tokio::time::timeout(Duration::from_secs(60), async {
+            while !service.find_project(&matrix).await.unwrap().is_destroyed() {
+                tokio::time::sleep(Duration::from_millis(100)).await;
+            }
+        })
+        .await?;
+
+        let docker = world.context();
+        let container_name = format!("{}matrix_run", docker.container_settings().prefix);
+        assert!(matches!(
+            docker
+                .docker()
+                .inspect_container(&container_name, None)
+                .await,
+            Err(bollard::errors::Error::DockerResponseServerError {
+                status_code: 404,
+                ..
             })
+        ));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn api_delete_while_creating() -> anyhow::Result<()> {
+        let world = World::new().await;
+        let service = Arc::new(GatewayService::init(world.args(), world.pool()).await);
+
+        let worker = crate::worker::Worker::new();
+        let sender = worker.sender();
+        tokio::spawn(worker.start());
+
+        let mut router = ApiBuilder::new()
+            .with_service(Arc::clone(&service))
+            .with_sender(sender.clone())
+            .with_default_routes()
+            .into_router();
+
+        let neo = service.create_user("neo".parse().unwrap()).await?;
+        let authorization = Authorization::bearer(neo.key.as_str()).unwrap();
+
+        let matrix: ProjectName = "matrix".parse().unwrap();
+        service.create_project(matrix.clone(), neo.name).await?;
+        let create = service
+            .new_task()
+            .project(matrix.clone())
+            .send(&sender)
+            .await?;
+
+        // wait for the task to be working on the creation
+        tokio::time::timeout(Duration::from_secs(60), async {
+            while !service
+                .task_tracker()
+                .list()
+                .await
+                .iter()
+                .any(|(_, record)| record.project_name == matrix && record.state == "creating")
+            {
+                tokio::time::sleep(Duration::from_millis(1)).await;
+            }
+        })
+        .await?;
+
+        router
+            .call(
+                Request::delete("/projects/matrix")
+                    .body(Body::empty())
+                    .unwrap()
+                    .with_header(&authorization),
+            )
+            .map_ok(|resp| assert_eq!(resp.status(), StatusCode::OK))
             .await
             .unwrap();
 
+        // the cancelled creation removes what it made on its way out,
+        // even though its project is marked destroyed already
+        tokio::time::timeout(Duration::from_secs(30), create).await?;
+
+        tokio::time::timeout(Duration::from_secs(60), async {
+            while !service.find_project(&matrix).await.unwrap().is_destroyed() {
+                tokio::time::sleep(Duration::from_millis(100)).await;
+            }
+        })
+        .await?;
+
+        let docker = world.context();
+        let container_name = format!("{}matrix_run", docker.container_settings().prefix);
+        assert!(matches!(
+            docker
+                .docker()
+                .inspect_container(&container_name, None)
+                .await,
+            Err(bollard::errors::Error::DockerResponseServerError {
+                status_code: 404,
+                ..
+            })
+        ));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn api_force_destroy_project() -> anyhow::Result<()> {
+        let world = World::new().await;
+        let (service, mut router) = test_router(&world).await;
+
+        let admin = service.create_user("neo".parse().unwrap()).await?;
+        service.set_super_user(&admin.name, true).await?;
+        let authorization = Authorization::bearer(admin.key.as_str()).unwrap();
+
+        let matrix: ProjectName = "matrix".parse().unwrap();
         service
-            .set_super_user(&"trinity".parse().unwrap(), true)
+            .create_project(matrix.clone(), admin.name.clone())
             .await?;
 
-        router
-            .call(get_project("reloaded").with_header(&authorization))
-            .map_ok(|resp| assert_eq!(resp.status(), StatusCode::OK))
-            .await
-            .unwrap();
+        // Get a container created and leave the project there, as if
+        // whatever was moving it along had died
+        let docker = world.context();
+        let stuck = service.find_project(&matrix).await?.next(&docker).await?;
+        assert_eq!(stuck.state(), "attaching");
+        service.update_project(&matrix, &stuck).await?;
 
-        router
-            .call(delete_project("reloaded").with_header(&authorization))
-            .map_ok(|resp| {
-                assert_eq!(resp.status(), StatusCode::OK);
-            })
-            .await
+        let container_id = stuck
+            .container()
+            .and_then(|container| container.id)
             .unwrap();
 
-        // delete returns 404 for project that doesn't exist
-        router
-            .call(delete_project("resurrections").with_header(&authorization))
-            .map_ok(|resp| {
-                assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+        let force_destroy = || {
+            Request::post("/admin/projects/matrix/force-destroy")
+                .body(Body::empty())
+                .unwrap()
+                .with_header(&authorization)
+        };
+
+        // Doing it again is harmless
+        for _ in 0..2 {
+            let resp = router.call(force_destroy()).await.unwrap();
+            assert_eq!(resp.status(), StatusCode::OK);
+            let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+            let project: project::Response = serde_json::from_slice(&body).unwrap();
+            assert_eq!(project.state, project::State::Destroyed);
+        }
+
+        assert!(service.find_project(&matrix).await?.is_destroyed());
+        assert!(matches!(
+            docker.docker().inspect_container(&container_id, None).await,
+            Err(bollard::errors::Error::DockerResponseServerError {
+                status_code: 404,
+                ..
             })
+        ));
+
+        // Only admins get to do this
+        let trinity = service.create_user("trinity".parse().unwrap()).await?;
+        let resp = router
+            .call(
+                Request::post("/admin/projects/matrix/force-destroy")
+                    .body(Body::empty())
+                    .unwrap()
+                    .with_header(&Authorization::bearer(trinity.key.as_str()).unwrap()),
+            )
             .await
             .unwrap();
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
 
         Ok(())
     }
 
     #[tokio::test]
-    async fn api_create_get_users() -> anyhow::Result<()> {
+    async fn api_project_diagnostics() -> anyhow::Result<()> {
         let world = World::new().await;
-        let service = Arc::new(GatewayService::init(world.args(), world.pool()).await);
+        let (service, mut router) = test_router(&world).await;
 
-        let (sender, mut receiver) = channel::<BoxedTask>(256);
-        tokio::spawn(async move {
-            while receiver.recv().await.is_some() {
-                // do not do any work with inbound requests
+        let admin = service.create_user("neo".parse().unwrap()).await?;
+        service.set_super_user(&admin.name, true).await?;
+        let trinity = service.create_user("trinity".parse().unwrap()).await?;
+
+        let matrix: ProjectName = "matrix".parse().unwrap();
+        service
+            .create_project(matrix.clone(), trinity.name.clone())
+            .await?;
+        service
+            .create_custom_domain(matrix.clone(), &fqdn::fqdn!("zion.org"), "cert", "key")
+            .await?;
+        service.activity_tracker().touch(&matrix);
+
+        let diagnostics = |key: &str| {
+            Request::builder()
+                .uri("/admin/projects/matrix/diagnostics")
+                .body(Body::empty())
+                .unwrap()
+                .with_header(&Authorization::bearer(key).unwrap())
+        };
+
+        let resp = router.call(diagnostics(trinity.key.as_str())).await?;
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+
+        let resp = router.call(diagnostics(admin.key.as_str())).await?;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(resp.into_body()).await?;
+        let diagnostics: project::DiagnosticsResponse = serde_json::from_slice(&body)?;
+
+        assert_eq!(diagnostics.name, "matrix");
+        assert_eq!(diagnostics.account_name, "trinity");
+        assert_eq!(diagnostics.state["creating"]["project_name"], "matrix");
+        assert_eq!(diagnostics.state["creating"]["initial_key"], REDACTED);
+        assert!(diagnostics.container.is_none());
+        assert!(diagnostics.tasks.is_empty());
+        assert!(diagnostics.last_activity.is_some());
+        assert!(diagnostics.backend.is_none());
+        assert_eq!(
+            diagnostics
+                .certificates
+                .iter()
+                .map(|cert| (cert.fqdn.as_str(), cert.custom, cert.served))
+                .collect::<Vec<_>>(),
+            vec![
+                (format!("matrix.{}", world.fqdn()).as_str(), false, None),
+                ("zion.org", true, None),
+            ]
+        );
+
+        // the secrets of a container are blanked out wherever they are
+        let mut state = serde_json::json!({
+            "ready": {
+                "container": {
+                    "Config": {
+                        "Cmd": ["--admin-secret", "hunter2", "--project", "matrix"],
+                        "Env": ["RUST_LOG=debug", "PILL=red"],
+                    }
+                }
             }
         });
+        redact_secrets(&mut state);
+        let config = &state["ready"]["container"]["Config"];
+        assert_eq!(
+            config["Cmd"],
+            serde_json::json!(["--admin-secret", REDACTED, "--project", "matrix"])
+        );
+        assert_eq!(
+            config["Env"],
+            serde_json::json!([format!("RUST_LOG={REDACTED}"), format!("PILL={REDACTED}")])
+        );
 
-        let mut router = ApiBuilder::new()
-            .with_service(Arc::clone(&service))
-            .with_sender(sender)
+        let resp = router
+            .call(
+                Request::builder()
+                    .uri("/admin/projects/reloaded/diagnostics")
+                    .body(Body::empty())
+                    .unwrap()
+                    .with_header(&Authorization::bearer(admin.key.as_str()).unwrap()),
+            )
+            .await?;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn api_project_diagnostics_served_certificates() -> anyhow::Result<()> {
+        let world = World::new().await;
+        let service = Arc::new(GatewayService::init(world.args(), world.pool()).await);
+
+        // with TLS on, as `main` sets it up before the default routes
+        let resolver = Arc::new(GatewayCertResolver::new());
+        let mut router = builder_for(Arc::clone(&service))
+            .with_acme(AcmeClient::new(), Arc::clone(&resolver))
             .with_default_routes()
             .into_router();
 
-        let get_neo = || {
+        let admin = service.create_user("neo".parse().unwrap()).await?;
+        service.set_super_user(&admin.name, true).await?;
+
+        let matrix: ProjectName = "matrix".parse().unwrap();
+        service
+            .create_project(matrix.clone(), admin.name.clone())
+            .await?;
+        service
+            .create_custom_domain(matrix.clone(), &fqdn::fqdn!("zion.org"), "cert", "key")
+            .await?;
+
+        let self_signed = |sni: &str| -> anyhow::Result<Cursor<Vec<u8>>> {
+            let cert = rcgen::generate_simple_self_signed(vec![sni.to_string()])?;
+            let mut buf = Vec::new();
+            buf.extend(cert.serialize_pem()?.as_bytes());
+            buf.extend(cert.serialize_private_key_pem().as_bytes());
+            Ok(Cursor::new(buf))
+        };
+        let subdomain = format!("matrix.{}", world.fqdn());
+        resolver.serve_default_pem(self_signed(&subdomain)?).await?;
+
+        let diagnostics = || {
             Request::builder()
-                .method("GET")
-                .uri("/users/neo")
+                .uri("/admin/projects/matrix/diagnostics")
                 .body(Body::empty())
                 .unwrap()
+                .with_header(&Authorization::bearer(admin.key.as_str()).unwrap())
+        };
+        let served = |diagnostics: project::DiagnosticsResponse| {
+            diagnostics
+                .certificates
+                .into_iter()
+                .map(|cert| (cert.fqdn, cert.served))
+                .collect::<Vec<_>>()
         };
 
-        let post_trinity = || {
+        let resp = router.call(diagnostics()).await?;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(resp.into_body()).await?;
+        assert_eq!(
+            served(serde_json::from_slice(&body)?),
+            vec![
+                (subdomain.clone(), Some(true)),
+                ("zion.org".to_string(), Some(false)),
+            ]
+        );
+
+        resolver
+            .serve_pem("zion.org", self_signed("zion.org")?)
+            .await?;
+        let resp = router.call(diagnostics()).await?;
+        let body = hyper::body::to_bytes(resp.into_body()).await?;
+        assert_eq!(
+            served(serde_json::from_slice(&body)?),
+            vec![
+                (subdomain, Some(true)),
+                ("zion.org".to_string(), Some(true)),
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn api_platform_stats() -> anyhow::Result<()> {
+        let world = World::new().await;
+        let (service, mut router) = test_router(&world).await;
+
+        let admin = service.create_user("neo".parse().unwrap()).await?;
+        service.set_super_user(&admin.name, true).await?;
+        let trinity = service.create_user("trinity".parse().unwrap()).await?;
+        let authorization = Authorization::bearer(admin.key.as_str()).unwrap();
+
+        for project in ["matrix", "reloaded", "revolutions"] {
+            service
+                .create_project(project.parse().unwrap(), trinity.name.clone())
+                .await?;
+        }
+        service
+            .update_project(
+                &"revolutions".parse().unwrap(),
+                &Project::Destroyed(crate::project::ProjectDestroyed::new(None)),
+            )
+            .await?;
+
+        service.counters().record_deployment();
+        service.counters().record_proxy_request();
+        service.counters().record_proxy_request();
+
+        let stats = |authorization: &Authorization<Bearer>| {
             Request::builder()
-                .method("POST")
-                .uri("/users/trinity")
+                .uri("/admin/stats")
                 .body(Body::empty())
                 .unwrap()
+                .with_header(authorization)
         };
 
-        router
-            .call(get_neo())
-            .map_ok(|resp| {
-                assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
-            })
+        let resp = router
+            .call(stats(&Authorization::bearer(trinity.key.as_str()).unwrap()))
             .await
             .unwrap();
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
 
-        let user = service.create_user("neo".parse().unwrap()).await?;
+        let resp = router.call(stats(&authorization)).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+        let stats: stats::PlatformResponse = serde_json::from_slice(&body).unwrap();
 
-        router
-            .call(get_neo())
-            .map_ok(|resp| {
-                assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
-            })
-            .await
-            .unwrap();
+        assert_eq!(stats.accounts, 2);
+        assert_eq!(stats.projects.get("creating"), Some(&2));
+        assert_eq!(stats.projects.get("destroyed"), Some(&1));
+        assert_eq!(stats.projects.len(), 2);
+        assert_eq!(stats.deployments_today, 1);
+        assert_eq!(stats.proxy_requests, 2);
 
-        let authorization = Authorization::bearer(user.key.as_str()).unwrap();
+        Ok(())
+    }
 
-        router
-            .call(get_neo().with_header(&authorization))
-            .map_ok(|resp| {
-                assert_eq!(resp.status(), StatusCode::FORBIDDEN);
-            })
-            .await
-            .unwrap();
+    #[tokio::test]
+    async fn api_db_pool_exhausted() -> anyhow::Result<()> {
+        let world = World::new().await;
+        let pool = sqlx::sqlite::SqlitePoolOptions::new()
+            .max_connections(1)
+            .acquire_timeout(Duration::from_millis(200))
+            .connect("sqlite::memory:")
+            .await?;
+        crate::service::MIGRATIONS.run(&pool).await?;
+        let service = Arc::new(GatewayService::init(world.args(), pool.clone()).await);
 
-        router
-            .call(post_trinity().with_header(&authorization))
-            .map_ok(|resp| assert_eq!(resp.status(), StatusCode::FORBIDDEN))
-            .await
-            .unwrap();
+        let mut router = router_for(Arc::clone(&service));
 
-        service.set_super_user(&user.name, true).await?;
+        let neo = service.create_user("neo".parse().unwrap()).await?;
+        let authorization = Authorization::bearer(neo.key.as_str()).unwrap();
+        let request = || {
+            Request::builder()
+                .uri("/projects")
+                .body(Body::empty())
+                .unwrap()
+                .with_header(&authorization)
+        };
 
-        router
-            .call(get_neo().with_header(&authorization))
-            .map_ok(|resp| {
-                assert_eq!(resp.status(), StatusCode::OK);
-            })
-            .await
-            .unwrap();
+        let resp = router.call(request()).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
 
-        router
-            .call(post_trinity().with_header(&authorization))
-            .map_ok(|resp| assert_eq!(resp.status(), StatusCode::OK))
-            .await
-            .unwrap();
+        // hold on to the only connection of the pool
+        let held = pool.acquire().await?;
 
-        router
-            .call(post_trinity().with_header(&authorization))
-            .map_ok(|resp| assert_eq!(resp.status(), StatusCode::BAD_REQUEST))
+        let resp = tokio::time::timeout(Duration::from_secs(5), router.call(request()))
             .await
+            .expect("request should fail fast instead of hanging")
             .unwrap();
+        assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(resp.headers().get(RETRY_AFTER).unwrap(), "1");
+
+        drop(held);
+
+        let resp = router.call(request()).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
 
         Ok(())
     }
 
-    #[tokio::test(flavor = "multi_thread")]
-    async fn status() {
+    #[tokio::test]
+    async fn api_certificate_status() -> anyhow::Result<()> {
         let world = World::new().await;
-        let service = Arc::new(GatewayService::init(world.args(), world.pool()).await);
+        let (service, mut router) = test_router(&world).await;
 
-        let (sender, mut receiver) = channel::<BoxedTask>(1);
-        let (ctl_send, ctl_recv) = oneshot::channel();
-        let (done_send, done_recv) = oneshot::channel();
-        let worker = tokio::spawn(async move {
-            let mut done_send = Some(done_send);
-            // do not process until instructed
-            ctl_recv.await.unwrap();
+        let admin = service.create_user("neo".parse().unwrap()).await?;
+        service.set_super_user(&admin.name, true).await?;
+        let trinity = service.create_user("trinity".parse().unwrap()).await?;
 
-            while receiver.recv().await.is_some() {
-                done_send.take().unwrap().send(()).unwrap();
-                // do nothing
-            }
-        });
+        let matrix: ProjectName = "matrix".parse().unwrap();
+        let zion: ProjectName = "zion".parse().unwrap();
+        for project_name in [&matrix, &zion] {
+            service
+                .create_project(project_name.clone(), trinity.name.clone())
+                .await?;
+        }
 
-        let mut router = ApiBuilder::new()
-            .with_service(Arc::clone(&service))
-            .with_sender(sender)
-            .with_default_routes()
-            .into_router();
+        let mut params = rcgen::CertificateParams::new(vec!["zion.org".to_string()]);
+        params.not_after = rcgen::date_time_ymd(2030, 1, 2);
+        params.distinguished_name = rcgen::DistinguishedName::new();
+        params
+            .distinguished_name
+            .push(rcgen::DnType::CommonName, "Zion CA");
+        let cert = rcgen::Certificate::from_params(params)?;
+        service
+            .create_custom_domain(
+                matrix.clone(),
+                &fqdn::fqdn!("zion.org"),
+                &cert.serialize_pem()?,
+                &cert.serialize_private_key_pem(),
+            )
+            .await?;
+        service
+            .record_certificate_attempt("zion.org", Some("ChallengeTimeout".to_string()))
+            .await?;
 
-        let get_status = || {
+        let get = |uri: &str, key: &str| {
             Request::builder()
-                .method("GET")
-                .uri("/")
+                .uri(uri)
                 .body(Body::empty())
                 .unwrap()
+                .with_header(&Authorization::bearer(key).unwrap())
         };
 
-        let resp = router.call(get_status()).await.unwrap();
+        // owners see the certificates of their custom domains
+        let resp = router
+            .call(get("/projects/matrix/cert", trinity.key.as_str()))
+            .await?;
         assert_eq!(resp.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(resp.into_body()).await?;
+        let certificates: Vec<project::CertificateDetails> = serde_json::from_slice(&body)?;
+        assert_eq!(certificates.len(), 1);
+        let certificate = &certificates[0];
+        assert_eq!(certificate.fqdn, "zion.org");
+        assert_eq!(certificate.issuer.as_deref(), Some("Zion CA"));
+        assert_eq!(
+            certificate.not_after.map(|at| at.to_rfc3339()),
+            Some("2030-01-02T00:00:00+00:00".to_string())
+        );
+        assert!(certificate.acme_managed);
+        assert!(certificate.last_renewal_attempt.is_some());
+        assert_eq!(
+            certificate.last_renewal_error.as_deref(),
+            Some("ChallengeTimeout")
+        );
 
-        let neo: AccountName = "neo".parse().unwrap();
-        let matrix: ProjectName = "matrix".parse().unwrap();
+        // and admins see them by domain
+        let resp = router
+            .call(get("/admin/certs?fqdn=zion.org", trinity.key.as_str()))
+            .await?;
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+        let resp = router
+            .call(get("/admin/certs?fqdn=ZION.org.", admin.key.as_str()))
+            .await?;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(resp.into_body()).await?;
+        let certificate: project::CertificateDetails = serde_json::from_slice(&body)?;
+        assert_eq!(certificate.fqdn, "zion.org");
+        assert_eq!(certificate.issuer.as_deref(), Some("Zion CA"));
 
-        let neo = service.create_user(neo).await.unwrap();
-        let authorization = Authorization::bearer(neo.key.as_str()).unwrap();
+        Ok(())
+    }
 
-        let create_project = Request::builder()
-            .method("POST")
-            .uri(format!("/projects/{matrix}"))
-            .body(Body::empty())
-            .unwrap()
-            .with_header(&authorization);
+    #[tokio::test]
+    async fn api_certificate_status_wildcard() -> anyhow::Result<()> {
+        let world = World::new().await;
+        let service = Arc::new(GatewayService::init(world.args(), world.pool()).await);
 
-        router.call(create_project).await.unwrap();
+        // with TLS on, as `main` sets it up before the default routes
+        let resolver = Arc::new(GatewayCertResolver::new());
+        let mut router = builder_for(Arc::clone(&service))
+            .with_acme(AcmeClient::new(), Arc::clone(&resolver))
+            .with_default_routes()
+            .into_router();
 
-        let resp = router.call(get_status()).await.unwrap();
-        assert_eq!(resp.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        let admin = service.create_user("neo".parse().unwrap()).await?;
+        service.set_super_user(&admin.name, true).await?;
 
-        ctl_send.send(()).unwrap();
-        done_recv.await.unwrap();
+        let wildcard = format!("*.{}", world.fqdn());
+        let cert = rcgen::generate_simple_self_signed(vec![wildcard.clone()])?;
+        let mut buf = Vec::new();
+        buf.extend(cert.serialize_pem()?.as_bytes());
+        buf.extend(cert.serialize_private_key_pem().as_bytes());
+        resolver.serve_pem(&wildcard, Cursor::new(buf)).await?;
 
-        let resp = router.call(get_status()).await.unwrap();
+        let resp = router
+            .call(
+                Request::builder()
+                    .uri(format!("/admin/certs?fqdn={wildcard}"))
+                    .body(Body::empty())
+                    .unwrap()
+                    .with_header(&Authorization::bearer(admin.key.as_str()).unwrap()),
+            )
+            .await?;
         assert_eq!(resp.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(resp.into_body()).await?;
+        let details: project::CertificateDetails = serde_json::from_slice(&body)?;
+        assert_eq!(details.fqdn, wildcard);
+        assert!(!details.acme_managed);
 
-        worker.abort();
-        let _ = worker.await;
+        Ok(())
+    }
 
-        let resp = router.call(get_status()).await.unwrap();
-        assert_eq!(resp.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    #[tokio::test]
+    async fn api_certificate_status_absent() -> anyhow::Result<()> {
+        let world = World::new().await;
+        let (service, mut router) = test_router(&world).await;
+
+        let admin = service.create_user("neo".parse().unwrap()).await?;
+        service.set_super_user(&admin.name, true).await?;
+        service
+            .create_project("matrix".parse().unwrap(), admin.name.clone())
+            .await?;
+
+        let get = |uri: &str| {
+            Request::builder()
+                .uri(uri)
+                .body(Body::empty())
+                .unwrap()
+                .with_header(&Authorization::bearer(admin.key.as_str()).unwrap())
+        };
+
+        // a project without custom domains has no certificates of its own
+        let resp = router.call(get("/projects/matrix/cert")).await?;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(resp.into_body()).await?;
+        let certificates: Vec<project::CertificateDetails> = serde_json::from_slice(&body)?;
+        assert!(certificates.is_empty());
+
+        // and domains without one are not found
+        for fqdn in ["nowhere.org", "*.nowhere.org"] {
+            let resp = router
+                .call(get(&format!("/admin/certs?fqdn={fqdn}")))
+                .await?;
+            assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+            let body = hyper::body::to_bytes(resp.into_body()).await?;
+            let error: ApiError = serde_json::from_slice(&body)?;
+            assert_eq!(error.code.as_deref(), Some("certificate_not_found"));
+        }
+
+        Ok(())
     }
 }
@@ -1,15 +1,32 @@
-use std::{net::SocketAddr, path::PathBuf};
+use std::{
+    net::SocketAddr,
+    path::{Path, PathBuf},
+};
 
 use clap::{Parser, Subcommand, ValueEnum};
 use fqdn::FQDN;
 
 use crate::auth::Key;
+use crate::service::is_valid_image_reference;
+use crate::{Error, ErrorKind};
 
 #[derive(Parser, Debug)]
 pub struct Args {
     /// Where to store gateway state (such as sqlite state, and certs)
     #[arg(long, default_value = "./")]
     pub state: PathBuf,
+    /// Most connections to open to the state database
+    #[arg(long, default_value = "10")]
+    pub db_max_connections: u32,
+    /// Seconds to wait for a connection to the state database before
+    /// turning requests away as unavailable
+    #[arg(long, default_value = "5")]
+    pub db_acquire_timeout: u64,
+    /// Which traces to emit at first, as tracing filter directives.
+    /// `RUST_LOG` takes precedence when set. Can be changed later
+    /// through `/admin/log-level`.
+    #[arg(long, default_value = "info")]
+    pub log_level: String,
 
     #[command(subcommand)]
     pub command: Commands,
@@ -21,30 +38,327 @@ pub enum UseTls {
     Enable,
 }
 
+/// Oldest version of TLS the user proxy accepts
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum TlsVersion {
+    #[value(name = "1.2")]
+    Tls12,
+    #[value(name = "1.3")]
+    Tls13,
+}
+
+/// What happens to a deploy to a project while another deploy of the
+/// same project is still in progress
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum DeployConcurrency {
+    /// Wait for the deploy in progress to be over
+    Queue,
+    /// Turn the deploy away
+    Reject,
+}
+
+/// What docker does when the container of a project exits
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ContainerRestart {
+    /// Leave it stopped, for the gateway to start again when needed
+    Never,
+    /// Restart it when it exits with an error, a limited number of times
+    OnFailure,
+    /// Restart it whenever it exits
+    Always,
+}
+
+/// What the gateway does, when it starts, with projects which were
+/// running before it went down
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum StartupPolicy {
+    /// Start them again if their container is not running anymore, for
+    /// a fast recovery
+    Restore,
+    /// Stop them, for them to be started again one at a time
+    Stopped,
+}
+
+/// How the user proxy hands the bodies of responses from projects on
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ProxyBodyMode {
+    /// Pass chunks on as they come, as needed by server-sent events
+    /// and large downloads
+    Streaming,
+    /// Read whole bodies first, up to a limit, to send them on with a
+    /// `Content-Length`
+    Buffered,
+}
+
+/// How the user proxy answers requests it cannot hand to a project,
+/// such as ones for hosts no project is served at
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ProxyErrorFormat {
+    Json,
+    /// A page meant to be seen in a browser
+    Html,
+}
+
 #[derive(Subcommand, Debug)]
 pub enum Commands {
     Start(StartArgs),
     Init(InitArgs),
+    /// Write a backup of the gateway state. The backup holds every API
+    /// key and custom domain private key in the clear, so it is only
+    /// readable by its owner.
+    Export(ExportArgs),
+    /// Restore a backup into a gateway which has no state yet
+    Import(ImportArgs),
 }
 
 #[derive(clap::Args, Debug, Clone)]
 pub struct StartArgs {
     /// Address to bind the control plane to
-    #[arg(long, default_value = "127.0.0.1:8001")]
+    #[arg(long, value_parser = parse_socket_addr, default_value = "127.0.0.1:8001")]
     pub control: SocketAddr,
     /// Address to bind the bouncer service to
-    #[arg(long, default_value = "127.0.0.1:7999")]
+    #[arg(long, value_parser = parse_socket_addr, default_value = "127.0.0.1:7999")]
     pub bouncer: SocketAddr,
     /// Address to bind the user proxy to
-    #[arg(long, default_value = "127.0.0.1:8000")]
+    #[arg(long, value_parser = parse_socket_addr, default_value = "127.0.0.1:8000")]
     pub user: SocketAddr,
     /// Allows to disable the use of TLS in the user proxy service (DANGEROUS)
     #[arg(long, default_value = "enable")]
     pub use_tls: UseTls,
+    /// Oldest version of TLS the user proxy accepts, 1.2 if not set
+    #[arg(long)]
+    pub tls_min_version: Option<TlsVersion>,
+    /// Pick the cipher suite by the preferences of the user proxy
+    /// rather than by those of the client
+    #[arg(long)]
+    pub tls_prefer_server_ciphers: bool,
+    /// Maximum number of seconds the user proxy waits to establish a
+    /// connection with a project
+    #[arg(long, default_value = "5")]
+    pub upstream_connect_timeout: u64,
+    /// Maximum number of seconds the user proxy waits for a project
+    /// to respond to a request
+    #[arg(long, default_value = "60")]
+    pub upstream_timeout: u64,
+    /// How many more times the user proxy tries to send an idempotent
+    /// request (GET, HEAD, PUT or DELETE) to a project which failed to
+    /// take it, within the upstream timeout
+    #[arg(long, default_value = "2")]
+    pub upstream_retries: u32,
+    /// Maximum number of bytes, names and values included, the headers
+    /// of a request to the user proxy can add up to
+    #[arg(long, default_value = "32768")]
+    pub max_header_size: usize,
+    /// Maximum number of headers a request to the user proxy can have.
+    /// Requests with over 100 headers are turned away regardless.
+    #[arg(long, default_value = "100")]
+    pub max_headers: usize,
+    /// Maximum number of seconds the user proxy holds a request to a
+    /// project which is still starting, in case it becomes ready
+    #[arg(long, default_value = "0")]
+    pub provisioning_hold: u64,
+    /// Maximum number of seconds the user proxy holds a request to a
+    /// stopped project it woke up, in case it becomes ready
+    #[arg(long, default_value = "30")]
+    pub cold_start_hold: u64,
+    /// Number of seconds a connection of a client to the user proxy
+    /// is idle before TCP keepalive probes are sent on it, or 0 to
+    /// send none
+    #[arg(long, default_value = "60")]
+    pub client_tcp_keepalive: u64,
+    /// Maximum number of seconds a client has to get its connection to
+    /// the user proxy accepted, TLS handshake included, or 0 for no
+    /// limit
+    #[arg(long, default_value = "10")]
+    pub client_accept_timeout: u64,
+    /// Number of seconds after which the user proxy closes a connection
+    /// of a client on which nothing was sent either way, or 0 to keep
+    /// it open. Waiting on a project counts, so it should be longer
+    /// than the upstream timeout.
+    #[arg(long, default_value = "0")]
+    pub client_idle_timeout: u64,
+    /// How the user proxy passes the bodies of responses on
+    #[arg(long, default_value = "streaming")]
+    pub proxy_body_mode: ProxyBodyMode,
+    /// How the user proxy answers requests for unknown hosts and for
+    /// projects which are not running
+    #[arg(long, default_value = "json")]
+    pub proxy_error_format: ProxyErrorFormat,
+    /// Path to an HTML page the user proxy answers its own `4xx`
+    /// errors with, in which `{{status}}`, `{{reason}}` and
+    /// `{{message}}` are filled in
+    #[arg(long)]
+    pub proxy_error_page_4xx: Option<PathBuf>,
+    /// Path to an HTML page the user proxy answers its own `5xx`
+    /// errors with. Errors of projects themselves are passed on as
+    /// they are.
+    #[arg(long)]
+    pub proxy_error_page_5xx: Option<PathBuf>,
+    /// Number of seconds the user proxy keeps an unused connection
+    /// to a project open
+    #[arg(long, default_value = "90")]
+    pub upstream_pool_idle_timeout: u64,
+    /// Maximum number of unused connections the user proxy keeps
+    /// open to a single project
+    #[arg(long, default_value = "32")]
+    pub upstream_pool_max_idle: usize,
+    /// Number of seconds between two passes reconciling the stored
+    /// state of projects with their containers
+    #[arg(long, default_value = "300")]
+    pub reconcile_interval: u64,
+    /// Number of seconds the container of a crashed project is kept
+    /// around, stopped, for its logs to be fetched before it is
+    /// removed. 0 keeps it for good.
+    #[arg(long, default_value = "86400")]
+    pub crashed_retention: u64,
+    /// What to do with projects which were running when the gateway
+    /// went down
+    #[arg(long, default_value = "restore")]
+    pub startup_policy: StartupPolicy,
+    /// What to do with a deploy to a project while another one is
+    /// still in progress
+    #[arg(long, default_value = "queue")]
+    pub deploy_concurrency: DeployConcurrency,
+    /// Start in maintenance mode, rejecting the creation of projects
+    /// and new deployments until it is turned off
+    #[arg(long)]
+    pub maintenance: bool,
+    /// Path to a PEM encoded public key to verify JWTs presented
+    /// instead of API keys with
+    #[arg(
+        long,
+        conflicts_with = "jwks_url",
+        requires_all = ["jwt_issuer", "jwt_audience"]
+    )]
+    pub jwt_public_key: Option<PathBuf>,
+    /// URL of the JWKS to verify JWTs presented instead of API keys
+    /// with
+    #[arg(long, requires_all = ["jwt_issuer", "jwt_audience"])]
+    pub jwks_url: Option<String>,
+    /// Let project webhooks point at loopback, private and other
+    /// addresses which are not public. Only for gateways whose
+    /// projects are trusted, as webhooks are sent from inside its
+    /// network
+    #[arg(long)]
+    pub allow_private_webhooks: bool,
+    /// The `iss` JWTs have to carry to be accepted
+    #[arg(long)]
+    pub jwt_issuer: Option<String>,
+    /// The `aud` JWTs have to carry to be accepted, so that tokens the
+    /// identity provider issued for other services are not
+    #[arg(long)]
+    pub jwt_audience: Option<String>,
+    /// Claim of a JWT holding the name of the account it was issued
+    /// for
+    #[arg(long, default_value = "sub")]
+    pub jwt_account_claim: String,
+    /// URL of an HTTP hook placing the DNS records of ACME dns-01
+    /// challenges. Without one, records have to be placed by hand
+    #[arg(long)]
+    pub dns_hook_url: Option<String>,
+    /// Bearer token to authenticate with the DNS hook
+    #[arg(long, requires = "dns_hook_url")]
+    pub dns_hook_token: Option<String>,
     #[command(flatten)]
     pub context: ContextArgs,
 }
 
+impl StartArgs {
+    /// Check the arguments make sense together, to refuse to start
+    /// rather than serve with a broken configuration. Certificates are
+    /// looked for under `state`. All the problems found are reported
+    /// at once.
+    pub fn validate(&self, state: &Path) -> Result<(), Error> {
+        let mut problems = Vec::new();
+
+        let addresses = [
+            ("control", self.control),
+            ("bouncer", self.bouncer),
+            ("user", self.user),
+        ];
+        for (i, (name, address)) in addresses.iter().enumerate() {
+            for (other_name, other_address) in &addresses[i + 1..] {
+                if address == other_address {
+                    problems.push(format!(
+                        "the {name} and {other_name} services cannot both bind to {address}"
+                    ));
+                }
+            }
+        }
+
+        if let UseTls::Enable = self.use_tls {
+            let certificate = state.join("ssl.pem");
+            let stored = state
+                .join("certs")
+                .join(format!("*.{}.pem", self.context.proxy_fqdn));
+            let credentials = state.join("acme.json");
+            if !certificate.exists() && !stored.exists() && !credentials.exists() {
+                problems.push(format!(
+                    "TLS is enabled but there is no certificate at {} nor ACME credentials at {} to create one with",
+                    certificate.display(),
+                    credentials.display()
+                ));
+            }
+        }
+
+        if let UseTls::Disable = self.use_tls {
+            if self.tls_min_version.is_some() || self.tls_prefer_server_ciphers {
+                problems.push(
+                    "TLS is disabled so --tls-min-version and --tls-prefer-server-ciphers cannot be used"
+                        .to_string(),
+                );
+            }
+        }
+
+        if !is_valid_image_reference(&self.context.image) {
+            problems.push(format!(
+                "`{}` is not a valid image reference",
+                self.context.image
+            ));
+        }
+
+        if self.context.proxy_fqdn.depth() == 0 {
+            problems.push("the proxy FQDN cannot be the root domain".to_string());
+        }
+        if self
+            .context
+            .additional_proxy_fqdns
+            .contains(&self.context.proxy_fqdn)
+        {
+            problems.push(format!(
+                "{} is both the proxy FQDN and an additional one",
+                self.context.proxy_fqdn
+            ));
+        }
+
+        if self.context.max_running_projects == Some(0) {
+            problems.push("the node has to be able to run at least one project".to_string());
+        }
+
+        for (name, value) in [
+            ("upstream-connect-timeout", self.upstream_connect_timeout),
+            ("upstream-timeout", self.upstream_timeout),
+            ("reconcile-interval", self.reconcile_interval),
+            ("max-header-size", self.max_header_size as u64),
+            ("max-headers", self.max_headers as u64),
+            ("warm-pool-ttl", self.context.warm_pool_ttl),
+        ] {
+            if value == 0 {
+                problems.push(format!("--{name} has to be more than 0"));
+            }
+        }
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::custom(
+                ErrorKind::Internal,
+                format!("invalid configuration: {}", problems.join("; ")),
+            ))
+        }
+    }
+}
+
 #[derive(clap::Args, Debug, Clone)]
 pub struct InitArgs {
     /// Name of initial account to create
@@ -55,6 +369,59 @@ pub struct InitArgs {
     pub key: Option<Key>,
 }
 
+#[derive(clap::Args, Debug, Clone)]
+pub struct ExportArgs {
+    /// File to write the backup to
+    #[arg(long)]
+    pub output: PathBuf,
+}
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct ImportArgs {
+    /// File to read the backup from
+    #[arg(long)]
+    pub input: PathBuf,
+}
+
+/// Parse the address a service binds to, saying what is expected of it
+/// rather than only that it is invalid
+fn parse_socket_addr(value: &str) -> Result<SocketAddr, String> {
+    value.parse().map_err(|_| {
+        let port = value.rsplit_once(':').map(|(_, port)| port);
+        let hint = if port.and_then(|port| port.parse::<u16>().ok()).is_none() {
+            "it is missing a port"
+        } else {
+            "host names are not resolved, use an IP address (IPv6 ones in brackets)"
+        };
+        format!(
+            "`{value}` is not a socket address ({hint}). \
+             Expected an IP address and a port, such as `127.0.0.1:8000` or `[::1]:8000`"
+        )
+    })
+}
+
+/// Parse a domain projects are served under, saying what is expected
+/// of it rather than only that it is invalid
+fn parse_fqdn(value: &str) -> Result<FQDN, String> {
+    let hint = if value.contains("://") {
+        Some("without a scheme")
+    } else if value.contains(['/', ':']) {
+        Some("without a port or path")
+    } else {
+        None
+    };
+
+    match (hint, value.parse::<FQDN>()) {
+        (None, Ok(fqdn)) => Ok(fqdn),
+        (hint, _) => Err(format!(
+            "`{value}` is not a fully qualified domain name{}. \
+             Expected labels of letters, digits and hyphens separated by dots, such as `shuttleapp.rs`",
+            hint.map(|hint| format!(" (give the domain {hint})"))
+                .unwrap_or_default()
+        )),
+    }
+}
+
 #[derive(clap::Args, Debug, Clone)]
 pub struct ContextArgs {
     /// Default image to deploy user runtimes into
@@ -71,10 +438,146 @@ pub struct ContextArgs {
     /// The Docker Network name in which to deploy user runtimes
     #[arg(long, default_value = "shuttle_default")]
     pub network_name: String,
+    /// Create the Docker network if it does not exist yet, instead of
+    /// refusing to start
+    #[arg(long)]
+    pub create_network: bool,
     /// FQDN where the proxy can be reached at
-    #[arg(long, default_value = "shuttleapp.rs")]
+    #[arg(long, value_parser = parse_fqdn, default_value = "shuttleapp.rs")]
     pub proxy_fqdn: FQDN,
+    /// Other FQDNs the proxy resolves projects from, such as the one
+    /// of a staging environment. Projects are only ever told about
+    /// `proxy_fqdn`
+    #[arg(long = "additional-proxy-fqdn", value_parser = parse_fqdn)]
+    pub additional_proxy_fqdns: Vec<FQDN>,
+    /// How many project containers this node runs at most. Projects
+    /// being created past it wait for a slot to free up
+    #[arg(long)]
+    pub max_running_projects: Option<usize>,
+    /// What docker does when the container of a project exits
+    #[arg(long, default_value = "never")]
+    pub restart_policy: ContainerRestart,
+    /// How many times docker restarts a container which keeps failing
+    /// under the `on-failure` policy
+    #[arg(long, default_value = "3")]
+    pub restart_max_retries: i64,
+    /// How many seconds the container of a project is given to shut
+    /// down once asked to stop, before it is killed
+    #[arg(long, default_value = "30")]
+    pub stop_timeout_secs: i64,
+    /// How many containers are kept started ahead of time for projects
+    /// being created to claim, 0 to keep none
+    #[arg(long, default_value = "0")]
+    pub warm_pool_size: usize,
+    /// Seconds a warm container is kept unclaimed before it is replaced
+    #[arg(long, default_value = "3600")]
+    pub warm_pool_ttl: u64,
     /// The path to the docker daemon socket
     #[arg(long, default_value = "/var/run/docker.sock")]
     pub docker_host: String,
 }
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    fn start_args(args: &[&str]) -> StartArgs {
+        let args = ["gateway", "start"].iter().chain(args);
+        match Args::try_parse_from(args).unwrap().command {
+            Commands::Start(start_args) => start_args,
+            _ => unreachable!(),
+        }
+    }
+
+    fn start_args_error(args: &[&str]) -> String {
+        let args = ["gateway", "start"].iter().chain(args);
+        Args::try_parse_from(args).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn malformed_start_args() {
+        let err = start_args_error(&["--user", "localhost:8000"]);
+        assert!(err.contains("--user"), "{err}");
+        assert!(
+            err.contains("`localhost:8000` is not a socket address"),
+            "{err}"
+        );
+        assert!(err.contains("host names are not resolved"), "{err}");
+        assert!(err.contains("such as `127.0.0.1:8000`"), "{err}");
+
+        let err = start_args_error(&["--control", "127.0.0.1"]);
+        assert!(err.contains("--control"), "{err}");
+        assert!(err.contains("it is missing a port"), "{err}");
+
+        let err = start_args_error(&["--proxy-fqdn", "https://shuttleapp.rs"]);
+        assert!(err.contains("--proxy-fqdn"), "{err}");
+        assert!(
+            err.contains("`https://shuttleapp.rs` is not a fully qualified domain name"),
+            "{err}"
+        );
+        assert!(err.contains("without a scheme"), "{err}");
+        assert!(err.contains("such as `shuttleapp.rs`"), "{err}");
+
+        let err = start_args_error(&["--additional-proxy-fqdn", "not a domain"]);
+        assert!(err.contains("--additional-proxy-fqdn"), "{err}");
+        assert!(err.contains("letters, digits and hyphens"), "{err}");
+
+        let args = start_args(&[
+            "--user",
+            "[::1]:8000",
+            "--proxy-fqdn",
+            "shuttle.dev",
+            "--additional-proxy-fqdn",
+            "staging.shuttle.dev",
+        ]);
+        assert_eq!(args.user, "[::1]:8000".parse().unwrap());
+        assert_eq!(args.context.proxy_fqdn, fqdn::fqdn!("shuttle.dev"));
+    }
+
+    #[test]
+    fn validate_start_args() {
+        let state = tempfile::tempdir().unwrap();
+
+        start_args(&["--use-tls", "disable"])
+            .validate(state.path())
+            .unwrap();
+
+        // TLS needs a certificate or a way to get one
+        let err = start_args(&[]).validate(state.path()).unwrap_err();
+        assert!(err.to_string().contains("TLS is enabled"), "{err}");
+
+        std::fs::write(state.path().join("acme.json"), "{}").unwrap();
+        start_args(&[]).validate(state.path()).unwrap();
+
+        let err = start_args(&[
+            "--use-tls",
+            "disable",
+            "--user",
+            "127.0.0.1:8001",
+            "--reconcile-interval",
+            "0",
+            "--image",
+            "Not An Image",
+        ])
+        .validate(state.path())
+        .unwrap_err()
+        .to_string();
+        assert!(
+            err.contains("the control and user services cannot both bind to 127.0.0.1:8001"),
+            "{err}"
+        );
+        assert!(err.contains("--reconcile-interval"), "{err}");
+        assert!(
+            err.contains("`Not An Image` is not a valid image reference"),
+            "{err}"
+        );
+
+        let err = start_args(&["--use-tls", "disable", "--tls-min-version", "1.3"])
+            .validate(state.path())
+            .unwrap_err();
+        assert!(err.to_string().contains("TLS is disabled"), "{err}");
+        start_args(&["--tls-min-version", "1.3", "--tls-prefer-server-ciphers"])
+            .validate(state.path())
+            .unwrap();
+    }
+}
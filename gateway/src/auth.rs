@@ -5,11 +5,14 @@ use axum::extract::{FromRef, FromRequestParts, Path, TypedHeader};
 use axum::headers::authorization::Bearer;
 use axum::headers::Authorization;
 use axum::http::request::Parts;
+use axum::http::Method;
 use rand::distributions::{Alphanumeric, DistString};
 use serde::{Deserialize, Serialize};
-use tracing::{trace, Span};
+use shuttle_common::models::user::Action;
+use tracing::{debug, trace, Span};
 
 use crate::api::latest::RouterState;
+use crate::jwt;
 use crate::service::GatewayService;
 use crate::{AccountName, Error, ErrorKind, ProjectName};
 
@@ -75,6 +78,12 @@ pub struct User {
     pub key: Key,
     pub projects: Vec<ProjectName>,
     pub permissions: Permissions,
+    /// Set when the user authenticated with a scoped key
+    pub scope: Option<KeyScope>,
+    /// The super user acting as this user, when authenticated with an
+    /// impersonation token
+    #[serde(default)]
+    pub impersonated_by: Option<AccountName>,
 }
 
 impl User {
@@ -82,12 +91,43 @@ impl User {
         self.permissions.is_super_user()
     }
 
+    pub fn is_auditor(&self) -> bool {
+        self.permissions.is_auditor()
+    }
+
     pub fn new_with_defaults(name: AccountName, key: Key) -> Self {
         Self {
             name,
             key,
             projects: Vec::new(),
             permissions: Permissions::default(),
+            scope: None,
+            impersonated_by: None,
+        }
+    }
+
+    /// Fail with [`ErrorKind::Forbidden`] if the key the user
+    /// authenticated with does not allow `action`. Auditors are only
+    /// ever allowed to read.
+    pub fn ensure_action_allowed(&self, action: Action) -> Result<(), Error> {
+        if self.is_auditor() && action != Action::Read {
+            return Err(Error::from(ErrorKind::Forbidden));
+        }
+
+        match &self.scope {
+            Some(scope) if !scope.allows_action(action) => Err(Error::from(ErrorKind::Forbidden)),
+            _ => Ok(()),
+        }
+    }
+
+    /// Fail with [`ErrorKind::Forbidden`] if the key the user
+    /// authenticated with does not allow `action` on `project_name`
+    pub fn ensure_allowed(&self, project_name: &ProjectName, action: Action) -> Result<(), Error> {
+        match &self.scope {
+            Some(scope) if !scope.allows_project(project_name) => {
+                Err(Error::from(ErrorKind::Forbidden))
+            }
+            _ => self.ensure_action_allowed(action),
         }
     }
 
@@ -103,25 +143,87 @@ impl User {
             key,
             projects,
             permissions,
+            scope: None,
+            impersonated_by: None,
         })
     }
 
     pub async fn retrieve_from_key(svc: &GatewayService, key: Key) -> Result<User, Error> {
-        let name = svc.account_name_from_key(&key).await?;
-        trace!(%name, "got account name from key");
+        let (name, scope, impersonated_by) = match svc.account_name_from_key(&key).await {
+            Ok(name) => (name, None, None),
+            Err(err) if err.kind() == ErrorKind::UserNotFound => {
+                match svc.find_scoped_key(&key).await {
+                    Ok((name, scope)) => (name, Some(scope), None),
+                    Err(err) if err.kind() == ErrorKind::UserNotFound => {
+                        let impersonation = svc.find_impersonation(&key).await?;
+                        (
+                            impersonation.account_name,
+                            Some(impersonation.scope),
+                            Some(impersonation.actor),
+                        )
+                    }
+                    Err(err) => return Err(err),
+                }
+            }
+            Err(err) => return Err(err),
+        };
+        trace!(%name, scoped = scope.is_some(), impersonated = impersonated_by.is_some(), "got account name from key");
+
+        let mut permissions = svc.get_permissions(&name).await?;
+        if impersonated_by.is_some() {
+            // The view of the user, without any rights over others
+            permissions = Permissions::builder().tier(*permissions.tier()).build();
+        } else {
+            svc.touch_key(&key, scope.is_some()).await?;
+        }
 
-        let permissions = svc.get_permissions(&name).await?;
         let projects = svc.iter_user_projects(&name).await?.collect();
         Ok(User {
             name,
             key,
             projects,
             permissions,
+            scope,
+            impersonated_by,
         })
     }
 }
 
-#[derive(Clone, Copy, Deserialize, PartialEq, Eq, Serialize, Debug, sqlx::Type)]
+/// A super user acting as `account_name` with a short-lived token,
+/// restricted to `scope`
+pub struct Impersonation {
+    pub actor: AccountName,
+    pub account_name: AccountName,
+    pub scope: KeyScope,
+}
+
+/// What a scoped key is restricted to. `None` leaves it unrestricted
+/// on that front.
+#[derive(Clone, Default, Deserialize, PartialEq, Eq, Serialize, Debug)]
+pub struct KeyScope {
+    pub projects: Option<Vec<ProjectName>>,
+    pub actions: Option<Vec<Action>>,
+}
+
+impl KeyScope {
+    pub fn allows_project(&self, project_name: &ProjectName) -> bool {
+        self.projects
+            .as_ref()
+            .map_or(true, |projects| projects.contains(project_name))
+    }
+
+    pub fn allows_action(&self, action: Action) -> bool {
+        self.actions
+            .as_ref()
+            .map_or(true, |actions| actions.contains(&action))
+    }
+}
+
+/// Tiers are in increasing order of priority: when the node is full, the
+/// projects of higher tiers make lower ones give up their slot
+#[derive(
+    Clone, Copy, Deserialize, PartialEq, Eq, PartialOrd, Ord, Serialize, Debug, sqlx::Type,
+)]
 #[sqlx(rename_all = "lowercase")]
 pub enum AccountTier {
     Basic,
@@ -133,6 +235,7 @@ pub enum AccountTier {
 pub struct PermissionsBuilder {
     tier: Option<AccountTier>,
     super_user: Option<bool>,
+    auditor: Option<bool>,
 }
 
 impl PermissionsBuilder {
@@ -141,6 +244,11 @@ impl PermissionsBuilder {
         self
     }
 
+    pub fn auditor(mut self, is_auditor: bool) -> Self {
+        self.auditor = Some(is_auditor);
+        self
+    }
+
     pub fn tier(mut self, tier: AccountTier) -> Self {
         self.tier = Some(tier);
         self
@@ -150,6 +258,7 @@ impl PermissionsBuilder {
         Permissions {
             tier: self.tier.unwrap_or(AccountTier::Basic),
             super_user: self.super_user.unwrap_or_default(),
+            auditor: self.auditor.unwrap_or_default(),
         }
     }
 }
@@ -158,6 +267,9 @@ impl PermissionsBuilder {
 pub struct Permissions {
     pub tier: AccountTier,
     pub super_user: bool,
+    /// Can read every project, but not change any of them
+    #[serde(default)]
+    pub auditor: bool,
 }
 
 impl Default for Permissions {
@@ -165,6 +277,7 @@ impl Default for Permissions {
         Self {
             tier: AccountTier::Basic,
             super_user: false,
+            auditor: false,
         }
     }
 }
@@ -181,6 +294,10 @@ impl Permissions {
     pub fn is_super_user(&self) -> bool {
         self.super_user
     }
+
+    pub fn is_auditor(&self) -> bool {
+        self.auditor
+    }
 }
 
 #[async_trait]
@@ -196,14 +313,41 @@ where
 
         let RouterState { service, .. } = RouterState::from_ref(state);
 
-        let user = User::retrieve_from_key(&service, key)
-            .await
-            // Absord any error into `Unauthorized`
-            .map_err(|e| Error::source(ErrorKind::Unauthorized, e))?;
+        // Bearer tokens are tried as JWTs first, and looked up as API
+        // keys if they are not a valid one
+        let from_jwt = match service.jwt_verifier() {
+            Some(verifier) if jwt::is_jwt(key.as_str()) => {
+                match verifier.verify(key.as_str()).await {
+                    Ok(name) => Some(User::retrieve_from_account_name(&service, name).await),
+                    Err(err) => {
+                        debug!(error = %err, "bearer token is not a valid JWT");
+                        None
+                    }
+                }
+            }
+            _ => None,
+        };
+
+        let user = match from_jwt {
+            Some(user) => user,
+            None => User::retrieve_from_key(&service, key).await,
+        }
+        // Absord any error into `Unauthorized`, except for the database
+        // being too busy which the client should retry
+        .map_err(|e| match e.kind() {
+            ErrorKind::ServiceUnavailable => e,
+            _ => Error::source(ErrorKind::Unauthorized, e),
+        })?;
 
         // Record current account name for tracing purposes
         Span::current().record("account.name", &user.name.to_string());
 
+        // Everything done as someone else is accounted for
+        if let Some(actor) = &user.impersonated_by {
+            let action = format!("{} {}", parts.method, parts.uri.path());
+            service.record_audit(actor, &user.name, &action).await?;
+        }
+
         Ok(user)
     }
 }
@@ -251,10 +395,15 @@ where
                 .unwrap(),
         };
 
-        if user.is_super_user() || user.projects.contains(&scope) {
-            Ok(Self { user, scope })
-        } else {
-            Err(Error::from(ErrorKind::ProjectNotFound))
+        if !user.is_super_user() && !user.is_auditor() && !user.projects.contains(&scope) {
+            return Err(Error::from(ErrorKind::ProjectNotFound));
+        }
+
+        match &user.scope {
+            Some(key_scope) if !key_scope.allows_project(&scope) => {
+                Err(Error::from(ErrorKind::Forbidden))
+            }
+            _ => Ok(Self { user, scope }),
         }
     }
 }
@@ -274,7 +423,14 @@ where
     async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
         let user = User::from_request_parts(parts, state).await?;
 
-        if user.is_super_user() {
+        // Scoped keys never grant admin rights
+        if user.scope.is_some() {
+            return Err(Error::from(ErrorKind::Forbidden));
+        }
+
+        // Auditors can look at the admin routes, but not act on them
+        let is_read = parts.method == Method::GET || parts.method == Method::HEAD;
+        if user.is_super_user() || (user.is_auditor() && is_read) {
             Ok(Self { user })
         } else {
             Err(Error::from(ErrorKind::Forbidden))
@@ -0,0 +1,317 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use shuttle_common::models::error::ErrorKind;
+use sqlx::sqlite::SqliteConnection;
+use sqlx::{query, Column, Row, SqlitePool, TypeInfo, ValueRef};
+
+use crate::Error;
+
+/// Version of the [`Backup`] format itself, as opposed to the schema
+/// of the database it holds
+pub const BACKUP_VERSION: u32 = 1;
+
+/// Every row of every table of the gateway state. Blobs are base64
+/// encoded.
+///
+/// Env var values, Git tokens and webhook secrets stay encrypted, so
+/// an instance restored from a backup needs the `env.key` of the one
+/// it was taken from. Everything else is in the clear, including the
+/// API keys of every account and the private keys of custom domains,
+/// so a backup is as sensitive as the instance itself.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Backup {
+    pub version: u32,
+    /// Latest migration applied to the database the backup was taken
+    /// from
+    pub schema: i64,
+    pub created_at: String,
+    pub tables: BTreeMap<String, Vec<BTreeMap<String, Value>>>,
+}
+
+fn invalid<S: AsRef<str>>(message: S) -> Error {
+    Error::custom(ErrorKind::InvalidBackup, message)
+}
+
+async fn schema_version(conn: &mut SqliteConnection) -> Result<i64, Error> {
+    let version = query("SELECT MAX(version) AS version FROM _sqlx_migrations")
+        .fetch_one(conn)
+        .await?
+        .get("version");
+
+    Ok(version)
+}
+
+async fn table_names(conn: &mut SqliteConnection) -> Result<BTreeSet<String>, Error> {
+    let names = query(
+        "SELECT name FROM sqlite_master WHERE type = 'table' AND name NOT LIKE 'sqlite_%' AND name != '_sqlx_migrations'",
+    )
+    .fetch_all(conn)
+    .await?
+    .into_iter()
+    .map(|row| row.get("name"))
+    .collect();
+
+    Ok(names)
+}
+
+/// The columns of `table` along with their declared type
+async fn table_columns(
+    conn: &mut SqliteConnection,
+    table: &str,
+) -> Result<Vec<(String, String)>, Error> {
+    let columns = query(&format!("PRAGMA table_info(\"{table}\")"))
+        .fetch_all(conn)
+        .await?
+        .into_iter()
+        .map(|row| (row.get("name"), row.get::<String, _>("type").to_uppercase()))
+        .collect();
+
+    Ok(columns)
+}
+
+fn column_value(row: &sqlx::sqlite::SqliteRow, index: usize) -> Result<Value, Error> {
+    let raw = row.try_get_raw(index)?;
+    if raw.is_null() {
+        return Ok(Value::Null);
+    }
+
+    let value = match raw.type_info().name() {
+        "INTEGER" => Value::from(row.try_get::<i64, _>(index)?),
+        "REAL" => Value::from(row.try_get::<f64, _>(index)?),
+        "BLOB" => Value::from(base64::encode(row.try_get::<Vec<u8>, _>(index)?)),
+        _ => Value::from(row.try_get::<String, _>(index)?),
+    };
+
+    Ok(value)
+}
+
+/// Take a [`Backup`] of everything in `pool`. Every table is read in
+/// the same transaction, so that writes made meanwhile cannot leave
+/// the backup with dangling references.
+pub async fn export(pool: &SqlitePool) -> Result<Backup, Error> {
+    let mut transaction = pool.begin().await?;
+
+    let mut tables = BTreeMap::new();
+    for table in table_names(&mut transaction).await? {
+        let rows = query(&format!("SELECT * FROM \"{table}\""))
+            .fetch_all(&mut transaction)
+            .await?
+            .iter()
+            .map(|row| {
+                row.columns()
+                    .iter()
+                    .map(|column| {
+                        let value = column_value(row, column.ordinal())?;
+                        Ok::<_, Error>((column.name().to_string(), value))
+                    })
+                    .collect::<Result<_, Error>>()
+            })
+            .collect::<Result<_, Error>>()?;
+
+        tables.insert(table, rows);
+    }
+
+    Ok(Backup {
+        version: BACKUP_VERSION,
+        schema: schema_version(&mut transaction).await?,
+        created_at: Utc::now().to_rfc3339(),
+        tables,
+    })
+}
+
+/// Restore `backup` into `pool`, which has to be empty. The backup is
+/// refused as a whole unless it was taken at the same schema and holds
+/// every table with all their columns.
+pub async fn import(pool: &SqlitePool, backup: &Backup) -> Result<(), Error> {
+    if backup.version != BACKUP_VERSION {
+        return Err(invalid(format!(
+            "backup format version {} is not supported, only {BACKUP_VERSION} is",
+            backup.version
+        )));
+    }
+
+    let mut transaction = pool.begin().await?;
+
+    let schema = schema_version(&mut transaction).await?;
+    if backup.schema != schema {
+        return Err(invalid(format!(
+            "backup was taken at schema version {} but this instance is at {schema}",
+            backup.schema
+        )));
+    }
+
+    let tables = table_names(&mut transaction).await?;
+    let backed_up: BTreeSet<_> = backup.tables.keys().cloned().collect();
+    if backed_up != tables {
+        let missing: Vec<_> = tables.difference(&backed_up).collect();
+        let unknown: Vec<_> = backed_up.difference(&tables).collect();
+        return Err(invalid(format!(
+            "backup does not match the schema, missing tables: {missing:?}, unknown tables: {unknown:?}"
+        )));
+    }
+
+    let mut columns = BTreeMap::new();
+    for table in &tables {
+        let count: i64 = query(&format!("SELECT COUNT(*) AS count FROM \"{table}\""))
+            .fetch_one(&mut transaction)
+            .await?
+            .get("count");
+        if count > 0 {
+            return Err(invalid(format!(
+                "can only restore into an empty instance, but `{table}` has {count} rows"
+            )));
+        }
+
+        columns.insert(table, table_columns(&mut transaction, table).await?);
+    }
+
+    // rows are inserted table by table, so references are only checked
+    // once all of them are in
+    query("PRAGMA defer_foreign_keys = ON")
+        .execute(&mut transaction)
+        .await?;
+
+    for (table, rows) in &backup.tables {
+        let columns = &columns[table];
+        let names: BTreeSet<_> = columns.iter().map(|(name, _)| name.as_str()).collect();
+        let sql = format!(
+            "INSERT INTO \"{table}\" ({}) VALUES ({})",
+            columns
+                .iter()
+                .map(|(name, _)| format!("\"{name}\""))
+                .collect::<Vec<_>>()
+                .join(", "),
+            vec!["?"; columns.len()].join(", ")
+        );
+
+        for row in rows {
+            if row.keys().map(String::as_str).collect::<BTreeSet<_>>() != names {
+                return Err(invalid(format!(
+                    "a row of `{table}` does not have exactly the columns {names:?}"
+                )));
+            }
+
+            let mut insert = query(&sql);
+            for (name, kind) in columns {
+                insert = match (&row[name], kind.as_str()) {
+                    (Value::Null, _) => insert.bind(None::<String>),
+                    (Value::Bool(value), _) => insert.bind(*value),
+                    (Value::Number(value), _) if value.is_i64() => insert.bind(value.as_i64()),
+                    (Value::Number(value), _) => insert.bind(value.as_f64()),
+                    (Value::String(value), "BLOB") => insert.bind(
+                        base64::decode(value)
+                            .map_err(|_| invalid(format!("`{table}.{name}` is not base64")))?,
+                    ),
+                    (Value::String(value), _) => insert.bind(value.clone()),
+                    (value, _) => {
+                        return Err(invalid(format!("`{table}.{name}` cannot hold {value}")))
+                    }
+                };
+            }
+
+            insert.execute(&mut transaction).await?;
+        }
+    }
+
+    transaction
+        .commit()
+        .await
+        .map_err(|err| invalid(format!("backup is inconsistent: {err}")))?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+pub mod tests {
+    use sqlx::sqlite::SqlitePoolOptions;
+
+    use super::*;
+    use crate::service::MIGRATIONS;
+
+    async fn fresh_pool() -> SqlitePool {
+        let pool = SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+        MIGRATIONS.run(&pool).await.unwrap();
+        pool
+    }
+
+    #[tokio::test]
+    async fn backup_round_trip() {
+        let pool = fresh_pool().await;
+        for statement in [
+            "INSERT INTO accounts (account_name, key, super_user) VALUES ('neo', 'neo-key', 1)",
+            "INSERT INTO projects (project_name, account_name, initial_key, project_state) VALUES ('matrix', 'neo', 'initial', '{\"ready\": {}}')",
+            "INSERT INTO custom_domains (fqdn, project_name, certificate, private_key) VALUES ('matrix.io', 'matrix', 'cert', 'key')",
+            "INSERT INTO project_env (project_name, name, value) VALUES ('matrix', 'PILL', x'00ff10')",
+            "INSERT INTO project_rate_limits (project_name, requests_per_second, burst) VALUES ('matrix', 5, 10)",
+        ] {
+            query(statement).execute(&pool).await.unwrap();
+        }
+
+        let backup = export(&pool).await.unwrap();
+        assert_eq!(backup.tables["accounts"].len(), 1);
+        assert_eq!(backup.tables["project_env"][0]["value"], "AP8Q");
+
+        // the backup goes through its serialized form like it would
+        // between instances
+        let backup: Backup =
+            serde_json::from_str(&serde_json::to_string(&backup).unwrap()).unwrap();
+
+        let restored = fresh_pool().await;
+        import(&restored, &backup).await.unwrap();
+
+        let again = export(&restored).await.unwrap();
+        assert_eq!(again.schema, backup.schema);
+        assert_eq!(again.tables, backup.tables);
+
+        // an instance with state is not overwritten
+        let err = import(&restored, &backup).await.unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidBackup);
+    }
+
+    #[tokio::test]
+    async fn backup_refuses_incompatible() {
+        let pool = fresh_pool().await;
+        query("INSERT INTO accounts (account_name, key, super_user) VALUES ('neo', 'neo-key', 1)")
+            .execute(&pool)
+            .await
+            .unwrap();
+        let backup = export(&pool).await.unwrap();
+
+        let mut other_schema = export(&pool).await.unwrap();
+        other_schema.schema -= 1;
+
+        let mut partial = export(&pool).await.unwrap();
+        partial.tables.remove("projects");
+
+        let mut missing_column = export(&pool).await.unwrap();
+        missing_column.tables.get_mut("accounts").unwrap()[0].remove("key");
+
+        let mut dangling = export(&pool).await.unwrap();
+        dangling
+            .tables
+            .get_mut("project_aliases")
+            .unwrap()
+            .push(BTreeMap::from([
+                ("alias".to_string(), Value::from("canary")),
+                ("project_name".to_string(), Value::from("matrix")),
+            ]));
+
+        for backup in [other_schema, partial, missing_column, dangling] {
+            let restored = fresh_pool().await;
+            let err = import(&restored, &backup).await.unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::InvalidBackup);
+
+            // nothing is left behind
+            assert!(export(&restored).await.unwrap().tables["accounts"].is_empty());
+        }
+
+        import(&fresh_pool().await, &backup).await.unwrap();
+    }
+}
@@ -0,0 +1,324 @@
+use std::collections::{HashMap, HashSet};
+use std::net::IpAddr;
+use std::num::NonZeroU32;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+use axum::headers::authorization::Basic;
+use axum::headers::{Authorization, HeaderMapExt};
+use http::HeaderMap;
+use ring::digest::{digest, SHA256};
+use ring::pbkdf2;
+use ring::rand::{SecureRandom, SystemRandom};
+use shuttle_common::models::error::ErrorKind;
+
+use crate::{Error, ProjectName};
+
+/// Kept low enough for the first request of every client not to stall
+/// the proxy, as this is only meant to keep staging deployments away
+/// from prying eyes
+const PBKDF2_ITERATIONS: u32 = 10_000;
+const SALT_LEN: usize = 16;
+const HASH_LEN: usize = 32;
+
+/// How many credentials which passed are remembered per project
+const MAX_VERIFIED: usize = 64;
+
+/// How many wrong credentials a client can send a project within
+/// [`FAILED_ATTEMPTS_WINDOW`] before being turned away unchecked
+const MAX_FAILED_ATTEMPTS: u32 = 5;
+const FAILED_ATTEMPTS_WINDOW: Duration = Duration::from_secs(60);
+
+/// How many clients with failed attempts are tracked per project
+const MAX_TRACKED_CLIENTS: usize = 1024;
+
+/// The credentials a project is gated behind. Only a salted hash of
+/// the password is kept, as `pbkdf2-sha256$<iterations>$<salt>$<hash>`.
+#[derive(Debug)]
+pub struct BasicAuth {
+    pub username: String,
+    pub password_hash: String,
+    /// Digests of the credentials which already passed, so that the
+    /// password is not hashed again on every request
+    verified: Mutex<HashSet<Vec<u8>>>,
+    /// Recent wrong credentials per client, with when the first of
+    /// them was sent
+    failures: Mutex<HashMap<IpAddr, (u32, Instant)>>,
+}
+
+impl BasicAuth {
+    pub fn new(username: String, password: &str) -> Result<Self, Error> {
+        if username.is_empty() || username.contains(':') || password.is_empty() {
+            return Err(Error::from_kind(ErrorKind::InvalidBasicAuth));
+        }
+
+        let mut salt = [0u8; SALT_LEN];
+        SystemRandom::new()
+            .fill(&mut salt)
+            .map_err(|_| Error::custom(ErrorKind::Internal, "failed to generate a salt"))?;
+
+        let mut hash = [0u8; HASH_LEN];
+        pbkdf2::derive(
+            pbkdf2::PBKDF2_HMAC_SHA256,
+            NonZeroU32::new(PBKDF2_ITERATIONS).unwrap(),
+            &salt,
+            password.as_bytes(),
+            &mut hash,
+        );
+
+        let password_hash = format!(
+            "pbkdf2-sha256${PBKDF2_ITERATIONS}${}${}",
+            base64::encode(salt),
+            base64::encode(hash)
+        );
+
+        Ok(Self::from_hash(username, password_hash))
+    }
+
+    pub fn from_hash(username: String, password_hash: String) -> Self {
+        Self {
+            username,
+            password_hash,
+            verified: Mutex::new(HashSet::new()),
+            failures: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Whether `headers` sent by `client` carry these credentials.
+    /// Hashing the password is left to the blocking pool, and clients
+    /// which sent too many wrong credentials are turned away without
+    /// it, with how long until they can try again.
+    pub async fn check(
+        self: Arc<Self>,
+        headers: &HeaderMap,
+        client: IpAddr,
+    ) -> Result<bool, Duration> {
+        let (username, password) = match headers.typed_get::<Authorization<Basic>>() {
+            Some(Authorization(credentials)) => (
+                credentials.username().to_string(),
+                credentials.password().to_string(),
+            ),
+            None => return Ok(false),
+        };
+
+        if self.is_verified(&username, &password) {
+            return Ok(true);
+        }
+
+        self.check_failures(client, Instant::now())?;
+
+        let auth = Arc::clone(&self);
+        let passed = tokio::task::spawn_blocking(move || auth.verify(&username, &password))
+            .await
+            .unwrap_or(false);
+        if !passed {
+            self.record_failure(client, Instant::now());
+        }
+
+        Ok(passed)
+    }
+
+    pub fn verify(&self, username: &str, password: &str) -> bool {
+        if self.is_verified(username, password) {
+            return true;
+        }
+
+        if username != self.username || !self.verify_password(password) {
+            return false;
+        }
+
+        let mut verified = self.verified.lock().unwrap();
+        if verified.len() >= MAX_VERIFIED {
+            verified.clear();
+        }
+        verified.insert(credentials_digest(username, password));
+
+        true
+    }
+
+    /// Whether these credentials already passed
+    fn is_verified(&self, username: &str, password: &str) -> bool {
+        self.verified
+            .lock()
+            .unwrap()
+            .contains(&credentials_digest(username, password))
+    }
+
+    /// Fail with how long `client` has to wait if it sent too many
+    /// wrong credentials lately
+    fn check_failures(&self, client: IpAddr, now: Instant) -> Result<(), Duration> {
+        match self.failures.lock().unwrap().get(&client) {
+            Some((count, since)) if *count >= MAX_FAILED_ATTEMPTS => {
+                let elapsed = now.saturating_duration_since(*since);
+                if elapsed < FAILED_ATTEMPTS_WINDOW {
+                    Err(FAILED_ATTEMPTS_WINDOW - elapsed)
+                } else {
+                    Ok(())
+                }
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn record_failure(&self, client: IpAddr, now: Instant) {
+        let mut failures = self.failures.lock().unwrap();
+        if failures.len() >= MAX_TRACKED_CLIENTS {
+            failures.retain(|_, (_, since)| {
+                now.saturating_duration_since(*since) < FAILED_ATTEMPTS_WINDOW
+            });
+        }
+
+        let (count, since) = failures.entry(client).or_insert((0, now));
+        if now.saturating_duration_since(*since) >= FAILED_ATTEMPTS_WINDOW {
+            *count = 0;
+            *since = now;
+        }
+        *count += 1;
+    }
+
+    fn verify_password(&self, password: &str) -> bool {
+        let parts: Vec<_> = self.password_hash.split('$').collect();
+        let (iterations, salt, hash) = match parts.as_slice() {
+            ["pbkdf2-sha256", iterations, salt, hash] => (iterations, salt, hash),
+            _ => return false,
+        };
+
+        match (
+            iterations.parse().ok().and_then(NonZeroU32::new),
+            base64::decode(salt),
+            base64::decode(hash),
+        ) {
+            (Some(iterations), Ok(salt), Ok(hash)) => pbkdf2::verify(
+                pbkdf2::PBKDF2_HMAC_SHA256,
+                iterations,
+                &salt,
+                password.as_bytes(),
+                &hash,
+            )
+            .is_ok(),
+            _ => false,
+        }
+    }
+}
+
+fn credentials_digest(username: &str, password: &str) -> Vec<u8> {
+    digest(&SHA256, format!("{username}:{password}").as_bytes())
+        .as_ref()
+        .to_vec()
+}
+
+/// Per-project basic auth enforced by the user proxy. Projects
+/// without credentials let every request through.
+#[derive(Clone, Default)]
+pub struct BasicAuthGate {
+    table: Arc<RwLock<HashMap<ProjectName, Arc<BasicAuth>>>>,
+}
+
+impl BasicAuthGate {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set or clear the credentials of a project. Takes effect for
+    /// the very next request.
+    pub fn set_auth(&self, project_name: &ProjectName, auth: Option<BasicAuth>) {
+        let mut table = self.table.write().unwrap();
+        match auth {
+            Some(auth) => {
+                table.insert(project_name.clone(), Arc::new(auth));
+            }
+            None => {
+                table.remove(project_name);
+            }
+        }
+    }
+
+    pub fn auth(&self, project_name: &ProjectName) -> Option<Arc<BasicAuth>> {
+        self.table.read().unwrap().get(project_name).cloned()
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    #[test]
+    fn basic_auth() {
+        let auth = BasicAuth::new("neo".to_string(), "red-pill").unwrap();
+        assert!(auth.password_hash.starts_with("pbkdf2-sha256$"));
+        assert!(!auth.password_hash.contains("red-pill"));
+
+        // twice to go through the remembered credentials as well
+        for _ in 0..2 {
+            assert!(auth.verify("neo", "red-pill"));
+            assert!(!auth.verify("neo", "blue-pill"));
+            assert!(!auth.verify("smith", "red-pill"));
+        }
+
+        // the stored hash is enough to check credentials again
+        let reloaded = BasicAuth::from_hash(auth.username.clone(), auth.password_hash.clone());
+        assert!(reloaded.verify("neo", "red-pill"));
+        assert!(!reloaded.verify("neo", "blue-pill"));
+
+        for (username, password) in [("", "red-pill"), ("ne:o", "red-pill"), ("neo", "")] {
+            assert_eq!(
+                BasicAuth::new(username.to_string(), password)
+                    .unwrap_err()
+                    .kind(),
+                ErrorKind::InvalidBasicAuth
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn basic_auth_failed_attempts() {
+        let auth = Arc::new(BasicAuth::new("neo".to_string(), "red-pill").unwrap());
+        let headers = |password: &str| {
+            let mut headers = HeaderMap::new();
+            headers.typed_insert(Authorization::basic("neo", password));
+            headers
+        };
+        let smith: IpAddr = "10.0.0.1".parse().unwrap();
+        let trinity: IpAddr = "10.0.0.2".parse().unwrap();
+
+        assert_eq!(
+            auth.clone().check(&HeaderMap::new(), smith).await,
+            Ok(false)
+        );
+
+        for _ in 0..MAX_FAILED_ATTEMPTS {
+            assert_eq!(
+                auth.clone().check(&headers("blue-pill"), smith).await,
+                Ok(false)
+            );
+        }
+
+        // too many wrong attempts are not checked anymore, not even
+        // when the credentials are right
+        assert!(auth
+            .clone()
+            .check(&headers("blue-pill"), smith)
+            .await
+            .is_err());
+        assert!(auth
+            .clone()
+            .check(&headers("red-pill"), smith)
+            .await
+            .is_err());
+
+        // other clients are left alone, and credentials which passed
+        // once are let through
+        assert_eq!(
+            auth.clone().check(&headers("red-pill"), trinity).await,
+            Ok(true)
+        );
+        assert_eq!(
+            auth.clone().check(&headers("red-pill"), smith).await,
+            Ok(true)
+        );
+
+        // until the window is over
+        let later = Instant::now() + FAILED_ATTEMPTS_WINDOW;
+        assert!(auth.check_failures(smith, later).is_ok());
+    }
+}
@@ -0,0 +1,244 @@
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use bollard::errors::Error as DockerError;
+use futures::Future;
+use once_cell::sync::Lazy;
+use tracing::{info, warn};
+
+/// How many Docker operations in a row have to fail for the breaker to open
+pub const FAILURE_THRESHOLD: u32 = 5;
+
+/// How long the breaker stays open before letting a probe through
+pub const OPEN_FOR: Duration = Duration::from_secs(30);
+
+/// Status code of the errors returned in place of the operations the
+/// breaker turned away
+pub const OPEN_STATUS_CODE: u16 = 503;
+
+const OPEN_MESSAGE: &str = "the Docker circuit breaker is open";
+
+/// The breaker every [`crate::docker_op`] goes through
+pub static DOCKER_BREAKER: Lazy<CircuitBreaker> =
+    Lazy::new(|| CircuitBreaker::new(FAILURE_THRESHOLD, OPEN_FOR));
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BreakerState {
+    /// Operations go through
+    Closed,
+    /// Operations are turned away without reaching Docker
+    Open,
+    /// A single operation is let through to see if Docker recovered
+    HalfOpen,
+}
+
+impl BreakerState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Closed => "closed",
+            Self::Open => "open",
+            Self::HalfOpen => "half-open",
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct BreakerSnapshot {
+    pub state: BreakerState,
+    pub consecutive_failures: u32,
+    /// How long until the next probe, when open
+    pub retry_in: Option<Duration>,
+}
+
+#[derive(Default)]
+struct Inner {
+    consecutive_failures: u32,
+    opened_at: Option<Instant>,
+    probe_started_at: Option<Instant>,
+}
+
+/// Stops sending operations to a Docker daemon which keeps failing them.
+/// The breaker opens after `threshold` failures in a row and turns
+/// operations away with a `503`. Once `open_for` passed, it lets a single
+/// probe through and closes again if that succeeds.
+pub struct CircuitBreaker {
+    threshold: u32,
+    open_for: Duration,
+    inner: Mutex<Inner>,
+}
+
+impl CircuitBreaker {
+    pub fn new(threshold: u32, open_for: Duration) -> Self {
+        Self {
+            threshold,
+            open_for,
+            inner: Mutex::new(Inner::default()),
+        }
+    }
+
+    /// Run `fut` unless the breaker is open, keeping track of how it went
+    pub async fn call<T, F>(&self, fut: F) -> Result<T, DockerError>
+    where
+        F: Future<Output = Result<T, DockerError>>,
+    {
+        if !self.admit() {
+            return Err(DockerError::DockerResponseServerError {
+                status_code: OPEN_STATUS_CODE,
+                message: OPEN_MESSAGE.to_string(),
+            });
+        }
+
+        let res = fut.await;
+        self.record(
+            res.as_ref()
+                .map_or_else(|err| !is_daemon_failure(err), |_| true),
+        );
+
+        res
+    }
+
+    pub fn snapshot(&self) -> BreakerSnapshot {
+        let inner = self.inner.lock().unwrap();
+        let (state, retry_in) = match (inner.opened_at, inner.probe_started_at) {
+            (None, _) => (BreakerState::Closed, None),
+            (Some(_), Some(_)) => (BreakerState::HalfOpen, None),
+            (Some(opened_at), None) => match self.open_for.checked_sub(opened_at.elapsed()) {
+                Some(retry_in) => (BreakerState::Open, Some(retry_in)),
+                None => (BreakerState::HalfOpen, None),
+            },
+        };
+
+        BreakerSnapshot {
+            state,
+            consecutive_failures: inner.consecutive_failures,
+            retry_in,
+        }
+    }
+
+    fn admit(&self) -> bool {
+        let mut inner = self.inner.lock().unwrap();
+        let opened_at = match inner.opened_at {
+            Some(opened_at) => opened_at,
+            None => return true,
+        };
+
+        // A probe which never reported back (because it was dropped) does
+        // not keep the breaker open forever
+        let probing = inner
+            .probe_started_at
+            .map_or(false, |started_at| started_at.elapsed() < self.open_for);
+        if probing || opened_at.elapsed() < self.open_for {
+            return false;
+        }
+
+        inner.probe_started_at = Some(Instant::now());
+        true
+    }
+
+    fn record(&self, ok: bool) {
+        let mut inner = self.inner.lock().unwrap();
+
+        if ok {
+            if inner.opened_at.is_some() {
+                info!("Docker recovered, closing the circuit breaker");
+            }
+            *inner = Inner::default();
+            return;
+        }
+
+        inner.consecutive_failures += 1;
+        if inner.probe_started_at.take().is_some()
+            || (inner.opened_at.is_none() && inner.consecutive_failures >= self.threshold)
+        {
+            warn!(
+                consecutive_failures = inner.consecutive_failures,
+                "Docker keeps failing, opening the circuit breaker"
+            );
+            inner.opened_at = Some(Instant::now());
+        }
+    }
+}
+
+/// Whether the error says something is wrong with Docker, rather than
+/// with what was asked of it (like inspecting a missing container)
+fn is_daemon_failure(err: &DockerError) -> bool {
+    !matches!(
+        err,
+        DockerError::DockerResponseServerError { status_code, .. } if *status_code < 500
+    )
+}
+
+/// Whether the error was returned by an open breaker
+pub fn is_open_error(err: &DockerError) -> bool {
+    matches!(
+        err,
+        DockerError::DockerResponseServerError { status_code, message }
+            if *status_code == OPEN_STATUS_CODE && message == OPEN_MESSAGE
+    )
+}
+
+#[cfg(test)]
+pub mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+
+    fn failure(status_code: u16) -> Result<(), DockerError> {
+        Err(DockerError::DockerResponseServerError {
+            status_code,
+            message: "oops".to_string(),
+        })
+    }
+
+    #[tokio::test]
+    async fn breaker_opens_then_recovers() {
+        let breaker = CircuitBreaker::new(3, Duration::from_millis(100));
+        let calls = &AtomicUsize::new(0);
+        let op = |res: Result<(), DockerError>| async move {
+            calls.fetch_add(1, Ordering::SeqCst);
+            res
+        };
+
+        // missing containers and the like are not Docker failing
+        for _ in 0..5 {
+            breaker.call(op(failure(404))).await.unwrap_err();
+        }
+        assert_eq!(breaker.snapshot().state, BreakerState::Closed);
+        assert_eq!(breaker.snapshot().consecutive_failures, 0);
+
+        for _ in 0..3 {
+            breaker.call(op(failure(500))).await.unwrap_err();
+        }
+        let snapshot = breaker.snapshot();
+        assert_eq!(snapshot.state, BreakerState::Open);
+        assert_eq!(snapshot.consecutive_failures, 3);
+        assert!(snapshot.retry_in.is_some());
+
+        // operations are turned away without reaching Docker
+        let err = breaker.call(op(Ok(()))).await.unwrap_err();
+        assert!(is_open_error(&err));
+        assert_eq!(calls.load(Ordering::SeqCst), 8);
+
+        // a failed probe opens the breaker again
+        tokio::time::sleep(Duration::from_millis(120)).await;
+        assert_eq!(breaker.snapshot().state, BreakerState::HalfOpen);
+        breaker.call(op(failure(500))).await.unwrap_err();
+        assert_eq!(calls.load(Ordering::SeqCst), 9);
+        assert_eq!(breaker.snapshot().state, BreakerState::Open);
+        assert!(is_open_error(&breaker.call(op(Ok(()))).await.unwrap_err()));
+
+        // and a successful one closes it
+        tokio::time::sleep(Duration::from_millis(120)).await;
+        breaker.call(op(Ok(()))).await.unwrap();
+        assert_eq!(
+            breaker.snapshot(),
+            BreakerSnapshot {
+                state: BreakerState::Closed,
+                consecutive_failures: 0,
+                retry_in: None,
+            }
+        );
+        breaker.call(op(Ok(()))).await.unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 11);
+    }
+}
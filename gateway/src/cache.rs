@@ -0,0 +1,134 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use ttl_cache::TtlCache;
+
+use crate::project::Project;
+use crate::ProjectName;
+
+/// How many project states are kept in memory at most
+pub const PROJECT_CACHE_CAPACITY: usize = 1024;
+
+/// How long a project state is served from memory before it is read
+/// from the database again
+pub const PROJECT_CACHE_TTL: Duration = Duration::from_secs(30);
+
+struct Inner {
+    entries: TtlCache<ProjectName, Project>,
+    /// Bumped on every invalidation so that a lookup which raced with
+    /// a state transition does not put the old state back
+    generation: u64,
+}
+
+/// A bounded in-memory cache of project states
+///
+/// Entries expire after a TTL and are dropped whenever the project
+/// they belong to transitions to a new state.
+#[derive(Clone)]
+pub struct ProjectCache {
+    inner: Arc<Mutex<Inner>>,
+    ttl: Duration,
+    hits: Arc<AtomicU64>,
+    misses: Arc<AtomicU64>,
+}
+
+impl ProjectCache {
+    pub fn new(capacity: usize, ttl: Duration) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(Inner {
+                entries: TtlCache::new(capacity),
+                generation: 0,
+            })),
+            ttl,
+            hits: Arc::new(AtomicU64::new(0)),
+            misses: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    /// Look up the state of `project_name`. On a miss, the current
+    /// generation is returned so that the state loaded from the
+    /// database can be handed back to [`ProjectCache::insert`].
+    pub fn get(&self, project_name: &ProjectName) -> Result<Project, u64> {
+        let inner = self.inner.lock().unwrap();
+        match inner.entries.get(project_name) {
+            Some(project) => {
+                self.hits.fetch_add(1, Ordering::Relaxed);
+                Ok(project.clone())
+            }
+            None => {
+                self.misses.fetch_add(1, Ordering::Relaxed);
+                Err(inner.generation)
+            }
+        }
+    }
+
+    /// Cache `project` if nothing was invalidated since `generation`
+    /// was handed out by [`ProjectCache::get`]
+    pub fn insert(&self, project_name: &ProjectName, project: &Project, generation: u64) {
+        let mut inner = self.inner.lock().unwrap();
+        if inner.generation == generation {
+            inner
+                .entries
+                .insert(project_name.clone(), project.clone(), self.ttl);
+        }
+    }
+
+    /// Drop the state of `project_name`. Has to be called after every
+    /// write to the state of a project.
+    pub fn invalidate(&self, project_name: &ProjectName) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.generation = inner.generation.wrapping_add(1);
+        inner.entries.remove(project_name);
+    }
+
+    pub fn hits(&self) -> u64 {
+        self.hits.load(Ordering::Relaxed)
+    }
+
+    pub fn misses(&self) -> u64 {
+        self.misses.load(Ordering::Relaxed)
+    }
+
+    /// The share of lookups served from memory, if there were any
+    pub fn hit_ratio(&self) -> Option<f64> {
+        let hits = self.hits();
+        let total = hits + self.misses();
+        if total == 0 {
+            None
+        } else {
+            Some(hits as f64 / total as f64)
+        }
+    }
+}
+
+impl Default for ProjectCache {
+    fn default() -> Self {
+        Self::new(PROJECT_CACHE_CAPACITY, PROJECT_CACHE_TTL)
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    #[test]
+    fn project_cache_discards_racing_insert() {
+        let cache = ProjectCache::default();
+        let matrix: ProjectName = "matrix".parse().unwrap();
+        let project = Project::create(matrix.clone());
+
+        let generation = cache.get(&matrix).unwrap_err();
+        // a state transition happens while the lookup hits the database
+        cache.invalidate(&matrix);
+        cache.insert(&matrix, &project, generation);
+        let generation = cache.get(&matrix).unwrap_err();
+
+        cache.insert(&matrix, &project, generation);
+        assert_eq!(cache.get(&matrix).unwrap(), project);
+
+        assert_eq!(cache.hits(), 1);
+        assert_eq!(cache.misses(), 2);
+        assert_eq!(cache.hit_ratio(), Some(1.0 / 3.0));
+    }
+}
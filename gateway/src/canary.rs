@@ -0,0 +1,154 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::net::IpAddr;
+use std::sync::{Arc, RwLock};
+
+use rand::Rng;
+use shuttle_common::models::error::ErrorKind;
+use shuttle_common::models::project::Canary;
+
+use crate::{Error, ProjectName};
+
+/// A [`Canary`] whose project name was checked
+#[derive(Debug)]
+pub struct CanarySplit {
+    pub canary: Canary,
+    pub project: ProjectName,
+}
+
+impl CanarySplit {
+    pub fn new(canary: Canary) -> Result<Self, Error> {
+        let invalid = |detail: &str| Error::custom(ErrorKind::InvalidCanary, detail.to_string());
+
+        if canary.weight > 100 {
+            return Err(invalid("the weight is a percentage, from 0 to 100"));
+        }
+        let project = canary
+            .project
+            .parse()
+            .map_err(|_| invalid("the canary is not a valid project name"))?;
+
+        Ok(Self { canary, project })
+    }
+
+    /// Whether a request from `client` goes to the canary. Sticky splits
+    /// always send the same client to the same side, and raising the
+    /// weight only ever moves clients onto the canary.
+    pub fn picks_canary(&self, project_name: &ProjectName, client: IpAddr) -> bool {
+        let bucket = if self.canary.sticky {
+            let mut hasher = DefaultHasher::new();
+            project_name.hash(&mut hasher);
+            client.hash(&mut hasher);
+            (hasher.finish() % 100) as u8
+        } else {
+            rand::thread_rng().gen_range(0..100)
+        };
+
+        bucket < self.canary.weight
+    }
+}
+
+/// Per-project canaries the user proxy splits traffic with. Projects
+/// without one get all their traffic.
+#[derive(Clone, Default)]
+pub struct Canaries {
+    table: Arc<RwLock<HashMap<ProjectName, Arc<CanarySplit>>>>,
+}
+
+impl Canaries {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set or clear the canary of a project. Takes effect for the very
+    /// next request.
+    pub fn set_split(&self, project_name: &ProjectName, split: Option<CanarySplit>) {
+        let mut table = self.table.write().unwrap();
+        match split {
+            Some(split) => {
+                table.insert(project_name.clone(), Arc::new(split));
+            }
+            None => {
+                table.remove(project_name);
+            }
+        }
+    }
+
+    pub fn split(&self, project_name: &ProjectName) -> Option<Arc<CanarySplit>> {
+        self.table.read().unwrap().get(project_name).cloned()
+    }
+
+    /// The canary a request to `project_name` from `client` goes to,
+    /// if it does not go to the project itself
+    pub fn pick(&self, project_name: &ProjectName, client: IpAddr) -> Option<ProjectName> {
+        self.split(project_name)
+            .filter(|split| split.picks_canary(project_name, client))
+            .map(|split| split.project.clone())
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    fn canary(weight: u8, sticky: bool) -> CanarySplit {
+        CanarySplit::new(Canary {
+            project: "matrix-reloaded".to_string(),
+            weight,
+            sticky,
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn canary_split() {
+        let matrix: ProjectName = "matrix".parse().unwrap();
+        let reloaded: ProjectName = "matrix-reloaded".parse().unwrap();
+        let canaries = Canaries::new();
+        let client = |i: u32| IpAddr::from((0x0a000000 + i).to_be_bytes());
+
+        // everything goes to the project until it has a canary
+        assert_eq!(canaries.pick(&matrix, client(1)), None);
+
+        // half of the requests go to each side
+        for sticky in [false, true] {
+            canaries.set_split(&matrix, Some(canary(50, sticky)));
+            let to_canary = (0..10_000)
+                .filter(|i| canaries.pick(&matrix, client(*i)).is_some())
+                .count();
+            assert!((4_500..5_500).contains(&to_canary), "{to_canary}");
+        }
+
+        // sticky clients stay on their side, and raising the weight
+        // only moves more of them to the canary
+        let sides = |canaries: &Canaries| {
+            (0..1_000)
+                .map(|i| canaries.pick(&matrix, client(i)).is_some())
+                .collect::<Vec<_>>()
+        };
+        let half = sides(&canaries);
+        assert_eq!(sides(&canaries), half);
+        canaries.set_split(&matrix, Some(canary(80, true)));
+        let most = sides(&canaries);
+        assert!(half.iter().zip(&most).all(|(half, most)| !half || *most));
+
+        // once promoted the canary gets everything
+        canaries.set_split(&matrix, Some(canary(100, false)));
+        assert!((0..1_000).all(|i| canaries.pick(&matrix, client(i)) == Some(reloaded.clone())));
+
+        // and rolling back gives it all back to the project
+        canaries.set_split(&matrix, None);
+        assert!((0..1_000).all(|i| canaries.pick(&matrix, client(i)).is_none()));
+
+        for (project, weight) in [("matrix-reloaded", 101), ("Not A Project", 50)] {
+            let err = CanarySplit::new(Canary {
+                project: project.to_string(),
+                weight,
+                sticky: false,
+            })
+            .unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::InvalidCanary);
+        }
+    }
+}
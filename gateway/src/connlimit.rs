@@ -0,0 +1,103 @@
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use shuttle_common::models::project::ConnectionLimit;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tokio::time::timeout;
+
+use crate::ProjectName;
+
+/// How many requests to a project can be in flight at once, with a
+/// slot taken by every one of them until its response is over
+struct Slots {
+    limit: ConnectionLimit,
+    semaphore: Arc<Semaphore>,
+}
+
+/// Per-project limits on the requests the user proxy has open with a
+/// project at once. Projects without a limit are never held back.
+#[derive(Clone, Default)]
+pub struct ConnectionLimiter {
+    slots: Arc<RwLock<HashMap<ProjectName, Slots>>>,
+}
+
+impl ConnectionLimiter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set or clear the limit of a project. Requests already in flight
+    /// do not count towards a new limit.
+    pub fn set_limit(&self, project_name: &ProjectName, limit: Option<ConnectionLimit>) {
+        let mut slots = self.slots.write().unwrap();
+        match limit {
+            Some(limit) => {
+                let semaphore = Arc::new(Semaphore::new(limit.max_connections as usize));
+                slots.insert(project_name.clone(), Slots { limit, semaphore });
+            }
+            None => {
+                slots.remove(project_name);
+            }
+        }
+    }
+
+    pub fn limit(&self, project_name: &ProjectName) -> Option<ConnectionLimit> {
+        self.slots
+            .read()
+            .unwrap()
+            .get(project_name)
+            .map(|slots| slots.limit.clone())
+    }
+
+    /// Take a slot to `project_name`, waiting up to `wait` for one to
+    /// free up. The slot is given back when the permit is dropped, and
+    /// there is no permit for projects without a limit.
+    pub async fn acquire(
+        &self,
+        project_name: &ProjectName,
+        wait: Duration,
+    ) -> Result<Option<OwnedSemaphorePermit>, ()> {
+        let semaphore = match self.slots.read().unwrap().get(project_name) {
+            Some(slots) => slots.semaphore.clone(),
+            None => return Ok(None),
+        };
+
+        match timeout(wait, semaphore.acquire_owned()).await {
+            Ok(Ok(permit)) => Ok(Some(permit)),
+            _ => Err(()),
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn connection_limiter() {
+        let limiter = ConnectionLimiter::new();
+        let matrix: ProjectName = "matrix".parse().unwrap();
+        let wait = Duration::from_millis(10);
+
+        assert!(limiter.acquire(&matrix, wait).await.unwrap().is_none());
+
+        limiter.set_limit(&matrix, Some(ConnectionLimit { max_connections: 2 }));
+        let first = limiter.acquire(&matrix, wait).await.unwrap();
+        let _second = limiter.acquire(&matrix, wait).await.unwrap();
+        assert!(limiter.acquire(&matrix, wait).await.is_err());
+
+        // a slot freeing up while waiting is taken
+        let acquire = tokio::spawn({
+            let limiter = limiter.clone();
+            let matrix = matrix.clone();
+            async move { limiter.acquire(&matrix, Duration::from_secs(5)).await }
+        });
+        tokio::time::sleep(wait).await;
+        drop(first);
+        assert!(acquire.await.unwrap().unwrap().is_some());
+
+        limiter.set_limit(&matrix, None);
+        assert!(limiter.acquire(&matrix, wait).await.unwrap().is_none());
+    }
+}
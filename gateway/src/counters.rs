@@ -0,0 +1,218 @@
+use std::collections::{BTreeMap, HashMap};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::task::{Context, Poll};
+
+use chrono::{NaiveDate, Utc};
+use futures::Stream;
+use http::HeaderMap;
+use hyper::body::{Bytes, HttpBody, SizeHint};
+
+use crate::ProjectName;
+
+/// Platform wide counters which are not persisted anywhere, so a
+/// restarted gateway starts counting from zero again.
+#[derive(Clone, Default)]
+pub struct PlatformCounters {
+    proxy_requests: Arc<AtomicU64>,
+    deployments: Arc<Mutex<Option<(NaiveDate, u64)>>>,
+}
+
+impl PlatformCounters {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Count a request which reached the user proxy
+    pub fn record_proxy_request(&self) {
+        self.proxy_requests.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// How many requests reached the user proxy since the gateway started
+    pub fn proxy_requests(&self) -> u64 {
+        self.proxy_requests.load(Ordering::Relaxed)
+    }
+
+    /// Count a deployment made today
+    pub fn record_deployment(&self) {
+        let today = Utc::now().date_naive();
+        let mut deployments = self.deployments.lock().unwrap();
+        match deployments.as_mut() {
+            Some((day, count)) if *day == today => *count += 1,
+            _ => *deployments = Some((today, 1)),
+        }
+    }
+
+    /// How many deployments were made today (UTC) since the gateway started
+    pub fn deployments_today(&self) -> u64 {
+        let today = Utc::now().date_naive();
+        match *self.deployments.lock().unwrap() {
+            Some((day, count)) if day == today => count,
+            _ => 0,
+        }
+    }
+}
+
+/// Bytes which went through the user proxy for a project, in the
+/// bodies of requests and of responses
+#[derive(Default)]
+pub struct ProjectTraffic {
+    bytes_in: AtomicU64,
+    bytes_out: AtomicU64,
+}
+
+impl ProjectTraffic {
+    /// Bytes received from clients since the gateway started
+    pub fn bytes_in(&self) -> u64 {
+        self.bytes_in.load(Ordering::Relaxed)
+    }
+
+    /// Bytes sent to clients since the gateway started
+    pub fn bytes_out(&self) -> u64 {
+        self.bytes_out.load(Ordering::Relaxed)
+    }
+}
+
+/// The traffic of every project which had some since the gateway
+/// started. Like the platform counters, it is not persisted.
+#[derive(Clone, Default)]
+pub struct TrafficCounters {
+    projects: Arc<RwLock<HashMap<ProjectName, Arc<ProjectTraffic>>>>,
+}
+
+impl TrafficCounters {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The traffic of `project_name`, to count more of it
+    pub fn project(&self, project_name: &ProjectName) -> Arc<ProjectTraffic> {
+        if let Some(traffic) = self.projects.read().unwrap().get(project_name) {
+            return traffic.clone();
+        }
+
+        self.projects
+            .write()
+            .unwrap()
+            .entry(project_name.clone())
+            .or_default()
+            .clone()
+    }
+
+    /// Bytes in and out of every project, by name
+    pub fn snapshot(&self) -> BTreeMap<String, (u64, u64)> {
+        self.projects
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(name, traffic)| (name.to_string(), (traffic.bytes_in(), traffic.bytes_out())))
+            .collect()
+    }
+}
+
+/// Which way a counted body goes through the proxy
+#[derive(Clone, Copy)]
+pub enum Direction {
+    /// From a client to a project
+    In,
+    /// From a project to a client
+    Out,
+}
+
+/// A body counting its bytes towards the traffic of a project as they
+/// are read, so that streams are counted without being held on to
+pub struct CountedBody<B> {
+    inner: B,
+    traffic: Arc<ProjectTraffic>,
+    direction: Direction,
+}
+
+impl<B> CountedBody<B> {
+    pub fn new(inner: B, traffic: Arc<ProjectTraffic>, direction: Direction) -> Self {
+        Self {
+            inner,
+            traffic,
+            direction,
+        }
+    }
+
+    fn count(&self, bytes: usize) {
+        let counter = match self.direction {
+            Direction::In => &self.traffic.bytes_in,
+            Direction::Out => &self.traffic.bytes_out,
+        };
+        counter.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+}
+
+impl<B> HttpBody for CountedBody<B>
+where
+    B: HttpBody<Data = Bytes> + Unpin,
+{
+    type Data = Bytes;
+    type Error = B::Error;
+
+    fn poll_data(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        let poll = Pin::new(&mut self.inner).poll_data(cx);
+        if let Poll::Ready(Some(Ok(chunk))) = &poll {
+            self.count(chunk.len());
+        }
+        poll
+    }
+
+    fn poll_trailers(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<Option<HeaderMap>, Self::Error>> {
+        Pin::new(&mut self.inner).poll_trailers(cx)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+impl<B> Stream for CountedBody<B>
+where
+    B: HttpBody<Data = Bytes> + Unpin,
+{
+    type Item = Result<Bytes, B::Error>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.poll_data(cx)
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    #[test]
+    fn platform_counters() {
+        let counters = PlatformCounters::new();
+        assert_eq!(counters.proxy_requests(), 0);
+        assert_eq!(counters.deployments_today(), 0);
+
+        counters.record_proxy_request();
+        counters.record_proxy_request();
+        counters.record_deployment();
+
+        assert_eq!(counters.proxy_requests(), 2);
+        assert_eq!(counters.deployments_today(), 1);
+
+        // counts from yesterday are not counted today
+        let yesterday = Utc::now().date_naive().pred_opt().unwrap();
+        *counters.deployments.lock().unwrap() = Some((yesterday, 5));
+        assert_eq!(counters.deployments_today(), 0);
+        counters.record_deployment();
+        assert_eq!(counters.deployments_today(), 1);
+    }
+}
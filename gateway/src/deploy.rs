@@ -0,0 +1,383 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Utc};
+use futures::prelude::*;
+use hyper::body::{Bytes, HttpBody};
+use hyper::{Body, Request};
+use shuttle_common::deployment::State;
+use shuttle_common::models::deployment::PendingDeploy;
+use tokio::sync::{Mutex as AsyncMutex, OwnedMutexGuard};
+use uuid::Uuid;
+
+use crate::args::DeployConcurrency;
+use crate::{AccountName, Error, ErrorKind, ProjectName};
+
+/// How a gzip stream compressed with deflate starts, as the crates
+/// `cargo shuttle` packs do
+const GZIP_MAGIC: [u8; 3] = [0x1f, 0x8b, 0x08];
+
+/// How long after a deploy was handed to the deployer its project is
+/// taken to still be switching over to it. The deployer answers right
+/// away and builds in the background, so the gateway cannot tell when
+/// it is done.
+pub const DEPLOY_TRANSITION: Duration = Duration::from_secs(5 * 60);
+
+/// Held for as long as a deployment of a project is in progress
+pub struct DeployGuard {
+    _lock: OwnedMutexGuard<()>,
+    _pending: Option<PendingGuard>,
+}
+
+/// A deploy which went through the gateway, in the order it came in
+struct Pending {
+    id: Uuid,
+    requested_by: String,
+    requested_at: DateTime<Utc>,
+    running: bool,
+}
+
+type PendingDeploys = Arc<Mutex<HashMap<ProjectName, Vec<Pending>>>>;
+
+/// Takes a deploy off the list of pending ones when it is done, or
+/// when it is given up on while it waits for its turn
+struct PendingGuard {
+    project_name: ProjectName,
+    id: Uuid,
+    pending: PendingDeploys,
+}
+
+impl Drop for PendingGuard {
+    fn drop(&mut self) {
+        let mut pending = self.pending.lock().unwrap();
+        if let Some(deploys) = pending.get_mut(&self.project_name) {
+            deploys.retain(|deploy| deploy.id != self.id);
+            if deploys.is_empty() {
+                pending.remove(&self.project_name);
+            }
+        }
+    }
+}
+
+/// Makes sure only one deployment of a project is in progress at a
+/// time, so that concurrent deploys cannot race each other
+pub struct DeployLocks {
+    concurrency: DeployConcurrency,
+    locks: Mutex<HashMap<ProjectName, Arc<AsyncMutex<()>>>>,
+    pending: PendingDeploys,
+    /// When the last deploy of each project was handed to the deployer
+    handed_over: Mutex<HashMap<ProjectName, Instant>>,
+}
+
+impl DeployLocks {
+    pub fn new(concurrency: DeployConcurrency) -> Self {
+        Self {
+            concurrency,
+            locks: Mutex::new(HashMap::new()),
+            pending: Default::default(),
+            handed_over: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Wait for the deployment in progress to be over, or fail with
+    /// [`ErrorKind::ProjectBusy`] when deploys are rejected instead
+    pub async fn lock(&self, project_name: &ProjectName) -> Result<DeployGuard, Error> {
+        let lock = self
+            .locks
+            .lock()
+            .unwrap()
+            .entry(project_name.clone())
+            .or_default()
+            .clone();
+
+        let lock = match self.concurrency {
+            DeployConcurrency::Queue => lock.lock_owned().await,
+            DeployConcurrency::Reject => lock
+                .try_lock_owned()
+                .map_err(|_| Error::from_kind(ErrorKind::ProjectBusy))?,
+        };
+
+        Ok(DeployGuard {
+            _lock: lock,
+            _pending: None,
+        })
+    }
+
+    /// Like [`DeployLocks::lock`], for a deploy `requested_by` an
+    /// account, which is listed among the [`pending`] deploys of the
+    /// project until it is done
+    ///
+    /// [`pending`]: DeployLocks::pending
+    pub async fn lock_deploy(
+        &self,
+        project_name: &ProjectName,
+        requested_by: &AccountName,
+    ) -> Result<DeployGuard, Error> {
+        let id = Uuid::new_v4();
+        self.pending
+            .lock()
+            .unwrap()
+            .entry(project_name.clone())
+            .or_default()
+            .push(Pending {
+                id,
+                requested_by: requested_by.to_string(),
+                requested_at: Utc::now(),
+                running: false,
+            });
+        let pending = PendingGuard {
+            project_name: project_name.clone(),
+            id,
+            pending: self.pending.clone(),
+        };
+
+        let guard = self.lock(project_name).await?;
+
+        if let Some(deploy) = self
+            .pending
+            .lock()
+            .unwrap()
+            .get_mut(project_name)
+            .and_then(|deploys| deploys.iter_mut().find(|deploy| deploy.id == id))
+        {
+            deploy.running = true;
+        }
+
+        Ok(DeployGuard {
+            _pending: Some(pending),
+            ..guard
+        })
+    }
+
+    /// The deploys of a project the gateway has yet to be done with:
+    /// the one in progress first, followed by the ones queued behind
+    /// it in the order they came in
+    pub fn pending(&self, project_name: &ProjectName) -> Vec<PendingDeploy> {
+        let pending = self.pending.lock().unwrap();
+        let deploys = match pending.get(project_name) {
+            Some(deploys) => deploys,
+            None => return Vec::new(),
+        };
+
+        deploys
+            .iter()
+            .filter(|deploy| deploy.running)
+            .chain(deploys.iter().filter(|deploy| !deploy.running))
+            .map(|deploy| PendingDeploy {
+                id: deploy.id,
+                state: if deploy.running {
+                    State::Running
+                } else {
+                    State::Queued
+                },
+                requested_by: deploy.requested_by.clone(),
+                requested_at: deploy.requested_at,
+            })
+            .collect()
+    }
+
+    /// Note that the deployer took a deploy of `project_name` on
+    pub fn handed_over(&self, project_name: &ProjectName) {
+        self.handed_over
+            .lock()
+            .unwrap()
+            .insert(project_name.clone(), Instant::now());
+    }
+
+    /// Whether `project_name` is being deployed: a deploy of it is still
+    /// going through the gateway, or the deployer was handed one less
+    /// than [`DEPLOY_TRANSITION`] ago
+    pub fn in_transition(&self, project_name: &ProjectName) -> bool {
+        if self.pending.lock().unwrap().contains_key(project_name) {
+            return true;
+        }
+
+        let mut handed_over = self.handed_over.lock().unwrap();
+        match handed_over.get(project_name) {
+            Some(at) if at.elapsed() < DEPLOY_TRANSITION => true,
+            Some(_) => {
+                handed_over.remove(project_name);
+                false
+            }
+            None => false,
+        }
+    }
+}
+
+impl Default for DeployLocks {
+    fn default() -> Self {
+        Self::new(DeployConcurrency::Queue)
+    }
+}
+
+/// Make sure an uploaded crate is a gzipped archive before anything is
+/// done with it, going by its first bytes only. The rest of the body is
+/// left to stream on to the deployer.
+pub async fn check_archive(req: Request<Body>) -> Result<Request<Body>, Error> {
+    let (parts, mut body) = req.into_parts();
+
+    let mut head = Vec::new();
+    while head.len() < GZIP_MAGIC.len() {
+        match body.data().await {
+            Some(chunk) => head.extend_from_slice(
+                &chunk.map_err(|err| Error::source(ErrorKind::InvalidOperation, err))?,
+            ),
+            None => break,
+        }
+    }
+
+    if !head.starts_with(&GZIP_MAGIC) {
+        return Err(Error::from_kind(ErrorKind::InvalidArchive));
+    }
+
+    let head = stream::once(future::ok(Bytes::from(head)));
+    Ok(Request::from_parts(
+        parts,
+        Body::wrap_stream(head.chain(body)),
+    ))
+}
+
+#[cfg(test)]
+pub mod tests {
+    use std::time::Duration;
+
+    use tokio::time::timeout;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn deploy_locks() {
+        let matrix: ProjectName = "matrix".parse().unwrap();
+        let reloaded: ProjectName = "reloaded".parse().unwrap();
+
+        let locks = DeployLocks::new(DeployConcurrency::Reject);
+        let guard = locks.lock(&matrix).await.unwrap();
+        assert_eq!(
+            locks.lock(&matrix).await.err().unwrap().kind(),
+            ErrorKind::ProjectBusy
+        );
+        // other projects can still deploy
+        assert!(locks.lock(&reloaded).await.is_ok());
+        drop(guard);
+        assert!(locks.lock(&matrix).await.is_ok());
+
+        let locks = Arc::new(DeployLocks::new(DeployConcurrency::Queue));
+        let guard = locks.lock(&matrix).await.unwrap();
+        let second = tokio::spawn({
+            let locks = locks.clone();
+            let matrix = matrix.clone();
+            async move { locks.lock(&matrix).await.map(|_| ()) }
+        });
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(!second.is_finished());
+
+        drop(guard);
+        timeout(Duration::from_secs(1), second)
+            .await
+            .expect("the second deploy to go ahead")
+            .unwrap()
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn pending_deploys() {
+        let matrix: ProjectName = "matrix".parse().unwrap();
+        let neo: AccountName = "neo".parse().unwrap();
+        let trinity: AccountName = "trinity".parse().unwrap();
+
+        let locks = Arc::new(DeployLocks::new(DeployConcurrency::Queue));
+        assert!(locks.pending(&matrix).is_empty());
+
+        let running = locks.lock_deploy(&matrix, &neo).await.unwrap();
+        let queued = tokio::spawn({
+            let locks = locks.clone();
+            let matrix = matrix.clone();
+            async move { locks.lock_deploy(&matrix, &trinity).await.map(|_| ()) }
+        });
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        // the deploy waiting for its turn is listed behind the one
+        // in progress
+        let pending = locks.pending(&matrix);
+        assert_eq!(
+            pending
+                .iter()
+                .map(|deploy| (deploy.state.to_string(), deploy.requested_by.as_str()))
+                .collect::<Vec<_>>(),
+            vec![
+                ("running".to_string(), "neo"),
+                ("queued".to_string(), "trinity")
+            ]
+        );
+        assert!(pending[0].requested_at <= pending[1].requested_at);
+
+        drop(running);
+        timeout(Duration::from_secs(1), queued)
+            .await
+            .expect("the queued deploy to go ahead")
+            .unwrap()
+            .unwrap();
+        assert!(locks.pending(&matrix).is_empty());
+
+        // a deploy which is turned away is not left behind either
+        let locks = DeployLocks::new(DeployConcurrency::Reject);
+        let _running = locks.lock_deploy(&matrix, &neo).await.unwrap();
+        assert!(locks.lock_deploy(&matrix, &neo).await.is_err());
+        assert_eq!(locks.pending(&matrix).len(), 1);
+    }
+
+    #[tokio::test]
+    async fn deploys_in_transition() {
+        let matrix: ProjectName = "matrix".parse().unwrap();
+        let neo: AccountName = "neo".parse().unwrap();
+
+        let locks = DeployLocks::new(DeployConcurrency::Queue);
+        assert!(!locks.in_transition(&matrix));
+
+        // from the moment a deploy comes in
+        let guard = locks.lock_deploy(&matrix, &neo).await.unwrap();
+        assert!(locks.in_transition(&matrix));
+
+        // until a while after the deployer took it on
+        locks.handed_over(&matrix);
+        drop(guard);
+        assert!(locks.in_transition(&matrix));
+
+        locks
+            .handed_over
+            .lock()
+            .unwrap()
+            .insert(matrix.clone(), Instant::now() - DEPLOY_TRANSITION);
+        assert!(!locks.in_transition(&matrix));
+    }
+
+    #[tokio::test]
+    async fn check_archive_magic() {
+        let upload = |chunks: Vec<&'static [u8]>| {
+            let chunks = chunks.into_iter().map(Ok::<_, std::io::Error>);
+            Request::post("/projects/matrix/services/matrix")
+                .body(Body::wrap_stream(stream::iter(chunks)))
+                .unwrap()
+        };
+
+        // the magic bytes can come in more than one chunk, and the
+        // body is passed on whole all the same
+        let req = check_archive(upload(vec![
+            &b"\x1f"[..],
+            &b"\x8b\x08rest"[..],
+            &b" of it"[..],
+        ]))
+        .await
+        .unwrap();
+        let body = hyper::body::to_bytes(req.into_body()).await.unwrap();
+        assert_eq!(&body[..], b"\x1f\x8b\x08rest of it");
+
+        for chunks in [vec![], vec![&b"\x1f"[..]], vec![&b"not a crate"[..]]] {
+            assert_eq!(
+                check_archive(upload(chunks)).await.unwrap_err().kind(),
+                ErrorKind::InvalidArchive
+            );
+        }
+    }
+}
@@ -0,0 +1,154 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tokio::sync::Notify;
+use tokio::time::timeout;
+
+use crate::ProjectName;
+
+/// How long a restart waits for the requests in flight to the old
+/// backend of a project before taking it down regardless
+pub const DRAIN_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Default)]
+struct Backend {
+    in_flight: usize,
+    draining: bool,
+    idle: Arc<Notify>,
+}
+
+/// The requests the user proxy has in flight to every project backend,
+/// so that a backend about to go away can be let finish them first.
+#[derive(Clone, Default)]
+pub struct ConnectionDrainer {
+    backends: Arc<Mutex<HashMap<(ProjectName, IpAddr), Backend>>>,
+}
+
+/// A request in flight to a backend, for as long as this is held
+pub struct InFlight {
+    drainer: ConnectionDrainer,
+    backend: (ProjectName, IpAddr),
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        let mut backends = self.drainer.backends.lock().unwrap();
+        if let Some(backend) = backends.get_mut(&self.backend) {
+            backend.in_flight -= 1;
+            if backend.in_flight == 0 {
+                if backend.draining {
+                    backend.idle.notify_one();
+                } else {
+                    backends.remove(&self.backend);
+                }
+            }
+        }
+    }
+}
+
+impl ConnectionDrainer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Count a request to `project_name` at `target_ip` until the
+    /// returned guard is dropped. There is no guard for a backend being
+    /// drained, as it should not be given any new requests.
+    pub fn track(&self, project_name: &ProjectName, target_ip: IpAddr) -> Option<InFlight> {
+        let backend = (project_name.clone(), target_ip);
+
+        let mut backends = self.backends.lock().unwrap();
+        let entry = backends.entry(backend.clone()).or_default();
+        if entry.draining {
+            return None;
+        }
+        entry.in_flight += 1;
+
+        Some(InFlight {
+            drainer: self.clone(),
+            backend,
+        })
+    }
+
+    pub fn in_flight(&self, project_name: &ProjectName, target_ip: IpAddr) -> usize {
+        self.backends
+            .lock()
+            .unwrap()
+            .get(&(project_name.clone(), target_ip))
+            .map(|backend| backend.in_flight)
+            .unwrap_or_default()
+    }
+
+    /// Turn new requests away from `project_name` at `target_ip` and
+    /// wait up to `wait` for the ones in flight to be over. Returns
+    /// whether they all were. The backend is forgotten afterwards.
+    pub async fn drain(
+        &self,
+        project_name: &ProjectName,
+        target_ip: IpAddr,
+        wait: Duration,
+    ) -> bool {
+        let backend = (project_name.clone(), target_ip);
+
+        let idle = {
+            let mut backends = self.backends.lock().unwrap();
+            let entry = backends.entry(backend.clone()).or_default();
+            if entry.in_flight == 0 {
+                backends.remove(&backend);
+                return true;
+            }
+            entry.draining = true;
+            entry.idle.clone()
+        };
+
+        let drained = timeout(wait, idle.notified()).await.is_ok();
+        self.backends.lock().unwrap().remove(&backend);
+
+        drained
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn connection_drainer() {
+        let drainer = ConnectionDrainer::new();
+        let matrix: ProjectName = "matrix".parse().unwrap();
+        let old: IpAddr = "10.0.0.1".parse().unwrap();
+        let new: IpAddr = "10.0.0.2".parse().unwrap();
+
+        // nothing in flight, nothing to wait for
+        assert!(drainer.drain(&matrix, old, Duration::ZERO).await);
+
+        let first = drainer.track(&matrix, old).unwrap();
+        let second = drainer.track(&matrix, old).unwrap();
+        assert_eq!(drainer.in_flight(&matrix, old), 2);
+
+        let draining = tokio::spawn({
+            let drainer = drainer.clone();
+            let matrix = matrix.clone();
+            async move { drainer.drain(&matrix, old, Duration::from_secs(5)).await }
+        });
+        tokio::time::sleep(Duration::from_millis(10)).await;
+
+        // the old backend takes nothing new while the new one does
+        assert!(drainer.track(&matrix, old).is_none());
+        assert!(drainer.track(&matrix, new).is_some());
+
+        drop(first);
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert!(!draining.is_finished());
+
+        drop(second);
+        assert!(draining.await.unwrap());
+        assert_eq!(drainer.in_flight(&matrix, old), 0);
+
+        // a request which never finishes is given up on
+        let _stuck = drainer.track(&matrix, new).unwrap();
+        assert!(!drainer.drain(&matrix, new, Duration::from_millis(10)).await);
+    }
+}
@@ -0,0 +1,127 @@
+use std::net::{IpAddr, SocketAddr};
+
+use tokio::net::lookup_host;
+
+/// Where a request the gateway makes on behalf of a project goes
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Destination {
+    /// The host, unless it is an address already
+    pub domain: Option<String>,
+    pub port: u16,
+    /// What the host resolved to, never empty
+    pub addrs: Vec<SocketAddr>,
+}
+
+/// Resolve the host of `url`, refusing it if any of its addresses is
+/// not [global](is_global) unless `allow_private`. Fails with why the
+/// URL cannot be sent to.
+pub async fn resolve(url: &reqwest::Url, allow_private: bool) -> Result<Destination, String> {
+    let host = url.host_str().ok_or("the URL has no host")?;
+    let port = url.port_or_known_default().ok_or("the URL has no port")?;
+
+    // IPv6 literals keep their brackets in URLs
+    let (domain, addrs) = match host
+        .trim_start_matches('[')
+        .trim_end_matches(']')
+        .parse::<IpAddr>()
+    {
+        Ok(ip) => (None, vec![SocketAddr::new(ip, port)]),
+        Err(_) => {
+            let addrs: Vec<_> = lookup_host((host, port))
+                .await
+                .map_err(|err| format!("could not resolve {host}: {err}"))?
+                .collect();
+            (Some(host.to_string()), addrs)
+        }
+    };
+
+    if addrs.is_empty() {
+        return Err(format!("{host} does not resolve to any address"));
+    }
+
+    if !allow_private && !addrs.iter().all(|addr| is_global(addr.ip())) {
+        return Err(format!("{host} is not a public address"));
+    }
+
+    Ok(Destination {
+        domain,
+        port,
+        addrs,
+    })
+}
+
+/// Whether `ip` is an address of the public internet, which requests
+/// made on behalf of projects are restricted to so that projects cannot
+/// use them to reach the gateway, its network or the metadata service
+/// of its cloud
+pub fn is_global(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, c, _] = ip.octets();
+            !(ip.is_unspecified()
+                || ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_broadcast()
+                || ip.is_documentation()
+                || ip.is_multicast()
+                // Shared address space of carrier-grade NATs
+                || (a == 100 && (64..128).contains(&b))
+                // IETF protocol assignments
+                || (a == 192 && b == 0 && c == 0)
+                // Benchmarking
+                || (a == 198 && (18..20).contains(&b))
+                // Reserved
+                || a >= 240)
+        }
+        IpAddr::V6(ip) => {
+            if let Some(ip) = ip.to_ipv4_mapped() {
+                return is_global(IpAddr::V4(ip));
+            }
+
+            let [first, second, ..] = ip.segments();
+            !(ip.is_unspecified()
+                || ip.is_loopback()
+                || ip.is_multicast()
+                // Unique local
+                || (first & 0xfe00) == 0xfc00
+                // Link local
+                || (first & 0xffc0) == 0xfe80
+                // Documentation
+                || (first == 0x2001 && second == 0x0db8)
+                // Translated to IPv4, which may be private
+                || (first == 0x0064 && second == 0xff9b))
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    #[test]
+    fn global_addresses() {
+        for ip in ["1.1.1.1", "140.82.121.4", "2606:4700::1111"] {
+            assert!(is_global(ip.parse().unwrap()), "{ip} is global");
+        }
+
+        for ip in [
+            "0.0.0.0",
+            "127.0.0.1",
+            "10.1.2.3",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.169.254",
+            "100.64.0.1",
+            "255.255.255.255",
+            "::",
+            "::1",
+            "fd00::1",
+            "fe80::1",
+            "::ffff:10.0.0.1",
+            "64:ff9b::a00:1",
+        ] {
+            assert!(!is_global(ip.parse().unwrap()), "{ip} is not global");
+        }
+    }
+}
@@ -0,0 +1,176 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, NONCE_LEN};
+use ring::rand::{SecureRandom, SystemRandom};
+use shuttle_common::models::error::ErrorKind;
+use tracing::{error, info};
+
+use crate::{Error, ProjectName};
+
+/// What is returned in place of the value of an environment variable
+pub const REDACTED: &str = "********";
+
+const KEY_LEN: usize = 32;
+
+/// Encrypts and decrypts project environment variables so that they
+/// are never stored in plaintext.
+///
+/// Every value is sealed with a fresh nonce and bound to the project
+/// and variable name it belongs to, so a value cannot be swapped over
+/// to a different row without failing to decrypt.
+#[derive(Clone)]
+pub struct EnvCipher {
+    key: Arc<LessSafeKey>,
+    rng: SystemRandom,
+}
+
+impl EnvCipher {
+    pub fn new(key: &[u8]) -> Result<Self, Error> {
+        let key = UnboundKey::new(&AES_256_GCM, key)
+            .map_err(|_| Error::custom(ErrorKind::Internal, "invalid env encryption key"))?;
+        Ok(Self {
+            key: Arc::new(LessSafeKey::new(key)),
+            rng: SystemRandom::new(),
+        })
+    }
+
+    /// Create a cipher with a random key which is not persisted
+    /// anywhere. Values encrypted with it are lost on restart.
+    pub fn new_random() -> Self {
+        let key = Self::random_key().expect("the system RNG to be available");
+        Self::new(&key).unwrap()
+    }
+
+    /// Load the key at `path`, generating a new one if there is none
+    pub fn load_or_create<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let path = path.as_ref();
+
+        let key = if path.exists() {
+            fs::read(path)?
+        } else {
+            info!(
+                "no env encryption key found at {}, creating one",
+                path.display()
+            );
+            let key = Self::random_key()?;
+            fs::write(path, key)?;
+            key.to_vec()
+        };
+
+        Self::new(&key).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+    }
+
+    fn random_key() -> io::Result<[u8; KEY_LEN]> {
+        let mut key = [0u8; KEY_LEN];
+        SystemRandom::new()
+            .fill(&mut key)
+            .map_err(|_| io::Error::new(io::ErrorKind::Other, "failed to generate a key"))?;
+        Ok(key)
+    }
+
+    pub fn encrypt(
+        &self,
+        project_name: &ProjectName,
+        name: &str,
+        value: &str,
+    ) -> Result<Vec<u8>, Error> {
+        let mut nonce = [0u8; NONCE_LEN];
+        self.rng
+            .fill(&mut nonce)
+            .map_err(|_| Error::custom(ErrorKind::Internal, "failed to generate a nonce"))?;
+
+        let mut sealed = value.as_bytes().to_vec();
+        self.key
+            .seal_in_place_append_tag(
+                Nonce::assume_unique_for_key(nonce),
+                Self::aad(project_name, name),
+                &mut sealed,
+            )
+            .map_err(|_| Error::custom(ErrorKind::Internal, "failed to encrypt env var"))?;
+
+        let mut out = nonce.to_vec();
+        out.extend(sealed);
+        Ok(out)
+    }
+
+    pub fn decrypt(
+        &self,
+        project_name: &ProjectName,
+        name: &str,
+        data: &[u8],
+    ) -> Result<String, Error> {
+        if data.len() < NONCE_LEN {
+            return Err(Error::custom(ErrorKind::Internal, "env var is malformed"));
+        }
+
+        let (nonce, sealed) = data.split_at(NONCE_LEN);
+        let nonce = Nonce::try_assume_unique_for_key(nonce)
+            .map_err(|_| Error::custom(ErrorKind::Internal, "env var is malformed"))?;
+
+        let mut sealed = sealed.to_vec();
+        let value = self
+            .key
+            .open_in_place(nonce, Self::aad(project_name, name), &mut sealed)
+            .map_err(|_| {
+                error!(%project_name, name, "failed to decrypt env var");
+                Error::custom(ErrorKind::Internal, "failed to decrypt env var")
+            })?;
+
+        String::from_utf8(value.to_vec())
+            .map_err(|_| Error::custom(ErrorKind::Internal, "env var is not valid UTF-8"))
+    }
+
+    fn aad(project_name: &ProjectName, name: &str) -> Aad<Vec<u8>> {
+        Aad::from(format!("{project_name}/{name}").into_bytes())
+    }
+}
+
+/// Names have to be usable from a shell: a letter or `_` followed by
+/// letters, digits or `_`
+pub fn is_valid_name(name: &str) -> bool {
+    let mut bytes = name.bytes();
+    matches!(bytes.next(), Some(b'a'..=b'z' | b'A'..=b'Z' | b'_'))
+        && bytes.all(|byte| matches!(byte, b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'_'))
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    #[test]
+    fn env_cipher_round_trip() {
+        let cipher = EnvCipher::new_random();
+        let matrix: ProjectName = "matrix".parse().unwrap();
+        let reloaded: ProjectName = "reloaded".parse().unwrap();
+
+        let sealed = cipher
+            .encrypt(&matrix, "DATABASE_URL", "postgres://neo")
+            .unwrap();
+        assert!(!sealed
+            .windows("postgres://neo".len())
+            .any(|window| window == b"postgres://neo"));
+
+        assert_eq!(
+            cipher.decrypt(&matrix, "DATABASE_URL", &sealed).unwrap(),
+            "postgres://neo"
+        );
+
+        // bound to the project and name it was encrypted for
+        assert!(cipher.decrypt(&reloaded, "DATABASE_URL", &sealed).is_err());
+        assert!(cipher.decrypt(&matrix, "API_KEY", &sealed).is_err());
+    }
+
+    #[test]
+    fn env_var_names() {
+        for name in ["FOO", "_FOO", "foo_bar", "FOO2"] {
+            assert!(is_valid_name(name), "{name} should be valid");
+        }
+
+        for name in ["", "2FOO", "FOO=BAR", "FOO-BAR", "FOO BAR"] {
+            assert!(!is_valid_name(name), "{name} should be invalid");
+        }
+    }
+}
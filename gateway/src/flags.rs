@@ -0,0 +1,104 @@
+use std::collections::{BTreeSet, HashMap};
+use std::sync::{Arc, RwLock};
+
+use shuttle_common::models::project::FeatureFlag;
+
+use crate::ProjectName;
+
+/// The feature flags turned on for each project. Flags which are not
+/// turned on for a project are off.
+#[derive(Clone, Default)]
+pub struct FeatureFlags {
+    table: Arc<RwLock<HashMap<ProjectName, BTreeSet<FeatureFlag>>>>,
+}
+
+impl FeatureFlags {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Turn a flag on or off for a project. Takes effect for the very
+    /// next request.
+    pub fn set(&self, project_name: &ProjectName, flag: FeatureFlag, enabled: bool) {
+        let mut table = self.table.write().unwrap();
+        if enabled {
+            table.entry(project_name.clone()).or_default().insert(flag);
+        } else if let Some(flags) = table.get_mut(project_name) {
+            flags.remove(&flag);
+            if flags.is_empty() {
+                table.remove(project_name);
+            }
+        }
+    }
+
+    pub fn is_enabled(&self, project_name: &ProjectName, flag: FeatureFlag) -> bool {
+        self.table
+            .read()
+            .unwrap()
+            .get(project_name)
+            .map(|flags| flags.contains(&flag))
+            .unwrap_or(false)
+    }
+
+    pub fn flags(&self, project_name: &ProjectName) -> BTreeSet<FeatureFlag> {
+        self.table
+            .read()
+            .unwrap()
+            .get(project_name)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// Turn on exactly `flags` for a project, turning off the others
+    pub fn replace(&self, project_name: &ProjectName, flags: BTreeSet<FeatureFlag>) {
+        let mut table = self.table.write().unwrap();
+        if flags.is_empty() {
+            table.remove(project_name);
+        } else {
+            table.insert(project_name.clone(), flags);
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    #[test]
+    fn feature_flags() {
+        let flags = FeatureFlags::new();
+        let matrix: ProjectName = "matrix".parse().unwrap();
+        let zion: ProjectName = "zion".parse().unwrap();
+
+        flags.set(&matrix, FeatureFlag::Http2Upstream, true);
+        flags.set(&matrix, FeatureFlag::BufferedBodies, true);
+        assert!(flags.is_enabled(&matrix, FeatureFlag::Http2Upstream));
+        assert!(!flags.is_enabled(&zion, FeatureFlag::Http2Upstream));
+
+        flags.set(&matrix, FeatureFlag::Http2Upstream, false);
+        assert_eq!(
+            flags.flags(&matrix),
+            BTreeSet::from([FeatureFlag::BufferedBodies])
+        );
+
+        flags.replace(&zion, flags.flags(&matrix));
+        flags.replace(&matrix, BTreeSet::new());
+        assert!(flags.flags(&matrix).is_empty());
+        assert!(flags.is_enabled(&zion, FeatureFlag::BufferedBodies));
+
+        // turning off a flag which is not on is fine
+        flags.set(&matrix, FeatureFlag::BufferedBodies, false);
+        assert!(flags.flags(&matrix).is_empty());
+    }
+
+    #[test]
+    fn feature_flag_names() {
+        for (name, flag) in [
+            ("http2-upstream", FeatureFlag::Http2Upstream),
+            ("buffered-bodies", FeatureFlag::BufferedBodies),
+        ] {
+            assert_eq!(name.parse::<FeatureFlag>().unwrap(), flag);
+            assert_eq!(flag.to_string(), name);
+        }
+    }
+}
@@ -0,0 +1,152 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, RwLock};
+
+use ipnet::IpNet;
+use shuttle_common::models::error::ErrorKind;
+use shuttle_common::models::project::IpFilter;
+
+use crate::{Error, ProjectName};
+
+/// The parsed ranges of an [`IpFilter`]
+#[derive(Debug)]
+pub struct IpRules {
+    pub filter: IpFilter,
+    allow: Vec<IpNet>,
+    deny: Vec<IpNet>,
+}
+
+impl IpRules {
+    /// Check every range of `filter`. A bare address stands for a
+    /// range of its own.
+    pub fn new(filter: IpFilter) -> Result<Self, Error> {
+        let parse = |range: &String| {
+            range
+                .parse::<IpNet>()
+                .or_else(|_| range.parse::<IpAddr>().map(IpNet::from))
+                .map_err(|_| {
+                    Error::custom(
+                        ErrorKind::InvalidIpFilter,
+                        format!("invalid IP range: {range}"),
+                    )
+                })
+        };
+
+        Ok(Self {
+            allow: filter
+                .allow
+                .iter()
+                .map(parse)
+                .collect::<Result<_, Error>>()?,
+            deny: filter
+                .deny
+                .iter()
+                .map(parse)
+                .collect::<Result<_, Error>>()?,
+            filter,
+        })
+    }
+
+    /// Denied ranges win over allowed ones, and an empty allow list
+    /// lets in everyone who is not denied
+    pub fn allows(&self, ip: IpAddr) -> bool {
+        let ip = unmap(ip);
+
+        if self.deny.iter().any(|range| range.contains(&ip)) {
+            return false;
+        }
+
+        self.allow.is_empty() || self.allow.iter().any(|range| range.contains(&ip))
+    }
+}
+
+/// IPv4 clients of a dual-stack socket show up as IPv4-mapped IPv6
+/// addresses, which IPv4 ranges would never match
+fn unmap(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V6(v6) => match v6.segments() {
+            [0, 0, 0, 0, 0, 0xffff, ..] => {
+                let [.., a, b, c, d] = v6.octets();
+                IpAddr::from([a, b, c, d])
+            }
+            _ => ip,
+        },
+        ip => ip,
+    }
+}
+
+/// Per-project IP filters enforced by the user proxy. Projects
+/// without a filter can be reached from anywhere.
+#[derive(Clone, Default)]
+pub struct IpFilters {
+    table: Arc<RwLock<HashMap<ProjectName, Arc<IpRules>>>>,
+}
+
+impl IpFilters {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set or clear the filter of a project. Takes effect for the
+    /// very next request.
+    pub fn set_rules(&self, project_name: &ProjectName, rules: Option<IpRules>) {
+        let mut table = self.table.write().unwrap();
+        match rules {
+            Some(rules) => {
+                table.insert(project_name.clone(), Arc::new(rules));
+            }
+            None => {
+                table.remove(project_name);
+            }
+        }
+    }
+
+    pub fn rules(&self, project_name: &ProjectName) -> Option<Arc<IpRules>> {
+        self.table.read().unwrap().get(project_name).cloned()
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    #[test]
+    fn ip_rules() {
+        let rules = IpRules::new(IpFilter {
+            allow: vec!["10.0.0.0/8".to_string(), "2001:db8::/32".to_string()],
+            deny: vec!["10.6.6.0/24".to_string(), "10.1.1.1".to_string()],
+        })
+        .unwrap();
+
+        for (ip, allowed) in [
+            ("10.1.2.3", true),
+            ("::ffff:10.1.2.3", true),
+            ("2001:db8::1", true),
+            ("10.6.6.6", false),
+            ("10.1.1.1", false),
+            ("192.168.0.1", false),
+            ("::ffff:192.168.0.1", false),
+            ("2001:db9::1", false),
+        ] {
+            assert_eq!(rules.allows(ip.parse().unwrap()), allowed, "{ip}");
+        }
+
+        // without an allow list only the denied are kept out
+        let rules = IpRules::new(IpFilter {
+            allow: Vec::new(),
+            deny: vec!["10.6.6.0/24".to_string()],
+        })
+        .unwrap();
+        assert!(rules.allows("192.168.0.1".parse().unwrap()));
+        assert!(!rules.allows("10.6.6.6".parse().unwrap()));
+
+        for range in ["10.0.0.0/33", "10.0.0", "zion", ""] {
+            let err = IpRules::new(IpFilter {
+                allow: vec![range.to_string()],
+                deny: Vec::new(),
+            })
+            .unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::InvalidIpFilter);
+        }
+    }
+}
@@ -0,0 +1,378 @@
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use jsonwebtoken::jwk::{AlgorithmParameters, EllipticCurve, Jwk};
+use jsonwebtoken::{decode, decode_header, Algorithm, DecodingKey, Validation};
+use serde::Deserialize;
+use shuttle_common::models::error::ErrorKind;
+use tokio::sync::RwLock;
+use tracing::{debug, warn};
+
+use crate::{AccountName, Error};
+
+/// The claim holding the account name when none is configured
+pub const DEFAULT_ACCOUNT_CLAIM: &str = "sub";
+
+/// How long keys fetched from a JWKS URL are used before they are
+/// fetched again
+pub const JWKS_REFRESH_INTERVAL: Duration = Duration::from_secs(300);
+
+/// Minimum time between two fetches of a JWKS URL, so that tokens
+/// with unknown key ids cannot make us hammer the identity provider
+pub const JWKS_MIN_REFRESH_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Clock skew tolerated when checking `exp` and `nbf`
+pub const JWT_LEEWAY: Duration = Duration::from_secs(30);
+
+/// Whether `token` has the shape of a JWT: three base64url segments
+pub fn is_jwt(token: &str) -> bool {
+    let mut segments = 0;
+    for segment in token.split('.') {
+        if segment.is_empty()
+            || !segment
+                .bytes()
+                .all(|byte| byte.is_ascii_alphanumeric() || byte == b'-' || byte == b'_')
+        {
+            return false;
+        }
+        segments += 1;
+    }
+    segments == 3
+}
+
+/// A key tokens can be signed with, the algorithm it signs with and
+/// the `kid` it goes by
+#[derive(Clone)]
+pub struct JwtKey {
+    kid: Option<String>,
+    algorithm: Algorithm,
+    key: DecodingKey,
+}
+
+impl JwtKey {
+    /// Parse a PEM encoded `PUBLIC KEY` (RSA, P-256 or Ed25519) or
+    /// `RSA PUBLIC KEY`
+    pub fn from_pem(pem: &str) -> Result<Self, Error> {
+        let pem = pem.as_bytes();
+
+        let (key, algorithm) = if let Ok(key) = DecodingKey::from_rsa_pem(pem) {
+            (key, Algorithm::RS256)
+        } else if let Ok(key) = DecodingKey::from_ec_pem(pem) {
+            (key, Algorithm::ES256)
+        } else if let Ok(key) = DecodingKey::from_ed_pem(pem) {
+            (key, Algorithm::EdDSA)
+        } else {
+            return Err(Error::custom(
+                ErrorKind::Internal,
+                "unsupported JWT public key",
+            ));
+        };
+
+        Ok(Self {
+            kid: None,
+            algorithm,
+            key,
+        })
+    }
+
+    fn from_jwk(jwk: &Jwk) -> Option<Self> {
+        let algorithm = match &jwk.algorithm {
+            AlgorithmParameters::RSA(_) => Algorithm::RS256,
+            AlgorithmParameters::EllipticCurve(params)
+                if matches!(params.curve, EllipticCurve::P256) =>
+            {
+                Algorithm::ES256
+            }
+            AlgorithmParameters::OctetKeyPair(params)
+                if matches!(params.curve, EllipticCurve::Ed25519) =>
+            {
+                Algorithm::EdDSA
+            }
+            _ => return None,
+        };
+
+        Some(Self {
+            kid: jwk.common.key_id.clone(),
+            algorithm,
+            key: DecodingKey::from_jwk(jwk).ok()?,
+        })
+    }
+}
+
+#[derive(Deserialize)]
+struct JwkSet {
+    /// Kept raw so that keys we cannot use do not spoil the others
+    keys: Vec<serde_json::Value>,
+}
+
+enum KeySource {
+    Static(JwtKey),
+    Jwks {
+        url: String,
+        client: reqwest::Client,
+        /// The keys last fetched and when they were fetched
+        keys: RwLock<Option<(Instant, Vec<JwtKey>)>>,
+    },
+}
+
+/// Verifies JWTs issued by an external identity provider and maps
+/// them to the account they were issued for
+#[derive(Clone)]
+pub struct JwtVerifier {
+    source: Arc<KeySource>,
+    issuer: String,
+    audience: String,
+    account_claim: String,
+}
+
+impl JwtVerifier {
+    /// Verify tokens issued by `issuer` for `audience` against a
+    /// single, static public key
+    pub fn from_key(key: JwtKey, issuer: String, audience: String) -> Self {
+        Self {
+            source: Arc::new(KeySource::Static(key)),
+            issuer,
+            audience,
+            account_claim: DEFAULT_ACCOUNT_CLAIM.to_string(),
+        }
+    }
+
+    /// Verify tokens issued by `issuer` for `audience` against the
+    /// keys published at `url`
+    pub fn from_jwks_url(url: String, issuer: String, audience: String) -> Self {
+        Self {
+            source: Arc::new(KeySource::Jwks {
+                url,
+                client: reqwest::Client::new(),
+                keys: RwLock::new(None),
+            }),
+            issuer,
+            audience,
+            account_claim: DEFAULT_ACCOUNT_CLAIM.to_string(),
+        }
+    }
+
+    /// Read the account name from `claim` instead of `sub`
+    pub fn with_account_claim(mut self, claim: String) -> Self {
+        self.account_claim = claim;
+        self
+    }
+
+    /// Check the signature, issuer, audience and validity period of
+    /// `token` and return the account it was issued for. Fails with
+    /// [`ErrorKind::Unauthorized`] for anything but a valid token.
+    pub async fn verify(&self, token: &str) -> Result<AccountName, Error> {
+        let unauthorized = |message: &str| Error::custom(ErrorKind::Unauthorized, message);
+
+        let header = decode_header(token).map_err(|_| unauthorized("malformed JWT"))?;
+        let algorithm = match header.alg {
+            algorithm @ (Algorithm::RS256 | Algorithm::ES256 | Algorithm::EdDSA) => algorithm,
+            _ => return Err(unauthorized("unsupported JWT algorithm")),
+        };
+
+        let mut validation = Validation::new(algorithm);
+        validation.leeway = JWT_LEEWAY.as_secs();
+        validation.validate_nbf = true;
+        validation.set_issuer(&[&self.issuer]);
+        validation.set_audience(&[&self.audience]);
+        validation.set_required_spec_claims(&["exp", "iss", "aud"]);
+
+        let keys = self.keys(header.kid.as_deref()).await?;
+        let mut claims = None;
+        for key in keys.iter().filter(|key| key.algorithm == algorithm) {
+            match decode::<serde_json::Value>(token, &key.key, &validation) {
+                Ok(data) => {
+                    claims = Some(data.claims);
+                    break;
+                }
+                Err(err) if *err.kind() == jsonwebtoken::errors::ErrorKind::InvalidSignature => {}
+                // The signature checked out, the claims did not
+                Err(err) => return Err(unauthorized(rejection(err.kind()))),
+            }
+        }
+        let claims = claims.ok_or_else(|| unauthorized("invalid JWT signature"))?;
+
+        claims
+            .get(&self.account_claim)
+            .and_then(serde_json::Value::as_str)
+            .ok_or_else(|| unauthorized("JWT does not name an account"))?
+            .parse()
+    }
+
+    /// The keys a token with `kid` may have been signed with
+    async fn keys(&self, kid: Option<&str>) -> Result<Vec<JwtKey>, Error> {
+        let (url, client, cached) = match self.source.as_ref() {
+            KeySource::Static(key) => return Ok(vec![key.clone()]),
+            KeySource::Jwks { url, client, keys } => (url, client, keys),
+        };
+
+        let matches = |key: &JwtKey| kid.is_none() || key.kid.as_deref() == kid;
+
+        if let Some((fetched_at, keys)) = cached.read().await.as_ref() {
+            let age = fetched_at.elapsed();
+            let known = keys.iter().any(matches);
+            if age < JWKS_REFRESH_INTERVAL && (known || age < JWKS_MIN_REFRESH_INTERVAL) {
+                return Ok(keys.iter().filter(|&key| matches(key)).cloned().collect());
+            }
+        }
+
+        let mut cached = cached.write().await;
+
+        debug!(url, "fetching JWKS");
+        let keys = match fetch_jwks(client, url).await {
+            Ok(keys) => keys,
+            Err(err) => {
+                warn!(url, error = %err, "failed to fetch JWKS");
+                // Keep going with what was fetched before rather than
+                // locking everyone out while the provider is down
+                match cached.as_ref() {
+                    Some((_, keys)) => keys.clone(),
+                    None => return Err(Error::source(ErrorKind::Unauthorized, err)),
+                }
+            }
+        };
+        *cached = Some((Instant::now(), keys.clone()));
+
+        Ok(keys.into_iter().filter(|key| matches(key)).collect())
+    }
+}
+
+/// Why a JWT with a valid signature is turned down
+fn rejection(kind: &jsonwebtoken::errors::ErrorKind) -> &'static str {
+    use jsonwebtoken::errors::ErrorKind::*;
+
+    match kind {
+        ExpiredSignature => "JWT has expired",
+        ImmatureSignature => "JWT is not valid yet",
+        InvalidIssuer => "JWT was issued by someone else",
+        InvalidAudience => "JWT is meant for someone else",
+        MissingRequiredClaim(claim) if claim == "exp" => "JWT has no expiry",
+        MissingRequiredClaim(_) => "JWT is missing its issuer or audience",
+        _ => "invalid JWT",
+    }
+}
+
+async fn fetch_jwks(client: &reqwest::Client, url: &str) -> Result<Vec<JwtKey>, reqwest::Error> {
+    let set: JwkSet = client
+        .get(url)
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+
+    Ok(set
+        .keys
+        .into_iter()
+        .filter_map(|jwk| serde_json::from_value(jwk).ok())
+        .filter_map(|jwk| JwtKey::from_jwk(&jwk))
+        .collect())
+}
+
+#[cfg(test)]
+pub mod tests {
+    use ring::rand::SystemRandom;
+    use ring::signature::{Ed25519KeyPair, KeyPair};
+    use serde_json::json;
+
+    use super::*;
+
+    /// `SubjectPublicKeyInfo` of an Ed25519 key, up to the key itself
+    const ED25519_SPKI_PREFIX: [u8; 12] = [
+        0x30, 0x2a, 0x30, 0x05, 0x06, 0x03, 0x2b, 0x65, 0x70, 0x03, 0x21, 0x00,
+    ];
+
+    fn sign(key_pair: &Ed25519KeyPair, claims: serde_json::Value) -> String {
+        let encode = |value: serde_json::Value| {
+            base64::encode_config(value.to_string(), base64::URL_SAFE_NO_PAD)
+        };
+
+        let message = format!(
+            "{}.{}",
+            encode(json!({ "alg": "EdDSA", "typ": "JWT" })),
+            encode(claims)
+        );
+        let signature = key_pair.sign(message.as_bytes());
+
+        format!(
+            "{message}.{}",
+            base64::encode_config(signature.as_ref(), base64::URL_SAFE_NO_PAD)
+        )
+    }
+
+    #[tokio::test]
+    async fn jwt_verifier() {
+        let pkcs8 = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new()).unwrap();
+        let key_pair = Ed25519KeyPair::from_pkcs8(pkcs8.as_ref()).unwrap();
+
+        let mut spki = ED25519_SPKI_PREFIX.to_vec();
+        spki.extend(key_pair.public_key().as_ref());
+        let pem = pem::encode(&pem::Pem {
+            tag: "PUBLIC KEY".to_string(),
+            contents: spki,
+        });
+
+        let verifier = JwtVerifier::from_key(
+            JwtKey::from_pem(&pem).unwrap(),
+            "https://idp.example".to_string(),
+            "gateway".to_string(),
+        )
+        .with_account_claim("account".to_string());
+
+        let now = chrono::Utc::now().timestamp();
+        let claims = |account: &str, exp: i64| {
+            json!({
+                "account": account,
+                "exp": exp,
+                "iss": "https://idp.example",
+                "aud": "gateway",
+            })
+        };
+
+        let valid = sign(&key_pair, claims("neo", now + 3600));
+        assert!(is_jwt(&valid));
+        assert_eq!(
+            verifier.verify(&valid).await.unwrap(),
+            "neo".parse::<AccountName>().unwrap()
+        );
+
+        let expired = sign(&key_pair, claims("neo", now - 3600));
+        assert_eq!(
+            verifier.verify(&expired).await.unwrap_err().kind(),
+            ErrorKind::Unauthorized
+        );
+
+        // tokens of the same provider meant for another service, or
+        // signed by the same key for another issuer, are turned down
+        let mut other_audience = claims("neo", now + 3600);
+        other_audience["aud"] = json!("billing");
+        let mut other_issuer = claims("neo", now + 3600);
+        other_issuer["iss"] = json!("https://evil.example");
+        let mut no_audience = claims("neo", now + 3600);
+        no_audience.as_object_mut().unwrap().remove("aud");
+        for claims in [other_audience, other_issuer, no_audience] {
+            let token = sign(&key_pair, claims);
+            assert_eq!(
+                verifier.verify(&token).await.unwrap_err().kind(),
+                ErrorKind::Unauthorized
+            );
+        }
+
+        // swap the claims of a valid token for someone else's
+        let (header, rest) = valid.split_once('.').unwrap();
+        let (_, signature) = rest.split_once('.').unwrap();
+        let tampered = format!(
+            "{header}.{}.{signature}",
+            base64::encode_config(
+                claims("smith", now + 3600).to_string(),
+                base64::URL_SAFE_NO_PAD
+            )
+        );
+        assert_eq!(
+            verifier.verify(&tampered).await.unwrap_err().kind(),
+            ErrorKind::Unauthorized
+        );
+
+        assert!(!is_jwt("not-a-jwt"));
+    }
+}
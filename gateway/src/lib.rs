@@ -1,35 +1,70 @@
 #[macro_use]
 extern crate async_trait;
 
+use std::collections::HashMap;
 use std::convert::Infallible;
 use std::error::Error as StdError;
 use std::fmt::Formatter;
 use std::io;
 use std::pin::Pin;
 use std::str::FromStr;
+use std::time::{Duration, Instant};
 
 use acme::AcmeClientError;
+use axum::http::header::RETRY_AFTER;
+use axum::http::HeaderValue;
 use axum::response::{IntoResponse, Response};
 use axum::Json;
+use bollard::container::{Config, CreateContainerOptions, ListContainersOptions, LogsOptions};
+use bollard::errors::Error as DockerError;
+use bollard::image::CreateImageOptions;
 use bollard::Docker;
+use futures::future::BoxFuture;
 use futures::prelude::*;
+use futures::stream::BoxStream;
 use serde::{Deserialize, Deserializer, Serialize};
 use shuttle_common::models::error::{ApiError, ErrorKind};
+use shuttle_common::project::ProjectNameError;
 use tokio::sync::mpsc::error::SendError;
-use tracing::error;
+use tracing::{error, field, info, info_span, Instrument};
 
 pub mod acme;
+pub mod activity;
 pub mod api;
 pub mod args;
 pub mod auth;
+pub mod backup;
+pub mod basicauth;
+pub mod breaker;
+pub mod cache;
+pub mod canary;
+pub mod connlimit;
+pub mod counters;
+pub mod deploy;
+pub mod drain;
+pub mod egress;
+pub mod env;
+pub mod flags;
+pub mod git;
+pub mod ipfilter;
+pub mod jwt;
+pub mod listener;
+pub mod loglevel;
 pub mod project;
 pub mod proxy;
+pub mod ratelimit;
+pub mod respcache;
+pub mod rewrite;
+pub mod rollout;
 pub mod service;
 pub mod task;
 pub mod tls;
+pub mod warm;
+pub mod webhook;
 pub mod worker;
 
 use crate::service::{ContainerSettings, GatewayService};
+use crate::warm::WarmPool;
 
 /// Server-side errors that do not have to do with the user runtime
 /// should be [`Error`]s.
@@ -43,6 +78,9 @@ use crate::service::{ContainerSettings, GatewayService};
 pub struct Error {
     kind: ErrorKind,
     source: Option<Box<dyn StdError + Sync + Send + 'static>>,
+    suggestions: Vec<String>,
+    detail: Option<String>,
+    retry_after: Option<Duration>,
 }
 
 impl Error {
@@ -50,6 +88,9 @@ impl Error {
         Self {
             kind,
             source: Some(Box::new(err)),
+            suggestions: Vec::new(),
+            detail: None,
+            retry_after: None,
         }
     }
 
@@ -60,16 +101,62 @@ impl Error {
                 io::ErrorKind::Other,
                 message.as_ref().to_string(),
             ))),
+            suggestions: Vec::new(),
+            detail: None,
+            retry_after: None,
         }
     }
 
     pub fn from_kind(kind: ErrorKind) -> Self {
-        Self { kind, source: None }
+        Self {
+            kind,
+            source: None,
+            suggestions: Vec::new(),
+            detail: None,
+            retry_after: None,
+        }
+    }
+
+    /// Attach alternatives the user could try instead. These are
+    /// returned as part of the response.
+    pub fn with_suggestions<I, S>(mut self, suggestions: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: ToString,
+    {
+        self.suggestions = suggestions.into_iter().map(|s| s.to_string()).collect();
+        self
+    }
+
+    /// Explain to the user what exactly went wrong. This is added to
+    /// the message of the response.
+    pub fn with_detail<D: ToString>(mut self, detail: D) -> Self {
+        self.detail = Some(detail.to_string());
+        self
+    }
+
+    /// Tell the client when it is worth trying again, through a
+    /// `Retry-After` header on the response
+    pub fn with_retry_after(mut self, retry_after: Duration) -> Self {
+        self.retry_after = Some(retry_after);
+        self
     }
 
     pub fn kind(&self) -> ErrorKind {
         self.kind
     }
+
+    pub fn retry_after(&self) -> Option<Duration> {
+        self.retry_after
+    }
+
+    pub fn detail(&self) -> Option<&str> {
+        self.detail.as_deref()
+    }
+
+    pub fn suggestions(&self) -> &[String] {
+        &self.suggestions
+    }
 }
 
 impl From<ErrorKind> for Error {
@@ -92,7 +179,19 @@ impl From<io::Error> for Error {
 
 impl From<AcmeClientError> for Error {
     fn from(error: AcmeClientError) -> Self {
-        Self::source(ErrorKind::Internal, error)
+        match error {
+            AcmeClientError::RateLimited { retry_after } => {
+                let error = Self::custom(
+                    ErrorKind::ServiceUnavailable,
+                    "the certificate authority is rate limiting new certificates",
+                );
+                match retry_after {
+                    Some(retry_after) => error.with_retry_after(retry_after),
+                    None => error,
+                }
+            }
+            error => Self::source(ErrorKind::Internal, error),
+        }
     }
 }
 
@@ -100,9 +199,22 @@ impl IntoResponse for Error {
     fn into_response(self) -> Response {
         error!(error = %self, "request had an error");
 
-        let error: ApiError = self.kind.into();
+        let mut error: ApiError = self.kind.into();
+        error.suggestions = self.suggestions;
+        if let Some(detail) = self.detail {
+            error.message = format!("{detail}\n{}", error.message);
+        }
+
+        let mut response = (error.status(), Json(error)).into_response();
+        if let Some(retry_after) = self.retry_after {
+            // Retry-After only has a precision of seconds
+            let secs = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+            response
+                .headers_mut()
+                .insert(RETRY_AFTER, HeaderValue::from(secs.max(1)));
+        }
 
-        (error.status(), Json(error)).into_response()
+        response
     }
 }
 
@@ -129,20 +241,51 @@ impl ProjectName {
     }
 
     pub fn is_valid(&self) -> bool {
-        let name = self.0.clone();
+        self.validate().is_ok()
+    }
 
-        fn is_valid_char(byte: u8) -> bool {
-            matches!(byte, b'a'..=b'z' | b'0'..=b'9' | b'-')
+    /// Check the name against the rules new projects are held to,
+    /// which are stricter than the ones of [`shuttle_common`]
+    pub fn validate(&self) -> Result<(), ProjectNameError> {
+        let name = self.0.as_str();
+
+        // Taken by routes of the API which sit next to projects
+        const RESERVED: [&str; 1] = ["check"];
+
+        fn is_valid_char(c: char) -> bool {
+            matches!(c, 'a'..='z' | '0'..='9' | '-')
+        }
+
+        if name.is_empty() {
+            return Err(ProjectNameError::Empty);
         }
 
         // each label in a hostname can be between 1 and 63 chars
-        let is_invalid_length = name.len() > 63;
+        if name.len() > 63 {
+            return Err(ProjectNameError::TooLong(63));
+        }
+
+        if name.chars().any(|c| c.is_ascii_uppercase()) {
+            return Err(ProjectNameError::Uppercase);
+        }
+
+        if let Some(c) = name.chars().find(|c| !is_valid_char(*c)) {
+            return Err(ProjectNameError::InvalidChar(c));
+        }
+
+        if name.starts_with('-') {
+            return Err(ProjectNameError::LeadingSeparator('-'));
+        }
+
+        if name.ends_with('-') {
+            return Err(ProjectNameError::TrailingSeparator('-'));
+        }
 
-        !(name.bytes().any(|byte| !is_valid_char(byte))
-            || name.ends_with('-')
-            || name.starts_with('-')
-            || name.is_empty()
-            || is_invalid_length)
+        if RESERVED.contains(&name) {
+            return Err(ProjectNameError::Reserved(name.to_string()));
+        }
+
+        Ok(())
     }
 }
 
@@ -162,7 +305,7 @@ impl FromStr for ProjectName {
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         s.parse::<shuttle_common::project::ProjectName>()
-            .map_err(|_| Error::from_kind(ErrorKind::InvalidProjectName))
+            .map_err(|err| Error::from_kind(ErrorKind::InvalidProjectName).with_detail(err))
             .map(|pn| Self(pn.to_string()))
     }
 }
@@ -217,10 +360,170 @@ impl From<ProjectDetails> for shuttle_common::models::project::AdminResponse {
     }
 }
 
+/// Run the Docker operation `op` inside a `docker` span recording how
+/// long it took and how it went. The exporter turns these spans into
+/// the latency of every kind of operation, so slow ones stand out.
+///
+/// Operations go through [`breaker::DOCKER_BREAKER`], so they fail fast
+/// while Docker is down.
+pub async fn docker_op<T, F>(op: &'static str, fut: F) -> Result<T, DockerError>
+where
+    F: Future<Output = Result<T, DockerError>>,
+{
+    let span = info_span!(
+        "docker",
+        docker.op = op,
+        docker.duration_ms = field::Empty,
+        docker.outcome = field::Empty,
+        docker.status_code = field::Empty,
+    );
+
+    let start = Instant::now();
+    let res = breaker::DOCKER_BREAKER
+        .call(fut.instrument(span.clone()))
+        .await;
+    span.record("docker.duration_ms", start.elapsed().as_millis() as u64);
+
+    match &res {
+        Ok(_) => span.record("docker.outcome", "ok"),
+        Err(err) => {
+            if let DockerError::DockerResponseServerError { status_code, .. } = err {
+                span.record("docker.status_code", status_code);
+            }
+            span.record("docker.outcome", "error")
+        }
+    };
+
+    res
+}
+
 pub trait DockerContext: Send + Sync {
     fn docker(&self) -> &Docker;
 
     fn container_settings(&self) -> &ContainerSettings;
+
+    /// The containers kept warm for projects being created to claim,
+    /// if any are
+    fn warm_pool(&self) -> Option<&WarmPool> {
+        None
+    }
+
+    /// Whether there is a container going by `container_id`
+    fn container_exists<'a>(
+        &'a self,
+        container_id: &'a str,
+    ) -> BoxFuture<'a, Result<bool, DockerError>> {
+        docker_op(
+            "inspect",
+            self.docker().inspect_container(container_id, None),
+        )
+        .map(|res| match res {
+            Ok(_) => Ok(true),
+            Err(DockerError::DockerResponseServerError {
+                status_code: 404, ..
+            }) => Ok(false),
+            Err(err) => Err(err),
+        })
+        .boxed()
+    }
+
+    /// Stream the stdout and stderr of a container, starting with
+    /// (about) its last `tail` lines. The stream carries on with new
+    /// output as it comes in if `follow` is set.
+    fn container_logs(
+        &self,
+        container_id: &str,
+        tail: usize,
+        follow: bool,
+    ) -> BoxStream<'static, Result<String, Error>> {
+        self.docker()
+            .logs(
+                container_id,
+                Some(LogsOptions::<String> {
+                    follow,
+                    stdout: true,
+                    stderr: true,
+                    tail: tail.to_string(),
+                    ..Default::default()
+                }),
+            )
+            .map(|output| match output {
+                Ok(output) => Ok(output.to_string()),
+                Err(DockerError::DockerResponseServerError {
+                    status_code: 404, ..
+                }) => Err(Error::from_kind(ErrorKind::ContainerNotFound)),
+                Err(err) => Err(Error::source(ErrorKind::Internal, err)),
+            })
+            .boxed()
+    }
+
+    /// Create a container, returning the id it was given
+    fn create_container(
+        &self,
+        options: CreateContainerOptions<String>,
+        config: Config<String>,
+    ) -> BoxFuture<'_, Result<String, DockerError>> {
+        docker_op(
+            "create",
+            self.docker().create_container(Some(options), config),
+        )
+        .map_ok(|response| response.id)
+        .boxed()
+    }
+
+    /// Count the project containers on this node which are running or
+    /// about to be
+    fn running_projects(&self) -> BoxFuture<'_, Result<usize, DockerError>> {
+        let prefix = &self.container_settings().prefix;
+        let list = self
+            .docker()
+            .list_containers(Some(ListContainersOptions::<String> {
+                all: true,
+                filters: HashMap::from([
+                    (
+                        "label".to_string(),
+                        vec![format!("shuttle.prefix={prefix}")],
+                    ),
+                    (
+                        "status".to_string(),
+                        vec![
+                            "created".to_string(),
+                            "restarting".to_string(),
+                            "running".to_string(),
+                        ],
+                    ),
+                ]),
+                ..Default::default()
+            }));
+
+        docker_op("list", list)
+            .map_ok(|containers| containers.len())
+            .boxed()
+    }
+
+    /// Pull `image` from its registry, logging the progress of the
+    /// download as it goes
+    fn pull_image<'a>(&'a self, image: &'a str) -> BoxFuture<'a, Result<(), DockerError>> {
+        let pull = self
+            .docker()
+            .create_image(
+                Some(CreateImageOptions {
+                    from_image: image,
+                    ..Default::default()
+                }),
+                None,
+                None,
+            )
+            .try_for_each(move |info| {
+                if let Some(status) = info.status {
+                    let progress = info.progress.unwrap_or_default();
+                    info!(%image, %status, %progress, "pulling image");
+                }
+                future::ok(())
+            });
+
+        docker_op("pull", pull).boxed()
+    }
 }
 
 #[async_trait]
@@ -322,6 +625,7 @@ pub trait Refresh<Ctx>: Sized {
 
 #[cfg(test)]
 pub mod tests {
+    use std::collections::HashMap;
     use std::env;
     use std::io::Read;
     use std::net::SocketAddr;
@@ -345,8 +649,12 @@ pub mod tests {
 
     use crate::acme::AcmeClient;
     use crate::api::latest::ApiBuilder;
-    use crate::args::{ContextArgs, StartArgs, UseTls};
+    use crate::args::{
+        ContainerRestart, ContextArgs, DeployConcurrency, ProxyBodyMode, ProxyErrorFormat,
+        StartArgs, StartupPolicy, UseTls,
+    };
     use crate::auth::User;
+    use crate::jwt::DEFAULT_ACCOUNT_CLAIM;
     use crate::proxy::UserServiceBuilder;
     use crate::service::{ContainerSettings, GatewayService, MIGRATIONS};
     use crate::worker::Worker;
@@ -590,19 +898,59 @@ pub mod tests {
                 user,
                 bouncer,
                 use_tls: UseTls::Disable,
+                tls_min_version: None,
+                tls_prefer_server_ciphers: false,
+                upstream_connect_timeout: 5,
+                upstream_timeout: 60,
+                upstream_retries: 2,
+                max_header_size: 32768,
+                max_headers: 100,
+                provisioning_hold: 0,
+                cold_start_hold: 30,
+                client_tcp_keepalive: 60,
+                client_accept_timeout: 10,
+                client_idle_timeout: 0,
+                upstream_pool_idle_timeout: 90,
+                upstream_pool_max_idle: 32,
+                reconcile_interval: 300,
+                crashed_retention: 86400,
+                startup_policy: StartupPolicy::Restore,
+                deploy_concurrency: DeployConcurrency::Queue,
+                proxy_body_mode: ProxyBodyMode::Streaming,
+                proxy_error_format: ProxyErrorFormat::Json,
+                proxy_error_page_4xx: None,
+                proxy_error_page_5xx: None,
+                maintenance: false,
+                jwt_public_key: None,
+                jwks_url: None,
+                allow_private_webhooks: false,
+                jwt_issuer: None,
+                jwt_audience: None,
+                jwt_account_claim: DEFAULT_ACCOUNT_CLAIM.to_string(),
+                dns_hook_url: None,
+                dns_hook_token: None,
                 context: ContextArgs {
                     docker_host,
                     image,
                     prefix,
                     provisioner_host,
                     network_name,
+                    create_network: false,
                     proxy_fqdn: FQDN::from_str("test.shuttleapp.rs").unwrap(),
+                    additional_proxy_fqdns: Vec::new(),
+                    max_running_projects: None,
+                    restart_policy: ContainerRestart::Never,
+                    restart_max_retries: 3,
+                    stop_timeout_secs: 30,
+                    warm_pool_size: 0,
+                    warm_pool_ttl: 3600,
                 },
             };
 
             let settings = ContainerSettings::builder(&docker)
                 .from_args(&args.context)
-                .await;
+                .await
+                .unwrap();
 
             let hyper = HyperClient::builder().build(HttpConnector::new());
 
@@ -662,6 +1010,134 @@ pub mod tests {
         }
     }
 
+    /// Keeps the fields recorded on `docker` spans once they close
+    #[derive(Clone, Default)]
+    struct DockerSpans(Arc<std::sync::Mutex<Vec<HashMap<String, String>>>>);
+
+    struct SpanFields<'a>(&'a mut HashMap<String, String>);
+
+    impl tracing::field::Visit for SpanFields<'_> {
+        fn record_str(&mut self, field: &tracing::field::Field, value: &str) {
+            self.0.insert(field.name().to_string(), value.to_string());
+        }
+
+        fn record_debug(&mut self, field: &tracing::field::Field, value: &dyn std::fmt::Debug) {
+            self.0
+                .insert(field.name().to_string(), format!("{value:?}"));
+        }
+    }
+
+    impl<S> tracing_subscriber::Layer<S> for DockerSpans
+    where
+        S: tracing::Subscriber + for<'a> tracing_subscriber::registry::LookupSpan<'a>,
+    {
+        fn on_new_span(
+            &self,
+            attrs: &tracing::span::Attributes<'_>,
+            id: &tracing::span::Id,
+            ctx: tracing_subscriber::layer::Context<'_, S>,
+        ) {
+            if attrs.metadata().name() == "docker" {
+                let mut fields = HashMap::new();
+                attrs.record(&mut SpanFields(&mut fields));
+                ctx.span(id).unwrap().extensions_mut().insert(fields);
+            }
+        }
+
+        fn on_record(
+            &self,
+            id: &tracing::span::Id,
+            values: &tracing::span::Record<'_>,
+            ctx: tracing_subscriber::layer::Context<'_, S>,
+        ) {
+            let span = ctx.span(id).unwrap();
+            let mut extensions = span.extensions_mut();
+            if let Some(fields) = extensions.get_mut::<HashMap<String, String>>() {
+                values.record(&mut SpanFields(fields));
+            }
+        }
+
+        fn on_close(&self, id: tracing::span::Id, ctx: tracing_subscriber::layer::Context<'_, S>) {
+            let span = ctx.span(&id).unwrap();
+            let fields = span.extensions_mut().remove::<HashMap<String, String>>();
+            if let Some(fields) = fields {
+                self.0.lock().unwrap().push(fields);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn docker_operation_spans() {
+        use axum::http::Method;
+        use axum::response::IntoResponse;
+        use bollard::container::{Config, CreateContainerOptions};
+        use tracing_subscriber::prelude::*;
+
+        // Creates containers, slowly, and knows of no others
+        let router = axum::Router::new().fallback(|method: Method, uri: Uri| async move {
+            if method == Method::POST && uri.path().ends_with("/containers/create") {
+                tokio::time::sleep(Duration::from_millis(50)).await;
+                (
+                    StatusCode::CREATED,
+                    axum::Json(serde_json::json!({ "Id": "matrix", "Warnings": [] })),
+                )
+                    .into_response()
+            } else {
+                (
+                    StatusCode::NOT_FOUND,
+                    axum::Json(serde_json::json!({ "message": "no such container" })),
+                )
+                    .into_response()
+            }
+        });
+        let port = portpicker::pick_unused_port().unwrap();
+        let addr: SocketAddr = format!("127.0.0.1:{port}").parse().unwrap();
+        tokio::spawn(axum::Server::bind(&addr).serve(router.into_make_service()));
+        // give the server a moment to start listening
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let docker =
+            Docker::connect_with_http(&format!("http://{addr}"), 5, bollard::API_DEFAULT_VERSION)
+                .unwrap();
+
+        let spans = DockerSpans::default();
+        let _guard =
+            tracing::subscriber::set_default(tracing_subscriber::registry().with(spans.clone()));
+
+        let created = crate::docker_op(
+            "create",
+            docker.create_container(
+                Some(CreateContainerOptions {
+                    name: "matrix".to_string(),
+                }),
+                Config::<String>::default(),
+            ),
+        )
+        .await
+        .unwrap();
+        assert_eq!(created.id, "matrix");
+
+        crate::docker_op("inspect", docker.inspect_container("zion", None))
+            .await
+            .unwrap_err();
+
+        let spans = spans.0.lock().unwrap();
+        assert_eq!(spans.len(), 2);
+
+        let create = &spans[0];
+        assert_eq!(create["docker.op"], "create");
+        assert_eq!(create["docker.outcome"], "ok");
+        let duration: u64 = create["docker.duration_ms"].parse().unwrap();
+        assert!(duration >= 50, "{duration}");
+        assert!(!create.contains_key("docker.status_code"));
+
+        let inspect = &spans[1];
+        assert_eq!(inspect["docker.op"], "inspect");
+        assert_eq!(inspect["docker.outcome"], "error");
+        assert_eq!(inspect["docker.status_code"], "404");
+        assert!(inspect.contains_key("docker.duration_ms"));
+    }
+
     #[tokio::test]
     async fn end_to_end() {
         let world = World::new().await;
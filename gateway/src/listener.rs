@@ -0,0 +1,302 @@
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use axum_server::accept::Accept;
+use axum_server::AddrIncomingConfig;
+use futures::future::BoxFuture;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::time::{sleep, timeout, Instant, Sleep};
+
+/// How the user proxy treats the connections of clients
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ListenerSettings {
+    /// How long a connection is idle before TCP keepalive probes are
+    /// sent on it, if they are sent at all
+    pub tcp_keepalive: Option<Duration>,
+    /// How long a client has to get its connection accepted, TLS
+    /// handshake included
+    pub accept_timeout: Option<Duration>,
+    /// How long a connection can go without anything sent either way
+    /// before it is closed
+    pub idle_timeout: Option<Duration>,
+}
+
+impl ListenerSettings {
+    pub fn incoming_config(&self) -> AddrIncomingConfig {
+        AddrIncomingConfig::new()
+            .tcp_keepalive(self.tcp_keepalive)
+            .build()
+    }
+}
+
+/// Wraps the acceptor of a listener to give up on connections which
+/// take too long to be accepted, and to close those which go idle
+#[derive(Clone)]
+pub struct ListenerAcceptor<A> {
+    inner: A,
+    accept_timeout: Option<Duration>,
+    idle_timeout: Option<Duration>,
+}
+
+impl<A> ListenerAcceptor<A> {
+    pub fn new(inner: A, settings: ListenerSettings) -> Self {
+        Self {
+            inner,
+            accept_timeout: settings.accept_timeout,
+            idle_timeout: settings.idle_timeout,
+        }
+    }
+}
+
+impl<A, I, S> Accept<I, S> for ListenerAcceptor<A>
+where
+    A: Accept<I, S>,
+    A::Future: Send + 'static,
+{
+    type Stream = IdleTimeout<A::Stream>;
+    type Service = A::Service;
+    type Future = BoxFuture<'static, io::Result<(Self::Stream, Self::Service)>>;
+
+    fn accept(&self, stream: I, service: S) -> Self::Future {
+        let accepting = self.inner.accept(stream, service);
+        let accept_timeout = self.accept_timeout;
+        let idle_timeout = self.idle_timeout;
+
+        Box::pin(async move {
+            let (stream, service) = match accept_timeout {
+                Some(accept_timeout) => {
+                    timeout(accept_timeout, accepting).await.map_err(|_| {
+                        io::Error::new(
+                            io::ErrorKind::TimedOut,
+                            "connection took too long to accept",
+                        )
+                    })??
+                }
+                None => accepting.await?,
+            };
+
+            Ok((IdleTimeout::new(stream, idle_timeout), service))
+        })
+    }
+}
+
+/// A connection which fails once nothing was read from or written to
+/// it for a while
+pub struct IdleTimeout<S> {
+    inner: S,
+    idle: Option<(Duration, Pin<Box<Sleep>>)>,
+}
+
+impl<S> IdleTimeout<S> {
+    pub fn new(inner: S, idle_timeout: Option<Duration>) -> Self {
+        Self {
+            inner,
+            idle: idle_timeout.map(|idle_timeout| (idle_timeout, Box::pin(sleep(idle_timeout)))),
+        }
+    }
+
+    fn touch(&mut self) {
+        if let Some((idle_timeout, sleep)) = &mut self.idle {
+            sleep.as_mut().reset(Instant::now() + *idle_timeout);
+        }
+    }
+
+    /// Fail a pending read or write if the connection has been idle
+    /// for too long, or else wake up when it will have
+    fn poll_idle<T>(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<T>> {
+        match &mut self.idle {
+            Some((_, sleep)) if sleep.as_mut().poll(cx).is_ready() => Poll::Ready(Err(
+                io::Error::new(io::ErrorKind::TimedOut, "connection was idle for too long"),
+            )),
+            _ => Poll::Pending,
+        }
+    }
+}
+
+impl<S> AsyncRead for IdleTimeout<S>
+where
+    S: AsyncRead + Unpin,
+{
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        match Pin::new(&mut this.inner).poll_read(cx, buf) {
+            Poll::Ready(res) => {
+                this.touch();
+                Poll::Ready(res)
+            }
+            Poll::Pending => this.poll_idle(cx),
+        }
+    }
+}
+
+impl<S> AsyncWrite for IdleTimeout<S>
+where
+    S: AsyncWrite + Unpin,
+{
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        match Pin::new(&mut this.inner).poll_write(cx, buf) {
+            Poll::Ready(res) => {
+                this.touch();
+                Poll::Ready(res)
+            }
+            Poll::Pending => this.poll_idle(cx),
+        }
+    }
+
+    fn poll_write_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[io::IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        match Pin::new(&mut this.inner).poll_write_vectored(cx, bufs) {
+            Poll::Ready(res) => {
+                this.touch();
+                Poll::Ready(res)
+            }
+            Poll::Pending => this.poll_idle(cx),
+        }
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.inner.is_write_vectored()
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use std::net::SocketAddr;
+
+    use axum::routing::get;
+    use axum::Router;
+    use axum_server::accept::DefaultAcceptor;
+    use futures::future;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpStream;
+
+    use super::*;
+
+    /// Never gets around to accepting a connection, like a client
+    /// which stalls its TLS handshake
+    #[derive(Clone)]
+    struct StallingAcceptor;
+
+    impl<I, S> Accept<I, S> for StallingAcceptor
+    where
+        I: Send + 'static,
+        S: Send + 'static,
+    {
+        type Stream = I;
+        type Service = S;
+        type Future = future::Pending<io::Result<(I, S)>>;
+
+        fn accept(&self, _stream: I, _service: S) -> Self::Future {
+            future::pending()
+        }
+    }
+
+    async fn serve<A>(acceptor: A, settings: ListenerSettings) -> SocketAddr
+    where
+        A: Accept<hyper::server::conn::AddrStream, Router, Service = Router>
+            + Clone
+            + Send
+            + Sync
+            + 'static,
+        A::Stream: AsyncRead + AsyncWrite + Unpin + Send,
+        A::Future: Send,
+    {
+        let port = portpicker::pick_unused_port().unwrap();
+        let addr: SocketAddr = format!("127.0.0.1:{port}").parse().unwrap();
+
+        let router = Router::new().route("/", get(|| async { "hello" }));
+        tokio::spawn(
+            axum_server::bind(addr)
+                .addr_incoming_config(settings.incoming_config())
+                .acceptor(ListenerAcceptor::new(acceptor, settings))
+                .serve(router.into_make_service()),
+        );
+        // give the server a moment to start listening
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        addr
+    }
+
+    /// How long it takes the other end to close `stream`
+    async fn closed_after(mut stream: TcpStream) -> Duration {
+        let start = std::time::Instant::now();
+        let mut buf = Vec::new();
+        let _ = tokio::time::timeout(Duration::from_secs(5), stream.read_to_end(&mut buf)).await;
+        start.elapsed()
+    }
+
+    #[tokio::test]
+    async fn listener_closes_idle_connections() {
+        let settings = ListenerSettings {
+            tcp_keepalive: Some(Duration::from_secs(60)),
+            accept_timeout: None,
+            idle_timeout: Some(Duration::from_millis(500)),
+        };
+        let addr = serve(DefaultAcceptor::new(), settings).await;
+
+        // a client which never says anything is let go
+        let stream = TcpStream::connect(addr).await.unwrap();
+        let elapsed = closed_after(stream).await;
+        assert!(elapsed >= Duration::from_millis(400), "{elapsed:?}");
+        assert!(elapsed < Duration::from_secs(2), "{elapsed:?}");
+
+        // so is one which goes quiet after a request, but only then
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        for _ in 0..3 {
+            tokio::time::sleep(Duration::from_millis(300)).await;
+            stream
+                .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n")
+                .await
+                .unwrap();
+            let mut buf = [0; 1024];
+            let read = stream.read(&mut buf).await.unwrap();
+            assert!(String::from_utf8_lossy(&buf[..read]).ends_with("hello"));
+        }
+        let elapsed = closed_after(stream).await;
+        assert!(elapsed < Duration::from_secs(2), "{elapsed:?}");
+
+        // unless there is no idle timeout
+        let addr = serve(DefaultAcceptor::new(), ListenerSettings::default()).await;
+        let stream = TcpStream::connect(addr).await.unwrap();
+        assert!(closed_after(stream).await >= Duration::from_secs(5));
+    }
+
+    #[tokio::test]
+    async fn listener_times_out_accepting() {
+        let settings = ListenerSettings {
+            tcp_keepalive: None,
+            accept_timeout: Some(Duration::from_millis(500)),
+            idle_timeout: None,
+        };
+        let addr = serve(StallingAcceptor, settings).await;
+
+        let stream = TcpStream::connect(addr).await.unwrap();
+        let elapsed = closed_after(stream).await;
+        assert!(elapsed >= Duration::from_millis(400), "{elapsed:?}");
+        assert!(elapsed < Duration::from_secs(2), "{elapsed:?}");
+    }
+}
@@ -0,0 +1,113 @@
+use std::sync::Mutex;
+
+use shuttle_common::models::error::ErrorKind;
+use tracing::info;
+use tracing_subscriber::{reload, EnvFilter};
+
+use crate::Error;
+
+type Reload = Box<dyn Fn(EnvFilter) -> Result<(), reload::Error> + Send + Sync>;
+
+/// The filter deciding which traces the gateway emits, which can be
+/// changed while it runs
+pub struct LogLevel {
+    current: Mutex<String>,
+    reload: Reload,
+}
+
+impl LogLevel {
+    /// Control the filter behind `handle`, which was last set to `current`
+    pub fn new<S>(current: String, handle: reload::Handle<EnvFilter, S>) -> Self
+    where
+        S: 'static,
+    {
+        Self {
+            current: Mutex::new(current),
+            reload: Box::new(move |filter| handle.reload(filter)),
+        }
+    }
+
+    /// The directives of the filter in use, like `info,shuttle_gateway=debug`
+    pub fn current(&self) -> String {
+        self.current.lock().unwrap().clone()
+    }
+
+    /// Replace the filter with one made of `directives`, keeping the
+    /// one in use if they are not valid
+    pub fn set(&self, directives: &str) -> Result<(), Error> {
+        let filter = EnvFilter::try_new(directives).map_err(|err| {
+            Error::from_kind(ErrorKind::InvalidLogLevel).with_detail(err.to_string())
+        })?;
+
+        let mut current = self.current.lock().unwrap();
+        (self.reload)(filter).map_err(|err| Error::source(ErrorKind::Internal, err))?;
+        info!(from = %current, to = %directives, "changed the log level");
+        *current = directives.to_string();
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use std::sync::Arc;
+
+    use tracing::field::{Field, Visit};
+    use tracing::{debug, warn};
+    use tracing_subscriber::prelude::*;
+
+    use super::*;
+
+    /// Keeps the messages of the events it sees
+    #[derive(Clone, Default)]
+    struct Messages(Arc<Mutex<Vec<String>>>);
+
+    impl Visit for Messages {
+        fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+            if field.name() == "message" {
+                self.0.lock().unwrap().push(format!("{value:?}"));
+            }
+        }
+    }
+
+    impl<S: tracing::Subscriber> tracing_subscriber::Layer<S> for Messages {
+        fn on_event(
+            &self,
+            event: &tracing::Event<'_>,
+            _ctx: tracing_subscriber::layer::Context<'_, S>,
+        ) {
+            event.record(&mut self.clone());
+        }
+    }
+
+    #[test]
+    fn log_level() {
+        let (filter, handle) = reload::Layer::new(EnvFilter::new("warn"));
+        let messages = Messages::default();
+        let _guard = tracing::subscriber::set_default(
+            tracing_subscriber::registry()
+                .with(filter)
+                .with(messages.clone()),
+        );
+        let log_level = LogLevel::new("warn".to_string(), handle);
+
+        debug!("hidden");
+        warn!("shown");
+        assert_eq!(*messages.0.lock().unwrap(), vec!["shown"]);
+
+        log_level.set("debug").unwrap();
+        assert_eq!(log_level.current(), "debug");
+        debug!("now shown");
+        assert_eq!(messages.0.lock().unwrap().last().unwrap(), "now shown");
+
+        let err = log_level.set("shuttle_gateway=loud").unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidLogLevel);
+        assert_eq!(log_level.current(), "debug");
+
+        log_level.set("error").unwrap();
+        messages.0.lock().unwrap().clear();
+        debug!("hidden again");
+        warn!("hidden too");
+        assert!(messages.0.lock().unwrap().is_empty());
+    }
+}
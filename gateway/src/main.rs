@@ -3,24 +3,41 @@ use fqdn::FQDN;
 use futures::prelude::*;
 use instant_acme::{AccountCredentials, ChallengeType};
 use opentelemetry::global;
-use shuttle_gateway::acme::{AcmeClient, CustomDomain};
+use shuttle_gateway::acme::{AcmeClient, CustomDomain, HttpDnsHook};
 use shuttle_gateway::api::latest::{ApiBuilder, SVC_DEGRADED_THRESHOLD};
 use shuttle_gateway::args::StartArgs;
-use shuttle_gateway::args::{Args, Commands, InitArgs, UseTls};
+use shuttle_gateway::args::{Args, Commands, ExportArgs, ImportArgs, InitArgs, UseTls};
 use shuttle_gateway::auth::Key;
-use shuttle_gateway::proxy::UserServiceBuilder;
-use shuttle_gateway::service::{GatewayService, MIGRATIONS};
+use shuttle_gateway::backup::{self, Backup};
+use shuttle_gateway::env::EnvCipher;
+use shuttle_gateway::jwt::{JwtKey, JwtVerifier};
+use shuttle_gateway::listener::ListenerSettings;
+use shuttle_gateway::loglevel::LogLevel;
+use shuttle_gateway::project::exec::{reap_crashed, reconcile, reconcile_on_startup};
+use shuttle_gateway::proxy::{ErrorPages, HeaderLimits, UserServiceBuilder};
+use shuttle_gateway::service::{check_schema, GatewayService, MIGRATIONS};
 use shuttle_gateway::task;
-use shuttle_gateway::tls::{make_tls_acceptor, ChainAndPrivateKey};
+use shuttle_gateway::tls::{
+    make_tls_acceptor, CertStore, ChainAndPrivateKey, FileCertStore, TlsOptions,
+};
 use shuttle_gateway::worker::{Worker, WORKER_QUEUE_SIZE};
+use shuttle_gateway::DockerContext;
 use sqlx::migrate::MigrateDatabase;
+use sqlx::sqlite::SqlitePoolOptions;
 use sqlx::{query, Sqlite, SqlitePool};
 use std::io::{self, Cursor};
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use std::time::Duration;
 use tracing::{debug, error, info, info_span, trace, warn, Instrument};
-use tracing_subscriber::{fmt, prelude::*, EnvFilter};
+use tracing_subscriber::{fmt, prelude::*, reload, EnvFilter};
+
+/// How often to remove expired impersonation tokens
+const IMPERSONATION_SWEEP_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+/// How often containers are started to make up for the warm ones
+/// claimed by projects
+const WARM_POOL_REFILL_INTERVAL: Duration = Duration::from_secs(5);
 
 #[tokio::main(flavor = "multi_thread")]
 async fn main() -> io::Result<()> {
@@ -31,9 +48,18 @@ async fn main() -> io::Result<()> {
     global::set_text_map_propagator(opentelemetry_datadog::DatadogPropagator::new());
 
     let fmt_layer = fmt::layer();
-    let filter_layer = EnvFilter::try_from_default_env()
-        .or_else(|_| EnvFilter::try_new("info"))
-        .unwrap();
+    let (filter, directives) = match EnvFilter::try_from_default_env() {
+        Ok(filter) => (
+            filter,
+            std::env::var(EnvFilter::DEFAULT_ENV).unwrap_or_default(),
+        ),
+        Err(_) => (
+            EnvFilter::try_new(&args.log_level).expect("--log-level to be valid directives"),
+            args.log_level.clone(),
+        ),
+    };
+    let (filter_layer, filter_handle) = reload::Layer::new(filter);
+    let log_level = Arc::new(LogLevel::new(directives, filter_handle));
 
     let tracer = opentelemetry_datadog::new_pipeline()
         .with_service_name("gateway")
@@ -60,38 +86,114 @@ async fn main() -> io::Result<()> {
             .unwrap()
             .to_string_lossy()
     );
-    let db = SqlitePool::connect(db_uri).await.unwrap();
+    let db = SqlitePoolOptions::new()
+        .max_connections(args.db_max_connections)
+        .acquire_timeout(Duration::from_secs(args.db_acquire_timeout))
+        .connect(db_uri)
+        .await
+        .unwrap();
 
     MIGRATIONS.run(&db).await.unwrap();
+    check_schema(&db)
+        .await
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err.to_string()))?;
 
     match args.command {
-        Commands::Start(start_args) => start(db, args.state, start_args).await,
+        Commands::Start(start_args) => start(db, args.state, start_args, log_level).await,
         Commands::Init(init_args) => init(db, init_args).await,
+        Commands::Export(export_args) => export(db, export_args).await,
+        Commands::Import(import_args) => import(db, import_args).await,
     }
 }
 
-async fn start(db: SqlitePool, fs: PathBuf, args: StartArgs) -> io::Result<()> {
-    let gateway = Arc::new(GatewayService::init(args.context.clone(), db).await);
+async fn start(
+    db: SqlitePool,
+    fs: PathBuf,
+    args: StartArgs,
+    log_level: Arc<LogLevel>,
+) -> io::Result<()> {
+    args.validate(&fs)
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err.to_string()))?;
+
+    info!(
+        control = %args.control,
+        bouncer = %args.bouncer,
+        user = %args.user,
+        use_tls = ?args.use_tls,
+        proxy_fqdn = %args.context.proxy_fqdn,
+        additional_proxy_fqdns = ?args.context.additional_proxy_fqdns,
+        image = %args.context.image,
+        prefix = %args.context.prefix,
+        network_name = %args.context.network_name,
+        max_running_projects = ?args.context.max_running_projects,
+        restart_policy = ?args.context.restart_policy,
+        startup_policy = ?args.startup_policy,
+        deploy_concurrency = ?args.deploy_concurrency,
+        proxy_body_mode = ?args.proxy_body_mode,
+        proxy_error_format = ?args.proxy_error_format,
+        proxy_error_page_4xx = ?args.proxy_error_page_4xx,
+        proxy_error_page_5xx = ?args.proxy_error_page_5xx,
+        upstream_retries = args.upstream_retries,
+        provisioning_hold = args.provisioning_hold,
+        cold_start_hold = args.cold_start_hold,
+        crashed_retention = args.crashed_retention,
+        client_tcp_keepalive = args.client_tcp_keepalive,
+        client_accept_timeout = args.client_accept_timeout,
+        client_idle_timeout = args.client_idle_timeout,
+        maintenance = args.maintenance,
+        jwt = args.jwt_public_key.is_some() || args.jwks_url.is_some(),
+        jwt_issuer = ?args.jwt_issuer,
+        jwt_audience = ?args.jwt_audience,
+        "effective configuration"
+    );
+
+    let env_cipher = EnvCipher::load_or_create(fs.join("env.key"))?;
+
+    // Both are required by the arguments as soon as JWTs are accepted
+    let issuer = args.jwt_issuer.clone().unwrap_or_default();
+    let audience = args.jwt_audience.clone().unwrap_or_default();
+    let jwt_verifier = match (&args.jwt_public_key, &args.jwks_url) {
+        (Some(path), _) => {
+            let key = JwtKey::from_pem(&std::fs::read_to_string(path)?)
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+            Some(JwtVerifier::from_key(key, issuer, audience))
+        }
+        (None, Some(url)) => Some(JwtVerifier::from_jwks_url(url.clone(), issuer, audience)),
+        (None, None) => None,
+    }
+    .map(|verifier| verifier.with_account_claim(args.jwt_account_claim.clone()));
+
+    let gateway = Arc::new(
+        GatewayService::init(args.context.clone(), db)
+            .await
+            .with_env_cipher(env_cipher)
+            .with_deploy_concurrency(args.deploy_concurrency)
+            .with_private_webhooks(args.allow_private_webhooks)
+            .with_jwt_verifier(jwt_verifier),
+    );
+
+    let sealed = gateway
+        .seal_webhook_secrets()
+        .await
+        .map_err(|err| io::Error::new(io::ErrorKind::Other, err.to_string()))?;
+    if sealed > 0 {
+        info!(sealed, "sealed webhook secrets stored in the clear");
+    }
+
+    if args.maintenance {
+        info!("starting in maintenance mode");
+        gateway.set_maintenance(true);
+    }
 
     let worker = Worker::new();
 
     let sender = worker.sender();
 
-    for (project_name, _) in gateway
-        .iter_projects()
+    // Containers may have changed while the gateway was down
+    let queued = reconcile_on_startup(Arc::clone(&gateway), sender.clone(), args.startup_policy)
         .await
-        .expect("could not list projects")
-    {
-        gateway
-            .clone()
-            .new_task()
-            .project(project_name)
-            .and_then(task::refresh())
-            .send(&sender)
-            .await
-            .ok()
-            .unwrap();
-    }
+        .expect("could not reconcile projects");
+    info!(queued, "reconciling projects");
 
     let worker_handle = tokio::spawn(
         worker
@@ -147,21 +249,164 @@ async fn start(db: SqlitePool, fs: PathBuf, args: StartArgs) -> io::Result<()> {
         }
     });
 
-    let acme_client = AcmeClient::new();
+    // Keep correcting drift between the stored state of projects and
+    // their containers, such as containers removed from under us
+    let reconcile_handle = tokio::spawn({
+        let gateway = Arc::clone(&gateway);
+        let sender = sender.clone();
+        let interval = Duration::from_secs(args.reconcile_interval);
+        async move {
+            loop {
+                tokio::time::sleep(interval).await;
+                if sender.capacity() < WORKER_QUEUE_SIZE - SVC_DEGRADED_THRESHOLD {
+                    // if degraded, don't stack more refreshes
+                    warn!(
+                        sender.capacity = sender.capacity(),
+                        "skipping reconciliation"
+                    );
+                    continue;
+                }
+
+                match reconcile(Arc::clone(&gateway), sender.clone()).await {
+                    Ok(queued) => debug!(queued, "reconciling projects"),
+                    Err(err) => error!(error = %err, "failed to reconcile projects"),
+                }
+            }
+        }
+    });
+
+    // Remove the containers crashed projects left behind once they
+    // have been kept long enough for their logs to be looked at
+    let reaper_handle = tokio::spawn({
+        let gateway = Arc::clone(&gateway);
+        let sender = sender.clone();
+        let interval = Duration::from_secs(args.reconcile_interval);
+        let retention = seconds_unless_zero(args.crashed_retention);
+        async move {
+            let retention = match retention {
+                Some(retention) => retention,
+                None => return future::pending::<()>().await,
+            };
+
+            loop {
+                tokio::time::sleep(interval).await;
+                if sender.capacity() < WORKER_QUEUE_SIZE - SVC_DEGRADED_THRESHOLD {
+                    // if degraded, don't stack more removals
+                    warn!(
+                        sender.capacity = sender.capacity(),
+                        "skipping reaping crashed projects"
+                    );
+                    continue;
+                }
+
+                match reap_crashed(Arc::clone(&gateway), sender.clone(), retention).await {
+                    Ok(queued) => debug!(queued, "reaping crashed projects"),
+                    Err(err) => error!(error = %err, "failed to reap crashed projects"),
+                }
+            }
+        }
+    });
+
+    // Expired impersonation tokens are never accepted, this only keeps
+    // them from piling up
+    let impersonation_handle = tokio::spawn({
+        let gateway = Arc::clone(&gateway);
+        async move {
+            loop {
+                tokio::time::sleep(IMPERSONATION_SWEEP_INTERVAL).await;
+                match gateway.sweep_impersonations().await {
+                    Ok(swept) => debug!(swept, "swept expired impersonation tokens"),
+                    Err(err) => error!(error = %err, "failed to sweep impersonation tokens"),
+                }
+            }
+        }
+    });
+
+    // Keep containers started ahead of time for projects being created
+    let warm_pool_handle = tokio::spawn({
+        let ctx = gateway.context();
+        async move {
+            let pool = match ctx.warm_pool() {
+                Some(pool) if pool.size() > 0 => pool,
+                _ => return future::pending::<()>().await,
+            };
+
+            if let Err(err) = pool.clear(&ctx).await {
+                error!(error = %err, "failed to remove warm containers left behind");
+            }
+
+            loop {
+                if let Err(err) = pool.refill(&ctx).await {
+                    error!(error = %err, "failed to refill the warm pool");
+                }
+                tokio::time::sleep(WARM_POOL_REFILL_INTERVAL).await;
+            }
+        }
+    });
+
+    let mut acme_client = AcmeClient::new();
+    if let Some(url) = args.dns_hook_url.clone() {
+        acme_client =
+            acme_client.with_dns_provider(HttpDnsHook::new(url, args.dns_hook_token.clone()));
+    }
 
     let mut api_builder = ApiBuilder::new()
         .with_service(Arc::clone(&gateway))
-        .with_sender(sender)
+        .with_sender(sender.clone())
+        .with_log_level(log_level)
         .binding_to(args.control);
 
     let mut user_builder = UserServiceBuilder::new()
         .with_service(Arc::clone(&gateway))
+        .with_sender(sender)
         .with_public(args.context.proxy_fqdn.clone())
         .with_user_proxy_binding_to(args.user)
-        .with_bouncer(args.bouncer);
+        .with_bouncer(args.bouncer)
+        .with_upstream_timeout(
+            Duration::from_secs(args.upstream_connect_timeout),
+            Duration::from_secs(args.upstream_timeout),
+        )
+        .with_upstream_retries(args.upstream_retries)
+        .with_provisioning_hold(Duration::from_secs(args.provisioning_hold))
+        .with_cold_start_hold(Duration::from_secs(args.cold_start_hold))
+        .with_listener(ListenerSettings {
+            tcp_keepalive: seconds_unless_zero(args.client_tcp_keepalive),
+            accept_timeout: seconds_unless_zero(args.client_accept_timeout),
+            idle_timeout: seconds_unless_zero(args.client_idle_timeout),
+        })
+        .with_header_limits(HeaderLimits {
+            max_size: args.max_header_size,
+            max_count: args.max_headers,
+        })
+        .with_upstream_pool(
+            Duration::from_secs(args.upstream_pool_idle_timeout),
+            args.upstream_pool_max_idle,
+        )
+        .with_body_mode(args.proxy_body_mode)
+        .with_error_format(args.proxy_error_format)
+        .with_error_pages(ErrorPages {
+            client: args
+                .proxy_error_page_4xx
+                .as_ref()
+                .map(std::fs::read_to_string)
+                .transpose()?,
+            server: args
+                .proxy_error_page_5xx
+                .as_ref()
+                .map(std::fs::read_to_string)
+                .transpose()?,
+        });
+
+    for public in &args.context.additional_proxy_fqdns {
+        user_builder = user_builder.with_public(public.clone());
+    }
 
     if let UseTls::Enable = args.use_tls {
-        let (resolver, tls_acceptor) = make_tls_acceptor();
+        let store: Arc<dyn CertStore> = Arc::new(FileCertStore::new(fs.join("certs")).unwrap());
+        let (resolver, tls_acceptor) =
+            make_tls_acceptor(Arc::clone(&store), TlsOptions::from_args(&args)).unwrap();
+        let served = resolver.reload().await.unwrap();
+        debug!(served, "loaded certificates from the store");
 
         user_builder = user_builder
             .with_acme(acme_client.clone())
@@ -187,7 +432,13 @@ async fn start(db: SqlitePool, fs: PathBuf, args: StartArgs) -> io::Result<()> {
 
         tokio::spawn(async move {
             // make sure we have a certificate for ourselves
-            let certs = init_certs(fs, args.context.proxy_fqdn.clone(), acme_client.clone()).await;
+            let certs = init_certs(
+                store.as_ref(),
+                fs,
+                args.context.proxy_fqdn.clone(),
+                acme_client.clone(),
+            )
+            .await;
             resolver.serve_default_der(certs).await.unwrap();
         });
     } else {
@@ -208,11 +459,20 @@ async fn start(db: SqlitePool, fs: PathBuf, args: StartArgs) -> io::Result<()> {
         _ = api_handle => error!("api handle finished"),
         _ = user_handle => error!("user handle finished"),
         _ = ambulance_handle => error!("ambulance handle finished"),
+        _ = reconcile_handle => error!("reconcile handle finished"),
+        _ = reaper_handle => error!("reaper handle finished"),
+        _ = impersonation_handle => error!("impersonation handle finished"),
+        _ = warm_pool_handle => error!("warm pool handle finished"),
     );
 
     Ok(())
 }
 
+/// A number of seconds from the command line, where 0 stands for none
+fn seconds_unless_zero(seconds: u64) -> Option<Duration> {
+    (seconds > 0).then_some(Duration::from_secs(seconds))
+}
+
 async fn init(db: SqlitePool, args: InitArgs) -> io::Result<()> {
     let key = match args.key {
         Some(key) => key,
@@ -230,10 +490,69 @@ async fn init(db: SqlitePool, args: InitArgs) -> io::Result<()> {
     Ok(())
 }
 
-async fn init_certs<P: AsRef<Path>>(fs: P, public: FQDN, acme: AcmeClient) -> ChainAndPrivateKey {
+async fn export(db: SqlitePool, args: ExportArgs) -> io::Result<()> {
+    let backup = backup::export(&db)
+        .await
+        .map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))?;
+
+    serde_json::to_writer(create_private_file(&args.output)?, &backup)?;
+
+    println!("gateway state backed up to {}", args.output.display());
+    Ok(())
+}
+
+/// Create (or truncate) the file at `path` so that only its owner can
+/// read it, as backups hold the keys of every account
+fn create_private_file(path: &Path) -> io::Result<std::fs::File> {
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+
+        options.mode(0o600);
+        let file = options.open(path)?;
+        // the mode only applies to files which did not exist yet
+        file.set_permissions(std::fs::Permissions::from_mode(0o600))?;
+        Ok(file)
+    }
+
+    #[cfg(not(unix))]
+    options.open(path)
+}
+
+async fn import(db: SqlitePool, args: ImportArgs) -> io::Result<()> {
+    let backup: Backup = serde_json::from_reader(std::fs::File::open(&args.input)?)?;
+
+    backup::import(&db, &backup)
+        .await
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+
+    println!(
+        "restored the backup taken at {} from {}",
+        backup.created_at,
+        args.input.display()
+    );
+    Ok(())
+}
+
+/// Get the wildcard certificate of the proxy from `store`, falling
+/// back on the `ssl.pem` of older gateways and then on creating one
+async fn init_certs<P: AsRef<Path>>(
+    store: &dyn CertStore,
+    fs: P,
+    public: FQDN,
+    acme: AcmeClient,
+) -> ChainAndPrivateKey {
+    let identifier = format!("*.{public}");
+    if let Some(certs) = store.get(&identifier).await.unwrap() {
+        return certs;
+    }
+
     let tls_path = fs.as_ref().join("ssl.pem");
 
-    match ChainAndPrivateKey::load_pem(&tls_path) {
+    let certs = match ChainAndPrivateKey::load_pem(&tls_path) {
         Ok(valid) => valid,
         Err(_) => {
             let creds_path = fs.as_ref().join("acme.json");
@@ -252,8 +571,6 @@ async fn init_certs<P: AsRef<Path>>(fs: P, public: FQDN, acme: AcmeClient) -> Ch
             let creds = std::fs::File::open(creds_path).unwrap();
             let creds: AccountCredentials = serde_json::from_reader(&creds).unwrap();
 
-            let identifier = format!("*.{public}");
-
             // Use ::Dns01 challenge because that's the only supported
             // challenge type for wildcard domains
             let (chain, private_key) = acme
@@ -271,5 +588,9 @@ async fn init_certs<P: AsRef<Path>>(fs: P, public: FQDN, acme: AcmeClient) -> Ch
 
             certs
         }
-    }
+    };
+
+    store.put(&identifier, &certs).await.unwrap();
+
+    certs
 }
@@ -1,18 +1,21 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
 use std::convert::{identity, Infallible};
 use std::net::{IpAddr, SocketAddr};
 use std::time::Duration;
 
-use bollard::container::{
-    Config, CreateContainerOptions, RemoveContainerOptions, StopContainerOptions,
-};
+use bollard::container::{Config, CreateContainerOptions, RemoveContainerOptions};
 use bollard::errors::Error as DockerError;
-use bollard::models::{ContainerInspectResponse, ContainerStateStatusEnum};
+use bollard::models::{
+    ContainerConfig, ContainerInspectResponse, ContainerStateStatusEnum, HostConfig, RestartPolicy,
+    RestartPolicyNameEnum,
+};
 use bollard::network::{ConnectNetworkOptions, DisconnectNetworkOptions};
 use bollard::service::EndpointSettings;
 use bollard::system::EventsOptions;
+use chrono::{DateTime, Utc};
 use fqdn::FQDN;
 use futures::prelude::*;
+use futures::stream::BoxStream;
 use http::uri::InvalidUri;
 use http::Uri;
 use hyper::client::HttpConnector;
@@ -20,12 +23,15 @@ use hyper::Client;
 use once_cell::sync::Lazy;
 use rand::distributions::{Alphanumeric, DistString};
 use serde::{Deserialize, Serialize};
+use shuttle_common::models::project::{ContainerSummary, DeploymentTimeout, ResourceLimits};
 use tokio::time::{self, timeout};
-use tracing::{debug, error, info, instrument};
+use tracing::{debug, error, info, instrument, warn};
 
+use crate::args::StartupPolicy;
+use crate::warm::CLAIM_PATH;
 use crate::{
-    ContainerSettings, DockerContext, EndState, Error, ErrorKind, IntoTryState, ProjectName,
-    Refresh, State, TryState,
+    docker_op, ContainerSettings, DockerContext, EndState, Error, ErrorKind, IntoTryState,
+    ProjectName, Refresh, State, TryState,
 };
 
 macro_rules! safe_unwrap {
@@ -66,6 +72,136 @@ macro_rules! impl_from_variant {
 const RUNTIME_API_PORT: u16 = 8001;
 const MAX_RESTARTS: usize = 3;
 
+/// How long to wait for the image of a project to be pulled when
+/// docker does not have it yet
+const IMAGE_PULL_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+
+/// How long a project waits before checking again whether the node
+/// has room for it, when it is queued
+const CAPACITY_RECHECK_INTERVAL: Duration = Duration::from_secs(2);
+
+/// What the containers of projects get when neither they nor their
+/// account have resource limits of their own
+pub const DEFAULT_RESOURCE_LIMITS: ResourceLimits = ResourceLimits {
+    memory_mib: 6 * 1024,
+    millicpus: 4000,
+};
+
+/// Length of the docker scheduling period CPU quotas are a share of, in
+/// microseconds
+const CPU_PERIOD: i64 = 100_000;
+
+/// Whether docker failed to create a container because it does not
+/// have its image locally
+fn is_image_not_found(err: &DockerError) -> bool {
+    matches!(
+        err,
+        DockerError::DockerResponseServerError { status_code: 404, message }
+            if message.starts_with("No such image")
+    )
+}
+
+/// The volume `container` keeps the state of its deployer in
+pub(crate) fn mounted_volume(container: &ContainerInspectResponse) -> Option<String> {
+    container
+        .host_config
+        .as_ref()?
+        .mounts
+        .as_ref()?
+        .iter()
+        .find(|mount| mount.target.as_deref() == Some("/opt/shuttle"))?
+        .source
+        .clone()
+}
+
+/// The host configuration of a project container keeping its state
+/// in `volume`
+fn host_config(
+    volume: &str,
+    ResourceLimits {
+        memory_mib,
+        millicpus,
+    }: ResourceLimits,
+    restart_policy: &RestartPolicy,
+) -> Option<HostConfig> {
+    let memory = i64::from(memory_mib) * 1024 * 1024;
+
+    deserialize_json!({
+        "Mounts": [{
+            "Target": "/opt/shuttle",
+            "Source": volume,
+            "Type": "volume"
+        }],
+        // https://docs.docker.com/config/containers/resource_constraints/#memory
+        "Memory": memory, // hard limit
+        "MemoryReservation": memory / 3 * 2, // soft limit, applied if host is low on memory
+        // https://docs.docker.com/config/containers/resource_constraints/#cpu
+        "CpuPeriod": CPU_PERIOD,
+        "CpuQuota": i64::from(millicpus) * CPU_PERIOD / 1000,
+        "RestartPolicy": restart_policy
+    })
+}
+
+/// The configuration of a container kept warm under `name`, whose
+/// deployer waits at [CLAIM_PATH] for the arguments naming the project
+/// which claims it
+pub(crate) fn warm_container_config<C: DockerContext>(
+    ctx: &C,
+    name: &str,
+    volume: &str,
+) -> (CreateContainerOptions<String>, Config<String>) {
+    let ContainerSettings {
+        image,
+        prefix,
+        provisioner_host,
+        network_name,
+        restart_policy,
+        ..
+    } = ctx.container_settings();
+
+    let container_config = deserialize_json!(ContainerConfig: {
+        "Image": image,
+        "Hostname": name,
+        "Labels": {
+            "shuttle.prefix": prefix,
+        },
+        "Cmd": [
+            "--claim",
+            CLAIM_PATH,
+            "--api-address",
+            format!("0.0.0.0:{RUNTIME_API_PORT}"),
+            "--provisioner-address",
+            provisioner_host,
+            "--provisioner-port",
+            "8000",
+            "--proxy-address",
+            "0.0.0.0:8000",
+            "--artifacts-path",
+            "/opt/shuttle",
+            "--state",
+            "/opt/shuttle/deployer.sqlite",
+        ],
+        "Env": [
+            "RUST_LOG=debug",
+        ]
+    });
+
+    let mut config = Config::<String>::from(container_config);
+
+    let mut host_config = host_config(volume, DEFAULT_RESOURCE_LIMITS, restart_policy);
+    if let Some(host_config) = host_config.as_mut() {
+        // Ready to reach the provisioner as soon as it is claimed
+        host_config.network_mode = Some(network_name.clone());
+    }
+    config.host_config = host_config;
+
+    let options = CreateContainerOptions {
+        name: name.to_string(),
+    };
+
+    (options, config)
+}
+
 // Client used for health checks
 static CLIENT: Lazy<Client<HttpConnector>> = Lazy::new(Client::new);
 // Health check must succeed within 10 seconds
@@ -78,9 +214,12 @@ where
 {
     type Error = DockerError;
     async fn refresh(self, ctx: &Ctx) -> Result<Self, Self::Error> {
-        ctx.docker()
-            .inspect_container(self.id.as_ref().unwrap(), None)
-            .await
+        docker_op(
+            "inspect",
+            ctx.docker()
+                .inspect_container(self.id.as_ref().unwrap(), None),
+        )
+        .await
     }
 }
 
@@ -139,8 +278,36 @@ impl ContainerInspectResponseExt for ContainerInspectResponse {
 
 impl From<DockerError> for Error {
     fn from(err: DockerError) -> Self {
-        error!(error = %err, "internal Docker error");
-        Self::source(ErrorKind::Internal, err)
+        if crate::breaker::is_open_error(&err) {
+            let retry_after = crate::breaker::DOCKER_BREAKER
+                .snapshot()
+                .retry_in
+                .unwrap_or(crate::breaker::OPEN_FOR);
+            return Self::source(ErrorKind::ServiceUnavailable, err).with_retry_after(retry_after);
+        }
+
+        match &err {
+            DockerError::DockerResponseServerError {
+                status_code: 404,
+                message,
+            } if message.to_lowercase().contains("no such container") => {
+                Self::source(ErrorKind::ContainerNotFound, err)
+            }
+            DockerError::DockerResponseServerError {
+                status_code: 409, ..
+            } => Self::source(ErrorKind::ContainerConflict, err),
+            // Docker could not be reached at all
+            DockerError::IOError { .. }
+            | DockerError::HyperResponseError { .. }
+            | DockerError::RequestTimeoutError => {
+                warn!(error = %err, "Docker is unreachable");
+                Self::source(ErrorKind::ServiceUnavailable, err)
+            }
+            _ => {
+                error!(error = %err, "internal Docker error");
+                Self::source(ErrorKind::Internal, err)
+            }
+        }
     }
 }
 
@@ -154,6 +321,7 @@ pub enum Project {
     Ready(ProjectReady),
     Stopping(ProjectStopping),
     Stopped(ProjectStopped),
+    Frozen(ProjectFrozen),
     Destroying(ProjectDestroying),
     Destroyed(ProjectDestroyed),
     Errored(ProjectError),
@@ -167,12 +335,20 @@ impl_from_variant!(Project:
                    ProjectReady => Ready,
                    ProjectStopping => Stopping,
                    ProjectStopped => Stopped,
+                   ProjectFrozen => Frozen,
                    ProjectDestroying => Destroying,
                    ProjectDestroyed => Destroyed,
                    ProjectError => Errored);
 
 impl Project {
     pub fn stop(self) -> Result<Self, Error> {
+        if self.is_frozen() {
+            return Err(Error::custom(
+                ErrorKind::InvalidOperation,
+                "cannot stop a frozen project",
+            ));
+        }
+
         if let Some(container) = self.container() {
             Ok(Self::Stopping(ProjectStopping { container }))
         } else {
@@ -203,9 +379,62 @@ impl Project {
         matches!(self, Self::Destroyed(_))
     }
 
+    pub fn is_frozen(&self) -> bool {
+        matches!(self, Self::Frozen(_))
+    }
+
+    /// Let a frozen project start again. Projects which are not
+    /// frozen are left as they are.
+    pub fn unfreeze(self) -> Self {
+        match self {
+            Self::Frozen(ProjectFrozen { container }) => {
+                Self::Stopped(ProjectStopped { container })
+            }
+            otherwise => otherwise,
+        }
+    }
+
+    /// Whether the project is on its way to being ready, without
+    /// anyone having to start it
+    pub fn is_stopped(&self) -> bool {
+        matches!(self, Self::Stopped(_))
+    }
+
+    pub fn is_provisioning(&self) -> bool {
+        matches!(
+            self,
+            Self::Creating(_) | Self::Attaching(_) | Self::Starting(_) | Self::Started(_)
+        )
+    }
+
+    pub fn is_running(&self) -> bool {
+        matches!(self, Self::Starting(_) | Self::Started(_) | Self::Ready(_))
+    }
+
+    /// Where the gateway takes a project which was running before it
+    /// went down, once refreshed against docker on startup
+    pub fn on_startup(self, policy: StartupPolicy) -> Self {
+        match (policy, self) {
+            (StartupPolicy::Restore, Self::Stopped(ProjectStopped { container })) => {
+                Self::Starting(ProjectStarting { container })
+            }
+            (
+                StartupPolicy::Stopped,
+                Self::Starting(ProjectStarting { container })
+                | Self::Started(ProjectStarted { container, .. })
+                | Self::Ready(ProjectReady { container, .. }),
+            ) => Self::Stopping(ProjectStopping { container }),
+            (_, otherwise) => otherwise,
+        }
+    }
+
+    /// Where the project can be reached, if it is ready and docker
+    /// told us its address
     pub fn target_ip(&self) -> Result<Option<IpAddr>, Error> {
         match self.clone() {
-            Self::Ready(project_ready) => Ok(Some(*project_ready.target_ip())),
+            Self::Ready(project_ready) => Ok(
+                Some(*project_ready.target_ip()).filter(|target_ip| !target_ip.is_unspecified())
+            ),
             _ => Ok(None), // not ready
         }
     }
@@ -221,6 +450,7 @@ impl Project {
             Self::Started(_) => "started",
             Self::Ready(_) => "ready",
             Self::Stopped(_) => "stopped",
+            Self::Frozen(_) => "frozen",
             Self::Starting(_) => "starting",
             Self::Stopping(_) => "stopping",
             Self::Creating(_) => "creating",
@@ -239,6 +469,7 @@ impl Project {
             | Self::Ready(ProjectReady { container, .. })
             | Self::Stopping(ProjectStopping { container })
             | Self::Stopped(ProjectStopped { container })
+            | Self::Frozen(ProjectFrozen { container })
             | Self::Destroying(ProjectDestroying { container }) => Some(container.clone()),
             Self::Errored(ProjectError { ctx: Some(ctx), .. }) => ctx.container(),
             Self::Errored(_) | Self::Creating(_) | Self::Destroyed(_) => None,
@@ -268,6 +499,7 @@ impl From<Project> for shuttle_common::models::project::State {
             Project::Ready(_) => Self::Ready,
             Project::Stopping(_) => Self::Stopping,
             Project::Stopped(_) => Self::Stopped,
+            Project::Frozen(_) => Self::Frozen,
             Project::Destroying(_) => Self::Destroying,
             Project::Destroyed(_) => Self::Destroyed,
             Project::Errored(_) => Self::Errored,
@@ -289,7 +521,15 @@ where
         let previous_state = previous.state();
 
         let mut new = match self {
-            Self::Creating(creating) => creating.next(ctx).await.into_try_state(),
+            Self::Creating(creating) => match creating.has_capacity(ctx).await {
+                Ok(true) => creating.next(ctx).await.into_try_state(),
+                Ok(false) => {
+                    // The node is full, so stay queued until a slot frees up
+                    time::sleep(CAPACITY_RECHECK_INTERVAL).await;
+                    Ok(Self::Creating(creating))
+                }
+                Err(err) => Ok(Self::Errored(err)),
+            },
             Self::Attaching(attaching) => match attaching.next(ctx).await {
                 Err(ProjectError {
                     kind: ProjectErrorKind::NoNetwork,
@@ -310,6 +550,7 @@ where
             Self::Ready(ready) => ready.next(ctx).await.into_try_state(),
             Self::Stopped(stopped) => stopped.next(ctx).await.into_try_state(),
             Self::Stopping(stopping) => stopping.next(ctx).await.into_try_state(),
+            Self::Frozen(frozen) => frozen.next(ctx).await.into_try_state(),
             Self::Destroying(destroying) => destroying.next(ctx).await.into_try_state(),
             Self::Destroyed(destroyed) => destroyed.next(ctx).await.into_try_state(),
             Self::Errored(errored) => Ok(Self::Errored(errored)),
@@ -338,7 +579,10 @@ where
     Ctx: DockerContext,
 {
     fn is_done(&self) -> bool {
-        matches!(self, Self::Errored(_) | Self::Ready(_) | Self::Destroyed(_))
+        matches!(
+            self,
+            Self::Errored(_) | Self::Ready(_) | Self::Frozen(_) | Self::Destroyed(_)
+        )
     }
 }
 
@@ -379,7 +623,9 @@ where
                 .await
             {
                 Ok(container) => match container.state.as_ref().unwrap().status.as_ref().unwrap() {
-                    ContainerStateStatusEnum::RUNNING => {
+                    // A restarting container is on its way back up under
+                    // the restart policy
+                    ContainerStateStatusEnum::RUNNING | ContainerStateStatusEnum::RESTARTING => {
                         Self::Started(ProjectStarted::new(container))
                     }
                     ContainerStateStatusEnum::CREATED => {
@@ -405,6 +651,8 @@ where
                 }
                 Err(err) => return Err(err.into()),
             },
+            // Frozen containers are stopped on purpose
+            Self::Frozen(frozen) => Self::Frozen(frozen),
             Self::Destroying(destroying) => Self::Destroying(destroying),
             Self::Destroyed(destroyed) => Self::Destroyed(destroyed),
             Self::Errored(err) => Self::Errored(err),
@@ -421,10 +669,27 @@ pub struct ProjectCreating {
     /// Override the default fqdn (`${project_name}.${public}`)
     fqdn: Option<String>,
     /// Override the default image (specified in the args to this gateway)
+    /// or the image of the container this is recreated `from`
     image: Option<String>,
     /// Configuration will be extracted from there if specified (will
-    /// take precedence over other overrides)
+    /// take precedence over other overrides, except for the image)
     from: Option<ContainerInspectResponse>,
+    /// Override the volume holding the state of the deployer
+    /// (`${prefix}${project_name}_vol`), as for renamed projects
+    volume: Option<String>,
+    /// User environment variables to set on the container. These are
+    /// secret so they are loaded right before the container is created
+    /// and never persisted as part of the state
+    #[serde(skip)]
+    env: Vec<(String, String)>,
+    /// Override how long deployments have to start, loaded along with
+    /// the env vars so that the container always gets the latest one
+    #[serde(skip)]
+    deployment_timeout: Option<DeploymentTimeout>,
+    /// Limits of the project or its account, loaded like the timeout.
+    /// The platform default applies if there are none.
+    #[serde(skip)]
+    resource_limits: Option<ResourceLimits>,
 }
 
 impl ProjectCreating {
@@ -435,6 +700,10 @@ impl ProjectCreating {
             fqdn: None,
             image: None,
             from: None,
+            volume: None,
+            env: Vec::new(),
+            deployment_timeout: None,
+            resource_limits: None,
         }
     }
 
@@ -458,6 +727,26 @@ impl ProjectCreating {
         self
     }
 
+    pub fn with_volume(mut self, volume: String) -> Self {
+        self.volume = Some(volume);
+        self
+    }
+
+    pub fn with_env(mut self, env: Vec<(String, String)>) -> Self {
+        self.env = env;
+        self
+    }
+
+    pub fn with_deployment_timeout(mut self, timeout: Option<DeploymentTimeout>) -> Self {
+        self.deployment_timeout = timeout;
+        self
+    }
+
+    pub fn with_resource_limits(mut self, limits: Option<ResourceLimits>) -> Self {
+        self.resource_limits = limits;
+        self
+    }
+
     pub fn project_name(&self) -> &ProjectName {
         &self.project_name
     }
@@ -466,7 +755,7 @@ impl ProjectCreating {
         &self.initial_key
     }
 
-    fn container_name<C: DockerContext>(&self, ctx: &C) -> String {
+    pub fn container_name<C: DockerContext>(&self, ctx: &C) -> String {
         let prefix = &ctx.container_settings().prefix;
 
         let Self { project_name, .. } = &self;
@@ -483,6 +772,7 @@ impl ProjectCreating {
             prefix,
             provisioner_host,
             fqdn: public,
+            restart_policy,
             ..
         } = ctx.container_settings();
 
@@ -491,9 +781,17 @@ impl ProjectCreating {
             project_name,
             fqdn,
             image,
+            volume,
             ..
         } = &self;
 
+        // A container recreated `from` another keeps its volume, which
+        // is not named after the project if it was renamed
+        let volume = volume
+            .clone()
+            .or_else(|| self.from.as_ref().and_then(mounted_volume))
+            .unwrap_or_else(|| format!("{prefix}{project_name}_vol"));
+
         let create_container_options = CreateContainerOptions {
             name: self.container_name(ctx),
         };
@@ -538,29 +836,163 @@ impl ProjectCreating {
 
         let mut config = Config::<String>::from(container_config);
 
-        config.host_config = deserialize_json!({
-            "Mounts": [{
-                "Target": "/opt/shuttle",
-                "Source": format!("{prefix}{project_name}_vol"),
-                "Type": "volume"
-            }],
-            // https://docs.docker.com/config/containers/resource_constraints/#memory
-            "Memory": 6442450000i64, // 6 GiB hard limit
-            "MemoryReservation": 4295000000i64, // 4 GiB soft limit, applied if host is low on memory
-            // https://docs.docker.com/config/containers/resource_constraints/#cpu
-            "CpuPeriod": 100000i64,
-            "CpuQuota": 400000i64
-        });
+        // An explicit image wins over the one of the container this is
+        // recreated from
+        if let Some(image) = image {
+            config.image = Some(image.clone());
+        }
+
+        if !self.env.is_empty() {
+            // User variables override whatever was there before
+            let mut env: Vec<String> = config
+                .env
+                .take()
+                .unwrap_or_default()
+                .into_iter()
+                .filter(|var| {
+                    let name = var.split('=').next().unwrap_or_default();
+                    !self.env.iter().any(|(user_name, _)| user_name == name)
+                })
+                .collect();
+            env.extend(
+                self.env
+                    .iter()
+                    .map(|(name, value)| format!("{name}={value}")),
+            );
+            config.env = Some(env);
+        }
+
+        // Drop the timeout of the container this is recreated from, so
+        // that removing an override goes back to the deployer default
+        let mut cmd: Vec<String> = config.cmd.take().unwrap_or_default();
+        if let Some(at) = cmd.iter().position(|arg| arg == "--deployment-timeout") {
+            cmd.drain(at..(at + 2).min(cmd.len()));
+        }
+        if let Some(DeploymentTimeout { timeout_secs }) = &self.deployment_timeout {
+            cmd.extend(["--deployment-timeout".to_string(), timeout_secs.to_string()]);
+        }
+        config.cmd = Some(cmd);
+
+        config.host_config = host_config(
+            &volume,
+            self.resource_limits
+                .clone()
+                .unwrap_or(DEFAULT_RESOURCE_LIMITS),
+            restart_policy,
+        );
 
         debug!(
             r"generated a container configuration:
 CreateContainerOpts: {create_container_options:#?}
-Config: {config:#?}
-"
+Config: {:#?}
+",
+            Config {
+                env: None,
+                ..config.clone()
+            }
         );
 
         (create_container_options, config)
     }
+
+    /// Whether the node can take this project without going over
+    /// [ContainerSettings::max_running_projects]. Containers kept warm
+    /// count as running, so there is room for the project if one of
+    /// them can be claimed
+    async fn has_capacity<C: DockerContext>(&self, ctx: &C) -> Result<bool, ProjectError> {
+        if self.can_claim_warm_container() && ctx.warm_pool().map_or(false, |pool| !pool.is_empty())
+        {
+            return Ok(true);
+        }
+
+        match ctx.container_settings().max_running_projects {
+            Some(max) => Ok(ctx.running_projects().await? < max),
+            None => Ok(true),
+        }
+    }
+
+    /// Only new projects sticking to the defaults can be handed a warm
+    /// container, as their configuration is set when it is created
+    fn can_claim_warm_container(&self) -> bool {
+        self.from.is_none()
+            && self.image.is_none()
+            && self.volume.is_none()
+            && self.env.is_empty()
+            && self.deployment_timeout.is_none()
+            && self.resource_limits.is_none()
+    }
+
+    /// Hand a warm container over to this project, if there is one
+    /// for it. Falls back to creating a container on failure.
+    async fn claim_warm_container<C: DockerContext>(&self, ctx: &C) -> bool {
+        let pool = match ctx.warm_pool() {
+            Some(pool) if self.can_claim_warm_container() => pool,
+            _ => return false,
+        };
+
+        let public = &ctx.container_settings().fqdn;
+        let Self {
+            initial_key,
+            project_name,
+            fqdn,
+            ..
+        } = self;
+        let args = [
+            "--admin-secret".to_string(),
+            initial_key.clone(),
+            "--project".to_string(),
+            project_name.to_string(),
+            "--proxy-fqdn".to_string(),
+            fqdn.clone().unwrap_or(format!("{project_name}.{public}")),
+        ];
+
+        match pool.claim(ctx, &self.container_name(ctx), &args).await {
+            Ok(claimed) => claimed,
+            Err(err) => {
+                warn!(
+                    error = &err as &dyn std::error::Error,
+                    "failed to claim a warm container"
+                );
+                false
+            }
+        }
+    }
+
+    /// Create the container of this project. If docker does not have
+    /// its image, the image is pulled and creation retried once.
+    async fn create_container<C: DockerContext>(&self, ctx: &C) -> Result<(), ProjectError> {
+        let (opts, config) = self.generate_container_config(ctx);
+        let image = config
+            .image
+            .clone()
+            .unwrap_or_else(|| ctx.container_settings().image.clone());
+
+        match ctx.create_container(opts.clone(), config.clone()).await {
+            Err(err) if is_image_not_found(&err) => {
+                info!(%image, "image not found locally, pulling it");
+                match timeout(IMAGE_PULL_TIMEOUT, ctx.pull_image(&image)).await {
+                    Ok(Ok(())) => {}
+                    Ok(Err(err)) => {
+                        return Err(ProjectError::image_pull(format!(
+                            "failed to pull image {image}: {err}"
+                        )))
+                    }
+                    Err(_) => {
+                        return Err(ProjectError::image_pull(format!(
+                            "timed out pulling image {image}"
+                        )))
+                    }
+                }
+
+                ctx.create_container(opts, config).await?;
+            }
+            result => {
+                result?;
+            }
+        }
+
+        Ok(())
+    }
 }
 
 #[async_trait]
@@ -574,20 +1006,17 @@ where
     #[instrument(skip_all)]
     async fn next(self, ctx: &Ctx) -> Result<Self::Next, Self::Error> {
         let container_name = self.container_name(ctx);
-        let container = ctx
-            .docker()
-            // If container already exists, use that
-            .inspect_container(&container_name.clone(), None)
+        // If container already exists, use that
+        let container = docker_op("inspect", ctx.docker().inspect_container(&container_name.clone(), None))
             // Otherwise create it
             .or_else(|err| async move {
                 if matches!(err, DockerError::DockerResponseServerError { status_code, .. } if status_code == 404) {
-                    let (opts, config) = self.generate_container_config(ctx);
-                    ctx.docker()
-                        .create_container(Some(opts), config)
-                        .and_then(|_| ctx.docker().inspect_container(&container_name, None))
-                        .await
+                    if !self.claim_warm_container(ctx).await {
+                        self.create_container(ctx).await?;
+                    }
+                    Ok(docker_op("inspect", ctx.docker().inspect_container(&container_name, None)).await?)
                 } else {
-                    Err(err)
+                    Err(ProjectError::from(err))
                 }
             })
             .await?;
@@ -691,10 +1120,12 @@ where
     async fn next(self, ctx: &Ctx) -> Result<Self::Next, Self::Error> {
         let container_id = self.container.id.as_ref().unwrap();
 
-        ctx.docker()
-            .start_container::<String>(container_id, None)
-            .await
-            .or_else(|err| {
+        docker_op(
+            "start",
+            ctx.docker().start_container::<String>(container_id, None),
+        )
+        .await
+        .or_else(|err| {
                 if matches!(err, DockerError::DockerResponseServerError { status_code, .. } if status_code == 304) {
                     // Already started
                     Ok(())
@@ -882,12 +1313,14 @@ where
     #[instrument(skip_all)]
     async fn next(self, ctx: &Ctx) -> Result<Self::Next, Self::Error> {
         let Self { container } = self;
-        ctx.docker()
-            .stop_container(
+        docker_op(
+            "stop",
+            ctx.docker().stop_container(
                 container.id.as_ref().unwrap(),
-                Some(StopContainerOptions { t: 30 }),
-            )
-            .await?;
+                Some(ctx.container_settings().stop_options()),
+            ),
+        )
+        .await?;
         Ok(Self::Next {
             container: container.refresh(ctx).await?,
         })
@@ -911,6 +1344,19 @@ where
     async fn next(self, ctx: &Ctx) -> Result<Self::Next, Self::Error> {
         let container = self.container;
 
+        // Docker gave up restarting it, so do not try any harder
+        if let RestartPolicy {
+            name: Some(RestartPolicyNameEnum::ON_FAILURE),
+            maximum_retry_count: Some(max),
+        } = &ctx.container_settings().restart_policy
+        {
+            if container.restart_count.unwrap_or_default() >= *max {
+                return Err(ProjectError::internal(
+                    "container kept failing and ran out of restarts",
+                ));
+            }
+        }
+
         let since = (chrono::Utc::now() - chrono::Duration::minutes(15))
             .timestamp()
             .to_string();
@@ -947,6 +1393,33 @@ where
     }
 }
 
+/// A project suspended by an admin. Its container is stopped but kept
+/// around, along with its data, until it is unfrozen.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ProjectFrozen {
+    container: ContainerInspectResponse,
+}
+
+impl ProjectFrozen {
+    pub fn new(container: ContainerInspectResponse) -> Self {
+        Self { container }
+    }
+}
+
+#[async_trait]
+impl<Ctx> State<Ctx> for ProjectFrozen
+where
+    Ctx: DockerContext,
+{
+    type Next = ProjectFrozen;
+    type Error = ProjectError;
+
+    #[instrument(skip_all)]
+    async fn next(self, _ctx: &Ctx) -> Result<Self::Next, Self::Error> {
+        Ok(self)
+    }
+}
+
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct ProjectDestroying {
     container: ContainerInspectResponse,
@@ -963,20 +1436,25 @@ where
     #[instrument(skip_all)]
     async fn next(self, ctx: &Ctx) -> Result<Self::Next, Self::Error> {
         let container_id = self.container.id.as_ref().unwrap();
-        ctx.docker()
-            .stop_container(container_id, Some(StopContainerOptions { t: 1 }))
-            .await
-            .unwrap_or(());
-        ctx.docker()
-            .remove_container(
+        docker_op(
+            "stop",
+            ctx.docker()
+                .stop_container(container_id, Some(ctx.container_settings().stop_options())),
+        )
+        .await
+        .unwrap_or(());
+        docker_op(
+            "remove",
+            ctx.docker().remove_container(
                 container_id,
                 Some(RemoveContainerOptions {
                     force: true,
                     ..Default::default()
                 }),
-            )
-            .await
-            .unwrap_or(());
+            ),
+        )
+        .await
+        .unwrap_or(());
         Ok(Self::Next {
             destroyed: Some(self.container),
         })
@@ -988,6 +1466,12 @@ pub struct ProjectDestroyed {
     destroyed: Option<ContainerInspectResponse>,
 }
 
+impl ProjectDestroyed {
+    pub fn new(destroyed: Option<ContainerInspectResponse>) -> Self {
+        Self { destroyed }
+    }
+}
+
 #[async_trait]
 impl<Ctx> State<Ctx> for ProjectDestroyed
 where
@@ -1006,6 +1490,7 @@ where
 pub enum ProjectErrorKind {
     Internal,
     NoNetwork,
+    ImagePull,
 }
 
 /// A runtime error coming from inside a project
@@ -1032,6 +1517,65 @@ impl ProjectError {
             ctx: None,
         }
     }
+
+    pub fn image_pull<S: AsRef<str>>(message: S) -> Self {
+        Self {
+            kind: ProjectErrorKind::ImagePull,
+            message: message.as_ref().to_string(),
+            ctx: None,
+        }
+    }
+
+    /// Remove the container the project left behind when it crashed at
+    /// `crashed_at`, once it has been kept for `retention` as of `now`.
+    /// Until then the project is handed back as it is.
+    pub async fn reap<Ctx: DockerContext>(
+        self,
+        ctx: &Ctx,
+        crashed_at: DateTime<Utc>,
+        retention: Duration,
+        now: DateTime<Utc>,
+    ) -> Result<Self, DockerError> {
+        let container_id = match self.ctx.as_ref().and_then(|ctx| ctx.container_id()) {
+            Some(container_id) => container_id,
+            None => return Ok(self),
+        };
+
+        if !retention_elapsed(crashed_at, retention, now) {
+            return Ok(self);
+        }
+
+        match docker_op(
+            "remove",
+            ctx.docker().remove_container(
+                &container_id,
+                Some(RemoveContainerOptions {
+                    force: true,
+                    ..Default::default()
+                }),
+            ),
+        )
+        .await
+        {
+            Ok(()) => info!(container_id, "removed container of a crashed project"),
+            Err(DockerError::DockerResponseServerError {
+                status_code: 404, ..
+            }) => {}
+            Err(err) => return Err(err),
+        }
+
+        Ok(Self { ctx: None, ..self })
+    }
+}
+
+/// Whether something which happened at `at` has been kept for
+/// `retention` as of `now`
+fn retention_elapsed(at: DateTime<Utc>, retention: Duration, now: DateTime<Utc>) -> bool {
+    chrono::Duration::from_std(retention)
+        .ok()
+        .and_then(|retention| at.checked_add_signed(retention))
+        .map(|until| until <= now)
+        .unwrap_or(false)
 }
 
 impl std::fmt::Display for ProjectError {
@@ -1097,6 +1641,119 @@ where
     }
 }
 
+/// Number of lines returned by [`container_logs`] when no tail is given
+pub const DEFAULT_CONTAINER_LOGS_TAIL: usize = 100;
+
+/// Maximum number of lines [`container_logs`] goes back
+pub const MAX_CONTAINER_LOGS_TAIL: usize = 10_000;
+
+/// The raw output of a project's container, up to its last `tail`
+/// lines. If `follow` is set, new output is streamed as it comes in.
+pub async fn container_logs<Ctx: DockerContext>(
+    ctx: &Ctx,
+    project: &Project,
+    tail: usize,
+    follow: bool,
+) -> Result<BoxStream<'static, Result<String, Error>>, Error> {
+    let container_id = project
+        .container_id()
+        .ok_or_else(|| Error::from_kind(ErrorKind::ContainerNotFound))?;
+    let tail = tail.min(MAX_CONTAINER_LOGS_TAIL);
+
+    if follow {
+        // Surface a missing container before anything is streamed. A
+        // quiet container may not output anything to go by for a while.
+        if !ctx.container_exists(&container_id).await? {
+            return Err(Error::from_kind(ErrorKind::ContainerNotFound));
+        }
+        return Ok(ctx.container_logs(&container_id, tail, follow));
+    }
+
+    let mut logs = ctx.container_logs(&container_id, tail, follow);
+
+    // A chunk of output can hold more than one line, so the tail
+    // is enforced here as well
+    let mut lines = VecDeque::with_capacity(tail);
+    while let Some(chunk) = logs.next().await {
+        for line in chunk?.lines() {
+            if tail == 0 {
+                break;
+            }
+            if lines.len() == tail {
+                lines.pop_front();
+            }
+            lines.push_back(format!("{line}\n"));
+        }
+    }
+
+    Ok(stream::iter(lines.into_iter().map(Ok)).boxed())
+}
+
+/// What docker currently reports about the container of a project, if
+/// it has one
+pub async fn container_summary<Ctx: DockerContext>(
+    ctx: &Ctx,
+    project_name: &ProjectName,
+) -> Result<Option<ContainerSummary>, Error> {
+    let settings = ctx.container_settings();
+    let container = match docker_op(
+        "inspect",
+        ctx.docker()
+            .inspect_container(&format!("{}{project_name}_run", settings.prefix), None),
+    )
+    .await
+    {
+        Ok(container) => container,
+        Err(DockerError::DockerResponseServerError {
+            status_code: 404, ..
+        }) => return Ok(None),
+        Err(err) => return Err(err.into()),
+    };
+
+    let address = container_address(settings, &container).map(|addr| addr.to_string());
+
+    Ok(Some(ContainerSummary {
+        id: container.id,
+        name: container.name,
+        image: container.config.and_then(|config| config.image),
+        status: container
+            .state
+            .as_ref()
+            .and_then(|state| state.status.as_ref())
+            .map(|status| status.to_string()),
+        started_at: container.state.and_then(|state| state.started_at),
+        restart_count: container.restart_count,
+        address,
+    }))
+}
+
+/// Where the proxy reaches a container on the network of the gateway,
+/// for as long as it is running. A container which is not on that
+/// network cannot be reached at all, whatever other networks it is on.
+fn container_address(
+    settings: &ContainerSettings,
+    container: &ContainerInspectResponse,
+) -> Option<SocketAddr> {
+    let running = container
+        .state
+        .as_ref()
+        .and_then(|state| state.running)
+        .unwrap_or(false);
+    if !running {
+        return None;
+    }
+
+    let network = container
+        .network_settings
+        .as_ref()?
+        .networks
+        .as_ref()?
+        .get(&settings.network_name)?;
+    let target_ip: IpAddr = network.ip_address.as_ref()?.parse().ok()?;
+
+    Some(SocketAddr::new(target_ip, 8000))
+}
+
 pub mod exec {
 
     use std::sync::Arc;
@@ -1179,6 +1836,109 @@ pub mod exec {
 
         Ok(())
     }
+
+    /// Queue the removal of the containers crashed projects left
+    /// behind, for the ones which have been kept for `retention`.
+    /// Returns how many projects were queued.
+    pub async fn reap_crashed(
+        gateway: Arc<GatewayService>,
+        sender: Sender<BoxedTask>,
+        retention: Duration,
+    ) -> Result<usize, Error> {
+        let now = Utc::now();
+        let mut queued = 0;
+        for (project_name, _) in gateway.iter_projects().await? {
+            let project = gateway.find_project(&project_name).await?;
+            if !matches!(project, Project::Errored(_)) || project.container().is_none() {
+                continue;
+            }
+
+            let crashed_at = match gateway.project_crashed_at(&project_name).await? {
+                Some(crashed_at) if retention_elapsed(crashed_at, retention, now) => crashed_at,
+                _ => continue,
+            };
+
+            gateway
+                .new_task()
+                .project(project_name)
+                .and_then(task::run(move |ctx| async move {
+                    match ctx.state {
+                        Project::Errored(err) => {
+                            match err
+                                .reap(&ctx.gateway, crashed_at, retention, Utc::now())
+                                .await
+                            {
+                                Ok(err) => TaskResult::Done(Project::Errored(err)),
+                                Err(err) => TaskResult::Err(err.into()),
+                            }
+                        }
+                        // it was revived in the meantime
+                        project => TaskResult::Done(project),
+                    }
+                }))
+                .send(&sender)
+                .await?;
+            queued += 1;
+        }
+
+        Ok(queued)
+    }
+
+    /// Queue a refresh of every project which is not destroyed, to
+    /// bring its stored state back in line with what docker reports.
+    /// Projects whose container is gone are recreated. Returns how
+    /// many projects were queued.
+    pub async fn reconcile(
+        gateway: Arc<GatewayService>,
+        sender: Sender<BoxedTask>,
+    ) -> Result<usize, Error> {
+        let mut queued = 0;
+        for (project_name, _) in gateway.iter_projects().await? {
+            if gateway.find_project(&project_name).await?.is_destroyed() {
+                continue;
+            }
+
+            gateway
+                .new_task()
+                .project(project_name)
+                .and_then(task::refresh())
+                .send(&sender)
+                .await?;
+            queued += 1;
+        }
+
+        Ok(queued)
+    }
+
+    /// Like [`reconcile`], with the projects which were running before
+    /// the gateway went down then started or stopped as `policy` says.
+    pub async fn reconcile_on_startup(
+        gateway: Arc<GatewayService>,
+        sender: Sender<BoxedTask>,
+        policy: StartupPolicy,
+    ) -> Result<usize, Error> {
+        let mut queued = 0;
+        for (project_name, _) in gateway.iter_projects().await? {
+            let project = gateway.find_project(&project_name).await?;
+            if project.is_destroyed() {
+                continue;
+            }
+
+            let mut builder = gateway
+                .new_task()
+                .project(project_name)
+                .and_then(task::refresh());
+            if project.is_running() {
+                builder = builder.and_then(task::run(move |ctx| async move {
+                    TaskResult::Done(ctx.state.on_startup(policy))
+                }));
+            }
+            builder.send(&sender).await?;
+            queued += 1;
+        }
+
+        Ok(queued)
+    }
 }
 
 #[cfg(test)]
@@ -1186,13 +1946,148 @@ pub mod tests {
 
     use bollard::models::ContainerState;
     use bollard::service::NetworkSettings;
+    use bollard::Docker;
+    use futures::future::BoxFuture;
     use futures::prelude::*;
     use hyper::{Body, Request, StatusCode};
 
     use super::*;
+    use crate::args::ContainerRestart;
     use crate::tests::{assert_matches, assert_stream_matches, World};
+    use crate::warm::WarmPool;
     use crate::EndStateExt;
 
+    /// A context whose containers always output the same logs
+    struct CannedLogsContext {
+        docker: Docker,
+        settings: ContainerSettings,
+    }
+
+    impl CannedLogsContext {
+        fn new() -> Self {
+            Self {
+                // never connected to
+                docker: Docker::connect_with_unix(
+                    "/var/run/docker.sock",
+                    60,
+                    bollard::API_DEFAULT_VERSION,
+                )
+                .unwrap(),
+                settings: ContainerSettings {
+                    prefix: "shuttle_test_".to_string(),
+                    image: "deployer".to_string(),
+                    provisioner_host: "provisioner".to_string(),
+                    network_name: "shuttle_default".to_string(),
+                    network_id: "shuttle_default".to_string(),
+                    fqdn: "test.shuttleapp.rs".to_string(),
+                    max_running_projects: None,
+                    restart_policy: Default::default(),
+                    stop_timeout_secs: 30,
+                },
+            }
+        }
+    }
+
+    impl DockerContext for CannedLogsContext {
+        fn docker(&self) -> &Docker {
+            &self.docker
+        }
+
+        fn container_settings(&self) -> &ContainerSettings {
+            &self.settings
+        }
+
+        fn container_exists<'a>(
+            &'a self,
+            container_id: &'a str,
+        ) -> BoxFuture<'a, Result<bool, DockerError>> {
+            future::ok(matches!(container_id, "matrix" | "quiet")).boxed()
+        }
+
+        fn container_logs(
+            &self,
+            container_id: &str,
+            _tail: usize,
+            _follow: bool,
+        ) -> BoxStream<'static, Result<String, Error>> {
+            if container_id == "matrix" {
+                stream::iter(
+                    ["starting\n", "line 1\nline 2\n", "line 3\n", "panicked!\n"]
+                        .map(|chunk| Ok(chunk.to_string())),
+                )
+                .boxed()
+            } else if container_id == "quiet" {
+                // a container which has nothing to say
+                stream::pending().boxed()
+            } else {
+                stream::once(future::ready(Err(Error::from_kind(
+                    ErrorKind::ContainerNotFound,
+                ))))
+                .boxed()
+            }
+        }
+    }
+
+    fn stopped_with_container(id: &str) -> Project {
+        Project::Stopped(ProjectStopped {
+            container: ContainerInspectResponse {
+                id: Some(id.to_string()),
+                ..Default::default()
+            },
+        })
+    }
+
+    #[tokio::test]
+    async fn container_logs_tail() -> anyhow::Result<()> {
+        let ctx = CannedLogsContext::new();
+        let project = stopped_with_container("matrix");
+
+        let logs: Vec<_> = container_logs(&ctx, &project, 3, false)
+            .await?
+            .try_collect()
+            .await?;
+        assert_eq!(logs, vec!["line 2\n", "line 3\n", "panicked!\n"]);
+
+        let logs: Vec<_> = container_logs(&ctx, &project, 100, false)
+            .await?
+            .try_collect()
+            .await?;
+        assert_eq!(logs.len(), 5);
+
+        let logs: Vec<String> = container_logs(&ctx, &project, 0, false)
+            .await?
+            .try_collect()
+            .await?;
+        assert!(logs.is_empty());
+
+        // following the logs of a container which is quiet does not wait
+        // on it to output anything
+        let mut quiet = tokio::time::timeout(
+            Duration::from_secs(1),
+            container_logs(&ctx, &stopped_with_container("quiet"), 0, true),
+        )
+        .await??;
+        assert!(quiet.next().now_or_never().is_none());
+
+        let no_such_container =
+            container_logs(&ctx, &stopped_with_container("reloaded"), 3, true).await;
+        assert_eq!(
+            no_such_container.err().map(|err| err.kind()),
+            Some(ErrorKind::ContainerNotFound)
+        );
+
+        let destroyed = Project::Destroyed(ProjectDestroyed { destroyed: None });
+        assert_eq!(
+            container_logs(&ctx, &destroyed, 3, false)
+                .await
+                .err()
+                .map(|err| err.kind()),
+            Some(ErrorKind::ContainerNotFound)
+        );
+
+        Ok(())
+    }
+
     #[tokio::test]
     async fn create_start_stop_destroy_project() -> anyhow::Result<()> {
         let world = World::new().await;
@@ -1207,6 +2102,8 @@ pub mod tests {
                 fqdn: None,
                 image: None,
                 from: None,
+                volume: None,
+                env: Vec::new(),
             }),
             #[assertion = "Container created, attach network"]
             Ok(Project::Attaching(ProjectAttaching {
@@ -1318,4 +2215,718 @@ pub mod tests {
 
         Ok(())
     }
+
+    /// A context which pretends docker does not have the image of a
+    /// project until it is pulled
+    struct MissingImageContext {
+        inner: crate::tests::WorldContext,
+        missing: std::sync::atomic::AtomicBool,
+        pull_fails: bool,
+        creates: std::sync::atomic::AtomicUsize,
+        pulls: std::sync::Mutex<Vec<String>>,
+    }
+
+    impl MissingImageContext {
+        fn new(inner: crate::tests::WorldContext, pull_fails: bool) -> Self {
+            Self {
+                inner,
+                missing: std::sync::atomic::AtomicBool::new(true),
+                pull_fails,
+                creates: Default::default(),
+                pulls: Default::default(),
+            }
+        }
+    }
+
+    impl DockerContext for MissingImageContext {
+        fn docker(&self) -> &Docker {
+            self.inner.docker()
+        }
+
+        fn container_settings(&self) -> &ContainerSettings {
+            self.inner.container_settings()
+        }
+
+        fn create_container(
+            &self,
+            options: CreateContainerOptions<String>,
+            config: Config<String>,
+        ) -> BoxFuture<'_, Result<String, DockerError>> {
+            use std::sync::atomic::Ordering;
+
+            self.creates.fetch_add(1, Ordering::SeqCst);
+            if self.missing.load(Ordering::SeqCst) {
+                future::ready(Err(DockerError::DockerResponseServerError {
+                    status_code: 404,
+                    message: format!("No such image: {}", config.image.unwrap()),
+                }))
+                .boxed()
+            } else {
+                self.inner.create_container(options, config)
+            }
+        }
+
+        fn pull_image<'a>(&'a self, image: &'a str) -> BoxFuture<'a, Result<(), DockerError>> {
+            self.pulls.lock().unwrap().push(image.to_string());
+            if self.pull_fails {
+                future::ready(Err(DockerError::DockerResponseServerError {
+                    status_code: 500,
+                    message: "registry unreachable".to_string(),
+                }))
+                .boxed()
+            } else {
+                self.missing
+                    .store(false, std::sync::atomic::Ordering::SeqCst);
+                future::ok(()).boxed()
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn create_pulls_missing_image() -> anyhow::Result<()> {
+        use std::sync::atomic::Ordering;
+
+        let world = World::new().await;
+        let ctx = MissingImageContext::new(world.context(), false);
+        let image = ctx.container_settings().image.clone();
+
+        let project = Project::Creating(ProjectCreating::new(
+            "pulling".parse().unwrap(),
+            "test".to_string(),
+        ));
+        let project = project.next(&ctx).await?;
+        assert!(matches!(project, Project::Attaching(_)));
+
+        // the image was pulled once and creation retried after it
+        assert_eq!(*ctx.pulls.lock().unwrap(), vec![image]);
+        assert_eq!(ctx.creates.load(Ordering::SeqCst), 2);
+
+        ctx.docker()
+            .remove_container(
+                project.container_id().unwrap().as_str(),
+                Some(RemoveContainerOptions {
+                    force: true,
+                    ..Default::default()
+                }),
+            )
+            .await?;
+
+        // when the pull fails, the project errors out without retrying
+        let ctx = MissingImageContext::new(world.context(), true);
+        let project = Project::Creating(ProjectCreating::new(
+            "pulling".parse().unwrap(),
+            "test".to_string(),
+        ));
+        let project = project.next(&ctx).await?;
+        assert!(matches!(
+            project,
+            Project::Errored(ProjectError {
+                kind: ProjectErrorKind::ImagePull,
+                ..
+            })
+        ));
+        assert_eq!(ctx.creates.load(Ordering::SeqCst), 1);
+
+        Ok(())
+    }
+
+    struct FullNodeContext {
+        inner: crate::tests::WorldContext,
+        running: std::sync::atomic::AtomicUsize,
+    }
+
+    impl DockerContext for FullNodeContext {
+        fn docker(&self) -> &Docker {
+            self.inner.docker()
+        }
+
+        fn container_settings(&self) -> &ContainerSettings {
+            self.inner.container_settings()
+        }
+
+        fn running_projects(&self) -> BoxFuture<'_, Result<usize, DockerError>> {
+            future::ok(self.running.load(std::sync::atomic::Ordering::SeqCst)).boxed()
+        }
+    }
+
+    #[tokio::test]
+    async fn create_waits_for_capacity() -> anyhow::Result<()> {
+        let world = World::new().await;
+        let mut inner = world.context();
+        inner.container_settings.max_running_projects = Some(1);
+        let ctx = FullNodeContext {
+            inner,
+            running: std::sync::atomic::AtomicUsize::new(1),
+        };
+
+        // the node is full, so the project stays queued
+        let project = Project::Creating(ProjectCreating::new(
+            "queued".parse().unwrap(),
+            "test".to_string(),
+        ));
+        let project = project.next(&ctx).await?;
+        assert!(matches!(project, Project::Creating(_)));
+        assert!(project.container_id().is_none());
+
+        // and goes ahead once a slot frees up
+        ctx.running.store(0, std::sync::atomic::Ordering::SeqCst);
+        let project = project.next(&ctx).await?;
+        assert!(matches!(project, Project::Attaching(_)));
+
+        ctx.docker()
+            .remove_container(
+                project.container_id().unwrap().as_str(),
+                Some(RemoveContainerOptions {
+                    force: true,
+                    ..Default::default()
+                }),
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    /// A context keeping containers warm, which counts the containers
+    /// it creates
+    struct WarmContext {
+        inner: crate::tests::WorldContext,
+        pool: WarmPool,
+        creates: std::sync::atomic::AtomicUsize,
+    }
+
+    impl DockerContext for WarmContext {
+        fn docker(&self) -> &Docker {
+            self.inner.docker()
+        }
+
+        fn container_settings(&self) -> &ContainerSettings {
+            self.inner.container_settings()
+        }
+
+        fn warm_pool(&self) -> Option<&WarmPool> {
+            Some(&self.pool)
+        }
+
+        fn create_container(
+            &self,
+            options: CreateContainerOptions<String>,
+            config: Config<String>,
+        ) -> BoxFuture<'_, Result<String, DockerError>> {
+            self.creates
+                .fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            self.inner.create_container(options, config)
+        }
+    }
+
+    #[tokio::test]
+    async fn create_claims_warm_container() -> anyhow::Result<()> {
+        use std::sync::atomic::Ordering;
+
+        let world = World::new().await;
+        let ctx = WarmContext {
+            inner: world.context(),
+            pool: WarmPool::new(1, Duration::from_secs(60)),
+            creates: Default::default(),
+        };
+
+        ctx.pool.refill(&ctx).await?;
+        assert_eq!(ctx.pool.len(), 1);
+        let warmed = ctx.creates.load(Ordering::SeqCst);
+
+        // a project starting from the pool does not wait on a container
+        // to be created
+        let warm = ProjectCreating::new("warm".parse().unwrap(), "test".to_string());
+        let container_name = warm.container_name(&ctx);
+        let warm = Project::Creating(warm).next(&ctx).await?;
+        assert!(matches!(warm, Project::Attaching(_)));
+        assert_eq!(ctx.creates.load(Ordering::SeqCst), warmed);
+        assert!(ctx.pool.is_empty());
+
+        // the warm container is now the project's
+        let container = ctx
+            .docker()
+            .inspect_container(&container_name, None)
+            .await?;
+        assert_eq!(container.id, warm.container_id());
+        let warm_prefix = format!("{}warm_", ctx.container_settings().prefix);
+        assert!(mounted_volume(&container)
+            .unwrap()
+            .starts_with(&warm_prefix));
+
+        // with the pool empty, the next project starts cold
+        let cold = Project::Creating(ProjectCreating::new(
+            "cold".parse().unwrap(),
+            "test".to_string(),
+        ));
+        let cold = cold.next(&ctx).await?;
+        assert!(matches!(cold, Project::Attaching(_)));
+        assert_eq!(ctx.creates.load(Ordering::SeqCst), warmed + 1);
+
+        for project in [warm, cold] {
+            ctx.docker()
+                .remove_container(
+                    project.container_id().unwrap().as_str(),
+                    Some(RemoveContainerOptions {
+                        force: true,
+                        ..Default::default()
+                    }),
+                )
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn container_summary_address() {
+        use axum::http::{StatusCode, Uri};
+        use axum::response::IntoResponse;
+        use axum::{Json, Router};
+
+        // Serves just enough of the Docker API to inspect a running
+        // and a stopped container
+        let router = Router::new().fallback(|uri: Uri| async move {
+            match uri.path().rsplit_once("/containers/") {
+                Some((_, "shuttle_test_matrix_run/json")) => Json(serde_json::json!({
+                    "Id": "matrix",
+                    "State": { "Status": "running", "Running": true },
+                    "NetworkSettings": {
+                        "Networks": {
+                            "bridge": { "IPAddress": "172.17.0.2" },
+                            "shuttle_default": { "IPAddress": "10.99.0.42" },
+                        }
+                    }
+                }))
+                .into_response(),
+                Some((_, "shuttle_test_nebuchadnezzar_run/json")) => Json(serde_json::json!({
+                    "Id": "nebuchadnezzar",
+                    "State": { "Status": "running", "Running": true },
+                    "NetworkSettings": {
+                        "Networks": { "bridge": { "IPAddress": "172.17.0.3" } }
+                    }
+                }))
+                .into_response(),
+                Some((_, "shuttle_test_reloaded_run/json")) => Json(serde_json::json!({
+                    "Id": "reloaded",
+                    "State": { "Status": "exited", "Running": false },
+                    "NetworkSettings": {
+                        "Networks": { "shuttle_default": { "IPAddress": "" } }
+                    }
+                }))
+                .into_response(),
+                _ => (
+                    StatusCode::NOT_FOUND,
+                    Json(serde_json::json!({ "message": "no such container" })),
+                )
+                    .into_response(),
+            }
+        });
+
+        let port = portpicker::pick_unused_port().unwrap();
+        let addr: SocketAddr = format!("127.0.0.1:{port}").parse().unwrap();
+        tokio::spawn(axum::Server::bind(&addr).serve(router.into_make_service()));
+        // give the server a moment to start listening
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let ctx = CannedLogsContext {
+            docker: Docker::connect_with_http(
+                &format!("http://{addr}"),
+                5,
+                bollard::API_DEFAULT_VERSION,
+            )
+            .unwrap(),
+            ..CannedLogsContext::new()
+        };
+
+        let summary = container_summary(&ctx, &"matrix".parse().unwrap())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(summary.status.as_deref(), Some("running"));
+        assert_eq!(summary.address.as_deref(), Some("10.99.0.42:8000"));
+
+        // a container off the network of the gateway cannot be reached
+        let summary = container_summary(&ctx, &"nebuchadnezzar".parse().unwrap())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(summary.status.as_deref(), Some("running"));
+        assert!(summary.address.is_none());
+
+        // a stopped container has no address to be reached at
+        let summary = container_summary(&ctx, &"reloaded".parse().unwrap())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(summary.status.as_deref(), Some("exited"));
+        assert!(summary.address.is_none());
+
+        assert!(container_summary(&ctx, &"zion".parse().unwrap())
+            .await
+            .unwrap()
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn crashed_containers_are_reaped_after_retention() {
+        use axum::http::{Method, StatusCode, Uri};
+        use axum::response::IntoResponse;
+        use axum::Router;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::sync::Arc;
+
+        // Serves just enough of the Docker API to remove a container,
+        // counting how many times it was asked to
+        let removed = Arc::new(AtomicUsize::new(0));
+        let router = Router::new().fallback({
+            let removed = removed.clone();
+            move |method: Method, uri: Uri| {
+                let removed = removed.clone();
+                async move {
+                    if method == Method::DELETE && uri.path().ends_with("/containers/matrix") {
+                        removed.fetch_add(1, Ordering::SeqCst);
+                        StatusCode::NO_CONTENT.into_response()
+                    } else {
+                        StatusCode::NOT_FOUND.into_response()
+                    }
+                }
+            }
+        });
+
+        let port = portpicker::pick_unused_port().unwrap();
+        let addr: SocketAddr = format!("127.0.0.1:{port}").parse().unwrap();
+        tokio::spawn(axum::Server::bind(&addr).serve(router.into_make_service()));
+        // give the server a moment to start listening
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let ctx = CannedLogsContext {
+            docker: Docker::connect_with_http(
+                &format!("http://{addr}"),
+                5,
+                bollard::API_DEFAULT_VERSION,
+            )
+            .unwrap(),
+            ..CannedLogsContext::new()
+        };
+
+        let crashed = ProjectError {
+            kind: ProjectErrorKind::Internal,
+            message: "too many restarts in the last 15 minutes".to_string(),
+            ctx: Some(Box::new(stopped_with_container("matrix"))),
+        };
+        let crashed_at = Utc::now();
+        let retention = Duration::from_secs(60 * 60);
+
+        // the container is kept for its logs while the period lasts
+        let crashed = crashed
+            .reap(
+                &ctx,
+                crashed_at,
+                retention,
+                crashed_at + chrono::Duration::minutes(59),
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            crashed.ctx.as_ref().unwrap().container_id().unwrap(),
+            "matrix"
+        );
+        assert_eq!(removed.load(Ordering::SeqCst), 0);
+
+        // and removed once it is over
+        let crashed = crashed
+            .reap(
+                &ctx,
+                crashed_at,
+                retention,
+                crashed_at + chrono::Duration::minutes(61),
+            )
+            .await
+            .unwrap();
+        assert!(Project::Errored(crashed.clone()).container().is_none());
+        assert_eq!(removed.load(Ordering::SeqCst), 1);
+
+        // after which there is nothing left to remove
+        crashed
+            .reap(
+                &ctx,
+                crashed_at,
+                retention,
+                crashed_at + chrono::Duration::days(2),
+            )
+            .await
+            .unwrap();
+        assert_eq!(removed.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn docker_error_kinds() {
+        let server_error = |status_code, message: &str| DockerError::DockerResponseServerError {
+            status_code,
+            message: message.to_string(),
+        };
+
+        for (err, kind) in [
+            (
+                server_error(404, "No such container: shuttle_matrix_run"),
+                ErrorKind::ContainerNotFound,
+            ),
+            (
+                server_error(404, "No such image: public.ecr.aws/shuttle/deployer"),
+                ErrorKind::Internal,
+            ),
+            (
+                server_error(
+                    409,
+                    "Conflict. The container name \"/shuttle_matrix_run\" is already in use",
+                ),
+                ErrorKind::ContainerConflict,
+            ),
+            (
+                server_error(
+                    409,
+                    "removal of container shuttle_matrix_run is already in progress",
+                ),
+                ErrorKind::ContainerConflict,
+            ),
+            (
+                server_error(500, "driver failed programming external connectivity"),
+                ErrorKind::Internal,
+            ),
+            (
+                DockerError::IOError {
+                    err: std::io::Error::new(
+                        std::io::ErrorKind::ConnectionRefused,
+                        "connection refused",
+                    ),
+                },
+                ErrorKind::ServiceUnavailable,
+            ),
+            (
+                DockerError::RequestTimeoutError,
+                ErrorKind::ServiceUnavailable,
+            ),
+        ] {
+            let message = err.to_string();
+            assert_eq!(Error::from(err).kind(), kind, "{message}");
+        }
+    }
+
+    #[test]
+    fn startup_policy() {
+        let container = ContainerInspectResponse {
+            id: Some("matrix".to_string()),
+            ..Default::default()
+        };
+        let running = Project::Started(ProjectStarted::new(container.clone()));
+        let exited = Project::Stopped(ProjectStopped {
+            container: container.clone(),
+        });
+        assert!(running.is_running());
+        assert!(!exited.is_running());
+
+        // restoring starts again what went down with the gateway and
+        // leaves what is still up as it is
+        assert!(matches!(
+            exited.clone().on_startup(StartupPolicy::Restore),
+            Project::Starting(_)
+        ));
+        assert!(matches!(
+            running.clone().on_startup(StartupPolicy::Restore),
+            Project::Started(_)
+        ));
+
+        // stopping takes down what is still up and leaves the rest down
+        assert!(matches!(
+            running.on_startup(StartupPolicy::Stopped),
+            Project::Stopping(_)
+        ));
+        assert!(matches!(
+            exited.on_startup(StartupPolicy::Stopped),
+            Project::Stopped(_)
+        ));
+    }
+
+    #[tokio::test]
+    async fn refresh_reconciles_drift() -> anyhow::Result<()> {
+        let world = World::new().await;
+        let ctx = world.context();
+
+        let project = Project::Creating(ProjectCreating::new(
+            "drifting".parse().unwrap(),
+            "test".to_string(),
+        ))
+        .next(&ctx)
+        .await?;
+        let container = project.container().unwrap();
+
+        // stored as stopped while docker only created the container
+        let project = Project::Stopped(ProjectStopped {
+            container: container.clone(),
+        })
+        .refresh(&ctx)
+        .await?;
+        assert!(matches!(project, Project::Starting(_)));
+
+        ctx.docker()
+            .remove_container(
+                container.id.as_ref().unwrap(),
+                Some(RemoveContainerOptions {
+                    force: true,
+                    ..Default::default()
+                }),
+            )
+            .await?;
+
+        // the container went away, so the project is recreated
+        let project = project.refresh(&ctx).await?;
+        match project {
+            Project::Creating(creating) => {
+                assert_eq!(creating.project_name.to_string(), "drifting");
+                assert_eq!(creating.from.unwrap().id, container.id);
+            }
+            other => panic!("expected the project to be recreated, got {other:?}"),
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn create_container_with_env() -> anyhow::Result<()> {
+        let world = World::new().await;
+
+        let ctx = world.context();
+
+        let creating = ProjectCreating::new("matrix".parse().unwrap(), "test".to_string())
+            .with_env(vec![
+                ("RUST_LOG".to_string(), "info".to_string()),
+                ("SECRET".to_string(), "the one".to_string()),
+            ]);
+
+        let (_, config) = creating.generate_container_config(&ctx);
+        let env = config.env.unwrap();
+
+        assert!(env.contains(&"SECRET=the one".to_string()));
+        // user variables take precedence over the defaults
+        assert!(env.contains(&"RUST_LOG=info".to_string()));
+        assert!(!env.contains(&"RUST_LOG=debug".to_string()));
+
+        // env vars are never persisted with the state
+        let state = serde_json::to_string(&Project::Creating(creating))?;
+        assert!(!state.contains("the one"));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn create_container_with_deployment_timeout() -> anyhow::Result<()> {
+        let world = World::new().await;
+
+        let ctx = world.context();
+
+        let timeout_of = |creating: &ProjectCreating| {
+            let (_, config) = creating.generate_container_config(&ctx);
+            let cmd = config.cmd.unwrap();
+            let at = cmd.iter().position(|arg| arg == "--deployment-timeout");
+            (
+                at.map(|at| cmd[at + 1].clone()),
+                cmd.iter()
+                    .filter(|arg| *arg == "--deployment-timeout")
+                    .count(),
+            )
+        };
+
+        let creating = ProjectCreating::new("matrix".parse().unwrap(), "test".to_string());
+        assert_eq!(timeout_of(&creating), (None, 0));
+
+        let creating =
+            creating.with_deployment_timeout(Some(DeploymentTimeout { timeout_secs: 600 }));
+        assert_eq!(timeout_of(&creating), (Some("600".to_string()), 1));
+
+        // a container recreated from another gets the latest override,
+        // or none at all
+        let (_, config) = creating.generate_container_config(&ctx);
+        let from: ContainerInspectResponse = deserialize_json!({
+            "Id": "matrix_run",
+            "Config": config,
+        });
+        let recreated = ProjectCreating::new("matrix".parse().unwrap(), "test".to_string())
+            .from(from)
+            .with_deployment_timeout(Some(DeploymentTimeout { timeout_secs: 60 }));
+        assert_eq!(timeout_of(&recreated), (Some("60".to_string()), 1));
+        assert_eq!(
+            timeout_of(&recreated.with_deployment_timeout(None)),
+            (None, 0)
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn create_container_with_resource_limits() -> anyhow::Result<()> {
+        let world = World::new().await;
+
+        let ctx = world.context();
+
+        let limits_of = |creating: &ProjectCreating| {
+            let (_, config) = creating.generate_container_config(&ctx);
+            let host_config = config.host_config.unwrap();
+            (host_config.memory.unwrap(), host_config.cpu_quota.unwrap())
+        };
+
+        let creating = ProjectCreating::new("matrix".parse().unwrap(), "test".to_string());
+        assert_eq!(limits_of(&creating), (6 * 1024 * 1024 * 1024, 400_000));
+
+        let creating = creating.with_resource_limits(Some(ResourceLimits {
+            memory_mib: 512,
+            millicpus: 250,
+        }));
+        assert_eq!(limits_of(&creating), (512 * 1024 * 1024, 25_000));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn create_container_with_restart_policy() -> anyhow::Result<()> {
+        let world = World::new().await;
+        let creating = ProjectCreating::new("matrix".parse().unwrap(), "test".to_string());
+
+        for (restart, expected) in [
+            (
+                ContainerRestart::Never,
+                RestartPolicy {
+                    name: Some(RestartPolicyNameEnum::NO),
+                    maximum_retry_count: None,
+                },
+            ),
+            (
+                ContainerRestart::OnFailure,
+                RestartPolicy {
+                    name: Some(RestartPolicyNameEnum::ON_FAILURE),
+                    maximum_retry_count: Some(5),
+                },
+            ),
+            (
+                ContainerRestart::Always,
+                RestartPolicy {
+                    name: Some(RestartPolicyNameEnum::ALWAYS),
+                    maximum_retry_count: None,
+                },
+            ),
+        ] {
+            let mut args = world.args();
+            args.restart_policy = restart;
+            args.restart_max_retries = 5;
+
+            let mut ctx = world.context();
+            ctx.container_settings = ContainerSettings::builder(&ctx.docker)
+                .from_args(&args)
+                .await?;
+
+            let (_, config) = creating.generate_container_config(&ctx);
+            assert_eq!(config.host_config.unwrap().restart_policy, Some(expected));
+        }
+
+        Ok(())
+    }
 }
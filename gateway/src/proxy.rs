@@ -1,37 +1,583 @@
+use std::collections::HashMap;
 use std::convert::Infallible;
 use std::future::Future;
 use std::io;
-use std::net::SocketAddr;
+use std::net::{IpAddr, SocketAddr};
 use std::pin::Pin;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 use std::task::{Context, Poll};
+use std::time::Duration;
 
 use axum::headers::{Error as HeaderError, Header, HeaderMapExt, HeaderName, HeaderValue, Host};
 use axum::response::{IntoResponse, Response};
 use axum_server::accept::DefaultAcceptor;
 use axum_server::tls_rustls::RustlsAcceptor;
-use fqdn::{fqdn, FQDN};
+use fqdn::FQDN;
 use futures::future::{ready, Ready};
 use futures::prelude::*;
 use hyper::body::{Body, HttpBody};
 use hyper::client::connect::dns::GaiResolver;
+use hyper::client::connect::Connect;
 use hyper::client::HttpConnector;
+use hyper::header::{
+    AUTHORIZATION, CONTENT_LENGTH, CONTENT_TYPE, HOST, RETRY_AFTER, TRANSFER_ENCODING,
+    WWW_AUTHENTICATE,
+};
 use hyper::server::conn::AddrStream;
-use hyper::{Client, Request};
-use hyper_reverse_proxy::ReverseProxy;
-use once_cell::sync::Lazy;
+use hyper::{Client, Method, Request, StatusCode, Uri, Version};
+use hyper_reverse_proxy::{ProxyError, ReverseProxy};
 use opentelemetry::global;
 use opentelemetry_http::HeaderInjector;
+use shuttle_common::models::error::ApiError;
+use shuttle_common::models::project::{FeatureFlag, MaintenancePage, UpstreamProtocol};
+use tokio::sync::mpsc::Sender;
+use tokio::sync::OwnedSemaphorePermit;
+use tokio::time::timeout;
 use tower::{Service, ServiceBuilder};
-use tracing::{debug_span, error, field, trace};
+use tracing::{debug, debug_span, error, field, trace, warn, Span};
 use tracing_opentelemetry::OpenTelemetrySpanExt;
+use uuid::Uuid;
 
 use crate::acme::{AcmeClient, ChallengeResponderLayer, CustomDomain};
+use crate::args::{ProxyBodyMode, ProxyErrorFormat};
+use crate::counters::{CountedBody, Direction, ProjectTraffic};
+use crate::listener::{ListenerAcceptor, ListenerSettings};
+use crate::project::Project;
+use crate::rewrite::HeaderRewrites;
 use crate::service::GatewayService;
+use crate::task::{self, BoxedTask, ProjectContext, Task};
 use crate::{Error, ErrorKind, ProjectName};
 
-static PROXY_CLIENT: Lazy<ReverseProxy<HttpConnector<GaiResolver>>> =
-    Lazy::new(|| ReverseProxy::new(Client::new()));
+pub const DEFAULT_UPSTREAM_CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+pub const DEFAULT_UPSTREAM_TIMEOUT: Duration = Duration::from_secs(60);
+pub const DEFAULT_UPSTREAM_POOL_IDLE_TIMEOUT: Duration = Duration::from_secs(90);
+pub const DEFAULT_UPSTREAM_POOL_MAX_IDLE: usize = 32;
+pub const DEFAULT_UPSTREAM_RETRIES: u32 = 2;
+pub const DEFAULT_MAX_HEADER_SIZE: usize = 32 * 1024;
+pub const DEFAULT_MAX_HEADERS: usize = 100;
+
+/// How long a request over the connection limit of a project waits for
+/// another one to finish before being turned away
+const CONNECTION_SLOT_WAIT: Duration = Duration::from_millis(500);
+
+/// How long the user proxy waits before trying a request again, which
+/// doubles with every retry
+const UPSTREAM_RETRY_BACKOFF: Duration = Duration::from_millis(100);
+
+/// Largest body read whole in [`ProxyBodyMode::Buffered`], bigger ones
+/// are streamed anyway
+const MAX_BUFFERED_BODY_SIZE: usize = 8 * 1024 * 1024;
+
+/// When clients are told to try a project which is still starting again
+const PROVISIONING_RETRY_AFTER: Duration = Duration::from_secs(5);
+
+/// How often a request held for a project which is still starting
+/// checks whether it is ready
+const PROVISIONING_POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// How long a request to a ready project without a known address is
+/// held while the project is refreshed against docker. Also how often
+/// such a project is refreshed at most.
+const BACKEND_RESOLVE_HOLD: Duration = Duration::from_secs(2);
+
+/// When clients are told to try a project without a known address
+/// again
+const BACKEND_RESOLVE_RETRY_AFTER: Duration = Duration::from_secs(1);
+
+type ProxyClient<C = HttpConnector<GaiResolver>> = ReverseProxy<C>;
+
+fn make_connector(connect_timeout: Duration) -> HttpConnector<GaiResolver> {
+    let mut connector = HttpConnector::new();
+    connector.set_connect_timeout(Some(connect_timeout));
+    connector
+}
+
+/// Create a proxy client which gives up connecting to an upstream
+/// after `connect_timeout`
+pub fn make_proxy_client(connect_timeout: Duration) -> Arc<ProxyClient> {
+    Arc::new(ReverseProxy::new(
+        Client::builder().build(make_connector(connect_timeout)),
+    ))
+}
+
+/// Keep-alive connections from the user proxy to the projects
+///
+/// Every project gets a client with its own pool of connections to
+/// its backend. The pool is dropped as soon as the backend shows up
+/// at another address (e.g. after a restart) so that no request is
+/// sent down a connection to a container which is gone. It is also
+/// dropped when the project switches to another protocol.
+#[derive(Clone)]
+pub struct UpstreamPool<C = HttpConnector<GaiResolver>> {
+    connector: C,
+    idle_timeout: Duration,
+    max_idle_per_host: usize,
+    #[allow(clippy::type_complexity)]
+    clients: Arc<Mutex<HashMap<ProjectName, (IpAddr, UpstreamProtocol, Arc<ProxyClient<C>>)>>>,
+}
+
+impl UpstreamPool {
+    /// A pool which gives up connecting to an upstream after
+    /// `connect_timeout`
+    pub fn new(connect_timeout: Duration) -> Self {
+        Self::with_connector(make_connector(connect_timeout))
+    }
+}
+
+impl<C> UpstreamPool<C>
+where
+    C: Connect + Clone + Send + Sync + 'static,
+{
+    pub fn with_connector(connector: C) -> Self {
+        Self {
+            connector,
+            idle_timeout: DEFAULT_UPSTREAM_POOL_IDLE_TIMEOUT,
+            max_idle_per_host: DEFAULT_UPSTREAM_POOL_MAX_IDLE,
+            clients: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Set how long an unused connection is kept open and how many of
+    /// them are kept for a single project
+    pub fn with_idle(mut self, idle_timeout: Duration, max_idle_per_host: usize) -> Self {
+        self.idle_timeout = idle_timeout;
+        self.max_idle_per_host = max_idle_per_host;
+        self
+    }
+
+    /// The client to reach `project_name` with at `target_ip` over
+    /// `protocol`
+    pub fn client(
+        &self,
+        project_name: &ProjectName,
+        target_ip: IpAddr,
+        protocol: UpstreamProtocol,
+    ) -> Arc<ProxyClient<C>> {
+        let mut clients = self.clients.lock().unwrap();
+
+        match clients.get(project_name) {
+            Some((ip, current, client)) if *ip == target_ip && *current == protocol => {
+                return client.clone()
+            }
+            Some((ip, current, _)) => {
+                debug!(%project_name, old = %ip, new = %target_ip, %current, %protocol, "project backend changed, evicting its connections");
+            }
+            None => {}
+        }
+
+        let client = Arc::new(ReverseProxy::new(
+            Client::builder()
+                .pool_idle_timeout(self.idle_timeout)
+                .pool_max_idle_per_host(self.max_idle_per_host)
+                .http2_only(protocol == UpstreamProtocol::Http2)
+                .build(self.connector.clone()),
+        ));
+        clients.insert(project_name.clone(), (target_ip, protocol, client.clone()));
+
+        client
+    }
+
+    /// Drop the connections to `project_name`
+    pub fn evict(&self, project_name: &ProjectName) {
+        self.clients.lock().unwrap().remove(project_name);
+    }
+}
+
+/// Forward `req` to `target_url`, giving up if the upstream has not
+/// responded within `upstream_timeout`. The headers of the request
+/// and of the response are rewritten according to `rewrites`.
+///
+/// Requests which can safely be sent twice are tried up to `retries`
+/// more times when the upstream fails to take them, as long as their
+/// body is small enough to be held on to. The retries count towards
+/// `upstream_timeout`.
+///
+/// An upstream which cannot be connected to yields a
+/// [`ErrorKind::ProjectUnreachable`] whereas one which is too slow
+/// to respond yields a [`ErrorKind::ProjectTimedOut`].
+#[allow(clippy::too_many_arguments)]
+pub async fn forward<C>(
+    client: &ProxyClient<C>,
+    upstream_timeout: Duration,
+    retries: u32,
+    client_ip: IpAddr,
+    target_url: &str,
+    rewrites: Option<&HeaderRewrites>,
+    mut req: Request<Body>,
+) -> Result<hyper::Response<Body>, Error>
+where
+    C: Connect + Clone + Send + Sync + 'static,
+{
+    if let Some(rewrites) = rewrites {
+        rewrites.request.apply(req.headers_mut());
+    }
+
+    let retries = if is_retryable(&req) { retries } else { 0 };
+
+    let attempts = async {
+        if retries == 0 {
+            return client.call(client_ip, target_url, req).await;
+        }
+
+        let (parts, body) = req.into_parts();
+        let body = hyper::body::to_bytes(body)
+            .await
+            .map_err(ProxyError::HyperError)?;
+
+        let mut backoff = UPSTREAM_RETRY_BACKOFF;
+        let mut attempt = 0;
+        loop {
+            let mut req = Request::new(Body::from(body.clone()));
+            *req.method_mut() = parts.method.clone();
+            *req.uri_mut() = parts.uri.clone();
+            *req.version_mut() = parts.version;
+            *req.headers_mut() = parts.headers.clone();
+
+            match client.call(client_ip, target_url, req).await {
+                Err(err) if attempt < retries => {
+                    debug!(
+                        ?err,
+                        attempt, target_url, "upstream failed to take request, retrying"
+                    );
+                    tokio::time::sleep(backoff).await;
+                    backoff *= 2;
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    };
+
+    match timeout(upstream_timeout, attempts).await {
+        Ok(Ok(mut resp)) => {
+            if let Some(rewrites) = rewrites {
+                rewrites.response.apply(resp.headers_mut());
+            }
+            Ok(resp)
+        }
+        Ok(Err(ProxyError::HyperError(err))) if err.is_connect() => {
+            warn!(error = %err, target_url, "upstream is unreachable");
+            Err(Error::source(ErrorKind::ProjectUnreachable, err))
+        }
+        Ok(Err(err)) => Err(Error::custom(
+            ErrorKind::ProjectUnavailable,
+            format!("{err:?}"),
+        )),
+        Err(_) => {
+            warn!(
+                target_url,
+                "upstream did not respond within {}s",
+                upstream_timeout.as_secs()
+            );
+            Err(Error::from_kind(ErrorKind::ProjectTimedOut))
+        }
+    }
+}
+
+/// Whether `req` can be sent to an upstream again without it doing
+/// anything twice, and is small enough to keep around for it
+fn is_retryable(req: &Request<Body>) -> bool {
+    let idempotent = matches!(
+        *req.method(),
+        Method::GET | Method::HEAD | Method::PUT | Method::DELETE
+    );
+    let small = req
+        .body()
+        .size_hint()
+        .upper()
+        .map_or(false, |size| size <= MAX_BUFFERED_BODY_SIZE as u64);
+
+    idempotent && small
+}
+
+/// Hand the response of a project on according to `mode`. Streaming
+/// never waits on more of the body than the client asks for.
+///
+/// HTTP/2 responses are always streamed, as buffering would lose the
+/// trailers which may follow their body (e.g. the status of a gRPC
+/// call).
+pub async fn relay_body(
+    resp: hyper::Response<Body>,
+    mode: ProxyBodyMode,
+) -> Result<hyper::Response<Body>, Error> {
+    match mode {
+        ProxyBodyMode::Buffered if resp.version() != Version::HTTP_2 => {
+            buffer_body(resp, MAX_BUFFERED_BODY_SIZE).await
+        }
+        _ => Ok(resp),
+    }
+}
+
+/// Count the body of `req` towards the traffic of its project as it is
+/// sent on. Requests without one are left as they are, not to be sent
+/// on with a chunked body.
+fn count_request(req: Request<Body>, traffic: Arc<ProjectTraffic>) -> Request<Body> {
+    if req.body().is_end_stream() {
+        return req;
+    }
+
+    req.map(|body| Body::wrap_stream(CountedBody::new(body, traffic, Direction::In)))
+}
+
+/// Read the whole body of `resp` before handing it on, as long as it
+/// stays under `limit` bytes. Bodies over it carry on as a stream from
+/// where the reading stopped.
+async fn buffer_body(
+    resp: hyper::Response<Body>,
+    limit: usize,
+) -> Result<hyper::Response<Body>, Error> {
+    let (mut parts, mut body) = resp.into_parts();
+    let mut buffered = Vec::new();
+
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk.map_err(|err| Error::source(ErrorKind::ProjectUnavailable, err))?;
+        buffered.extend_from_slice(&chunk);
+
+        if buffered.len() > limit {
+            let head = stream::once(future::ok(hyper::body::Bytes::from(buffered)));
+            let body = Body::wrap_stream(head.chain(body));
+            return Ok(hyper::Response::from_parts(parts, body));
+        }
+    }
+
+    parts.headers.remove(TRANSFER_ENCODING);
+    parts
+        .headers
+        .insert(CONTENT_LENGTH, HeaderValue::from(buffered.len()));
+
+    Ok(hyper::Response::from_parts(parts, Body::from(buffered)))
+}
+
+/// The label naming a project in `fqdn` when it is a direct
+/// subdomain of one of the `public` base FQDNs
+fn project_label<'f>(fqdn: &'f FQDN, public: &[FQDN]) -> Option<&'f str> {
+    public
+        .iter()
+        .any(|public| fqdn.is_subdomain_of(public) && fqdn.depth() - public.depth() == 1)
+        .then(|| fqdn.labels().next())
+        .flatten()
+}
+
+/// A `301` sending the client to the same path on the `target`
+/// subdomain in place of the one it asked for
+fn moved_to(fqdn: &FQDN, target: &ProjectName, path: &Uri) -> Response {
+    let parent = fqdn.labels().skip(1).collect::<Vec<_>>().join(".");
+
+    let body = <Body as HttpBody>::map_err(Body::empty(), axum::Error::new).boxed_unsync();
+
+    hyper::Response::builder()
+        .status(301)
+        .header("Location", format!("https://{target}.{parent}{path}"))
+        .body(body)
+        .unwrap()
+}
+
+/// The host a request is for, from its `Host` header or from its URI,
+/// where HTTP/2 clients name it instead. A request which names no host,
+/// or one which is not a domain, cannot be routed anywhere.
+fn request_host(req: &Request<Body>) -> Result<(String, FQDN), Error> {
+    let hostname = match req.headers().typed_get::<Host>() {
+        Some(host) => host.hostname().to_string(),
+        None if req.headers().contains_key(HOST) => {
+            return Err(Error::from_kind(ErrorKind::BadHost))
+        }
+        None => req
+            .uri()
+            .host()
+            .ok_or_else(|| Error::from_kind(ErrorKind::BadHost))?
+            .to_string(),
+    };
+
+    let fqdn = hostname
+        .parse()
+        .map_err(|_| Error::from_kind(ErrorKind::BadHost))?;
+
+    Ok((hostname, fqdn))
+}
+
+/// A `401` asking the client for the credentials of `project_name`
+fn basic_auth_required(project_name: &ProjectName) -> Response {
+    let mut resp = Error::from_kind(ErrorKind::Unauthorized).into_response();
+    resp.headers_mut().insert(
+        WWW_AUTHENTICATE,
+        HeaderValue::from_str(&format!("Basic realm=\"{project_name}\"")).unwrap(),
+    );
+
+    resp
+}
+
+/// A `429` telling the client to come back after `retry_after`
+fn rate_limited(retry_after: Duration) -> Response {
+    let mut resp = Error::from_kind(ErrorKind::RateLimited).into_response();
+    set_retry_after(&mut resp, retry_after);
+
+    resp
+}
+
+fn set_retry_after(resp: &mut Response, retry_after: Duration) {
+    // Retry-After only has a precision of seconds
+    let secs = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+    resp.headers_mut()
+        .insert(RETRY_AFTER, HeaderValue::from(secs.max(1)));
+}
+
+/// Pages the user proxy answers with in place of its own errors, by
+/// class of status. `{{status}}`, `{{reason}}` and `{{message}}` are
+/// filled in wherever they appear in a page.
+#[derive(Clone, Debug, Default)]
+pub struct ErrorPages {
+    /// The page of `4xx` errors
+    pub client: Option<String>,
+    /// The page of `5xx` errors
+    pub server: Option<String>,
+}
+
+impl ErrorPages {
+    fn template(&self, status: StatusCode) -> Option<&str> {
+        if status.is_client_error() {
+            self.client.as_deref()
+        } else if status.is_server_error() {
+            self.server.as_deref()
+        } else {
+            None
+        }
+    }
+
+    fn render(template: &str, status: StatusCode, message: &str) -> Response {
+        let page = template
+            .replace("{{status}}", &status.as_u16().to_string())
+            .replace(
+                "{{reason}}",
+                &escape_html(status.canonical_reason().unwrap_or_default()),
+            )
+            .replace("{{message}}", &escape_html(message));
+
+        let mut resp = (status, page).into_response();
+        resp.headers_mut().insert(
+            CONTENT_TYPE,
+            HeaderValue::from_static("text/html; charset=utf-8"),
+        );
+        resp
+    }
+}
+
+/// The response to a request which did not make it to a project. Hosts
+/// no project is served at get a `404` of their own, so that visitors
+/// are not told to create a project with `cargo shuttle`. Responses of
+/// projects themselves never come through here.
+fn error_page(err: Error, host: &str, format: ProxyErrorFormat, pages: &ErrorPages) -> Response {
+    let kind = err.kind();
+    let status = ApiError::from(kind).status();
+    let message = match kind {
+        ErrorKind::ProjectNotFound => Some(format!("there is no project at {host}")),
+        ErrorKind::ProjectNotReady => {
+            Some(format!("the project at {host} is not running right now"))
+        }
+        ErrorKind::ProjectStarting => Some(format!(
+            "the project at {host} is starting up, try again in a few seconds"
+        )),
+        _ => None,
+    };
+
+    if let Some(template) = pages.template(status) {
+        let message = message.unwrap_or_else(|| ApiError::from(kind).message);
+        let mut resp = ErrorPages::render(template, status, &message);
+        if let Some(retry_after) = err.retry_after() {
+            set_retry_after(&mut resp, retry_after);
+        }
+        return resp;
+    }
+
+    let message = match message {
+        Some(message) => message,
+        None => return err.into_response(),
+    };
+
+    match format {
+        ProxyErrorFormat::Json if kind == ErrorKind::ProjectNotFound => (
+            status,
+            axum::Json(ApiError {
+                message,
+                status_code: status.as_u16(),
+                code: Some(kind.code().to_string()),
+                suggestions: Vec::new(),
+            }),
+        )
+            .into_response(),
+        ProxyErrorFormat::Json => err.into_response(),
+        ProxyErrorFormat::Html => {
+            let title = status.canonical_reason().unwrap_or_default();
+            let page = format!(
+                r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+<meta charset="utf-8">
+<title>{code} {title}</title>
+<style>body{{font-family:sans-serif;text-align:center;margin-top:15vh;color:#333}}h1{{font-size:4em;margin:0;color:#ff8a3f}}</style>
+</head>
+<body>
+<h1>{code}</h1>
+<p>{message}</p>
+<p><small>served by <a href="https://www.shuttle.rs">shuttle</a></small></p>
+</body>
+</html>
+"#,
+                code = status.as_u16(),
+                message = escape_html(&message),
+            );
+
+            let mut resp = (status, page).into_response();
+            resp.headers_mut().insert(
+                CONTENT_TYPE,
+                HeaderValue::from_static("text/html; charset=utf-8"),
+            );
+            if let Some(retry_after) = err.retry_after() {
+                set_retry_after(&mut resp, retry_after);
+            }
+            resp
+        }
+    }
+}
+
+/// Whether an error means the project could not be reached, as it
+/// cannot while switching over to a new deploy
+fn is_unavailable(kind: ErrorKind) -> bool {
+    matches!(
+        kind,
+        ErrorKind::ProjectNotReady
+            | ErrorKind::ProjectStarting
+            | ErrorKind::ProjectUnreachable
+            | ErrorKind::ProjectUnavailable
+            | ErrorKind::ProjectTimedOut
+    )
+}
+
+/// A `503` with the maintenance page of a project
+fn maintenance_page(page: &MaintenancePage) -> Response {
+    let mut resp = (StatusCode::SERVICE_UNAVAILABLE, page.body.clone()).into_response();
+    resp.headers_mut().insert(
+        CONTENT_TYPE,
+        HeaderValue::from_static("text/html; charset=utf-8"),
+    );
+    set_retry_after(&mut resp, Duration::from_secs(page.retry_after_secs.into()));
+
+    resp
+}
+
+fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '&' => escaped.push_str("&amp;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
 
 pub trait AsResponderTo<R> {
     fn as_responder_to(&self, req: R) -> Self;
@@ -67,8 +613,12 @@ where
 
 lazy_static::lazy_static! {
     pub static ref X_SHUTTLE_PROJECT: HeaderName = HeaderName::from_static("x-shuttle-project");
+    pub static ref X_REQUEST_ID: HeaderName = HeaderName::from_static("x-request-id");
 }
 
+/// Longest request id taken from a client, longer ones are replaced
+const MAX_REQUEST_ID_LEN: usize = 200;
+
 pub struct XShuttleProject(ProjectName);
 
 impl Header for XShuttleProject {
@@ -96,11 +646,134 @@ impl Header for XShuttleProject {
     }
 }
 
+/// Identifies a request across the proxy, the project and the logs
+/// of both
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct XRequestId(String);
+
+impl XRequestId {
+    pub fn new() -> Self {
+        Self(Uuid::new_v4().to_string())
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Default for XRequestId {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl std::fmt::Display for XRequestId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl Header for XRequestId {
+    fn name() -> &'static HeaderName {
+        &X_REQUEST_ID
+    }
+
+    fn encode<E: Extend<HeaderValue>>(&self, values: &mut E) {
+        values.extend(std::iter::once(HeaderValue::from_str(&self.0).unwrap()));
+    }
+
+    fn decode<'i, I>(values: &mut I) -> Result<Self, HeaderError>
+    where
+        Self: Sized,
+        I: Iterator<Item = &'i HeaderValue>,
+    {
+        values
+            .last()
+            .and_then(|value| value.to_str().ok())
+            .filter(|value| !value.is_empty() && value.len() <= MAX_REQUEST_ID_LEN)
+            .map(|value| Self(value.to_string()))
+            .ok_or_else(HeaderError::invalid)
+    }
+}
+
 #[derive(Clone)]
 pub struct UserProxy {
     gateway: Arc<GatewayService>,
+    pool: UpstreamPool,
+    upstream_timeout: Duration,
+    upstream_retries: u32,
+    provisioning_hold: Duration,
+    cold_start_hold: Duration,
+    header_limits: HeaderLimits,
+    backend_resolver: BackendResolver,
+    body_mode: ProxyBodyMode,
+    error_format: ProxyErrorFormat,
+    error_pages: Arc<ErrorPages>,
     remote_addr: SocketAddr,
-    public: FQDN,
+    public: Vec<FQDN>,
+}
+
+/// Gets projects which are ready but whose address the gateway lost,
+/// say after docker was restarted under it, refreshed so that their
+/// address is looked up again. Also gets stopped projects which are
+/// sent a request started again.
+#[derive(Clone, Default)]
+pub struct BackendResolver {
+    sender: Option<Sender<BoxedTask>>,
+    /// When each project was last queued a task
+    queued: Arc<Mutex<HashMap<ProjectName, std::time::Instant>>>,
+}
+
+impl BackendResolver {
+    pub fn new(sender: Sender<BoxedTask>) -> Self {
+        Self {
+            sender: Some(sender),
+            queued: Default::default(),
+        }
+    }
+
+    /// Queue a refresh of the project, unless a task was queued for
+    /// it not long ago
+    async fn refresh(&self, gateway: &Arc<GatewayService>, project_name: &ProjectName) {
+        self.queue(gateway, project_name, task::refresh()).await
+    }
+
+    /// Queue a start of the project, unless a task was queued for it
+    /// not long ago
+    async fn wake(&self, gateway: &Arc<GatewayService>, project_name: &ProjectName) {
+        self.queue(gateway, project_name, task::run_until_done())
+            .await
+    }
+
+    async fn queue<T>(&self, gateway: &Arc<GatewayService>, project_name: &ProjectName, task: T)
+    where
+        T: Task<ProjectContext, Output = Project, Error = Error> + 'static,
+    {
+        let sender = match &self.sender {
+            Some(sender) => sender,
+            None => return,
+        };
+
+        {
+            let mut queued = self.queued.lock().unwrap();
+            let now = std::time::Instant::now();
+            queued.retain(|_, at| now.duration_since(*at) < BACKEND_RESOLVE_HOLD);
+            if queued.contains_key(project_name) {
+                return;
+            }
+            queued.insert(project_name.clone(), now);
+        }
+
+        if let Err(err) = gateway
+            .new_task()
+            .project(project_name.clone())
+            .and_then(task)
+            .send(sender)
+            .await
+        {
+            warn!(error = %err, project = %project_name, "failed to queue a task for the project");
+        }
+    }
 }
 
 impl<'r> AsResponderTo<&'r AddrStream> for UserProxy {
@@ -111,44 +784,263 @@ impl<'r> AsResponderTo<&'r AddrStream> for UserProxy {
     }
 }
 
+/// How large the headers of a request to the user proxy can be. Larger
+/// ones are turned away with a `431` rather than kept around and
+/// forwarded.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct HeaderLimits {
+    /// Bytes of all the names and values together
+    pub max_size: usize,
+    pub max_count: usize,
+}
+
+impl Default for HeaderLimits {
+    fn default() -> Self {
+        Self {
+            max_size: DEFAULT_MAX_HEADER_SIZE,
+            max_count: DEFAULT_MAX_HEADERS,
+        }
+    }
+}
+
+impl HeaderLimits {
+    fn check(&self, headers: &HeaderMap) -> Result<(), Error> {
+        if headers.len() > self.max_count {
+            return Err(Error::from_kind(ErrorKind::HeadersTooLarge)
+                .with_detail(format!("{} headers", headers.len())));
+        }
+
+        let size: usize = headers
+            .iter()
+            .map(|(name, value)| name.as_str().len() + value.len())
+            .sum();
+        if size > self.max_size {
+            return Err(Error::from_kind(ErrorKind::HeadersTooLarge)
+                .with_detail(format!("{size} bytes of headers")));
+        }
+
+        Ok(())
+    }
+}
+
 impl UserProxy {
+    /// Hold a request to a project which is ready but whose address is
+    /// not known while it is refreshed, in case its address turns up.
+    /// Clients are told when to try again otherwise.
+    async fn resolve_backend(&self, project_name: &ProjectName) -> Result<IpAddr, Error> {
+        debug!(project = %project_name, "project is ready without an address, refreshing it");
+        self.backend_resolver
+            .refresh(&self.gateway, project_name)
+            .await;
+
+        let deadline = tokio::time::Instant::now() + BACKEND_RESOLVE_HOLD;
+        while tokio::time::Instant::now() + PROVISIONING_POLL_INTERVAL <= deadline {
+            tokio::time::sleep(PROVISIONING_POLL_INTERVAL).await;
+
+            let project = self.gateway.find_project(project_name).await?;
+            if let Some(target_ip) = project.target_ip()? {
+                return Ok(target_ip);
+            }
+            if !project.is_ready() && !project.is_provisioning() {
+                break;
+            }
+        }
+
+        Err(Error::from_kind(ErrorKind::ProjectNotReady)
+            .with_retry_after(BACKEND_RESOLVE_RETRY_AFTER))
+    }
+
+    /// Hold a request to a project which is still starting for up to
+    /// `provisioning_hold`, in case it becomes ready. Clients are told
+    /// when to try again otherwise, rather than the request failing to
+    /// connect.
+    async fn wait_until_ready(&self, project_name: &ProjectName) -> Result<IpAddr, Error> {
+        let deadline = tokio::time::Instant::now() + self.provisioning_hold;
+        while tokio::time::Instant::now() + PROVISIONING_POLL_INTERVAL <= deadline {
+            tokio::time::sleep(PROVISIONING_POLL_INTERVAL).await;
+
+            let project = self.gateway.find_project(project_name).await?;
+            if let Some(target_ip) = project.target_ip()? {
+                return Ok(target_ip);
+            }
+            if !project.is_provisioning() {
+                return Err(Error::from_kind(ErrorKind::ProjectNotReady));
+            }
+        }
+
+        Err(Error::from_kind(ErrorKind::ProjectStarting).with_retry_after(PROVISIONING_RETRY_AFTER))
+    }
+
+    /// Start a stopped project again and hold the request which woke
+    /// it for up to `cold_start_hold`, in case it becomes ready. Clients
+    /// are told when to try again otherwise.
+    async fn cold_start(&self, project_name: &ProjectName) -> Result<IpAddr, Error> {
+        // A gateway on its way out does not start anything
+        if self.gateway.is_draining() {
+            return Err(Error::from_kind(ErrorKind::ProjectNotReady));
+        }
+
+        debug!(project = %project_name, "project is stopped, starting it");
+        self.backend_resolver
+            .wake(&self.gateway, project_name)
+            .await;
+
+        let deadline = tokio::time::Instant::now() + self.cold_start_hold;
+        while tokio::time::Instant::now() + PROVISIONING_POLL_INTERVAL <= deadline {
+            tokio::time::sleep(PROVISIONING_POLL_INTERVAL).await;
+
+            let project = self.gateway.find_project(project_name).await?;
+            if let Some(target_ip) = project.target_ip()? {
+                return Ok(target_ip);
+            }
+            if !project.is_stopped() && !project.is_provisioning() {
+                return Err(Error::from_kind(ErrorKind::ProjectNotReady));
+            }
+        }
+
+        Err(Error::from_kind(ErrorKind::ProjectStarting).with_retry_after(PROVISIONING_RETRY_AFTER))
+    }
+
     async fn proxy(self, mut req: Request<Body>) -> Result<Response, Error> {
-        let span = debug_span!("proxy", http.method = %req.method(), http.host = ?req.headers().get("Host"), http.uri = %req.uri(), http.status_code = field::Empty, project = field::Empty);
+        let request_id = req.headers().typed_get::<XRequestId>().unwrap_or_default();
+        let span = debug_span!("proxy", http.method = %req.method(), http.host = ?req.headers().get("Host"), http.uri = %req.uri(), http.request_id = %request_id, http.status_code = field::Empty, project = field::Empty);
         trace!(?req, "serving proxy request");
 
-        let fqdn = req
-            .headers()
-            .typed_get::<Host>()
-            .map(|host| fqdn!(host.hostname()))
-            .ok_or_else(|| Error::from_kind(ErrorKind::ProjectNotFound))?;
-
-        let project_name =
-            if fqdn.is_subdomain_of(&self.public) && fqdn.depth() - self.public.depth() == 1 {
-                fqdn.labels()
-                    .next()
-                    .unwrap()
-                    .to_owned()
-                    .parse()
-                    .map_err(|_| Error::from_kind(ErrorKind::ProjectNotFound))?
-            } else if let Ok(CustomDomain { project_name, .. }) =
-                self.gateway.project_details_for_custom_domain(&fqdn).await
-            {
-                project_name
-            } else {
-                return Err(Error::from_kind(ErrorKind::ProjectNotFound));
-            };
+        self.header_limits.check(req.headers())?;
+
+        let (_, fqdn) = request_host(&req)?;
+
+        let project_name = if let Some(label) = project_label(&fqdn, &self.public) {
+            let name = label
+                .parse()
+                .map_err(|_| Error::from_kind(ErrorKind::ProjectNotFound))?;
+            self.gateway.resolve_project_alias(name).await?
+        } else if let Ok(CustomDomain { project_name, .. }) =
+            self.gateway.project_details_for_custom_domain(&fqdn).await
+        {
+            project_name
+        } else {
+            return Err(Error::from_kind(ErrorKind::ProjectNotFound));
+        };
+
+        // The user proxy is the edge, so the peer is the client as far
+        // as filters go. Any X-Forwarded-For it sent is not trusted.
+        if let Some(rules) = self.gateway.ip_filters().rules(&project_name) {
+            if !rules.allows(self.remote_addr.ip()) {
+                return Err(Error::from_kind(ErrorKind::IpBlocked));
+            }
+        }
+
+        // Turn away requests over the project's limit before doing
+        // any more work for them
+        if let Err(retry_after) = self.gateway.rate_limiter().check(&project_name) {
+            return Ok(rate_limited(retry_after));
+        }
+
+        // Gated projects only let through requests with the right
+        // credentials, which are not passed on to them
+        if let Some(auth) = self.gateway.basic_auth_gate().auth(&project_name) {
+            match auth.check(req.headers(), self.remote_addr.ip()).await {
+                Ok(true) => {}
+                Ok(false) => return Ok(basic_auth_required(&project_name)),
+                Err(retry_after) => return Ok(rate_limited(retry_after)),
+            }
+            req.headers_mut().remove(AUTHORIZATION);
+        }
+
+        // The slot is held until the response is over, not just until
+        // its head is back
+        let permit = match self
+            .gateway
+            .connection_limiter()
+            .acquire(&project_name, CONNECTION_SLOT_WAIT)
+            .await
+        {
+            Ok(permit) => permit,
+            Err(()) => {
+                return Err(Error::from_kind(ErrorKind::ProjectOverloaded)
+                    .with_retry_after(Duration::from_secs(1)))
+            }
+        };
 
         req.headers_mut()
             .typed_insert(XShuttleProject(project_name.clone()));
 
-        let project = self.gateway.find_project(&project_name).await?;
+        let project = match self.gateway.find_project(&project_name).await {
+            // Renamed projects keep answering to their old name for a
+            // while, unless someone else took it since
+            Err(err) if err.kind() == ErrorKind::ProjectNotFound => {
+                return match self.gateway.find_project_redirect(&project_name).await? {
+                    Some(target) => Ok(moved_to(&fqdn, &target, req.uri())),
+                    None => Err(err),
+                };
+            }
+            project => project?,
+        };
+
+        // Traffic counts as activity even if the project cannot serve it
+        self.gateway.activity_tracker().touch(&project_name);
 
         // Record current project for tracing purposes
         span.record("project", &project_name.to_string());
 
-        let target_ip = project
-            .target_ip()?
-            .ok_or_else(|| Error::from_kind(ErrorKind::ProjectNotReady))?;
+        if project.is_frozen() {
+            return Err(Error::from_kind(ErrorKind::ProjectFrozen));
+        }
+
+        // Part of the traffic may go to a canary of the project, as long
+        // as it is there to take it
+        let (backend, project) = match self
+            .gateway
+            .canaries()
+            .pick(&project_name, self.remote_addr.ip())
+        {
+            Some(canary) => match self.gateway.find_project(&canary).await {
+                Ok(found) if found.target_ip()?.is_some() => (canary, found),
+                _ => {
+                    debug!(project = %project_name, %canary, "canary is not ready, using the project");
+                    (project_name.clone(), project)
+                }
+            },
+            None => (project_name.clone(), project),
+        };
+
+        // A project switching over to a new deploy shows the page it left
+        // for the occasion rather than an error
+        match self
+            .forward_to(req, &span, &project_name, &backend, project, permit)
+            .await
+        {
+            Err(err) if is_unavailable(err.kind()) => {
+                match self.gateway.project_maintenance_page(&project_name) {
+                    Some(page) if self.gateway.is_deploying(&project_name) => {
+                        debug!(project = %project_name, error = %err, "serving the maintenance page");
+                        Ok(maintenance_page(&page))
+                    }
+                    _ => Err(err),
+                }
+            }
+            res => res,
+        }
+    }
+
+    /// Send `req` on to `backend`, the project itself or its canary
+    async fn forward_to(
+        &self,
+        mut req: Request<Body>,
+        span: &Span,
+        project_name: &ProjectName,
+        backend: &ProjectName,
+        project: Project,
+        permit: Option<OwnedSemaphorePermit>,
+    ) -> Result<Response, Error> {
+        let target_ip = match project.target_ip()? {
+            Some(target_ip) => target_ip,
+            None if project.is_provisioning() => self.wait_until_ready(backend).await?,
+            None if project.is_ready() => self.resolve_backend(backend).await?,
+            None if project.is_stopped() => self.cold_start(backend).await?,
+            None => return Err(Error::from_kind(ErrorKind::ProjectNotReady)),
+        };
 
         let target_url = format!("http://{}:{}", target_ip, 8000);
 
@@ -158,13 +1050,64 @@ impl UserProxy {
             propagator.inject_context(&cx, &mut HeaderInjector(req.headers_mut()))
         });
 
-        let proxy = PROXY_CLIENT
-            .call(self.remote_addr.ip(), &target_url, req)
-            .await
-            .map_err(|_| Error::from_kind(ErrorKind::ProjectUnavailable))?;
+        // Whatever the client speaks, the project is spoken to in its
+        // own protocol
+        let protocol = self.gateway.project_upstream_protocol(backend);
+        *req.version_mut() = match protocol {
+            UpstreamProtocol::Http1 => Version::HTTP_11,
+            UpstreamProtocol::Http2 => Version::HTTP_2,
+        };
+
+        // A backend on its way out only finishes what it already has,
+        // and new requests wait for its replacement to be ready
+        let in_flight = self
+            .gateway
+            .drainer()
+            .track(backend, target_ip)
+            .ok_or_else(|| {
+                Error::from_kind(ErrorKind::ProjectNotReady)
+                    .with_retry_after(Duration::from_secs(1))
+            })?;
+
+        let traffic = self.gateway.traffic().project(project_name);
+        let req = count_request(req, traffic.clone());
+
+        let client = self.pool.client(backend, target_ip, protocol);
+        let rewrites = self.gateway.header_rewriter().rewrites(project_name);
+        let proxy = self
+            .gateway
+            .response_cache()
+            .serve(project_name, req, |req| {
+                forward(
+                    &client,
+                    self.upstream_timeout,
+                    self.upstream_retries,
+                    self.remote_addr.ip(),
+                    &target_url,
+                    rewrites.as_deref(),
+                    req,
+                )
+            })
+            .await?;
+        let body_mode = if self
+            .gateway
+            .feature_flags()
+            .is_enabled(project_name, FeatureFlag::BufferedBodies)
+        {
+            ProxyBodyMode::Buffered
+        } else {
+            self.body_mode
+        };
+        let proxy = relay_body(proxy, body_mode).await?;
 
         let (parts, body) = proxy.into_parts();
-        let body = <Body as HttpBody>::map_err(body, axum::Error::new).boxed_unsync();
+        let body = CountedBody::new(body, traffic, Direction::Out);
+        let body = HttpBody::map_err(body, move |err| {
+            let _slot = &permit;
+            let _in_flight = &in_flight;
+            axum::Error::new(err)
+        })
+        .boxed_unsync();
 
         span.record("http.status_code", parts.status.as_u16());
 
@@ -182,10 +1125,32 @@ impl Service<Request<Body>> for UserProxy {
         Poll::Ready(Ok(()))
     }
 
-    fn call(&mut self, req: Request<Body>) -> Self::Future {
-        self.clone()
+    fn call(&mut self, mut req: Request<Body>) -> Self::Future {
+        self.gateway.counters().record_proxy_request();
+
+        // Reuse the id of the client if it sent one, so that the id it
+        // reports can be found in our logs
+        let request_id = req.headers().typed_get::<XRequestId>().unwrap_or_default();
+        req.headers_mut().typed_insert(request_id.clone());
+
+        let host = req
+            .headers()
+            .typed_get::<Host>()
+            .map(|host| host.hostname().to_string())
+            .or_else(|| req.uri().host().map(str::to_string))
+            .unwrap_or_default();
+        let error_format = self.error_format;
+        let error_pages = Arc::clone(&self.error_pages);
+
+        self.clone()
             .proxy(req)
-            .or_else(|err: Error| future::ready(Ok(err.into_response())))
+            .or_else(move |err: Error| {
+                future::ready(Ok(error_page(err, &host, error_format, &error_pages)))
+            })
+            .map_ok(move |mut resp| {
+                resp.headers_mut().typed_insert(request_id);
+                resp
+            })
             .boxed()
     }
 }
@@ -193,7 +1158,7 @@ impl Service<Request<Body>> for UserProxy {
 #[derive(Clone)]
 pub struct Bouncer {
     gateway: Arc<GatewayService>,
-    public: FQDN,
+    public: Vec<FQDN>,
 }
 
 impl<'r> AsResponderTo<&'r AddrStream> for Bouncer {
@@ -206,13 +1171,17 @@ impl Bouncer {
     async fn bounce(self, req: Request<Body>) -> Result<Response, Error> {
         let mut resp = Response::builder();
 
-        let host = req.headers().typed_get::<Host>().unwrap();
-        let hostname = host.hostname();
-        let fqdn = fqdn!(hostname);
+        let (hostname, fqdn) = match request_host(&req) {
+            Ok(host) => host,
+            Err(err) => return Ok(err.into_response()),
+        };
 
         let path = req.uri();
 
-        if fqdn.is_subdomain_of(&self.public)
+        if self
+            .public
+            .iter()
+            .any(|public| fqdn.is_subdomain_of(public))
             || self
                 .gateway
                 .project_details_for_custom_domain(&fqdn)
@@ -253,7 +1222,19 @@ pub struct UserServiceBuilder {
     tls_acceptor: Option<RustlsAcceptor<DefaultAcceptor>>,
     bouncer_binds_to: Option<SocketAddr>,
     user_binds_to: Option<SocketAddr>,
-    public: Option<FQDN>,
+    public: Vec<FQDN>,
+    upstream_connect_timeout: Option<Duration>,
+    upstream_timeout: Option<Duration>,
+    upstream_retries: u32,
+    provisioning_hold: Duration,
+    cold_start_hold: Duration,
+    header_limits: HeaderLimits,
+    backend_resolver: BackendResolver,
+    listener: ListenerSettings,
+    upstream_pool_idle: Option<(Duration, usize)>,
+    body_mode: ProxyBodyMode,
+    error_format: ProxyErrorFormat,
+    error_pages: ErrorPages,
 }
 
 impl Default for UserServiceBuilder {
@@ -266,16 +1247,30 @@ impl UserServiceBuilder {
     pub fn new() -> Self {
         Self {
             service: None,
-            public: None,
+            public: Vec::new(),
             acme: None,
             tls_acceptor: None,
             bouncer_binds_to: None,
             user_binds_to: None,
+            upstream_connect_timeout: None,
+            upstream_timeout: None,
+            upstream_retries: DEFAULT_UPSTREAM_RETRIES,
+            provisioning_hold: Duration::ZERO,
+            cold_start_hold: Duration::ZERO,
+            header_limits: HeaderLimits::default(),
+            backend_resolver: BackendResolver::default(),
+            listener: ListenerSettings::default(),
+            upstream_pool_idle: None,
+            body_mode: ProxyBodyMode::Streaming,
+            error_format: ProxyErrorFormat::Json,
+            error_pages: ErrorPages::default(),
         }
     }
 
+    /// Serve projects as subdomains of `public`. Can be called once
+    /// for every base FQDN projects should be reachable under
     pub fn with_public(mut self, public: FQDN) -> Self {
-        self.public = Some(public);
+        self.public.push(public);
         self
     }
 
@@ -304,15 +1299,111 @@ impl UserServiceBuilder {
         self
     }
 
+    /// Set how long the user proxy waits on a project: `connect` to
+    /// establish a connection and `response` to get a response back
+    pub fn with_upstream_timeout(mut self, connect: Duration, response: Duration) -> Self {
+        self.upstream_connect_timeout = Some(connect);
+        self.upstream_timeout = Some(response);
+        self
+    }
+
+    /// Set how many more times the user proxy tries to send an
+    /// idempotent request to a project which failed to take it
+    pub fn with_upstream_retries(mut self, retries: u32) -> Self {
+        self.upstream_retries = retries;
+        self
+    }
+
+    /// Set how long the user proxy holds a request to a project which
+    /// is still starting, in case it becomes ready
+    pub fn with_provisioning_hold(mut self, hold: Duration) -> Self {
+        self.provisioning_hold = hold;
+        self
+    }
+
+    /// Set how long the user proxy holds a request to a stopped
+    /// project it woke up, in case it becomes ready
+    pub fn with_cold_start_hold(mut self, hold: Duration) -> Self {
+        self.cold_start_hold = hold;
+        self
+    }
+
+    /// Set how the connections of clients are kept alive and timed out
+    pub fn with_listener(mut self, listener: ListenerSettings) -> Self {
+        self.listener = listener;
+        self
+    }
+
+    /// Set how large the headers of requests to the user proxy can be
+    pub fn with_header_limits(mut self, limits: HeaderLimits) -> Self {
+        self.header_limits = limits;
+        self
+    }
+
+    /// Let the user proxy queue refreshes of ready projects whose
+    /// address is not known
+    pub fn with_sender(mut self, sender: Sender<BoxedTask>) -> Self {
+        self.backend_resolver = BackendResolver::new(sender);
+        self
+    }
+
+    /// Set how long the user proxy keeps unused connections to a
+    /// project open and how many of them it keeps per project
+    pub fn with_upstream_pool(mut self, idle_timeout: Duration, max_idle_per_host: usize) -> Self {
+        self.upstream_pool_idle = Some((idle_timeout, max_idle_per_host));
+        self
+    }
+
+    /// Set how the user proxy passes the bodies of responses on
+    pub fn with_body_mode(mut self, mode: ProxyBodyMode) -> Self {
+        self.body_mode = mode;
+        self
+    }
+
+    /// Set how the user proxy answers requests for unknown hosts and
+    /// for projects which are not running
+    pub fn with_error_format(mut self, format: ProxyErrorFormat) -> Self {
+        self.error_format = format;
+        self
+    }
+
+    /// Answer errors of the user proxy with `pages` where one is set
+    /// for their class of status
+    pub fn with_error_pages(mut self, pages: ErrorPages) -> Self {
+        self.error_pages = pages;
+        self
+    }
+
     pub fn serve(self) -> impl Future<Output = Result<(), io::Error>> {
         let service = self.service.expect("a GatewayService is required");
-        let public = self.public.expect("a public FQDN is required");
+        assert!(!self.public.is_empty(), "a public FQDN is required");
+        let public = self.public;
         let user_binds_to = self
             .user_binds_to
             .expect("a socket address to bind to is required");
 
+        let (idle_timeout, max_idle_per_host) = self.upstream_pool_idle.unwrap_or((
+            DEFAULT_UPSTREAM_POOL_IDLE_TIMEOUT,
+            DEFAULT_UPSTREAM_POOL_MAX_IDLE,
+        ));
+        let pool = UpstreamPool::new(
+            self.upstream_connect_timeout
+                .unwrap_or(DEFAULT_UPSTREAM_CONNECT_TIMEOUT),
+        )
+        .with_idle(idle_timeout, max_idle_per_host);
+
         let user_proxy = UserProxy {
             gateway: service.clone(),
+            pool,
+            upstream_timeout: self.upstream_timeout.unwrap_or(DEFAULT_UPSTREAM_TIMEOUT),
+            upstream_retries: self.upstream_retries,
+            provisioning_hold: self.provisioning_hold,
+            cold_start_hold: self.cold_start_hold,
+            header_limits: self.header_limits,
+            backend_resolver: self.backend_resolver,
+            body_mode: self.body_mode,
+            error_format: self.error_format,
+            error_pages: Arc::new(self.error_pages),
             remote_addr: "127.0.0.1:80".parse().unwrap(),
             public: public.clone(),
         };
@@ -337,6 +1428,8 @@ impl UserServiceBuilder {
                 .service(bouncer);
 
             let bouncer = axum_server::Server::bind(bouncer_binds_to)
+                .addr_incoming_config(self.listener.incoming_config())
+                .acceptor(ListenerAcceptor::new(DefaultAcceptor::new(), self.listener))
                 .serve(bouncer.into_make_service())
                 .map(|handle| ("bouncer (with challenge responder)", handle))
                 .boxed();
@@ -344,7 +1437,8 @@ impl UserServiceBuilder {
             futs.push(bouncer);
 
             let user_with_tls = axum_server::Server::bind(user_binds_to)
-                .acceptor(tls_acceptor)
+                .addr_incoming_config(self.listener.incoming_config())
+                .acceptor(ListenerAcceptor::new(tls_acceptor, self.listener))
                 .serve(user_proxy.into_make_service())
                 .map(|handle| ("user proxy (with TLS)", handle))
                 .boxed();
@@ -354,6 +1448,8 @@ impl UserServiceBuilder {
                 // bouncer is enabled
                 let bouncer_binds_to = self.bouncer_binds_to.unwrap();
                 let bouncer = axum_server::Server::bind(bouncer_binds_to)
+                    .addr_incoming_config(self.listener.incoming_config())
+                    .acceptor(ListenerAcceptor::new(DefaultAcceptor::new(), self.listener))
                     .serve(bouncer.into_make_service())
                     .map(|handle| ("bouncer (without challenge responder)", handle))
                     .boxed();
@@ -361,6 +1457,8 @@ impl UserServiceBuilder {
             }
 
             let user_without_tls = axum_server::Server::bind(user_binds_to)
+                .addr_incoming_config(self.listener.incoming_config())
+                .acceptor(ListenerAcceptor::new(DefaultAcceptor::new(), self.listener))
                 .serve(user_proxy.into_make_service())
                 .map(|handle| ("user proxy (no TLS)", handle))
                 .boxed();
@@ -373,3 +1471,1848 @@ impl UserServiceBuilder {
         })
     }
 }
+
+#[cfg(test)]
+pub mod tests {
+    use std::collections::BTreeMap;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Instant;
+
+    use axum::routing::get;
+    use axum::Router;
+
+    use axum::headers::Authorization;
+    use fqdn::fqdn;
+    use http::{StatusCode, Uri};
+    use shuttle_common::models::project::{
+        self, ConnectionLimit, HeaderRuleSet, HeaderRules, IpFilter, RateLimit,
+    };
+
+    use super::*;
+    use crate::api::latest::ApiBuilder;
+    use crate::counters::TrafficCounters;
+    use crate::drain::{ConnectionDrainer, DRAIN_TIMEOUT};
+    use crate::task::BoxedTask;
+    use crate::tests::{assert_err_kind, RequestBuilderExt, World};
+
+    impl UserProxy {
+        /// A proxy for `gateway` serving projects under `public`, with
+        /// the defaults of the user proxy
+        fn for_test(gateway: Arc<GatewayService>, public: FQDN) -> Self {
+            Self {
+                gateway,
+                pool: UpstreamPool::new(DEFAULT_UPSTREAM_CONNECT_TIMEOUT),
+                upstream_timeout: DEFAULT_UPSTREAM_TIMEOUT,
+                upstream_retries: 0,
+                provisioning_hold: Duration::ZERO,
+                cold_start_hold: Duration::ZERO,
+                header_limits: HeaderLimits::default(),
+                backend_resolver: BackendResolver::default(),
+                body_mode: ProxyBodyMode::Streaming,
+                error_format: ProxyErrorFormat::Json,
+                error_pages: Default::default(),
+                remote_addr: "127.0.0.1:80".parse().unwrap(),
+                public: vec![public],
+            }
+        }
+
+        fn with_public(mut self, public: FQDN) -> Self {
+            self.public.push(public);
+            self
+        }
+
+        fn with_provisioning_hold(mut self, provisioning_hold: Duration) -> Self {
+            self.provisioning_hold = provisioning_hold;
+            self
+        }
+
+        fn with_cold_start_hold(mut self, cold_start_hold: Duration) -> Self {
+            self.cold_start_hold = cold_start_hold;
+            self
+        }
+
+        fn with_header_limits(mut self, header_limits: HeaderLimits) -> Self {
+            self.header_limits = header_limits;
+            self
+        }
+
+        fn with_backend_resolver(mut self, backend_resolver: BackendResolver) -> Self {
+            self.backend_resolver = backend_resolver;
+            self
+        }
+
+        fn with_error_format(mut self, error_format: ProxyErrorFormat) -> Self {
+            self.error_format = error_format;
+            self
+        }
+
+        fn with_error_pages(mut self, error_pages: ErrorPages) -> Self {
+            self.error_pages = Arc::new(error_pages);
+            self
+        }
+
+        fn with_remote_addr(mut self, remote_addr: SocketAddr) -> Self {
+            self.remote_addr = remote_addr;
+            self
+        }
+    }
+
+    fn localhost() -> IpAddr {
+        "127.0.0.1".parse().unwrap()
+    }
+
+    #[tokio::test]
+    async fn proxy_unreachable_upstream() {
+        let port = portpicker::pick_unused_port().unwrap();
+        let client = make_proxy_client(Duration::from_secs(1));
+
+        let start = Instant::now();
+        assert_err_kind!(
+            forward(
+                &client,
+                Duration::from_secs(10),
+                0,
+                localhost(),
+                &format!("http://127.0.0.1:{port}"),
+                None,
+                Request::get("/").body(Body::empty()).unwrap(),
+            )
+            .await,
+            ErrorKind::ProjectUnreachable
+        );
+        assert!(Instant::now() - start < Duration::from_secs(2));
+    }
+
+    #[tokio::test]
+    async fn proxy_slow_upstream() {
+        let port = portpicker::pick_unused_port().unwrap();
+        let addr: SocketAddr = format!("127.0.0.1:{port}").parse().unwrap();
+
+        let router = Router::new().route(
+            "/",
+            get(|| async {
+                tokio::time::sleep(Duration::from_secs(5)).await;
+                "too late"
+            }),
+        );
+        tokio::spawn(axum::Server::bind(&addr).serve(router.into_make_service()));
+        // give the server a moment to start listening
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let client = make_proxy_client(Duration::from_secs(1));
+
+        let start = Instant::now();
+        assert_err_kind!(
+            forward(
+                &client,
+                Duration::from_millis(500),
+                0,
+                localhost(),
+                &format!("http://{addr}"),
+                None,
+                Request::get("/").body(Body::empty()).unwrap(),
+            )
+            .await,
+            ErrorKind::ProjectTimedOut
+        );
+        assert!(Instant::now() - start < Duration::from_secs(2));
+    }
+
+    /// Counts the connections it opens
+    #[derive(Clone)]
+    struct CountingConnector {
+        inner: HttpConnector,
+        connects: Arc<AtomicUsize>,
+    }
+
+    impl Service<Uri> for CountingConnector {
+        type Response = <HttpConnector as Service<Uri>>::Response;
+        type Error = <HttpConnector as Service<Uri>>::Error;
+        type Future = <HttpConnector as Service<Uri>>::Future;
+
+        fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+            self.inner.poll_ready(cx)
+        }
+
+        fn call(&mut self, uri: Uri) -> Self::Future {
+            self.connects.fetch_add(1, Ordering::SeqCst);
+            self.inner.call(uri)
+        }
+    }
+
+    #[tokio::test]
+    async fn proxy_reuses_upstream_connections() {
+        let port = portpicker::pick_unused_port().unwrap();
+        let addr: SocketAddr = format!("127.0.0.1:{port}").parse().unwrap();
+
+        let router = Router::new().route("/", get(|| async { "hello" }));
+        tokio::spawn(axum::Server::bind(&addr).serve(router.into_make_service()));
+        // give the server a moment to start listening
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let connects = Arc::new(AtomicUsize::new(0));
+        let pool = UpstreamPool::with_connector(CountingConnector {
+            inner: HttpConnector::new(),
+            connects: connects.clone(),
+        });
+        let matrix: ProjectName = "matrix".parse().unwrap();
+
+        let send = |target_ip: IpAddr| {
+            let client = pool.client(&matrix, target_ip, UpstreamProtocol::Http1);
+            async move {
+                let resp = forward(
+                    &client,
+                    DEFAULT_UPSTREAM_TIMEOUT,
+                    0,
+                    localhost(),
+                    &format!("http://{addr}"),
+                    None,
+                    Request::get("/").body(Body::empty()).unwrap(),
+                )
+                .await
+                .unwrap();
+                assert_eq!(resp.status(), StatusCode::OK);
+                hyper::body::to_bytes(resp.into_body()).await.unwrap();
+
+                // the connection goes back to the pool in the background
+                tokio::time::sleep(Duration::from_millis(50)).await;
+            }
+        };
+
+        for _ in 0..5 {
+            send(localhost()).await;
+        }
+        assert_eq!(connects.load(Ordering::SeqCst), 1);
+
+        // the project restarted somewhere else so its connections are
+        // not reused
+        send("127.0.0.2".parse().unwrap()).await;
+        assert_eq!(connects.load(Ordering::SeqCst), 2);
+
+        send("127.0.0.2".parse().unwrap()).await;
+        assert_eq!(connects.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn proxy_drains_restarting_backend() {
+        let serve = |name: &'static str| {
+            let port = portpicker::pick_unused_port().unwrap();
+            let addr: SocketAddr = format!("127.0.0.1:{port}").parse().unwrap();
+            let router = Router::new().route(
+                "/",
+                get(move || async move {
+                    tokio::time::sleep(Duration::from_millis(300)).await;
+                    name
+                }),
+            );
+            tokio::spawn(axum::Server::bind(&addr).serve(router.into_make_service()));
+            addr
+        };
+        let old_addr = serve("old");
+        let new_addr = serve("new");
+        // give the servers a moment to start listening
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let drainer = ConnectionDrainer::new();
+        let pool = UpstreamPool::new(DEFAULT_UPSTREAM_CONNECT_TIMEOUT);
+        let matrix: ProjectName = "matrix".parse().unwrap();
+        let old_ip = localhost();
+        let new_ip: IpAddr = "127.0.0.2".parse().unwrap();
+
+        // what the user proxy does with a request to the backend
+        // currently at `target_ip`
+        let send = |target_ip: IpAddr, addr: SocketAddr| {
+            let in_flight = drainer.track(&matrix, target_ip);
+            let client = pool.client(&matrix, target_ip, UpstreamProtocol::Http1);
+            async move {
+                let _in_flight = in_flight.ok_or(StatusCode::SERVICE_UNAVAILABLE)?;
+                let resp = forward(
+                    &client,
+                    DEFAULT_UPSTREAM_TIMEOUT,
+                    0,
+                    localhost(),
+                    &format!("http://{addr}"),
+                    None,
+                    Request::get("/").body(Body::empty()).unwrap(),
+                )
+                .await
+                .unwrap();
+                assert_eq!(resp.status(), StatusCode::OK);
+                let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+                Ok::<_, StatusCode>(String::from_utf8(body.to_vec()).unwrap())
+            }
+        };
+
+        let long = tokio::spawn(send(old_ip, old_addr));
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(drainer.in_flight(&matrix, old_ip), 1);
+
+        // the restart waits for the old backend before taking it down
+        let restart = tokio::spawn({
+            let drainer = drainer.clone();
+            let matrix = matrix.clone();
+            async move { drainer.drain(&matrix, old_ip, DRAIN_TIMEOUT).await }
+        });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        // nothing new is sent to the old backend while it drains...
+        assert_eq!(
+            send(old_ip, old_addr).await,
+            Err(StatusCode::SERVICE_UNAVAILABLE)
+        );
+        assert!(!restart.is_finished());
+
+        // ...whereas the new one takes requests once it is ready
+        assert_eq!(send(new_ip, new_addr).await, Ok("new".to_string()));
+
+        assert_eq!(long.await.unwrap(), Ok("old".to_string()));
+        assert!(restart.await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn proxy_rewrites_headers() {
+        let port = portpicker::pick_unused_port().unwrap();
+        let addr: SocketAddr = format!("127.0.0.1:{port}").parse().unwrap();
+
+        let router = Router::new().route(
+            "/",
+            get(|headers: http::HeaderMap| async move {
+                let internal = headers.contains_key("x-internal-token");
+                ([("server", "upstream")], format!("internal: {internal}"))
+            }),
+        );
+        tokio::spawn(axum::Server::bind(&addr).serve(router.into_make_service()));
+        // give the server a moment to start listening
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let rewrites = HeaderRewrites::new(HeaderRules {
+            request: HeaderRuleSet {
+                remove: vec!["x-internal-token".to_string()],
+                ..Default::default()
+            },
+            response: HeaderRuleSet {
+                set: BTreeMap::from([(
+                    "strict-transport-security".to_string(),
+                    "max-age=63072000".to_string(),
+                )]),
+                remove: vec!["server".to_string()],
+                ..Default::default()
+            },
+        })
+        .unwrap();
+
+        let client = make_proxy_client(DEFAULT_UPSTREAM_CONNECT_TIMEOUT);
+        let resp = forward(
+            &client,
+            DEFAULT_UPSTREAM_TIMEOUT,
+            0,
+            localhost(),
+            &format!("http://{addr}"),
+            Some(&rewrites),
+            Request::get("/")
+                .header("x-internal-token", "hunter2")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(
+            resp.headers().get("strict-transport-security").unwrap(),
+            "max-age=63072000"
+        );
+        assert!(resp.headers().get("server").is_none());
+
+        let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+        assert_eq!(body, "internal: false");
+    }
+
+    #[tokio::test]
+    async fn proxy_counts_traffic() {
+        let port = portpicker::pick_unused_port().unwrap();
+        let addr: SocketAddr = format!("127.0.0.1:{port}").parse().unwrap();
+
+        let router = Router::new().route(
+            "/",
+            axum::routing::post(|body: axum::body::Bytes| async move {
+                assert_eq!(body.len(), 512);
+                "x".repeat(1000)
+            }),
+        );
+        tokio::spawn(axum::Server::bind(&addr).serve(router.into_make_service()));
+        // give the server a moment to start listening
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let traffic = TrafficCounters::new();
+        let matrix: ProjectName = "matrix".parse().unwrap();
+
+        // a body streamed in chunks, of a size the proxy is not told
+        let chunks = (0..4).map(|_| Ok::<_, io::Error>(vec![b'a'; 128]));
+        let req = Request::post("/")
+            .body(Body::wrap_stream(stream::iter(chunks)))
+            .unwrap();
+        let req = count_request(req, traffic.project(&matrix));
+
+        let client = make_proxy_client(DEFAULT_UPSTREAM_CONNECT_TIMEOUT);
+        let resp = forward(
+            &client,
+            DEFAULT_UPSTREAM_TIMEOUT,
+            0,
+            localhost(),
+            &format!("http://{addr}"),
+            None,
+            req,
+        )
+        .await
+        .unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(traffic.project(&matrix).bytes_in(), 512);
+
+        // what goes out is counted as the client reads it
+        let body = CountedBody::new(resp.into_body(), traffic.project(&matrix), Direction::Out);
+        assert_eq!(traffic.project(&matrix).bytes_out(), 0);
+        let body = hyper::body::to_bytes(body).await.unwrap();
+        assert_eq!(body.len(), 1000);
+
+        assert_eq!(traffic.project(&matrix).bytes_in(), 512);
+        assert_eq!(traffic.project(&matrix).bytes_out(), 1000);
+        assert_eq!(
+            traffic.snapshot(),
+            BTreeMap::from([("matrix".to_string(), (512, 1000))])
+        );
+    }
+
+    #[tokio::test]
+    async fn proxy_retries_idempotent_requests() {
+        // an upstream which drops the first connection it gets, like
+        // one in the middle of restarting
+        async fn flaky_upstream() -> String {
+            let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let addr = listener.local_addr().unwrap();
+            tokio::spawn(async move {
+                let (stream, _) = listener.accept().await.unwrap();
+                drop(stream);
+
+                loop {
+                    let (stream, _) = listener.accept().await.unwrap();
+                    tokio::spawn(hyper::server::conn::Http::new().serve_connection(
+                        stream,
+                        hyper::service::service_fn(|_| async {
+                            Ok::<_, Infallible>(hyper::Response::new(Body::from("ok")))
+                        }),
+                    ));
+                }
+            });
+            format!("http://{addr}")
+        }
+
+        let client = make_proxy_client(DEFAULT_UPSTREAM_CONNECT_TIMEOUT);
+        let request = |method| {
+            Request::builder()
+                .method(method)
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        let resp = forward(
+            &client,
+            DEFAULT_UPSTREAM_TIMEOUT,
+            1,
+            localhost(),
+            &flaky_upstream().await,
+            None,
+            request(Method::GET),
+        )
+        .await
+        .unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+        assert_eq!(body, "ok");
+
+        // a POST could end up being processed twice
+        let err = forward(
+            &client,
+            DEFAULT_UPSTREAM_TIMEOUT,
+            1,
+            localhost(),
+            &flaky_upstream().await,
+            None,
+            request(Method::POST),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::ProjectUnavailable);
+    }
+
+    #[tokio::test]
+    async fn proxy_http2_upstream() {
+        // an h2 only upstream echoing the body and trailers it gets,
+        // the way a gRPC service would answer
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                tokio::spawn(
+                    hyper::server::conn::Http::new()
+                        .http2_only(true)
+                        .serve_connection(
+                            stream,
+                            hyper::service::service_fn(|req: Request<Body>| async move {
+                                assert_eq!(req.version(), Version::HTTP_2);
+
+                                let mut body = req.into_body();
+                                let data = hyper::body::to_bytes(&mut body).await.unwrap();
+                                let mut trailers = body.trailers().await.unwrap().unwrap();
+                                trailers.insert("grpc-status", HeaderValue::from_static("0"));
+
+                                let (mut sender, body) = Body::channel();
+                                tokio::spawn(async move {
+                                    sender.send_data(data).await.unwrap();
+                                    sender.send_trailers(trailers).await.unwrap();
+                                });
+
+                                Ok::<_, Infallible>(hyper::Response::new(body))
+                            }),
+                        ),
+                );
+            }
+        });
+
+        let pool = UpstreamPool::new(DEFAULT_UPSTREAM_CONNECT_TIMEOUT);
+        let matrix: ProjectName = "matrix".parse().unwrap();
+        let client = pool.client(&matrix, localhost(), UpstreamProtocol::Http2);
+
+        let (mut sender, body) = Body::channel();
+        tokio::spawn(async move {
+            sender.send_data("red pill".into()).await.unwrap();
+            let mut trailers = http::HeaderMap::new();
+            trailers.insert("x-dose", HeaderValue::from_static("1"));
+            sender.send_trailers(trailers).await.unwrap();
+        });
+        let req = Request::post("/matrix.Construct/Load")
+            .version(Version::HTTP_2)
+            .header("content-type", "application/grpc")
+            .body(body)
+            .unwrap();
+
+        let resp = forward(
+            &client,
+            DEFAULT_UPSTREAM_TIMEOUT,
+            0,
+            localhost(),
+            &format!("http://{addr}"),
+            None,
+            req,
+        )
+        .await
+        .unwrap();
+
+        // trailers have to make it through even when bodies are
+        // meant to be buffered
+        let resp = relay_body(resp, ProxyBodyMode::Buffered).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(resp.version(), Version::HTTP_2);
+
+        let mut body = resp.into_body();
+        let data = hyper::body::to_bytes(&mut body).await.unwrap();
+        assert_eq!(data, "red pill");
+
+        let trailers = body.trailers().await.unwrap().unwrap();
+        assert_eq!(trailers["grpc-status"], "0");
+        assert_eq!(trailers["x-dose"], "1");
+    }
+
+    #[tokio::test]
+    async fn proxy_relays_partial_content() {
+        let port = portpicker::pick_unused_port().unwrap();
+        let addr: SocketAddr = format!("127.0.0.1:{port}").parse().unwrap();
+
+        let router = Router::new().route(
+            "/video.mp4",
+            get(|headers: http::HeaderMap| async move {
+                assert_eq!(headers.get("range").unwrap(), "bytes=2-5");
+                (
+                    StatusCode::PARTIAL_CONTENT,
+                    [
+                        ("content-range", "bytes 2-5/10"),
+                        ("accept-ranges", "bytes"),
+                    ],
+                    "2345",
+                )
+            }),
+        );
+        tokio::spawn(axum::Server::bind(&addr).serve(router.into_make_service()));
+        // give the server a moment to start listening
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let client = make_proxy_client(DEFAULT_UPSTREAM_CONNECT_TIMEOUT);
+        let resp = forward(
+            &client,
+            DEFAULT_UPSTREAM_TIMEOUT,
+            0,
+            localhost(),
+            &format!("http://{addr}"),
+            None,
+            Request::get("/video.mp4")
+                .header("range", "bytes=2-5")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(resp.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(resp.headers().get("content-range").unwrap(), "bytes 2-5/10");
+        assert_eq!(resp.headers().get("accept-ranges").unwrap(), "bytes");
+
+        let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+        assert_eq!(body, "2345");
+    }
+
+    #[tokio::test]
+    async fn proxy_body_modes() {
+        let port = portpicker::pick_unused_port().unwrap();
+        let addr: SocketAddr = format!("127.0.0.1:{port}").parse().unwrap();
+
+        // every request gets the next body in line, which the test
+        // then feeds chunk by chunk
+        let (bodies_tx, bodies_rx) = std::sync::mpsc::channel::<Body>();
+        let bodies_rx = Arc::new(Mutex::new(bodies_rx));
+        let router = Router::new().route(
+            "/events",
+            get(move || {
+                let body = bodies_rx.lock().unwrap().recv().unwrap();
+                async move { body }
+            }),
+        );
+        tokio::spawn(axum::Server::bind(&addr).serve(router.into_make_service()));
+        // give the server a moment to start listening
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let client = make_proxy_client(DEFAULT_UPSTREAM_CONNECT_TIMEOUT);
+        let request = || {
+            forward(
+                &client,
+                DEFAULT_UPSTREAM_TIMEOUT,
+                0,
+                localhost(),
+                &format!("http://{addr}"),
+                None,
+                Request::get("/events").body(Body::empty()).unwrap(),
+            )
+        };
+
+        // streaming hands the first chunk on while the rest is not
+        // even produced yet
+        let (mut sender, body) = Body::channel();
+        bodies_tx.send(body).unwrap();
+        let resp = request().await.unwrap();
+        let resp = relay_body(resp, ProxyBodyMode::Streaming).await.unwrap();
+        assert!(resp.headers().get(CONTENT_LENGTH).is_none());
+        let mut body = resp.into_body();
+
+        sender.send_data("first".into()).await.unwrap();
+        let chunk = timeout(Duration::from_secs(5), body.data())
+            .await
+            .expect("the first chunk should be forwarded on its own")
+            .unwrap()
+            .unwrap();
+        assert_eq!(chunk, "first");
+
+        sender.send_data("second".into()).await.unwrap();
+        drop(sender);
+        let rest = hyper::body::to_bytes(body).await.unwrap();
+        assert_eq!(rest, "second");
+
+        // buffering waits for the whole body
+        let (mut sender, body) = Body::channel();
+        bodies_tx.send(body).unwrap();
+        tokio::spawn(async move {
+            sender.send_data("first".into()).await.unwrap();
+            sender.send_data("second".into()).await.unwrap();
+        });
+        let resp = request().await.unwrap();
+        let resp = relay_body(resp, ProxyBodyMode::Buffered).await.unwrap();
+        assert_eq!(resp.headers().get(CONTENT_LENGTH).unwrap(), "11");
+        let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+        assert_eq!(body, "firstsecond");
+
+        // unless it is too big
+        let (mut sender, body) = Body::channel();
+        bodies_tx.send(body).unwrap();
+        tokio::spawn(async move {
+            sender.send_data("first".into()).await.unwrap();
+            sender.send_data("second".into()).await.unwrap();
+        });
+        let resp = buffer_body(request().await.unwrap(), 4).await.unwrap();
+        assert!(resp.headers().get(CONTENT_LENGTH).is_none());
+        let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+        assert_eq!(body, "firstsecond");
+    }
+
+    #[tokio::test]
+    async fn proxy_rate_limited() -> anyhow::Result<()> {
+        let world = World::new().await;
+        let service = Arc::new(GatewayService::init(world.args(), world.pool()).await);
+
+        let neo = service.create_user("neo".parse().unwrap()).await?;
+        let matrix: ProjectName = "matrix".parse().unwrap();
+        service.create_project(matrix.clone(), neo.name).await?;
+        service
+            .set_project_rate_limit(
+                &matrix,
+                RateLimit {
+                    requests_per_second: 1,
+                    burst: 2,
+                },
+            )
+            .await?;
+
+        let mut proxy = UserProxy::for_test(service, world.fqdn());
+
+        let request = || {
+            Request::get("/")
+                .header("Host", format!("matrix.{}", world.fqdn()))
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        // the project is not running so the requests within the limit
+        // make it past the limiter only to find nothing there
+        for _ in 0..2 {
+            let resp = proxy.call(request()).await.unwrap();
+            assert_ne!(resp.status(), StatusCode::TOO_MANY_REQUESTS);
+        }
+
+        let resp = proxy.call(request()).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(resp.headers().get(RETRY_AFTER).unwrap(), "1");
+
+        // other projects are not affected
+        let resp = proxy
+            .call(
+                Request::get("/")
+                    .header("Host", format!("reloaded.{}", world.fqdn()))
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_ne!(resp.status(), StatusCode::TOO_MANY_REQUESTS);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn proxy_resolves_all_public_fqdns() -> anyhow::Result<()> {
+        let world = World::new().await;
+        let service = Arc::new(GatewayService::init(world.args(), world.pool()).await);
+
+        let neo = service.create_user("neo".parse().unwrap()).await?;
+        service
+            .create_project("matrix".parse().unwrap(), neo.name)
+            .await?;
+
+        let mut proxy = UserProxy::for_test(service, fqdn!("shuttleapp.rs"))
+            .with_public(fqdn!("staging.shuttleapp.rs"));
+
+        let request = |host: &str| {
+            Request::get("/")
+                .header("Host", host)
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        // the project is found under either base, only to not be
+        // running yet
+        for host in ["matrix.shuttleapp.rs", "matrix.staging.shuttleapp.rs"] {
+            let resp = proxy.call(request(host)).await.unwrap();
+            assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE, "{host}");
+        }
+
+        for host in [
+            "matrix.example.com",
+            "matrix.dev.shuttleapp.rs",
+            "reloaded.staging.shuttleapp.rs",
+        ] {
+            let resp = proxy.call(request(host)).await.unwrap();
+            assert_eq!(resp.status(), StatusCode::NOT_FOUND, "{host}");
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn proxy_resolves_aliases() -> anyhow::Result<()> {
+        let world = World::new().await;
+        let service = Arc::new(GatewayService::init(world.args(), world.pool()).await);
+
+        let neo = service.create_user("neo".parse().unwrap()).await?;
+        let matrix: ProjectName = "matrix".parse().unwrap();
+        let canary: ProjectName = "matrix-canary".parse().unwrap();
+        service
+            .create_project(matrix.clone(), neo.name.clone())
+            .await?;
+        service
+            .create_project("reloaded".parse().unwrap(), neo.name.clone())
+            .await?;
+        service.add_project_alias(&matrix, &canary).await?;
+
+        // aliases share their names with projects
+        let err = service
+            .add_project_alias(&matrix, &"reloaded".parse().unwrap())
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::ProjectAlreadyExists);
+        let err = service
+            .create_project(canary.clone(), neo.name)
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::ProjectAlreadyExists);
+
+        let mut proxy = UserProxy::for_test(Arc::clone(&service), world.fqdn());
+
+        let request = |host: String| {
+            Request::get("/")
+                .header("Host", host)
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        // the alias reaches the project, only to not be running yet
+        let resp = proxy
+            .call(request(format!("matrix-canary.{}", world.fqdn())))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert!(service.activity_tracker().last_activity(&matrix).is_some());
+        assert!(service.activity_tracker().last_activity(&canary).is_none());
+
+        let resp = proxy
+            .call(request(format!("matrix.{}", world.fqdn())))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+        service.remove_project_alias(&matrix, &canary).await?;
+        let resp = proxy
+            .call(request(format!("matrix-canary.{}", world.fqdn())))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn proxy_follows_renamed_projects() -> anyhow::Result<()> {
+        let world = World::new().await;
+        let service = Arc::new(GatewayService::init(world.args(), world.pool()).await);
+
+        let neo = service.create_user("neo".parse().unwrap()).await?;
+        let matrix: ProjectName = "matrix".parse().unwrap();
+        let reloaded: ProjectName = "reloaded".parse().unwrap();
+        let canary: ProjectName = "matrix-canary".parse().unwrap();
+        service
+            .create_project(matrix.clone(), neo.name.clone())
+            .await?;
+        service.add_project_alias(&matrix, &canary).await?;
+        service
+            .set_project_env(&matrix, &[("PILL".to_string(), "red".to_string())].into())
+            .await?;
+        let limit = RateLimit {
+            requests_per_second: 5,
+            burst: 5,
+        };
+        service
+            .set_project_rate_limit(&matrix, limit.clone())
+            .await?;
+
+        // the new name has to be free
+        let err = service
+            .rename_project(&matrix, &canary, true)
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::ProjectAlreadyExists);
+
+        let renamed = service.rename_project(&matrix, &reloaded, true).await?;
+        assert!(
+            matches!(renamed, Project::Creating(ref creating) if creating.project_name() == &reloaded)
+        );
+
+        assert_err_kind!(
+            service.find_project(&matrix).await,
+            ErrorKind::ProjectNotFound
+        );
+        assert_eq!(service.find_project(&reloaded).await?, renamed);
+        assert_eq!(
+            service.project_env(&reloaded).await?,
+            vec![("PILL".to_string(), "red".to_string())]
+        );
+        assert_eq!(service.rate_limiter().limit(&reloaded), Some(limit));
+        assert_eq!(service.rate_limiter().limit(&matrix), None);
+        assert_eq!(
+            service.resolve_project_alias(canary.clone()).await?,
+            reloaded
+        );
+        assert!(service.is_project_name_available(&matrix).await?);
+
+        let mut proxy = UserProxy::for_test(Arc::clone(&service), world.fqdn());
+
+        let request = |host: String| {
+            Request::get("/red/pill?dose=1")
+                .header("Host", host)
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        // the new name reaches the project, only to not be running yet
+        let resp = proxy
+            .call(request(format!("reloaded.{}", world.fqdn())))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+        // while the old one sends clients over to it
+        let resp = proxy
+            .call(request(format!("matrix.{}", world.fqdn())))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::MOVED_PERMANENTLY);
+        assert_eq!(
+            resp.headers()["Location"],
+            format!("https://reloaded.{}/red/pill?dose=1", world.fqdn())
+        );
+
+        // until someone else takes it
+        service.create_project(matrix.clone(), neo.name).await?;
+        let resp = proxy
+            .call(request(format!("matrix.{}", world.fqdn())))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn proxy_frozen_project() -> anyhow::Result<()> {
+        let world = World::new().await;
+        let service = Arc::new(GatewayService::init(world.args(), world.pool()).await);
+
+        let neo = service.create_user("neo".parse().unwrap()).await?;
+        let matrix: ProjectName = "matrix".parse().unwrap();
+        service.create_project(matrix.clone(), neo.name).await?;
+
+        let proxy = UserProxy::for_test(Arc::clone(&service), world.fqdn());
+        let send = || {
+            let mut proxy = proxy.clone();
+            let req = Request::get("/")
+                .header("Host", format!("matrix.{}", world.fqdn()))
+                .body(Body::empty())
+                .unwrap();
+            async move {
+                let resp = proxy.call(req).await.unwrap();
+                let status = resp.status();
+                let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+                let error: ApiError = serde_json::from_slice(&body).unwrap();
+                (status, error.code)
+            }
+        };
+
+        let frozen: Project = serde_json::from_value(serde_json::json!({
+            "frozen": { "container": {} }
+        }))?;
+        service.update_project(&matrix, &frozen).await?;
+
+        let (status, code) = send().await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        assert_eq!(code.as_deref(), Some("project_frozen"));
+
+        // once unfrozen, requests get through to the project again, which
+        // is just not running yet
+        let stopped: Project = serde_json::from_value(serde_json::json!({
+            "stopped": { "container": {} }
+        }))?;
+        service.update_project(&matrix, &stopped).await?;
+
+        let (status, code) = send().await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(code.as_deref(), Some("project_not_ready"));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn proxy_error_pages() -> anyhow::Result<()> {
+        let world = World::new().await;
+        let service = Arc::new(GatewayService::init(world.args(), world.pool()).await);
+
+        let neo = service.create_user("neo".parse().unwrap()).await?;
+        let matrix: ProjectName = "matrix".parse().unwrap();
+        service.create_project(matrix.clone(), neo.name).await?;
+        let stopped: Project = serde_json::from_value(serde_json::json!({
+            "stopped": { "container": {} }
+        }))?;
+        service.update_project(&matrix, &stopped).await?;
+
+        let proxy = |error_format| {
+            UserProxy::for_test(Arc::clone(&service), world.fqdn()).with_error_format(error_format)
+        };
+
+        let send = |error_format, project: &str| {
+            let mut proxy = proxy(error_format);
+            let req = Request::get("/")
+                .header("Host", format!("{project}.{}", world.fqdn()))
+                .body(Body::empty())
+                .unwrap();
+            async move {
+                let resp = proxy.call(req).await.unwrap();
+                let status = resp.status();
+                let content_type = resp.headers()[CONTENT_TYPE].to_str().unwrap().to_string();
+                let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+                (
+                    status,
+                    content_type,
+                    String::from_utf8(body.to_vec()).unwrap(),
+                )
+            }
+        };
+
+        // nothing at the host
+        let (status, content_type, body) = send(ProxyErrorFormat::Json, "reloaded").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(content_type, "application/json");
+        let error: ApiError = serde_json::from_str(&body)?;
+        assert_eq!(error.code.as_deref(), Some("project_not_found"));
+        assert_eq!(
+            error.message,
+            format!("there is no project at reloaded.{}", world.fqdn())
+        );
+
+        let (status, content_type, body) = send(ProxyErrorFormat::Html, "reloaded").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(content_type, "text/html; charset=utf-8");
+        assert!(body.contains(&format!("there is no project at reloaded.{}", world.fqdn())));
+
+        // a project which is there but stopped
+        let (status, _, body) = send(ProxyErrorFormat::Json, "matrix").await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        let error: ApiError = serde_json::from_str(&body)?;
+        assert_eq!(error.code.as_deref(), Some("project_not_ready"));
+
+        let (status, content_type, body) = send(ProxyErrorFormat::Html, "matrix").await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(content_type, "text/html; charset=utf-8");
+        assert!(body.contains(&format!(
+            "the project at matrix.{} is not running right now",
+            world.fqdn()
+        )));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn proxy_custom_error_pages() -> anyhow::Result<()> {
+        let world = World::new().await;
+        let service = Arc::new(GatewayService::init(world.args(), world.pool()).await);
+
+        // a project which fails on its own
+        let backend: IpAddr = "127.0.0.69".parse().unwrap();
+        let router = Router::new().route(
+            "/",
+            get(|| async { (StatusCode::INTERNAL_SERVER_ERROR, "it broke") }),
+        );
+        tokio::spawn(
+            axum::Server::bind(&SocketAddr::new(backend, 8000)).serve(router.into_make_service()),
+        );
+        // give the server a moment to start listening
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        // and one where nothing listens
+        let neo = service.create_user("neo".parse().unwrap()).await?;
+        for (name, target) in [("matrix", "127.0.0.69"), ("zion", "127.0.0.70")] {
+            let ready: Project = serde_json::from_value(serde_json::json!({
+                "ready": {
+                    "container": {},
+                    "service": { "name": name, "target": target, "last_check": null }
+                }
+            }))?;
+            let project_name: ProjectName = name.parse()?;
+            service
+                .create_project(project_name.clone(), neo.name.clone())
+                .await?;
+            service.update_project(&project_name, &ready).await?;
+        }
+
+        let proxy =
+            UserProxy::for_test(Arc::clone(&service), world.fqdn()).with_error_pages(ErrorPages {
+                client: None,
+                server: Some("<h1>{{status}} {{reason}}</h1><p>{{message}}</p>".to_string()),
+            });
+        let send = |project: &str| {
+            let mut proxy = proxy.clone();
+            let req = Request::get("/")
+                .header("Host", format!("{project}.{}", world.fqdn()))
+                .body(Body::empty())
+                .unwrap();
+            async move {
+                let resp = proxy.call(req).await.unwrap();
+                let status = resp.status();
+                let content_type = resp.headers()[CONTENT_TYPE].to_str().unwrap().to_string();
+                let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+                (
+                    status,
+                    content_type,
+                    String::from_utf8(body.to_vec()).unwrap(),
+                )
+            }
+        };
+
+        // errors of the proxy get the page of their class
+        let (status, content_type, body) = send("zion").await;
+        assert_eq!(status, StatusCode::BAD_GATEWAY);
+        assert_eq!(content_type, "text/html; charset=utf-8");
+        assert_eq!(
+            body,
+            "<h1>502 Bad Gateway</h1><p>project is unreachable</p>"
+        );
+
+        // those of projects are passed on as they are
+        let (status, content_type, body) = send("matrix").await;
+        assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(content_type, "text/plain; charset=utf-8");
+        assert_eq!(body, "it broke");
+
+        // and classes without a page keep the default
+        let (status, content_type, body) = send("reloaded").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(content_type, "application/json");
+        let error: ApiError = serde_json::from_str(&body)?;
+        assert_eq!(error.code.as_deref(), Some("project_not_found"));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn proxy_missing_host() -> anyhow::Result<()> {
+        let world = World::new().await;
+        let service = Arc::new(GatewayService::init(world.args(), world.pool()).await);
+
+        let proxy = UserProxy::for_test(Arc::clone(&service), world.fqdn());
+        let send = |req: Request<Body>| {
+            let mut proxy = proxy.clone();
+            async move {
+                let resp = proxy.call(req).await.unwrap();
+                let status = resp.status();
+                let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+                let error: ApiError = serde_json::from_slice(&body).unwrap();
+                (status, error.code)
+            }
+        };
+
+        // a request naming no host at all
+        let req = Request::get("/").body(Body::empty()).unwrap();
+        let (status, code) = send(req).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(code.as_deref(), Some("bad_host"));
+
+        // or one which is not a domain
+        let req = Request::get("/")
+            .header("Host", "not a host")
+            .body(Body::empty())
+            .unwrap();
+        let (status, code) = send(req).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(code.as_deref(), Some("bad_host"));
+
+        // neither takes the bouncer down
+        let mut bouncer = Bouncer {
+            gateway: Arc::clone(&service),
+            public: vec![world.fqdn()],
+        };
+        let resp = bouncer
+            .call(Request::get("/").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn proxy_maintenance_page_during_deploy() -> anyhow::Result<()> {
+        let world = World::new().await;
+        let service = Arc::new(GatewayService::init(world.args(), world.pool()).await);
+
+        // the project is down while its new deploy is switched over to
+        let neo = service.create_user("neo".parse().unwrap()).await?;
+        let matrix: ProjectName = "matrix".parse().unwrap();
+        service
+            .create_project(matrix.clone(), neo.name.clone())
+            .await?;
+        let ready: Project = serde_json::from_value(serde_json::json!({
+            "ready": {
+                "container": {},
+                "service": { "name": "matrix", "target": "127.0.0.71", "last_check": null }
+            }
+        }))?;
+        service.update_project(&matrix, &ready).await?;
+
+        assert_err_kind!(
+            service
+                .set_project_maintenance_page(
+                    &matrix,
+                    MaintenancePage {
+                        body: String::new(),
+                        retry_after_secs: 30,
+                    },
+                )
+                .await,
+            ErrorKind::InvalidMaintenancePage
+        );
+        service
+            .set_project_maintenance_page(
+                &matrix,
+                MaintenancePage {
+                    body: "<h1>back in a moment</h1>".to_string(),
+                    retry_after_secs: 30,
+                },
+            )
+            .await?;
+
+        let proxy = UserProxy::for_test(Arc::clone(&service), world.fqdn());
+        let send = || {
+            let mut proxy = proxy.clone();
+            let req = Request::get("/")
+                .header("Host", format!("matrix.{}", world.fqdn()))
+                .body(Body::empty())
+                .unwrap();
+            async move {
+                let resp = proxy.call(req).await.unwrap();
+                let status = resp.status();
+                let retry_after = resp
+                    .headers()
+                    .get(RETRY_AFTER)
+                    .map(|value| value.to_str().unwrap().to_string());
+                let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+                (
+                    status,
+                    retry_after,
+                    String::from_utf8(body.to_vec()).unwrap(),
+                )
+            }
+        };
+
+        // outside of a deploy, errors are errors
+        let (status, _, body) = send().await;
+        assert_eq!(status, StatusCode::BAD_GATEWAY);
+        let error: ApiError = serde_json::from_str(&body)?;
+        assert_eq!(error.code.as_deref(), Some("project_unreachable"));
+
+        // while one goes through the gateway, visitors get the page
+        let guard = service.lock_deploy(&matrix, &neo.name).await?;
+        let (status, retry_after, body) = send().await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(retry_after.as_deref(), Some("30"));
+        assert_eq!(body, "<h1>back in a moment</h1>");
+
+        // and for a while after the deployer took it on
+        service.deploy_handed_over(&matrix);
+        drop(guard);
+        assert_eq!(send().await.2, "<h1>back in a moment</h1>");
+
+        // unless the project has no page
+        service.remove_project_maintenance_page(&matrix).await?;
+        let (status, _, _) = send().await;
+        assert_eq!(status, StatusCode::BAD_GATEWAY);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn proxy_provisioning_project() -> anyhow::Result<()> {
+        let world = World::new().await;
+        let service = Arc::new(GatewayService::init(world.args(), world.pool()).await);
+
+        let neo = service.create_user("neo".parse().unwrap()).await?;
+        let matrix: ProjectName = "matrix".parse().unwrap();
+        service.create_project(matrix.clone(), neo.name).await?;
+        assert!(service.find_project(&matrix).await?.is_provisioning());
+
+        let send = |provisioning_hold, error_format| {
+            let mut proxy = UserProxy::for_test(Arc::clone(&service), world.fqdn())
+                .with_provisioning_hold(provisioning_hold)
+                .with_error_format(error_format);
+            let req = Request::get("/")
+                .header("Host", format!("matrix.{}", world.fqdn()))
+                .body(Body::empty())
+                .unwrap();
+            async move { proxy.call(req).await.unwrap() }
+        };
+
+        // clients are told to come back rather than failing to connect
+        let resp = send(Duration::ZERO, ProxyErrorFormat::Json).await;
+        assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(resp.headers()[RETRY_AFTER], "5");
+        let body = hyper::body::to_bytes(resp.into_body()).await?;
+        let error: ApiError = serde_json::from_slice(&body)?;
+        assert_eq!(error.code.as_deref(), Some("project_starting"));
+
+        let resp = send(Duration::ZERO, ProxyErrorFormat::Html).await;
+        assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(resp.headers()[RETRY_AFTER], "5");
+        let body = hyper::body::to_bytes(resp.into_body()).await?;
+        assert!(String::from_utf8(body.to_vec())?.contains("is starting up"));
+
+        // requests are held for a while in case the project gets ready
+        let start = Instant::now();
+        let resp = send(Duration::from_millis(600), ProxyErrorFormat::Json).await;
+        assert!(start.elapsed() >= Duration::from_millis(500));
+        assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(resp.headers()[RETRY_AFTER], "5");
+
+        // but not once it stopped on its way
+        let stopped: Project = serde_json::from_value(serde_json::json!({
+            "stopped": { "container": {} }
+        }))?;
+        let held = tokio::spawn(send(Duration::from_secs(5), ProxyErrorFormat::Json));
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        service.update_project(&matrix, &stopped).await?;
+        let start = Instant::now();
+        let resp = held.await?;
+        assert!(start.elapsed() < Duration::from_secs(1));
+        assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert!(resp.headers().get(RETRY_AFTER).is_none());
+        let body = hyper::body::to_bytes(resp.into_body()).await?;
+        let error: ApiError = serde_json::from_slice(&body)?;
+        assert_eq!(error.code.as_deref(), Some("project_not_ready"));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn proxy_ip_filter() -> anyhow::Result<()> {
+        let world = World::new().await;
+        let service = Arc::new(GatewayService::init(world.args(), world.pool()).await);
+
+        let neo = service.create_user("neo".parse().unwrap()).await?;
+        let matrix: ProjectName = "matrix".parse().unwrap();
+        service.create_project(matrix.clone(), neo.name).await?;
+
+        assert_err_kind!(
+            service
+                .set_project_ip_filter(
+                    &matrix,
+                    IpFilter {
+                        allow: vec!["10.0.0.0/33".to_string()],
+                        deny: Vec::new(),
+                    },
+                )
+                .await,
+            ErrorKind::InvalidIpFilter
+        );
+        service
+            .set_project_ip_filter(
+                &matrix,
+                IpFilter {
+                    allow: vec!["10.0.0.0/8".to_string()],
+                    deny: vec!["10.6.6.0/24".to_string()],
+                },
+            )
+            .await?;
+
+        let send = |remote_addr: &str| {
+            let mut proxy = UserProxy::for_test(Arc::clone(&service), world.fqdn())
+                .with_remote_addr(remote_addr.parse().unwrap());
+            let req = Request::get("/")
+                .header("Host", format!("matrix.{}", world.fqdn()))
+                // not trusted to tell who the client is
+                .header("X-Forwarded-For", "10.1.2.3")
+                .body(Body::empty())
+                .unwrap();
+            async move { proxy.call(req).await.unwrap().status() }
+        };
+
+        // in range, the request makes it through only to find the
+        // project is not running
+        assert_eq!(send("10.1.2.3:4242").await, StatusCode::SERVICE_UNAVAILABLE);
+
+        // out of range or denied, it is blocked
+        assert_eq!(send("192.168.0.1:4242").await, StatusCode::FORBIDDEN);
+        assert_eq!(send("10.6.6.6:4242").await, StatusCode::FORBIDDEN);
+
+        service.remove_project_ip_filter(&matrix).await?;
+        assert_eq!(
+            send("192.168.0.1:4242").await,
+            StatusCode::SERVICE_UNAVAILABLE
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn proxy_connection_limit() -> anyhow::Result<()> {
+        let world = World::new().await;
+        let service = Arc::new(GatewayService::init(world.args(), world.pool()).await);
+
+        let neo = service.create_user("neo".parse().unwrap()).await?;
+        let matrix: ProjectName = "matrix".parse().unwrap();
+        service.create_project(matrix.clone(), neo.name).await?;
+
+        assert_err_kind!(
+            service
+                .set_project_connection_limit(&matrix, ConnectionLimit { max_connections: 0 })
+                .await,
+            ErrorKind::InvalidConnectionLimit
+        );
+        service
+            .set_project_connection_limit(&matrix, ConnectionLimit { max_connections: 2 })
+            .await?;
+
+        let proxy = UserProxy::for_test(Arc::clone(&service), world.fqdn());
+
+        let send = || {
+            let mut proxy = proxy.clone();
+            let req = Request::get("/")
+                .header("Host", format!("matrix.{}", world.fqdn()))
+                .body(Body::empty())
+                .unwrap();
+            async move {
+                let resp = proxy.call(req).await.unwrap();
+                let status = resp.status();
+                let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+                let error: ApiError = serde_json::from_slice(&body).unwrap();
+                (status, error.code.unwrap())
+            }
+        };
+
+        // the project is not running so requests which get a slot only
+        // find nothing there, and give their slot back straight away
+        for _ in 0..3 {
+            assert_eq!(
+                send().await,
+                (
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    "project_not_ready".to_string()
+                )
+            );
+        }
+
+        // with as many connections open as allowed, more are shed
+        let limiter = service.connection_limiter();
+        let wait = Duration::from_millis(10);
+        let first = limiter.acquire(&matrix, wait).await.unwrap();
+        let _second = limiter.acquire(&matrix, wait).await.unwrap();
+        for _ in 0..3 {
+            assert_eq!(
+                send().await,
+                (
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    "project_overloaded".to_string()
+                )
+            );
+        }
+
+        // unless a connection closes while they wait
+        let waiting = tokio::spawn(send());
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        drop(first);
+        assert_eq!(waiting.await?.1, "project_not_ready");
+
+        // and the limit can be lifted on the fly
+        service.remove_project_connection_limit(&matrix).await?;
+        let _third = limiter.acquire(&matrix, wait).await.unwrap();
+        assert_eq!(send().await.1, "project_not_ready");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn proxy_basic_auth() -> anyhow::Result<()> {
+        let world = World::new().await;
+        let service = Arc::new(GatewayService::init(world.args(), world.pool()).await);
+
+        let neo = service.create_user("neo".parse().unwrap()).await?;
+        let matrix: ProjectName = "matrix".parse().unwrap();
+        service.create_project(matrix.clone(), neo.name).await?;
+
+        assert_err_kind!(
+            service
+                .set_project_basic_auth(&matrix, "ne:o".to_string(), "red-pill")
+                .await,
+            ErrorKind::InvalidBasicAuth
+        );
+        service
+            .set_project_basic_auth(&matrix, "neo".to_string(), "red-pill")
+            .await?;
+
+        let mut proxy = UserProxy::for_test(Arc::clone(&service), world.fqdn());
+
+        let request = |credentials: Option<(&str, &str)>| {
+            let mut req = Request::get("/")
+                .header("Host", format!("matrix.{}", world.fqdn()))
+                .body(Body::empty())
+                .unwrap();
+            if let Some((username, password)) = credentials {
+                req.headers_mut()
+                    .typed_insert(Authorization::basic(username, password));
+            }
+            req
+        };
+
+        // missing and wrong credentials are asked for again
+        for credentials in [
+            None,
+            Some(("neo", "blue-pill")),
+            Some(("smith", "red-pill")),
+        ] {
+            let resp = proxy.call(request(credentials)).await.unwrap();
+            assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+            assert_eq!(resp.headers()[WWW_AUTHENTICATE], "Basic realm=\"matrix\"");
+        }
+
+        // the right ones reach the project, only to not be running yet
+        let resp = proxy
+            .call(request(Some(("neo", "red-pill"))))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+        // and once removed, nothing is asked for
+        service.remove_project_basic_auth(&matrix).await?;
+        let resp = proxy.call(request(None)).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+        // credentials survive a restart of the gateway
+        service
+            .set_project_basic_auth(&matrix, "neo".to_string(), "red-pill")
+            .await?;
+        let reloaded = GatewayService::init(world.args(), world.pool()).await;
+        let auth = reloaded.basic_auth_gate().auth(&matrix).unwrap();
+        assert!(auth.verify("neo", "red-pill"));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn proxy_request_id() {
+        let world = World::new().await;
+        let service = Arc::new(GatewayService::init(world.args(), world.pool()).await);
+
+        let mut proxy = UserProxy::for_test(service, world.fqdn());
+
+        let request = || Request::get("/").header("Host", format!("matrix.{}", world.fqdn()));
+
+        // the id of the client is echoed back, even on errors
+        let resp = proxy
+            .call(
+                request()
+                    .header("x-request-id", "client-id-42")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+        assert_eq!(resp.headers().get("x-request-id").unwrap(), "client-id-42");
+
+        // otherwise one is made up
+        let resp = proxy
+            .call(request().body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let request_id = resp
+            .headers()
+            .get("x-request-id")
+            .unwrap()
+            .to_str()
+            .unwrap();
+        assert!(request_id.parse::<Uuid>().is_ok());
+    }
+
+    #[tokio::test]
+    async fn proxy_header_limits() {
+        let world = World::new().await;
+        let service = Arc::new(GatewayService::init(world.args(), world.pool()).await);
+
+        let mut proxy =
+            UserProxy::for_test(service, world.fqdn()).with_header_limits(HeaderLimits {
+                max_size: 1024,
+                max_count: 10,
+            });
+
+        let request = || Request::get("/").header("Host", format!("matrix.{}", world.fqdn()));
+
+        // within the limits, the request goes on to find there is no project
+        let resp = proxy
+            .call(request().body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+        // a single header too large
+        let resp = proxy
+            .call(
+                request()
+                    .header("x-large", "a".repeat(2048))
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE);
+        let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+        let error: ApiError = serde_json::from_slice(&body).unwrap();
+        assert_eq!(error.code.as_deref(), Some("headers_too_large"));
+
+        // too many small ones
+        let mut builder = request();
+        for i in 0..20 {
+            builder = builder.header(format!("x-header-{i}"), "value");
+        }
+        let resp = proxy
+            .call(builder.body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE);
+    }
+
+    #[tokio::test]
+    async fn proxy_records_activity() -> anyhow::Result<()> {
+        let world = World::new().await;
+        let service = Arc::new(GatewayService::init(world.args(), world.pool()).await);
+
+        let (sender, mut receiver) = tokio::sync::mpsc::channel::<BoxedTask>(256);
+        tokio::spawn(async move {
+            while receiver.recv().await.is_some() {
+                // do not do any work with inbound requests
+            }
+        });
+
+        let mut router = ApiBuilder::new()
+            .with_service(Arc::clone(&service))
+            .with_sender(sender)
+            .with_default_routes()
+            .into_router();
+
+        let neo = service.create_user("neo".parse().unwrap()).await?;
+        let authorization = Authorization::bearer(neo.key.as_str()).unwrap();
+        service
+            .create_project("matrix".parse().unwrap(), neo.name)
+            .await?;
+
+        let mut get_project = || {
+            let request = Request::get("/projects/matrix")
+                .body(Body::empty())
+                .unwrap()
+                .with_header(&authorization);
+            let call = router.call(request);
+            async move {
+                let resp = call.await.unwrap();
+                assert_eq!(resp.status(), StatusCode::OK);
+                let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+                serde_json::from_slice::<project::Response>(&body).unwrap()
+            }
+        };
+
+        assert_eq!(get_project().await.last_activity, None);
+
+        let mut proxy = UserProxy::for_test(Arc::clone(&service), world.fqdn());
+
+        let before = chrono::Utc::now();
+        proxy
+            .call(
+                Request::get("/")
+                    .header("Host", format!("matrix.{}", world.fqdn()))
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        let last_activity = get_project().await.last_activity.unwrap();
+        assert!(last_activity >= before);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn proxy_resolves_unknown_backend() -> anyhow::Result<()> {
+        let world = World::new().await;
+        let service = Arc::new(GatewayService::init(world.args(), world.pool()).await);
+
+        let neo = service.create_user("neo".parse().unwrap()).await?;
+        let ready = |name: &str, target: &str| -> Project {
+            serde_json::from_value(serde_json::json!({
+                "ready": {
+                    "container": {},
+                    "service": { "name": name, "target": target, "last_check": null }
+                }
+            }))
+            .unwrap()
+        };
+        let matrix: ProjectName = "matrix".parse().unwrap();
+        let zion: ProjectName = "zion".parse().unwrap();
+        for project_name in [&matrix, &zion] {
+            service
+                .create_project(project_name.clone(), neo.name.clone())
+                .await?;
+            service
+                .update_project(project_name, &ready(&project_name.to_string(), "0.0.0.0"))
+                .await?;
+        }
+
+        // stands in for the workers: refreshes of matrix find its
+        // address, and those of zion find nothing
+        let refreshes = Arc::new(AtomicUsize::new(0));
+        let resolves = Arc::new(std::sync::atomic::AtomicBool::new(true));
+        let (sender, mut receiver) = tokio::sync::mpsc::channel::<BoxedTask>(256);
+        tokio::spawn({
+            let service = Arc::clone(&service);
+            let refreshes = Arc::clone(&refreshes);
+            let resolves = Arc::clone(&resolves);
+            let resolved = ready("matrix", "127.0.0.1");
+            async move {
+                while receiver.recv().await.is_some() {
+                    refreshes.fetch_add(1, Ordering::SeqCst);
+                    if resolves.load(Ordering::SeqCst) {
+                        service
+                            .update_project(&"matrix".parse().unwrap(), &resolved)
+                            .await
+                            .unwrap();
+                    }
+                }
+            }
+        });
+
+        let proxy = UserProxy::for_test(Arc::clone(&service), world.fqdn())
+            .with_backend_resolver(BackendResolver::new(sender));
+        let send = |project_name: &ProjectName| {
+            let mut proxy = proxy.clone();
+            let req = Request::get("/")
+                .header("Host", format!("{project_name}.{}", world.fqdn()))
+                .body(Body::empty())
+                .unwrap();
+            async move { proxy.call(req).await.unwrap() }
+        };
+
+        // the refresh finds the address and the request goes through
+        assert_eq!(service.find_project(&matrix).await?.target_ip()?, None);
+        let resp = send(&matrix).await;
+        assert_ne!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(refreshes.load(Ordering::SeqCst), 1);
+        assert_eq!(
+            service.find_project(&matrix).await?.target_ip()?,
+            Some(localhost())
+        );
+
+        // otherwise requests get a clean 503 after a while, with a
+        // single refresh queued for all of them
+        resolves.store(false, Ordering::SeqCst);
+        let start = Instant::now();
+        let (first, second) = tokio::join!(send(&zion), send(&zion));
+        assert!(start.elapsed() >= BACKEND_RESOLVE_HOLD - PROVISIONING_POLL_INTERVAL);
+        assert_eq!(refreshes.load(Ordering::SeqCst), 2);
+        for resp in [first, second] {
+            assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+            assert_eq!(resp.headers()[RETRY_AFTER], "1");
+            let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+            let error: ApiError = serde_json::from_slice(&body).unwrap();
+            assert_eq!(error.code.as_deref(), Some("project_not_ready"));
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn proxy_cold_starts_stopped_project() -> anyhow::Result<()> {
+        let world = World::new().await;
+        let service = Arc::new(GatewayService::init(world.args(), world.pool()).await);
+
+        // the project listens where the proxy expects it once started
+        let backend: IpAddr = "127.0.0.68".parse().unwrap();
+        let router = Router::new().route("/", get(|| async { "awake" }));
+        tokio::spawn(
+            axum::Server::bind(&SocketAddr::new(backend, 8000)).serve(router.into_make_service()),
+        );
+
+        let neo = service.create_user("neo".parse().unwrap()).await?;
+        let stopped: Project = serde_json::from_value(serde_json::json!({
+            "stopped": { "container": {} }
+        }))?;
+        let matrix: ProjectName = "matrix".parse().unwrap();
+        let zion: ProjectName = "zion".parse().unwrap();
+        for project_name in [&matrix, &zion] {
+            service
+                .create_project(project_name.clone(), neo.name.clone())
+                .await?;
+            service.update_project(project_name, &stopped).await?;
+        }
+
+        // stands in for the workers: starting matrix takes a moment,
+        // and zion never makes it
+        let starts = Arc::new(AtomicUsize::new(0));
+        let (sender, mut receiver) = tokio::sync::mpsc::channel::<BoxedTask>(256);
+        tokio::spawn({
+            let service = Arc::clone(&service);
+            let starts = Arc::clone(&starts);
+            let ready: Project = serde_json::from_value(serde_json::json!({
+                "ready": {
+                    "container": {},
+                    "service": { "name": "matrix", "target": backend, "last_check": null }
+                }
+            }))?;
+            async move {
+                while receiver.recv().await.is_some() {
+                    if starts.fetch_add(1, Ordering::SeqCst) == 1 {
+                        tokio::time::sleep(Duration::from_millis(500)).await;
+                        service
+                            .update_project(&"matrix".parse().unwrap(), &ready)
+                            .await
+                            .unwrap();
+                    }
+                }
+            }
+        });
+
+        let proxy = UserProxy::for_test(Arc::clone(&service), world.fqdn())
+            .with_cold_start_hold(Duration::from_secs(2))
+            .with_backend_resolver(BackendResolver::new(sender));
+        let send = |project_name: &ProjectName| {
+            let mut proxy = proxy.clone();
+            let req = Request::get("/")
+                .header("Host", format!("{project_name}.{}", world.fqdn()))
+                .body(Body::empty())
+                .unwrap();
+            async move { proxy.call(req).await.unwrap() }
+        };
+
+        // a project which does not start in time gets clients told to
+        // come back
+        let start = Instant::now();
+        let resp = send(&zion).await;
+        assert!(start.elapsed() >= Duration::from_millis(1500));
+        assert_eq!(starts.load(Ordering::SeqCst), 1);
+        assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(resp.headers()[RETRY_AFTER], "5");
+        let body = hyper::body::to_bytes(resp.into_body()).await?;
+        let error: ApiError = serde_json::from_slice(&body)?;
+        assert_eq!(error.code.as_deref(), Some("project_starting"));
+
+        // the first request to a stopped project is held until it is
+        // started, and goes through
+        let start = Instant::now();
+        let resp = send(&matrix).await;
+        assert!(start.elapsed() >= Duration::from_millis(500));
+        assert!(start.elapsed() < Duration::from_secs(2));
+        assert_eq!(starts.load(Ordering::SeqCst), 2);
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(resp.into_body()).await?;
+        assert_eq!(&body[..], b"awake");
+
+        Ok(())
+    }
+}
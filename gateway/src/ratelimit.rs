@@ -0,0 +1,118 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use shuttle_common::models::project::RateLimit;
+
+use crate::ProjectName;
+
+/// A token bucket holding up to `burst` tokens and refilled with
+/// `requests_per_second` tokens every second
+#[derive(Debug)]
+struct TokenBucket {
+    limit: RateLimit,
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    fn new(limit: RateLimit) -> Self {
+        Self {
+            tokens: limit.burst as f64,
+            limit,
+            last_refill: Instant::now(),
+        }
+    }
+
+    /// Take a token, or return how long until one is available
+    fn take(&mut self, now: Instant) -> Result<(), Duration> {
+        let rate = self.limit.requests_per_second as f64;
+        let elapsed = now.saturating_duration_since(self.last_refill);
+
+        self.tokens = (self.tokens + elapsed.as_secs_f64() * rate).min(self.limit.burst as f64);
+        self.last_refill = now;
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - self.tokens) / rate))
+        }
+    }
+}
+
+/// Per-project rate limits enforced by the user proxy. Projects
+/// without a limit are never throttled.
+#[derive(Clone, Default)]
+pub struct RateLimiter {
+    buckets: Arc<Mutex<HashMap<ProjectName, TokenBucket>>>,
+}
+
+impl RateLimiter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set or clear the limit of a project. Takes effect for the very
+    /// next request.
+    pub fn set_limit(&self, project_name: &ProjectName, limit: Option<RateLimit>) {
+        let mut buckets = self.buckets.lock().unwrap();
+        match limit {
+            Some(limit) => {
+                buckets.insert(project_name.clone(), TokenBucket::new(limit));
+            }
+            None => {
+                buckets.remove(project_name);
+            }
+        }
+    }
+
+    pub fn limit(&self, project_name: &ProjectName) -> Option<RateLimit> {
+        self.buckets
+            .lock()
+            .unwrap()
+            .get(project_name)
+            .map(|bucket| bucket.limit.clone())
+    }
+
+    /// Account for a request to `project_name`. If it is over its
+    /// limit, returns how long to wait before retrying.
+    pub fn check(&self, project_name: &ProjectName) -> Result<(), Duration> {
+        match self.buckets.lock().unwrap().get_mut(project_name) {
+            Some(bucket) => bucket.take(Instant::now()),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    #[test]
+    fn token_bucket_refills() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::new(RateLimit {
+            requests_per_second: 2,
+            burst: 3,
+        });
+        bucket.last_refill = start;
+
+        for _ in 0..3 {
+            assert!(bucket.take(start).is_ok());
+        }
+        assert_eq!(bucket.take(start), Err(Duration::from_millis(500)));
+
+        // half a second brings back one token
+        let later = start + Duration::from_millis(500);
+        assert!(bucket.take(later).is_ok());
+        assert!(bucket.take(later).is_err());
+
+        // but never more than the burst
+        let much_later = later + Duration::from_secs(60);
+        for _ in 0..3 {
+            assert!(bucket.take(much_later).is_ok());
+        }
+        assert!(bucket.take(much_later).is_err());
+    }
+}
@@ -0,0 +1,579 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use http::header::{
+    AGE, AUTHORIZATION, CACHE_CONTROL, CONTENT_LENGTH, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH,
+    LAST_MODIFIED, SET_COOKIE, VARY,
+};
+use http::{HeaderMap, HeaderName, HeaderValue, Method, Request, Response, StatusCode};
+use hyper::body::Bytes;
+use hyper::Body;
+use shuttle_common::models::project::ResponseCache as CacheConfig;
+
+use crate::{Error, ErrorKind, ProjectName};
+
+/// Largest body the cache of a project keeps
+pub const MAX_CACHED_BODY_SIZE: u64 = 1024 * 1024;
+
+/// Most responses the cache of a project can be set to keep
+pub const MAX_CACHE_ENTRIES: u32 = 10_000;
+
+/// Tells clients of a project with a cache whether their response came
+/// from it: `hit`, `revalidated` or `miss`
+pub const CACHE_STATUS_HEADER: &str = "x-shuttle-cache";
+
+/// The `Cache-Control` directives the cache acts on
+#[derive(Default)]
+struct CacheControl {
+    no_store: bool,
+    no_cache: bool,
+    private: bool,
+    max_age: Option<u64>,
+    s_maxage: Option<u64>,
+}
+
+impl CacheControl {
+    fn parse(headers: &HeaderMap) -> Self {
+        let mut cache_control = Self::default();
+
+        let directives = headers
+            .get_all(CACHE_CONTROL)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','));
+
+        for directive in directives {
+            let directive = directive.trim().to_ascii_lowercase();
+            let (name, value) = match directive.split_once('=') {
+                Some((name, value)) => (name, Some(value.trim_matches('"'))),
+                None => (directive.as_str(), None),
+            };
+
+            match name {
+                "no-store" => cache_control.no_store = true,
+                "no-cache" => cache_control.no_cache = true,
+                "private" => cache_control.private = true,
+                "max-age" => cache_control.max_age = value.and_then(|value| value.parse().ok()),
+                "s-maxage" => cache_control.s_maxage = value.and_then(|value| value.parse().ok()),
+                _ => {}
+            }
+        }
+
+        cache_control
+    }
+
+    /// How long a response is fresh for. Responses which have to be
+    /// revalidated every time are never fresh.
+    fn freshness(&self) -> Duration {
+        if self.no_cache {
+            return Duration::ZERO;
+        }
+
+        // A shared cache goes by `s-maxage` first
+        Duration::from_secs(self.s_maxage.or(self.max_age).unwrap_or_default())
+    }
+}
+
+/// A response kept for the requests it was stored for, and any other
+/// with the same values for the headers it varies on
+#[derive(Clone)]
+struct Entry {
+    vary: Vec<(HeaderName, Option<HeaderValue>)>,
+    status: StatusCode,
+    headers: HeaderMap,
+    body: Bytes,
+    stored_at: Instant,
+    fresh_for: Duration,
+}
+
+impl Entry {
+    fn matches(&self, headers: &HeaderMap) -> bool {
+        self.vary
+            .iter()
+            .all(|(name, value)| headers.get(name) == value.as_ref())
+    }
+
+    fn is_fresh(&self, now: Instant) -> bool {
+        now.saturating_duration_since(self.stored_at) < self.fresh_for
+    }
+
+    /// Take the headers of a `304 Not Modified` which revalidated this
+    /// entry, and start its freshness over
+    fn refresh(&mut self, headers: &HeaderMap) {
+        for name in [CACHE_CONTROL, ETAG, LAST_MODIFIED] {
+            if let Some(value) = headers.get(&name) {
+                self.headers.insert(name, value.clone());
+            }
+        }
+
+        self.stored_at = Instant::now();
+        self.fresh_for = CacheControl::parse(&self.headers).freshness();
+    }
+
+    fn response(&self, status: &'static str) -> Response<Body> {
+        let mut resp = Response::new(Body::from(self.body.clone()));
+        *resp.status_mut() = self.status;
+        *resp.headers_mut() = self.headers.clone();
+
+        let age = self.stored_at.elapsed().as_secs();
+        resp.headers_mut().insert(AGE, HeaderValue::from(age));
+        resp.headers_mut()
+            .insert(CACHE_STATUS_HEADER, HeaderValue::from_static(status));
+
+        resp
+    }
+}
+
+/// The responses of a project whose cache is turned on, by path and
+/// query
+struct Store {
+    config: CacheConfig,
+    entries: HashMap<String, Vec<Entry>>,
+}
+
+impl Store {
+    fn len(&self) -> usize {
+        self.entries.values().map(Vec::len).sum()
+    }
+
+    fn insert(&mut self, key: String, entry: Entry) {
+        let variants = self.entries.entry(key).or_default();
+        variants.retain(|variant| variant.vary != entry.vary);
+        variants.push(entry);
+
+        // Make room by letting go of whatever was stored the longest ago
+        while self.len() > self.config.max_entries as usize {
+            let oldest = self
+                .entries
+                .iter()
+                .flat_map(|(key, variants)| {
+                    variants
+                        .iter()
+                        .enumerate()
+                        .map(move |(index, variant)| (variant.stored_at, key, index))
+                })
+                .min()
+                .map(|(_, key, index)| (key.clone(), index));
+
+            if let Some((key, index)) = oldest {
+                let variants = self.entries.get_mut(&key).unwrap();
+                variants.remove(index);
+                if variants.is_empty() {
+                    self.entries.remove(&key);
+                }
+            } else {
+                break;
+            }
+        }
+    }
+
+    fn remove(&mut self, key: &str, headers: &HeaderMap) {
+        if let Some(variants) = self.entries.get_mut(key) {
+            variants.retain(|variant| !variant.matches(headers));
+            if variants.is_empty() {
+                self.entries.remove(key);
+            }
+        }
+    }
+}
+
+/// Responses of projects which turned caching on, kept by the user
+/// proxy to answer `GET`s without going to the project. What is kept
+/// and for how long follows the `Cache-Control` of the responses, as a
+/// shared cache would. Projects without a cache are always gone to.
+#[derive(Clone, Default)]
+pub struct ResponseCache {
+    stores: Arc<Mutex<HashMap<ProjectName, Store>>>,
+}
+
+impl ResponseCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Turn the cache of a project on or off. Responses kept under an
+    /// old config are let go of either way.
+    pub fn set_config(&self, project_name: &ProjectName, config: Option<CacheConfig>) {
+        let mut stores = self.stores.lock().unwrap();
+        match config {
+            Some(config) => {
+                stores.insert(
+                    project_name.clone(),
+                    Store {
+                        config,
+                        entries: HashMap::new(),
+                    },
+                );
+            }
+            None => {
+                stores.remove(project_name);
+            }
+        }
+    }
+
+    pub fn config(&self, project_name: &ProjectName) -> Option<CacheConfig> {
+        self.stores
+            .lock()
+            .unwrap()
+            .get(project_name)
+            .map(|store| store.config.clone())
+    }
+
+    /// Let go of every response kept for a project, keeping its cache on
+    pub fn purge(&self, project_name: &ProjectName) {
+        if let Some(store) = self.stores.lock().unwrap().get_mut(project_name) {
+            store.entries.clear();
+        }
+    }
+
+    /// Number of responses kept for a project
+    pub fn len(&self, project_name: &ProjectName) -> usize {
+        self.stores
+            .lock()
+            .unwrap()
+            .get(project_name)
+            .map(Store::len)
+            .unwrap_or_default()
+    }
+
+    /// Answer `req` to `project_name` from its cache when a fresh
+    /// response is kept for it, or else with `upstream`. Stale responses
+    /// with an `ETag` or `Last-Modified` are revalidated with a
+    /// conditional request rather than fetched again.
+    pub async fn serve<F, Fut>(
+        &self,
+        project_name: &ProjectName,
+        mut req: Request<Body>,
+        upstream: F,
+    ) -> Result<Response<Body>, Error>
+    where
+        F: FnOnce(Request<Body>) -> Fut,
+        Fut: Future<Output = Result<Response<Body>, Error>>,
+    {
+        if self.config(project_name).is_none() || !is_cacheable_request(&req) {
+            return upstream(req).await;
+        }
+
+        let key = req
+            .uri()
+            .path_and_query()
+            .map(|path_and_query| path_and_query.as_str())
+            .unwrap_or("/")
+            .to_string();
+        let request_cache_control = CacheControl::parse(req.headers());
+
+        let cached = self.lookup(project_name, &key, req.headers());
+        if let Some(entry) = &cached {
+            let must_revalidate =
+                request_cache_control.no_cache || request_cache_control.max_age == Some(0);
+            if !must_revalidate && entry.is_fresh(Instant::now()) {
+                return Ok(entry.response("hit"));
+            }
+
+            if let Some(etag) = entry.headers.get(ETAG) {
+                req.headers_mut().insert(IF_NONE_MATCH, etag.clone());
+            }
+            if let Some(last_modified) = entry.headers.get(LAST_MODIFIED) {
+                req.headers_mut()
+                    .insert(IF_MODIFIED_SINCE, last_modified.clone());
+            }
+        }
+
+        let request_headers = req.headers().clone();
+        let resp = upstream(req).await?;
+
+        if resp.status() == StatusCode::NOT_MODIFIED {
+            if let Some(mut entry) = cached {
+                entry.refresh(resp.headers());
+                let resp = entry.response("revalidated");
+                self.store(project_name, key, entry);
+                return Ok(resp);
+            }
+            return Ok(resp);
+        }
+
+        let vary = match storable_vary(&resp, &request_headers) {
+            Some(vary) => vary,
+            None => {
+                self.forget(project_name, &key, &request_headers);
+                return Ok(with_cache_status(resp, "miss"));
+            }
+        };
+
+        let (parts, body) = resp.into_parts();
+        let body = hyper::body::to_bytes(body)
+            .await
+            .map_err(|err| Error::source(ErrorKind::ProjectUnavailable, err))?;
+
+        let entry = Entry {
+            vary,
+            status: parts.status,
+            headers: parts.headers.clone(),
+            body: body.clone(),
+            stored_at: Instant::now(),
+            fresh_for: CacheControl::parse(&parts.headers).freshness(),
+        };
+        self.store(project_name, key, entry);
+
+        Ok(with_cache_status(
+            Response::from_parts(parts, Body::from(body)),
+            "miss",
+        ))
+    }
+
+    fn lookup(&self, project_name: &ProjectName, key: &str, headers: &HeaderMap) -> Option<Entry> {
+        self.stores
+            .lock()
+            .unwrap()
+            .get(project_name)?
+            .entries
+            .get(key)?
+            .iter()
+            .find(|entry| entry.matches(headers))
+            .cloned()
+    }
+
+    fn store(&self, project_name: &ProjectName, key: String, entry: Entry) {
+        if let Some(store) = self.stores.lock().unwrap().get_mut(project_name) {
+            store.insert(key, entry);
+        }
+    }
+
+    fn forget(&self, project_name: &ProjectName, key: &str, headers: &HeaderMap) {
+        if let Some(store) = self.stores.lock().unwrap().get_mut(project_name) {
+            store.remove(key, headers);
+        }
+    }
+}
+
+/// Check the config of a project's cache before it is stored
+pub fn validate_config(config: &CacheConfig) -> Result<(), Error> {
+    if config.max_entries == 0 || config.max_entries > MAX_CACHE_ENTRIES {
+        return Err(Error::from_kind(ErrorKind::InvalidResponseCache));
+    }
+
+    Ok(())
+}
+
+/// Only plain `GET`s are answered from the cache. Requests with their
+/// own credentials or validators, or which ask for nothing to be
+/// stored, always go to the project.
+fn is_cacheable_request(req: &Request<Body>) -> bool {
+    req.method() == Method::GET
+        && !req.headers().contains_key(AUTHORIZATION)
+        && !req.headers().contains_key(IF_NONE_MATCH)
+        && !req.headers().contains_key(IF_MODIFIED_SINCE)
+        && !CacheControl::parse(req.headers()).no_store
+}
+
+/// The request headers `resp` varies on, with their values in the
+/// request it answered, if it can be kept at all
+fn storable_vary(
+    resp: &Response<Body>,
+    request_headers: &HeaderMap,
+) -> Option<Vec<(HeaderName, Option<HeaderValue>)>> {
+    if resp.status() != StatusCode::OK || resp.headers().contains_key(SET_COOKIE) {
+        return None;
+    }
+
+    let cache_control = CacheControl::parse(resp.headers());
+    if cache_control.no_store || cache_control.private {
+        return None;
+    }
+
+    let has_validators =
+        resp.headers().contains_key(ETAG) || resp.headers().contains_key(LAST_MODIFIED);
+    if cache_control.freshness().is_zero() && !has_validators {
+        return None;
+    }
+
+    // Bodies of unknown length are streamed on rather than held on to
+    let size = resp
+        .headers()
+        .get(CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<u64>().ok())?;
+    if size > MAX_CACHED_BODY_SIZE {
+        return None;
+    }
+
+    let mut vary = Vec::new();
+    let names = resp
+        .headers()
+        .get_all(VARY)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .filter(|name| !name.is_empty());
+    for name in names {
+        if name == "*" {
+            return None;
+        }
+        let name = HeaderName::from_bytes(name.as_bytes()).ok()?;
+        let value = request_headers.get(&name).cloned();
+        vary.push((name, value));
+    }
+
+    Some(vary)
+}
+
+fn with_cache_status(mut resp: Response<Body>, status: &'static str) -> Response<Body> {
+    resp.headers_mut()
+        .insert(CACHE_STATUS_HEADER, HeaderValue::from_static(status));
+    resp
+}
+
+#[cfg(test)]
+pub mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+    use crate::tests::assert_err_kind;
+
+    fn get(uri: &str) -> Request<Body> {
+        Request::get(uri).body(Body::empty()).unwrap()
+    }
+
+    async fn body(resp: Response<Body>) -> String {
+        let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+        String::from_utf8(body.to_vec()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn response_cache() {
+        let cache = ResponseCache::new();
+        let matrix: ProjectName = "matrix".parse().unwrap();
+        let hits = AtomicUsize::new(0);
+
+        let backend = |cache_control: &'static str| {
+            let hits = &hits;
+            move |req: Request<Body>| async move {
+                hits.fetch_add(1, Ordering::SeqCst);
+
+                let resp = if req.headers().get(IF_NONE_MATCH)
+                    == Some(&HeaderValue::from_static("\"v1\""))
+                {
+                    Response::builder()
+                        .status(StatusCode::NOT_MODIFIED)
+                        .header(CACHE_CONTROL, cache_control)
+                        .body(Body::empty())
+                } else {
+                    Response::builder()
+                        .header(CACHE_CONTROL, cache_control)
+                        .header(ETAG, "\"v1\"")
+                        .header(CONTENT_LENGTH, 5)
+                        .header(VARY, "accept-language")
+                        .body(Body::from("hello"))
+                };
+
+                Ok::<_, Error>(resp.unwrap())
+            }
+        };
+
+        // nothing is kept for projects without a cache
+        cache
+            .serve(&matrix, get("/"), backend("max-age=60"))
+            .await
+            .unwrap();
+        cache
+            .serve(&matrix, get("/"), backend("max-age=60"))
+            .await
+            .unwrap();
+        assert_eq!(hits.load(Ordering::SeqCst), 2);
+
+        assert_err_kind!(
+            validate_config(&CacheConfig { max_entries: 0 }),
+            ErrorKind::InvalidResponseCache
+        );
+        cache.set_config(&matrix, Some(CacheConfig { max_entries: 10 }));
+
+        // a second identical request never reaches the backend
+        let resp = cache
+            .serve(&matrix, get("/"), backend("max-age=60"))
+            .await
+            .unwrap();
+        assert_eq!(resp.headers()[CACHE_STATUS_HEADER], "miss");
+        assert_eq!(body(resp).await, "hello");
+
+        let resp = cache
+            .serve(&matrix, get("/"), backend("max-age=60"))
+            .await
+            .unwrap();
+        assert_eq!(resp.headers()[CACHE_STATUS_HEADER], "hit");
+        assert!(resp.headers().contains_key(AGE));
+        assert_eq!(body(resp).await, "hello");
+        assert_eq!(hits.load(Ordering::SeqCst), 3);
+
+        // another query, or another value of a header varied on, is
+        // another response
+        cache
+            .serve(&matrix, get("/?page=2"), backend("max-age=60"))
+            .await
+            .unwrap();
+        let req = Request::get("/")
+            .header("accept-language", "fr")
+            .body(Body::empty())
+            .unwrap();
+        cache
+            .serve(&matrix, req, backend("max-age=60"))
+            .await
+            .unwrap();
+        assert_eq!(hits.load(Ordering::SeqCst), 5);
+        assert_eq!(cache.len(&matrix), 3);
+
+        // a client asking for a fresh copy has the kept one revalidated
+        let req = Request::get("/")
+            .header(CACHE_CONTROL, "no-cache")
+            .body(Body::empty())
+            .unwrap();
+        let resp = cache
+            .serve(&matrix, req, backend("max-age=60"))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(resp.headers()[CACHE_STATUS_HEADER], "revalidated");
+        assert_eq!(body(resp).await, "hello");
+        assert_eq!(hits.load(Ordering::SeqCst), 6);
+
+        // neither private nor unstorable responses are kept
+        cache.purge(&matrix);
+        for cache_control in ["private, max-age=60", "no-store"] {
+            cache
+                .serve(&matrix, get("/"), backend(cache_control))
+                .await
+                .unwrap();
+        }
+        assert_eq!(cache.len(&matrix), 0);
+
+        // neither are responses to anything but a GET
+        let req = Request::post("/").body(Body::empty()).unwrap();
+        cache
+            .serve(&matrix, req, backend("max-age=60"))
+            .await
+            .unwrap();
+        assert_eq!(cache.len(&matrix), 0);
+
+        // the oldest responses make room for new ones
+        cache.set_config(&matrix, Some(CacheConfig { max_entries: 2 }));
+        for uri in ["/a", "/b", "/c"] {
+            cache
+                .serve(&matrix, get(uri), backend("max-age=60"))
+                .await
+                .unwrap();
+        }
+        assert_eq!(cache.len(&matrix), 2);
+        let before = hits.load(Ordering::SeqCst);
+        cache
+            .serve(&matrix, get("/c"), backend("max-age=60"))
+            .await
+            .unwrap();
+        cache
+            .serve(&matrix, get("/a"), backend("max-age=60"))
+            .await
+            .unwrap();
+        assert_eq!(hits.load(Ordering::SeqCst), before + 1);
+    }
+}
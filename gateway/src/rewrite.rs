@@ -0,0 +1,158 @@
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+use http::header::{HeaderName, HeaderValue, HOST};
+use http::HeaderMap;
+use shuttle_common::models::error::ErrorKind;
+use shuttle_common::models::project::{HeaderRuleSet, HeaderRules};
+
+use crate::proxy::X_SHUTTLE_PROJECT;
+use crate::{Error, ProjectName};
+
+/// Changes made to the headers of one side of an exchange
+#[derive(Debug, Default)]
+pub struct HeaderRewrite {
+    add: Vec<(HeaderName, HeaderValue)>,
+    set: Vec<(HeaderName, HeaderValue)>,
+    remove: Vec<HeaderName>,
+}
+
+impl HeaderRewrite {
+    /// Check every name and value of `rules`. Headers the gateway
+    /// relies on to route requests cannot be touched.
+    pub fn new(rules: &HeaderRuleSet) -> Result<Self, Error> {
+        let parse_name = |name: &str| match HeaderName::from_bytes(name.as_bytes()) {
+            Ok(name) if name != HOST && name != *X_SHUTTLE_PROJECT => Ok(name),
+            _ => Err(Error::custom(
+                ErrorKind::InvalidHeaderRule,
+                format!("invalid header name: {name}"),
+            )),
+        };
+        let parse = |(name, value): (&String, &String)| {
+            let value = HeaderValue::from_str(value).map_err(|_| {
+                Error::custom(
+                    ErrorKind::InvalidHeaderRule,
+                    format!("invalid value for header {name}"),
+                )
+            })?;
+            Ok((parse_name(name)?, value))
+        };
+
+        Ok(Self {
+            add: rules.add.iter().map(parse).collect::<Result<_, Error>>()?,
+            set: rules.set.iter().map(parse).collect::<Result<_, Error>>()?,
+            remove: rules
+                .remove
+                .iter()
+                .map(|name| parse_name(name))
+                .collect::<Result<_, Error>>()?,
+        })
+    }
+
+    /// Remove, then set and finally add headers to `headers`
+    pub fn apply(&self, headers: &mut HeaderMap) {
+        for name in &self.remove {
+            headers.remove(name);
+        }
+        for (name, value) in &self.set {
+            headers.insert(name.clone(), value.clone());
+        }
+        for (name, value) in &self.add {
+            headers.append(name.clone(), value.clone());
+        }
+    }
+}
+
+/// The header rules of a project, ready to be applied by the proxy
+#[derive(Debug)]
+pub struct HeaderRewrites {
+    pub rules: HeaderRules,
+    pub request: HeaderRewrite,
+    pub response: HeaderRewrite,
+}
+
+impl HeaderRewrites {
+    pub fn new(rules: HeaderRules) -> Result<Self, Error> {
+        Ok(Self {
+            request: HeaderRewrite::new(&rules.request)?,
+            response: HeaderRewrite::new(&rules.response)?,
+            rules,
+        })
+    }
+}
+
+/// Per-project header rules applied by the user proxy. Projects
+/// without rules have their headers passed through untouched.
+#[derive(Clone, Default)]
+pub struct HeaderRewriter {
+    rewrites: Arc<RwLock<HashMap<ProjectName, Arc<HeaderRewrites>>>>,
+}
+
+impl HeaderRewriter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set or clear the rules of a project. Takes effect for the very
+    /// next request.
+    pub fn set_rewrites(&self, project_name: &ProjectName, rewrites: Option<HeaderRewrites>) {
+        let mut table = self.rewrites.write().unwrap();
+        match rewrites {
+            Some(rewrites) => {
+                table.insert(project_name.clone(), Arc::new(rewrites));
+            }
+            None => {
+                table.remove(project_name);
+            }
+        }
+    }
+
+    pub fn rewrites(&self, project_name: &ProjectName) -> Option<Arc<HeaderRewrites>> {
+        self.rewrites.read().unwrap().get(project_name).cloned()
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use std::collections::BTreeMap;
+
+    use super::*;
+
+    #[test]
+    fn header_rewrite() {
+        let rewrite = HeaderRewrite::new(&HeaderRuleSet {
+            add: BTreeMap::from([("vary".to_string(), "origin".to_string())]),
+            set: BTreeMap::from([("x-frame-options".to_string(), "DENY".to_string())]),
+            remove: vec!["server".to_string()],
+        })
+        .unwrap();
+
+        let mut headers = HeaderMap::new();
+        headers.insert("server", HeaderValue::from_static("upstream"));
+        headers.insert("vary", HeaderValue::from_static("accept"));
+        headers.insert("x-frame-options", HeaderValue::from_static("SAMEORIGIN"));
+
+        rewrite.apply(&mut headers);
+
+        assert!(headers.get("server").is_none());
+        assert_eq!(headers.get_all("vary").iter().count(), 2);
+        assert_eq!(headers.get("x-frame-options").unwrap(), "DENY");
+
+        for rules in [
+            HeaderRuleSet {
+                remove: vec!["not a header".to_string()],
+                ..Default::default()
+            },
+            HeaderRuleSet {
+                add: BTreeMap::from([("x-bad".to_string(), "line\nbreak".to_string())]),
+                ..Default::default()
+            },
+            HeaderRuleSet {
+                set: BTreeMap::from([("host".to_string(), "elsewhere.com".to_string())]),
+                ..Default::default()
+            },
+        ] {
+            assert!(HeaderRewrite::new(&rules).is_err());
+        }
+    }
+}
@@ -0,0 +1,264 @@
+use std::sync::Arc;
+
+use futures::prelude::*;
+use shuttle_common::models::project::{RolloutState, RolloutStatus};
+use tokio::sync::mpsc::Sender;
+use tokio::sync::RwLock;
+use tracing::{error, info, warn};
+
+use crate::project::Project;
+use crate::service::GatewayService;
+use crate::task::{self, BoxedTask};
+use crate::{Error, ErrorKind, ProjectName};
+
+/// A rollout halts once this many projects in a row failed to be
+/// recreated
+pub const MAX_CONSECUTIVE_ROLLOUT_FAILURES: usize = 3;
+
+/// Upper bound on the number of projects recreated at the same time
+pub const MAX_ROLLOUT_CONCURRENCY: usize = 4;
+
+/// Moves running projects onto a new deployer image, recreating their
+/// containers a few at a time
+#[derive(Clone, Default)]
+pub struct Rollouts {
+    current: Arc<RwLock<Option<RolloutStatus>>>,
+}
+
+impl Rollouts {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The progress of the last rollout, if there was one
+    pub async fn status(&self) -> Option<RolloutStatus> {
+        self.current.read().await.clone()
+    }
+
+    /// Start moving every running project which is not already on
+    /// `image` onto it. Only one rollout can be running at a time.
+    pub async fn start(
+        &self,
+        service: Arc<GatewayService>,
+        sender: Sender<BoxedTask>,
+        image: String,
+        concurrency: usize,
+    ) -> Result<RolloutStatus, Error> {
+        let mut current = self.current.write().await;
+        if matches!(current.as_ref(), Some(status) if status.state == RolloutState::Running) {
+            return Err(Error::custom(
+                ErrorKind::InvalidOperation,
+                "a rollout is already in progress",
+            ));
+        }
+
+        let projects = projects_to_migrate(&service, &image).await?;
+
+        info!(%image, projects = projects.len(), "starting rollout");
+
+        let status = RolloutStatus {
+            image: image.clone(),
+            state: RolloutState::Running,
+            started_at: chrono::Utc::now(),
+            total: projects.len(),
+            migrated: Vec::new(),
+            failed: Vec::new(),
+        };
+        *current = Some(status.clone());
+
+        let concurrency = concurrency.clamp(1, MAX_ROLLOUT_CONCURRENCY);
+        tokio::spawn(
+            self.clone()
+                .run(service, sender, image, projects, concurrency),
+        );
+
+        Ok(status)
+    }
+
+    async fn run(
+        self,
+        service: Arc<GatewayService>,
+        sender: Sender<BoxedTask>,
+        image: String,
+        projects: Vec<ProjectName>,
+        concurrency: usize,
+    ) {
+        let mut results = stream::iter(projects)
+            .map(|project_name| {
+                let service = service.clone();
+                let sender = sender.clone();
+                let image = image.clone();
+                async move {
+                    let res = migrate(&service, &sender, &project_name, image).await;
+                    (project_name, res)
+                }
+            })
+            .buffer_unordered(concurrency);
+
+        let mut consecutive_failures = 0;
+        while let Some((project_name, res)) = results.next().await {
+            let mut current = self.current.write().await;
+            let status = current.as_mut().expect("a rollout to be in progress");
+
+            match res {
+                Ok(()) => {
+                    info!(%project_name, "project moved onto the new image");
+                    consecutive_failures = 0;
+                    status.migrated.push(project_name.to_string());
+                }
+                Err(err) => {
+                    warn!(%project_name, error = %err, "failed to move project onto the new image");
+                    consecutive_failures += 1;
+                    status.failed.push(project_name.to_string());
+
+                    if consecutive_failures >= MAX_CONSECUTIVE_ROLLOUT_FAILURES {
+                        error!(
+                            image = %status.image,
+                            "halting rollout after {consecutive_failures} failures in a row"
+                        );
+                        status.state = RolloutState::Halted;
+                        return;
+                    }
+                }
+            }
+        }
+
+        if let Some(status) = self.current.write().await.as_mut() {
+            info!(image = %status.image, "rollout completed");
+            status.state = RolloutState::Completed;
+        }
+    }
+}
+
+/// The projects which are running on another image than `image`
+async fn projects_to_migrate(
+    service: &GatewayService,
+    image: &str,
+) -> Result<Vec<ProjectName>, Error> {
+    let mut projects = Vec::new();
+
+    for (project_name, _) in service.iter_projects().await? {
+        let project = service.find_project(&project_name).await?;
+        if !project.is_ready() {
+            continue;
+        }
+
+        let current_image = project
+            .container()
+            .and_then(|container| container.config)
+            .and_then(|config| config.image);
+        if current_image.as_deref() != Some(image) {
+            projects.push(project_name);
+        }
+    }
+
+    Ok(projects)
+}
+
+async fn migrate(
+    service: &Arc<GatewayService>,
+    sender: &Sender<BoxedTask>,
+    project_name: &ProjectName,
+    image: String,
+) -> Result<(), Error> {
+    service
+        .new_task()
+        .project(project_name.clone())
+        .and_then(task::recreate_with_image(image))
+        .send(sender)
+        .await?
+        .await;
+
+    match service.find_project(project_name).await? {
+        Project::Ready(_) => Ok(()),
+        project => Err(Error::custom(
+            ErrorKind::Internal,
+            format!("project ended up {}", project.state()),
+        )),
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::tests::World;
+    use crate::worker::Worker;
+    use crate::DockerContext;
+
+    #[tokio::test]
+    async fn rollout_recreates_projects_with_new_image() -> anyhow::Result<()> {
+        let world = World::new().await;
+        let service = Arc::new(GatewayService::init(world.args(), world.pool()).await);
+
+        let worker = Worker::new();
+        let sender = worker.sender();
+        tokio::spawn(worker.start());
+
+        let neo = service.create_user("neo".parse().unwrap()).await?;
+        let projects: Vec<ProjectName> = ["matrix", "reloaded", "revolutions"]
+            .into_iter()
+            .map(|name| name.parse().unwrap())
+            .collect();
+
+        for project_name in &projects {
+            service
+                .create_project(project_name.clone(), neo.name.clone())
+                .await?;
+            service
+                .new_task()
+                .project(project_name.clone())
+                .send(&sender)
+                .await?
+                .await;
+            assert!(service.find_project(project_name).await?.is_ready());
+        }
+
+        // The same image under another name is enough to tell the old
+        // and new containers apart
+        let docker = world.context();
+        let image = docker
+            .docker()
+            .inspect_image(&docker.container_settings().image)
+            .await?
+            .id
+            .expect("the image to have an id");
+
+        let status = service
+            .rollouts()
+            .start(service.clone(), sender, image.clone(), 2)
+            .await?;
+        assert_eq!(status.state, RolloutState::Running);
+        assert_eq!(status.total, 3);
+
+        let status = loop {
+            let status = service.rollouts().status().await.unwrap();
+            if status.state != RolloutState::Running {
+                break status;
+            }
+            tokio::time::sleep(Duration::from_secs(1)).await;
+        };
+
+        assert_eq!(status.state, RolloutState::Completed);
+        assert!(status.failed.is_empty());
+        assert_eq!(status.migrated.len(), 3);
+
+        for project_name in &projects {
+            let project = service.find_project(project_name).await?;
+            assert!(project.is_ready());
+            assert_eq!(
+                project
+                    .container()
+                    .and_then(|container| container.config)
+                    .and_then(|config| config.image),
+                Some(image.clone())
+            );
+        }
+
+        // Nothing is left to move onto the image
+        assert!(projects_to_migrate(&service, &image).await?.is_empty());
+
+        Ok(())
+    }
+}
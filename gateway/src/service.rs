@@ -1,12 +1,19 @@
-use std::collections::HashMap;
-use std::sync::Arc;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
 
 use axum::body::Body;
 use axum::headers::{Authorization, HeaderMapExt};
 use axum::http::Request;
 use axum::response::Response;
-use bollard::network::ListNetworksOptions;
+use bollard::container::{RemoveContainerOptions, StopContainerOptions};
+use bollard::errors::Error as DockerError;
+use bollard::models::{RestartPolicy, RestartPolicyNameEnum};
+use bollard::network::{CreateNetworkOptions, ListNetworksOptions};
 use bollard::{Docker, API_DEFAULT_VERSION};
+use chrono::{DateTime, Utc};
 use fqdn::Fqdn;
 use http::HeaderValue;
 use hyper::client::connect::dns::GaiResolver;
@@ -16,28 +23,154 @@ use hyper_reverse_proxy::ReverseProxy;
 use once_cell::sync::Lazy;
 use opentelemetry::global;
 use opentelemetry_http::HeaderInjector;
+use shuttle_common::models::deployment::PendingDeploy;
+use shuttle_common::models::project::{
+    Canary, ConnectionLimit, DeploymentTimeout, Event, EventKind, FeatureFlag, HeaderRules,
+    IpFilter, MaintenancePage, RateLimit, ResourceLimits, ResponseCache as ResponseCacheConfig,
+    Route, RouteKind, UpstreamProtocol,
+};
+use shuttle_common::models::user;
 use sqlx::error::DatabaseError;
 use sqlx::migrate::Migrator;
-use sqlx::sqlite::SqlitePool;
+use sqlx::pool::PoolConnection;
+use sqlx::sqlite::{SqliteConnection, SqlitePool, SqliteRow};
 use sqlx::types::Json as SqlxJson;
-use sqlx::{query, Error as SqlxError, Row};
-use tracing::{debug, Span};
+use sqlx::{query, Connection, Error as SqlxError, Row, Sqlite};
+use tokio::sync::mpsc::Sender;
+use tracing::{debug, info, trace, warn, Span};
 use tracing_opentelemetry::OpenTelemetrySpanExt;
+use uuid::Uuid;
 
 use crate::acme::CustomDomain;
-use crate::args::ContextArgs;
-use crate::auth::{Key, Permissions, ScopedUser, User};
-use crate::project::Project;
-use crate::task::{BoxedTask, TaskBuilder};
-use crate::worker::TaskRouter;
-use crate::{AccountName, DockerContext, Error, ErrorKind, ProjectDetails, ProjectName};
+use crate::activity::ActivityTracker;
+use crate::args::{ContainerRestart, ContextArgs, DeployConcurrency};
+use crate::auth::{AccountTier, Impersonation, Key, KeyScope, Permissions, ScopedUser, User};
+use crate::backup::{self, Backup};
+use crate::basicauth::{BasicAuth, BasicAuthGate};
+use crate::cache::ProjectCache;
+use crate::canary::{Canaries, CanarySplit};
+use crate::connlimit::ConnectionLimiter;
+use crate::counters::{PlatformCounters, TrafficCounters};
+use crate::deploy::{DeployGuard, DeployLocks};
+use crate::drain::ConnectionDrainer;
+use crate::env::{self, EnvCipher};
+use crate::flags::FeatureFlags;
+use crate::ipfilter::{IpFilters, IpRules};
+use crate::jwt::JwtVerifier;
+use crate::project::{mounted_volume, Project, ProjectCreating, ProjectDestroyed};
+use crate::ratelimit::RateLimiter;
+use crate::respcache::{self, ResponseCache};
+use crate::rewrite::{HeaderRewriter, HeaderRewrites};
+use crate::rollout::Rollouts;
+use crate::task::{self, BoxedTask, TaskBuilder};
+use crate::warm::WarmPool;
+use crate::webhook::{DeliverWebhook, Webhook};
+use crate::worker::{TaskRouter, TaskTracker};
+use crate::{docker_op, AccountName, DockerContext, Error, ErrorKind, ProjectDetails, ProjectName};
 
 pub static MIGRATIONS: Migrator = sqlx::migrate!("./migrations");
+
+/// Check that `pool` has every table and column the migrations give a
+/// fresh database, so that a schema which was only partly migrated or
+/// changed by hand fails startup rather than the queries relying on it
+pub async fn check_schema(pool: &SqlitePool) -> Result<(), Error> {
+    let mut fresh = SqliteConnection::connect("sqlite::memory:").await?;
+    MIGRATIONS
+        .run(&mut fresh)
+        .await
+        .map_err(|err| Error::source(ErrorKind::Internal, err))?;
+
+    let tables = query(
+        "SELECT name FROM sqlite_master WHERE type = 'table' AND name NOT LIKE 'sqlite_%' ORDER BY name",
+    )
+    .fetch_all(&mut fresh)
+    .await?;
+
+    let columns = |row: &SqliteRow| row.get::<String, _>("name");
+    let mut missing = Vec::new();
+    for table in tables {
+        let table: String = table.get("name");
+
+        let found: BTreeSet<_> = query("SELECT name FROM pragma_table_info(?1)")
+            .bind(&table)
+            .fetch_all(pool)
+            .await?
+            .iter()
+            .map(columns)
+            .collect();
+        if found.is_empty() {
+            missing.push(table);
+            continue;
+        }
+
+        let expected = query("SELECT name FROM pragma_table_info(?1)")
+            .bind(&table)
+            .fetch_all(&mut fresh)
+            .await?;
+        for column in expected.iter().map(columns) {
+            if !found.contains(&column) {
+                missing.push(format!("{table}.{column}"));
+            }
+        }
+    }
+
+    if missing.is_empty() {
+        Ok(())
+    } else {
+        Err(Error::custom(
+            ErrorKind::Internal,
+            format!(
+                "the database schema does not match its migrations, it is missing {}",
+                missing.join(", ")
+            ),
+        ))
+    }
+}
+/// The id the account's own key is listed under
+pub const PRIMARY_KEY_ID: &str = "primary";
+
+const MAX_PROJECT_NAME_SUGGESTIONS: usize = 3;
+const PROJECT_NAME_SUFFIXES: [&str; 3] = ["app", "api", "rs"];
+
 static PROXY_CLIENT: Lazy<ReverseProxy<HttpConnector<GaiResolver>>> =
     Lazy::new(|| ReverseProxy::new(Client::new()));
 
+/// How long clients are asked to wait when the database is too busy
+/// to hand out a connection
+const DB_BUSY_RETRY_AFTER: Duration = Duration::from_secs(1);
+
+/// How long getting a connection to the database can take before it
+/// is reported as pressure on the pool
+const DB_SLOW_ACQUIRE: Duration = Duration::from_millis(100);
+
+/// How many seconds project containers are given to shut down once
+/// asked to stop, unless configured otherwise
+pub const DEFAULT_STOP_TIMEOUT_SECS: i64 = 30;
+
+/// How long a super user can act as another account with a single
+/// impersonation token
+pub const IMPERSONATION_TTL: Duration = Duration::from_secs(15 * 60);
+
+/// How long the old name of a renamed project keeps redirecting to
+/// the new one, when asked to
+pub const RENAME_REDIRECT_GRACE: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+/// How long the lease a task takes on a project lasts without being
+/// renewed, after which another gateway node may act on the project
+pub const PROJECT_LEASE_TTL: Duration = Duration::from_secs(5 * 60);
+
+/// Largest maintenance page a project can have
+const MAX_MAINTENANCE_PAGE_SIZE: usize = 64 * 1024;
+
 impl From<SqlxError> for Error {
     fn from(err: SqlxError) -> Self {
+        if let SqlxError::PoolTimedOut = err {
+            // Fail fast rather than leave requests hanging on the database
+            warn!("timed out waiting for a database connection");
+            return Self::source(ErrorKind::ServiceUnavailable, err)
+                .with_retry_after(DB_BUSY_RETRY_AFTER);
+        }
+
         debug!("internal SQLx error: {err}");
         Self::source(ErrorKind::Internal, err)
     }
@@ -49,7 +182,11 @@ pub struct ContainerSettingsBuilder<'d> {
     image: Option<String>,
     provisioner: Option<String>,
     network_name: Option<String>,
+    create_network: bool,
     fqdn: Option<String>,
+    max_running_projects: Option<usize>,
+    restart_policy: RestartPolicy,
+    stop_timeout_secs: i64,
 }
 
 impl<'d> ContainerSettingsBuilder<'d> {
@@ -60,24 +197,51 @@ impl<'d> ContainerSettingsBuilder<'d> {
             image: None,
             provisioner: None,
             network_name: None,
+            create_network: false,
             fqdn: None,
+            max_running_projects: None,
+            restart_policy: RestartPolicy::default(),
+            stop_timeout_secs: DEFAULT_STOP_TIMEOUT_SECS,
         }
     }
 
-    pub async fn from_args(self, args: &ContextArgs) -> ContainerSettings {
+    pub async fn from_args(self, args: &ContextArgs) -> Result<ContainerSettings, Error> {
         let ContextArgs {
             prefix,
             network_name,
+            create_network,
             provisioner_host,
             image,
             proxy_fqdn,
+            max_running_projects,
+            restart_policy,
+            restart_max_retries,
+            stop_timeout_secs,
             ..
         } = args;
+        let restart_policy = match restart_policy {
+            ContainerRestart::Never => RestartPolicy {
+                name: Some(RestartPolicyNameEnum::NO),
+                maximum_retry_count: None,
+            },
+            ContainerRestart::OnFailure => RestartPolicy {
+                name: Some(RestartPolicyNameEnum::ON_FAILURE),
+                maximum_retry_count: Some(*restart_max_retries),
+            },
+            ContainerRestart::Always => RestartPolicy {
+                name: Some(RestartPolicyNameEnum::ALWAYS),
+                maximum_retry_count: None,
+            },
+        };
         self.prefix(prefix)
             .image(image)
             .provisioner_host(provisioner_host)
             .network_name(network_name)
+            .create_network(*create_network)
             .fqdn(proxy_fqdn)
+            .max_running_projects(*max_running_projects)
+            .restart_policy(restart_policy)
+            .stop_timeout_secs(*stop_timeout_secs)
             .build()
             .await
     }
@@ -102,22 +266,45 @@ impl<'d> ContainerSettingsBuilder<'d> {
         self
     }
 
+    /// Create the network when it does not exist rather than failing
+    pub fn create_network(mut self, create_network: bool) -> Self {
+        self.create_network = create_network;
+        self
+    }
+
+    /// Cap how many project containers can run at once, `None` for no cap
+    pub fn max_running_projects(mut self, max: Option<usize>) -> Self {
+        self.max_running_projects = max;
+        self
+    }
+
+    /// What docker does when the container of a project exits
+    pub fn restart_policy(mut self, policy: RestartPolicy) -> Self {
+        self.restart_policy = policy;
+        self
+    }
+
+    /// How long the container of a project is given to shut down once
+    /// asked to stop, before it is killed
+    pub fn stop_timeout_secs(mut self, secs: i64) -> Self {
+        self.stop_timeout_secs = secs;
+        self
+    }
+
     pub fn fqdn<S: ToString>(mut self, fqdn: S) -> Self {
         self.fqdn = Some(fqdn.to_string().trim_end_matches('.').to_string());
         self
     }
 
-    /// Resolves the Docker network ID for the given network name.
-    ///
-    /// # Panics
-    /// If no such Docker network can be found.
-    async fn resolve_network_id(&self, network_name: &str) -> String {
-        self.docker
+    /// Resolves the Docker network ID for the given network name,
+    /// creating the network first if allowed to.
+    async fn resolve_network_id(&self, network_name: &str) -> Result<String, Error> {
+        let existing = self
+            .docker
             .list_networks(Some(ListNetworksOptions {
                 filters: HashMap::from([("name", vec![network_name])]),
             }))
-            .await
-            .unwrap()
+            .await?
             .into_iter()
             .find_map(|network| {
                 network.name.as_ref().and_then(|name| {
@@ -127,28 +314,236 @@ impl<'d> ContainerSettingsBuilder<'d> {
                         None
                     }
                 })
+            });
+
+        if let Some(network_id) = existing {
+            return Ok(network_id);
+        }
+
+        if !self.create_network {
+            return Err(Error::custom(
+                ErrorKind::Internal,
+                format!("cannot find a Docker network with name=`{network_name}`. Create it or start with `--create-network`"),
+            ));
+        }
+
+        info!(network_name, "creating missing Docker network");
+        self.docker
+            .create_network(CreateNetworkOptions {
+                name: network_name,
+                check_duplicate: true,
+                driver: "bridge",
+                ..Default::default()
+            })
+            .await?
+            .id
+            .ok_or_else(|| {
+                Error::custom(
+                    ErrorKind::Internal,
+                    format!("Docker did not return the id of network `{network_name}`"),
+                )
             })
-            .unwrap_or_else(|| panic!("cannot find a Docker network with name=`{network_name}`"))
     }
 
-    pub async fn build(mut self) -> ContainerSettings {
+    /// Check the settings make sense before anything is created with
+    /// them
+    fn validate(&self) -> Result<(), Error> {
+        let invalid = |message: String| Err(Error::custom(ErrorKind::Internal, message));
+
+        match self.prefix.as_deref() {
+            None | Some("") => return invalid("the container prefix cannot be empty".to_string()),
+            Some(prefix) if !is_valid_docker_name(prefix) => {
+                return invalid(format!(
+                    "`{prefix}` is not a valid container prefix: it has to start with an alphanumeric character and only contain alphanumeric characters, `_`, `.` or `-`"
+                ))
+            }
+            _ => {}
+        }
+
+        match self.image.as_deref() {
+            Some(image) if is_valid_image_reference(image) => {}
+            Some(image) => return invalid(format!("`{image}` is not a valid image reference")),
+            None => return invalid("an image is required".to_string()),
+        }
+
+        match self.network_name.as_deref() {
+            None | Some("") => return invalid("the network name cannot be empty".to_string()),
+            Some(name) if !is_valid_docker_name(name) => {
+                return invalid(format!("`{name}` is not a valid network name"))
+            }
+            _ => {}
+        }
+
+        if self.provisioner.as_deref().unwrap_or_default().is_empty() {
+            return invalid("the provisioner host cannot be empty".to_string());
+        }
+
+        if self.fqdn.as_deref().unwrap_or_default().is_empty() {
+            return invalid("the proxy FQDN cannot be empty".to_string());
+        }
+
+        if self.stop_timeout_secs < 0 {
+            return invalid("the stop timeout cannot be negative".to_string());
+        }
+
+        Ok(())
+    }
+
+    pub async fn build(mut self) -> Result<ContainerSettings, Error> {
+        self.validate()?;
+
         let prefix = self.prefix.take().unwrap();
         let image = self.image.take().unwrap();
         let provisioner_host = self.provisioner.take().unwrap();
 
         let network_name = self.network_name.take().unwrap();
-        let network_id = self.resolve_network_id(&network_name).await;
+        let network_id = self.resolve_network_id(&network_name).await?;
         let fqdn = self.fqdn.take().unwrap();
 
-        ContainerSettings {
+        Ok(ContainerSettings {
             prefix,
             image,
             provisioner_host,
             network_name,
             network_id,
             fqdn,
+            max_running_projects: self.max_running_projects,
+            restart_policy: self.restart_policy,
+            stop_timeout_secs: self.stop_timeout_secs,
+        })
+    }
+}
+
+/// What Git tokens are sealed as, which cannot clash with env vars as
+/// it is not a valid env var name
+const GIT_TOKEN_NAME: &str = "git token";
+
+/// What webhook secrets are sealed as, for the same reason
+const WEBHOOK_SECRET_NAME: &str = "webhook secret";
+
+/// Longest a project tag can be
+pub const MAX_TAG_LEN: usize = 64;
+
+/// Most tags a single project can have
+pub const MAX_TAGS_PER_PROJECT: usize = 20;
+
+/// Check that `tag` is fit to tag a project with, such as `env:staging`,
+/// and normalize it to lowercase so that tags are matched regardless of
+/// case
+pub fn parse_project_tag(tag: &str) -> Result<String, Error> {
+    let tag = tag.trim().to_ascii_lowercase();
+
+    let mut chars = tag.chars();
+    let valid = tag.len() <= MAX_TAG_LEN
+        && matches!(chars.next(), Some(c) if c.is_ascii_alphanumeric())
+        && chars.all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | ':'));
+    if !valid {
+        return Err(Error::from_kind(ErrorKind::InvalidProjectTag).with_detail(format!("`{tag}`")));
+    }
+
+    Ok(tag)
+}
+
+/// Check that `limits` leave a container something to run with
+fn check_resource_limits(limits: &ResourceLimits) -> Result<(), Error> {
+    if limits.memory_mib == 0 || limits.millicpus == 0 {
+        return Err(Error::from_kind(ErrorKind::InvalidResourceLimits));
+    }
+
+    Ok(())
+}
+
+/// Fetch the rows `select` gives for `project_name` only, or for all
+/// projects if `None`
+async fn fetch_project_rows(
+    conn: &mut SqliteConnection,
+    select: &str,
+    project_name: Option<&ProjectName>,
+) -> Result<Vec<SqliteRow>, Error> {
+    let rows = query(&format!("{select} WHERE ?1 IS NULL OR project_name = ?1"))
+        .bind(project_name.map(ToString::to_string))
+        .fetch_all(conn)
+        .await?;
+    Ok(rows)
+}
+
+/// Whether `name` can be used to name Docker containers and networks
+fn is_valid_docker_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphanumeric())
+        && chars.all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '-'))
+}
+
+/// Whether `image` is a well formed reference of the form
+/// `[registry[:port]/]path[:tag][@digest]`
+pub(crate) fn is_valid_image_reference(image: &str) -> bool {
+    let (name, digest) = match image.split_once('@') {
+        Some((name, digest)) => (name, Some(digest)),
+        None => (image, None),
+    };
+
+    if let Some(digest) = digest {
+        match digest.split_once(':') {
+            Some((algorithm, hex))
+                if !algorithm.is_empty()
+                    && algorithm.chars().all(|c| c.is_ascii_alphanumeric())
+                    && !hex.is_empty()
+                    && hex.chars().all(|c| c.is_ascii_hexdigit()) => {}
+            _ => return false,
+        }
+    }
+
+    // A `:` after the last `/` separates the tag, anything before
+    // is a registry port
+    let last_component = name.rsplit('/').next().unwrap_or_default();
+    let (name, tag) = match last_component.rsplit_once(':') {
+        Some((_, tag)) => (&name[..name.len() - tag.len() - 1], Some(tag)),
+        None => (name, None),
+    };
+
+    if let Some(tag) = tag {
+        let mut chars = tag.chars();
+        let valid = tag.len() <= 128
+            && matches!(chars.next(), Some(c) if c.is_ascii_alphanumeric() || c == '_')
+            && chars.all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '-'));
+        if !valid {
+            return false;
+        }
+    }
+
+    let mut components = name.split('/').peekable();
+    let mut first = true;
+    while let Some(component) = components.next() {
+        let is_registry = first
+            && components.peek().is_some()
+            && (component.contains('.') || component.contains(':') || component == "localhost");
+        first = false;
+
+        let valid = if is_registry {
+            let host = component.split(':').next().unwrap_or_default();
+            !host.is_empty()
+                && host
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-'))
+                && component
+                    .split_once(':')
+                    .map(|(_, port)| !port.is_empty() && port.chars().all(|c| c.is_ascii_digit()))
+                    .unwrap_or(true)
+        } else {
+            !component.is_empty()
+                && !component.starts_with(['.', '_', '-'])
+                && !component.ends_with(['.', '_', '-'])
+                && component.chars().all(|c| {
+                    c.is_ascii_lowercase() || c.is_ascii_digit() || matches!(c, '.' | '_' | '-')
+                })
+        };
+
+        if !valid {
+            return false;
         }
     }
+
+    true
 }
 
 #[derive(Clone)]
@@ -159,28 +554,54 @@ pub struct ContainerSettings {
     pub network_name: String,
     pub network_id: String,
     pub fqdn: String,
+    pub max_running_projects: Option<usize>,
+    pub restart_policy: RestartPolicy,
+    pub stop_timeout_secs: i64,
 }
 
 impl ContainerSettings {
     pub fn builder(docker: &Docker) -> ContainerSettingsBuilder {
         ContainerSettingsBuilder::new(docker)
     }
+
+    /// How project containers are stopped, giving them
+    /// `stop_timeout_secs` to shut down before they are killed
+    pub fn stop_options(&self) -> StopContainerOptions {
+        StopContainerOptions {
+            t: self.stop_timeout_secs,
+        }
+    }
 }
 
 pub struct GatewayContextProvider {
     docker: Docker,
     settings: ContainerSettings,
+    drainer: ConnectionDrainer,
+    warm_pool: WarmPool,
 }
 
 impl GatewayContextProvider {
     pub fn new(docker: Docker, settings: ContainerSettings) -> Self {
-        Self { docker, settings }
+        Self {
+            docker,
+            settings,
+            drainer: ConnectionDrainer::new(),
+            warm_pool: WarmPool::default(),
+        }
+    }
+
+    /// Keep containers warm for projects being created to claim
+    pub fn with_warm_pool(mut self, warm_pool: WarmPool) -> Self {
+        self.warm_pool = warm_pool;
+        self
     }
 
     pub fn context(&self) -> GatewayContext {
         GatewayContext {
             docker: self.docker.clone(),
             settings: self.settings.clone(),
+            drainer: self.drainer.clone(),
+            warm_pool: self.warm_pool.clone(),
         }
     }
 }
@@ -189,6 +610,34 @@ pub struct GatewayService {
     provider: GatewayContextProvider,
     db: SqlitePool,
     task_router: TaskRouter<BoxedTask>,
+    task_tracker: TaskTracker,
+    env_cipher: EnvCipher,
+    project_cache: ProjectCache,
+    webhook_router: TaskRouter<BoxedTask>,
+    /// Whether webhooks may point at addresses which are not public
+    private_webhooks: bool,
+    maintenance: AtomicBool,
+    draining: AtomicBool,
+    rate_limiter: RateLimiter,
+    connection_limiter: ConnectionLimiter,
+    header_rewriter: HeaderRewriter,
+    ip_filters: IpFilters,
+    response_cache: ResponseCache,
+    basic_auth_gate: BasicAuthGate,
+    canaries: Canaries,
+    upstream_protocols: RwLock<HashMap<ProjectName, UpstreamProtocol>>,
+    feature_flags: FeatureFlags,
+    maintenance_pages: RwLock<HashMap<ProjectName, MaintenancePage>>,
+    activity_tracker: ActivityTracker,
+    counters: PlatformCounters,
+    traffic: TrafficCounters,
+    deploy_locks: DeployLocks,
+    rollouts: Rollouts,
+    jwt_verifier: Option<JwtVerifier>,
+    /// Held while a delete moves a project to `destroying`, so that
+    /// deletes of the same project racing each other do not both act
+    /// on its old state
+    destroy_locks: std::sync::Mutex<HashMap<ProjectName, Arc<tokio::sync::Mutex<()>>>>,
 }
 
 impl GatewayService {
@@ -199,17 +648,377 @@ impl GatewayService {
     pub async fn init(args: ContextArgs, db: SqlitePool) -> Self {
         let docker = Docker::connect_with_unix(&args.docker_host, 60, API_DEFAULT_VERSION).unwrap();
 
-        let container_settings = ContainerSettings::builder(&docker).from_args(&args).await;
+        let container_settings = ContainerSettings::builder(&docker)
+            .from_args(&args)
+            .await
+            .unwrap_or_else(|err| panic!("invalid container settings: {err}"));
 
-        let provider = GatewayContextProvider::new(docker, container_settings);
+        let warm_pool = WarmPool::new(args.warm_pool_size, Duration::from_secs(args.warm_pool_ttl));
+        let provider =
+            GatewayContextProvider::new(docker, container_settings).with_warm_pool(warm_pool);
 
         let task_router = TaskRouter::new();
 
-        Self {
+        let task_tracker = TaskTracker::new();
+
+        let env_cipher = EnvCipher::new_random();
+
+        let project_cache = ProjectCache::default();
+
+        // Webhooks are delivered by their own workers so that a slow
+        // receiver never holds up a project's state machine
+        let webhook_router = TaskRouter::new();
+
+        let service = Self {
             provider,
             db,
             task_router,
+            task_tracker,
+            env_cipher,
+            project_cache,
+            webhook_router,
+            private_webhooks: false,
+            maintenance: AtomicBool::new(false),
+            draining: AtomicBool::new(false),
+            rate_limiter: RateLimiter::new(),
+            connection_limiter: ConnectionLimiter::new(),
+            header_rewriter: HeaderRewriter::new(),
+            ip_filters: IpFilters::new(),
+            response_cache: ResponseCache::new(),
+            basic_auth_gate: BasicAuthGate::new(),
+            canaries: Canaries::new(),
+            upstream_protocols: RwLock::new(HashMap::new()),
+            feature_flags: FeatureFlags::new(),
+            maintenance_pages: RwLock::new(HashMap::new()),
+            activity_tracker: ActivityTracker::new(),
+            counters: PlatformCounters::new(),
+            traffic: TrafficCounters::new(),
+            deploy_locks: DeployLocks::default(),
+            rollouts: Rollouts::new(),
+            jwt_verifier: None,
+            destroy_locks: std::sync::Mutex::new(HashMap::new()),
+        };
+
+        service
+            .load_project_settings(None)
+            .await
+            .expect("to load project settings");
+
+        service
+    }
+
+    /// Load the settings the proxy keeps in memory for `project_name`
+    /// from the database, or those of all projects if `None`. Settings
+    /// the project no longer has in the database are dropped.
+    async fn load_project_settings(&self, project_name: Option<&ProjectName>) -> Result<(), Error> {
+        let mut conn = self.acquire().await?;
+
+        let rate_limits = fetch_project_rows(
+            &mut conn,
+            "SELECT project_name, requests_per_second, burst FROM project_rate_limits",
+            project_name,
+        )
+        .await?;
+        let connection_limits = fetch_project_rows(
+            &mut conn,
+            "SELECT project_name, max_connections FROM project_connection_limits",
+            project_name,
+        )
+        .await?;
+        let header_rules = fetch_project_rows(
+            &mut conn,
+            "SELECT project_name, rules FROM project_header_rules",
+            project_name,
+        )
+        .await?;
+        let ip_filters = fetch_project_rows(
+            &mut conn,
+            "SELECT project_name, filter FROM project_ip_filters",
+            project_name,
+        )
+        .await?;
+        let response_caches = fetch_project_rows(
+            &mut conn,
+            "SELECT project_name, max_entries FROM project_response_caches",
+            project_name,
+        )
+        .await?;
+        let basic_auth = fetch_project_rows(
+            &mut conn,
+            "SELECT project_name, username, password_hash FROM project_basic_auth",
+            project_name,
+        )
+        .await?;
+        let canaries = fetch_project_rows(
+            &mut conn,
+            "SELECT project_name, canary, weight, sticky FROM project_canaries",
+            project_name,
+        )
+        .await?;
+        let upstreams = fetch_project_rows(
+            &mut conn,
+            "SELECT project_name, protocol FROM project_upstreams",
+            project_name,
+        )
+        .await?;
+        let maintenance_pages = fetch_project_rows(
+            &mut conn,
+            "SELECT project_name, body, retry_after_secs FROM project_maintenance_pages",
+            project_name,
+        )
+        .await?;
+        let feature_flags = fetch_project_rows(
+            &mut conn,
+            "SELECT project_name, flag FROM project_feature_flags",
+            project_name,
+        )
+        .await?;
+        drop(conn);
+
+        if let Some(project_name) = project_name {
+            self.rate_limiter.set_limit(project_name, None);
+            self.connection_limiter.set_limit(project_name, None);
+            self.header_rewriter.set_rewrites(project_name, None);
+            self.ip_filters.set_rules(project_name, None);
+            self.response_cache.set_config(project_name, None);
+            self.basic_auth_gate.set_auth(project_name, None);
+            self.canaries.set_split(project_name, None);
+            self.upstream_protocols
+                .write()
+                .unwrap()
+                .remove(project_name);
+            self.maintenance_pages.write().unwrap().remove(project_name);
+            self.feature_flags.replace(project_name, BTreeSet::new());
+        }
+
+        for row in rate_limits {
+            let limit = RateLimit {
+                requests_per_second: row.get("requests_per_second"),
+                burst: row.get("burst"),
+            };
+            self.rate_limiter
+                .set_limit(&row.get("project_name"), Some(limit));
+        }
+
+        for row in connection_limits {
+            let limit = ConnectionLimit {
+                max_connections: row.get("max_connections"),
+            };
+            self.connection_limiter
+                .set_limit(&row.get("project_name"), Some(limit));
+        }
+
+        for row in header_rules {
+            let rules = row.get::<SqlxJson<HeaderRules>, _>("rules").0;
+            let rewrites = HeaderRewrites::new(rules)?;
+            self.header_rewriter
+                .set_rewrites(&row.get("project_name"), Some(rewrites));
+        }
+
+        for row in ip_filters {
+            let filter = row.get::<SqlxJson<IpFilter>, _>("filter").0;
+            let rules = IpRules::new(filter)?;
+            self.ip_filters
+                .set_rules(&row.get("project_name"), Some(rules));
+        }
+
+        for row in response_caches {
+            let config = ResponseCacheConfig {
+                max_entries: row.get("max_entries"),
+            };
+            self.response_cache
+                .set_config(&row.get("project_name"), Some(config));
+        }
+
+        for row in basic_auth {
+            let auth = BasicAuth::from_hash(row.get("username"), row.get("password_hash"));
+            self.basic_auth_gate
+                .set_auth(&row.get("project_name"), Some(auth));
+        }
+
+        for row in canaries {
+            let canary = Canary {
+                project: row.get("canary"),
+                weight: row.get("weight"),
+                sticky: row.get("sticky"),
+            };
+            let split = CanarySplit::new(canary)?;
+            self.canaries
+                .set_split(&row.get("project_name"), Some(split));
+        }
+
+        for row in upstreams {
+            let protocol = row
+                .get::<String, _>("protocol")
+                .parse()
+                .map_err(|err| Error::source(ErrorKind::Internal, err))?;
+            self.upstream_protocols
+                .write()
+                .unwrap()
+                .insert(row.get("project_name"), protocol);
+        }
+
+        for row in maintenance_pages {
+            let page = MaintenancePage {
+                body: row.get("body"),
+                retry_after_secs: row.get("retry_after_secs"),
+            };
+            self.maintenance_pages
+                .write()
+                .unwrap()
+                .insert(row.get("project_name"), page);
+        }
+
+        for row in feature_flags {
+            // Flags which were retired since are left alone
+            if let Ok(flag) = row.get::<String, _>("flag").parse() {
+                self.feature_flags.set(&row.get("project_name"), flag, true);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Use `env_cipher` to encrypt project environment variables
+    /// instead of a throwaway key
+    pub fn with_env_cipher(mut self, env_cipher: EnvCipher) -> Self {
+        self.env_cipher = env_cipher;
+        self
+    }
+
+    /// Decide what happens to a deploy to a project while another is
+    /// still in progress
+    pub fn with_deploy_concurrency(mut self, concurrency: DeployConcurrency) -> Self {
+        self.deploy_locks = DeployLocks::new(concurrency);
+        self
+    }
+
+    /// Let webhooks point at loopback, private and other addresses
+    /// which are not public, for gateways whose projects are trusted
+    pub fn with_private_webhooks(mut self, allow: bool) -> Self {
+        self.private_webhooks = allow;
+        self
+    }
+
+    /// Accept JWTs verified by `jwt_verifier` in addition to API keys
+    pub fn with_jwt_verifier(mut self, jwt_verifier: Option<JwtVerifier>) -> Self {
+        self.jwt_verifier = jwt_verifier;
+        self
+    }
+
+    pub fn jwt_verifier(&self) -> Option<&JwtVerifier> {
+        self.jwt_verifier.as_ref()
+    }
+
+    /// Use `project_cache` to keep project states in memory instead
+    /// of the default one
+    pub fn with_project_cache(mut self, project_cache: ProjectCache) -> Self {
+        self.project_cache = project_cache;
+        self
+    }
+
+    /// Get a connection to the database, recording how long the pool
+    /// kept the request waiting for it
+    async fn acquire(&self) -> Result<PoolConnection<Sqlite>, Error> {
+        let start = Instant::now();
+        let conn = self.db.acquire().await;
+        let waited = start.elapsed();
+
+        Span::current().record("db.pool.wait_ms", waited.as_millis() as u64);
+        if waited >= DB_SLOW_ACQUIRE {
+            warn!(
+                db.pool.wait_ms = waited.as_millis() as u64,
+                db.pool.size = self.db.size(),
+                db.pool.idle = self.db.num_idle(),
+                "waited on the database pool"
+            );
+        } else {
+            trace!(
+                db.pool.wait_ms = waited.as_millis() as u64,
+                "acquired a database connection"
+            );
+        }
+
+        Ok(conn?)
+    }
+
+    /// Turn maintenance mode on or off. While it is on, projects
+    /// cannot be created and nothing new can be deployed.
+    pub fn set_maintenance(&self, enabled: bool) {
+        self.maintenance.store(enabled, Ordering::SeqCst);
+    }
+
+    pub fn is_in_maintenance(&self) -> bool {
+        self.maintenance.load(Ordering::SeqCst)
+    }
+
+    /// Fail with [`ErrorKind::Maintenance`] if maintenance mode is on
+    pub fn ensure_not_in_maintenance(&self) -> Result<(), Error> {
+        if self.is_in_maintenance() {
+            Err(Error::from_kind(ErrorKind::Maintenance))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Take the node out of rotation, or put it back in. While it is
+    /// draining, no new projects are placed on it but the ones already
+    /// there are left alone.
+    pub fn set_draining(&self, draining: bool) {
+        self.draining.store(draining, Ordering::SeqCst);
+    }
+
+    pub fn is_draining(&self) -> bool {
+        self.draining.load(Ordering::SeqCst)
+    }
+
+    /// Fail with [`ErrorKind::NodeDraining`] if the node is draining
+    pub fn ensure_not_draining(&self) -> Result<(), Error> {
+        if self.is_draining() {
+            Err(Error::from_kind(ErrorKind::NodeDraining))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Mark the node as draining and, if `stop_running`, queue a stop for
+    /// every project running on it. Returns the projects being stopped.
+    pub async fn drain(
+        &self,
+        stop_running: bool,
+        sender: &Sender<BoxedTask>,
+    ) -> Result<Vec<ProjectName>, Error> {
+        self.set_draining(true);
+
+        if !stop_running {
+            return Ok(Vec::new());
+        }
+
+        let running = self.iter_running_projects().await?;
+        for project_name in &running {
+            self.new_task()
+                .project(project_name.clone())
+                .and_then(task::stop())
+                .send(sender)
+                .await?;
         }
+
+        Ok(running)
+    }
+
+    /// The projects which last were seen running
+    pub async fn iter_running_projects(&self) -> Result<Vec<ProjectName>, Error> {
+        Ok(query("SELECT project_name, project_state FROM projects")
+            .fetch_all(&mut *self.acquire().await?)
+            .await?
+            .into_iter()
+            .filter(|row| {
+                row.get::<SqlxJson<Project>, _>("project_state")
+                    .0
+                    .is_running()
+            })
+            .map(|row| row.get("project_name"))
+            .collect())
     }
 
     pub async fn route(
@@ -218,9 +1027,13 @@ impl GatewayService {
         mut req: Request<Body>,
     ) -> Result<Response<Body>, Error> {
         let project_name = &scoped_user.scope;
-        let target_ip = self
-            .find_project(project_name)
-            .await?
+        let project = self.find_project(project_name).await?;
+
+        if project.is_frozen() {
+            return Err(Error::from_kind(ErrorKind::ProjectFrozen));
+        }
+
+        let target_ip = project
             .target_ip()?
             .ok_or_else(|| Error::from_kind(ErrorKind::ProjectNotReady))?;
 
@@ -256,7 +1069,7 @@ impl GatewayService {
         &self,
     ) -> Result<impl ExactSizeIterator<Item = (ProjectName, AccountName)>, Error> {
         let iter = query("SELECT project_name, account_name FROM projects")
-            .fetch_all(&self.db)
+            .fetch_all(&mut *self.acquire().await?)
             .await?
             .into_iter()
             .map(|row| (row.get("project_name"), row.get("account_name")));
@@ -264,9 +1077,26 @@ impl GatewayService {
     }
 
     pub async fn find_project(&self, project_name: &ProjectName) -> Result<Project, Error> {
+        let generation = match self.project_cache.get(project_name) {
+            Ok(project) => return Ok(project),
+            Err(generation) => generation,
+        };
+
+        let project = self.load_project(project_name).await?;
+
+        self.project_cache
+            .insert(project_name, &project, generation);
+
+        Ok(project)
+    }
+
+    /// The state of a project as persisted, however recent the one in
+    /// the cache. For the holder of the lease on the project, as other
+    /// nodes may have moved it along since it was cached here.
+    pub async fn load_project(&self, project_name: &ProjectName) -> Result<Project, Error> {
         query("SELECT project_state FROM projects WHERE project_name=?1")
             .bind(project_name)
-            .fetch_optional(&self.db)
+            .fetch_optional(&mut *self.acquire().await?)
             .await?
             .map(|r| {
                 r.try_get::<SqlxJson<Project>, _>("project_state")
@@ -283,7 +1113,7 @@ impl GatewayService {
         let iter =
             query("SELECT project_name, project_state FROM projects WHERE account_name = ?1")
                 .bind(account_name)
-                .fetch_all(&self.db)
+                .fetch_all(&mut *self.acquire().await?)
                 .await?
                 .into_iter()
                 .map(|row| {
@@ -295,6 +1125,32 @@ impl GatewayService {
         Ok(iter)
     }
 
+    /// Up to `limit` projects of `account_name` in the order of their
+    /// names, starting after `after`
+    pub async fn iter_user_projects_page(
+        &self,
+        account_name: &AccountName,
+        after: Option<&ProjectName>,
+        limit: u32,
+    ) -> Result<impl Iterator<Item = (ProjectName, Project)>, Error> {
+        let iter = query(
+            "SELECT project_name, project_state FROM projects WHERE account_name = ?1 AND project_name > ?2 ORDER BY project_name LIMIT ?3",
+        )
+        .bind(account_name)
+        .bind(after.map(ProjectName::as_str).unwrap_or_default())
+        .bind(limit)
+        .fetch_all(&mut *self.acquire().await?)
+        .await?
+        .into_iter()
+        .map(|row| {
+            (
+                row.get("project_name"),
+                row.get::<SqlxJson<Project>, _>("project_state").0,
+            )
+        });
+        Ok(iter)
+    }
+
     pub async fn update_project(
         &self,
         project_name: &ProjectName,
@@ -311,26 +1167,396 @@ impl GatewayService {
                 .bind(SqlxJson(project))
                 .bind(project_name),
         };
-        query.execute(&self.db).await?;
+        query.execute(&mut *self.acquire().await?).await?;
+
+        self.project_cache.invalidate(project_name);
+
         Ok(())
     }
 
-    pub async fn account_name_from_project(
+    /// Mark a project as being destroyed, so that its status reads as
+    /// such from the moment a delete is accepted rather than once the
+    /// destroy task gets to it. A project already on its way out is
+    /// left as it is. Returns the state the project is now in.
+    ///
+    /// Frozen projects are refused, as deleting and creating them again
+    /// would lift the freeze. Only an admin can force destroy them.
+    pub async fn begin_destroy_project(
         &self,
         project_name: &ProjectName,
-    ) -> Result<AccountName, Error> {
-        query("SELECT account_name FROM projects WHERE project_name = ?1")
-            .bind(project_name)
-            .fetch_optional(&self.db)
-            .await?
-            .map(|row| row.get("account_name"))
-            .ok_or_else(|| Error::from(ErrorKind::ProjectNotFound))
-    }
+    ) -> Result<Project, Error> {
+        let lock = self
+            .destroy_locks
+            .lock()
+            .unwrap()
+            .entry(project_name.clone())
+            .or_default()
+            .clone();
+        let res = {
+            let _guard = lock.lock().await;
+            self.begin_destroy_locked_project(project_name).await
+        };
+        drop(lock);
 
-    pub async fn key_from_account_name(&self, account_name: &AccountName) -> Result<Key, Error> {
-        let key = query("SELECT key FROM accounts WHERE account_name = ?1")
+        // Unless someone else is waiting on it, the lock goes, for there
+        // not to be one left behind for every project ever deleted
+        let mut locks = self.destroy_locks.lock().unwrap();
+        if locks
+            .get(project_name)
+            .map_or(false, |lock| Arc::strong_count(lock) == 1)
+        {
+            locks.remove(project_name);
+        }
+
+        res
+    }
+
+    async fn begin_destroy_locked_project(
+        &self,
+        project_name: &ProjectName,
+    ) -> Result<Project, Error> {
+        let project = self.find_project(project_name).await?;
+        if matches!(project, Project::Destroying(_) | Project::Destroyed(_)) {
+            return Ok(project);
+        }
+
+        if project.is_frozen() {
+            return Err(Error::from_kind(ErrorKind::ProjectFrozen));
+        }
+
+        // Whatever is still being done to the project is moot now. A
+        // creation in particular would otherwise leave a container behind
+        let cancelled = self.task_tracker.cancel(project_name);
+        if cancelled > 0 {
+            info!(
+                %project_name,
+                cancelled, "cancelled in-flight tasks of project being deleted"
+            );
+        }
+
+        let destroying = project.destroy()?;
+        self.update_project(project_name, &destroying).await?;
+        self.notify_project_state(project_name, &destroying).await;
+        self.record_project_state(project_name, &destroying).await;
+
+        Ok(destroying)
+    }
+
+    /// Remove the container of a project and mark it destroyed without
+    /// going through its state machine. Meant for projects wedged in a
+    /// state from which a normal delete cannot make progress.
+    pub async fn force_destroy_project(
+        &self,
+        project_name: &ProjectName,
+    ) -> Result<Project, Error> {
+        let project = self.find_project(project_name).await?;
+        if project.is_destroyed() {
+            return Ok(project);
+        }
+
+        warn!(%project_name, state = project.state(), "force destroying project");
+
+        let cancelled = self.task_tracker.cancel(project_name);
+        if cancelled > 0 {
+            warn!(%project_name, cancelled, "cancelled in-flight tasks of project being force destroyed");
+        }
+
+        let ctx = self.context();
+        let container = project.container();
+
+        // The container may have been created without making it into
+        // the state yet, so it is looked for by name as well
+        let prefix = &ctx.container_settings().prefix;
+        let mut targets = vec![format!("{prefix}{project_name}_run")];
+        if let Some(id) = container
+            .as_ref()
+            .and_then(|container| container.id.clone())
+        {
+            targets.push(id);
+        }
+
+        for target in targets {
+            match docker_op(
+                "remove",
+                ctx.docker().remove_container(
+                    &target,
+                    Some(RemoveContainerOptions {
+                        force: true,
+                        ..Default::default()
+                    }),
+                ),
+            )
+            .await
+            {
+                Ok(_) => warn!(%project_name, container = target, "force removed container"),
+                Err(DockerError::DockerResponseServerError {
+                    status_code: 404, ..
+                }) => {}
+                Err(err) => return Err(err.into()),
+            }
+        }
+
+        let destroyed = Project::Destroyed(ProjectDestroyed::new(container));
+        self.update_project(project_name, &destroyed).await?;
+        self.notify_project_state(project_name, &destroyed).await;
+
+        warn!(%project_name, "project force destroyed");
+
+        Ok(destroyed)
+    }
+
+    /// Move a project over to `new_name`, along with its env vars,
+    /// custom domains and the rest of its configuration. The deployer
+    /// only answers to the name it was started with, so the container
+    /// of the project is recreated on the same volume. Keys scoped to
+    /// the old name are left as they are.
+    ///
+    /// Only projects which are ready, stopped or errored can be renamed,
+    /// and the lease on the project is held meanwhile so that no task
+    /// acts on it halfway through.
+    ///
+    /// The wildcard certificate of the public FQDN already covers the
+    /// new subdomain, so no certificate has to be requested for it.
+    pub async fn rename_project(
+        &self,
+        project_name: &ProjectName,
+        new_name: &ProjectName,
+        redirect: bool,
+    ) -> Result<Project, Error> {
+        new_name
+            .validate()
+            .map_err(|err| Error::from_kind(ErrorKind::InvalidProjectName).with_detail(err))?;
+
+        if !self.is_project_name_available(new_name).await? {
+            return Err(Error::from_kind(ErrorKind::ProjectAlreadyExists));
+        }
+
+        // The lease can only be taken on a project which exists
+        self.find_project(project_name).await?;
+
+        let holder = Uuid::new_v4();
+        if !self
+            .acquire_project_lease(project_name, &holder, PROJECT_LEASE_TTL)
+            .await?
+        {
+            return Err(Error::custom(
+                ErrorKind::ProjectBusy,
+                "the project is being worked on, try again once it is done",
+            ));
+        }
+
+        let res = self
+            .rename_leased_project(project_name, new_name, redirect)
+            .await;
+
+        // The lease moves along with the project when it is renamed
+        let leased = if res.is_ok() { new_name } else { project_name };
+        if let Err(err) = self.release_project_lease(leased, &holder).await {
+            warn!(error = %err, "could not release the lease on the project, it will expire");
+        }
+
+        res
+    }
+
+    async fn rename_leased_project(
+        &self,
+        project_name: &ProjectName,
+        new_name: &ProjectName,
+        redirect: bool,
+    ) -> Result<Project, Error> {
+        let project = self.find_project(project_name).await?;
+        match project {
+            Project::Ready(_) | Project::Stopped(_) | Project::Errored(_) => {}
+            Project::Frozen(_) => return Err(Error::from_kind(ErrorKind::ProjectFrozen)),
+            _ => {
+                return Err(Error::custom(
+                    ErrorKind::InvalidOperation,
+                    "only projects which are ready, stopped or errored can be renamed",
+                ))
+            }
+        }
+
+        let ctx = self.context();
+        let container = project.container();
+        let prefix = &ctx.container_settings().prefix;
+
+        let volume = container
+            .as_ref()
+            .and_then(mounted_volume)
+            .unwrap_or_else(|| format!("{prefix}{project_name}_vol"));
+        let initial_key = self.control_key_from_project_name(project_name).await?;
+        let mut creating = ProjectCreating::new(new_name.clone(), initial_key).with_volume(volume);
+        if let Some(image) = container
+            .as_ref()
+            .and_then(|container| container.config.as_ref())
+            .and_then(|config| config.image.clone())
+        {
+            creating = creating.with_image(image);
+        }
+        let renamed = Project::Creating(creating);
+
+        // Env vars and the other secrets are sealed with the name of
+        // their project, so they have to be sealed again under the new one
+        let env = self.project_env(project_name).await?;
+        let git_token = self.project_git_token(project_name).await?;
+        let webhook = self.find_project_webhook(project_name).await?;
+
+        let mut conn = self.acquire().await?;
+        let mut transaction = conn.begin().await?;
+
+        // The rows of the project in other tables follow it through
+        // their foreign keys
+        query("UPDATE projects SET project_name = ?1, project_state = ?2 WHERE project_name = ?3")
+            .bind(new_name)
+            .bind(SqlxJson(&renamed))
+            .bind(project_name)
+            .execute(&mut transaction)
+            .await
+            .map_err(|err| {
+                // Someone else took the name in the meantime
+                if let Some(db_err_code) = err.as_database_error().and_then(DatabaseError::code) {
+                    if db_err_code == "1555" {
+                        return Error::from_kind(ErrorKind::ProjectAlreadyExists);
+                    }
+                }
+                err.into()
+            })?;
+
+        for (name, value) in env {
+            let sealed = self.env_cipher.encrypt(new_name, &name, &value)?;
+            query("UPDATE project_env SET value = ?1 WHERE project_name = ?2 AND name = ?3")
+                .bind(sealed)
+                .bind(new_name)
+                .bind(name)
+                .execute(&mut transaction)
+                .await?;
+        }
+
+        if let Some(token) = git_token {
+            let sealed = self.env_cipher.encrypt(new_name, GIT_TOKEN_NAME, &token)?;
+            query("UPDATE project_git_tokens SET token = ?1 WHERE project_name = ?2")
+                .bind(sealed)
+                .bind(new_name)
+                .execute(&mut transaction)
+                .await?;
+        }
+
+        if let Some(webhook) = webhook {
+            let sealed = self
+                .env_cipher
+                .encrypt(new_name, WEBHOOK_SECRET_NAME, &webhook.secret)?;
+            query("UPDATE project_webhooks SET secret = '', sealed_secret = ?1 WHERE project_name = ?2")
+                .bind(sealed)
+                .bind(new_name)
+                .execute(&mut transaction)
+                .await?;
+        }
+
+        // Projects only refer to their canary by name, which no foreign
+        // key keeps up to date
+        let stables: Vec<ProjectName> =
+            query("SELECT project_name FROM project_canaries WHERE canary = ?1")
+                .bind(project_name)
+                .fetch_all(&mut transaction)
+                .await?
+                .into_iter()
+                .map(|row| row.get("project_name"))
+                .collect();
+        query("UPDATE project_canaries SET canary = ?1 WHERE canary = ?2")
+            .bind(new_name)
+            .bind(project_name)
+            .execute(&mut transaction)
+            .await?;
+
+        if redirect {
+            let expires_at =
+                Utc::now() + chrono::Duration::from_std(RENAME_REDIRECT_GRACE).unwrap();
+            query("INSERT OR REPLACE INTO project_redirects (project_name, target, expires_at) VALUES (?1, ?2, ?3)")
+                .bind(project_name)
+                .bind(new_name)
+                .bind(expires_at.to_rfc3339())
+                .execute(&mut transaction)
+                .await?;
+        }
+
+        transaction.commit().await?;
+        drop(conn);
+
+        info!(%project_name, %new_name, "renamed project");
+
+        self.project_cache.invalidate(project_name);
+        self.project_cache.invalidate(new_name);
+
+        self.load_project_settings(Some(project_name)).await?;
+        self.load_project_settings(Some(new_name)).await?;
+        for stable in &stables {
+            self.load_project_settings(Some(stable)).await?;
+        }
+
+        self.task_tracker.cancel(project_name);
+
+        // The old container answers to the old name only. Its volume
+        // is kept for the new one.
+        if let Some(id) = container.and_then(|container| container.id) {
+            match docker_op(
+                "remove",
+                ctx.docker().remove_container(
+                    &id,
+                    Some(RemoveContainerOptions {
+                        force: true,
+                        ..Default::default()
+                    }),
+                ),
+            )
+            .await
+            {
+                Ok(_)
+                | Err(DockerError::DockerResponseServerError {
+                    status_code: 404, ..
+                }) => {}
+                Err(err) => return Err(err.into()),
+            }
+        }
+
+        Ok(renamed)
+    }
+
+    /// The name `project_name` redirects to, if it is the old name of
+    /// a renamed project still in its grace period
+    pub async fn find_project_redirect(
+        &self,
+        project_name: &ProjectName,
+    ) -> Result<Option<ProjectName>, Error> {
+        let target =
+            query("SELECT target, expires_at FROM project_redirects WHERE project_name = ?1")
+                .bind(project_name)
+                .fetch_optional(&mut *self.acquire().await?)
+                .await?
+                .filter(|row| {
+                    DateTime::parse_from_rfc3339(row.get("expires_at"))
+                        .map(|expires_at| expires_at > Utc::now())
+                        .unwrap_or_default()
+                })
+                .map(|row| row.get("target"));
+        Ok(target)
+    }
+
+    pub async fn account_name_from_project(
+        &self,
+        project_name: &ProjectName,
+    ) -> Result<AccountName, Error> {
+        query("SELECT account_name FROM projects WHERE project_name = ?1")
+            .bind(project_name)
+            .fetch_optional(&mut *self.acquire().await?)
+            .await?
+            .map(|row| row.get("account_name"))
+            .ok_or_else(|| Error::from(ErrorKind::ProjectNotFound))
+    }
+
+    pub async fn key_from_account_name(&self, account_name: &AccountName) -> Result<Key, Error> {
+        let key = query("SELECT key FROM accounts WHERE account_name = ?1")
             .bind(account_name)
-            .fetch_optional(&self.db)
+            .fetch_optional(&mut *self.acquire().await?)
             .await?
             .map(|row| row.try_get("key").unwrap())
             .ok_or_else(|| Error::from(ErrorKind::UserNotFound))?;
@@ -340,38 +1566,279 @@ impl GatewayService {
     pub async fn account_name_from_key(&self, key: &Key) -> Result<AccountName, Error> {
         let name = query("SELECT account_name FROM accounts WHERE key = ?1")
             .bind(key)
-            .fetch_optional(&self.db)
+            .fetch_optional(&mut *self.acquire().await?)
             .await?
             .map(|row| row.try_get("account_name").unwrap())
             .ok_or_else(|| Error::from(ErrorKind::UserNotFound))?;
         Ok(name)
     }
 
+    /// Mint a new key for `account_name` restricted to `scope`, returning
+    /// the id it can later be listed and revoked by along with the key
+    pub async fn create_scoped_key(
+        &self,
+        account_name: &AccountName,
+        scope: &KeyScope,
+    ) -> Result<(String, Key), Error> {
+        let id = format!("{:016x}", rand::random::<u64>());
+        let key = Key::new_random();
+        query(
+            "INSERT INTO scoped_keys (id, key, account_name, scope, created_at) VALUES (?1, ?2, ?3, ?4, ?5)",
+        )
+        .bind(&id)
+        .bind(&key)
+        .bind(account_name)
+        .bind(SqlxJson(scope))
+        .bind(Utc::now().to_rfc3339())
+        .execute(&mut *self.acquire().await?)
+        .await?;
+        Ok((id, key))
+    }
+
+    /// Record that `key` was just used to authenticate
+    pub async fn touch_key(&self, key: &Key, scoped: bool) -> Result<(), Error> {
+        let statement = if scoped {
+            "UPDATE scoped_keys SET last_used_at = ?1 WHERE key = ?2"
+        } else {
+            "UPDATE accounts SET key_last_used_at = ?1 WHERE key = ?2"
+        };
+        query(statement)
+            .bind(Utc::now().to_rfc3339())
+            .bind(key)
+            .execute(&mut *self.acquire().await?)
+            .await?;
+        Ok(())
+    }
+
+    /// List the keys of `account_name`, starting with its own key. The
+    /// secrets themselves are never returned
+    pub async fn list_keys(
+        &self,
+        account_name: &AccountName,
+    ) -> Result<Vec<user::KeyResponse>, Error> {
+        let parse_time = |time: Option<String>| {
+            time.and_then(|time| DateTime::parse_from_rfc3339(&time).ok())
+                .map(|time| time.with_timezone(&Utc))
+        };
+
+        let last_used_at = query("SELECT key_last_used_at FROM accounts WHERE account_name = ?1")
+            .bind(account_name)
+            .fetch_optional(&mut *self.acquire().await?)
+            .await?
+            .map(|row| row.get::<Option<String>, _>("key_last_used_at"))
+            .ok_or_else(|| Error::from(ErrorKind::UserNotFound))?;
+
+        let mut keys = vec![user::KeyResponse {
+            id: PRIMARY_KEY_ID.to_string(),
+            created_at: None,
+            last_used_at: parse_time(last_used_at),
+            scope: None,
+        }];
+
+        let scoped = query(
+            "SELECT id, scope, created_at, last_used_at FROM scoped_keys WHERE account_name = ?1 ORDER BY created_at",
+        )
+        .bind(account_name)
+        .fetch_all(&mut *self.acquire().await?)
+        .await?
+        .into_iter()
+        .map(|row| {
+            let scope = row.get::<SqlxJson<KeyScope>, _>("scope").0;
+            user::KeyResponse {
+                id: row.get("id"),
+                created_at: parse_time(row.get("created_at")),
+                last_used_at: parse_time(row.get("last_used_at")),
+                scope: Some(user::KeyScope {
+                    projects: scope
+                        .projects
+                        .map(|projects| projects.iter().map(ToString::to_string).collect()),
+                    actions: scope.actions,
+                }),
+            }
+        });
+        keys.extend(scoped);
+
+        Ok(keys)
+    }
+
+    /// Revoke the scoped key `id` of `account_name`. The account's own key
+    /// cannot be revoked, only its scoped keys
+    pub async fn revoke_key(&self, account_name: &AccountName, id: &str) -> Result<(), Error> {
+        if id == PRIMARY_KEY_ID {
+            return Err(Error::from_kind(ErrorKind::InvalidOperation));
+        }
+
+        let result = query("DELETE FROM scoped_keys WHERE account_name = ?1 AND id = ?2")
+            .bind(account_name)
+            .bind(id)
+            .execute(&mut *self.acquire().await?)
+            .await?;
+
+        if result.rows_affected() == 0 {
+            return Err(Error::from_kind(ErrorKind::KeyNotFound));
+        }
+
+        Ok(())
+    }
+
+    pub async fn find_scoped_key(&self, key: &Key) -> Result<(AccountName, KeyScope), Error> {
+        query("SELECT account_name, scope FROM scoped_keys WHERE key = ?1")
+            .bind(key)
+            .fetch_optional(&mut *self.acquire().await?)
+            .await?
+            .map(|row| {
+                (
+                    row.get("account_name"),
+                    row.get::<SqlxJson<KeyScope>, _>("scope").0,
+                )
+            })
+            .ok_or_else(|| Error::from(ErrorKind::UserNotFound))
+    }
+
+    /// Mint a token for `actor` to act as `account_name` until it
+    /// expires, only reading unless `allow_writes`. Super users and
+    /// auditors cannot be impersonated.
+    pub async fn create_impersonation(
+        &self,
+        actor: &AccountName,
+        account_name: &AccountName,
+        allow_writes: bool,
+    ) -> Result<(Key, DateTime<Utc>), Error> {
+        let permissions = query("SELECT super_user, auditor FROM accounts WHERE account_name = ?1")
+            .bind(account_name)
+            .fetch_optional(&mut *self.acquire().await?)
+            .await?
+            .ok_or_else(|| Error::from_kind(ErrorKind::UserNotFound))?;
+        if permissions.get::<bool, _>("super_user") || permissions.get::<bool, _>("auditor") {
+            return Err(Error::custom(
+                ErrorKind::Forbidden,
+                "super users and auditors cannot be impersonated",
+            ));
+        }
+
+        let scope = KeyScope {
+            projects: None,
+            actions: (!allow_writes).then(|| vec![user::Action::Read]),
+        };
+        let key = Key::new_random();
+        let expires_at = Utc::now() + chrono::Duration::from_std(IMPERSONATION_TTL).unwrap();
+
+        query("INSERT INTO impersonation_tokens (key, actor, account_name, scope, expires_at) VALUES (?1, ?2, ?3, ?4, ?5)")
+            .bind(&key)
+            .bind(actor)
+            .bind(account_name)
+            .bind(SqlxJson(&scope))
+            .bind(expires_at.to_rfc3339())
+            .execute(&mut *self.acquire().await?)
+            .await?;
+
+        let mode = if allow_writes {
+            "read-write"
+        } else {
+            "read-only"
+        };
+        self.record_audit(actor, account_name, &format!("impersonate ({mode})"))
+            .await?;
+        info!(%actor, %account_name, mode, "impersonation started");
+
+        Ok((key, expires_at))
+    }
+
+    /// The impersonation `key` is for, if it has not expired yet
+    pub async fn find_impersonation(&self, key: &Key) -> Result<Impersonation, Error> {
+        query("SELECT actor, account_name, scope FROM impersonation_tokens WHERE key = ?1 AND expires_at > ?2")
+            .bind(key)
+            .bind(Utc::now().to_rfc3339())
+            .fetch_optional(&mut *self.acquire().await?)
+            .await?
+            .map(|row| Impersonation {
+                actor: row.get("actor"),
+                account_name: row.get("account_name"),
+                scope: row.get::<SqlxJson<KeyScope>, _>("scope").0,
+            })
+            .ok_or_else(|| Error::from(ErrorKind::UserNotFound))
+    }
+
+    /// Remove the impersonation tokens which have expired, returning
+    /// how many were removed
+    pub async fn sweep_impersonations(&self) -> Result<u64, Error> {
+        let swept = query("DELETE FROM impersonation_tokens WHERE expires_at <= ?1")
+            .bind(Utc::now().to_rfc3339())
+            .execute(&mut *self.acquire().await?)
+            .await?
+            .rows_affected();
+
+        Ok(swept)
+    }
+
+    /// Keep track of `actor` doing `action` as `effective`
+    pub async fn record_audit(
+        &self,
+        actor: &AccountName,
+        effective: &AccountName,
+        action: &str,
+    ) -> Result<(), Error> {
+        query("INSERT INTO audit_log (at, actor, effective, action) VALUES (?1, ?2, ?3, ?4)")
+            .bind(Utc::now().to_rfc3339())
+            .bind(actor)
+            .bind(effective)
+            .bind(action)
+            .execute(&mut *self.acquire().await?)
+            .await?;
+
+        Ok(())
+    }
+
+    /// The latest entries of the audit log, newest first
+    pub async fn list_audit_log(&self, limit: u32) -> Result<Vec<user::AuditEntry>, Error> {
+        let entries =
+            query("SELECT at, actor, effective, action FROM audit_log ORDER BY id DESC LIMIT ?1")
+                .bind(limit)
+                .fetch_all(&mut *self.acquire().await?)
+                .await?
+                .into_iter()
+                .map(|row| user::AuditEntry {
+                    at: DateTime::parse_from_rfc3339(row.get("at"))
+                        .expect("audit log times to be RFC 3339")
+                        .with_timezone(&Utc),
+                    actor: row.get("actor"),
+                    effective: row.get("effective"),
+                    action: row.get("action"),
+                })
+                .collect();
+
+        Ok(entries)
+    }
+
     pub async fn control_key_from_project_name(
         &self,
         project_name: &ProjectName,
     ) -> Result<String, Error> {
         let control_key = query("SELECT initial_key FROM projects WHERE project_name = ?1")
             .bind(project_name)
-            .fetch_optional(&self.db)
+            .fetch_optional(&mut *self.acquire().await?)
             .await?
             .map(|row| row.try_get("initial_key").unwrap())
             .ok_or_else(|| Error::from(ErrorKind::ProjectNotFound))?;
         Ok(control_key)
     }
 
+    /// Create an account with a fresh key. Creating an account which
+    /// already exists is a conflict rather than a way to get its key:
+    /// it fails with [`ErrorKind::UserAlreadyExists`] and leaves the
+    /// existing account untouched.
     pub async fn create_user(&self, name: AccountName) -> Result<User, Error> {
         let key = Key::new_random();
         query("INSERT INTO accounts (account_name, key) VALUES (?1, ?2)")
             .bind(&name)
             .bind(&key)
-            .execute(&self.db)
+            .execute(&mut *self.acquire().await?)
             .await
             .map_err(|err| {
-                // If the error is a broken PK constraint, this is a
-                // project name clash
-                if let Some(db_err) = err.as_database_error() {
-                    if db_err.code().unwrap() == "1555" {
+                // If the error is a broken PK constraint, this is an
+                // account name clash
+                if let Some(db_err_code) = err.as_database_error().and_then(DatabaseError::code) {
+                    if db_err_code == "1555" {
                         // SQLITE_CONSTRAINT_PRIMARYKEY
                         return Error::from_kind(ErrorKind::UserAlreadyExists);
                     }
@@ -384,13 +1851,14 @@ impl GatewayService {
 
     pub async fn get_permissions(&self, account_name: &AccountName) -> Result<Permissions, Error> {
         let permissions =
-            query("SELECT super_user, account_tier FROM accounts WHERE account_name = ?1")
+            query("SELECT super_user, auditor, account_tier FROM accounts WHERE account_name = ?1")
                 .bind(account_name)
-                .fetch_optional(&self.db)
+                .fetch_optional(&mut *self.acquire().await?)
                 .await?
                 .map(|row| {
                     Permissions::builder()
                         .super_user(row.try_get("super_user").unwrap())
+                        .auditor(row.try_get("auditor").unwrap())
                         .tier(row.try_get("account_tier").unwrap())
                         .build()
                 })
@@ -406,7 +1874,20 @@ impl GatewayService {
         query("UPDATE accounts SET super_user = ?1 WHERE account_name = ?2")
             .bind(super_user)
             .bind(account_name)
-            .execute(&self.db)
+            .execute(&mut *self.acquire().await?)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn set_auditor(
+        &self,
+        account_name: &AccountName,
+        auditor: bool,
+    ) -> Result<(), Error> {
+        query("UPDATE accounts SET auditor = ?1 WHERE account_name = ?2")
+            .bind(auditor)
+            .bind(account_name)
+            .execute(&mut *self.acquire().await?)
             .await?;
         Ok(())
     }
@@ -416,95 +1897,1454 @@ impl GatewayService {
         account_name: &AccountName,
         permissions: &Permissions,
     ) -> Result<(), Error> {
-        query("UPDATE accounts SET super_user = ?1, account_tier = ?2 WHERE account_name = ?3")
-            .bind(permissions.super_user)
-            .bind(permissions.tier)
+        query(
+            "UPDATE accounts SET super_user = ?1, auditor = ?2, account_tier = ?3 WHERE account_name = ?4",
+        )
+        .bind(permissions.super_user)
+        .bind(permissions.auditor)
+        .bind(permissions.tier)
+        .bind(account_name)
+        .execute(&mut *self.acquire().await?)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn iter_user_projects(
+        &self,
+        AccountName(account_name): &AccountName,
+    ) -> Result<impl Iterator<Item = ProjectName>, Error> {
+        let iter = query("SELECT project_name FROM projects WHERE account_name = ?1")
             .bind(account_name)
-            .execute(&self.db)
+            .fetch_all(&mut *self.acquire().await?)
+            .await?
+            .into_iter()
+            .map(|row| row.try_get::<ProjectName, _>("project_name").unwrap());
+        Ok(iter)
+    }
+
+    pub async fn create_project(
+        &self,
+        project_name: ProjectName,
+        account_name: AccountName,
+    ) -> Result<Project, Error> {
+        let existing = query("SELECT project_name, account_name, initial_key, project_state FROM projects WHERE project_name = ?1 AND account_name = ?2")
+            .bind(&project_name)
+            .bind(&account_name)
+            .fetch_optional(&mut *self.acquire().await?)
             .await?;
-        Ok(())
+
+        if let Some(row) = existing {
+            // If the project already exists and belongs to this account
+            let project = row.get::<SqlxJson<Project>, _>("project_state").0;
+            if project.is_destroyed() {
+                // But is in `::Destroyed` state, recreate it
+                let project = Project::create(project_name.clone());
+                self.update_project(&project_name, &project).await?;
+                self.record_project_event(&project_name, EventKind::Created, None)
+                    .await;
+                Ok(project)
+            } else {
+                // Otherwise it already exists
+                Err(Error::from_kind(ErrorKind::ProjectAlreadyExists))
+            }
+        } else {
+            // Check if project name is valid according to new rules if it
+            // doesn't exist.
+            // TODO: remove this check when we update the project name rules
+            // in shuttle-common
+            match project_name.validate() {
+                // Aliases of other projects take up their names too
+                Ok(())
+                    if self.resolve_project_alias(project_name.clone()).await? != project_name =>
+                {
+                    Err(Error::from_kind(ErrorKind::ProjectAlreadyExists))
+                }
+                // Otherwise attempt to create a new one. This will fail
+                // outright if the project already exists (this happens if
+                // it belongs to another account).
+                Ok(()) => {
+                    let project = self
+                        .insert_project(project_name.clone(), account_name)
+                        .await?;
+                    self.record_project_event(&project_name, EventKind::Created, None)
+                        .await;
+                    Ok(project)
+                }
+                Err(err) => Err(Error::from_kind(ErrorKind::InvalidProjectName).with_detail(err)),
+            }
+        }
+    }
+
+    /// The tier of the account owning a project, which is the priority
+    /// of the project when the node is full
+    pub async fn project_tier(&self, project_name: &ProjectName) -> Result<AccountTier, Error> {
+        query("SELECT account_tier FROM projects JOIN accounts ON projects.account_name = accounts.account_name WHERE project_name = ?1")
+            .bind(project_name)
+            .fetch_optional(&mut *self.acquire().await?)
+            .await?
+            .map(|row| row.get("account_tier"))
+            .ok_or_else(|| Error::from_kind(ErrorKind::ProjectNotFound))
+    }
+
+    /// When the node is full, stop the least recently used running
+    /// project of a lower tier than `project_name` to make room for it.
+    /// Its owner hears about it through the webhook of the project, as
+    /// for any other change of state. Returns the project stopped, if any.
+    pub async fn make_room_for(
+        self: &Arc<Self>,
+        project_name: &ProjectName,
+        sender: &Sender<BoxedTask>,
+    ) -> Result<Option<ProjectName>, Error> {
+        let max = match self.context().container_settings().max_running_projects {
+            Some(max) => max,
+            None => return Ok(None),
+        };
+
+        let running: Vec<(ProjectName, AccountTier)> = query("SELECT project_name, project_state, account_tier FROM projects JOIN accounts ON projects.account_name = accounts.account_name")
+            .fetch_all(&mut *self.acquire().await?)
+            .await?
+            .into_iter()
+            .filter(|row| row.get::<SqlxJson<Project>, _>("project_state").0.is_running())
+            .map(|row| (row.get("project_name"), row.get("account_tier")))
+            .filter(|(name, _)| name != project_name)
+            .collect();
+        if running.len() < max {
+            return Ok(None);
+        }
+
+        let tier = self.project_tier(project_name).await?;
+        let activity = self.activity_tracker();
+        let evicted = match running
+            .into_iter()
+            .filter(|(_, other)| *other < tier)
+            .min_by_key(|(name, _)| activity.last_activity(name))
+        {
+            Some((evicted, _)) => evicted,
+            None => return Ok(None),
+        };
+
+        warn!(
+            %evicted,
+            for_project = %project_name,
+            ?tier,
+            "the node is full, stopping a project of a lower tier to make room"
+        );
+        self.new_task()
+            .project(evicted.clone())
+            .and_then(task::stop())
+            .send(sender)
+            .await?;
+
+        Ok(Some(evicted))
+    }
+
+    pub async fn is_project_name_available(
+        &self,
+        project_name: &ProjectName,
+    ) -> Result<bool, Error> {
+        let taken = query(
+            "SELECT project_name FROM projects WHERE project_name = ?1 UNION SELECT alias FROM project_aliases WHERE alias = ?1",
+        )
+        .bind(project_name)
+        .fetch_optional(&mut *self.acquire().await?)
+        .await?
+        .is_some();
+        Ok(!taken)
+    }
+
+    /// Have `alias` serve `project_name` as well. Aliases share their
+    /// names with projects, so they cannot be the name of another
+    /// project or alias.
+    pub async fn add_project_alias(
+        &self,
+        project_name: &ProjectName,
+        alias: &ProjectName,
+    ) -> Result<(), Error> {
+        alias
+            .validate()
+            .map_err(|err| Error::from_kind(ErrorKind::InvalidProjectName).with_detail(err))?;
+
+        if !self.is_project_name_available(alias).await? {
+            return Err(Error::from_kind(ErrorKind::ProjectAlreadyExists));
+        }
+
+        query("INSERT INTO project_aliases (alias, project_name) VALUES (?1, ?2)")
+            .bind(alias)
+            .bind(project_name)
+            .execute(&mut *self.acquire().await?)
+            .await
+            .map_err(|err| {
+                // Someone else took the alias in the meantime
+                if let Some(db_err_code) = err.as_database_error().and_then(DatabaseError::code) {
+                    if db_err_code == "1555" {
+                        return Error::from_kind(ErrorKind::ProjectAlreadyExists);
+                    }
+                }
+                err.into()
+            })?;
+
+        Ok(())
+    }
+
+    pub async fn iter_project_aliases(
+        &self,
+        project_name: &ProjectName,
+    ) -> Result<impl Iterator<Item = ProjectName>, Error> {
+        let iter =
+            query("SELECT alias FROM project_aliases WHERE project_name = ?1 ORDER BY alias")
+                .bind(project_name)
+                .fetch_all(&mut *self.acquire().await?)
+                .await?
+                .into_iter()
+                .map(|row| row.get("alias"));
+        Ok(iter)
+    }
+
+    pub async fn remove_project_alias(
+        &self,
+        project_name: &ProjectName,
+        alias: &ProjectName,
+    ) -> Result<(), Error> {
+        let result = query("DELETE FROM project_aliases WHERE alias = ?1 AND project_name = ?2")
+            .bind(alias)
+            .bind(project_name)
+            .execute(&mut *self.acquire().await?)
+            .await?;
+
+        if result.rows_affected() == 0 {
+            return Err(Error::from_kind(ErrorKind::AliasNotFound));
+        }
+
+        Ok(())
+    }
+
+    /// The project `name` refers to, which is `name` itself unless it
+    /// is an alias
+    pub async fn resolve_project_alias(&self, name: ProjectName) -> Result<ProjectName, Error> {
+        let project_name = query("SELECT project_name FROM project_aliases WHERE alias = ?1")
+            .bind(&name)
+            .fetch_optional(&mut *self.acquire().await?)
+            .await?
+            .map(|row| row.get("project_name"))
+            .unwrap_or(name);
+        Ok(project_name)
+    }
+
+    /// Come up with a few available alternatives to a project name
+    /// which is already taken
+    pub async fn suggest_project_names(
+        &self,
+        project_name: &ProjectName,
+    ) -> Result<Vec<ProjectName>, Error> {
+        let mut candidates: Vec<String> = (2..=4).map(|n| format!("{project_name}-{n}")).collect();
+        candidates.extend(
+            PROJECT_NAME_SUFFIXES
+                .iter()
+                .map(|suffix| format!("{project_name}-{suffix}")),
+        );
+
+        let mut suggestions = Vec::new();
+        for candidate in candidates {
+            if suggestions.len() == MAX_PROJECT_NAME_SUGGESTIONS {
+                break;
+            }
+
+            // Suggestions have to pass the same checks as any new name
+            if let Ok(candidate) = candidate.parse::<ProjectName>() {
+                if candidate.is_valid() && self.is_project_name_available(&candidate).await? {
+                    suggestions.push(candidate);
+                }
+            }
+        }
+
+        Ok(suggestions)
+    }
+
+    pub async fn insert_project(
+        &self,
+        project_name: ProjectName,
+        account_name: AccountName,
+    ) -> Result<Project, Error> {
+        let project = SqlxJson(Project::create(project_name.clone()));
+
+        query("INSERT INTO projects (project_name, account_name, initial_key, project_state) VALUES (?1, ?2, ?3, ?4)")
+            .bind(&project_name)
+            .bind(&account_name)
+            .bind(project.initial_key().unwrap())
+            .bind(&project)
+            .execute(&mut *self.acquire().await?)
+            .await
+            .map_err(|err| {
+                // If the error is a broken PK constraint, this is a
+                // project name clash
+                if let Some(db_err_code) = err.as_database_error().and_then(DatabaseError::code) {
+                    if db_err_code == "1555" {  // SQLITE_CONSTRAINT_PRIMARYKEY
+                        return Error::from_kind(ErrorKind::ProjectAlreadyExists)
+                    }
+                }
+                // Otherwise this is internal
+                err.into()
+            })?;
+
+        let project = project.0;
+
+        Ok(project)
+    }
+
+    /// Set (or override) environment variables for a project. They
+    /// are only picked up when the project's container is created.
+    pub async fn set_project_env(
+        &self,
+        project_name: &ProjectName,
+        vars: &BTreeMap<String, String>,
+    ) -> Result<(), Error> {
+        if let Some(name) = vars.keys().find(|name| !env::is_valid_name(name)) {
+            return Err(Error::custom(
+                ErrorKind::InvalidEnvVar,
+                format!("invalid env var name: {name}"),
+            ));
+        }
+
+        let mut conn = self.acquire().await?;
+        let mut transaction = conn.begin().await?;
+        for (name, value) in vars {
+            let sealed = self.env_cipher.encrypt(project_name, name, value)?;
+            query("INSERT OR REPLACE INTO project_env (project_name, name, value) VALUES (?1, ?2, ?3)")
+                .bind(project_name)
+                .bind(name)
+                .bind(sealed)
+                .execute(&mut transaction)
+                .await?;
+        }
+        transaction.commit().await?;
+
+        Ok(())
+    }
+
+    pub async fn remove_project_env(
+        &self,
+        project_name: &ProjectName,
+        name: &str,
+    ) -> Result<(), Error> {
+        query("DELETE FROM project_env WHERE project_name = ?1 AND name = ?2")
+            .bind(project_name)
+            .bind(name)
+            .execute(&mut *self.acquire().await?)
+            .await?;
+        Ok(())
+    }
+
+    /// The names of the environment variables set on a project
+    pub async fn iter_project_env_names(
+        &self,
+        project_name: &ProjectName,
+    ) -> Result<impl Iterator<Item = String>, Error> {
+        let iter = query("SELECT name FROM project_env WHERE project_name = ?1 ORDER BY name")
+            .bind(project_name)
+            .fetch_all(&mut *self.acquire().await?)
+            .await?
+            .into_iter()
+            .map(|row| row.get("name"));
+        Ok(iter)
+    }
+
+    /// The decrypted environment variables of a project. These should
+    /// never be sent back to a client.
+    pub async fn project_env(
+        &self,
+        project_name: &ProjectName,
+    ) -> Result<Vec<(String, String)>, Error> {
+        query("SELECT name, value FROM project_env WHERE project_name = ?1 ORDER BY name")
+            .bind(project_name)
+            .fetch_all(&mut *self.acquire().await?)
+            .await?
+            .into_iter()
+            .map(|row| {
+                let name: String = row.get("name");
+                let value: Vec<u8> = row.get("value");
+                self.env_cipher
+                    .decrypt(project_name, &name, &value)
+                    .map(|value| (name, value))
+            })
+            .collect()
+    }
+
+    /// Store the token used to fetch the private repositories a project
+    /// is deployed from
+    pub async fn set_project_git_token(
+        &self,
+        project_name: &ProjectName,
+        token: &str,
+    ) -> Result<(), Error> {
+        let sealed = self
+            .env_cipher
+            .encrypt(project_name, GIT_TOKEN_NAME, token)?;
+
+        query("INSERT OR REPLACE INTO project_git_tokens (project_name, token) VALUES (?1, ?2)")
+            .bind(project_name)
+            .bind(sealed)
+            .execute(&mut *self.acquire().await?)
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn remove_project_git_token(&self, project_name: &ProjectName) -> Result<(), Error> {
+        query("DELETE FROM project_git_tokens WHERE project_name = ?1")
+            .bind(project_name)
+            .execute(&mut *self.acquire().await?)
+            .await?;
+
+        Ok(())
+    }
+
+    /// The decrypted Git token of a project. It should never be sent
+    /// back to a client.
+    pub async fn project_git_token(
+        &self,
+        project_name: &ProjectName,
+    ) -> Result<Option<String>, Error> {
+        query("SELECT token FROM project_git_tokens WHERE project_name = ?1")
+            .bind(project_name)
+            .fetch_optional(&mut *self.acquire().await?)
+            .await?
+            .map(|row| {
+                let sealed: Vec<u8> = row.get("token");
+                self.env_cipher
+                    .decrypt(project_name, GIT_TOKEN_NAME, &sealed)
+            })
+            .transpose()
+    }
+
+    /// Set the webhook of a project, replacing any previous one along
+    /// with its secret. The webhook has to point at a public address.
+    pub async fn set_project_webhook(
+        &self,
+        project_name: &ProjectName,
+        url: String,
+    ) -> Result<Webhook, Error> {
+        let webhook = Webhook::new(url)?;
+        webhook.resolve(self.private_webhooks).await?;
+
+        let sealed = self
+            .env_cipher
+            .encrypt(project_name, WEBHOOK_SECRET_NAME, &webhook.secret)?;
+        query("INSERT OR REPLACE INTO project_webhooks (project_name, url, secret, sealed_secret) VALUES (?1, ?2, '', ?3)")
+            .bind(project_name)
+            .bind(&webhook.url)
+            .bind(sealed)
+            .execute(&mut *self.acquire().await?)
+            .await?;
+
+        Ok(webhook)
+    }
+
+    pub async fn find_project_webhook(
+        &self,
+        project_name: &ProjectName,
+    ) -> Result<Option<Webhook>, Error> {
+        query("SELECT url, secret, sealed_secret FROM project_webhooks WHERE project_name = ?1")
+            .bind(project_name)
+            .fetch_optional(&mut *self.acquire().await?)
+            .await?
+            .map(|row| {
+                // Secrets set before they were sealed are still in the
+                // clear until the gateway next starts
+                let secret = match row.get::<Option<Vec<u8>>, _>("sealed_secret") {
+                    Some(sealed) => {
+                        self.env_cipher
+                            .decrypt(project_name, WEBHOOK_SECRET_NAME, &sealed)?
+                    }
+                    None => row.get("secret"),
+                };
+                Ok(Webhook {
+                    url: row.get("url"),
+                    secret,
+                })
+            })
+            .transpose()
+    }
+
+    /// Seal the webhook secrets which are still stored in the clear.
+    /// Returns how many there were.
+    pub async fn seal_webhook_secrets(&self) -> Result<u64, Error> {
+        let mut conn = self.acquire().await?;
+        let mut transaction = conn.begin().await?;
+
+        let rows =
+            query("SELECT project_name, secret FROM project_webhooks WHERE sealed_secret IS NULL")
+                .fetch_all(&mut transaction)
+                .await?;
+
+        let mut sealed = 0;
+        for row in rows {
+            let project_name: ProjectName = row.get("project_name");
+            let secret: String = row.get("secret");
+            let sealed_secret =
+                self.env_cipher
+                    .encrypt(&project_name, WEBHOOK_SECRET_NAME, &secret)?;
+            sealed += query("UPDATE project_webhooks SET secret = '', sealed_secret = ?1 WHERE project_name = ?2")
+                .bind(sealed_secret)
+                .bind(&project_name)
+                .execute(&mut transaction)
+                .await?
+                .rows_affected();
+        }
+
+        transaction.commit().await?;
+
+        Ok(sealed)
+    }
+
+    pub async fn remove_project_webhook(&self, project_name: &ProjectName) -> Result<(), Error> {
+        query("DELETE FROM project_webhooks WHERE project_name = ?1")
+            .bind(project_name)
+            .execute(&mut *self.acquire().await?)
+            .await?;
+        Ok(())
+    }
+
+    /// Queue the delivery of a state change to the project's webhook,
+    /// if it has one. Failures are only logged.
+    pub async fn notify_project_state(&self, project_name: &ProjectName, project: &Project) {
+        let webhook = match self.find_project_webhook(project_name).await {
+            Ok(Some(webhook)) => webhook,
+            Ok(None) => return,
+            Err(err) => {
+                warn!(%project_name, error = %err, "could not look up project webhook");
+                return;
+            }
+        };
+
+        let task = match DeliverWebhook::new(
+            project_name.clone(),
+            webhook,
+            project,
+            self.private_webhooks,
+        ) {
+            Ok(task) => task,
+            Err(err) => {
+                warn!(%project_name, error = %err, "could not prepare webhook delivery");
+                return;
+            }
+        };
+
+        if self
+            .webhook_router
+            .route(project_name, Box::new(task))
+            .await
+            .is_err()
+        {
+            warn!(%project_name, "could not queue webhook delivery");
+        }
+    }
+
+    /// Add an entry to the activity feed of a project. The feed is only
+    /// informative, so failures are only logged.
+    pub async fn record_project_event(
+        &self,
+        project_name: &ProjectName,
+        kind: EventKind,
+        detail: Option<String>,
+    ) {
+        let res = query(
+            "INSERT INTO project_events (project_name, at, kind, detail) VALUES (?1, ?2, ?3, ?4)",
+        )
+        .bind(project_name)
+        .bind(Utc::now().to_rfc3339())
+        .bind(kind.to_string())
+        .bind(detail)
+        .execute(&self.db)
+        .await;
+
+        if let Err(err) = res {
+            warn!(%project_name, %kind, error = %err, "could not record project event");
+        }
+    }
+
+    /// Add the change of a project to the `project` state to its
+    /// activity feed, if it is one users care about
+    pub async fn record_project_state(&self, project_name: &ProjectName, project: &Project) {
+        let (kind, detail) = match project {
+            Project::Ready(_) => {
+                let started_before = query(
+                    "SELECT id FROM project_events WHERE project_name = ?1 AND kind IN (?2, ?3) LIMIT 1",
+                )
+                .bind(project_name)
+                .bind(EventKind::Started.to_string())
+                .bind(EventKind::Restarted.to_string())
+                .fetch_optional(&self.db)
+                .await
+                .map(|row| row.is_some())
+                .unwrap_or_default();
+
+                if started_before {
+                    (EventKind::Restarted, None)
+                } else {
+                    (EventKind::Started, None)
+                }
+            }
+            Project::Stopped(_) => (EventKind::Stopped, None),
+            Project::Errored(err) => (EventKind::Crashed, Some(err.to_string())),
+            Project::Destroyed(_) => (EventKind::Destroyed, None),
+            _ => return,
+        };
+
+        self.record_project_event(project_name, kind, detail).await;
+    }
+
+    /// When a project last crashed, going by its activity feed
+    pub async fn project_crashed_at(
+        &self,
+        project_name: &ProjectName,
+    ) -> Result<Option<DateTime<Utc>>, Error> {
+        let at = query(
+            "SELECT at FROM project_events WHERE project_name = ?1 AND kind = ?2 ORDER BY id DESC LIMIT 1",
+        )
+        .bind(project_name)
+        .bind(EventKind::Crashed.to_string())
+        .fetch_optional(&mut *self.acquire().await?)
+        .await?
+        .map(|row| {
+            DateTime::parse_from_rfc3339(row.get("at"))
+                .expect("project event times to be RFC 3339")
+                .with_timezone(&Utc)
+        });
+
+        Ok(at)
+    }
+
+    /// A page of the activity feed of a project, newest first. Only
+    /// events older than the one with the `before` id are listed if given.
+    pub async fn list_project_events(
+        &self,
+        project_name: &ProjectName,
+        before: Option<i64>,
+        limit: u32,
+    ) -> Result<Vec<Event>, Error> {
+        let events = query(
+            "SELECT id, at, kind, detail FROM project_events WHERE project_name = ?1 AND id < ?2 ORDER BY id DESC LIMIT ?3",
+        )
+        .bind(project_name)
+        .bind(before.unwrap_or(i64::MAX))
+        .bind(limit)
+        .fetch_all(&mut *self.acquire().await?)
+        .await?
+        .into_iter()
+        .map(|row| Event {
+            id: row.get("id"),
+            at: DateTime::parse_from_rfc3339(row.get("at"))
+                .expect("project event times to be RFC 3339")
+                .with_timezone(&Utc),
+            kind: row
+                .get::<&str, _>("kind")
+                .parse()
+                .expect("project event kinds to be known"),
+            detail: row.get("detail"),
+        })
+        .collect();
+
+        Ok(events)
+    }
+
+    /// Limit how many requests the proxy lets through to a project.
+    /// The new limit applies right away.
+    pub async fn set_project_rate_limit(
+        &self,
+        project_name: &ProjectName,
+        limit: RateLimit,
+    ) -> Result<(), Error> {
+        if limit.requests_per_second == 0 || limit.burst == 0 {
+            return Err(Error::from_kind(ErrorKind::InvalidRateLimit));
+        }
+
+        query("INSERT OR REPLACE INTO project_rate_limits (project_name, requests_per_second, burst) VALUES (?1, ?2, ?3)")
+            .bind(project_name)
+            .bind(limit.requests_per_second)
+            .bind(limit.burst)
+            .execute(&mut *self.acquire().await?)
+            .await?;
+
+        self.rate_limiter.set_limit(project_name, Some(limit));
+
+        Ok(())
+    }
+
+    pub async fn remove_project_rate_limit(&self, project_name: &ProjectName) -> Result<(), Error> {
+        query("DELETE FROM project_rate_limits WHERE project_name = ?1")
+            .bind(project_name)
+            .execute(&mut *self.acquire().await?)
+            .await?;
+
+        self.rate_limiter.set_limit(project_name, None);
+
+        Ok(())
+    }
+
+    pub fn rate_limiter(&self) -> &RateLimiter {
+        &self.rate_limiter
+    }
+
+    /// Take a backup of the whole state of the gateway
+    pub async fn backup(&self) -> Result<Backup, Error> {
+        backup::export(&self.db).await
+    }
+
+    pub async fn set_project_connection_limit(
+        &self,
+        project_name: &ProjectName,
+        limit: ConnectionLimit,
+    ) -> Result<(), Error> {
+        if limit.max_connections == 0 {
+            return Err(Error::from_kind(ErrorKind::InvalidConnectionLimit));
+        }
+
+        query("INSERT OR REPLACE INTO project_connection_limits (project_name, max_connections) VALUES (?1, ?2)")
+            .bind(project_name)
+            .bind(limit.max_connections)
+            .execute(&mut *self.acquire().await?)
+            .await?;
+
+        self.connection_limiter.set_limit(project_name, Some(limit));
+
+        Ok(())
+    }
+
+    pub async fn remove_project_connection_limit(
+        &self,
+        project_name: &ProjectName,
+    ) -> Result<(), Error> {
+        query("DELETE FROM project_connection_limits WHERE project_name = ?1")
+            .bind(project_name)
+            .execute(&mut *self.acquire().await?)
+            .await?;
+
+        self.connection_limiter.set_limit(project_name, None);
+
+        Ok(())
+    }
+
+    pub fn connection_limiter(&self) -> &ConnectionLimiter {
+        &self.connection_limiter
+    }
+
+    /// Override how long the deployments of a project have to start. It
+    /// is handed to the deployer of the project when its container is
+    /// next created.
+    pub async fn set_project_deployment_timeout(
+        &self,
+        project_name: &ProjectName,
+        timeout: DeploymentTimeout,
+    ) -> Result<(), Error> {
+        if timeout.timeout_secs == 0 {
+            return Err(Error::from_kind(ErrorKind::InvalidDeploymentTimeout));
+        }
+
+        query("INSERT OR REPLACE INTO project_deployment_timeouts (project_name, timeout_secs) VALUES (?1, ?2)")
+            .bind(project_name)
+            .bind(timeout.timeout_secs)
+            .execute(&mut *self.acquire().await?)
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn remove_project_deployment_timeout(
+        &self,
+        project_name: &ProjectName,
+    ) -> Result<(), Error> {
+        query("DELETE FROM project_deployment_timeouts WHERE project_name = ?1")
+            .bind(project_name)
+            .execute(&mut *self.acquire().await?)
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn project_deployment_timeout(
+        &self,
+        project_name: &ProjectName,
+    ) -> Result<Option<DeploymentTimeout>, Error> {
+        Ok(
+            query("SELECT timeout_secs FROM project_deployment_timeouts WHERE project_name = ?1")
+                .bind(project_name)
+                .fetch_optional(&mut *self.acquire().await?)
+                .await?
+                .map(|row| DeploymentTimeout {
+                    timeout_secs: row.get("timeout_secs"),
+                }),
+        )
+    }
+
+    /// Set the resource limits every project of an account gets,
+    /// unless it has limits of its own
+    pub async fn set_account_resource_limits(
+        &self,
+        account_name: &AccountName,
+        limits: ResourceLimits,
+    ) -> Result<(), Error> {
+        check_resource_limits(&limits)?;
+
+        query("INSERT OR REPLACE INTO account_resource_limits (account_name, memory_mib, millicpus) VALUES (?1, ?2, ?3)")
+            .bind(account_name)
+            .bind(limits.memory_mib)
+            .bind(limits.millicpus)
+            .execute(&mut *self.acquire().await?)
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn remove_account_resource_limits(
+        &self,
+        account_name: &AccountName,
+    ) -> Result<(), Error> {
+        query("DELETE FROM account_resource_limits WHERE account_name = ?1")
+            .bind(account_name)
+            .execute(&mut *self.acquire().await?)
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn account_resource_limits(
+        &self,
+        account_name: &AccountName,
+    ) -> Result<Option<ResourceLimits>, Error> {
+        Ok(query(
+            "SELECT memory_mib, millicpus FROM account_resource_limits WHERE account_name = ?1",
+        )
+        .bind(account_name)
+        .fetch_optional(&mut *self.acquire().await?)
+        .await?
+        .map(|row| ResourceLimits {
+            memory_mib: row.get("memory_mib"),
+            millicpus: row.get("millicpus"),
+        }))
+    }
+
+    /// Override the resource limits of a project, whatever the default
+    /// of its account
+    pub async fn set_project_resource_limits(
+        &self,
+        project_name: &ProjectName,
+        limits: ResourceLimits,
+    ) -> Result<(), Error> {
+        check_resource_limits(&limits)?;
+
+        query("INSERT OR REPLACE INTO project_resource_limits (project_name, memory_mib, millicpus) VALUES (?1, ?2, ?3)")
+            .bind(project_name)
+            .bind(limits.memory_mib)
+            .bind(limits.millicpus)
+            .execute(&mut *self.acquire().await?)
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn remove_project_resource_limits(
+        &self,
+        project_name: &ProjectName,
+    ) -> Result<(), Error> {
+        query("DELETE FROM project_resource_limits WHERE project_name = ?1")
+            .bind(project_name)
+            .execute(&mut *self.acquire().await?)
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn project_resource_limits(
+        &self,
+        project_name: &ProjectName,
+    ) -> Result<Option<ResourceLimits>, Error> {
+        Ok(query(
+            "SELECT memory_mib, millicpus FROM project_resource_limits WHERE project_name = ?1",
+        )
+        .bind(project_name)
+        .fetch_optional(&mut *self.acquire().await?)
+        .await?
+        .map(|row| ResourceLimits {
+            memory_mib: row.get("memory_mib"),
+            millicpus: row.get("millicpus"),
+        }))
+    }
+
+    /// The resource limits the container of a project is created with:
+    /// its own if it has any, or else the default of its account. It
+    /// is `None` when neither is set, for the platform default to apply.
+    pub async fn effective_resource_limits(
+        &self,
+        project_name: &ProjectName,
+    ) -> Result<Option<ResourceLimits>, Error> {
+        if let Some(limits) = self.project_resource_limits(project_name).await? {
+            return Ok(Some(limits));
+        }
+
+        let account_name = self.account_name_from_project(project_name).await?;
+        self.account_resource_limits(&account_name).await
+    }
+
+    pub async fn set_project_header_rules(
+        &self,
+        project_name: &ProjectName,
+        rules: HeaderRules,
+    ) -> Result<(), Error> {
+        let rewrites = HeaderRewrites::new(rules.clone())?;
+
+        query("INSERT OR REPLACE INTO project_header_rules (project_name, rules) VALUES (?1, ?2)")
+            .bind(project_name)
+            .bind(SqlxJson(rules))
+            .execute(&mut *self.acquire().await?)
+            .await?;
+
+        self.header_rewriter
+            .set_rewrites(project_name, Some(rewrites));
+
+        Ok(())
+    }
+
+    pub async fn remove_project_header_rules(
+        &self,
+        project_name: &ProjectName,
+    ) -> Result<(), Error> {
+        query("DELETE FROM project_header_rules WHERE project_name = ?1")
+            .bind(project_name)
+            .execute(&mut *self.acquire().await?)
+            .await?;
+
+        self.header_rewriter.set_rewrites(project_name, None);
+
+        Ok(())
+    }
+
+    /// Copy the configuration of `source` over to `target`: the names
+    /// of its env vars, its header rules and its rate limit. Secrets
+    /// are left behind, so env vars are copied without their values
+    /// and webhooks are not copied at all.
+    pub async fn copy_project_config(
+        &self,
+        source: &ProjectName,
+        target: &ProjectName,
+    ) -> Result<(), Error> {
+        let vars = self
+            .iter_project_env_names(source)
+            .await?
+            .map(|name| (name, String::new()))
+            .collect();
+        self.set_project_env(target, &vars).await?;
+
+        if let Some(rewrites) = self.header_rewriter.rewrites(source) {
+            self.set_project_header_rules(target, rewrites.rules.clone())
+                .await?;
+        }
+
+        if let Some(limit) = self.rate_limiter.limit(source) {
+            self.set_project_rate_limit(target, limit).await?;
+        }
+
+        if let Some(limit) = self.connection_limiter.limit(source) {
+            self.set_project_connection_limit(target, limit).await?;
+        }
+
+        if let Some(rules) = self.ip_filters.rules(source) {
+            self.set_project_ip_filter(target, rules.filter.clone())
+                .await?;
+        }
+
+        if let Some(config) = self.response_cache.config(source) {
+            self.set_project_response_cache(target, config).await?;
+        }
+
+        if let Some(page) = self.project_maintenance_page(source) {
+            self.set_project_maintenance_page(target, page).await?;
+        }
+
+        let tags = self.project_tags(source).await?;
+        if !tags.is_empty() {
+            self.set_project_tags(target, &tags).await?;
+        }
+
+        self.set_project_upstream_protocol(target, self.project_upstream_protocol(source))
+            .await?;
+
+        Ok(())
+    }
+
+    pub fn header_rewriter(&self) -> &HeaderRewriter {
+        &self.header_rewriter
+    }
+
+    pub async fn set_project_ip_filter(
+        &self,
+        project_name: &ProjectName,
+        filter: IpFilter,
+    ) -> Result<(), Error> {
+        let rules = IpRules::new(filter.clone())?;
+
+        query("INSERT OR REPLACE INTO project_ip_filters (project_name, filter) VALUES (?1, ?2)")
+            .bind(project_name)
+            .bind(SqlxJson(filter))
+            .execute(&mut *self.acquire().await?)
+            .await?;
+
+        self.ip_filters.set_rules(project_name, Some(rules));
+
+        Ok(())
+    }
+
+    pub async fn remove_project_ip_filter(&self, project_name: &ProjectName) -> Result<(), Error> {
+        query("DELETE FROM project_ip_filters WHERE project_name = ?1")
+            .bind(project_name)
+            .execute(&mut *self.acquire().await?)
+            .await?;
+
+        self.ip_filters.set_rules(project_name, None);
+
+        Ok(())
+    }
+
+    pub fn ip_filters(&self) -> &IpFilters {
+        &self.ip_filters
+    }
+
+    /// Send part of the traffic of a project to another project of the
+    /// same account, or change how much of it goes there
+    pub async fn set_project_canary(
+        &self,
+        project_name: &ProjectName,
+        canary: Canary,
+    ) -> Result<(), Error> {
+        let split = CanarySplit::new(canary.clone())?;
+        if &split.project == project_name {
+            return Err(Error::custom(
+                ErrorKind::InvalidCanary,
+                "a project cannot be its own canary",
+            ));
+        }
+
+        let owner = self.account_name_from_project(project_name).await?;
+        match self.account_name_from_project(&split.project).await {
+            Ok(account_name) if account_name == owner => {}
+            Ok(_) => {
+                return Err(Error::custom(
+                    ErrorKind::InvalidCanary,
+                    "the canary belongs to another account",
+                ))
+            }
+            Err(err) if err.kind() == ErrorKind::ProjectNotFound => {
+                return Err(Error::custom(
+                    ErrorKind::InvalidCanary,
+                    format!("there is no project named {}", split.project),
+                ))
+            }
+            Err(err) => return Err(err),
+        }
+
+        query("INSERT OR REPLACE INTO project_canaries (project_name, canary, weight, sticky) VALUES (?1, ?2, ?3, ?4)")
+            .bind(project_name)
+            .bind(&canary.project)
+            .bind(canary.weight)
+            .bind(canary.sticky)
+            .execute(&mut *self.acquire().await?)
+            .await?;
+
+        self.canaries.set_split(project_name, Some(split));
+
+        Ok(())
+    }
+
+    /// Send all the traffic of a project to its canary
+    pub async fn promote_project_canary(
+        &self,
+        project_name: &ProjectName,
+    ) -> Result<Canary, Error> {
+        let mut canary = self
+            .canaries
+            .split(project_name)
+            .map(|split| split.canary.clone())
+            .ok_or_else(|| {
+                Error::custom(
+                    ErrorKind::InvalidCanary,
+                    "the project has no canary to promote",
+                )
+            })?;
+        canary.weight = 100;
+
+        self.set_project_canary(project_name, canary.clone())
+            .await?;
+
+        Ok(canary)
+    }
+
+    /// Roll back to the project getting all its traffic
+    pub async fn remove_project_canary(&self, project_name: &ProjectName) -> Result<(), Error> {
+        query("DELETE FROM project_canaries WHERE project_name = ?1")
+            .bind(project_name)
+            .execute(&mut *self.acquire().await?)
+            .await?;
+
+        self.canaries.set_split(project_name, None);
+
+        Ok(())
+    }
+
+    pub fn canaries(&self) -> &Canaries {
+        &self.canaries
+    }
+
+    /// Turn on the response cache of a project, or change its size.
+    /// Whatever it kept so far is let go of.
+    pub async fn set_project_response_cache(
+        &self,
+        project_name: &ProjectName,
+        config: ResponseCacheConfig,
+    ) -> Result<(), Error> {
+        respcache::validate_config(&config)?;
+
+        query("INSERT OR REPLACE INTO project_response_caches (project_name, max_entries) VALUES (?1, ?2)")
+            .bind(project_name)
+            .bind(config.max_entries)
+            .execute(&mut *self.acquire().await?)
+            .await?;
+
+        self.response_cache.set_config(project_name, Some(config));
+
+        Ok(())
+    }
+
+    pub async fn remove_project_response_cache(
+        &self,
+        project_name: &ProjectName,
+    ) -> Result<(), Error> {
+        query("DELETE FROM project_response_caches WHERE project_name = ?1")
+            .bind(project_name)
+            .execute(&mut *self.acquire().await?)
+            .await?;
+
+        self.response_cache.set_config(project_name, None);
+
+        Ok(())
+    }
+
+    pub fn response_cache(&self) -> &ResponseCache {
+        &self.response_cache
+    }
+
+    /// Have the proxy answer visitors of a project with `page` while it
+    /// is being deployed, rather than with whatever error it runs into
+    pub async fn set_project_maintenance_page(
+        &self,
+        project_name: &ProjectName,
+        page: MaintenancePage,
+    ) -> Result<(), Error> {
+        if page.body.is_empty()
+            || page.body.len() > MAX_MAINTENANCE_PAGE_SIZE
+            || page.retry_after_secs == 0
+        {
+            return Err(Error::from_kind(ErrorKind::InvalidMaintenancePage));
+        }
+
+        query("INSERT OR REPLACE INTO project_maintenance_pages (project_name, body, retry_after_secs) VALUES (?1, ?2, ?3)")
+            .bind(project_name)
+            .bind(&page.body)
+            .bind(page.retry_after_secs)
+            .execute(&mut *self.acquire().await?)
+            .await?;
+
+        self.maintenance_pages
+            .write()
+            .unwrap()
+            .insert(project_name.clone(), page);
+
+        Ok(())
+    }
+
+    pub async fn remove_project_maintenance_page(
+        &self,
+        project_name: &ProjectName,
+    ) -> Result<(), Error> {
+        query("DELETE FROM project_maintenance_pages WHERE project_name = ?1")
+            .bind(project_name)
+            .execute(&mut *self.acquire().await?)
+            .await?;
+
+        self.maintenance_pages.write().unwrap().remove(project_name);
+
+        Ok(())
+    }
+
+    pub fn project_maintenance_page(&self, project_name: &ProjectName) -> Option<MaintenancePage> {
+        self.maintenance_pages
+            .read()
+            .unwrap()
+            .get(project_name)
+            .cloned()
+    }
+
+    /// Replace the tags of a project, returning them as stored
+    pub async fn set_project_tags(
+        &self,
+        project_name: &ProjectName,
+        tags: &[String],
+    ) -> Result<Vec<String>, Error> {
+        let tags = tags
+            .iter()
+            .map(|tag| parse_project_tag(tag))
+            .collect::<Result<BTreeSet<_>, _>>()?;
+        if tags.len() > MAX_TAGS_PER_PROJECT {
+            return Err(Error::from_kind(ErrorKind::InvalidProjectTag)
+                .with_detail(format!("{} tags", tags.len())));
+        }
+
+        let mut conn = self.acquire().await?;
+        let mut transaction = conn.begin().await?;
+        query("DELETE FROM project_tags WHERE project_name = ?1")
+            .bind(project_name)
+            .execute(&mut transaction)
+            .await?;
+        for tag in &tags {
+            query("INSERT INTO project_tags (project_name, tag) VALUES (?1, ?2)")
+                .bind(project_name)
+                .bind(tag)
+                .execute(&mut transaction)
+                .await?;
+        }
+        transaction.commit().await?;
+
+        Ok(tags.into_iter().collect())
+    }
+
+    pub async fn remove_project_tag(
+        &self,
+        project_name: &ProjectName,
+        tag: &str,
+    ) -> Result<(), Error> {
+        query("DELETE FROM project_tags WHERE project_name = ?1 AND tag = ?2")
+            .bind(project_name)
+            .bind(parse_project_tag(tag)?)
+            .execute(&mut *self.acquire().await?)
+            .await?;
+
+        Ok(())
+    }
+
+    /// The tags of a project, in order
+    pub async fn project_tags(&self, project_name: &ProjectName) -> Result<Vec<String>, Error> {
+        let tags = query("SELECT tag FROM project_tags WHERE project_name = ?1 ORDER BY tag")
+            .bind(project_name)
+            .fetch_all(&mut *self.acquire().await?)
+            .await?
+            .into_iter()
+            .map(|row| row.get("tag"))
+            .collect();
+
+        Ok(tags)
+    }
+
+    /// The projects of `account_name` tagged with `tag`
+    pub async fn iter_user_projects_tagged(
+        &self,
+        account_name: &AccountName,
+        tag: &str,
+    ) -> Result<impl Iterator<Item = ProjectName>, Error> {
+        let iter = query(
+            "SELECT projects.project_name FROM projects JOIN project_tags ON projects.project_name = project_tags.project_name WHERE account_name = ?1 AND tag = ?2",
+        )
+        .bind(account_name)
+        .bind(parse_project_tag(tag)?)
+        .fetch_all(&mut *self.acquire().await?)
+        .await?
+        .into_iter()
+        .map(|row| row.get("project_name"));
+
+        Ok(iter)
+    }
+
+    /// How the proxy talks to a project. Switching protocols takes
+    /// effect for the next request.
+    pub async fn set_project_upstream_protocol(
+        &self,
+        project_name: &ProjectName,
+        protocol: UpstreamProtocol,
+    ) -> Result<(), Error> {
+        match protocol {
+            UpstreamProtocol::Http1 => {
+                query("DELETE FROM project_upstreams WHERE project_name = ?1")
+                    .bind(project_name)
+                    .execute(&mut *self.acquire().await?)
+                    .await?;
+                self.upstream_protocols
+                    .write()
+                    .unwrap()
+                    .remove(project_name);
+            }
+            protocol => {
+                query("INSERT OR REPLACE INTO project_upstreams (project_name, protocol) VALUES (?1, ?2)")
+                    .bind(project_name)
+                    .bind(protocol.to_string())
+                    .execute(&mut *self.acquire().await?)
+                    .await?;
+                self.upstream_protocols
+                    .write()
+                    .unwrap()
+                    .insert(project_name.clone(), protocol);
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn project_upstream_protocol(&self, project_name: &ProjectName) -> UpstreamProtocol {
+        if self
+            .feature_flags
+            .is_enabled(project_name, FeatureFlag::Http2Upstream)
+        {
+            return UpstreamProtocol::Http2;
+        }
+
+        self.upstream_protocols
+            .read()
+            .unwrap()
+            .get(project_name)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Have the proxy ask for `username` and `password` before letting
+    /// requests through to a project. Only a hash of the password is
+    /// stored.
+    pub async fn set_project_basic_auth(
+        &self,
+        project_name: &ProjectName,
+        username: String,
+        password: &str,
+    ) -> Result<(), Error> {
+        let auth = BasicAuth::new(username, password)?;
+
+        query("INSERT OR REPLACE INTO project_basic_auth (project_name, username, password_hash) VALUES (?1, ?2, ?3)")
+            .bind(project_name)
+            .bind(&auth.username)
+            .bind(&auth.password_hash)
+            .execute(&mut *self.acquire().await?)
+            .await?;
+
+        self.basic_auth_gate.set_auth(project_name, Some(auth));
+
+        Ok(())
+    }
+
+    pub async fn remove_project_basic_auth(&self, project_name: &ProjectName) -> Result<(), Error> {
+        query("DELETE FROM project_basic_auth WHERE project_name = ?1")
+            .bind(project_name)
+            .execute(&mut *self.acquire().await?)
+            .await?;
+
+        self.basic_auth_gate.set_auth(project_name, None);
+
+        Ok(())
+    }
+
+    pub fn basic_auth_gate(&self) -> &BasicAuthGate {
+        &self.basic_auth_gate
+    }
+
+    /// Turn an experimental behaviour on or off for a project. Takes
+    /// effect for the next request.
+    pub async fn set_project_feature_flag(
+        &self,
+        project_name: &ProjectName,
+        flag: FeatureFlag,
+        enabled: bool,
+    ) -> Result<(), Error> {
+        if enabled {
+            query(
+                "INSERT OR IGNORE INTO project_feature_flags (project_name, flag) VALUES (?1, ?2)",
+            )
+            .bind(project_name)
+            .bind(flag.to_string())
+            .execute(&mut *self.acquire().await?)
+            .await?;
+        } else {
+            query("DELETE FROM project_feature_flags WHERE project_name = ?1 AND flag = ?2")
+                .bind(project_name)
+                .bind(flag.to_string())
+                .execute(&mut *self.acquire().await?)
+                .await?;
+        }
+
+        self.feature_flags.set(project_name, flag, enabled);
+
+        Ok(())
+    }
+
+    pub fn feature_flags(&self) -> &FeatureFlags {
+        &self.feature_flags
     }
 
-    pub async fn iter_user_projects(
-        &self,
-        AccountName(account_name): &AccountName,
-    ) -> Result<impl Iterator<Item = ProjectName>, Error> {
-        let iter = query("SELECT project_name FROM projects WHERE account_name = ?1")
-            .bind(account_name)
-            .fetch_all(&self.db)
-            .await?
-            .into_iter()
-            .map(|row| row.try_get::<ProjectName, _>("project_name").unwrap());
-        Ok(iter)
+    pub fn activity_tracker(&self) -> &ActivityTracker {
+        &self.activity_tracker
     }
 
-    pub async fn create_project(
-        &self,
-        project_name: ProjectName,
-        account_name: AccountName,
-    ) -> Result<Project, Error> {
-        if let Some(row) = query("SELECT project_name, account_name, initial_key, project_state FROM projects WHERE project_name = ?1 AND account_name = ?2")
-            .bind(&project_name)
-            .bind(&account_name)
-            .fetch_optional(&self.db)
+    pub fn counters(&self) -> &PlatformCounters {
+        &self.counters
+    }
+
+    pub fn traffic(&self) -> &TrafficCounters {
+        &self.traffic
+    }
+
+    pub async fn count_accounts(&self) -> Result<u64, Error> {
+        let count: i64 = query("SELECT COUNT(*) AS count FROM accounts")
+            .fetch_one(&mut *self.acquire().await?)
             .await?
-        {
-            // If the project already exists and belongs to this account
-            let project = row.get::<SqlxJson<Project>, _>("project_state").0;
-            if project.is_destroyed() {
-                // But is in `::Destroyed` state, recreate it
-                let project = Project::create(project_name.clone());
-                self.update_project(&project_name, &project).await?;
-                Ok(project)
-            } else {
-                // Otherwise it already exists
-                Err(Error::from_kind(ErrorKind::ProjectAlreadyExists))
-            }
-        } else {
-            // Check if project name is valid according to new rules if it
-            // doesn't exist.
-            // TODO: remove this check when we update the project name rules
-            // in shuttle-common
-            if project_name.is_valid() {
-                // Otherwise attempt to create a new one. This will fail
-                // outright if the project already exists (this happens if
-                // it belongs to another account).
-                self.insert_project(project_name, account_name).await
-            } else {
-                Err(Error::from_kind(ErrorKind::InvalidProjectName))
-            }
-        }
+            .get("count");
+        Ok(count as u64)
     }
 
-    pub async fn insert_project(
-        &self,
-        project_name: ProjectName,
-        account_name: AccountName,
-    ) -> Result<Project, Error> {
-        let project = SqlxJson(Project::create(project_name.clone()));
+    /// How many projects are in each state, keyed by the name of the state
+    pub async fn count_projects_by_state(&self) -> Result<BTreeMap<String, u64>, Error> {
+        let counts = query(
+            "SELECT (SELECT key FROM json_each(project_state)) AS state, COUNT(*) AS count FROM projects GROUP BY state",
+        )
+        .fetch_all(&mut *self.acquire().await?)
+        .await?
+        .into_iter()
+        .map(|row| (row.get("state"), row.get::<i64, _>("count") as u64))
+        .collect();
+        Ok(counts)
+    }
 
-        query("INSERT INTO projects (project_name, account_name, initial_key, project_state) VALUES (?1, ?2, ?3, ?4)")
-            .bind(&project_name)
-            .bind(&account_name)
-            .bind(project.initial_key().unwrap())
-            .bind(&project)
-            .execute(&self.db)
+    /// Hold the deployments of `project_name` for as long as the
+    /// returned guard lives
+    pub async fn lock_deployments(&self, project_name: &ProjectName) -> Result<DeployGuard, Error> {
+        self.deploy_locks.lock(project_name).await
+    }
+
+    /// Like [`GatewayService::lock_deployments`], for a deploy which is
+    /// listed among the pending ones of the project while it lasts
+    pub async fn lock_deploy(
+        &self,
+        project_name: &ProjectName,
+        requested_by: &AccountName,
+    ) -> Result<DeployGuard, Error> {
+        self.deploy_locks
+            .lock_deploy(project_name, requested_by)
             .await
-            .map_err(|err| {
-                // If the error is a broken PK constraint, this is a
-                // project name clash
-                if let Some(db_err_code) = err.as_database_error().and_then(DatabaseError::code) {
-                    if db_err_code == "1555" {  // SQLITE_CONSTRAINT_PRIMARYKEY
-                        return Error::from_kind(ErrorKind::ProjectAlreadyExists)
-                    }
-                }
-                // Otherwise this is internal
-                err.into()
-            })?;
+    }
 
-        let project = project.0;
+    pub fn pending_deploys(&self, project_name: &ProjectName) -> Vec<PendingDeploy> {
+        self.deploy_locks.pending(project_name)
+    }
 
-        Ok(project)
+    /// Note that the deployer took a deploy of `project_name` on, which
+    /// it goes on to build and switch over to in its own time
+    pub fn deploy_handed_over(&self, project_name: &ProjectName) {
+        self.deploy_locks.handed_over(project_name);
+    }
+
+    pub fn is_deploying(&self, project_name: &ProjectName) -> bool {
+        self.deploy_locks.in_transition(project_name)
     }
 
     pub async fn create_custom_domain(
@@ -519,15 +3359,79 @@ impl GatewayService {
             .bind(&project_name)
             .bind(certs)
             .bind(private_key)
-            .execute(&self.db)
+            .execute(&mut *self.acquire().await?)
+            .await?;
+
+        self.record_project_event(
+            &project_name,
+            EventKind::DomainAdded,
+            Some(fqdn.to_string()),
+        )
+        .await;
+
+        Ok(())
+    }
+
+    /// Swap the certificate of a custom domain for a new one. Returns
+    /// whether `fqdn` is a custom domain at all.
+    pub async fn update_custom_domain_certificate(
+        &self,
+        fqdn: &Fqdn,
+        certs: &str,
+        private_key: &str,
+    ) -> Result<bool, Error> {
+        let updated =
+            query("UPDATE custom_domains SET certificate = ?1, private_key = ?2 WHERE fqdn = ?3")
+                .bind(certs)
+                .bind(private_key)
+                .bind(fqdn.to_string())
+                .execute(&mut *self.acquire().await?)
+                .await?
+                .rows_affected();
+
+        Ok(updated > 0)
+    }
+
+    /// Keep track of an attempt to get a certificate issued for `fqdn`,
+    /// and of why it failed if it did
+    pub async fn record_certificate_attempt(
+        &self,
+        fqdn: &str,
+        error: Option<String>,
+    ) -> Result<(), Error> {
+        query("INSERT OR REPLACE INTO certificate_attempts (fqdn, attempted_at, error) VALUES (?1, ?2, ?3)")
+            .bind(fqdn)
+            .bind(Utc::now().to_rfc3339())
+            .bind(error)
+            .execute(&mut *self.acquire().await?)
             .await?;
 
         Ok(())
     }
 
+    /// When a certificate was last tried to be issued for `fqdn`, and
+    /// why that failed if it did
+    pub async fn last_certificate_attempt(
+        &self,
+        fqdn: &str,
+    ) -> Result<Option<(DateTime<Utc>, Option<String>)>, Error> {
+        Ok(
+            query("SELECT attempted_at, error FROM certificate_attempts WHERE fqdn = ?1")
+                .bind(fqdn)
+                .fetch_optional(&mut *self.acquire().await?)
+                .await?
+                .map(|row| {
+                    let at = DateTime::parse_from_rfc3339(row.get("attempted_at"))
+                        .expect("certificate attempt times to be RFC 3339")
+                        .with_timezone(&Utc);
+                    (at, row.get("error"))
+                }),
+        )
+    }
+
     pub async fn iter_custom_domains(&self) -> Result<impl Iterator<Item = CustomDomain>, Error> {
         query("SELECT fqdn, project_name, certificate, private_key FROM custom_domains")
-            .fetch_all(&self.db)
+            .fetch_all(&mut *self.acquire().await?)
             .await
             .map(|res| {
                 res.into_iter().map(|row| CustomDomain {
@@ -540,6 +3444,20 @@ impl GatewayService {
             .map_err(|_| Error::from_kind(ErrorKind::Internal))
     }
 
+    /// The custom domains pointing to a project
+    pub async fn iter_project_custom_domains(
+        &self,
+        project_name: &ProjectName,
+    ) -> Result<impl Iterator<Item = String>, Error> {
+        let iter = query("SELECT fqdn FROM custom_domains WHERE project_name = ?1 ORDER BY fqdn")
+            .bind(project_name)
+            .fetch_all(&mut *self.acquire().await?)
+            .await?
+            .into_iter()
+            .map(|row| row.get("fqdn"));
+        Ok(iter)
+    }
+
     pub async fn project_details_for_custom_domain(
         &self,
         fqdn: &Fqdn,
@@ -548,7 +3466,7 @@ impl GatewayService {
             "SELECT fqdn, project_name, certificate, private_key FROM custom_domains WHERE fqdn = ?1",
         )
         .bind(fqdn.to_string())
-        .fetch_optional(&self.db)
+        .fetch_optional(&mut *self.acquire().await?)
         .await?
         .map(|row| CustomDomain {
             fqdn: row.get::<&str, _>("fqdn").parse().unwrap(),
@@ -560,11 +3478,90 @@ impl GatewayService {
         Ok(custom_domain)
     }
 
+    /// Every host the proxy answers to, along with the project it
+    /// sends the host to and the address the project is reached at
+    pub async fn routing_table(&self) -> Result<Vec<Route>, Error> {
+        let projects: HashMap<ProjectName, Project> =
+            query("SELECT project_name, project_state FROM projects")
+                .fetch_all(&mut *self.acquire().await?)
+                .await?
+                .into_iter()
+                .map(|row| {
+                    (
+                        row.get("project_name"),
+                        row.get::<SqlxJson<Project>, _>("project_state").0,
+                    )
+                })
+                .collect();
+        let aliases: HashMap<ProjectName, ProjectName> =
+            query("SELECT alias, project_name FROM project_aliases")
+                .fetch_all(&mut *self.acquire().await?)
+                .await?
+                .into_iter()
+                .map(|row| (row.get("alias"), row.get("project_name")))
+                .collect();
+        let public = self.context().container_settings().fqdn.clone();
+
+        let route = |host: String, kind: RouteKind, project_name: &ProjectName| {
+            let (state, backend) = match projects.get(project_name) {
+                Some(project) => (
+                    project.state().to_string(),
+                    project
+                        .target_ip()
+                        .ok()
+                        .flatten()
+                        .map(|ip| SocketAddr::new(ip, 8000).to_string()),
+                ),
+                None => ("missing".to_string(), None),
+            };
+
+            Route {
+                host,
+                kind,
+                project: project_name.to_string(),
+                state,
+                backend,
+                shadowed: false,
+            }
+        };
+
+        let mut table: Vec<Route> = projects
+            .keys()
+            .map(|project_name| {
+                let mut entry = route(
+                    format!("{project_name}.{public}"),
+                    RouteKind::Project,
+                    project_name,
+                );
+                // Aliases are looked up first
+                entry.shadowed = aliases.contains_key(project_name);
+                entry
+            })
+            .collect();
+        table.extend(aliases.iter().map(|(alias, project_name)| {
+            route(format!("{alias}.{public}"), RouteKind::Alias, project_name)
+        }));
+        for CustomDomain {
+            fqdn, project_name, ..
+        } in self.iter_custom_domains().await?
+        {
+            table.push(route(
+                fqdn.to_string(),
+                RouteKind::CustomDomain,
+                &project_name,
+            ));
+        }
+
+        table.sort_by(|a, b| a.host.cmp(&b.host));
+
+        Ok(table)
+    }
+
     pub async fn iter_projects_detailed(
         &self,
     ) -> Result<impl Iterator<Item = ProjectDetails>, Error> {
         let iter = query("SELECT project_name, account_name FROM projects")
-            .fetch_all(&self.db)
+            .fetch_all(&mut *self.acquire().await?)
             .await?
             .into_iter()
             .map(|row| ProjectDetails {
@@ -578,6 +3575,10 @@ impl GatewayService {
         self.provider.context()
     }
 
+    pub fn drainer(&self) -> &ConnectionDrainer {
+        &self.provider.drainer
+    }
+
     /// Create a builder for a new [ProjectTask]
     pub fn new_task(self: &Arc<Self>) -> TaskBuilder {
         TaskBuilder::new(self.clone())
@@ -586,12 +3587,79 @@ impl GatewayService {
     pub fn task_router(&self) -> TaskRouter<BoxedTask> {
         self.task_router.clone()
     }
+
+    pub fn task_tracker(&self) -> TaskTracker {
+        self.task_tracker.clone()
+    }
+
+    /// Take the lease on a project for `holder`, or renew it if
+    /// `holder` already has it, for `ttl`.
+    ///
+    /// Gateway nodes sharing the database only act on a project while
+    /// holding its lease. Returns `false` when someone else holds a
+    /// lease on the project which has not expired yet.
+    pub async fn acquire_project_lease(
+        &self,
+        project_name: &ProjectName,
+        holder: &Uuid,
+        ttl: Duration,
+    ) -> Result<bool, Error> {
+        let now = Utc::now().timestamp_millis();
+        let expires_at = now + ttl.as_millis() as i64;
+
+        let acquired = query(
+            "INSERT INTO project_leases (project_name, holder, expires_at) VALUES (?1, ?2, ?3) \
+             ON CONFLICT (project_name) DO UPDATE SET holder = excluded.holder, expires_at = excluded.expires_at \
+             WHERE project_leases.holder = excluded.holder OR project_leases.expires_at <= ?4",
+        )
+        .bind(project_name)
+        .bind(holder.to_string())
+        .bind(expires_at)
+        .bind(now)
+        .execute(&mut *self.acquire().await?)
+        .await?
+        .rows_affected()
+            == 1;
+
+        Ok(acquired)
+    }
+
+    /// Give up the lease `holder` has on a project, if any
+    pub async fn release_project_lease(
+        &self,
+        project_name: &ProjectName,
+        holder: &Uuid,
+    ) -> Result<(), Error> {
+        query("DELETE FROM project_leases WHERE project_name = ?1 AND holder = ?2")
+            .bind(project_name)
+            .bind(holder.to_string())
+            .execute(&mut *self.acquire().await?)
+            .await?;
+
+        Ok(())
+    }
+
+    pub fn project_cache(&self) -> &ProjectCache {
+        &self.project_cache
+    }
+
+    pub fn rollouts(&self) -> Rollouts {
+        self.rollouts.clone()
+    }
 }
 
 #[derive(Clone)]
 pub struct GatewayContext {
     docker: Docker,
     settings: ContainerSettings,
+    drainer: ConnectionDrainer,
+    warm_pool: WarmPool,
+}
+
+impl GatewayContext {
+    pub fn drainer(&self) -> &ConnectionDrainer {
+        &self.drainer
+    }
 }
 
 impl DockerContext for GatewayContext {
@@ -602,12 +3670,18 @@ impl DockerContext for GatewayContext {
     fn container_settings(&self) -> &ContainerSettings {
         &self.settings
     }
+
+    fn warm_pool(&self) -> Option<&WarmPool> {
+        Some(&self.warm_pool)
+    }
 }
 
 #[cfg(test)]
 pub mod tests {
 
     use std::str::FromStr;
+    use std::sync::atomic::AtomicUsize;
+    use std::time::Duration;
 
     use fqdn::FQDN;
 
@@ -615,8 +3689,240 @@ pub mod tests {
     use crate::auth::AccountTier;
     use crate::task::{self, TaskResult};
     use crate::tests::{assert_err_kind, World};
+    use crate::webhook::SIGNATURE_HEADER;
     use crate::{Error, ErrorKind};
 
+    fn settings_builder(docker: &Docker) -> ContainerSettingsBuilder {
+        ContainerSettings::builder(docker)
+            .prefix("shuttle_test_")
+            .image("public.ecr.aws/shuttle/deployer:latest")
+            .provisioner_host("provisioner")
+            .network_name("shuttle_default")
+            .fqdn("test.shuttleapp.rs")
+    }
+
+    #[tokio::test]
+    async fn schema_check() -> anyhow::Result<()> {
+        let world = World::new().await;
+        let pool = world.pool();
+        check_schema(&pool).await?;
+
+        // as if a migration had only gone halfway, and someone had been
+        // at the database by hand
+        query("DROP TABLE project_feature_flags")
+            .execute(&pool)
+            .await?;
+        query("ALTER TABLE accounts RENAME COLUMN auditor TO auditing")
+            .execute(&pool)
+            .await?;
+
+        let err = check_schema(&pool).await.unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Internal);
+        let message = err.to_string();
+        assert!(
+            message.ends_with("it is missing accounts.auditor, project_feature_flags"),
+            "{message}"
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn container_settings_validation() {
+        // Validation happens before the daemon is ever contacted
+        let docker =
+            Docker::connect_with_unix("/var/run/docker.sock", 60, API_DEFAULT_VERSION).unwrap();
+
+        let err = settings_builder(&docker)
+            .prefix("")
+            .build()
+            .await
+            .err()
+            .expect("an empty prefix to be rejected");
+        assert!(err.to_string().contains("prefix"), "{err}");
+
+        for image in [
+            "",
+            "Shuttle/Deployer",
+            "deployer:",
+            "deployer:la test",
+            "registry.io/deployer@sha256",
+            "/deployer",
+        ] {
+            let err = settings_builder(&docker)
+                .image(image)
+                .build()
+                .await
+                .err()
+                .unwrap_or_else(|| panic!("`{image}` to be rejected"));
+            assert!(err.to_string().contains("image reference"), "{err}");
+        }
+
+        let err = settings_builder(&docker)
+            .network_name("")
+            .build()
+            .await
+            .err()
+            .expect("an empty network name to be rejected");
+        assert!(err.to_string().contains("network name"), "{err}");
+
+        for image in [
+            "deployer",
+            "shuttle/deployer:latest",
+            "public.ecr.aws/shuttle/deployer:v0.9.0",
+            "localhost:5000/deployer",
+            "deployer@sha256:0123456789abcdef",
+        ] {
+            assert!(is_valid_image_reference(image), "`{image}` should be valid");
+        }
+    }
+
+    /// Serves just enough of the Docker API to look up and create
+    /// networks. Returns how many networks were created.
+    async fn mock_docker(network_exists: bool) -> (Docker, Arc<AtomicUsize>) {
+        use axum::http::{Method, StatusCode, Uri};
+        use axum::response::IntoResponse;
+        use axum::{Json, Router};
+
+        let created = Arc::new(AtomicUsize::new(0));
+        let exists = Arc::new(AtomicBool::new(network_exists));
+
+        let router = Router::new().fallback({
+            let created = created.clone();
+            move |method: Method, uri: Uri| {
+                let created = created.clone();
+                let exists = exists.clone();
+                async move {
+                    if method == Method::POST && uri.path().ends_with("/networks/create") {
+                        created.fetch_add(1, Ordering::SeqCst);
+                        exists.store(true, Ordering::SeqCst);
+                        Json(serde_json::json!({ "Id": "mock_network_id", "Warning": "" }))
+                            .into_response()
+                    } else if uri.path().ends_with("/networks") {
+                        let networks = if exists.load(Ordering::SeqCst) {
+                            serde_json::json!([{ "Name": "shuttle_default", "Id": "mock_network_id" }])
+                        } else {
+                            serde_json::json!([])
+                        };
+                        Json(networks).into_response()
+                    } else {
+                        StatusCode::NOT_FOUND.into_response()
+                    }
+                }
+            }
+        });
+
+        let port = portpicker::pick_unused_port().unwrap();
+        let addr: std::net::SocketAddr = format!("127.0.0.1:{port}").parse().unwrap();
+        tokio::spawn(axum::Server::bind(&addr).serve(router.into_make_service()));
+        // give the server a moment to start listening
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let docker =
+            Docker::connect_with_http(&format!("http://{addr}"), 5, API_DEFAULT_VERSION).unwrap();
+
+        (docker, created)
+    }
+
+    #[tokio::test]
+    async fn container_settings_network_check() {
+        // a missing network is an error unless it may be created
+        let (docker, created) = mock_docker(false).await;
+        let err = settings_builder(&docker)
+            .build()
+            .await
+            .err()
+            .expect("a missing network to be rejected");
+        assert!(err.to_string().contains("--create-network"), "{err}");
+        assert_eq!(created.load(Ordering::SeqCst), 0);
+
+        let settings = settings_builder(&docker)
+            .create_network(true)
+            .build()
+            .await
+            .unwrap();
+        assert_eq!(settings.network_id, "mock_network_id");
+        assert_eq!(created.load(Ordering::SeqCst), 1);
+
+        // an existing network is used as is
+        let (docker, created) = mock_docker(true).await;
+        let settings = settings_builder(&docker)
+            .create_network(true)
+            .build()
+            .await
+            .unwrap();
+        assert_eq!(settings.network_id, "mock_network_id");
+        assert_eq!(created.load(Ordering::SeqCst), 0);
+    }
+
+    #[tokio::test]
+    async fn container_stop_timeout() {
+        use axum::http::{Method, StatusCode, Uri};
+        use axum::response::IntoResponse;
+        use axum::{Json, Router};
+
+        use crate::State as _;
+
+        // Serves just enough of the Docker API to stop a container,
+        // recording what it was asked to stop it with
+        let stopped_with = Arc::new(std::sync::Mutex::new(None));
+        let router = Router::new().fallback({
+            let stopped_with = stopped_with.clone();
+            move |method: Method, uri: Uri| {
+                let stopped_with = stopped_with.clone();
+                async move {
+                    let path = uri.path();
+                    if path.ends_with("/networks") {
+                        Json(serde_json::json!([{ "Name": "shuttle_default", "Id": "mock_network_id" }]))
+                            .into_response()
+                    } else if method == Method::POST && path.ends_with("/containers/matrix/stop") {
+                        *stopped_with.lock().unwrap() = uri.query().map(ToString::to_string);
+                        StatusCode::NO_CONTENT.into_response()
+                    } else if path.ends_with("/containers/matrix/json") {
+                        Json(serde_json::json!({ "Id": "matrix", "State": { "Status": "exited" } }))
+                            .into_response()
+                    } else {
+                        StatusCode::NOT_FOUND.into_response()
+                    }
+                }
+            }
+        });
+
+        let port = portpicker::pick_unused_port().unwrap();
+        let addr: std::net::SocketAddr = format!("127.0.0.1:{port}").parse().unwrap();
+        tokio::spawn(axum::Server::bind(&addr).serve(router.into_make_service()));
+        // give the server a moment to start listening
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let docker =
+            Docker::connect_with_http(&format!("http://{addr}"), 5, API_DEFAULT_VERSION).unwrap();
+
+        let err = settings_builder(&docker)
+            .stop_timeout_secs(-1)
+            .build()
+            .await
+            .err()
+            .expect("a negative stop timeout to be rejected");
+        assert!(err.to_string().contains("stop timeout"), "{err}");
+
+        let settings = settings_builder(&docker)
+            .stop_timeout_secs(90)
+            .build()
+            .await
+            .unwrap();
+        assert_eq!(settings.stop_options().t, 90);
+
+        let ctx = GatewayContextProvider::new(docker, settings).context();
+        let stopping: Project = serde_json::from_value(serde_json::json!({
+            "stopping": { "container": { "Id": "matrix" } }
+        }))
+        .unwrap();
+        let stopped = stopping.next(&ctx).await.unwrap();
+
+        assert_eq!(stopped.state(), "stopped");
+        assert_eq!(stopped_with.lock().unwrap().as_deref(), Some("t=90"));
+    }
+
     #[tokio::test]
     async fn service_create_find_user() -> anyhow::Result<()> {
         let world = World::new().await;
@@ -646,24 +3952,62 @@ pub mod tests {
             key,
             projects,
             permissions,
+            scope,
+            impersonated_by,
         } = user;
 
         assert!(projects.is_empty());
 
-        assert!(!permissions.is_super_user());
+        assert!(scope.is_none());
+
+        assert!(impersonated_by.is_none());
+
+        assert!(!permissions.is_super_user());
+
+        assert_eq!(*permissions.tier(), AccountTier::Basic);
+
+        assert_eq!(name, account_name);
+
+        assert_err_kind!(
+            svc.create_user(account_name.clone()).await,
+            ErrorKind::UserAlreadyExists
+        );
+
+        let user_key = svc.key_from_account_name(&account_name).await?;
+
+        assert_eq!(key, user_key);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn service_impersonation_expiry() -> anyhow::Result<()> {
+        let world = World::new().await;
+        let svc = GatewayService::init(world.args(), world.pool()).await;
 
-        assert_eq!(*permissions.tier(), AccountTier::Basic);
+        let trinity = svc.create_user("trinity".parse()?).await?;
+        let neo = svc.create_user("neo".parse()?).await?;
 
-        assert_eq!(name, account_name);
+        let (key, _) = svc
+            .create_impersonation(&trinity.name, &neo.name, false)
+            .await?;
+        let impersonation = svc.find_impersonation(&key).await?;
+        assert_eq!(impersonation.account_name, neo.name);
+        assert_eq!(svc.sweep_impersonations().await?, 0);
 
+        // once expired, the token is refused even before it is swept
+        query("UPDATE impersonation_tokens SET expires_at = ?1 WHERE key = ?2")
+            .bind((Utc::now() - chrono::Duration::seconds(1)).to_rfc3339())
+            .bind(&key)
+            .execute(&svc.db)
+            .await?;
         assert_err_kind!(
-            svc.create_user(account_name.clone()).await,
-            ErrorKind::UserAlreadyExists
+            svc.find_impersonation(&key).await.map(|_| ()),
+            ErrorKind::UserNotFound
         );
 
-        let user_key = svc.key_from_account_name(&account_name).await?;
-
-        assert_eq!(key, user_key);
+        assert_eq!(svc.sweep_impersonations().await?, 1);
+        assert_eq!(svc.sweep_impersonations().await?, 0);
 
         Ok(())
     }
@@ -748,6 +4092,493 @@ pub mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn service_destroy_locks_do_not_pile_up() -> anyhow::Result<()> {
+        let world = World::new().await;
+        let svc = GatewayService::init(world.args(), world.pool()).await;
+
+        let neo: AccountName = "neo".parse().unwrap();
+        let matrix: ProjectName = "matrix".parse().unwrap();
+        svc.create_user(neo.clone()).await?;
+        svc.create_project(matrix.clone(), neo).await?;
+
+        let deletes =
+            futures::future::join_all((0..5).map(|_| svc.begin_destroy_project(&matrix))).await;
+        for res in deletes {
+            assert!(res?.is_destroyed());
+        }
+
+        // the lock is gone with the last of the deletes waiting on it
+        assert!(svc.destroy_locks.lock().unwrap().is_empty());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn service_project_cache() -> anyhow::Result<()> {
+        let world = World::new().await;
+        let svc = GatewayService::init(world.args(), world.pool())
+            .await
+            .with_project_cache(ProjectCache::new(16, Duration::from_millis(500)));
+
+        let neo: AccountName = "neo".parse().unwrap();
+        let matrix: ProjectName = "matrix".parse().unwrap();
+
+        svc.create_user(neo.clone()).await.unwrap();
+        let project = svc.create_project(matrix.clone(), neo).await.unwrap();
+
+        assert_eq!(svc.find_project(&matrix).await.unwrap(), project);
+        assert_eq!(svc.find_project(&matrix).await.unwrap(), project);
+        assert_eq!(svc.project_cache().misses(), 1);
+        assert_eq!(svc.project_cache().hits(), 1);
+
+        // A cached read after a transition sees the new state
+        let destroyed = project.destroy().unwrap();
+        svc.update_project(&matrix, &destroyed).await.unwrap();
+        assert_eq!(svc.find_project(&matrix).await.unwrap(), destroyed);
+
+        // A write which does not go through the service is only seen
+        // once the cached state expired
+        let recreated = Project::create(matrix.clone());
+        query("UPDATE projects SET project_state = ?1 WHERE project_name = ?2")
+            .bind(SqlxJson(&recreated))
+            .bind(&matrix)
+            .execute(&world.pool())
+            .await?;
+        assert_eq!(svc.find_project(&matrix).await.unwrap(), destroyed);
+
+        tokio::time::sleep(Duration::from_millis(600)).await;
+
+        assert_eq!(svc.find_project(&matrix).await.unwrap(), recreated);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn service_project_webhook() -> anyhow::Result<()> {
+        let world = World::new().await;
+        let svc = Arc::new(
+            GatewayService::init(world.args(), world.pool())
+                .await
+                .with_private_webhooks(true),
+        );
+
+        let (sender, mut receiver) = tokio::sync::mpsc::channel(1);
+        let router = axum::Router::new().route(
+            "/hook",
+            axum::routing::post(move |headers: http::HeaderMap, body: axum::body::Bytes| {
+                let sender = sender.clone();
+                async move {
+                    let signature = headers
+                        .get(SIGNATURE_HEADER)
+                        .map(|value| value.to_str().unwrap().to_string());
+                    sender.send((signature, body)).await.unwrap();
+                }
+            }),
+        );
+        let port = portpicker::pick_unused_port().unwrap();
+        let addr: std::net::SocketAddr = format!("127.0.0.1:{port}").parse().unwrap();
+        tokio::spawn(axum::Server::bind(&addr).serve(router.into_make_service()));
+
+        let neo: AccountName = "neo".parse().unwrap();
+        let matrix: ProjectName = "matrix".parse().unwrap();
+
+        svc.create_user(neo.clone()).await.unwrap();
+        svc.create_project(matrix.clone(), neo).await.unwrap();
+
+        let webhook = svc
+            .set_project_webhook(&matrix, format!("http://{addr}/hook"))
+            .await?;
+        assert_eq!(
+            svc.find_project_webhook(&matrix).await?,
+            Some(webhook.clone())
+        );
+
+        // the secret is not kept in the clear
+        let row =
+            query("SELECT secret, sealed_secret FROM project_webhooks WHERE project_name = ?1")
+                .bind(&matrix)
+                .fetch_one(&svc.db)
+                .await?;
+        assert_eq!(row.get::<String, _>("secret"), "");
+        assert!(
+            !String::from_utf8_lossy(&row.get::<Vec<u8>, _>("sealed_secret"))
+                .contains(&webhook.secret)
+        );
+
+        let mut work = svc
+            .new_task()
+            .project(matrix.clone())
+            .and_then(task::destroy())
+            .build();
+
+        while let TaskResult::Pending(_) = work.poll(()).await {}
+
+        let (signature, body) = tokio::time::timeout(Duration::from_secs(10), receiver.recv())
+            .await?
+            .expect("a webhook to be delivered");
+
+        assert!(webhook.verify(&body, &signature.expect("a signature header")));
+
+        let event: shuttle_common::models::project::WebhookEvent = serde_json::from_slice(&body)?;
+        assert_eq!(event.project_name, "matrix");
+        assert_eq!(
+            event.state,
+            shuttle_common::models::project::State::Destroyed
+        );
+
+        svc.remove_project_webhook(&matrix).await?;
+        assert_eq!(svc.find_project_webhook(&matrix).await?, None);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn service_project_webhook_destinations() -> anyhow::Result<()> {
+        let world = World::new().await;
+        let svc = GatewayService::init(world.args(), world.pool()).await;
+
+        let neo: AccountName = "neo".parse().unwrap();
+        let matrix: ProjectName = "matrix".parse().unwrap();
+        svc.create_user(neo.clone()).await?;
+        svc.create_project(matrix.clone(), neo).await?;
+
+        for url in [
+            "http://127.0.0.1:8000/hook",
+            "http://169.254.169.254/latest/meta-data",
+            "http://10.1.2.3/hook",
+        ] {
+            assert_err_kind!(
+                svc.set_project_webhook(&matrix, url.to_string())
+                    .await
+                    .map(|_| ()),
+                ErrorKind::InvalidWebhookUrl
+            );
+        }
+        assert_eq!(svc.find_project_webhook(&matrix).await?, None);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn service_seal_webhook_secrets() -> anyhow::Result<()> {
+        let world = World::new().await;
+        let svc = GatewayService::init(world.args(), world.pool()).await;
+
+        let neo: AccountName = "neo".parse().unwrap();
+        let matrix: ProjectName = "matrix".parse().unwrap();
+        svc.create_user(neo.clone()).await?;
+        svc.create_project(matrix.clone(), neo).await?;
+
+        // a secret stored before secrets were sealed
+        query("INSERT INTO project_webhooks (project_name, url, secret) VALUES (?1, ?2, ?3)")
+            .bind(&matrix)
+            .bind("https://example.com/hook")
+            .bind("follow the white rabbit")
+            .execute(&svc.db)
+            .await?;
+        let legacy = Webhook {
+            url: "https://example.com/hook".to_string(),
+            secret: "follow the white rabbit".to_string(),
+        };
+        assert_eq!(
+            svc.find_project_webhook(&matrix).await?,
+            Some(legacy.clone())
+        );
+
+        assert_eq!(svc.seal_webhook_secrets().await?, 1);
+        assert_eq!(svc.seal_webhook_secrets().await?, 0);
+        assert_eq!(svc.find_project_webhook(&matrix).await?, Some(legacy));
+
+        let secret: String = query("SELECT secret FROM project_webhooks WHERE project_name = ?1")
+            .bind(&matrix)
+            .fetch_one(&svc.db)
+            .await?
+            .get("secret");
+        assert_eq!(secret, "");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn service_resource_limits() -> anyhow::Result<()> {
+        let world = World::new().await;
+        let svc = GatewayService::init(world.args(), world.pool()).await;
+
+        let neo: AccountName = "neo".parse().unwrap();
+        let trinity: AccountName = "trinity".parse().unwrap();
+        svc.create_user(neo.clone()).await?;
+        svc.create_user(trinity.clone()).await?;
+
+        let small = ResourceLimits {
+            memory_mib: 512,
+            millicpus: 500,
+        };
+        let large = ResourceLimits {
+            memory_mib: 16 * 1024,
+            millicpus: 8000,
+        };
+
+        assert_err_kind!(
+            svc.set_account_resource_limits(
+                &neo,
+                ResourceLimits {
+                    memory_mib: 0,
+                    millicpus: 500
+                }
+            )
+            .await,
+            ErrorKind::InvalidResourceLimits
+        );
+        svc.set_account_resource_limits(&neo, small.clone()).await?;
+
+        // projects of the account inherit its defaults, others do not
+        let matrix: ProjectName = "matrix".parse().unwrap();
+        let zion: ProjectName = "zion".parse().unwrap();
+        svc.create_project(matrix.clone(), neo.clone()).await?;
+        svc.create_project(zion.clone(), trinity.clone()).await?;
+        assert_eq!(
+            svc.effective_resource_limits(&matrix).await?,
+            Some(small.clone())
+        );
+        assert_eq!(svc.effective_resource_limits(&zion).await?, None);
+
+        // unless overridden
+        svc.set_project_resource_limits(&matrix, large.clone())
+            .await?;
+        assert_eq!(
+            svc.effective_resource_limits(&matrix).await?,
+            Some(large.clone())
+        );
+        svc.remove_account_resource_limits(&neo).await?;
+        assert_eq!(svc.effective_resource_limits(&matrix).await?, Some(large));
+
+        svc.remove_project_resource_limits(&matrix).await?;
+        assert_eq!(svc.effective_resource_limits(&matrix).await?, None);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn service_feature_flags() -> anyhow::Result<()> {
+        let world = World::new().await;
+        let svc = GatewayService::init(world.args(), world.pool()).await;
+
+        let neo: AccountName = "neo".parse().unwrap();
+        let matrix: ProjectName = "matrix".parse().unwrap();
+        let zion: ProjectName = "zion".parse().unwrap();
+        svc.create_user(neo.clone()).await?;
+        svc.create_project(matrix.clone(), neo.clone()).await?;
+        svc.create_project(zion.clone(), neo).await?;
+
+        svc.set_project_feature_flag(&matrix, FeatureFlag::Http2Upstream, true)
+            .await?;
+        assert_eq!(
+            svc.project_upstream_protocol(&matrix),
+            UpstreamProtocol::Http2
+        );
+        assert_eq!(
+            svc.project_upstream_protocol(&zion),
+            UpstreamProtocol::Http1
+        );
+
+        // flags outlive a restart of the gateway
+        let svc = GatewayService::init(world.args(), world.pool()).await;
+        assert_eq!(
+            svc.project_upstream_protocol(&matrix),
+            UpstreamProtocol::Http2
+        );
+        assert_eq!(
+            svc.project_upstream_protocol(&zion),
+            UpstreamProtocol::Http1
+        );
+
+        svc.set_project_feature_flag(&matrix, FeatureFlag::Http2Upstream, false)
+            .await?;
+        assert_eq!(
+            svc.project_upstream_protocol(&matrix),
+            UpstreamProtocol::Http1
+        );
+
+        let svc = GatewayService::init(world.args(), world.pool()).await;
+        assert!(svc.feature_flags().flags(&matrix).is_empty());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn service_project_leases() -> anyhow::Result<()> {
+        let world = World::new().await;
+        // Two gateway nodes sharing the same database
+        let node_a = GatewayService::init(world.args(), world.pool()).await;
+        let node_b = GatewayService::init(world.args(), world.pool()).await;
+
+        let neo: AccountName = "neo".parse().unwrap();
+        let matrix: ProjectName = "matrix".parse().unwrap();
+        node_a.create_user(neo.clone()).await?;
+        node_a.create_project(matrix.clone(), neo).await?;
+
+        let (task_a, task_b) = (Uuid::new_v4(), Uuid::new_v4());
+
+        // only one of the contenders proceeds
+        let (acquired_a, acquired_b) = tokio::join!(
+            node_a.acquire_project_lease(&matrix, &task_a, PROJECT_LEASE_TTL),
+            node_b.acquire_project_lease(&matrix, &task_b, PROJECT_LEASE_TTL),
+        );
+        let (winner, winner_node, loser, loser_node) = match (acquired_a?, acquired_b?) {
+            (true, false) => (task_a, &node_a, task_b, &node_b),
+            (false, true) => (task_b, &node_b, task_a, &node_a),
+            other => panic!("expected exactly one contender to get the lease, got {other:?}"),
+        };
+
+        // the other waits for as long as the lease is renewed
+        assert!(
+            winner_node
+                .acquire_project_lease(&matrix, &winner, PROJECT_LEASE_TTL)
+                .await?
+        );
+        assert!(
+            !loser_node
+                .acquire_project_lease(&matrix, &loser, PROJECT_LEASE_TTL)
+                .await?
+        );
+        loser_node.release_project_lease(&matrix, &loser).await?;
+        assert!(
+            !loser_node
+                .acquire_project_lease(&matrix, &loser, PROJECT_LEASE_TTL)
+                .await?
+        );
+
+        // and proceeds once it is released
+        winner_node.release_project_lease(&matrix, &winner).await?;
+        assert!(
+            loser_node
+                .acquire_project_lease(&matrix, &loser, Duration::ZERO)
+                .await?
+        );
+
+        // or once it expires
+        assert!(
+            winner_node
+                .acquire_project_lease(&matrix, &winner, PROJECT_LEASE_TTL)
+                .await?
+        );
+        assert!(
+            !loser_node
+                .acquire_project_lease(&matrix, &loser, PROJECT_LEASE_TTL)
+                .await?
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn service_rename_project() -> anyhow::Result<()> {
+        let world = World::new().await;
+        let svc = GatewayService::init(world.args(), world.pool()).await;
+
+        let neo: AccountName = "neo".parse().unwrap();
+        let matrix: ProjectName = "matrix".parse().unwrap();
+        let zion: ProjectName = "zion".parse().unwrap();
+        svc.create_user(neo.clone()).await?;
+        svc.create_project(matrix.clone(), neo.clone()).await?;
+
+        let limit = RateLimit {
+            requests_per_second: 10,
+            burst: 20,
+        };
+        svc.set_project_rate_limit(&matrix, limit.clone()).await?;
+        svc.set_project_env(
+            &matrix,
+            &BTreeMap::from([("SECRET".to_string(), "red pill".to_string())]),
+        )
+        .await?;
+
+        // a project still being created cannot be renamed
+        assert_err_kind!(
+            svc.rename_project(&matrix, &zion, false).await.map(|_| ()),
+            ErrorKind::InvalidOperation
+        );
+
+        // nor can a frozen one, which stays frozen
+        let frozen: Project = serde_json::from_value(serde_json::json!({
+            "frozen": { "container": {} }
+        }))?;
+        svc.update_project(&matrix, &frozen).await?;
+        assert_err_kind!(
+            svc.rename_project(&matrix, &zion, false).await.map(|_| ()),
+            ErrorKind::ProjectFrozen
+        );
+        assert!(svc.find_project(&matrix).await?.is_frozen());
+
+        let stopped: Project = serde_json::from_value(serde_json::json!({
+            "stopped": { "container": {} }
+        }))?;
+        svc.update_project(&matrix, &stopped).await?;
+
+        // or one a task is acting on
+        let holder = Uuid::new_v4();
+        assert!(
+            svc.acquire_project_lease(&matrix, &holder, PROJECT_LEASE_TTL)
+                .await?
+        );
+        assert_err_kind!(
+            svc.rename_project(&matrix, &zion, false).await.map(|_| ()),
+            ErrorKind::ProjectBusy
+        );
+        svc.release_project_lease(&matrix, &holder).await?;
+
+        let renamed = svc.rename_project(&matrix, &zion, false).await?;
+        assert!(matches!(renamed, Project::Creating(_)));
+        assert_err_kind!(
+            svc.find_project(&matrix).await.map(|_| ()),
+            ErrorKind::ProjectNotFound
+        );
+
+        // the settings of the project moved along with it
+        assert_eq!(svc.rate_limiter().limit(&matrix), None);
+        assert_eq!(svc.rate_limiter().limit(&zion), Some(limit));
+        assert_eq!(
+            svc.project_env(&zion).await?,
+            vec![("SECRET".to_string(), "red pill".to_string())]
+        );
+        assert!(svc.project_env(&matrix).await?.is_empty());
+
+        // and the lease taken for the rename was given up
+        assert!(
+            svc.acquire_project_lease(&zion, &holder, PROJECT_LEASE_TTL)
+                .await?
+        );
+
+        // a project the traffic of another is split onto stays its
+        // canary under its new name
+        let nebuchadnezzar: ProjectName = "nebuchadnezzar".parse().unwrap();
+        let logos: ProjectName = "logos".parse().unwrap();
+        svc.create_project(nebuchadnezzar.clone(), neo.clone())
+            .await?;
+        svc.set_project_canary(
+            &nebuchadnezzar,
+            Canary {
+                project: zion.to_string(),
+                weight: 10,
+                sticky: false,
+            },
+        )
+        .await?;
+        svc.release_project_lease(&zion, &holder).await?;
+        svc.update_project(&zion, &stopped).await?;
+        svc.rename_project(&zion, &logos, false).await?;
+
+        assert_eq!(
+            svc.canaries()
+                .split(&nebuchadnezzar)
+                .map(|split| split.project.clone()),
+            Some(logos)
+        );
+
+        Ok(())
+    }
+
     #[tokio::test]
     async fn service_create_ready_kill_restart_docker() -> anyhow::Result<()> {
         let world = World::new().await;
@@ -860,4 +4691,60 @@ pub mod tests {
 
         Ok(())
     }
+
+    #[tokio::test]
+    async fn evicts_lower_tier_projects_when_full() -> anyhow::Result<()> {
+        use bollard::models::ContainerInspectResponse;
+
+        use crate::project::ProjectStarted;
+
+        let world = World::new().await;
+        let mut args = world.args();
+        args.max_running_projects = Some(2);
+        let svc = Arc::new(GatewayService::init(args, world.pool()).await);
+
+        let (sender, mut receiver) = tokio::sync::mpsc::channel(4);
+
+        let basic: AccountName = "neo".parse().unwrap();
+        let pro: AccountName = "trinity".parse().unwrap();
+        svc.create_user(basic.clone()).await?;
+        svc.create_user(pro.clone()).await?;
+        svc.set_permissions(&pro, &Permissions::builder().tier(AccountTier::Pro).build())
+            .await?;
+
+        // the node is full of projects of the basic tier, the least
+        // recently used of which is `matrix`
+        for name in ["matrix", "zion"] {
+            let project_name: ProjectName = name.parse().unwrap();
+            svc.create_project(project_name.clone(), basic.clone())
+                .await?;
+            let running = Project::Started(ProjectStarted::new(ContainerInspectResponse {
+                id: Some(name.to_string()),
+                ..Default::default()
+            }));
+            svc.update_project(&project_name, &running).await?;
+            svc.activity_tracker().touch(&project_name);
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        // projects of the same tier wait for a slot
+        let nebuchadnezzar: ProjectName = "nebuchadnezzar".parse().unwrap();
+        svc.create_project(nebuchadnezzar.clone(), basic.clone())
+            .await?;
+        assert_eq!(svc.make_room_for(&nebuchadnezzar, &sender).await?, None);
+        assert!(receiver.try_recv().is_err());
+
+        // and those of a higher one take the slot of the least recently
+        // used project
+        let logos: ProjectName = "logos".parse().unwrap();
+        svc.create_project(logos.clone(), pro.clone()).await?;
+        assert_eq!(svc.project_tier(&logos).await?, AccountTier::Pro);
+        assert_eq!(
+            svc.make_room_for(&logos, &sender).await?,
+            Some("matrix".parse().unwrap())
+        );
+        assert!(receiver.try_recv().is_ok());
+
+        Ok(())
+    }
 }
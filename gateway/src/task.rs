@@ -1,3 +1,5 @@
+use bollard::container::RemoveContainerOptions;
+use bollard::errors::Error as DockerError;
 use futures::Future;
 use std::collections::VecDeque;
 use std::marker::PhantomData;
@@ -7,13 +9,16 @@ use std::time::{Duration, Instant};
 use tokio::sync::mpsc::Sender;
 use tokio::sync::oneshot;
 use tokio::time::{sleep, timeout};
-use tracing::{error, info, info_span, warn};
+use tracing::{debug, error, info, info_span, warn};
 use uuid::Uuid;
 
+use crate::drain::DRAIN_TIMEOUT;
 use crate::project::*;
-use crate::service::{GatewayContext, GatewayService};
-use crate::worker::TaskRouter;
-use crate::{AccountName, EndState, Error, ErrorKind, ProjectName, Refresh, State};
+use crate::service::{GatewayContext, GatewayService, PROJECT_LEASE_TTL};
+use crate::worker::{CancellationToken, TaskRouter};
+use crate::{
+    docker_op, AccountName, DockerContext, EndState, Error, ErrorKind, ProjectName, Refresh, State,
+};
 
 // Default maximum _total_ time a task is allowed to run
 pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(300);
@@ -21,6 +26,8 @@ pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(300);
 pub const TASK_SEND_TIMEOUT: Duration = Duration::from_secs(9);
 // Maximum time before a task is considered degraded
 pub const PROJECT_TASK_MAX_IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+// Time to wait before trying again to take the lease on a project someone else holds
+pub const PROJECT_LEASE_RETRY: Duration = Duration::from_secs(1);
 
 #[async_trait]
 pub trait Task<Ctx>: Send {
@@ -126,6 +133,15 @@ pub fn destroy() -> impl Task<ProjectContext, Output = Project, Error = Error> {
     })
 }
 
+pub fn stop() -> impl Task<ProjectContext, Output = Project, Error = Error> {
+    run(|ctx| async move {
+        match ctx.state.stop() {
+            Ok(state) => TaskResult::Done(state),
+            Err(err) => TaskResult::Err(err),
+        }
+    })
+}
+
 pub fn check_health() -> impl Task<ProjectContext, Output = Project, Error = Error> {
     run(|ctx| async move {
         match ctx.state.refresh(&ctx.gateway).await {
@@ -142,6 +158,129 @@ pub fn check_health() -> impl Task<ProjectContext, Output = Project, Error = Err
     })
 }
 
+/// Let the requests in flight to the project's current backend finish
+/// before its container is taken down, for up to [`DRAIN_TIMEOUT`]
+async fn drain(ctx: &ProjectContext) {
+    if let Ok(Some(target_ip)) = ctx.state.target_ip() {
+        let drained = ctx
+            .gateway
+            .drainer()
+            .drain(&ctx.project_name, target_ip, DRAIN_TIMEOUT)
+            .await;
+        if !drained {
+            warn!(project_name = %ctx.project_name, %target_ip, "gave up waiting for requests to the old backend to finish");
+        }
+    }
+}
+
+/// Replace the project's container with one running `image`. The
+/// volume of the project is kept so nothing is lost in the process.
+/// Requests in flight to the old container are let finish first.
+pub fn recreate_with_image(
+    image: String,
+) -> impl Task<ProjectContext, Output = Project, Error = Error> {
+    run(move |ctx| {
+        let image = image.clone();
+        async move {
+            let container = match ctx.state.container() {
+                Some(container) => container,
+                None => return TaskResult::Err(Error::from_kind(ErrorKind::ContainerNotFound)),
+            };
+
+            let prefix = &ctx.gateway.container_settings().prefix;
+            let (project_name, initial_key) =
+                match (container.project_name(prefix), container.initial_key()) {
+                    (Ok(project_name), Ok(initial_key)) => (project_name, initial_key),
+                    (Err(err), _) | (_, Err(err)) => return TaskResult::Err(err.into()),
+                };
+
+            drain(&ctx).await;
+
+            let docker = ctx.gateway.docker();
+            let container_id = container.id.clone().unwrap_or_default();
+            docker_op(
+                "stop",
+                docker.stop_container(
+                    &container_id,
+                    Some(ctx.gateway.container_settings().stop_options()),
+                ),
+            )
+            .await
+            .unwrap_or(());
+            if let Err(err) = docker_op(
+                "remove",
+                docker.remove_container(
+                    &container_id,
+                    Some(RemoveContainerOptions {
+                        force: true,
+                        ..Default::default()
+                    }),
+                ),
+            )
+            .await
+            {
+                return TaskResult::Err(err.into());
+            }
+
+            TaskResult::Done(Project::Creating(
+                ProjectCreating::new(project_name, initial_key)
+                    .from(container)
+                    .with_image(image),
+            ))
+        }
+    })
+}
+
+/// Stop the project's container and keep it that way until the
+/// project is unfrozen. Freezing a frozen project does nothing.
+pub fn freeze() -> impl Task<ProjectContext, Output = Project, Error = Error> {
+    run(|ctx| async move {
+        if ctx.state.is_frozen() {
+            return TaskResult::Done(ctx.state);
+        }
+
+        let container = match &ctx.state {
+            Project::Destroying(_) | Project::Destroyed(_) => None,
+            state => state.container(),
+        };
+
+        let container = match container {
+            Some(container) => container,
+            None => {
+                return TaskResult::Err(Error::custom(
+                    ErrorKind::InvalidOperation,
+                    format!(
+                        "cannot freeze a project in the `{}` state",
+                        ctx.state.state()
+                    ),
+                ))
+            }
+        };
+
+        drain(&ctx).await;
+
+        let container_id = container.id.clone().unwrap_or_default();
+        docker_op(
+            "stop",
+            ctx.gateway.docker().stop_container(
+                &container_id,
+                Some(ctx.gateway.container_settings().stop_options()),
+            ),
+        )
+        .await
+        .unwrap_or(());
+
+        match container.refresh(&ctx.gateway).await {
+            Ok(container) => TaskResult::Done(Project::Frozen(ProjectFrozen::new(container))),
+            Err(err) => TaskResult::Err(err.into()),
+        }
+    })
+}
+
+pub fn unfreeze() -> impl Task<ProjectContext, Output = Project, Error = Error> {
+    run(|ctx| async move { TaskResult::Done(ctx.state.unfreeze()) })
+}
+
 pub fn run_until_done() -> impl Task<ProjectContext, Output = Project, Error = Error> {
     RunUntilDone
 }
@@ -188,13 +327,20 @@ impl TaskBuilder {
 
         let timeout = self.timeout.unwrap_or(DEFAULT_TIMEOUT);
 
+        let uuid = Uuid::new_v4();
+        let project_name = self.project_name.expect("project_name is required");
+        let cancel = self.service.task_tracker().register(uuid, &project_name);
+
         Box::new(WithTimeout::on(
             timeout,
             ProjectTask {
-                uuid: Uuid::new_v4(),
-                project_name: self.project_name.expect("project_name is required"),
+                uuid,
+                project_name,
                 service: self.service,
                 tasks: self.tasks,
+                cancel,
+                creating: None,
+                leased: false,
             },
         ))
     }
@@ -387,17 +533,94 @@ where
 /// the error. The value returned by the inner tasks upon their
 /// completion is committed back to persistence through
 /// [GatewayService].
+///
+/// A `ProjectTask` can be cancelled through the [TaskTracker] of the
+/// gateway, in which case it stops where it is and removes whatever
+/// it created without recording it yet.
+///
+/// A `ProjectTask` only makes progress while it holds the lease on its
+/// project, so that gateway nodes sharing a database never act on the
+/// same project at once. It defers for as long as someone else holds
+/// the lease, and gives it up when it is done or dropped, as it is
+/// when it times out.
+///
+/// [TaskTracker]: crate::worker::TaskTracker
 pub struct ProjectTask<T> {
     uuid: Uuid,
     project_name: ProjectName,
     service: Arc<GatewayService>,
     tasks: VecDeque<T>,
+    cancel: CancellationToken,
+    /// The container the project was last seen being created into,
+    /// which may exist without being in its state yet
+    creating: Option<String>,
+    /// Whether the task holds the lease on its project
+    leased: bool,
 }
 
 impl<T> ProjectTask<T> {
     pub fn uuid(&self) -> &Uuid {
         &self.uuid
     }
+
+    /// Remove the container of a project which was cancelled before
+    /// the container made it into its state, as nothing else would.
+    /// The project may well be marked as destroyed by then, so this
+    /// goes by what the task last saw rather than what is persisted.
+    async fn clean_up_cancelled(&self) {
+        warn!(project_name = %self.project_name, "project task was cancelled");
+
+        let container_name = match &self.creating {
+            Some(container_name) => container_name.clone(),
+            None => return,
+        };
+
+        let ctx = self.service.context();
+        match docker_op(
+            "remove",
+            ctx.docker().remove_container(
+                &container_name,
+                Some(RemoveContainerOptions {
+                    force: true,
+                    ..Default::default()
+                }),
+            ),
+        )
+        .await
+        {
+            Ok(_) => info!(container_name, "removed container of a cancelled task"),
+            Err(DockerError::DockerResponseServerError {
+                status_code: 404, ..
+            }) => {}
+            Err(err) => {
+                error!(container_name, error = %err, "could not remove container of a cancelled task")
+            }
+        }
+    }
+}
+
+impl<T> Drop for ProjectTask<T> {
+    fn drop(&mut self) {
+        self.service.task_tracker().remove(self.uuid);
+
+        // A task given up on before it was done would otherwise keep
+        // the project from being acted on until its lease expires
+        if self.leased {
+            // Without a runtime to give it up on, the lease is left to expire
+            let handle = match tokio::runtime::Handle::try_current() {
+                Ok(handle) => handle,
+                Err(_) => return,
+            };
+            let service = Arc::clone(&self.service);
+            let project_name = self.project_name.clone();
+            let uuid = self.uuid;
+            handle.spawn(async move {
+                if let Err(err) = service.release_project_lease(&project_name, &uuid).await {
+                    warn!(error = %err, "could not release the lease on the project, it will expire");
+                }
+            });
+        }
+    }
 }
 
 /// A context for tasks which are scoped to a specific project.
@@ -432,13 +655,86 @@ where
             return TaskResult::Done(());
         }
 
+        match self
+            .service
+            .acquire_project_lease(&self.project_name, &self.uuid, PROJECT_LEASE_TTL)
+            .await
+        {
+            Ok(true) => self.leased = true,
+            Ok(false) => {
+                debug!(project_name = %self.project_name, "project is leased by someone else, deferring");
+                sleep(PROJECT_LEASE_RETRY).await;
+                return TaskResult::TryAgain;
+            }
+            Err(err) => return TaskResult::Err(err),
+        }
+
+        let res = self.poll_leased().await;
+
+        if res.is_done() {
+            self.leased = false;
+            if let Err(err) = self
+                .service
+                .release_project_lease(&self.project_name, &self.uuid)
+                .await
+            {
+                warn!(error = %err, "could not release the lease on the project, it will expire");
+            }
+        }
+
+        res
+    }
+}
+
+impl<T> ProjectTask<T>
+where
+    T: Task<ProjectContext, Output = Project, Error = Error>,
+{
+    /// Make progress on the tasks, with the lease on the project taken
+    async fn poll_leased(&mut self) -> TaskResult<(), Error> {
+        if self.cancel.is_cancelled() {
+            self.clean_up_cancelled().await;
+            return TaskResult::Cancelled;
+        }
+
         let ctx = self.service.context();
 
-        let project = match self.service.find_project(&self.project_name).await {
+        let project = match self.service.load_project(&self.project_name).await {
+            Ok(Project::Creating(creating)) => {
+                // Env vars are secret so they are not part of the
+                // persisted state and need to be loaded every time
+                let creating = match self.service.project_env(&self.project_name).await {
+                    Ok(env) => creating.with_env(env),
+                    Err(err) => return TaskResult::Err(err),
+                };
+
+                let creating = match self
+                    .service
+                    .project_deployment_timeout(&self.project_name)
+                    .await
+                {
+                    Ok(timeout) => creating.with_deployment_timeout(timeout),
+                    Err(err) => return TaskResult::Err(err),
+                };
+
+                match self
+                    .service
+                    .effective_resource_limits(&self.project_name)
+                    .await
+                {
+                    Ok(limits) => Project::Creating(creating.with_resource_limits(limits)),
+                    Err(err) => return TaskResult::Err(err),
+                }
+            }
             Ok(project) => project,
             Err(err) => return TaskResult::Err(err),
         };
 
+        self.creating = match &project {
+            Project::Creating(creating) => Some(creating.container_name(&ctx)),
+            _ => None,
+        };
+
         let account_name = match self
             .service
             .account_name_from_project(&self.project_name)
@@ -448,6 +744,13 @@ where
             Err(err) => return TaskResult::Err(err),
         };
 
+        let previous_state = project.state();
+
+        self.service
+            .task_tracker()
+            .update(self.uuid, &self.project_name, previous_state)
+            .await;
+
         let project_ctx = ProjectContext {
             project_name: self.project_name.clone(),
             account_name: account_name.clone(),
@@ -466,17 +769,23 @@ where
         let task = self.tasks.front_mut().unwrap();
 
         let timeout = sleep(PROJECT_TASK_MAX_IDLE_TIMEOUT);
+        let cancelled = self.cancel.cancelled();
+        tokio::pin!(cancelled);
         let res = {
             let mut poll = task.poll(project_ctx);
             tokio::select! {
                 res = &mut poll => res,
+                _ = &mut cancelled => TaskResult::Cancelled,
                 _ = timeout => {
                     warn!(
                         project_name = ?self.project_name,
                         account_name = ?account_name,
                         "a task has been idling for a long time"
                     );
-                    poll.await
+                    tokio::select! {
+                        res = poll => res,
+                        _ = cancelled => TaskResult::Cancelled,
+                    }
                 }
             }
         };
@@ -490,6 +799,17 @@ where
             {
                 Ok(_) => {
                     info!(new_state = ?update.state(), "successfully updated project state");
+                    if !matches!(update, Project::Creating(_)) {
+                        self.creating = None;
+                    }
+                    if update.state() != previous_state {
+                        self.service
+                            .notify_project_state(&self.project_name, update)
+                            .await;
+                        self.service
+                            .record_project_state(&self.project_name, update)
+                            .await;
+                    }
                 }
                 Err(err) => {
                     error!(err = %err, "could not update project state");
@@ -511,7 +831,12 @@ where
                     TaskResult::Pending(())
                 }
             }
-            TaskResult::Cancelled => TaskResult::Cancelled,
+            TaskResult::Cancelled => {
+                if self.cancel.is_cancelled() {
+                    self.clean_up_cancelled().await;
+                }
+                TaskResult::Cancelled
+            }
             TaskResult::Err(err) => {
                 error!(err = %err, "project task failure");
                 TaskResult::Err(err)
@@ -523,6 +848,7 @@ where
 #[cfg(test)]
 pub mod tests {
     use super::*;
+    use crate::tests::World;
 
     struct NeverEnding;
 
@@ -553,4 +879,98 @@ pub mod tests {
 
         Ok(())
     }
+
+    #[tokio::test]
+    async fn task_timeout_releases_lease() -> anyhow::Result<()> {
+        let world = World::new().await;
+        let service = Arc::new(GatewayService::init(world.args(), world.pool()).await);
+
+        let neo: AccountName = "neo".parse().unwrap();
+        let matrix: ProjectName = "matrix".parse().unwrap();
+        service.create_user(neo.clone()).await?;
+        service.create_project(matrix.clone(), neo).await?;
+
+        // a project with nothing left to do, for the tasks not to get
+        // to docker
+        let destroyed: Project = serde_json::from_value(serde_json::json!({
+            "destroyed": { "destroyed": null }
+        }))?;
+        service.update_project(&matrix, &destroyed).await?;
+
+        let task_timeout = Duration::from_millis(100);
+        let mut stuck = service
+            .new_task()
+            .project(matrix.clone())
+            .and_then(run(|ctx| async move { TaskResult::Pending(ctx.state) }))
+            .with_timeout(task_timeout)
+            .build();
+
+        // the task takes the lease and times out while holding it
+        assert!(matches!(stuck.poll(()).await, TaskResult::Pending(())));
+        sleep(task_timeout).await;
+        assert!(matches!(stuck.poll(()).await, TaskResult::Cancelled));
+        drop(stuck);
+
+        // which does not hold up the next task until the lease expires
+        let mut next = service
+            .new_task()
+            .project(matrix.clone())
+            .and_then(run(|ctx| async move { TaskResult::Done(ctx.state) }))
+            .build();
+        let res = timeout(Duration::from_secs(10), async {
+            loop {
+                let res = next.poll(()).await;
+                if res.is_done() {
+                    break res;
+                }
+            }
+        })
+        .await?;
+        assert!(matches!(res, TaskResult::Done(())));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn task_sees_state_left_by_other_node() -> anyhow::Result<()> {
+        let world = World::new().await;
+        // Two gateway nodes sharing the same database
+        let node_a = Arc::new(GatewayService::init(world.args(), world.pool()).await);
+        let node_b = Arc::new(GatewayService::init(world.args(), world.pool()).await);
+
+        let neo: AccountName = "neo".parse().unwrap();
+        let matrix: ProjectName = "matrix".parse().unwrap();
+        node_a.create_user(neo.clone()).await?;
+        node_a.create_project(matrix.clone(), neo).await?;
+
+        // node b caches the project as it is created
+        assert!(matches!(
+            node_b.find_project(&matrix).await?,
+            Project::Creating(_)
+        ));
+
+        // before node a moves it along
+        let destroyed: Project = serde_json::from_value(serde_json::json!({
+            "destroyed": { "destroyed": null }
+        }))?;
+        node_a.update_project(&matrix, &destroyed).await?;
+
+        // a task on node b takes over from where node a left the project
+        let seen = Arc::new(std::sync::Mutex::new(None));
+        let mut task = node_b
+            .new_task()
+            .project(matrix.clone())
+            .and_then(run({
+                let seen = Arc::clone(&seen);
+                move |ctx| {
+                    *seen.lock().unwrap() = Some(ctx.state.clone());
+                    async move { TaskResult::Done(ctx.state) }
+                }
+            }))
+            .build();
+        assert!(matches!(task.poll(()).await, TaskResult::Done(())));
+        assert_eq!(seen.lock().unwrap().take(), Some(destroyed));
+
+        Ok(())
+    }
 }
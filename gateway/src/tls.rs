@@ -1,21 +1,25 @@
 use std::collections::HashMap;
 use std::fs::File;
-use std::io::{BufReader, Read, Write};
-use std::path::Path;
-use std::sync::Arc;
+use std::io::{BufReader, Cursor, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
 
+use async_trait::async_trait;
 use axum_server::accept::DefaultAcceptor;
 use axum_server::tls_rustls::{RustlsAcceptor, RustlsConfig};
+use chrono::{DateTime, NaiveDateTime, Utc};
 use futures::executor::block_on;
 use pem::Pem;
 use rustls::server::{ClientHello, ResolvesServerCert};
 use rustls::sign::{self, CertifiedKey};
-use rustls::{Certificate, PrivateKey, ServerConfig};
+use rustls::version::{TLS12, TLS13};
+use rustls::{Certificate, PrivateKey, ServerConfig, SupportedProtocolVersion};
 use rustls_pemfile::Item;
 use shuttle_common::models::error::ErrorKind;
 use tokio::runtime::Handle;
 use tokio::sync::RwLock;
 
+use crate::args::{StartArgs, TlsVersion};
 use crate::Error;
 
 #[derive(Clone)]
@@ -41,10 +45,14 @@ impl ChainAndPrivateKey {
             }
         }
 
-        Ok(Self {
-            chain,
-            private_key: private_key.unwrap(),
-        })
+        let private_key = private_key.ok_or_else(|| {
+            Error::custom(
+                ErrorKind::Internal,
+                "certificate comes without a private key",
+            )
+        })?;
+
+        Ok(Self { chain, private_key })
     }
 
     pub fn load_pem<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
@@ -69,6 +77,27 @@ impl ChainAndPrivateKey {
         Ok(pem::encode_many(&pems))
     }
 
+    /// When the leaf certificate of the chain stops being valid
+    pub fn not_after(&self) -> Result<DateTime<Utc>, Error> {
+        self.chain
+            .first()
+            .and_then(|leaf| certificate_not_after(&leaf.0))
+            .ok_or_else(|| {
+                Error::custom(
+                    ErrorKind::Internal,
+                    "could not read when the certificate expires",
+                )
+            })
+    }
+
+    /// Who issued the leaf certificate of the chain, by their common
+    /// name or else their organization
+    pub fn issuer(&self) -> Option<String> {
+        self.chain
+            .first()
+            .and_then(|leaf| certificate_issuer(&leaf.0))
+    }
+
     pub fn into_certified_key(self) -> Result<CertifiedKey, Error> {
         let signing_key = sign::any_supported_type(&self.private_key)
             .map_err(|_| Error::from_kind(ErrorKind::Internal))?;
@@ -83,9 +112,243 @@ impl ChainAndPrivateKey {
     }
 }
 
+/// The contents of a DER encoded X.509 certificate which are signed,
+/// from its serial number on. The rest of it is not looked at, so that
+/// a whole parser is not needed.
+fn tbs_certificate(der: &[u8]) -> Option<&[u8]> {
+    let (_, certificate, _) = der_element(der)?;
+    let (_, tbs_certificate, _) = der_element(certificate)?;
+
+    // The version is only there when it is not the first one
+    let (tag, _, rest) = der_element(tbs_certificate)?;
+    if tag == 0xa0 {
+        Some(rest)
+    } else {
+        Some(tbs_certificate)
+    }
+}
+
+/// Read the common name, or else the organization, of the issuer of a
+/// DER encoded X.509 certificate
+fn certificate_issuer(der: &[u8]) -> Option<String> {
+    const COMMON_NAME: &[u8] = &[0x55, 0x04, 0x03];
+    const ORGANIZATION: &[u8] = &[0x55, 0x04, 0x0a];
+
+    // The serial number and the signature algorithm come before the
+    // issuer
+    let mut tbs_certificate = tbs_certificate(der)?;
+    for _ in 0..2 {
+        tbs_certificate = der_element(tbs_certificate)?.2;
+    }
+    let (_, mut issuer, _) = der_element(tbs_certificate)?;
+
+    // The issuer is a sequence of sets of attribute types and values
+    let mut common_name = None;
+    let mut organization = None;
+    while !issuer.is_empty() {
+        let (_, mut attributes, rest) = der_element(issuer)?;
+        issuer = rest;
+        while !attributes.is_empty() {
+            let (_, attribute, rest) = der_element(attributes)?;
+            attributes = rest;
+            let (_, kind, attribute) = der_element(attribute)?;
+            let (_, value, _) = der_element(attribute)?;
+            let value = std::str::from_utf8(value).ok().map(str::to_string);
+            match kind {
+                COMMON_NAME => common_name = value,
+                ORGANIZATION => organization = value,
+                _ => {}
+            }
+        }
+    }
+
+    common_name.or(organization)
+}
+
+/// Read the end of the validity of a DER encoded X.509 certificate
+fn certificate_not_after(der: &[u8]) -> Option<DateTime<Utc>> {
+    // The serial number, the signature algorithm and the issuer come
+    // before the validity
+    let mut tbs_certificate = tbs_certificate(der)?;
+    for _ in 0..3 {
+        tbs_certificate = der_element(tbs_certificate)?.2;
+    }
+    let (_, validity, _) = der_element(tbs_certificate)?;
+    let (_, _, validity) = der_element(validity)?;
+    let (tag, not_after, _) = der_element(validity)?;
+
+    let not_after = std::str::from_utf8(not_after).ok()?;
+    let not_after = match tag {
+        // UTCTime has two digit years, standing for 1950 to 2049
+        0x17 => {
+            let year: u8 = not_after.get(..2)?.parse().ok()?;
+            let century = if year < 50 { "20" } else { "19" };
+            format!("{century}{not_after}")
+        }
+        // GeneralizedTime
+        0x18 => not_after.to_string(),
+        _ => return None,
+    };
+
+    NaiveDateTime::parse_from_str(&not_after, "%Y%m%d%H%M%SZ")
+        .ok()
+        .map(|at| DateTime::from_utc(at, Utc))
+}
+
+/// Split the first element off `der`, into its tag, its contents and
+/// what comes after it
+fn der_element(der: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+    let (&tag, rest) = der.split_first()?;
+    let (&length, rest) = rest.split_first()?;
+
+    let (length, rest) = if length & 0x80 == 0 {
+        (length as usize, rest)
+    } else {
+        let octets = (length & 0x7f) as usize;
+        if octets == 0 || octets > std::mem::size_of::<usize>() || rest.len() < octets {
+            return None;
+        }
+        let (length, rest) = rest.split_at(octets);
+        let length = length
+            .iter()
+            .fold(0, |length, octet| length << 8 | *octet as usize);
+        (length, rest)
+    };
+
+    if rest.len() < length {
+        return None;
+    }
+    let (contents, rest) = rest.split_at(length);
+
+    Some((tag, contents, rest))
+}
+
+/// Where certificates are kept so that they outlive the gateway, and
+/// can be picked up by every node serving the same domains
+#[async_trait]
+pub trait CertStore: Send + Sync {
+    /// Keep `certs` as the certificate of `sni`, replacing any
+    /// previous one
+    async fn put(&self, sni: &str, certs: &ChainAndPrivateKey) -> Result<(), Error>;
+
+    /// The certificate of `sni`, if there is one
+    async fn get(&self, sni: &str) -> Result<Option<ChainAndPrivateKey>, Error>;
+
+    /// All the certificates kept, along with their domain
+    async fn all(&self) -> Result<Vec<(String, ChainAndPrivateKey)>, Error>;
+}
+
+/// A [`CertStore`] which does not survive the gateway. Clones share
+/// the same certificates.
+#[derive(Clone, Default)]
+pub struct MemoryCertStore {
+    certs: Arc<Mutex<HashMap<String, String>>>,
+}
+
+impl MemoryCertStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl CertStore for MemoryCertStore {
+    async fn put(&self, sni: &str, certs: &ChainAndPrivateKey) -> Result<(), Error> {
+        let pem = certs.clone().into_pem()?;
+        self.certs.lock().unwrap().insert(sni.to_string(), pem);
+        Ok(())
+    }
+
+    async fn get(&self, sni: &str) -> Result<Option<ChainAndPrivateKey>, Error> {
+        let pem = self.certs.lock().unwrap().get(sni).cloned();
+        pem.map(|pem| ChainAndPrivateKey::parse_pem(Cursor::new(pem)))
+            .transpose()
+    }
+
+    async fn all(&self) -> Result<Vec<(String, ChainAndPrivateKey)>, Error> {
+        let certs = self.certs.lock().unwrap().clone();
+        certs
+            .into_iter()
+            .map(|(sni, pem)| Ok((sni, ChainAndPrivateKey::parse_pem(Cursor::new(pem))?)))
+            .collect()
+    }
+}
+
+/// A [`CertStore`] keeping every certificate as a `<sni>.pem` file
+/// in a directory, which can be on a volume shared between nodes
+pub struct FileCertStore {
+    dir: PathBuf,
+}
+
+impl FileCertStore {
+    /// Keep certificates under `dir`, creating it if needs be
+    pub fn new<P: AsRef<Path>>(dir: P) -> Result<Self, Error> {
+        std::fs::create_dir_all(&dir)?;
+        Ok(Self {
+            dir: dir.as_ref().to_path_buf(),
+        })
+    }
+
+    fn path(&self, sni: &str) -> Result<PathBuf, Error> {
+        if sni.is_empty() || sni.starts_with('.') || sni.contains(['/', '\\']) {
+            return Err(Error::from_kind(ErrorKind::InvalidCustomDomain));
+        }
+
+        Ok(self.dir.join(format!("{sni}.pem")))
+    }
+}
+
+#[async_trait]
+impl CertStore for FileCertStore {
+    async fn put(&self, sni: &str, certs: &ChainAndPrivateKey) -> Result<(), Error> {
+        let path = self.path(sni)?;
+        let pem = certs.clone().into_pem()?;
+
+        // write to the side first, so that other nodes never read a
+        // partial certificate
+        let tmp = path.with_extension("pem.tmp");
+        tokio::fs::write(&tmp, pem).await?;
+        tokio::fs::rename(&tmp, &path).await?;
+
+        Ok(())
+    }
+
+    async fn get(&self, sni: &str) -> Result<Option<ChainAndPrivateKey>, Error> {
+        match tokio::fs::read(self.path(sni)?).await {
+            Ok(pem) => ChainAndPrivateKey::parse_pem(Cursor::new(pem)).map(Some),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    async fn all(&self) -> Result<Vec<(String, ChainAndPrivateKey)>, Error> {
+        let mut certs = Vec::new();
+        let mut entries = tokio::fs::read_dir(&self.dir).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let file_name = entry.file_name();
+            let sni = match file_name
+                .to_str()
+                .and_then(|name| name.strip_suffix(".pem"))
+            {
+                Some(sni) => sni,
+                None => continue,
+            };
+
+            let pem = tokio::fs::read(entry.path()).await?;
+            certs.push((
+                sni.to_string(),
+                ChainAndPrivateKey::parse_pem(Cursor::new(pem))?,
+            ));
+        }
+
+        Ok(certs)
+    }
+}
+
 pub struct GatewayCertResolver {
     keys: RwLock<HashMap<String, Arc<CertifiedKey>>>,
     default: RwLock<Option<Arc<CertifiedKey>>>,
+    store: Arc<dyn CertStore>,
 }
 
 impl Default for GatewayCertResolver {
@@ -95,19 +358,64 @@ impl Default for GatewayCertResolver {
 }
 
 impl GatewayCertResolver {
+    /// A resolver whose certificates only live as long as it does
     pub fn new() -> Self {
+        Self::with_store(Arc::new(MemoryCertStore::new()))
+    }
+
+    /// A resolver keeping the certificates it serves in `store`. They
+    /// are only served again after a [`GatewayCertResolver::reload`].
+    pub fn with_store(store: Arc<dyn CertStore>) -> Self {
         Self {
             keys: RwLock::new(HashMap::default()),
             default: RwLock::new(None),
+            store,
         }
     }
 
+    pub fn store(&self) -> &Arc<dyn CertStore> {
+        &self.store
+    }
+
+    /// Serve all the certificates of the store, returning how many
+    /// there are
+    pub async fn reload(&self) -> Result<usize, Error> {
+        let certs = self.store.all().await?;
+        let count = certs.len();
+
+        let mut keys = HashMap::with_capacity(count);
+        for (sni, certs) in certs {
+            keys.insert(sni, Arc::new(certs.into_certified_key()?));
+        }
+        *self.keys.write().await = keys;
+
+        Ok(count)
+    }
+
     /// Get the loaded [CertifiedKey] associated with the given
     /// domain.
     pub async fn get(&self, sni: &str) -> Option<Arc<CertifiedKey>> {
         self.keys.read().await.get(sni).map(Arc::clone)
     }
 
+    /// Like [`GatewayCertResolver::get`] but falling back on a
+    /// wildcard certificate for the parent domain
+    async fn get_or_wildcard(&self, sni: &str) -> Option<Arc<CertifiedKey>> {
+        let keys = self.keys.read().await;
+        keys.get(sni)
+            .or_else(|| {
+                let (_, parent) = sni.split_once('.')?;
+                keys.get(&format!("*.{parent}"))
+            })
+            .map(Arc::clone)
+    }
+
+    /// Whether there is a certificate for the domains which do not
+    /// have one of their own
+    pub async fn has_default(&self) -> bool {
+        self.default.read().await.is_some()
+    }
+
     pub async fn serve_default_der(&self, certs: ChainAndPrivateKey) -> Result<(), Error> {
         *self.default.write().await = Some(Arc::new(certs.into_certified_key()?));
         Ok(())
@@ -119,8 +427,10 @@ impl GatewayCertResolver {
     }
 
     /// Load a new certificate chain and private key to serve when
-    /// receiving incoming TLS connections for the given domain.
+    /// receiving incoming TLS connections for the given domain. It is
+    /// put in the store as well.
     pub async fn serve_der(&self, sni: &str, certs: ChainAndPrivateKey) -> Result<(), Error> {
+        self.store.put(sni, &certs).await?;
         let certified_key = certs.into_certified_key()?;
         self.keys
             .write()
@@ -141,7 +451,7 @@ impl ResolvesServerCert for GatewayCertResolver {
         let handle = Handle::current();
         let _ = handle.enter();
         block_on(async move {
-            if let Some(cert) = self.get(sni).await {
+            if let Some(cert) = self.get_or_wildcard(sni).await {
                 Some(cert)
             } else {
                 self.default.read().await.clone()
@@ -150,16 +460,270 @@ impl ResolvesServerCert for GatewayCertResolver {
     }
 }
 
-pub fn make_tls_acceptor() -> (Arc<GatewayCertResolver>, RustlsAcceptor<DefaultAcceptor>) {
-    let resolver = Arc::new(GatewayCertResolver::new());
+/// How the user proxy negotiates TLS with clients
+#[derive(Clone, Copy, Debug)]
+pub struct TlsOptions {
+    pub min_version: TlsVersion,
+    pub prefer_server_ciphers: bool,
+}
+
+impl Default for TlsOptions {
+    fn default() -> Self {
+        Self {
+            min_version: TlsVersion::Tls12,
+            prefer_server_ciphers: false,
+        }
+    }
+}
 
+impl TlsOptions {
+    pub fn from_args(args: &StartArgs) -> Self {
+        Self {
+            min_version: args.tls_min_version.unwrap_or(TlsVersion::Tls12),
+            prefer_server_ciphers: args.tls_prefer_server_ciphers,
+        }
+    }
+
+    fn versions(&self) -> &'static [&'static SupportedProtocolVersion] {
+        match self.min_version {
+            TlsVersion::Tls12 => &[&TLS13, &TLS12],
+            TlsVersion::Tls13 => &[&TLS13],
+        }
+    }
+}
+
+fn server_config(
+    resolver: Arc<GatewayCertResolver>,
+    options: TlsOptions,
+) -> Result<ServerConfig, Error> {
     let mut server_config = ServerConfig::builder()
-        .with_safe_defaults()
+        .with_safe_default_cipher_suites()
+        .with_safe_default_kx_groups()
+        .with_protocol_versions(options.versions())
+        .map_err(|err| Error::source(ErrorKind::Internal, err))?
         .with_no_client_auth()
-        .with_cert_resolver(Arc::clone(&resolver) as Arc<dyn ResolvesServerCert>);
-    server_config.alpn_protocols = vec![b"http/1.1".to_vec()];
+        .with_cert_resolver(resolver as Arc<dyn ResolvesServerCert>);
+    server_config.ignore_client_order = options.prefer_server_ciphers;
+    // h2 lets gRPC clients through, along with their trailers
+    server_config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+
+    Ok(server_config)
+}
 
+pub fn make_tls_acceptor(
+    store: Arc<dyn CertStore>,
+    options: TlsOptions,
+) -> Result<(Arc<GatewayCertResolver>, RustlsAcceptor<DefaultAcceptor>), Error> {
+    let resolver = Arc::new(GatewayCertResolver::with_store(store));
+
+    let server_config = server_config(Arc::clone(&resolver), options)?;
     let rustls_config = RustlsConfig::from_config(Arc::new(server_config));
 
-    (resolver, RustlsAcceptor::new(rustls_config))
+    Ok((resolver, RustlsAcceptor::new(rustls_config)))
+}
+
+#[cfg(test)]
+pub mod tests {
+    use clap::Parser;
+    use rcgen::generate_simple_self_signed;
+
+    use super::*;
+
+    #[test]
+    fn certificate_expiry() {
+        // UTCTime is used up to 2049, and GeneralizedTime from 2050 on
+        for (year, expected) in [
+            (2030, "2030-01-02T00:00:00Z"),
+            (2060, "2060-01-02T00:00:00Z"),
+        ] {
+            let mut params = rcgen::CertificateParams::new(vec!["example.com".to_string()]);
+            params.not_after = rcgen::date_time_ymd(year, 1, 2);
+            let cert = rcgen::Certificate::from_params(params).unwrap();
+
+            let mut buf = Vec::new();
+            buf.extend(cert.serialize_pem().unwrap().as_bytes());
+            buf.extend(cert.serialize_private_key_pem().as_bytes());
+            let certs = ChainAndPrivateKey::parse_pem(Cursor::new(buf)).unwrap();
+
+            assert_eq!(
+                certs
+                    .not_after()
+                    .unwrap()
+                    .to_rfc3339_opts(chrono::SecondsFormat::Secs, true),
+                expected
+            );
+        }
+
+        assert_eq!(certificate_not_after(b"\x30\x82\xff"), None);
+    }
+
+    #[test]
+    fn certificate_issuer_name() {
+        let issued_by = |attributes: &[(rcgen::DnType, &str)]| {
+            let mut params = rcgen::CertificateParams::new(vec!["example.com".to_string()]);
+            params.distinguished_name = rcgen::DistinguishedName::new();
+            for (kind, value) in attributes {
+                params.distinguished_name.push(kind.clone(), *value);
+            }
+            let cert = rcgen::Certificate::from_params(params).unwrap();
+
+            let mut buf = Vec::new();
+            buf.extend(cert.serialize_pem().unwrap().as_bytes());
+            buf.extend(cert.serialize_private_key_pem().as_bytes());
+            ChainAndPrivateKey::parse_pem(Cursor::new(buf))
+                .unwrap()
+                .issuer()
+        };
+
+        assert_eq!(
+            issued_by(&[
+                (rcgen::DnType::OrganizationName, "Zion"),
+                (rcgen::DnType::CommonName, "Zion CA"),
+            ]),
+            Some("Zion CA".to_string())
+        );
+        assert_eq!(
+            issued_by(&[(rcgen::DnType::OrganizationName, "Zion")]),
+            Some("Zion".to_string())
+        );
+        assert_eq!(issued_by(&[]), None);
+
+        assert_eq!(certificate_issuer(b"\x30\x82\xff"), None);
+        assert!(ChainAndPrivateKey::parse_pem(Cursor::new("cert")).is_err());
+    }
+
+    fn self_signed(sni: &str) -> ChainAndPrivateKey {
+        let cert = generate_simple_self_signed(vec![sni.to_string()]).unwrap();
+        let mut buf = Vec::new();
+        buf.extend(cert.serialize_pem().unwrap().as_bytes());
+        buf.extend(cert.serialize_private_key_pem().as_bytes());
+
+        ChainAndPrivateKey::parse_pem(Cursor::new(buf)).unwrap()
+    }
+
+    #[tokio::test]
+    async fn cert_store_survives_reload() {
+        let dir = tempfile::tempdir().unwrap();
+        let sni = "matrix.shuttleapp.rs";
+
+        {
+            let store = Arc::new(FileCertStore::new(dir.path()).unwrap());
+            let resolver = GatewayCertResolver::with_store(store);
+            resolver.serve_der(sni, self_signed(sni)).await.unwrap();
+            assert!(resolver.get(sni).await.is_some());
+        }
+
+        let store: Arc<dyn CertStore> = Arc::new(FileCertStore::new(dir.path()).unwrap());
+        assert!(store.get(sni).await.unwrap().is_some());
+        assert!(store.get("zion.shuttleapp.rs").await.unwrap().is_none());
+        assert!(store.get("../ssl").await.is_err());
+
+        let resolver = GatewayCertResolver::with_store(store);
+        assert!(resolver.get(sni).await.is_none());
+        assert_eq!(resolver.reload().await.unwrap(), 1);
+        assert!(resolver.get(sni).await.is_some());
+    }
+
+    /// Run a handshake in memory with a client which speaks `versions`,
+    /// returning the version agreed on
+    fn handshake(
+        server_config: ServerConfig,
+        root: &Certificate,
+        versions: &[&'static SupportedProtocolVersion],
+    ) -> Result<rustls::ProtocolVersion, rustls::Error> {
+        let mut roots = rustls::RootCertStore::empty();
+        roots.add(root).unwrap();
+        let client_config = rustls::ClientConfig::builder()
+            .with_safe_default_cipher_suites()
+            .with_safe_default_kx_groups()
+            .with_protocol_versions(versions)
+            .unwrap()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+
+        let mut client = rustls::ClientConnection::new(
+            Arc::new(client_config),
+            "matrix.shuttleapp.rs".try_into().unwrap(),
+        )
+        .unwrap();
+        let mut server = rustls::ServerConnection::new(Arc::new(server_config)).unwrap();
+
+        for _ in 0..10 {
+            if !client.is_handshaking() && !server.is_handshaking() {
+                break;
+            }
+
+            let mut buf = Vec::new();
+            client.write_tls(&mut buf).unwrap();
+            if !buf.is_empty() {
+                server.read_tls(&mut buf.as_slice()).unwrap();
+            }
+            server.process_new_packets()?;
+
+            let mut buf = Vec::new();
+            server.write_tls(&mut buf).unwrap();
+            if !buf.is_empty() {
+                client.read_tls(&mut buf.as_slice()).unwrap();
+            }
+            client.process_new_packets()?;
+        }
+
+        Ok(server.protocol_version().unwrap())
+    }
+
+    #[tokio::test]
+    async fn tls_min_version() {
+        let sni = "matrix.shuttleapp.rs";
+        let certs = self_signed(sni);
+        let root = certs.chain[0].clone();
+
+        let resolver = Arc::new(GatewayCertResolver::new());
+        resolver.serve_der(sni, certs).await.unwrap();
+
+        let config = |args: &[&str]| {
+            let args = ["gateway", "start"].iter().chain(args);
+            let args = match crate::args::Args::try_parse_from(args).unwrap().command {
+                crate::args::Commands::Start(args) => args,
+                _ => unreachable!(),
+            };
+            server_config(Arc::clone(&resolver), TlsOptions::from_args(&args)).unwrap()
+        };
+
+        let server_config = config(&[]);
+        assert!(!server_config.ignore_client_order);
+        assert_eq!(
+            handshake(server_config, &root, &[&TLS12]).unwrap(),
+            rustls::ProtocolVersion::TLSv1_2
+        );
+
+        let server_config = config(&["--tls-min-version", "1.3", "--tls-prefer-server-ciphers"]);
+        assert!(server_config.ignore_client_order);
+        assert!(handshake(server_config.clone(), &root, &[&TLS12]).is_err());
+        assert_eq!(
+            handshake(server_config, &root, &[&TLS13, &TLS12]).unwrap(),
+            rustls::ProtocolVersion::TLSv1_3
+        );
+    }
+
+    #[tokio::test]
+    async fn cert_store_wildcard() {
+        let store = MemoryCertStore::new();
+        store
+            .put("*.shuttleapp.rs", &self_signed("*.shuttleapp.rs"))
+            .await
+            .unwrap();
+
+        let resolver = GatewayCertResolver::with_store(Arc::new(store.clone()));
+        resolver.reload().await.unwrap();
+
+        assert!(resolver
+            .get_or_wildcard("matrix.shuttleapp.rs")
+            .await
+            .is_some());
+        assert!(resolver.get_or_wildcard("shuttleapp.rs").await.is_none());
+        assert!(resolver
+            .get_or_wildcard("matrix.example.com")
+            .await
+            .is_none());
+    }
 }
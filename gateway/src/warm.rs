@@ -0,0 +1,250 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use bollard::container::{
+    ListContainersOptions, RemoveContainerOptions, RenameContainerOptions, UploadToContainerOptions,
+};
+use bollard::errors::Error as DockerError;
+use bollard::volume::RemoveVolumeOptions;
+use rand::distributions::{Alphanumeric, DistString};
+use tracing::{info, warn};
+
+use crate::project::warm_container_config;
+use crate::{docker_op, DockerContext};
+
+/// Where the deployer of a warm container waits for the arguments
+/// naming the project which claims it. It is on the project volume, so
+/// that the container can be recreated with the same claim.
+pub const CLAIM_PATH: &str = "/opt/shuttle/claim.json";
+
+#[derive(Clone, Debug)]
+struct WarmContainer {
+    name: String,
+    volume: String,
+    started_at: Instant,
+}
+
+/// Containers started ahead of time, which projects being created
+/// claim rather than wait for docker to create and start their own
+#[derive(Clone, Default)]
+pub struct WarmPool {
+    size: usize,
+    ttl: Duration,
+    containers: Arc<Mutex<VecDeque<WarmContainer>>>,
+}
+
+impl WarmPool {
+    /// A pool kept at `size` containers, where those left unclaimed for
+    /// `ttl` are reclaimed and replaced
+    pub fn new(size: usize, ttl: Duration) -> Self {
+        Self {
+            size,
+            ttl,
+            containers: Default::default(),
+        }
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// How many containers are there to be claimed
+    pub fn len(&self) -> usize {
+        self.containers.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Hand a warm container over to a project: give its deployer the
+    /// `args` naming the project and rename it to `name`, the name of
+    /// the project container. Returns whether there was one to claim.
+    pub async fn claim<C: DockerContext>(
+        &self,
+        ctx: &C,
+        name: &str,
+        args: &[String],
+    ) -> Result<bool, DockerError> {
+        let container = match self.containers.lock().unwrap().pop_front() {
+            Some(container) => container,
+            None => return Ok(false),
+        };
+
+        let claim = claim_archive(args);
+        let res = async {
+            docker_op(
+                "upload",
+                ctx.docker().upload_to_container(
+                    &container.name,
+                    Some(UploadToContainerOptions {
+                        path: "/opt/shuttle".to_string(),
+                        ..Default::default()
+                    }),
+                    claim.into(),
+                ),
+            )
+            .await?;
+
+            docker_op(
+                "rename",
+                ctx.docker().rename_container(
+                    &container.name,
+                    RenameContainerOptions {
+                        name: name.to_string(),
+                    },
+                ),
+            )
+            .await
+        }
+        .await;
+
+        match res {
+            Ok(()) => {
+                info!(warm = %container.name, %name, "claimed a warm container");
+                Ok(true)
+            }
+            Err(err) => {
+                remove(ctx, &container.name, &container.volume).await;
+                Err(err)
+            }
+        }
+    }
+
+    /// Reclaim the containers left unclaimed for longer than the time to
+    /// live of the pool, then start new ones until the pool is full or
+    /// the node is
+    pub async fn refill<C: DockerContext>(&self, ctx: &C) -> Result<(), DockerError> {
+        let expired: Vec<_> = {
+            let mut containers = self.containers.lock().unwrap();
+            let (expired, fresh) = containers
+                .drain(..)
+                .partition(|container| container.started_at.elapsed() >= self.ttl);
+            *containers = fresh;
+            expired
+        };
+        for container in expired {
+            info!(name = %container.name, "reclaiming an unclaimed warm container");
+            remove(ctx, &container.name, &container.volume).await;
+        }
+
+        while self.len() < self.size {
+            if let Some(max) = ctx.container_settings().max_running_projects {
+                if ctx.running_projects().await? >= max {
+                    break;
+                }
+            }
+
+            let container = start(ctx).await?;
+            self.containers.lock().unwrap().push_back(container);
+        }
+
+        Ok(())
+    }
+
+    /// Remove the warm containers a previous run of the gateway left
+    /// behind, which this pool does not know about
+    pub async fn clear<C: DockerContext>(&self, ctx: &C) -> Result<(), DockerError> {
+        let prefix = &ctx.container_settings().prefix;
+        let containers = docker_op(
+            "list",
+            ctx.docker()
+                .list_containers(Some(ListContainersOptions::<String> {
+                    all: true,
+                    filters: HashMap::from([
+                        (
+                            "label".to_string(),
+                            vec![format!("shuttle.prefix={prefix}")],
+                        ),
+                        ("name".to_string(), vec![format!("^/{prefix}warm_")]),
+                    ]),
+                    ..Default::default()
+                })),
+        )
+        .await?;
+
+        let warm_prefix = format!("/{prefix}warm_");
+        for name in containers
+            .into_iter()
+            .filter_map(|container| container.names?.into_iter().next())
+            // The container of a project named `warm` matches too
+            .filter(|name| name.starts_with(&warm_prefix) && !name.ends_with("_run"))
+        {
+            let name = name.trim_start_matches('/');
+            info!(%name, "removing a warm container left behind");
+            remove(ctx, name, &format!("{name}_vol")).await;
+        }
+
+        Ok(())
+    }
+}
+
+async fn start<C: DockerContext>(ctx: &C) -> Result<WarmContainer, DockerError> {
+    let prefix = &ctx.container_settings().prefix;
+    let id = Alphanumeric
+        .sample_string(&mut rand::thread_rng(), 12)
+        .to_lowercase();
+    let name = format!("{prefix}warm_{id}");
+    let volume = format!("{name}_vol");
+
+    let (options, config) = warm_container_config(ctx, &name, &volume);
+    ctx.create_container(options, config).await?;
+
+    let started = docker_op("start", ctx.docker().start_container::<String>(&name, None)).await;
+    if let Err(err) = started {
+        remove(ctx, &name, &volume).await;
+        return Err(err);
+    }
+
+    Ok(WarmContainer {
+        name,
+        volume,
+        started_at: Instant::now(),
+    })
+}
+
+/// Remove a warm container along with its volume, which no project has
+/// any use for
+async fn remove<C: DockerContext>(ctx: &C, name: &str, volume: &str) {
+    let res = docker_op(
+        "remove",
+        ctx.docker().remove_container(
+            name,
+            Some(RemoveContainerOptions {
+                force: true,
+                ..Default::default()
+            }),
+        ),
+    )
+    .await;
+    if let Err(err) = res {
+        warn!(error = %err, %name, "failed to remove a warm container");
+        return;
+    }
+
+    if let Err(err) = docker_op(
+        "remove volume",
+        ctx.docker()
+            .remove_volume(volume, None::<RemoveVolumeOptions>),
+    )
+    .await
+    {
+        warn!(error = %err, %volume, "failed to remove the volume of a warm container");
+    }
+}
+
+/// A tar archive holding the claim of a warm container, as docker takes
+/// files to copy into containers
+fn claim_archive(args: &[String]) -> Vec<u8> {
+    let claim = serde_json::to_vec(args).unwrap();
+
+    let mut header = tar::Header::new_gnu();
+    header.set_size(claim.len() as u64);
+    header.set_mode(0o644);
+
+    let mut tar = tar::Builder::new(Vec::new());
+    tar.append_data(&mut header, "claim.json", claim.as_slice())
+        .unwrap();
+    tar.into_inner().unwrap()
+}
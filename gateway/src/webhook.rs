@@ -0,0 +1,244 @@
+use std::time::Duration;
+
+use rand::distributions::{Alphanumeric, DistString};
+use ring::hmac;
+use shuttle_common::models::error::ErrorKind;
+use shuttle_common::models::project;
+use tokio::time::sleep;
+use tracing::{error, warn};
+
+use crate::egress::{self, Destination};
+use crate::project::Project;
+use crate::task::{Task, TaskResult};
+use crate::{Error, ProjectName};
+
+/// Header carrying the signature of a webhook payload
+pub const SIGNATURE_HEADER: &str = "X-Shuttle-Signature";
+
+/// Maximum number of times a delivery is attempted before giving up
+pub const WEBHOOK_MAX_ATTEMPTS: u32 = 5;
+
+/// Time waited before the first retry. It doubles with every retry
+pub const WEBHOOK_BACKOFF: Duration = Duration::from_secs(2);
+
+/// Maximum time a single delivery attempt may take
+pub const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Where events about a project are sent to and the secret they are
+/// signed with
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Webhook {
+    pub url: String,
+    pub secret: String,
+}
+
+impl Webhook {
+    pub fn new(url: String) -> Result<Self, Error> {
+        match reqwest::Url::parse(&url) {
+            Ok(parsed) if matches!(parsed.scheme(), "http" | "https") => Ok(Self {
+                url,
+                secret: Alphanumeric.sample_string(&mut rand::thread_rng(), 32),
+            }),
+            _ => Err(Error::from_kind(ErrorKind::InvalidWebhookUrl)),
+        }
+    }
+
+    /// Resolve the host of the webhook, failing with
+    /// [`ErrorKind::InvalidWebhookUrl`] if it is not public, unless
+    /// `allow_private` destinations
+    pub async fn resolve(&self, allow_private: bool) -> Result<Destination, Error> {
+        let invalid = || Error::from_kind(ErrorKind::InvalidWebhookUrl);
+
+        let url = reqwest::Url::parse(&self.url).map_err(|_| invalid())?;
+        egress::resolve(&url, allow_private)
+            .await
+            .map_err(|detail| invalid().with_detail(detail))
+    }
+
+    /// A client which only reaches the webhook on the addresses it was
+    /// checked to resolve to, so that its host cannot resolve somewhere
+    /// else by the time it is connected to. Redirects are not followed
+    /// for the same reason.
+    async fn client(&self, allow_private: bool) -> Result<reqwest::Client, Error> {
+        let destination = self.resolve(allow_private).await?;
+
+        let mut builder = reqwest::Client::builder()
+            .redirect(reqwest::redirect::Policy::none())
+            .timeout(WEBHOOK_TIMEOUT);
+        if let Some(domain) = &destination.domain {
+            builder = builder.resolve(domain, destination.addrs[0]);
+        }
+
+        builder
+            .build()
+            .map_err(|err| Error::source(ErrorKind::Internal, err))
+    }
+
+    /// The value of the [`SIGNATURE_HEADER`] for `payload`: the hex
+    /// encoded HMAC-SHA256 of the payload keyed with the secret
+    pub fn sign(&self, payload: &[u8]) -> String {
+        let key = hmac::Key::new(hmac::HMAC_SHA256, self.secret.as_bytes());
+        let tag = hmac::sign(&key, payload);
+
+        let hex: String = tag
+            .as_ref()
+            .iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect();
+
+        format!("sha256={hex}")
+    }
+
+    /// Check that `signature` was made for `payload` with this
+    /// webhook's secret
+    pub fn verify(&self, payload: &[u8], signature: &str) -> bool {
+        ring::constant_time::verify_slices_are_equal(
+            self.sign(payload).as_bytes(),
+            signature.as_bytes(),
+        )
+        .is_ok()
+    }
+}
+
+/// Delivers an event to a project's webhook, retrying with an
+/// exponential backoff until it succeeds or runs out of attempts
+pub struct DeliverWebhook {
+    allow_private: bool,
+    project_name: ProjectName,
+    webhook: Webhook,
+    payload: Vec<u8>,
+    attempts: u32,
+    max_attempts: u32,
+    backoff: Duration,
+}
+
+impl DeliverWebhook {
+    /// Deliver the state of `project` to `webhook`, which is checked to
+    /// be public before every attempt unless `allow_private`
+    pub fn new(
+        project_name: ProjectName,
+        webhook: Webhook,
+        project: &Project,
+        allow_private: bool,
+    ) -> Result<Self, Error> {
+        let event = project::WebhookEvent {
+            project_name: project_name.to_string(),
+            state: project.clone().into(),
+            timestamp: chrono::Utc::now(),
+        };
+        let payload =
+            serde_json::to_vec(&event).map_err(|err| Error::source(ErrorKind::Internal, err))?;
+
+        Ok(Self {
+            allow_private,
+            project_name,
+            webhook,
+            payload,
+            attempts: 0,
+            max_attempts: WEBHOOK_MAX_ATTEMPTS,
+            backoff: WEBHOOK_BACKOFF,
+        })
+    }
+
+    pub fn with_retries(mut self, max_attempts: u32, backoff: Duration) -> Self {
+        self.max_attempts = max_attempts;
+        self.backoff = backoff;
+        self
+    }
+}
+
+#[async_trait]
+impl Task<()> for DeliverWebhook {
+    type Output = ();
+
+    type Error = Error;
+
+    async fn poll(&mut self, _: ()) -> TaskResult<Self::Output, Self::Error> {
+        if self.attempts > 0 {
+            sleep(self.backoff * 2u32.pow(self.attempts - 1)).await;
+        }
+        self.attempts += 1;
+
+        let res = match self.webhook.client(self.allow_private).await {
+            Ok(client) => client
+                .post(&self.webhook.url)
+                .header(reqwest::header::CONTENT_TYPE, "application/json")
+                .header(SIGNATURE_HEADER, self.webhook.sign(&self.payload))
+                .body(self.payload.clone())
+                .send()
+                .await
+                .and_then(|resp| resp.error_for_status())
+                .map_err(|err| Error::source(ErrorKind::Internal, err)),
+            Err(err) => Err(err),
+        };
+
+        match res {
+            Ok(_) => TaskResult::Done(()),
+            Err(err) if self.attempts < self.max_attempts => {
+                warn!(
+                    project_name = %self.project_name,
+                    attempt = self.attempts,
+                    error = %err,
+                    "failed to deliver webhook, will retry"
+                );
+                TaskResult::Pending(())
+            }
+            Err(err) => {
+                error!(
+                    project_name = %self.project_name,
+                    attempts = self.attempts,
+                    error = %err,
+                    "giving up on delivering webhook"
+                );
+                TaskResult::Err(err)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    #[test]
+    fn webhook_signature() {
+        let webhook = Webhook::new("https://example.com/hook".to_string()).unwrap();
+        let other = Webhook::new("https://example.com/hook".to_string()).unwrap();
+
+        let signature = webhook.sign(b"payload");
+        assert!(signature.starts_with("sha256="));
+        assert!(webhook.verify(b"payload", &signature));
+        assert!(!webhook.verify(b"tampered", &signature));
+        assert!(!other.verify(b"payload", &signature));
+
+        assert!(Webhook::new("ftp://example.com".to_string()).is_err());
+        assert!(Webhook::new("not a url".to_string()).is_err());
+    }
+
+    #[tokio::test]
+    async fn webhook_destinations() {
+        for url in [
+            "http://127.0.0.1/hook",
+            "http://localhost:8000/hook",
+            "http://10.0.0.1/hook",
+            "http://192.168.1.1/hook",
+            "http://169.254.169.254/latest/meta-data",
+            "http://[::1]/hook",
+            "http://[fd00::1]/hook",
+            "http://[::ffff:127.0.0.1]/hook",
+        ] {
+            let webhook = Webhook::new(url.to_string()).unwrap();
+            assert_eq!(
+                webhook.resolve(false).await.unwrap_err().kind(),
+                ErrorKind::InvalidWebhookUrl,
+                "{url} should be refused"
+            );
+        }
+
+        let webhook = Webhook::new("http://127.0.0.1/hook".to_string()).unwrap();
+        assert!(webhook.resolve(true).await.is_ok());
+
+        let webhook = Webhook::new("https://1.1.1.1/hook".to_string()).unwrap();
+        assert!(webhook.resolve(false).await.is_ok());
+    }
+}
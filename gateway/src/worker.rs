@@ -1,10 +1,13 @@
 use std::collections::HashMap;
-use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
 
+use chrono::{DateTime, Utc};
 use tokio::sync::mpsc::error::SendError;
 use tokio::sync::mpsc::{channel, Receiver, Sender};
-use tokio::sync::RwLock;
+use tokio::sync::{Notify, RwLock};
 use tracing::{debug, info};
+use uuid::Uuid;
 
 use crate::task::{BoxedTask, TaskResult};
 use crate::{Error, ProjectName};
@@ -126,3 +129,149 @@ impl TaskRouter<BoxedTask> {
         }
     }
 }
+
+/// Tells a task to stop at the earliest opportunity
+#[derive(Clone, Default)]
+pub struct CancellationToken {
+    inner: Arc<(AtomicBool, Notify)>,
+}
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.inner.0.store(true, Ordering::SeqCst);
+        self.inner.1.notify_waiters();
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.inner.0.load(Ordering::SeqCst)
+    }
+
+    /// Resolves once the token is cancelled
+    pub async fn cancelled(&self) {
+        loop {
+            // Has to be created before checking the flag to not miss
+            // a cancellation happening in between
+            let notified = self.inner.1.notified();
+            if self.is_cancelled() {
+                return;
+            }
+            notified.await;
+        }
+    }
+}
+
+/// What is known about a task which is currently being run by a worker
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TaskRecord {
+    pub project_name: ProjectName,
+    pub state: &'static str,
+    pub started_at: DateTime<Utc>,
+}
+
+/// Keeps track of the project tasks currently in-flight, so that
+/// operators can see what the workers are busy with.
+pub struct TaskTracker {
+    table: Arc<RwLock<HashMap<Uuid, TaskRecord>>>,
+    /// Tokens of the tasks which were built but are not done yet,
+    /// including the ones still waiting in a queue
+    tokens: Arc<Mutex<HashMap<Uuid, (ProjectName, CancellationToken)>>>,
+}
+
+impl Clone for TaskTracker {
+    fn clone(&self) -> Self {
+        Self {
+            table: self.table.clone(),
+            tokens: self.tokens.clone(),
+        }
+    }
+}
+
+impl Default for TaskTracker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TaskTracker {
+    pub fn new() -> Self {
+        Self {
+            table: Arc::new(RwLock::new(HashMap::new())),
+            tokens: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Hand out the token through which the task `id` can be
+    /// cancelled with [`TaskTracker::cancel`]
+    pub fn register(&self, id: Uuid, project_name: &ProjectName) -> CancellationToken {
+        let token = CancellationToken::new();
+        self.tokens
+            .lock()
+            .unwrap()
+            .insert(id, (project_name.clone(), token.clone()));
+        token
+    }
+
+    /// Cancel all the tasks of `project_name` which are queued or
+    /// running. Returns how many there were.
+    pub fn cancel(&self, project_name: &ProjectName) -> usize {
+        let mut tokens = self.tokens.lock().unwrap();
+        let mut cancelled = 0;
+        tokens.retain(|_, (name, token)| {
+            if name == project_name {
+                token.cancel();
+                cancelled += 1;
+                false
+            } else {
+                true
+            }
+        });
+        cancelled
+    }
+
+    /// Record the latest known project state of the task `id`,
+    /// starting to track it if it is not already
+    pub async fn update(&self, id: Uuid, project_name: &ProjectName, state: &'static str) {
+        self.table
+            .write()
+            .await
+            .entry(id)
+            .and_modify(|record| record.state = state)
+            .or_insert_with(|| TaskRecord {
+                project_name: project_name.clone(),
+                state,
+                started_at: Utc::now(),
+            });
+    }
+
+    /// Stop tracking the task `id`. This does not need to be awaited
+    /// so it can be called when a task is dropped.
+    pub fn remove(&self, id: Uuid) {
+        self.tokens.lock().unwrap().remove(&id);
+
+        if let Ok(mut table) = self.table.try_write() {
+            table.remove(&id);
+        } else {
+            let table = self.table.clone();
+            tokio::spawn(async move {
+                table.write().await.remove(&id);
+            });
+        }
+    }
+
+    /// List all the in-flight tasks, oldest first
+    pub async fn list(&self) -> Vec<(Uuid, TaskRecord)> {
+        let mut records: Vec<_> = self
+            .table
+            .read()
+            .await
+            .iter()
+            .map(|(id, record)| (*id, record.clone()))
+            .collect();
+        records.sort_by_key(|(_, record)| record.started_at);
+        records
+    }
+}